@@ -0,0 +1,52 @@
+/* C interface to juicydb, built with the 'ffi' feature, e.g.
+ * `cargo rustc --release --features ffi --crate-type cdylib`. See src/ffi.rs. */
+
+#ifndef JUICYDB_H
+#define JUICYDB_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define JUICYDB_OK 0
+#define JUICYDB_ERROR 1
+#define JUICYDB_MISUSE 2
+#define JUICYDB_ROW 100
+#define JUICYDB_DONE 101
+
+#define JUICYDB_NULL 0
+#define JUICYDB_INTEGER 1
+#define JUICYDB_REAL 2
+#define JUICYDB_TEXT 3
+
+typedef struct JuicyDb juicydb;
+typedef struct JuicyStmt juicydb_stmt;
+
+int juicydb_open(const char *path, juicydb **db);
+int juicydb_close(juicydb *db);
+int juicydb_exec(juicydb *db, const char *sql);
+const char *juicydb_errmsg(const juicydb *db);
+
+int juicydb_prepare(juicydb *db, const char *sql, juicydb_stmt **stmt);
+int juicydb_bind_int(juicydb_stmt *stmt, int index, int64_t value);
+int juicydb_bind_double(juicydb_stmt *stmt, int index, double value);
+int juicydb_bind_text(juicydb_stmt *stmt, int index, const char *value);
+int juicydb_bind_null(juicydb_stmt *stmt, int index);
+int juicydb_step(juicydb_stmt *stmt);
+int juicydb_reset(juicydb_stmt *stmt);
+int juicydb_finalize(juicydb_stmt *stmt);
+
+int juicydb_column_count(const juicydb_stmt *stmt);
+const char *juicydb_column_name(const juicydb_stmt *stmt, int column);
+int juicydb_column_type(const juicydb_stmt *stmt, int column);
+int64_t juicydb_column_int(const juicydb_stmt *stmt, int column);
+double juicydb_column_double(const juicydb_stmt *stmt, int column);
+const char *juicydb_column_text(juicydb_stmt *stmt, int column);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
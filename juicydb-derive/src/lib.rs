@@ -0,0 +1,142 @@
+//! `#[derive(FromRow)]`, implementing `juicydb::from_row::FromRow` for a struct: a struct with
+//! named fields gets each field from the column of the same name, and a tuple struct gets its
+//! fields from the columns in order. The struct is read straight from the tokens, as the crate
+//! has no dependencies.
+
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, TokenStream, TokenTree};
+
+#[proc_macro_derive(FromRow)]
+pub fn derive_from_row(input: TokenStream) -> TokenStream {
+    let code = match parse_struct(input) {
+        Ok((name, fields)) => from_row_impl(&name, &fields),
+        Err(message) => format!("compile_error!({:?});", message),
+    };
+    code.parse().expect("Generated code is valid")
+}
+
+/// Fields of a struct: their names, or their number for a tuple struct.
+enum Fields {
+    Named(Vec<String>),
+    Unnamed(usize),
+}
+
+/// The name and fields of the struct the tokens declare.
+fn parse_struct(input: TokenStream) -> Result<(String, Fields), &'static str> {
+    let mut tokens = input.into_iter().peekable();
+    let mut name = None;
+    while let Some(token) = tokens.next() {
+        match token {
+            TokenTree::Ident(ident) if ident.to_string() == "struct" => match tokens.next() {
+                Some(TokenTree::Ident(ident)) => {
+                    name = Some(ident.to_string());
+                    break;
+                }
+                _ => return Err("Expected the name of the struct"),
+            },
+            TokenTree::Ident(ident) if ident.to_string() == "enum" => {
+                return Err("FromRow can only be derived for structs")
+            }
+            _ => (),
+        }
+    }
+    let name = name.ok_or("FromRow can only be derived for structs")?;
+    for token in tokens {
+        match token {
+            TokenTree::Punct(punct) if punct.as_char() == '<' => {
+                return Err("FromRow can't be derived for generic structs")
+            }
+            TokenTree::Group(group) if group.delimiter() == Delimiter::Brace => {
+                return Ok((name, Fields::Named(field_names(group.stream()))))
+            }
+            TokenTree::Group(group) if group.delimiter() == Delimiter::Parenthesis => {
+                return Ok((name, Fields::Unnamed(split_fields(group.stream()).len())))
+            }
+            _ => (),
+        }
+    }
+    Ok((name, Fields::Named(Vec::new())))
+}
+
+/// The tokens of the fields, split at the commas between them. Commas within the generic
+/// arguments of types, which are not groups of their own, don't split.
+fn split_fields(stream: TokenStream) -> Vec<Vec<TokenTree>> {
+    let mut fields = vec![Vec::new()];
+    let mut depth = 0;
+    for token in stream {
+        if let TokenTree::Punct(punct) = &token {
+            match punct.as_char() {
+                '<' => depth += 1,
+                // Not the end of generic arguments if part of `->`
+                '>' if !ends_arrow(fields.last()) => depth -= 1,
+                ',' if depth == 0 => {
+                    fields.push(Vec::new());
+                    continue;
+                }
+                _ => (),
+            }
+        }
+        if let Some(field) = fields.last_mut() {
+            field.push(token);
+        }
+    }
+    fields.retain(|field| !field.is_empty());
+    fields
+}
+
+fn ends_arrow(field: Option<&Vec<TokenTree>>) -> bool {
+    match field.and_then(|field| field.last()) {
+        Some(TokenTree::Punct(punct)) => punct.as_char() == '-',
+        _ => false,
+    }
+}
+
+/// The names of named fields: the identifier before the first colon of each field, after its
+/// attributes and visibility.
+fn field_names(stream: TokenStream) -> Vec<String> {
+    split_fields(stream)
+        .into_iter()
+        .filter_map(|field| {
+            let colon = field.iter().position(|token| match token {
+                TokenTree::Punct(punct) => punct.as_char() == ':',
+                _ => false,
+            })?;
+            match &field[..colon].last() {
+                Some(TokenTree::Ident(ident)) => Some(ident.to_string()),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn from_row_impl(name: &str, fields: &Fields) -> String {
+    let body = match fields {
+        Fields::Named(names) => {
+            let fields: Vec<_> = names
+                .iter()
+                .map(|field| {
+                    let column = field.trim_start_matches("r#");
+                    format!("{}: row.get({:?})?", field, column)
+                })
+                .collect();
+            format!("{} {{ {} }}", name, fields.join(", "))
+        }
+        Fields::Unnamed(count) => {
+            let fields: Vec<_> = (0..*count)
+                .map(|index| format!("row.get_index({})?", index))
+                .collect();
+            format!("{}({})", name, fields.join(", "))
+        }
+    };
+    format!(
+        "impl ::juicydb::from_row::FromRow for {} {{
+            fn from_row(
+                row: &::juicydb::from_row::ResultRow<'_>,
+            ) -> ::std::result::Result<Self, ::juicydb::from_row::FromRowError> {{
+                ::std::result::Result::Ok({})
+            }}
+        }}",
+        name, body
+    )
+}
@@ -0,0 +1,262 @@
+//! Arrow support, under the `arrow` feature: the rows of queries as Arrow record batches, to hand
+//! results to DataFusion, Polars or Arrow IPC streams as they are; see
+//! [`Database::query_arrow`].
+//!
+//! Columns map to the closest Arrow type: integers to `Int64`, reals to `Float64`, text to
+//! `Utf8`, booleans to `Boolean`, dates to `Date32`, times to `Time64` and timestamps to
+//! `Timestamp` without a time zone, both in microseconds, decimals to `Decimal128` of their
+//! precision and scale, and UUIDs to 16-byte `FixedSizeBinary` of the `arrow.uuid` extension
+//! type. Columns without a type, e.g. of `null`, and decimals too precise for `Decimal128` are
+//! `Utf8` of the text of their values, as in the results of the REPL. All fields are nullable.
+//!
+//! [`Database::query_arrow`]: crate::database::Database::query_arrow
+
+use crate::db::{DBType, DBValue, ResultColumn, Row};
+use crate::storage_manager::StorageError;
+use arrow_array::{
+    ArrayRef, BooleanArray, Date32Array, Decimal128Array, FixedSizeBinaryArray, Float64Array,
+    Int64Array, RecordBatch, RecordBatchOptions, StringArray, Time64MicrosecondArray,
+    TimestampMicrosecondArray,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit, DECIMAL128_MAX_PRECISION};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::iter::FromIterator;
+use std::sync::Arc;
+
+/// The Arrow type of the values of a column of the given type.
+pub fn data_type(db_type: Option<DBType>) -> DataType {
+    match db_type {
+        Some(DBType::Integer) => DataType::Int64,
+        Some(DBType::Real) => DataType::Float64,
+        Some(DBType::Boolean) => DataType::Boolean,
+        Some(DBType::Date) => DataType::Date32,
+        Some(DBType::Time) => DataType::Time64(TimeUnit::Microsecond),
+        Some(DBType::Timestamp) => DataType::Timestamp(TimeUnit::Microsecond, None),
+        Some(DBType::Decimal(precision, scale))
+            if (1..=DECIMAL128_MAX_PRECISION).contains(&precision) && scale <= precision =>
+        {
+            DataType::Decimal128(precision, scale as i8)
+        }
+        Some(DBType::Uuid) => DataType::FixedSizeBinary(16),
+        _ => DataType::Utf8,
+    }
+}
+
+/// The Arrow schema of the columns of a result.
+pub fn schema(columns: &[ResultColumn]) -> Schema {
+    let fields: Vec<_> = columns
+        .iter()
+        .map(|column| {
+            let field = Field::new(column.name.as_str(), data_type(column.db_type), true);
+            match column.db_type {
+                Some(DBType::Uuid) => field.with_metadata(HashMap::from([(
+                    String::from("ARROW:extension:name"),
+                    String::from("arrow.uuid"),
+                )])),
+                _ => field,
+            }
+        })
+        .collect();
+    Schema::new(fields)
+}
+
+/// The rows as a record batch with the schema of their columns. Fails with
+/// [`StorageError::TypeError`] for a value that doesn't fit the type of its column, even
+/// coerced to it.
+pub fn record_batch(columns: &[ResultColumn], rows: &[Row]) -> Result<RecordBatch, StorageError> {
+    batch(&Arc::new(schema(columns)), columns, rows)
+}
+
+/// The rows of a query with the columns as record batches of at most `batch_size` rows each,
+/// pulling a batch of rows at a time from the cursor, e.g. a [`crate::connection::Cursor`]. A
+/// query without rows gives a single empty batch, which still carries the schema.
+pub fn record_batches(
+    columns: &[ResultColumn],
+    rows: impl Iterator<Item = Result<Row, StorageError>>,
+    batch_size: usize,
+) -> Result<Vec<RecordBatch>, StorageError> {
+    let batch_size = batch_size.max(1);
+    let schema = Arc::new(schema(columns));
+    let mut batches = Vec::new();
+    let mut pending = Vec::with_capacity(batch_size);
+    for row in rows {
+        pending.push(row?);
+        if pending.len() == batch_size {
+            batches.push(batch(&schema, columns, &pending)?);
+            pending.clear();
+        }
+    }
+    if !pending.is_empty() || batches.is_empty() {
+        batches.push(batch(&schema, columns, &pending)?);
+    }
+    Ok(batches)
+}
+
+fn batch(
+    schema: &SchemaRef,
+    columns: &[ResultColumn],
+    rows: &[Row],
+) -> Result<RecordBatch, StorageError> {
+    let arrays = schema
+        .fields()
+        .iter()
+        .zip(columns)
+        .enumerate()
+        .map(|(i, (field, column))| array(field.data_type(), column.db_type, i, rows))
+        .collect::<Result<_, _>>()?;
+    // The row count is given for results without columns
+    let options = RecordBatchOptions::new().with_row_count(Some(rows.len()));
+    RecordBatch::try_new_with_options(schema.clone(), arrays, &options)
+        .map_err(|_| StorageError::TypeError)
+}
+
+/// The values of the column at `column` of the rows as an array of the given type.
+fn array(
+    data_type: &DataType,
+    db_type: Option<DBType>,
+    column: usize,
+    rows: &[Row],
+) -> Result<ArrayRef, StorageError> {
+    let values: Vec<_> = rows
+        .iter()
+        .map(|row| {
+            let value = &row[column];
+            db_type
+                .and_then(|db_type| db_type.coerce(value))
+                .unwrap_or_else(|| value.clone())
+        })
+        .collect();
+    let array: ArrayRef = match data_type {
+        DataType::Int64 => Arc::new(collect::<Int64Array, _>(&values, |value| match value {
+            DBValue::Integer(i) => Some(*i),
+            _ => None,
+        })?),
+        DataType::Float64 => Arc::new(collect::<Float64Array, _>(&values, |value| match value {
+            DBValue::Real(r) => Some(*r),
+            _ => None,
+        })?),
+        DataType::Boolean => Arc::new(collect::<BooleanArray, _>(&values, |value| match value {
+            DBValue::Boolean(b) => Some(*b),
+            _ => None,
+        })?),
+        DataType::Date32 => Arc::new(collect::<Date32Array, _>(&values, |value| match value {
+            DBValue::Date(days) => i32::try_from(*days).ok(),
+            _ => None,
+        })?),
+        DataType::Time64(_) => {
+            Arc::new(collect::<Time64MicrosecondArray, _>(
+                &values,
+                |value| match value {
+                    DBValue::Time(micros) => Some(*micros),
+                    _ => None,
+                },
+            )?)
+        }
+        DataType::Timestamp(..) => Arc::new(collect::<TimestampMicrosecondArray, _>(
+            &values,
+            |value| match value {
+                DBValue::Timestamp(micros) => Some(*micros),
+                _ => None,
+            },
+        )?),
+        DataType::Decimal128(precision, scale) => {
+            let array = collect::<Decimal128Array, _>(&values, |value| match value {
+                DBValue::Decimal(d) => Some(d.mantissa()),
+                _ => None,
+            })?;
+            let array = array.with_precision_and_scale(*precision, *scale);
+            Arc::new(array.map_err(|_| StorageError::TypeError)?)
+        }
+        DataType::FixedSizeBinary(size) => {
+            let uuids = collect::<Vec<_>, _>(&values, |value| match value {
+                DBValue::Uuid(uuid) => Some(*uuid.as_bytes()),
+                _ => None,
+            })?;
+            let array =
+                FixedSizeBinaryArray::try_from_sparse_iter_with_size(uuids.into_iter(), *size);
+            Arc::new(array.map_err(|_| StorageError::TypeError)?)
+        }
+        _ => Arc::new(collect::<StringArray, _>(&values, |value| match value {
+            DBValue::Text(text) => Some(text.clone()),
+            value => Some(value.to_string()),
+        })?),
+    };
+    Ok(array)
+}
+
+/// Collects the values into an array, with nulls as nulls and other values as given by
+/// `native`, failing with [`StorageError::TypeError`] where it gives `None`.
+fn collect<A, T>(
+    values: &[DBValue],
+    native: impl Fn(&DBValue) -> Option<T>,
+) -> Result<A, StorageError>
+where
+    A: FromIterator<Option<T>>,
+{
+    values
+        .iter()
+        .map(|value| match value {
+            DBValue::Null => Ok(None),
+            value => native(value).map(Some).ok_or(StorageError::TypeError),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    use arrow_array::Array;
+
+    #[test]
+    fn record_batches_of_queries() {
+        let mut db = Database::new();
+        for statement in &[
+            "create table t (id integer, name text, at date, price decimal(5, 2), u uuid);",
+            "insert into t values (1, 'a', '2024-01-02', 1.5, \
+             '67e55044-10b1-426f-9247-bb680e5fe0c8');",
+            "insert into t (id) values (2);",
+            "insert into t (id, name) values (3, 'c');",
+        ] {
+            assert!(db.execute(statement).is_ok(), "{}", statement);
+        }
+        let sql = "select id, name, at, price, u, null from t order by id;";
+        let batches = db
+            .query_arrow(sql, 2)
+            .unwrap_or_else(|err| panic!("{}", err));
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].num_rows(), 2);
+        assert_eq!(batches[1].num_rows(), 1);
+        let schema = batches[0].schema();
+        let types: Vec<_> = schema
+            .fields()
+            .iter()
+            .map(|f| f.data_type().clone())
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                DataType::Int64,
+                DataType::Utf8,
+                DataType::Date32,
+                DataType::Decimal128(5, 2),
+                DataType::FixedSizeBinary(16),
+                DataType::Utf8,
+            ]
+        );
+        let ids = batches[0].column(0).as_any().downcast_ref::<Int64Array>();
+        assert_eq!(ids.map(|ids| ids.values().to_vec()), Some(vec![1, 2]));
+        let dates = batches[0].column(2).as_any().downcast_ref::<Date32Array>();
+        assert_eq!(dates.map(|dates| dates.value(0)), Some(19724));
+        let prices = batches[0]
+            .column(3)
+            .as_any()
+            .downcast_ref::<Decimal128Array>();
+        assert_eq!(prices.map(|prices| prices.value(0)), Some(150));
+        assert_eq!(batches[0].column(1).null_count(), 1);
+        assert_eq!(batches[1].column(5).null_count(), 1);
+        let empty = db.query_arrow("select id from t where id > 3;", 2);
+        assert_eq!(empty.map(|batches| batches.len()).ok(), Some(1));
+    }
+}
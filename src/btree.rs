@@ -1,7 +1,17 @@
+use crate::cipher::{Cipher, Kdf, SALT_SIZE};
 use crate::db::*;
-use std::fs::File;
+use crate::pager::{Page, PageCounts, PageId, Pager, ReadMode, PAGE_SIZE, USABLE_SIZE};
+use crate::record::{read_row, write_row};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{Arc, RwLock};
+use std::vec;
 
-/// B-tree datatype, consisting of a file handle and an in-memory root node. B-trees can be seen as
+/// B-tree datatype, consisting of a [`Pager`] over its file and an in-memory root node. B-trees can be seen as
 /// an on-disk data structure for tables. Each table in juicydb is stored in it's own file and
 /// b-tree nodes in these files are broken up into contiguous 4kb pages. When reading/updating
 /// database values, these pages are loaded into memory and flushed to disk as needed.
@@ -11,104 +21,824 @@ use std::fs::File;
 /// the one in the current node, and the right child consists of keys greater than the one in the
 /// current node. A b-tree is simply a generalization of this scheme to multiple keys, where the
 /// child "in between two keys" has keys greater than or equal to the key left of the child, but
-/// less than the key right of the child. In juicydb, k == 255, meaning that each node has 255
-/// keys and 256 children. This property of splitting to multiple children, referred to as fanout,
-/// is typically high in b-trees to reduce the height of built trees. Smaller height means that we
-/// need fewer "jumps" in the tree to locate a key and thus fewer disk seeks, which are relatively
-/// expensive.
+/// less than the key right of the child. In juicydb, nodes hold as many keys as fit in their
+/// page, which for short keys is several hundred. This property of splitting to multiple
+/// children, referred to as fanout, is typically high in b-trees to reduce the height of built
+/// trees. Smaller height means that we need fewer "jumps" in the tree to locate a key and thus
+/// fewer disk seeks, which are relatively expensive.
 ///
-/// Each file begins with a (4kb) header node, consisting of e.g. schema information. The exact format
-/// for headers is under construction. The header is followed by 1 or more b-tree nodes. For the
-/// file format of b-tree nodes, refer to the documentation on [`BTreeNode`]s.
-
+/// Keys are byte strings compared byte by byte, so that any key that encodes into bytes in an
+/// order-preserving way can be stored, see [`crate::record::encode_key`]: the primary keys or
+/// rowids of rows, text, or several values making up a composite key, such as the indexed value
+/// followed by the key of the row in the entries of an index.
+///
+/// Each file begins with a (4kb) header page, whose format is described by [`Header`]. The
+/// header is followed by 1 or more b-tree nodes. For the file format of b-tree nodes, refer to
+/// the documentation on [`BTreeNode`]s. The root node is the first node, in page 1. A file may
+/// hold further trees, each with a root page of its own, created with [`BTree::create_tree`]:
+/// the file of a database keeps a catalog of its tables in the tree at page 1, and the rows of
+/// each table and the entries of each index in a tree of their own; see [`SavedTree`].
+#[derive(Debug)]
 pub struct BTree {
-    file: File,
+    pager: Pager,
     schema: Schema,
+    header: Header,
+    root: BTreeNode,
 }
 
+/// Cell of an internal node: the key separating a child from the child before it, and the page
+/// of the child.
+#[derive(Clone, Debug, PartialEq)]
 pub struct KeyCell {
-    pub key: u32,
-    pub page_id: u32,
+    pub key: Key,
+    pub page_id: PageId,
+}
+
+/// Cell of a leaf node: a row along with its key. Rows too large for the cell are stored in a
+/// chain of overflow pages instead, with the cell holding the first page of the chain and an
+/// empty row.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DataCell {
+    pub key: Key,
+    pub row: Row,
+    pub overflow: Option<PageId>,
 }
 
+/// A B-tree node datatype. A node is either internal to the tree, or a leaf node which represents
+/// a row in the database. The page format in juicydb is referred to as slotted pages; this means
+/// that (after the header) each page consists of a contiguous segment of pointers to
+/// variable-size segments in the same page. These segments are referred to as cells. The cells
+/// have a key and in the case of internal nodes, a page id, giving the offset to a page of a
+/// child, and in the case of leaf nodes, a data record i.e. a database row. The pointers in the
+/// beginning of a page are sorted according to the key contained in the cell they are pointing
+/// to; this means we can perform a binary search on the pointers for fast access of children in
+/// the b-tree. Nodes hold their cells in key order.
+///
+/// Keys are at most [`MAX_KEY_SIZE`] bytes and page ids are unsigned 32-bit integers, meaning
+/// that the file representing a table can have a maximum file size of 4kb * 2^32 ~= 16
+/// terabytes.
+#[derive(Clone, Debug, PartialEq)]
 pub enum BTreeNode {
     Internal {
-        freecells: [bool; 256],
-        pointers: [u8; 256],
-        cells: [KeyCell; 256],
+        cells: Vec<KeyCell>,
     },
+    /// Leaf nodes link to the previous and the next leaf in key order, if any.
     Leaf {
-        freecells: [bool; 64],
-        pointers: [u8; 64],
-        data_cells: [Row; 64],
+        prev: Option<PageId>,
+        next: Option<PageId>,
+        cells: Vec<DataCell>,
     },
 }
 
+/// Flags telling the kind of a node, in the first byte of its page.
+const INTERNAL: u8 = 0;
+const LEAF: u8 = 1;
+
+/// Offset of the number of cells in the page of an internal node, followed by the pointers.
+const INTERNAL_COUNT: usize = 1;
+
+/// Offsets of the sibling links and the number of cells in the page of a leaf node, followed by
+/// the pointers.
+const LEAF_PREV: usize = 1;
+const LEAF_NEXT: usize = LEAF_PREV + 4;
+const LEAF_COUNT: usize = LEAF_NEXT + 4;
+
+/// Size of the number of cells, of a pointer and of the length of a key in bytes.
+const COUNT_SIZE: usize = 2;
+const POINTER_SIZE: usize = 2;
+const KEY_LENGTH_SIZE: usize = 2;
+
+/// Largest key in bytes.
+pub const MAX_KEY_SIZE: usize = 256;
+
+/// Largest cell in bytes, including its pointer: a quarter of a page, so that both halves of a
+/// node split by size fit in a page. Rows that would make their cell larger overflow.
+const MAX_CELL_SIZE: usize = USABLE_SIZE / 4;
+
+/// Offset of the payload in an overflow page, after the page id of the next page in the chain.
+const OVERFLOW_PAYLOAD: usize = 4;
+
+fn read_u32(input: &[u8]) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&input[..4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u16(input: &[u8]) -> usize {
+    usize::from(u16::from_le_bytes([input[0], input[1]]))
+}
+
+fn write_u16(output: &mut [u8], value: usize) {
+    output[..2].copy_from_slice(&(value as u16).to_le_bytes());
+}
+
+/// Reads a key, prefixed by its length, returning it along with the bytes after it.
+fn read_key(input: &[u8]) -> (Key, &[u8]) {
+    let length = read_u16(input);
+    let rest = &input[KEY_LENGTH_SIZE..];
+    (rest[..length].to_vec(), &rest[length..])
+}
+
+impl KeyCell {
+    /// Size of the cell in bytes, including its pointer.
+    fn size(&self) -> usize {
+        POINTER_SIZE + KEY_LENGTH_SIZE + self.key.len() + 4
+    }
+}
+
+impl DataCell {
+    /// Size of the cell in bytes, including its pointer.
+    fn size(&self) -> usize {
+        let payload = match self.overflow {
+            Some(_) => 4,
+            None => row_size(&self.row),
+        };
+        POINTER_SIZE + KEY_LENGTH_SIZE + self.key.len() + 1 + payload
+    }
+}
+
+/// Size of the row encoded by [`write_row`] in bytes.
+fn row_size(row: &[DBValue]) -> usize {
+    let mut bytes = Vec::new();
+    write_row(&mut bytes, row).map_or(usize::MAX, |_| bytes.len())
+}
+
 impl BTreeNode {
+    /// Reads a node from its page. The page is packed binary, with numbers in little-endian. The
+    /// first byte tells the kind of the node, 0 for internal and 1 for leaf nodes. It is followed
+    /// by the number of cells as a `u16` and the pointers, the `u16` offset of each cell in the
+    /// page, in key order. The cells are packed at the end of the usable part of the page, the
+    /// first cell last. Cells start with the key, a `u16` length followed by the bytes of the
+    /// key. The key cells of internal nodes follow it with a `u32` page id; the data cells of
+    /// leaf nodes with a byte 0 and the row, or a byte 1 and the `u32` page id of the overflow
+    /// pages holding the row. Leaf nodes have the page ids of their previous and next leaves
+    /// before the number of cells, with 0, the page of the header, for none.
     pub fn read(input: [u8; 4096]) -> Self {
+        let pointers = |count_offset: usize| {
+            let count = read_u16(&input[count_offset..]);
+            (0..count).map(move |i| read_u16(&input[(count_offset + COUNT_SIZE + i * 2)..]))
+        };
         match input[0] {
-            b'0' => {
-                let freecells = {
-                    let mut bool_array = [false; 256];
-                    for (i, byte) in input[1..257].iter().enumerate() {
-                        match byte {
-                            b'0' => bool_array[i] = false,
-                            b'1' => bool_array[i] = true,
-                            _ => panic!("Invalid freecell list"),
+            INTERNAL => {
+                let cells = pointers(INTERNAL_COUNT)
+                    .map(|offset| {
+                        let (key, rest) = read_key(&input[offset..]);
+                        KeyCell {
+                            key,
+                            page_id: read_u32(rest),
                         }
-                    }
-                    bool_array
-                };
-                let pointers = {
-                    let mut byte_array = [b'0'; 256];
-                    for (i, byte) in input[1792..2048].iter().enumerate() {
-                        byte_array[i] = *byte;
-                    }
-                    byte_array
-                };
-                let cells = {
-                    let mut cell_array = [KeyCell { key: 0, page_id: 0 }; 256];
-                    for i in 0..256 {
-                        let mut key_bytes = [b'0', b'0', b'0', b'0'];
-                        for (i, byte) in input[(i * 8 + 2048)..(i * 8 + 2052)].iter().enumerate() {
-                            key_bytes[i] = *byte;
+                    })
+                    .collect();
+                BTreeNode::Internal { cells }
+            }
+            LEAF => {
+                let sibling = |offset| Some(read_u32(&input[offset..])).filter(|id| *id != 0);
+                let prev = sibling(LEAF_PREV);
+                let next = sibling(LEAF_NEXT);
+                let cells = pointers(LEAF_COUNT)
+                    .map(|offset| {
+                        let (key, rest) = read_key(&input[offset..]);
+                        if rest[0] != 0 {
+                            return DataCell {
+                                key,
+                                row: Vec::new(),
+                                overflow: Some(read_u32(&rest[1..])),
+                            };
+                        }
+                        match read_row(&mut &rest[1..]) {
+                            Ok(Some(row)) => DataCell {
+                                key,
+                                row,
+                                overflow: None,
+                            },
+                            _ => panic!("Invalid data cell"),
+                        }
+                    })
+                    .collect();
+                BTreeNode::Leaf { prev, next, cells }
+            }
+            _ => panic!("Invalid enum flag"),
+        }
+    }
+
+    /// Writes the node into a page in the format read by [`BTreeNode::read`]. Unused bytes are
+    /// zeroed. Panics if the node doesn't fit in a page.
+    pub fn write(&self) -> [u8; 4096] {
+        let mut output = [0; 4096];
+        assert!(self.size() <= USABLE_SIZE, "Node too large for a page");
+        let mut end = USABLE_SIZE;
+        // Writes a cell, given its key and the bytes after it, returning its offset
+        let mut write_cell = |output: &mut [u8], key: &[u8], rest: &[u8]| {
+            end -= KEY_LENGTH_SIZE + key.len() + rest.len();
+            write_u16(&mut output[end..], key.len());
+            let key_end = end + KEY_LENGTH_SIZE + key.len();
+            output[(end + KEY_LENGTH_SIZE)..key_end].copy_from_slice(key);
+            output[key_end..(key_end + rest.len())].copy_from_slice(rest);
+            end
+        };
+        let count_offset = match self {
+            BTreeNode::Internal { cells } => {
+                output[0] = INTERNAL;
+                for (i, cell) in cells.iter().enumerate() {
+                    let offset = write_cell(&mut output, &cell.key, &cell.page_id.to_le_bytes());
+                    write_u16(&mut output[(INTERNAL_COUNT + COUNT_SIZE + i * 2)..], offset);
+                }
+                INTERNAL_COUNT
+            }
+            BTreeNode::Leaf { prev, next, cells } => {
+                output[0] = LEAF;
+                let prev = prev.unwrap_or(0).to_le_bytes();
+                output[LEAF_PREV..LEAF_NEXT].copy_from_slice(&prev);
+                let next = next.unwrap_or(0).to_le_bytes();
+                output[LEAF_NEXT..LEAF_COUNT].copy_from_slice(&next);
+                for (i, cell) in cells.iter().enumerate() {
+                    let mut rest = Vec::new();
+                    match cell.overflow {
+                        Some(page_id) => {
+                            rest.push(1);
+                            rest.extend_from_slice(&page_id.to_le_bytes());
                         }
-                        let mut page_id_bytes = [b'0', b'0', b'0', b'0'];
-                        for (i, byte) in input[(i * 8 + 2052)..(i * 8 + 2056)].iter().enumerate() {
-                            page_id_bytes[i] = *byte;
+                        None => {
+                            rest.push(0);
+                            write_row(&mut rest, &cell.row).expect("Rows encode into memory");
                         }
-                        let key = ((key_bytes[0] as u32) << 24)
-                            | ((key_bytes[1] as u32) << 16)
-                            | ((key_bytes[2] as u32) << 8)
-                            | ((key_bytes[3] as u32) << 0);
-                        let page_id = ((page_id_bytes[0] as u32) << 24)
-                            | ((page_id_bytes[1] as u32) << 16)
-                            | ((page_id_bytes[2] as u32) << 8)
-                            | ((page_id_bytes[3] as u32) << 0);
-                        cell_array[i] = KeyCell { key, page_id };
                     }
-                    cell_array
-                };
-                BTreeNode::Internal {
-                    freecells,
-                    pointers,
-                    cells,
+                    let offset = write_cell(&mut output, &cell.key, &rest);
+                    write_u16(&mut output[(LEAF_COUNT + COUNT_SIZE + i * 2)..], offset);
                 }
+                LEAF_COUNT
             }
-            b'1' => {
-                let freecells = {
-                };
-                let pointers = {
-                };
-                let data_cells = {
-                };
+        };
+        write_u16(&mut output[count_offset..], self.cell_count());
+        output
+    }
+
+    /// Size of the node in a page in bytes, up to the end of its last cell.
+    fn size(&self) -> usize {
+        match self {
+            BTreeNode::Internal { cells } => {
+                let cells: usize = cells.iter().map(KeyCell::size).sum();
+                INTERNAL_COUNT + COUNT_SIZE + cells
+            }
+            BTreeNode::Leaf { cells, .. } => {
+                let cells: usize = cells.iter().map(DataCell::size).sum();
+                LEAF_COUNT + COUNT_SIZE + cells
             }
-            _ => panic!("Invalid enum flag"),
         }
     }
+
+    /// Whether the node fits in a page.
+    fn fits(&self) -> bool {
+        self.size() <= USABLE_SIZE
+    }
+
+    /// A leaf without rows or siblings.
+    fn empty_leaf() -> Self {
+        Self::leaf(Vec::new(), None, None)
+    }
+
+    /// A leaf holding the given cells, in key order.
+    fn leaf(cells: Vec<DataCell>, prev: Option<PageId>, next: Option<PageId>) -> Self {
+        BTreeNode::Leaf { prev, next, cells }
+    }
+
+    /// An internal node holding the given cells, in key order.
+    fn internal(cells: Vec<KeyCell>) -> Self {
+        BTreeNode::Internal { cells }
+    }
+
+    /// The cells of a leaf in key order.
+    fn leaf_cells(&self) -> Vec<DataCell> {
+        match self {
+            BTreeNode::Leaf { cells, .. } => cells.clone(),
+            BTreeNode::Internal { .. } => Vec::new(),
+        }
+    }
+
+    /// The cells of an internal node in key order.
+    fn internal_cells(&self) -> Vec<KeyCell> {
+        match self {
+            BTreeNode::Internal { cells } => cells.clone(),
+            BTreeNode::Leaf { .. } => Vec::new(),
+        }
+    }
+
+    fn cell_count(&self) -> usize {
+        match self {
+            BTreeNode::Internal { cells } => cells.len(),
+            BTreeNode::Leaf { cells, .. } => cells.len(),
+        }
+    }
+
+    /// Number of cells with a key less than the given key, or at most the key if inclusive.
+    fn count_below(&self, key: &[u8], inclusive: bool) -> usize {
+        let below = |cell_key: &[u8]| cell_key < key || (inclusive && cell_key == key);
+        match self {
+            BTreeNode::Internal { cells } => cells.partition_point(|cell| below(&cell.key)),
+            BTreeNode::Leaf { cells, .. } => cells.partition_point(|cell| below(&cell.key)),
+        }
+    }
+
+    /// Position in key order of the child of an internal node that may hold the key: the last
+    /// child whose key is at most the key, or the first child.
+    fn child_position(&self, key: &[u8]) -> usize {
+        self.count_below(key, true).saturating_sub(1)
+    }
+}
+
+/// Groups the cells, in order, into as few nodes as they fit in, each node but the last filled as
+/// far as it goes. The node header takes up the given number of bytes. There is always at least
+/// one group, which may be empty.
+fn pack<T>(cells: Vec<T>, size: impl Fn(&T) -> usize, header: usize) -> Vec<Vec<T>> {
+    let mut groups = vec![Vec::new()];
+    let mut used = header;
+    for cell in cells {
+        let cell_size = size(&cell);
+        if used + cell_size > USABLE_SIZE {
+            groups.push(Vec::new());
+            used = header;
+        }
+        used += cell_size;
+        groups
+            .last_mut()
+            .expect("There is always a group")
+            .push(cell);
+    }
+    groups
+}
+
+/// The shortest prefix of the right key that is greater than the left key, which is less than
+/// it. The prefix separates the keys up to the left key from the keys from the right key on just
+/// as well as the right key itself, and takes less room in the internal node above them.
+fn shortest_separator(left: &[u8], right: &[u8]) -> Key {
+    let shared = left.iter().zip(right).take_while(|(l, r)| l == r).count();
+    right[..(shared + 1).min(right.len())].to_vec()
+}
+
+/// Position at which to split cells of the given sizes, such that the cells before it take up
+/// about half of the space. Both halves get at least one cell.
+fn split_position(sizes: impl Iterator<Item = usize> + Clone) -> usize {
+    let total: usize = sizes.clone().sum();
+    let mut taken = 0;
+    let count = sizes.clone().count();
+    let position = sizes
+        .take_while(|size| {
+            taken += size;
+            taken - size < total / 2
+        })
+        .count();
+    position.clamp(1, count - 1)
 }
 
 impl BTree {
+    /// Opens the b-tree stored in the pages, validating its header and reading its root node.
+    pub fn open(pager: Pager, schema: Schema) -> io::Result<Self> {
+        Self::open_encrypted(pager, schema, None)
+    }
+
+    /// Opens the b-tree like [`BTree::open`], decrypting its pages with the key derived from the
+    /// passphrase as the header describes, if the file is encrypted. Files are only opened with
+    /// a passphrase if they are encrypted, and the other way around.
+    pub fn open_encrypted(
+        mut pager: Pager,
+        schema: Schema,
+        passphrase: Option<&str>,
+    ) -> io::Result<Self> {
+        if pager.page_count() == 0 {
+            return Err(invalid_file("Not a juicydb database file"));
+        }
+        let header = Header::read(&pager.read_page(0)?, pager.page_count())?;
+        match (header.encryption, passphrase) {
+            (Some(kdf), Some(passphrase)) => {
+                pager.set_cipher(Some(Cipher::derive(passphrase, kdf)?))
+            }
+            (Some(_), None) => return Err(invalid_file("The database file is encrypted")),
+            (None, Some(_)) => return Err(invalid_file("The database file is not encrypted")),
+            (None, None) => (),
+        }
+        let root = BTreeNode::read(pager.read_page(ROOT)?);
+        Ok(Self {
+            pager,
+            schema,
+            header,
+            root,
+        })
+    }
+
+    /// Creates an empty b-tree in the pages, overwriting anything in them. The pages are
+    /// encrypted if the pager has a cipher.
+    pub fn create(pager: Pager, schema: Schema) -> io::Result<Self> {
+        let encryption = pager.cipher().map(Cipher::kdf);
+        let mut tree = Self {
+            pager,
+            schema,
+            header: Header {
+                page_count: ROOT + 1,
+                freelist: None,
+                catalog_root: ROOT,
+                schema_cookie: 0,
+                encryption,
+            },
+            root: BTreeNode::empty_leaf(),
+        };
+        tree.clear()?;
+        Ok(tree)
+    }
+
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// The cipher the pages are encrypted with, if any.
+    pub fn cipher(&self) -> Option<&Cipher> {
+        self.pager.cipher()
+    }
+
+    /// Removes all rows from the tree, truncating its file to the header and an empty root.
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.pager.truncate(0)?;
+        self.header.page_count = ROOT + 1;
+        self.header.freelist = None;
+        self.pager.write_page(0, &self.header.write())?;
+        self.write_node(ROOT, &BTreeNode::empty_leaf())
+    }
+
+    /// Flushes the pages written so far to the disk; see [`Pager::checkpoint`].
+    pub fn checkpoint(&mut self) -> io::Result<()> {
+        self.pager.checkpoint()
+    }
+
+    /// The pager of the tree, e.g. to commit or roll back the journal of the pages written.
+    pub fn into_pager(self) -> Pager {
+        self.pager
+    }
+
+    pub fn pager_mut(&mut self) -> &mut Pager {
+        &mut self.pager
+    }
+
+    /// Reads the header and the root node again, e.g. once a journal rolled back the pages
+    /// written since they were read.
+    pub fn reload(&mut self) -> io::Result<()> {
+        self.header = Header::read(&self.pager.read_page(0)?, self.pager.page_count())?;
+        self.root = BTreeNode::read(self.pager.read_page(ROOT)?);
+        Ok(())
+    }
+
+    fn node(&self, page_id: PageId) -> io::Result<BTreeNode> {
+        if page_id == ROOT {
+            return Ok(self.root.clone());
+        }
+        Ok(BTreeNode::read(self.pager.read_page(page_id)?))
+    }
+
+    fn write_node(&mut self, page_id: PageId, node: &BTreeNode) -> io::Result<()> {
+        self.pager.write_page(page_id, &node.write())?;
+        if page_id == ROOT {
+            self.root = node.clone();
+        }
+        Ok(())
+    }
+
+    /// A new page at the end of the file. Pages are never freed, so the pages of nodes and
+    /// overflowing rows that are no longer in use stay in the file until it is cleared.
+    fn allocate(&mut self) -> PageId {
+        self.pager.allocate_page()
+    }
+
+    /// The row with the given key, if any.
+    pub fn get(&self, key: &[u8]) -> io::Result<Option<Row>> {
+        self.get_in(ROOT, key)
+    }
+
+    /// The row with the given key in the tree with the given root page, if any.
+    pub fn get_in(&self, root: PageId, key: &[u8]) -> io::Result<Option<Row>> {
+        match self.cursor_from(root, key)?.next().transpose()? {
+            Some((found, row)) if found == key => Ok(Some(row)),
+            _ => Ok(None),
+        }
+    }
+
+    /// A cursor positioned before the first row of the tree.
+    pub fn cursor(&self) -> io::Result<Cursor<'_>> {
+        self.cursor_in(ROOT)
+    }
+
+    /// A cursor positioned before the first row of the tree with the given root page.
+    pub fn cursor_in(&self, root: PageId) -> io::Result<Cursor<'_>> {
+        self.cursor_from(root, &[])
+    }
+
+    /// A cursor positioned before the first row with a key at least the given key, in the tree
+    /// with the given root page.
+    pub fn cursor_from(&self, root: PageId, key: &[u8]) -> io::Result<Cursor<'_>> {
+        let mut cursor = Cursor {
+            tree: self,
+            root,
+            leaf: BTreeNode::empty_leaf(),
+            position: 0,
+        };
+        cursor.seek(key)?;
+        Ok(cursor)
+    }
+
+    /// Creates an empty tree in a new page of the file, returning its root page.
+    pub fn create_tree(&mut self) -> io::Result<PageId> {
+        let root = self.allocate();
+        self.write_node(root, &BTreeNode::empty_leaf())?;
+        self.write_page_count()?;
+        Ok(root)
+    }
+
+    /// Inserts the row with the given key, replacing any row with the same key. Keys are at most
+    /// [`MAX_KEY_SIZE`] bytes. A full leaf is split in halves by size, the right half moving to a
+    /// new page. The shortest prefix of its first key that still sorts after the last key of the
+    /// left half is then inserted into the parent as the separator, which may in turn split. When
+    /// the root splits, both halves move to new pages, so that the root stays in page 1 and the
+    /// tree grows by a level.
+    ///
+    /// Returns whether a row with the key was replaced.
+    pub fn insert(&mut self, key: &[u8], row: &Row) -> io::Result<bool> {
+        self.insert_in(ROOT, key, row)
+    }
+
+    /// Inserts the row into the tree with the given root page, like [`BTree::insert`].
+    pub fn insert_in(&mut self, root: PageId, key: &[u8], row: &Row) -> io::Result<bool> {
+        let replaced = self.insert_cell(root, key, row)?;
+        self.write_page_count()?;
+        Ok(replaced)
+    }
+
+    /// Removes the row with the given key, returning whether there was one. Nodes aren't merged
+    /// as they empty: an empty leaf stays linked to its siblings, which cursors move on to, and
+    /// its page is reused by the rows inserted into it later.
+    pub fn delete(&mut self, key: &[u8]) -> io::Result<bool> {
+        self.delete_in(ROOT, key)
+    }
+
+    /// Removes the row with the given key from the tree with the given root page, like
+    /// [`BTree::delete`].
+    pub fn delete_in(&mut self, root: PageId, key: &[u8]) -> io::Result<bool> {
+        let mut page_id = root;
+        let mut node = self.node(root)?;
+        while let BTreeNode::Internal { cells } = &node {
+            if cells.is_empty() {
+                return Ok(false);
+            }
+            page_id = cells[node.child_position(key)].page_id;
+            node = self.node(page_id)?;
+        }
+        let (prev, next, mut cells) = match node {
+            BTreeNode::Leaf { prev, next, cells } => (prev, next, cells),
+            BTreeNode::Internal { .. } => unreachable!(),
+        };
+        match cells.binary_search_by(|cell| cell.key.as_slice().cmp(key)) {
+            Ok(position) => {
+                cells.remove(position);
+                self.write_node(page_id, &BTreeNode::leaf(cells, prev, next))?;
+                Ok(true)
+            }
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// Replaces the rows of the tree by the given rows, which must be in ascending key order,
+    /// building the tree bottom-up. Leaves are filled as far as they fit, one after the other, and
+    /// then each level of internal nodes is built over the level below, up to the root, with the
+    /// leaves separated by shortened keys as on insertion. This writes every page once, and packs
+    /// the nodes tighter than inserting the rows one by one, which leaves nodes half full as they
+    /// split.
+    pub fn load(&mut self, rows: impl IntoIterator<Item = (Key, Row)>) -> io::Result<()> {
+        self.clear()?;
+        self.load_in(ROOT, rows)
+    }
+
+    /// Replaces the rows of the tree with the given root page like [`BTree::load`], keeping the
+    /// root in its page. The other pages of the tree are left as they are, unused.
+    pub fn load_in(
+        &mut self,
+        root: PageId,
+        rows: impl IntoIterator<Item = (Key, Row)>,
+    ) -> io::Result<()> {
+        let cells = rows
+            .into_iter()
+            .map(|(key, row)| self.data_cell(&key, &row))
+            .collect::<io::Result<Vec<_>>>()?;
+        let groups = pack(cells, DataCell::size, LEAF_COUNT + COUNT_SIZE);
+        if groups.len() == 1 {
+            let cells = groups.into_iter().flatten().collect();
+            self.write_node(root, &BTreeNode::leaf(cells, None, None))?;
+            return self.write_page_count();
+        }
+        let pages: Vec<_> = groups.iter().map(|_| self.allocate()).collect();
+        let mut level = Vec::new();
+        let mut last: Option<Key> = None;
+        for (i, cells) in groups.into_iter().enumerate() {
+            let prev = i.checked_sub(1).map(|i| pages[i]);
+            let next = pages.get(i + 1).copied();
+            let first = &cells[0].key;
+            level.push(KeyCell {
+                key: last.map_or_else(|| first.clone(), |last| shortest_separator(&last, first)),
+                page_id: pages[i],
+            });
+            last = Some(cells[cells.len() - 1].key.clone());
+            self.write_node(pages[i], &BTreeNode::leaf(cells, prev, next))?;
+        }
+        let mut groups = pack(level, KeyCell::size, INTERNAL_COUNT + COUNT_SIZE);
+        while groups.len() > 1 {
+            let mut parents = Vec::new();
+            for cells in groups {
+                let page_id = self.allocate();
+                parents.push(KeyCell {
+                    key: cells[0].key.clone(),
+                    page_id,
+                });
+                self.write_node(page_id, &BTreeNode::internal(cells))?;
+            }
+            groups = pack(parents, KeyCell::size, INTERNAL_COUNT + COUNT_SIZE);
+        }
+        let level = groups.into_iter().flatten().collect();
+        self.write_node(root, &BTreeNode::internal(level))?;
+        self.write_page_count()
+    }
+
+    /// Rewrites the header if pages were allocated since it was last written.
+    fn write_page_count(&mut self) -> io::Result<()> {
+        if self.header.page_count != self.pager.page_count() {
+            self.header.page_count = self.pager.page_count();
+            self.pager.write_page(0, &self.header.write())?;
+        }
+        Ok(())
+    }
+
+    fn insert_cell(&mut self, root: PageId, key: &[u8], row: &Row) -> io::Result<bool> {
+        let cell = self.data_cell(key, row)?;
+        let mut path = Vec::new();
+        let mut page_id = root;
+        let mut node = self.node(root)?;
+        while let BTreeNode::Internal { .. } = node {
+            if node.cell_count() == 0 {
+                node = BTreeNode::empty_leaf();
+                break;
+            }
+            let cells = node.internal_cells();
+            let child = cells[node.child_position(key)].page_id;
+            path.push((page_id, cells));
+            page_id = child;
+            node = self.node(child)?;
+        }
+        let (prev, next) = match node {
+            BTreeNode::Leaf { prev, next, .. } => (prev, next),
+            BTreeNode::Internal { .. } => unreachable!(),
+        };
+        let mut cells = node.leaf_cells();
+        let replaced = match cells.binary_search_by(|cell| cell.key.as_slice().cmp(key)) {
+            // The row is already there as it is, and the page needn't be written
+            Ok(position) if cells[position] == cell => return Ok(true),
+            Ok(position) => {
+                cells[position] = cell;
+                true
+            }
+            Err(position) => {
+                cells.insert(position, cell);
+                false
+            }
+        };
+        let leaf = BTreeNode::leaf(cells, prev, next);
+        if leaf.fits() {
+            self.write_node(page_id, &leaf)?;
+            return Ok(replaced);
+        }
+
+        let mut cells = leaf.leaf_cells();
+        let right = cells.split_off(split_position(cells.iter().map(DataCell::size)));
+        let last = &cells[cells.len() - 1].key;
+        let separator = shortest_separator(last, &right[0].key);
+        if page_id == root {
+            let (left_id, right_id) = (self.allocate(), self.allocate());
+            self.write_node(left_id, &BTreeNode::leaf(cells, None, Some(right_id)))?;
+            self.write_node(right_id, &BTreeNode::leaf(right, Some(left_id), None))?;
+            self.split_root(root, left_id, right_id, separator)?;
+            return Ok(replaced);
+        }
+        let right_id = self.allocate();
+        self.write_node(page_id, &BTreeNode::leaf(cells, prev, Some(right_id)))?;
+        self.write_node(right_id, &BTreeNode::leaf(right, Some(page_id), next))?;
+        if let Some(next) = next {
+            let mut node = self.node(next)?;
+            if let BTreeNode::Leaf { prev, .. } = &mut node {
+                *prev = Some(right_id);
+            }
+            self.write_node(next, &node)?;
+        }
+
+        let mut new_cell = KeyCell {
+            key: separator,
+            page_id: right_id,
+        };
+        while let Some((page_id, mut cells)) = path.pop() {
+            let position = cells.partition_point(|cell| cell.key <= new_cell.key);
+            cells.insert(position, new_cell);
+            let node = BTreeNode::internal(cells);
+            if node.fits() {
+                self.write_node(page_id, &node)?;
+                return Ok(replaced);
+            }
+            let mut cells = node.internal_cells();
+            let right = cells.split_off(split_position(cells.iter().map(KeyCell::size)));
+            let separator = right[0].key.clone();
+            if page_id == root {
+                let (left_id, right_id) = (self.allocate(), self.allocate());
+                self.write_node(left_id, &BTreeNode::internal(cells))?;
+                self.write_node(right_id, &BTreeNode::internal(right))?;
+                self.split_root(root, left_id, right_id, separator)?;
+                return Ok(replaced);
+            }
+            let right_id = self.allocate();
+            self.write_node(page_id, &BTreeNode::internal(cells))?;
+            self.write_node(right_id, &BTreeNode::internal(right))?;
+            new_cell = KeyCell {
+                key: separator,
+                page_id: right_id,
+            };
+        }
+        Ok(replaced)
+    }
+
+    /// Makes the root an internal node over the two halves it was split into.
+    fn split_root(
+        &mut self,
+        root: PageId,
+        left: PageId,
+        right: PageId,
+        separator: Key,
+    ) -> io::Result<()> {
+        let cells = vec![
+            KeyCell {
+                key: Vec::new(),
+                page_id: left,
+            },
+            KeyCell {
+                key: separator,
+                page_id: right,
+            },
+        ];
+        self.write_node(root, &BTreeNode::internal(cells))
+    }
+
+    /// A data cell holding the row, writing the row into overflow pages if the cell would be
+    /// larger than [`MAX_CELL_SIZE`]. Fails if the key is longer than [`MAX_KEY_SIZE`].
+    fn data_cell(&mut self, key: &[u8], row: &Row) -> io::Result<DataCell> {
+        if key.len() > MAX_KEY_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Key too large"));
+        }
+        let cell = DataCell {
+            key: key.to_vec(),
+            row: row.clone(),
+            overflow: None,
+        };
+        if cell.size() <= MAX_CELL_SIZE {
+            return Ok(cell);
+        }
+        let mut bytes = Vec::new();
+        write_row(&mut bytes, row)?;
+        let chunks: Vec<_> = bytes.chunks(USABLE_SIZE - OVERFLOW_PAYLOAD).collect();
+        let first = self.pager.page_count();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let page_id = self.allocate();
+            let next = if i + 1 < chunks.len() { page_id + 1 } else { 0 };
+            let mut page = [0; PAGE_SIZE];
+            page[..OVERFLOW_PAYLOAD].copy_from_slice(&next.to_le_bytes());
+            page[OVERFLOW_PAYLOAD..(OVERFLOW_PAYLOAD + chunk.len())].copy_from_slice(chunk);
+            self.pager.write_page(page_id, &page)?;
+        }
+        Ok(DataCell {
+            key: key.to_vec(),
+            row: Vec::new(),
+            overflow: Some(first),
+        })
+    }
+
+    /// The row of a data cell, reading it from its overflow pages if it didn't fit in the cell.
+    fn cell_row(&self, cell: &DataCell) -> io::Result<Row> {
+        let mut page_id = match cell.overflow {
+            Some(page_id) => page_id,
+            None => return Ok(cell.row.clone()),
+        };
+        let mut bytes = Vec::new();
+        while page_id != 0 {
+            let page = self.pager.read_page(page_id)?;
+            bytes.extend_from_slice(&page[OVERFLOW_PAYLOAD..USABLE_SIZE]);
+            page_id = read_u32(&page);
+        }
+        read_row(&mut bytes.as_slice())?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Empty overflow pages"))
+    }
+
     /*
         pub fn serialize(&self) {
             let header_page: [u8; 4096] = {
@@ -129,30 +859,470 @@ impl BTree {
     */
 }
 
-/// A B-tree node datatype. A node is either internal to the tree, or a leaf node which represents
-/// a row in the database. The page format in juicydb is referred to as slotted pages; this means
-/// that (after the header) each page consists of a contiguous segment of keys pointing to
-/// fixed-size segments in the same page. These segments are referred to as cells. The cells have a
-/// key and in the case of internal nodes, a page id, giving the offset to a page of a child, and
-/// in the case of leaf nodes, a data record i.e. a database row. The keys in the beginning of a
-/// page are sorted according to the key contained in the cell they are pointing to; this means we
-/// can perform a binary search on the pointers for fast access of children in the b-tree.
-///
-/// As each node (page) has at most 256 children (cells), the keys can be represented as 8-bit
-/// unsigned integers. Keys and page ID's are both represented as unsigned 32-bit integers, meaning
-/// that a database table may have at most 2(cells), the keys can be represented as 8-bit unsigned
-/// integers. Keys and page ID's are both represented as unsigned 32-bit integers, meaning that a
-/// table can hold at most 2^32 = 4294967296 rows, and the file representing a table can have a
-/// maximum file size of 4kb * 2^32 ~= 16 terabytes.
-/*
-pub enum BTreeNode {
-    Internal { cells: Cell<Key, PageId> },
-    Leaf { cells: Cell<Key, Row> },
+/// Keys are byte strings, compared byte by byte.
+pub type Key = Vec<u8>;
+
+/// Page of the root node, following the header.
+const ROOT: PageId = 1;
+
+/// Magic bytes at the start of every b-tree file.
+const MAGIC: &[u8; 8] = b"juicydb\0";
+
+/// Version of the file format, bumped on incompatible changes to it.
+const FORMAT_VERSION: u32 = 4;
+
+/// Offset of the salt in the header page, after the fields.
+const SALT: usize = MAGIC.len() + 4 * 7;
+
+/// Contents of the header page, the first page of a b-tree file. The page starts with
+/// [`MAGIC`], followed by `u32`s in little-endian: the format version, the page size, the number
+/// of pages in the file, the first page of the list of free pages, or 0 for none, the root page
+/// of the tree, the schema cookie, and the number of iterations deriving the key of an encrypted
+/// file, or 0 for none, followed by the salt. The rest of the page is zeroed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Header {
+    pub page_count: PageId,
+    pub freelist: Option<PageId>,
+    pub catalog_root: PageId,
+    /// Number changed by the owner of the file whenever the schema of its rows changes.
+    pub schema_cookie: u32,
+    /// Parameters for deriving the key of the pages, if they are encrypted.
+    pub encryption: Option<Kdf>,
 }
-*/
 
-type Key = u32;
-type PageId = u32;
+impl Header {
+    /// Reads the header page, rejecting pages of other kinds of files and headers
+    /// inconsistent with a file of the given number of pages.
+    pub fn read(page: &Page, file_pages: PageId) -> io::Result<Self> {
+        let field = |i: usize| read_u32(&page[(MAGIC.len() + 4 * i)..]);
+        if &page[..MAGIC.len()] != MAGIC {
+            return Err(invalid_file("Not a juicydb database file"));
+        }
+        if field(0) != FORMAT_VERSION {
+            let message = format!("Unsupported file format version {}", field(0));
+            return Err(invalid_file(&message));
+        }
+        if field(1) as usize != PAGE_SIZE {
+            return Err(invalid_file(&format!("Unsupported page size {}", field(1))));
+        }
+        let header = Self {
+            page_count: field(2),
+            freelist: Some(field(3)).filter(|page_id| *page_id != 0),
+            catalog_root: field(4),
+            schema_cookie: field(5),
+            encryption: Some(field(6))
+                .filter(|iterations| *iterations != 0)
+                .map(|iterations| {
+                    let mut salt = [0; SALT_SIZE];
+                    salt.copy_from_slice(&page[SALT..(SALT + SALT_SIZE)]);
+                    Kdf { salt, iterations }
+                }),
+        };
+        if header.page_count <= ROOT || header.page_count > file_pages {
+            return Err(invalid_file("Corrupt header: invalid page count"));
+        }
+        if header
+            .freelist
+            .is_some_and(|page_id| page_id >= header.page_count)
+        {
+            return Err(invalid_file("Corrupt header: invalid freelist head"));
+        }
+        if header.catalog_root != ROOT {
+            return Err(invalid_file("Corrupt header: invalid root page"));
+        }
+        Ok(header)
+    }
+
+    pub fn write(&self) -> Page {
+        let mut page = [0; PAGE_SIZE];
+        page[..MAGIC.len()].copy_from_slice(MAGIC);
+        let fields = [
+            FORMAT_VERSION,
+            PAGE_SIZE as u32,
+            self.page_count,
+            self.freelist.unwrap_or(0),
+            self.catalog_root,
+            self.schema_cookie,
+            self.encryption.map_or(0, |kdf| kdf.iterations),
+        ];
+        for (i, field) in fields.iter().enumerate() {
+            let start = MAGIC.len() + 4 * i;
+            page[start..(start + 4)].copy_from_slice(&field.to_le_bytes());
+        }
+        if let Some(kdf) = self.encryption {
+            page[SALT..(SALT + SALT_SIZE)].copy_from_slice(&kdf.salt);
+        }
+        page
+    }
+}
+
+fn invalid_file(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Cursor over the rows of a [`BTree`] in key order, moving in either direction. The cursor is
+/// positioned between rows: [`Cursor::next`] returns the row after it and [`Cursor::prev`] the
+/// row before it, moving past the row. It holds only its current leaf; moving past either end of
+/// the leaf follows the sibling link to the neighbouring leaf, without going through the root.
+pub struct Cursor<'a> {
+    tree: &'a BTree,
+    root: PageId,
+    leaf: BTreeNode,
+    /// Position in key order of the row after the cursor.
+    position: usize,
+}
+
+impl Cursor<'_> {
+    /// Positions the cursor before the first row with a key at least the given key.
+    pub fn seek(&mut self, key: &[u8]) -> io::Result<()> {
+        let mut node = self.tree.node(self.root)?;
+        while let BTreeNode::Internal { cells } = &node {
+            if cells.is_empty() {
+                node = BTreeNode::empty_leaf();
+                break;
+            }
+            let position = node.child_position(key);
+            node = self.tree.node(cells[position].page_id)?;
+        }
+        self.position = node.count_below(key, false);
+        self.leaf = node;
+        Ok(())
+    }
+
+    fn next_row(&mut self) -> io::Result<Option<(Key, Row)>> {
+        loop {
+            if self.position < self.leaf.cell_count() {
+                let row = self.cell()?;
+                self.position += 1;
+                return Ok(Some(row));
+            }
+            if !self.step(true)? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// The row before the cursor along with its key, or `None` at the start of the tree.
+    pub fn prev(&mut self) -> io::Result<Option<(Key, Row)>> {
+        loop {
+            if self.position > 0 {
+                self.position -= 1;
+                return self.cell().map(Some);
+            }
+            if !self.step(false)? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// The key and the row of the cell after the cursor.
+    fn cell(&self) -> io::Result<(Key, Row)> {
+        match &self.leaf {
+            BTreeNode::Leaf { cells, .. } => {
+                let cell = &cells[self.position];
+                Ok((cell.key.clone(), self.tree.cell_row(cell)?))
+            }
+            BTreeNode::Internal { .. } => unreachable!(),
+        }
+    }
+
+    /// Moves the cursor to the start of the next leaf, or the end of the previous one. Returns
+    /// false, leaving the cursor as is, if there is no such leaf.
+    fn step(&mut self, forward: bool) -> io::Result<bool> {
+        let sibling = match self.leaf {
+            BTreeNode::Leaf { next, .. } if forward => next,
+            BTreeNode::Leaf { prev, .. } => prev,
+            BTreeNode::Internal { .. } => None,
+        };
+        let page_id = match sibling {
+            Some(page_id) => page_id,
+            None => return Ok(false),
+        };
+        self.leaf = self.tree.node(page_id)?;
+        self.position = if forward { 0 } else { self.leaf.cell_count() };
+        Ok(true)
+    }
+}
+
+impl Iterator for Cursor<'_> {
+    type Item = io::Result<(Key, Row)>;
+
+    /// The row after the cursor along with its key, or `None` at the end of the tree.
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_row().transpose()
+    }
+}
+
+/// Rows of a table stored in a [`BTree`] in a file of its own, under the keys the table gives
+/// them. The file is created with a unique name in the given directory and removed once the
+/// rows are dropped.
+pub struct RowFile {
+    tree: BTree,
+    path: PathBuf,
+    len: usize,
+}
+
+/// Counter distinguishing the files of the tables of a process.
+static FILES: AtomicUsize = AtomicUsize::new(0);
+
+impl RowFile {
+    /// Creates the file, counting the pages read and written from it in the given counts.
+    pub fn create(
+        directory: &Path,
+        schema: Schema,
+        mode: ReadMode,
+        counts: Arc<PageCounts>,
+    ) -> io::Result<Self> {
+        let name = format!(
+            "juicydb-{}-{}.btree",
+            process::id(),
+            FILES.fetch_add(1, AtomicOrdering::Relaxed)
+        );
+        let path = directory.join(name);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        let mut pager = Pager::with_mode(file, mode)?;
+        pager.set_counts(counts);
+        Ok(Self {
+            tree: BTree::create(pager, schema)?,
+            path,
+            len: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The row with the given key, if any.
+    pub fn get(&self, key: &[u8]) -> io::Result<Option<Row>> {
+        self.tree.get(key)
+    }
+
+    /// The rows along with their keys, in key order, from the first row with a key at least the
+    /// given key on.
+    pub fn range(&self, from: &[u8]) -> impl Iterator<Item = io::Result<(Key, Row)>> + '_ {
+        let cursor = self.tree.cursor_from(ROOT, from);
+        let (cursor, error) = match cursor {
+            Ok(cursor) => (Some(cursor), None),
+            Err(err) => (None, Some(Err(err))),
+        };
+        error.into_iter().chain(cursor.into_iter().flatten())
+    }
+
+    /// Inserts the row under the key, replacing any row with the key. Returns whether there was
+    /// one.
+    pub fn insert(&mut self, key: &[u8], row: &Row) -> io::Result<bool> {
+        let replaced = self.tree.insert(key, row)?;
+        if !replaced {
+            self.len += 1;
+        }
+        Ok(replaced)
+    }
+
+    /// Removes the row with the key, returning whether there was one.
+    pub fn remove(&mut self, key: &[u8]) -> io::Result<bool> {
+        let removed = self.tree.delete(key)?;
+        if removed {
+            self.len -= 1;
+        }
+        Ok(removed)
+    }
+
+    /// Replaces all rows of the table, loading them into the tree from scratch. The rows must be
+    /// in ascending key order.
+    pub fn replace_all(&mut self, rows: Vec<(Key, Row)>) -> io::Result<()> {
+        self.len = rows.len();
+        self.tree.load(rows)
+    }
+
+    pub fn checkpoint(&mut self) -> io::Result<()> {
+        self.tree.checkpoint()
+    }
+
+    /// Rewrites the tree from scratch, dropping the overflow pages of replaced rows and the
+    /// slack left in nodes by deletions. Returns the number of bytes the file shrank by.
+    pub fn vacuum(&mut self) -> io::Result<u64> {
+        let before = fs::metadata(&self.path)?.len();
+        let rows = self.range(&[]).collect::<io::Result<Vec<_>>>()?;
+        self.replace_all(rows)?;
+        self.checkpoint()?;
+        Ok(before.saturating_sub(fs::metadata(&self.path)?.len()))
+    }
+
+    /// A new, empty file in the same directory, read in the same mode and counting its pages in
+    /// the same counts, e.g. for an index over the rows of this one.
+    pub fn create_beside(&self, schema: Schema) -> io::Result<Self> {
+        let directory = self.path.parent().unwrap_or_else(|| Path::new("."));
+        Self::create(
+            directory,
+            schema,
+            self.tree.pager.mode(),
+            self.tree.pager.counts().clone(),
+        )
+    }
+
+    /// A copy of the rows in a new file in the same directory.
+    pub fn try_clone(&self) -> io::Result<Self> {
+        let mode = self.tree.pager.mode();
+        let counts = self.tree.pager.counts().clone();
+        let mut copy = self.create_beside(self.tree.schema.clone())?;
+        fs::copy(&self.path, &copy.path)?;
+        let file = OpenOptions::new().read(true).write(true).open(&copy.path)?;
+        let mut pager = Pager::with_mode(file, mode)?;
+        pager.set_counts(counts);
+        copy.tree = BTree::open(pager, self.tree.schema.clone())?;
+        copy.len = self.len;
+        Ok(copy)
+    }
+}
+
+impl fmt::Debug for RowFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RowFile")
+            .field("path", &self.path)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl Drop for RowFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The file of a database, holding the trees of its tables, shared by the tables reading their
+/// rows from it and written by the saves of the database.
+pub type DatabaseFile = Arc<RwLock<BTree>>;
+
+/// Numbers of rows a [`SavedRows`] reads at a time, holding the lock of the file meanwhile: few
+/// at first, as for looking up a key, and more as the scan goes on, up to the greatest number.
+const FIRST_BATCH: usize = 8;
+const LAST_BATCH: usize = 256;
+
+/// A tree in the file of a database, holding the rows of a table or the entries of an index as
+/// they were last saved. The tree is created by the first save of its rows, and written in place
+/// by the later ones. Copies of the table share the tree.
+pub struct SavedTree {
+    file: DatabaseFile,
+    /// Root page of the tree, or 0 until it is saved.
+    root: AtomicU32,
+}
+
+impl SavedTree {
+    /// A tree of the file not saved yet, holding no rows.
+    pub fn new(file: DatabaseFile) -> Self {
+        Self::open(file, 0)
+    }
+
+    /// The tree of the file with the given root page.
+    pub fn open(file: DatabaseFile, root: PageId) -> Self {
+        Self {
+            file,
+            root: AtomicU32::new(root),
+        }
+    }
+
+    pub fn file(&self) -> &DatabaseFile {
+        &self.file
+    }
+
+    /// The root page of the tree, if it was saved.
+    pub fn root(&self) -> Option<PageId> {
+        Some(self.root.load(AtomicOrdering::Relaxed)).filter(|root| *root != 0)
+    }
+
+    /// Sets the root page of the tree, once the save creating it succeeded.
+    pub fn set_root(&self, root: PageId) {
+        self.root.store(root, AtomicOrdering::Relaxed);
+    }
+
+    /// The row with the given key, if any.
+    pub fn get(&self, key: &[u8]) -> io::Result<Option<Row>> {
+        match self.root() {
+            Some(root) => self.file.read().unwrap().get_in(root, key),
+            None => Ok(None),
+        }
+    }
+
+    /// The rows along with their keys, in key order, from the first row with a key at least the
+    /// given key on.
+    pub fn range(&self, from: &[u8]) -> SavedRows<'_> {
+        SavedRows {
+            tree: self,
+            from: Some(from.to_vec()),
+            batch: FIRST_BATCH,
+            rows: Vec::new().into_iter(),
+        }
+    }
+
+    /// The given number of rows, or fewer at the end of the tree, from the first row with a key
+    /// at least the given key on.
+    fn batch(&self, from: &[u8], rows: usize) -> io::Result<Vec<(Key, Row)>> {
+        let root = match self.root() {
+            Some(root) => root,
+            None => return Ok(Vec::new()),
+        };
+        let file = self.file.read().unwrap();
+        let cursor = file.cursor_from(root, from)?;
+        cursor.take(rows).collect()
+    }
+}
+
+impl fmt::Debug for SavedTree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SavedTree")
+            .field("root", &self.root())
+            .finish()
+    }
+}
+
+/// Iterator over the rows of a [`SavedTree`]. Rows are read a batch at a time, so that the file
+/// is only locked while reading a batch, and the next batch is sought from the key following
+/// the last key read.
+pub struct SavedRows<'a> {
+    tree: &'a SavedTree,
+    /// Key to read the next batch from, or `None` once the last batch was read.
+    from: Option<Key>,
+    /// Number of rows to read in the next batch.
+    batch: usize,
+    rows: vec::IntoIter<(Key, Row)>,
+}
+
+impl Iterator for SavedRows<'_> {
+    type Item = io::Result<(Key, Row)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(row) = self.rows.next() {
+                return Some(Ok(row));
+            }
+            let from = self.from.take()?;
+            let rows = match self.tree.batch(&from, self.batch) {
+                Ok(rows) => rows,
+                Err(err) => return Some(Err(err)),
+            };
+            if rows.len() == self.batch {
+                // The least key greater than the last one
+                self.from = rows.last().map(|(key, _)| [key.as_slice(), &[0]].concat());
+                self.batch = (self.batch * 2).min(LAST_BATCH);
+            }
+            self.rows = rows.into_iter();
+        }
+    }
+}
 
 /*
 /// An in-memory datastructure representing a cell in a page. Essentially an AVL-tree.
@@ -216,3 +1386,563 @@ impl<K, V> Cell<K, V> {
     }
 }
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uuid::Uuid;
+    use std::fs::File;
+
+    /// Generator of pseudo-random numbers (xorshift), for reproducible arbitrary nodes.
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+
+        /// A row of at most three values, small enough for a data cell.
+        fn row(&mut self) -> Row {
+            (0..self.below(4))
+                .map(|_| match self.below(7) {
+                    0 => DBValue::Null,
+                    1 => DBValue::Integer(self.next() as i64),
+                    2 => DBValue::Real(f64::from_bits(self.next())),
+                    3 => {
+                        let length = self.below(9);
+                        DBValue::Text(
+                            (0..length)
+                                .map(|_| (b'a' + self.below(26) as u8) as char)
+                                .collect(),
+                        )
+                    }
+                    4 => DBValue::Boolean(self.below(2) == 0),
+                    5 => DBValue::Date(self.next() as i64 >> 32),
+                    _ => {
+                        let bits = (self.next() as u128) << 64 | self.next() as u128;
+                        DBValue::Uuid(Uuid::from_bytes(bits.to_be_bytes()))
+                    }
+                })
+                .collect()
+        }
+
+        /// A key of at most 16 bytes.
+        fn key(&mut self) -> Key {
+            (0..self.below(17)).map(|_| self.next() as u8).collect()
+        }
+
+        fn node(&mut self) -> BTreeNode {
+            if self.below(2) == 0 {
+                let cells = (0..self.below(200))
+                    .map(|_| KeyCell {
+                        key: self.key(),
+                        page_id: self.next() as u32,
+                    })
+                    .collect();
+                BTreeNode::Internal { cells }
+            } else {
+                let mut sibling = || Some(self.next() as u32).filter(|_| self.below(2) == 0);
+                let prev = sibling().filter(|id| *id != 0);
+                let next = sibling().filter(|id| *id != 0);
+                let mut leaf = BTreeNode::leaf(Vec::new(), prev, next);
+                loop {
+                    let cell = if self.below(4) == 0 {
+                        DataCell {
+                            key: self.key(),
+                            row: Vec::new(),
+                            overflow: Some(self.next() as u32),
+                        }
+                    } else {
+                        DataCell {
+                            key: self.key(),
+                            row: self.row(),
+                            overflow: None,
+                        }
+                    };
+                    let mut grown = leaf.clone();
+                    if let BTreeNode::Leaf { cells, .. } = &mut grown {
+                        cells.push(cell);
+                    }
+                    if !grown.fits() || self.below(50) == 0 {
+                        return leaf;
+                    }
+                    leaf = grown;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn write_and_read_nodes() {
+        let mut random = Random(0x2545_f491_4f6c_dd1d);
+        for _ in 0..200 {
+            let node = random.node();
+            let page = node.write();
+            assert_eq!(BTreeNode::read(page), node);
+            assert_eq!(BTreeNode::read(page).write(), page);
+        }
+    }
+
+    #[test]
+    fn read_internal_node() {
+        let mut page = [0; 4096];
+        page[0] = INTERNAL;
+        page[INTERNAL_COUNT] = 2;
+        // The first cell is stored after the second one
+        page[(INTERNAL_COUNT + 2)..(INTERNAL_COUNT + 6)].copy_from_slice(&[0xf9, 0x0e, 0xf0, 0x0e]);
+        page[0xef0..0xf00].copy_from_slice(&[
+            3, 0, b'f', b'o', b'o', 1, 0, 0, 255, //
+            0, 0, 7, 0, 0, 0, 0,
+        ]);
+        assert_eq!(
+            BTreeNode::read(page),
+            BTreeNode::Internal {
+                cells: vec![
+                    KeyCell {
+                        key: Vec::new(),
+                        page_id: 7,
+                    },
+                    KeyCell {
+                        key: b"foo".to_vec(),
+                        page_id: 0xff00_0001,
+                    },
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn read_leaf_node() {
+        let rows = [
+            vec![DBValue::Integer(1), DBValue::Text(String::from("foo"))],
+            vec![DBValue::Null, DBValue::Boolean(true), DBValue::Real(0.5)],
+        ];
+        let mut page = [0; 4096];
+        page[0] = LEAF;
+        page[LEAF_NEXT] = 12;
+        page[LEAF_COUNT] = 3;
+        let mut end = USABLE_SIZE;
+        for (i, (key, row)) in [(3, &rows[1]), (9, &rows[0])].iter().enumerate() {
+            let mut cell = vec![1, 0, *key, 0];
+            write_row(&mut cell, row).unwrap();
+            end -= cell.len();
+            page[end..(end + cell.len())].copy_from_slice(&cell);
+            write_u16(&mut page[(LEAF_COUNT + 2 + i * 2)..], end);
+        }
+        // A row in overflow pages
+        end -= 9;
+        page[end..(end + 9)].copy_from_slice(&[2, 0, 10, 0, 1, 5, 0, 0, 0]);
+        write_u16(&mut page[(LEAF_COUNT + 6)..], end);
+        assert_eq!(
+            BTreeNode::read(page),
+            BTreeNode::Leaf {
+                prev: None,
+                next: Some(12),
+                cells: vec![
+                    DataCell {
+                        key: vec![3],
+                        row: rows[1].clone(),
+                        overflow: None,
+                    },
+                    DataCell {
+                        key: vec![9],
+                        row: rows[0].clone(),
+                        overflow: None,
+                    },
+                    DataCell {
+                        key: vec![10, 0],
+                        row: Vec::new(),
+                        overflow: Some(5),
+                    },
+                ],
+            }
+        );
+    }
+
+    /// A key ordered as the given number.
+    fn key(n: u32) -> Key {
+        n.to_be_bytes().to_vec()
+    }
+
+    /// The number a key was made from by [`key`].
+    fn number(key: &[u8]) -> u32 {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(key);
+        u32::from_be_bytes(bytes)
+    }
+
+    /// A leaf with the given rows, by key, and with the given siblings.
+    fn leaf(keys: &[u32], prev: Option<PageId>, next: Option<PageId>) -> BTreeNode {
+        let cells = keys
+            .iter()
+            .map(|n| DataCell {
+                key: key(*n),
+                row: vec![DBValue::Integer(*n as i64)],
+                overflow: None,
+            })
+            .collect();
+        BTreeNode::leaf(cells, prev, next)
+    }
+
+    /// An internal node with the given children, by their key and page.
+    fn internal(children: &[(u32, PageId)]) -> BTreeNode {
+        let cells = children
+            .iter()
+            .map(|(n, page_id)| KeyCell {
+                key: key(*n),
+                page_id: *page_id,
+            })
+            .collect();
+        BTreeNode::internal(cells)
+    }
+
+    #[test]
+    fn lookups_and_cursors() {
+        let nodes = [
+            internal(&[(0, 2), (100, 3)]),
+            internal(&[(0, 4), (20, 5)]),
+            internal(&[(100, 6), (150, 7)]),
+            leaf(&[0, 5, 10], None, Some(5)),
+            leaf(&[20, 25], Some(4), Some(6)),
+            leaf(&[], Some(5), Some(7)),
+            leaf(&[150, 160, 170], Some(6), None),
+        ];
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("btree");
+        let header = Header {
+            page_count: nodes.len() as PageId + 1,
+            freelist: None,
+            catalog_root: ROOT,
+            schema_cookie: 0,
+            encryption: None,
+        };
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        let mut pager = Pager::new(file).unwrap();
+        pager.write_page(0, &header.write()).unwrap();
+        for (page_id, node) in (ROOT..).zip(&nodes) {
+            pager.write_page(page_id, &node.write()).unwrap();
+        }
+        let tree = BTree::open(pager, Schema::new()).unwrap();
+        let keys =
+            |rows: Vec<(Key, Row)>| rows.iter().map(|(key, _)| number(key)).collect::<Vec<_>>();
+
+        assert_eq!(
+            tree.get(&key(25)).ok(),
+            Some(Some(vec![DBValue::Integer(25)]))
+        );
+        assert_eq!(
+            tree.get(&key(170)).ok(),
+            Some(Some(vec![DBValue::Integer(170)]))
+        );
+        for missing in &[1, 26, 100, 200] {
+            assert_eq!(tree.get(&key(*missing)).ok(), Some(None));
+        }
+
+        let mut cursor = tree.cursor().unwrap();
+        assert!(cursor.prev().unwrap().is_none());
+        let forward = cursor.by_ref().collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(keys(forward), vec![0, 5, 10, 20, 25, 150, 160, 170]);
+        let mut backward = Vec::new();
+        while let Some(row) = cursor.prev().unwrap() {
+            backward.push(row);
+        }
+        assert_eq!(keys(backward), vec![170, 160, 150, 25, 20, 10, 5, 0]);
+
+        cursor.seek(&key(11)).unwrap();
+        assert_eq!(cursor.next().unwrap().unwrap().0, key(20));
+        assert_eq!(cursor.prev().unwrap().unwrap().0, key(20));
+        assert_eq!(cursor.prev().unwrap().unwrap().0, key(10));
+        cursor.seek(&key(100)).unwrap();
+        assert_eq!(cursor.next().unwrap().unwrap().0, key(150));
+        cursor.seek(&key(101)).unwrap();
+        assert_eq!(cursor.prev().unwrap().unwrap().0, key(25));
+        cursor.seek(&key(171)).unwrap();
+        assert!(cursor.next().is_none());
+        // Keys shorter than the others order before them
+        cursor.seek(&[0, 0, 0]).unwrap();
+        assert_eq!(cursor.next().unwrap().unwrap().0, key(0));
+
+        // Scans follow the links between leaves, never reading the nodes above them again
+        let mut cursor = tree.cursor().unwrap();
+        let mut pages = std::fs::read(&path).unwrap();
+        for page in pages.chunks_mut(PAGE_SIZE).take(4).skip(2) {
+            page[0] = 0xff;
+        }
+        std::fs::write(&path, &pages).unwrap();
+        let forward = cursor.by_ref().collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(keys(forward).len(), 8);
+    }
+
+    #[test]
+    fn inserts_and_splits() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("splits");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        let mut tree = BTree::create(Pager::new(file).unwrap(), Schema::new()).unwrap();
+        let row = |n: u32| {
+            let text = "x".repeat(n as usize % 100);
+            vec![DBValue::Integer(n as i64), DBValue::Text(text)]
+        };
+        // Enough keys, in no particular order, to split the root both as a leaf and as an
+        // internal node
+        let count: u32 = 20_000;
+        for i in 0..count {
+            let n = i.wrapping_mul(7919) % count;
+            tree.insert(&key(n), &row(n)).unwrap();
+        }
+        tree.insert(&key(5), &vec![DBValue::Null]).unwrap();
+        let long = vec![b'x'; MAX_KEY_SIZE + 1];
+        let error = tree.insert(&long, &vec![DBValue::Null]).unwrap_err();
+        assert_eq!(error.to_string(), "Key too large");
+        let child = tree.node(tree.root.internal_cells()[0].page_id).unwrap();
+        assert!(matches!(child, BTreeNode::Internal { .. }));
+
+        let tree = BTree::open(
+            Pager::new(File::open(&path).unwrap()).unwrap(),
+            Schema::new(),
+        )
+        .unwrap();
+        assert_eq!(tree.get(&key(5)).unwrap(), Some(vec![DBValue::Null]));
+        assert_eq!(tree.get(&key(12_345)).unwrap(), Some(row(12_345)));
+        assert_eq!(tree.get(&key(count)).unwrap(), None);
+        let mut cursor = tree.cursor().unwrap();
+        let forward = cursor.by_ref().collect::<io::Result<Vec<_>>>().unwrap();
+        assert!(forward.iter().map(|(key, _)| number(key)).eq(0..count));
+        assert!(forward
+            .iter()
+            .all(|(key, value)| number(key) == 5 || *value == row(number(key))));
+        let mut backward = Vec::new();
+        while let Some((key, _)) = cursor.prev().unwrap() {
+            backward.push(number(&key));
+        }
+        assert!(backward.into_iter().eq((0..count).rev()));
+        let file_pages = std::fs::metadata(&path).unwrap().len() / PAGE_SIZE as u64;
+        assert_eq!(tree.header().page_count as u64, file_pages);
+
+        // Deleting all but every hundredth key empties whole leaves, which cursors step over
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let mut tree = BTree::open(Pager::new(file).unwrap(), Schema::new()).unwrap();
+        for n in (0..count).filter(|n| n % 100 != 0) {
+            assert!(tree.delete(&key(n)).unwrap());
+        }
+        assert!(!tree.delete(&key(1)).unwrap());
+        assert!(!tree.insert(&key(1), &row(1)).unwrap());
+        assert!(tree.insert(&key(1), &row(2)).unwrap());
+        let mut cursor = tree.cursor().unwrap();
+        let forward = cursor.by_ref().collect::<io::Result<Vec<_>>>().unwrap();
+        let expected: Vec<_> = (0..count).filter(|n| n % 100 == 0 || *n == 1).collect();
+        assert_eq!(
+            forward
+                .iter()
+                .map(|(key, _)| number(key))
+                .collect::<Vec<_>>(),
+            expected
+        );
+        let mut backward = Vec::new();
+        while let Some((key, _)) = cursor.prev().unwrap() {
+            backward.push(number(&key));
+        }
+        assert!(backward.into_iter().eq(expected.into_iter().rev()));
+    }
+
+    #[test]
+    fn bulk_loads() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("load");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        let mut tree = BTree::create(Pager::new(file).unwrap(), Schema::new()).unwrap();
+        let row = |n: u32| vec![DBValue::Text("x".repeat(n as usize % 100 * 20))];
+        for count in [0, 1, 64, 65, 20_000].iter().copied() {
+            tree.load((0..count).map(|n| (key(n * 2), row(n * 2))))
+                .unwrap();
+            let mut cursor = tree.cursor().unwrap();
+            let forward = cursor.by_ref().collect::<io::Result<Vec<_>>>().unwrap();
+            assert!(forward
+                .iter()
+                .map(|(key, _)| number(key))
+                .eq((0..count).map(|n| n * 2)));
+            assert!(forward
+                .iter()
+                .all(|(key, value)| *value == row(number(key))));
+            let mut backward = Vec::new();
+            while let Some((key, _)) = cursor.prev().unwrap() {
+                backward.push(number(&key));
+            }
+            assert!(backward.into_iter().eq((0..count).rev().map(|n| n * 2)));
+        }
+        // Leaves filled as far as they go, over two levels of internal nodes, with the larger
+        // rows overflowing into pages of their own
+        let mut leaves = Vec::new();
+        let mut page_id = Some(tree.root.internal_cells()[0].page_id);
+        while let BTreeNode::Internal { cells } = tree.node(page_id.unwrap()).unwrap() {
+            page_id = Some(cells[0].page_id);
+        }
+        while let Some(id) = page_id.take() {
+            let leaf = tree.node(id).unwrap();
+            if let BTreeNode::Leaf { next, .. } = leaf {
+                page_id = next;
+            }
+            leaves.push(leaf);
+        }
+        let child = tree.node(tree.root.internal_cells()[0].page_id).unwrap();
+        assert!(matches!(child, BTreeNode::Internal { .. }));
+        for pair in leaves.windows(2) {
+            let mut grown = pair[0].clone();
+            if let BTreeNode::Leaf { cells, .. } = &mut grown {
+                cells.push(pair[1].leaf_cells()[0].clone());
+            }
+            assert!(!grown.fits());
+        }
+        let cells = leaves.iter().flat_map(BTreeNode::leaf_cells);
+        let overflowing = cells.filter(|cell| cell.overflow.is_some()).count() as PageId;
+        let parents = tree.root.cell_count() as PageId;
+        assert_eq!(
+            tree.header().page_count,
+            ROOT + 1 + overflowing + leaves.len() as PageId + parents
+        );
+        for n in [0, 1, 12_345, 39_998, 40_001].iter().copied() {
+            tree.insert(&key(n), &vec![DBValue::Null]).unwrap();
+            assert_eq!(tree.get(&key(n)).unwrap(), Some(vec![DBValue::Null]));
+        }
+        assert_eq!(tree.get(&key(12_346)).unwrap(), Some(row(12_346)));
+        assert_eq!(tree.cursor().unwrap().count(), 20_003);
+    }
+
+    #[test]
+    fn truncates_separators() {
+        assert_eq!(shortest_separator(b"apple", b"apricot"), b"apr".to_vec());
+        assert_eq!(shortest_separator(b"ab", b"abc"), b"abc".to_vec());
+        assert_eq!(shortest_separator(b"", b"b"), b"b".to_vec());
+
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("prefixes");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        let mut tree = BTree::create(Pager::new(file).unwrap(), Schema::new()).unwrap();
+        // Long keys that differ from their neighbours in their first few bytes
+        let long = |n: u32| [key(n), vec![b'k'; 200]].concat();
+        let separators = |tree: &BTree| {
+            let cells = tree.root.internal_cells();
+            assert!(cells.len() > 1);
+            cells.into_iter().skip(1).map(|cell| cell.key)
+        };
+        let count = 2_000;
+        for n in 0..count {
+            tree.insert(&long(n), &vec![DBValue::Integer(n as i64)])
+                .unwrap();
+        }
+        let keys = |tree: &BTree| {
+            let rows = tree.cursor().unwrap().collect::<io::Result<Vec<_>>>();
+            rows.unwrap().into_iter().map(|(key, _)| key)
+        };
+        assert!(keys(&tree).eq((0..count).map(long)));
+        assert!(separators(&tree).all(|key| key.len() < long(0).len()));
+
+        tree.load((0..count).map(|n| (long(n), vec![DBValue::Integer(n as i64)])))
+            .unwrap();
+        assert!(keys(&tree).eq((0..count).map(long)));
+        assert!(separators(&tree).all(|key| key.len() < long(0).len()));
+        for n in 0..count {
+            assert_eq!(
+                tree.get(&long(n)).unwrap(),
+                Some(vec![DBValue::Integer(n as i64)])
+            );
+        }
+        assert_eq!(tree.get(&key(5)).unwrap(), None);
+    }
+
+    #[test]
+    fn header_validation() {
+        let header = Header {
+            page_count: 9,
+            freelist: Some(4),
+            catalog_root: ROOT,
+            schema_cookie: 3,
+            encryption: Some(Kdf {
+                salt: [5; SALT_SIZE],
+                iterations: 1000,
+            }),
+        };
+        let page = header.write();
+        assert_eq!(Header::read(&page, 9).ok(), Some(header));
+        let error = |page: &Page, file_pages| Header::read(page, file_pages).unwrap_err();
+        assert_eq!(
+            error(&page, 8).to_string(),
+            "Corrupt header: invalid page count"
+        );
+        let mut foreign = page;
+        foreign[..4].copy_from_slice(b"\x7fELF");
+        assert_eq!(
+            error(&foreign, 9).to_string(),
+            "Not a juicydb database file"
+        );
+        let mut version = page;
+        version[8] = 3;
+        assert_eq!(
+            error(&version, 9).to_string(),
+            "Unsupported file format version 3"
+        );
+        let mut page_size = page;
+        page_size[13] = 0x20;
+        assert_eq!(
+            error(&page_size, 9).to_string(),
+            "Unsupported page size 8192"
+        );
+        let mut freelist = page;
+        freelist[20] = 9;
+        assert_eq!(
+            error(&freelist, 9).to_string(),
+            "Corrupt header: invalid freelist head"
+        );
+        let mut root = page;
+        root[24] = 2;
+        assert_eq!(
+            error(&root, 9).to_string(),
+            "Corrupt header: invalid root page"
+        );
+
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("header");
+        std::fs::write(&path, b"not a database").unwrap();
+        let pager = Pager::new(File::open(&path).unwrap()).unwrap();
+        let opened = BTree::open(pager, Schema::new());
+        assert_eq!(
+            opened.err().map(|err| err.to_string()).as_deref(),
+            Some("Not a juicydb database file")
+        );
+    }
+}
@@ -0,0 +1,252 @@
+//! Encryption of pages at rest with XChaCha20-Poly1305, keyed by a passphrase. The 256-bit key
+//! is derived from the passphrase with PBKDF2-HMAC-SHA-256 over a random salt; the salt and the
+//! number of iterations are stored in the header of the file, which stays readable, so that the
+//! key can be derived again when the file is opened. Each page is encrypted with a fresh random
+//! 192-bit nonce, and authenticated along with its page id, so that pages can neither be altered
+//! nor moved around in the file without reading them failing.
+//!
+//! The primitives are those of the RustCrypto crates, and salts and nonces come from the random
+//! numbers of the operating system, under the 'encryption' feature. Without it, or on wasm32,
+//! where there are no random numbers of the operating system to call on, a cipher can't be made,
+//! so encrypted files can be neither created nor opened.
+
+#[cfg(all(feature = "encryption", not(target_arch = "wasm32")))]
+use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+#[cfg(all(feature = "encryption", not(target_arch = "wasm32")))]
+use chacha20poly1305::{Key, Tag, XChaCha20Poly1305, XNonce};
+#[cfg(all(feature = "encryption", not(target_arch = "wasm32")))]
+use sha2::Sha256;
+use std::fmt;
+use std::io;
+
+/// Size of a nonce in bytes.
+pub const NONCE_SIZE: usize = 24;
+
+/// Size of an authentication tag in bytes.
+pub const TAG_SIZE: usize = 16;
+
+/// Size of a salt in bytes.
+pub const SALT_SIZE: usize = 16;
+
+/// Number of PBKDF2 iterations for new keys.
+pub const DEFAULT_ITERATIONS: u32 = 100_000;
+
+/// Parameters for deriving the key from the passphrase, stored in the header of the file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Kdf {
+    pub salt: [u8; SALT_SIZE],
+    pub iterations: u32,
+}
+
+/// Key for encrypting and decrypting pages, along with the parameters it was derived with.
+#[derive(Clone)]
+pub struct Cipher {
+    kdf: Kdf,
+    #[cfg(all(feature = "encryption", not(target_arch = "wasm32")))]
+    aead: XChaCha20Poly1305,
+}
+
+impl fmt::Debug for Cipher {
+    /// Leaves the key out, so that it doesn't end up in logs.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Cipher").field("kdf", &self.kdf).finish()
+    }
+}
+
+impl Cipher {
+    /// A key derived from the passphrase with a new random salt.
+    pub fn new(passphrase: &str) -> io::Result<Self> {
+        let kdf = Kdf {
+            salt: random_bytes()?,
+            iterations: DEFAULT_ITERATIONS,
+        };
+        Self::derive(passphrase, kdf)
+    }
+
+    /// The key derived from the passphrase with the given parameters.
+    #[cfg(all(feature = "encryption", not(target_arch = "wasm32")))]
+    pub fn derive(passphrase: &str, kdf: Kdf) -> io::Result<Self> {
+        let mut key = Key::default();
+        pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), &kdf.salt, kdf.iterations, &mut key);
+        Ok(Self {
+            kdf,
+            aead: XChaCha20Poly1305::new(&key),
+        })
+    }
+
+    #[cfg(any(not(feature = "encryption"), target_arch = "wasm32"))]
+    pub fn derive(_: &str, _: Kdf) -> io::Result<Self> {
+        Err(unavailable())
+    }
+
+    /// A random key, with no passphrase to derive it again, for files kept no longer than the
+    /// key, such as the runs of rows a sort spills.
+    #[cfg(all(feature = "encryption", not(target_arch = "wasm32")))]
+    pub fn random() -> io::Result<Self> {
+        let key: [u8; 32] = random_bytes()?;
+        let kdf = Kdf {
+            salt: [0; SALT_SIZE],
+            iterations: 0,
+        };
+        Ok(Self {
+            kdf,
+            aead: XChaCha20Poly1305::new(Key::from_slice(&key)),
+        })
+    }
+
+    #[cfg(any(not(feature = "encryption"), target_arch = "wasm32"))]
+    pub fn random() -> io::Result<Self> {
+        Err(unavailable())
+    }
+
+    pub fn kdf(&self) -> Kdf {
+        self.kdf
+    }
+
+    /// Encrypts the bytes in place with a new random nonce, returning the nonce and the tag
+    /// authenticating the bytes along with the associated data.
+    pub fn encrypt(
+        &self,
+        bytes: &mut [u8],
+        data: &[u8],
+    ) -> io::Result<([u8; NONCE_SIZE], [u8; TAG_SIZE])> {
+        let nonce = random_bytes::<NONCE_SIZE>()?;
+        let tag = self.seal(&nonce, bytes, data)?;
+        Ok((nonce, tag))
+    }
+
+    /// Encrypts the bytes in place with the given nonce, returning the tag.
+    #[cfg(all(feature = "encryption", not(target_arch = "wasm32")))]
+    fn seal(
+        &self,
+        nonce: &[u8; NONCE_SIZE],
+        bytes: &mut [u8],
+        data: &[u8],
+    ) -> io::Result<[u8; TAG_SIZE]> {
+        let tag = self
+            .aead
+            .encrypt_in_place_detached(XNonce::from_slice(nonce), data, bytes)
+            .map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidInput, "Too many bytes to encrypt")
+            })?;
+        Ok(tag.into())
+    }
+
+    #[cfg(any(not(feature = "encryption"), target_arch = "wasm32"))]
+    fn seal(&self, _: &[u8; NONCE_SIZE], _: &mut [u8], _: &[u8]) -> io::Result<[u8; TAG_SIZE]> {
+        Err(unavailable())
+    }
+
+    /// Decrypts the bytes in place, or leaves them as they are and returns false if the tag
+    /// doesn't authenticate them along with the associated data, e.g. as the key is wrong.
+    #[cfg(all(feature = "encryption", not(target_arch = "wasm32")))]
+    pub fn decrypt(
+        &self,
+        bytes: &mut [u8],
+        data: &[u8],
+        nonce: &[u8; NONCE_SIZE],
+        tag: &[u8; TAG_SIZE],
+    ) -> bool {
+        // The tag is checked in constant time before anything is decrypted
+        self.aead
+            .decrypt_in_place_detached(XNonce::from_slice(nonce), data, bytes, Tag::from_slice(tag))
+            .is_ok()
+    }
+
+    #[cfg(any(not(feature = "encryption"), target_arch = "wasm32"))]
+    pub fn decrypt(
+        &self,
+        _: &mut [u8],
+        _: &[u8],
+        _: &[u8; NONCE_SIZE],
+        _: &[u8; TAG_SIZE],
+    ) -> bool {
+        false
+    }
+}
+
+/// Bytes from the random number generator of the operating system, for salts and nonces.
+#[cfg(all(feature = "encryption", not(target_arch = "wasm32")))]
+fn random_bytes<const N: usize>() -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    getrandom::fill(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(any(not(feature = "encryption"), target_arch = "wasm32"))]
+fn random_bytes<const N: usize>() -> io::Result<[u8; N]> {
+    Err(unavailable())
+}
+
+#[cfg(any(not(feature = "encryption"), target_arch = "wasm32"))]
+fn unavailable() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Encryption needs juicydb built with the 'encryption' feature",
+    )
+}
+
+#[cfg(all(test, feature = "encryption", not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..(i + 2)], 16).unwrap())
+            .collect()
+    }
+
+    fn cipher(passphrase: &str) -> Cipher {
+        let kdf = Kdf {
+            salt: [7; SALT_SIZE],
+            iterations: 2,
+        };
+        Cipher::derive(passphrase, kdf).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Pages encrypted before the primitives came from RustCrypto still decrypt.
+    #[test]
+    fn known_answer() {
+        let mut nonce = [0; NONCE_SIZE];
+        for (i, byte) in nonce.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let mut bytes = b"rows that no one else may read".to_vec();
+        let tag = cipher("secret").seal(&nonce, &mut bytes, b"page 1").ok();
+        assert_eq!(
+            bytes,
+            hex("32c523886ba3799194fc97aff17254b7553985b5a1c148f76141a1d85ea3")
+        );
+        assert_eq!(
+            tag.map(Vec::from),
+            Some(hex("14ff364bc202a330595fac22bf71451b"))
+        );
+    }
+
+    #[test]
+    fn encrypts_and_authenticates() {
+        let cipher = cipher("secret");
+        let encrypt = |bytes: &mut [u8]| {
+            cipher
+                .encrypt(bytes, b"page 1")
+                .unwrap_or_else(|err| panic!("{}", err))
+        };
+        let plaintext = b"rows that no one else may read".to_vec();
+        let mut bytes = plaintext.clone();
+        let (nonce, tag) = encrypt(&mut bytes);
+        assert_ne!(bytes, plaintext);
+        let mut again = plaintext.clone();
+        assert_ne!(encrypt(&mut again).0, nonce);
+
+        let mut tampered = bytes.clone();
+        tampered[0] ^= 1;
+        assert!(!cipher.decrypt(&mut tampered, b"page 1", &nonce, &tag));
+        let mut moved = bytes.clone();
+        assert!(!cipher.decrypt(&mut moved, b"page 2", &nonce, &tag));
+        let wrong = Cipher::derive("guess", cipher.kdf()).unwrap_or_else(|err| panic!("{}", err));
+        assert!(!wrong.decrypt(&mut bytes.clone(), b"page 1", &nonce, &tag));
+        assert!(cipher.decrypt(&mut bytes, b"page 1", &nonce, &tag));
+        assert_eq!(bytes, plaintext);
+    }
+}
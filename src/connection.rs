@@ -0,0 +1,1262 @@
+//! Connections to a database shared by any number of them, e.g. one per thread. Each connection
+//! runs its statements in a session of its own, with its own transaction and settings, so that
+//! connections see each other's changes only once committed, as their isolation level allows;
+//! see [`crate::transaction`]. Statements of different connections take turns on the database.
+
+use crate::db::*;
+use crate::from_row::{FromRow, FromRowError, ResultRow};
+use crate::functions::{Aggregate, AggregateFunction, ScalarFunction};
+use crate::hooks::RowChange;
+use crate::params::ToValue;
+use crate::parser::*;
+use crate::progress::InterruptHandle;
+#[cfg(feature = "sqlite")]
+use crate::sqlite;
+use crate::storage_manager::*;
+use crate::transaction::TransactionId;
+use crate::virtual_table::VirtualTable;
+use std::cell::Cell;
+use std::fmt;
+use std::io::{Read, Write};
+use std::ops::ControlFlow;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// First pause before retrying a statement that failed on the locks of other connections.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(1);
+
+/// Longest pause between retries of a statement.
+const MAX_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Savepoint a script runs under within a transaction; see [`Connection::execute_batch`].
+const BATCH_SAVEPOINT: &str = "execute_batch";
+
+/// Session over a database, executing SQL text. A transaction still in progress as the
+/// connection is dropped is rolled back.
+pub struct Connection {
+    /// The database, shared with the other connections to it.
+    storage: Arc<Mutex<StorageManager>>,
+    session: Session,
+}
+
+/// Error from either parsing or executing a statement.
+pub enum Error {
+    Parse(ParseError),
+    Storage(StorageError),
+    /// A row of a result couldn't be read as the Rust type asked for.
+    FromRow(FromRowError),
+    /// A statement of a script failed; see [`Connection::execute_batch`].
+    Batch {
+        /// Position of the statement in the script, from 1.
+        statement: usize,
+        /// Text of the statement.
+        sql: String,
+        error: Box<Error>,
+    },
+}
+
+/// Shows the message of the error, as storage errors have no other representation, so that
+/// e.g. `main` can return the error.
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Parse(err) => write!(f, "Parse error: {}", err),
+            Self::Storage(err) => write!(f, "SQL error: {}", err),
+            Self::FromRow(err) => write!(f, "Conversion error: {}", err),
+            Self::Batch {
+                statement,
+                sql,
+                error,
+            } => write!(f, "Statement {} failed, '{}': {}", statement, sql, error),
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Self {
+        Self::Parse(err)
+    }
+}
+
+impl From<StorageError> for Error {
+    fn from(err: StorageError) -> Self {
+        Self::Storage(err)
+    }
+}
+
+impl From<FromRowError> for Error {
+    fn from(err: FromRowError) -> Self {
+        Self::FromRow(err)
+    }
+}
+
+/// Statement parsed once by [`Connection::prepare`], to be executed any number of times with
+/// different values bound to its `?` placeholders.
+pub struct PreparedStatement {
+    statement: Statement,
+    /// Text of the statement, for the spans of its executions.
+    sql: String,
+}
+
+impl PreparedStatement {
+    pub fn parameter_count(&self) -> usize {
+        self.statement.parameter_count()
+    }
+
+    /// Whether the statement is a query, to be run by [`Connection::query_prepared`].
+    pub fn is_query(&self) -> bool {
+        matches!(self.statement, Statement::Select { .. })
+    }
+}
+
+impl Connection {
+    /// A connection to a new database of its own, keeping its tables in memory.
+    pub fn new() -> Self {
+        Self::from(StorageManager::new())
+    }
+
+    /// A connection to a new database storing its tables in the given directory, or in memory
+    /// for `:memory:`; see [`StorageManager::with_location`].
+    pub fn with_location(location: &str) -> Self {
+        Self::from(StorageManager::with_location(location))
+    }
+
+    /// A connection to the database saved in the given file; see [`StorageManager::open`].
+    pub fn open(path: &str) -> Result<Self, Error> {
+        Ok(Self::from(StorageManager::open(path)?))
+    }
+
+    /// A connection to the database saved encrypted in the given file; see
+    /// [`StorageManager::open_encrypted`].
+    pub fn open_encrypted(path: &str, passphrase: &str) -> Result<Self, Error> {
+        Ok(Self::from(StorageManager::open_encrypted(
+            path, passphrase,
+        )?))
+    }
+
+    /// A connection to the database in the bytes of a file, kept in memory; see
+    /// [`StorageManager::from_bytes`].
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, Error> {
+        Ok(Self::from(StorageManager::from_bytes(bytes)?))
+    }
+
+    /// A new connection to the same database, in a session of its own.
+    pub fn connect(&self) -> Connection {
+        Self {
+            storage: Arc::clone(&self.storage),
+            session: Session::new(),
+        }
+    }
+
+    fn storage(&self) -> MutexGuard<'_, StorageManager> {
+        self.storage.lock().unwrap()
+    }
+
+    /// The id of the transaction in progress on the connection, if any.
+    pub fn transaction(&self) -> Option<TransactionId> {
+        self.session.transaction()
+    }
+
+    /// Sets how long statements wait for the locks of other connections; see
+    /// [`Session::busy_timeout`].
+    pub fn set_busy_timeout(&mut self, timeout: Duration) {
+        self.session.set_busy_timeout(timeout);
+    }
+
+    /// Sets the hook called when the changes of the connection are committed; see
+    /// [`crate::hooks`].
+    pub fn set_commit_hook(&mut self, hook: impl FnMut() + Send + 'static) {
+        self.session.hooks_mut().set_commit(hook);
+    }
+
+    /// Sets the hook called when a transaction of the connection is rolled back.
+    pub fn set_rollback_hook(&mut self, hook: impl FnMut() + Send + 'static) {
+        self.session.hooks_mut().set_rollback(hook);
+    }
+
+    /// Sets the hook called for each row the connection inserts, updates or deletes.
+    pub fn set_update_hook(&mut self, hook: impl FnMut(&RowChange<'_>) + Send + 'static) {
+        self.session.hooks_mut().set_update(hook);
+    }
+
+    /// Removes the hooks of the connection.
+    pub fn clear_hooks(&mut self) {
+        self.session.hooks_mut().clear();
+    }
+
+    /// A handle interrupting the statement the connection is running, from another thread;
+    /// including while it waits for the locks of other connections. See [`crate::progress`].
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.session.progress().interrupt_handle()
+    }
+
+    /// Sets the handler called every `steps` steps of the statements of the connection, which
+    /// interrupts the statement by breaking; see [`crate::progress`].
+    pub fn set_progress_handler(
+        &mut self,
+        steps: u64,
+        handler: impl FnMut() -> ControlFlow<()> + Send + 'static,
+    ) {
+        self.session.progress_mut().set_handler(steps, handler);
+    }
+
+    pub fn clear_progress_handler(&mut self) {
+        self.session.progress_mut().clear_handler();
+    }
+
+    /// Runs `f` on the database in the session of the connection. While `f` fails on the locks
+    /// of other connections, it is run again after a pause, doubling up to [`MAX_BACKOFF`],
+    /// until the busy timeout of the session has passed. The database is free for other
+    /// connections during the pauses. In browsers, which have neither the clock nor the threads
+    /// to wait with, `f` is run just once.
+    fn run<T>(
+        &mut self,
+        f: impl FnMut(&mut StorageManager) -> Result<T, StorageError>,
+    ) -> Result<T, StorageError> {
+        lock_in_session(&self.storage, &mut self.session, f).map(|(_, result)| result)
+    }
+
+    /// Runs `f` on the database as [`Connection::run`] does, in an `execute` span of the
+    /// statement under the 'tracing' feature. The span records the rows of the result, as
+    /// counted by `rows`, and the pages of the database read and written meanwhile.
+    fn run_statement<T>(
+        &mut self,
+        sql: &str,
+        f: impl FnMut(&mut StorageManager) -> Result<T, StorageError>,
+        rows: impl FnOnce(&T) -> usize,
+    ) -> Result<T, StorageError> {
+        #[cfg(feature = "tracing")]
+        {
+            use tracing::field::Empty;
+            let span = tracing::debug_span!(
+                "execute",
+                sql,
+                rows = Empty,
+                pages_read = Empty,
+                pages_written = Empty
+            );
+            let _entered = span.enter();
+            let mut f = f;
+            let mut pages = (0, 0);
+            let result = self.run(|storage| {
+                let (read, written) = storage.page_counts();
+                let result = f(storage);
+                let (read_after, written_after) = storage.page_counts();
+                pages = (
+                    pages.0 + read_after - read,
+                    pages.1 + written_after - written,
+                );
+                result
+            });
+            span.record("pages_read", pages.0);
+            span.record("pages_written", pages.1);
+            if let Ok(result) = &result {
+                span.record("rows", rows(result));
+            }
+            result
+        }
+        #[cfg(not(feature = "tracing"))]
+        {
+            let _ = (sql, rows);
+            self.run(f)
+        }
+    }
+
+    /// Parses a single statement, failing with [`ParseError::MultipleStatements`] if there is
+    /// more input after it; see [`Connection::execute_batch`] for scripts.
+    pub fn prepare(&self, sql: &str) -> Result<PreparedStatement, Error> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("parse", sql).entered();
+        let mut parser = Parser::new(sql);
+        let command = parser.parse_command()?;
+        if !parser.at_end() {
+            return Err(Error::Parse(ParseError::MultipleStatements));
+        }
+        match command {
+            Command::Statement(statement) => Ok(PreparedStatement {
+                statement,
+                sql: String::from(sql),
+            }),
+            Command::MetaCommand(_) => Err(Error::Parse(ParseError::UnrecognizedStatement)),
+        }
+    }
+
+    /// Executes a single statement, failing without executing anything if the SQL holds more
+    /// than one. Statements are planned once and their plans cached, so executing the same SQL
+    /// text again skips parsing and planning; see [`StorageManager::cache_plan`].
+    pub fn execute(&mut self, sql: &str) -> Result<ResultSet, Error> {
+        let plan = self.plan(sql)?;
+        Ok(self.run_statement(
+            sql,
+            |storage| storage.execute_plan(plan.clone()),
+            |result| result.rows.len(),
+        )?)
+    }
+
+    /// The cached plan of a statement, or else its new plan, which gets cached. Plans are
+    /// shared by the connections to the database.
+    fn plan(&self, sql: &str) -> Result<Plan, Error> {
+        let storage = self.storage();
+        if let Some(plan) = storage.cached_plan(sql) {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(sql, "cached plan");
+            return Ok(plan);
+        }
+        let statement = self.prepare(sql)?.statement.bind(&[])?;
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("plan", sql).entered();
+        let plan = match statement {
+            Statement::Select { .. } => Plan::Query(storage.plan(statement)?),
+            statement => Plan::Statement(statement),
+        };
+        storage.cache_plan(sql, plan.clone());
+        Ok(plan)
+    }
+
+    /// Saves the database to the file it was opened from; see [`StorageManager::save`].
+    pub fn save(&mut self) -> Result<(), Error> {
+        Ok(self.run(StorageManager::save)?)
+    }
+
+    /// Writes a copy of the database as of the last commit to the given file; see
+    /// [`StorageManager::backup`].
+    pub fn backup(&mut self, path: &str) -> Result<(), Error> {
+        Ok(self.run(|storage| storage.backup(path))?)
+    }
+
+    /// The database as of the last commit, as the bytes of a file; see
+    /// [`StorageManager::to_bytes`].
+    pub fn to_bytes(&mut self) -> Result<Vec<u8>, Error> {
+        Ok(self.run(|storage| storage.to_bytes())?)
+    }
+
+    /// The statements recreating the given table or view, or all of them; see
+    /// [`StorageManager::schema`].
+    pub fn schema(&mut self, name: Option<&str>) -> Result<Vec<String>, Error> {
+        Ok(self.run(|storage| storage.schema(name))?)
+    }
+
+    /// The resources used by the database; see [`StorageManager::stats`].
+    pub fn stats(&self) -> Stats {
+        self.storage().stats()
+    }
+
+    /// The numbers of pages of the database read and written so far; see
+    /// [`StorageManager::page_counts`].
+    pub fn page_counts(&self) -> (u64, u64) {
+        self.storage().page_counts()
+    }
+
+    /// The number of rows fetched from the tables of the database so far; see
+    /// [`StorageManager::rows_scanned`].
+    pub fn rows_scanned(&self) -> u64 {
+        self.storage().rows_scanned()
+    }
+
+    /// Writes the rows of the table as JSON objects, one per line; see
+    /// [`StorageManager::export_json`].
+    pub fn export_json(&mut self, table: &str, mut out: impl Write) -> Result<usize, Error> {
+        Ok(self.run(|storage| storage.export_json(table, &mut out))?)
+    }
+
+    /// Inserts the rows read as JSON objects, one per line, into the table; see
+    /// [`StorageManager::import_json`]. The input is read as a whole first, as inserting may
+    /// have to wait for the locks of other connections and be tried again.
+    pub fn import_json(&mut self, table: &str, mut input: impl Read) -> Result<usize, Error> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes).map_err(StorageError::from)?;
+        Ok(self.run(|storage| storage.import_json(table, bytes.as_slice()))?)
+    }
+
+    /// Copies the tables of the SQLite database in the file along with their rows; see
+    /// [`crate::sqlite::import`].
+    #[cfg(feature = "sqlite")]
+    pub fn import_sqlite(&mut self, path: &str) -> Result<Vec<(String, usize)>, Error> {
+        Ok(self.run(|storage| sqlite::import(storage, path))?)
+    }
+
+    /// Flushes the tables stored in files to the disk; see [`StorageManager::checkpoint`].
+    pub fn checkpoint(&mut self) -> Result<(), Error> {
+        Ok(self.run(StorageManager::checkpoint)?)
+    }
+
+    /// Sets the number of statements whose plans are cached for the database; zero disables the
+    /// cache.
+    pub fn set_plan_cache_size(&mut self, plans: usize) {
+        self.storage().set_plan_cache_size(plans);
+    }
+
+    /// Registers a function of `arity` arguments callable from expressions, for all connections
+    /// to the database. `f` gets the values of the arguments, and returns the value of the call
+    /// or an error message failing the statement. See [`crate::functions`].
+    pub fn create_function<F, T>(&mut self, name: &str, arity: usize, f: F)
+    where
+        F: Fn(&[DBValue]) -> Result<T, String> + Send + Sync + 'static,
+        T: ToValue,
+    {
+        self.storage()
+            .create_function(name, ScalarFunction::new(arity, f));
+    }
+
+    /// Registers an aggregate function of `arity` arguments callable from expressions, for all
+    /// connections to the database; see [`crate::functions::Aggregate`].
+    pub fn create_aggregate(&mut self, name: &str, arity: usize, aggregate: impl Aggregate) {
+        self.storage()
+            .create_aggregate(name, AggregateFunction::new(arity, aggregate));
+    }
+
+    /// Registers a module making virtual tables with `create virtual table <name> using
+    /// <module>(<arg>, ...);`, for all connections to the database. `f` gets the values of the
+    /// arguments, and returns the table or an error message. See [`crate::virtual_table`].
+    pub fn create_module<F>(&mut self, name: &str, f: F)
+    where
+        F: Fn(&[DBValue]) -> Result<Box<dyn VirtualTable>, String> + Send + Sync + 'static,
+    {
+        self.storage().create_module(name, Arc::new(f));
+    }
+
+    /// Executes a prepared statement with `params` bound to its placeholders, in order.
+    pub fn execute_prepared(
+        &mut self,
+        statement: &PreparedStatement,
+        params: &[DBValue],
+    ) -> Result<ResultSet, Error> {
+        let sql = &statement.sql;
+        let statement = statement.statement.bind(params)?;
+        Ok(self.run_statement(
+            sql,
+            |storage| storage.execute(statement.clone()),
+            |result| result.rows.len(),
+        )?)
+    }
+
+    /// Runs a query, returning a cursor producing its rows as it is iterated, which holds the
+    /// database until dropped; see [`Cursor`].
+    pub fn query(&mut self, sql: &str) -> Result<Cursor<'_>, Error> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("query", sql).entered();
+        match self.plan(sql)? {
+            Plan::Query(plan) => Ok(Cursor::new(self, plan)?),
+            Plan::Statement(_) => Err(Error::Storage(StorageError::SchemaMismatch)),
+        }
+    }
+
+    /// Executes the statements of an SQL script in turn, returning their results. The script is
+    /// parsed as a whole first, so a syntax error anywhere executes nothing. Stops at the first
+    /// statement that fails, returning an [`Error::Batch`] with the statement. In a transaction,
+    /// the statements before are then undone: the script runs in a transaction of its own, or
+    /// under a savepoint if a transaction is in progress already.
+    pub fn execute_batch(
+        &mut self,
+        sql: &str,
+        in_transaction: bool,
+    ) -> Result<Vec<ResultSet>, Error> {
+        #[cfg(feature = "tracing")]
+        let parse_span = tracing::debug_span!("parse", sql).entered();
+        let mut parser = Parser::new(sql);
+        let mut statements = Vec::new();
+        while !parser.at_end() {
+            let start = parser.offset();
+            let parsed = parser.parse_command();
+            if parsed.is_err() {
+                parser.skip_statement();
+            }
+            let text = String::from(sql[start..parser.offset()].trim());
+            let failed = |error: Error| Error::Batch {
+                statement: statements.len() + 1,
+                sql: text.clone(),
+                error: Box::new(error),
+            };
+            match parsed {
+                Ok(Command::Statement(statement)) => statements.push((text, statement)),
+                Ok(Command::MetaCommand(_)) => {
+                    return Err(failed(Error::Parse(ParseError::UnrecognizedStatement)))
+                }
+                Err(err) => return Err(failed(Error::Parse(err))),
+            }
+        }
+        #[cfg(feature = "tracing")]
+        drop(parse_span);
+        let savepoint = self.session.transaction().is_some();
+        if in_transaction {
+            self.run(|storage| match savepoint {
+                true => storage.savepoint(String::from(BATCH_SAVEPOINT)),
+                false => storage.begin().map(|_| ()),
+            })?;
+        }
+        let mut results = Vec::new();
+        for (position, (text, statement)) in statements.into_iter().enumerate() {
+            let result = self.run_statement(
+                &text,
+                |storage| storage.execute(statement.clone()),
+                |result| result.rows.len(),
+            );
+            match result {
+                Ok(result) => results.push(result),
+                Err(error) => {
+                    if in_transaction {
+                        self.run(|storage| match savepoint {
+                            true => storage
+                                .rollback_to(String::from(BATCH_SAVEPOINT))
+                                .and_then(|_| storage.release(String::from(BATCH_SAVEPOINT))),
+                            false => storage.rollback(),
+                        })?;
+                    }
+                    return Err(Error::Batch {
+                        statement: position + 1,
+                        sql: text,
+                        error: Box::new(Error::Storage(error)),
+                    });
+                }
+            }
+        }
+        if in_transaction {
+            self.run(|storage| match savepoint {
+                true => storage.release(String::from(BATCH_SAVEPOINT)),
+                false => storage.commit(),
+            })?;
+        }
+        Ok(results)
+    }
+
+    /// Runs a query, passing its rows to `f` one at a time without collecting them, until `f`
+    /// returns [`ControlFlow::Break`], whose value is returned; see
+    /// [`StorageManager::query_each`].
+    pub fn query_each<B>(
+        &mut self,
+        sql: &str,
+        mut f: impl FnMut(&ResultRow<'_>) -> ControlFlow<B>,
+    ) -> Result<Option<B>, Error> {
+        match self.plan(sql)? {
+            Plan::Query(plan) => {
+                let rows = Cell::new(0);
+                let mut f = |row: &ResultRow<'_>| {
+                    rows.set(rows.get() + 1);
+                    f(row)
+                };
+                Ok(self.run_statement(
+                    sql,
+                    |storage| storage.query_each(plan.clone(), &mut f),
+                    |_| rows.get(),
+                )?)
+            }
+            Plan::Statement(_) => Err(Error::Storage(StorageError::SchemaMismatch)),
+        }
+    }
+
+    /// Runs a query, reading each of its rows as a `T`; see [`crate::from_row`].
+    pub fn query_as<T: FromRow>(&mut self, sql: &str) -> Result<Vec<T>, Error> {
+        let rows = self.query(sql)?;
+        let columns = rows.columns().to_vec();
+        rows.map(|row| {
+            let row = row?;
+            Ok(T::from_row(&ResultRow::new(&columns, &row))?)
+        })
+        .collect()
+    }
+
+    /// Runs a prepared query with `params` bound to its placeholders, in order, returning a
+    /// cursor producing its rows; see [`Connection::query`].
+    pub fn query_prepared(
+        &mut self,
+        statement: &PreparedStatement,
+        params: &[DBValue],
+    ) -> Result<Cursor<'_>, Error> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("query", sql = statement.sql.as_str()).entered();
+        match statement.statement.bind(params)? {
+            query @ Statement::Select { .. } => {
+                let plan = self.storage().plan(query)?;
+                Ok(Cursor::new(self, plan)?)
+            }
+            _ => Err(Error::Storage(StorageError::SchemaMismatch)),
+        }
+    }
+}
+
+/// Runs `f` on the database as [`Connection::run`] does, returning the database still locked
+/// along with the result, for a cursor to go on with it.
+fn lock_in_session<'s, T>(
+    storage: &'s Mutex<StorageManager>,
+    session: &mut Session,
+    mut f: impl FnMut(&mut StorageManager) -> Result<T, StorageError>,
+) -> Result<(MutexGuard<'s, StorageManager>, T), StorageError> {
+    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+        let mut storage = storage.lock().unwrap();
+        let result = storage.in_session(session, &mut f)?;
+        return Ok((storage, result));
+    }
+    let started = Instant::now();
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        let mut storage = storage.lock().unwrap();
+        let result = storage.in_session(session, &mut f);
+        let waited = started.elapsed();
+        let timeout = session.busy_timeout();
+        match result {
+            Err(StorageError::TableLocked(_) | StorageError::RowLocked(_)) if waited < timeout => {
+                drop(storage);
+                thread::sleep(backoff.min(timeout - waited));
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                if session.progress().is_interrupted() {
+                    return Err(StorageError::Interrupted);
+                }
+            }
+            result => return result.map(|result| (storage, result)),
+        }
+    }
+}
+
+/// Cursor over the rows of a query, pulling them from the operator pipeline one at a time as it
+/// is iterated; see [`Connection::query`]. The cursor keeps the database, and the locks of the
+/// query, until it is dropped: other connections wait for it meanwhile, and the connection's
+/// thread must not use another connection to the database before dropping it.
+pub struct Cursor<'c> {
+    /// The rows, borrowing `storage`, so declared before it to be dropped first.
+    rows: Option<Rows<'c>>,
+    storage: MutexGuard<'c, StorageManager>,
+    /// The session of the connection, swapped out for that of the query while the cursor lives.
+    session: &'c mut Session,
+    owner: TransactionId,
+}
+
+impl<'c> Cursor<'c> {
+    /// Takes the locks of the query, waiting for other connections as [`Connection::run`]
+    /// does, and starts running it in the session.
+    fn new(connection: &'c mut Connection, plan: QueryPlan) -> Result<Self, StorageError> {
+        let Connection { storage, session } = connection;
+        let (storage, owner) =
+            lock_in_session(storage, session, |storage| storage.begin_query(&plan))?;
+        let mut cursor = Self {
+            rows: None,
+            storage,
+            session,
+            owner,
+        };
+        cursor.storage.swap_session(cursor.session);
+        let storage: *const StorageManager = &*cursor.storage;
+        // SAFETY: The database stays locked, at the same place in its mutex, for as long as the
+        // guard in the cursor, and the rows borrowing it are dropped before the guard.
+        let rows = unsafe { &*storage }.run(plan)?;
+        cursor.rows = Some(rows);
+        Ok(cursor)
+    }
+
+    /// Names and types of the columns of the rows.
+    pub fn columns(&self) -> &[ResultColumn] {
+        self.rows.as_ref().map_or(&[], Rows::columns)
+    }
+}
+
+impl Iterator for Cursor<'_> {
+    type Item = Result<Row, StorageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rows.as_mut()?.next()
+    }
+}
+
+/// Ends the query, releasing its locks and giving the session back to the connection.
+impl Drop for Cursor<'_> {
+    fn drop(&mut self) {
+        self.rows = None;
+        self.storage.end_query(self.owner);
+        self.storage.swap_session(self.session);
+    }
+}
+
+impl From<StorageManager> for Connection {
+    fn from(storage: StorageManager) -> Self {
+        Self {
+            storage: Arc::new(Mutex::new(storage)),
+            session: Session::new(),
+        }
+    }
+}
+
+impl fmt::Debug for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.storage().fmt(f)
+    }
+}
+
+impl Default for Connection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if self.session.transaction().is_some() {
+            // The rollback only fails if the tables can't be put back, and there is no one to
+            // report it to
+            self.run(StorageManager::rollback).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "tracing")]
+    use std::collections::HashMap;
+
+    #[test]
+    fn execute_prepared_statement() {
+        let mut conn = Connection::new();
+        assert!(conn
+            .execute("create table tbl (id integer, name text);")
+            .is_ok());
+        let insert = conn.prepare("insert into tbl values (?, ?);").ok().unwrap();
+        assert_eq!(insert.parameter_count(), 2);
+        for (id, name) in [(1, "foo"), (2, "bar")] {
+            let params = [DBValue::Integer(id), DBValue::Text(String::from(name))];
+            assert!(conn.execute_prepared(&insert, &params).is_ok());
+        }
+        let missing = conn.execute_prepared(&insert, &[DBValue::Integer(3)]);
+        assert!(matches!(
+            missing,
+            Err(Error::Parse(ParseError::ParameterCount { .. }))
+        ));
+        let select = conn
+            .prepare("select (name) from tbl where id = ?;")
+            .ok()
+            .unwrap();
+        let result = conn.execute_prepared(&select, &[DBValue::Integer(2)]).ok();
+        assert_eq!(
+            result,
+            Some(ResultSet {
+                columns: vec![ResultColumn {
+                    name: String::from("name"),
+                    db_type: Some(DBType::Text),
+                }],
+                rows: vec![vec![DBValue::Text(String::from("bar"))]],
+            })
+        );
+        let mut rows = conn
+            .query_prepared(&select, &[DBValue::Integer(1)])
+            .ok()
+            .unwrap();
+        assert_eq!(rows.columns().len(), 1);
+        assert_eq!(
+            rows.next().and_then(Result::ok),
+            Some(vec![DBValue::Text(String::from("foo"))])
+        );
+        assert!(rows.next().is_none());
+        drop(rows);
+        assert!(matches!(
+            conn.query("insert into tbl values (3, 'baz');"),
+            Err(Error::Storage(StorageError::SchemaMismatch))
+        ));
+        assert!(matches!(
+            conn.prepare(".exit"),
+            Err(Error::Parse(ParseError::UnrecognizedStatement))
+        ));
+    }
+
+    #[test]
+    fn single_statements() {
+        let mut conn = Connection::new();
+        assert!(conn.execute("create table t (a integer);").is_ok());
+        assert!(matches!(
+            conn.execute("insert into t values (1); insert into t values (2);"),
+            Err(Error::Parse(ParseError::MultipleStatements))
+        ));
+        assert!(conn.execute("insert into t values (1); -- one row").is_ok());
+        let rows = conn
+            .execute("select a from t;")
+            .ok()
+            .map(|result| result.rows);
+        assert_eq!(rows, Some(vec![vec![DBValue::Integer(1)]]));
+    }
+
+    #[test]
+    fn cached_plans() {
+        let mut conn = Connection::new();
+        for sql in &[
+            "create table t (a integer);",
+            "insert into t values (1);",
+            "insert into t values (1);",
+        ] {
+            assert!(conn.execute(sql).is_ok());
+        }
+        let select = "select a, count(*) from t group by a;";
+        let rows = conn.execute(select).ok().map(|result| result.rows);
+        let int = DBValue::Integer;
+        assert_eq!(rows, Some(vec![vec![int(1), int(2)]]));
+        assert!(conn
+            .storage()
+            .cached_plan("select a,  count(*)\nfrom t group by a;")
+            .is_some());
+        assert!(conn.execute("insert into t values (2);").is_ok());
+        let rows = conn.execute(select).ok().map(|result| result.rows);
+        assert_eq!(rows, Some(vec![vec![int(1), int(2)], vec![int(2), int(1)]]));
+        for sql in &[
+            "drop table t;",
+            "create table t (a text);",
+            "insert into t values ('x');",
+        ] {
+            assert!(conn.execute(sql).is_ok());
+        }
+        assert!(conn.storage().cached_plan(select).is_none());
+        let result = conn.execute(select).ok();
+        let text = DBValue::Text(String::from("x"));
+        assert_eq!(
+            result.as_ref().map(|result| &result.rows),
+            Some(&vec![vec![text, int(1)]])
+        );
+        assert_eq!(
+            result.map(|result| result.columns[0].db_type),
+            Some(Some(DBType::Text))
+        );
+        conn.set_plan_cache_size(0);
+        assert!(conn.execute(select).is_ok());
+        assert!(conn.storage().cached_plan(select).is_none());
+    }
+
+    #[test]
+    fn concurrent_connections() {
+        let mut a = Connection::new();
+        let mut b = a.connect();
+        for sql in &[
+            "create table t (id integer primary key, v integer);",
+            "insert into t values (1, 10);",
+        ] {
+            assert!(a.execute(sql).is_ok());
+        }
+        a.set_busy_timeout(Duration::ZERO);
+        b.set_busy_timeout(Duration::ZERO);
+        let int = DBValue::Integer;
+        let select = "select v from t where id = 1;";
+        // Reads of a transaction reading committed changes don't hold their locks
+        assert!(b
+            .execute("set transaction isolation level read committed;")
+            .is_ok());
+        assert!(b.execute("begin;").is_ok());
+        let rows = b.execute(select).ok().map(|result| result.rows);
+        assert_eq!(rows, Some(vec![vec![int(10)]]));
+        assert!(a.execute("update t set v = 20 where id = 1;").is_ok());
+        let rows = b.execute(select).ok().map(|result| result.rows);
+        assert_eq!(rows, Some(vec![vec![int(20)]]));
+        assert!(b.execute("commit;").is_ok());
+        // Serializable ones do, until they end
+        assert!(b
+            .execute("set transaction isolation level serializable;")
+            .is_ok());
+        assert!(b.execute("begin;").is_ok());
+        assert!(b.execute(select).is_ok());
+        assert!(matches!(
+            a.execute("update t set v = 30 where id = 1;"),
+            Err(Error::Storage(StorageError::TableLocked(_)))
+        ));
+        assert!(b.execute("commit;").is_ok());
+        // Rolling back keeps the rows written by the other connection meanwhile
+        assert!(a.execute("begin;").is_ok());
+        assert!(b.execute("begin;").is_ok());
+        assert!(a.execute("insert into t values (2, 20);").is_ok());
+        assert!(a.execute("update t set v = 11 where id = 1;").is_ok());
+        assert!(b.execute("insert into t values (3, 30);").is_ok());
+        assert!(matches!(
+            b.execute("update t set v = 12 where id = 1;"),
+            Err(Error::Storage(StorageError::RowLocked(_)))
+        ));
+        assert!(b.execute("commit;").is_ok());
+        assert!(a.execute("rollback;").is_ok());
+        let rows = a
+            .execute("select id, v from t;")
+            .ok()
+            .map(|result| result.rows);
+        let expected = vec![vec![int(1), int(20)], vec![int(3), int(30)]];
+        assert_eq!(rows, Some(expected));
+        // Connections are rolled back as they are dropped, on whichever thread
+        assert!(b.execute("begin;").is_ok());
+        assert!(b.execute("delete from t where id = 3;").is_ok());
+        let mut c = a.connect();
+        let inserted = std::thread::spawn(move || {
+            drop(b);
+            c.execute("insert into t values (4, 40);").is_ok()
+        });
+        assert!(inserted.join().unwrap());
+        let rows = a
+            .execute("select id from t;")
+            .ok()
+            .map(|result| result.rows);
+        assert_eq!(rows, Some(vec![vec![int(1)], vec![int(3)], vec![int(4)]]));
+    }
+
+    #[test]
+    fn busy_timeout() {
+        let mut a = Connection::new();
+        let mut b = a.connect();
+        for sql in &[
+            "create table t (id integer primary key, v integer);",
+            "insert into t values (1, 10);",
+            "begin;",
+            "update t set v = 11 where id = 1;",
+        ] {
+            assert!(a.execute(sql).is_ok());
+        }
+        assert!(b.execute("pragma busy_timeout = 0;").is_ok());
+        let rows = b
+            .execute("pragma busy_timeout;")
+            .ok()
+            .map(|result| result.rows);
+        assert_eq!(rows, Some(vec![vec![DBValue::Text(String::from("0"))]]));
+        assert!(matches!(
+            b.execute("update t set v = 12 where id = 1;"),
+            Err(Error::Storage(StorageError::RowLocked(_)))
+        ));
+        assert!(matches!(
+            b.execute("pragma busy_timeout = soon;"),
+            Err(Error::Storage(StorageError::InvalidPragmaValue(_)))
+        ));
+        // Waits for the other connection to commit
+        assert!(b.execute("pragma busy_timeout = 10000;").is_ok());
+        let updated = thread::spawn(move || {
+            let updated = b.execute("update t set v = v + 1 where id = 1;").is_ok();
+            let rows = b.execute("select v from t;").ok().map(|result| result.rows);
+            updated && rows == Some(vec![vec![DBValue::Integer(12)]])
+        });
+        thread::sleep(Duration::from_millis(20));
+        assert!(a.execute("commit;").is_ok());
+        assert!(updated.join().unwrap());
+    }
+
+    #[test]
+    fn cursors() {
+        let int = DBValue::Integer;
+        let mut a = Connection::new();
+        let mut b = a.connect();
+        for sql in &[
+            "create table t (id integer);",
+            "insert into t values (1);",
+            "insert into t values (2);",
+            "insert into t values (3);",
+            "begin;",
+        ] {
+            assert!(a.execute(sql).is_ok());
+        }
+        let mut rows = a
+            .query("select id from t order by id;")
+            .unwrap_or_else(|err| panic!("{}", err));
+        assert_eq!(rows.next().and_then(Result::ok), Some(vec![int(1)]));
+        // Waits for the cursor, then for the transaction to commit
+        let inserted = thread::spawn(move || b.execute("insert into t values (4);").is_ok());
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(rows.next().and_then(Result::ok), Some(vec![int(2)]));
+        assert_eq!(rows.next().and_then(Result::ok), Some(vec![int(3)]));
+        drop(rows);
+        assert!(a.transaction().is_some());
+        assert!(a.execute("commit;").is_ok());
+        assert!(inserted.join().unwrap());
+        let rows = a.query("select id from t;").map(Iterator::count).ok();
+        assert_eq!(rows, Some(4));
+    }
+
+    #[test]
+    fn execute_batch() {
+        let mut conn = Connection::new();
+        let results = conn.execute_batch(
+            "create table t (id integer, v integer);\n\
+             insert into t values (1, 10);\n\
+             select v from t;",
+            false,
+        );
+        assert_eq!(results.ok().map(|results| results.len()), Some(3));
+        let failed = conn.execute_batch(
+            "insert into t values (2, 20);\n\
+             insert into nowhere values (3, 30);\n\
+             insert into t values (4, 40);",
+            true,
+        );
+        assert!(matches!(
+            failed,
+            Err(Error::Batch { statement: 2, ref sql, .. })
+                if sql == "insert into nowhere values (3, 30);"
+        ));
+        let rows = conn
+            .execute("select v from t;")
+            .ok()
+            .map(|result| result.rows);
+        assert_eq!(rows, Some(vec![vec![DBValue::Integer(10)]]));
+        let unparsed = conn.execute_batch("insert into t values (5, 50);\nselect from;", false);
+        assert!(matches!(
+            unparsed,
+            Err(Error::Batch {
+                statement: 2,
+                ref error,
+                ..
+            }) if matches!(**error, Error::Parse(_))
+        ));
+        // Within a transaction, only the script is undone
+        assert!(conn.execute("begin;").is_ok());
+        assert!(conn.execute("insert into t values (6, 60);").is_ok());
+        assert!(conn
+            .execute_batch(
+                "insert into t values (7, 70);\ninsert into nowhere values (8);",
+                true
+            )
+            .is_err());
+        assert!(conn.execute("commit;").is_ok());
+        let rows = conn
+            .execute("select v from t;")
+            .ok()
+            .map(|result| result.rows);
+        assert_eq!(
+            rows,
+            Some(vec![vec![DBValue::Integer(10)], vec![DBValue::Integer(60)]])
+        );
+    }
+
+    #[test]
+    fn hooks() {
+        use crate::hooks::Operation;
+
+        let mut conn = Connection::new();
+        let mut other = conn.connect();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let (commits, rollbacks, changes) = (
+            Arc::clone(&events),
+            Arc::clone(&events),
+            Arc::clone(&events),
+        );
+        conn.set_commit_hook(move || commits.lock().unwrap().push(String::from("commit")));
+        conn.set_rollback_hook(move || rollbacks.lock().unwrap().push(String::from("rollback")));
+        conn.set_update_hook(move |change| {
+            let entry = format!("{} {} {}", change.operation, change.table, change.rowid);
+            changes.lock().unwrap().push(entry);
+        });
+        let take = || std::mem::take(&mut *events.lock().unwrap());
+        for sql in &[
+            "create table parent (id integer primary key);",
+            "create table child (id integer references parent (id) on delete cascade);",
+        ] {
+            assert!(conn.execute(sql).is_ok());
+        }
+        assert_eq!(take(), vec!["commit", "commit"]);
+        assert!(other.execute("insert into parent values (1);").is_ok());
+        assert!(take().is_empty());
+        for sql in &[
+            "begin;",
+            "insert into parent values (2);",
+            "insert into child values (1);",
+            "update parent set id = 3 where id = 2;",
+            "commit;",
+        ] {
+            assert!(conn.execute(sql).is_ok());
+        }
+        assert_eq!(
+            take(),
+            vec![
+                "insert parent 2",
+                "insert child 1",
+                "update parent 3",
+                "commit"
+            ]
+        );
+        for sql in &["begin;", "delete from parent where id = 1;", "rollback;"] {
+            assert!(conn.execute(sql).is_ok());
+        }
+        // Cascading deletes come in no particular order
+        let mut rolled_back = take();
+        rolled_back.sort();
+        assert_eq!(
+            rolled_back,
+            vec!["delete child 1", "delete parent 1", "rollback"]
+        );
+        assert!(matches!(
+            conn.execute("insert into nowhere values (1);"),
+            Err(Error::Storage(StorageError::TableNotFound))
+        ));
+        assert!(take().is_empty());
+        // Rowids stay with their rows as other rows are deleted
+        for sql in &[
+            "insert into child values (null);",
+            "delete from child where id = 1;",
+            "update child set id = 1;",
+        ] {
+            assert!(conn.execute(sql).is_ok());
+        }
+        assert_eq!(
+            take(),
+            vec![
+                "insert child 2",
+                "commit",
+                "delete child 1",
+                "commit",
+                "update child 2",
+                "commit"
+            ]
+        );
+        conn.clear_hooks();
+        assert!(conn.execute("delete from child;").is_ok());
+        assert!(take().is_empty());
+        assert_eq!(Operation::Update.to_string(), "update");
+    }
+
+    #[test]
+    fn interrupt() {
+        use std::sync::mpsc;
+
+        let mut conn = Connection::new();
+        assert!(conn.execute("create table t (a integer);").is_ok());
+        assert!(conn.execute("create table u (b integer);").is_ok());
+        for i in 0..50 {
+            let insert = format!("insert into t values ({0}); insert into u values ({0});", i);
+            assert!(conn.execute_batch(&insert, false).is_ok());
+        }
+        let join = "select t.a from t join u on t.a < u.b;";
+        // The handler waits for the other thread to interrupt the statement it reports on
+        let (started, running) = mpsc::channel();
+        let (interrupted, done) = mpsc::channel();
+        let handle = conn.interrupt_handle();
+        let interrupter = thread::spawn(move || {
+            running.recv().ok();
+            handle.interrupt();
+            interrupted.send(()).ok();
+        });
+        let mut calls = 0;
+        conn.set_progress_handler(10, move || {
+            calls += 1;
+            if calls == 1 {
+                started.send(()).ok();
+                done.recv().ok();
+            }
+            ControlFlow::Continue(())
+        });
+        assert!(matches!(
+            conn.execute(join),
+            Err(Error::Storage(StorageError::Interrupted))
+        ));
+        interrupter.join().unwrap();
+        // Later statements run as usual
+        let rows = conn.execute(join).ok().map(|result| result.rows.len());
+        assert_eq!(rows, Some(50 * 49 / 2));
+        conn.set_progress_handler(100, || ControlFlow::Break(()));
+        assert!(matches!(
+            conn.execute(join),
+            Err(Error::Storage(StorageError::Interrupted))
+        ));
+        conn.clear_progress_handler();
+        assert!(conn.execute(join).is_ok());
+    }
+
+    /// The name of a span and its fields as text.
+    #[cfg(feature = "tracing")]
+    type Span = (&'static str, HashMap<String, String>);
+
+    /// A subscriber keeping the spans it is given, with their names, and the events, with their
+    /// fields as text.
+    #[cfg(feature = "tracing")]
+    #[derive(Clone, Default)]
+    struct Capture {
+        spans: Arc<Mutex<Vec<Span>>>,
+        events: Arc<Mutex<Vec<HashMap<String, String>>>>,
+    }
+
+    #[cfg(feature = "tracing")]
+    struct Fields<'a>(&'a mut HashMap<String, String>);
+
+    #[cfg(feature = "tracing")]
+    impl tracing::field::Visit for Fields<'_> {
+        fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+            self.0
+                .insert(String::from(field.name()), String::from(value));
+        }
+
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn fmt::Debug) {
+            self.0
+                .insert(String::from(field.name()), format!("{:?}", value));
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for Capture {
+        fn enabled(&self, _: &tracing::Metadata) -> bool {
+            true
+        }
+
+        fn new_span(&self, attributes: &tracing::span::Attributes) -> tracing::span::Id {
+            let mut spans = self.spans.lock().unwrap();
+            let mut fields = HashMap::new();
+            attributes.record(&mut Fields(&mut fields));
+            spans.push((attributes.metadata().name(), fields));
+            tracing::span::Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, span: &tracing::span::Id, values: &tracing::span::Record) {
+            let mut spans = self.spans.lock().unwrap();
+            let (_, fields) = &mut spans[span.into_u64() as usize - 1];
+            values.record(&mut Fields(fields));
+        }
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, event: &tracing::Event) {
+            let mut fields = HashMap::new();
+            event.record(&mut Fields(&mut fields));
+            self.events.lock().unwrap().push(fields);
+        }
+
+        fn enter(&self, _: &tracing::span::Id) {}
+
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn traced_statements() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("traced.db");
+        let capture = Capture::default();
+        let select = "select id from t;";
+        tracing::subscriber::with_default(capture.clone(), || {
+            let mut conn = Connection::open(&path.to_string_lossy()).unwrap();
+            for sql in &["create table t (id integer);", "insert into t values (1);"] {
+                assert!(conn.execute(sql).is_ok(), "{}", sql);
+            }
+            for _ in 0..2 {
+                assert!(conn.execute(select).is_ok());
+            }
+        });
+        let spans = capture.spans.lock().unwrap();
+        let named = |name: &str, sql: &str| -> Vec<_> {
+            let spans = spans.iter().filter(|(span, fields)| {
+                *span == name && fields.get("sql").map(String::as_str) == Some(sql)
+            });
+            spans.map(|(_, fields)| fields.clone()).collect()
+        };
+        // The second run of the query takes its plan from the cache, without parsing it again
+        assert_eq!(named("parse", select).len(), 1);
+        assert_eq!(named("plan", select).len(), 1);
+        let executed = named("execute", select);
+        assert_eq!(executed.len(), 2);
+        for fields in &executed {
+            assert_eq!(fields.get("rows").map(String::as_str), Some("1"));
+            let read: u64 = fields["pages_read"].parse().unwrap();
+            assert!(read > 0, "{} pages read", read);
+            assert_eq!(fields.get("pages_written").map(String::as_str), Some("0"));
+        }
+        // Inserting saves the row to the file as the statement commits
+        let inserted = named("execute", "insert into t values (1);");
+        let written: u64 = inserted[0]["pages_written"].parse().unwrap();
+        assert!(written > 0, "{} pages written", written);
+        let events = capture.events.lock().unwrap();
+        let cached: Vec<_> = events
+            .iter()
+            .filter(|fields| fields.get("message").map(String::as_str) == Some("cached plan"))
+            .map(|fields| fields.get("sql").map(String::as_str))
+            .collect();
+        assert_eq!(cached, vec![Some(select)]);
+    }
+}
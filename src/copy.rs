@@ -0,0 +1,236 @@
+//! Reading the files of 'copy'-statements, e.g.
+//! `copy users from 'users.csv' with (format csv, header true);`, in the two formats of Postgres:
+//!
+//! - `text`, the default: a record per line, with fields separated by tabs, nulls written as
+//!   `\N`, and tabs, line breaks and backslashes in values escaped with backslashes, as `.mode
+//!   tsv` prints them.
+//! - `csv`: comma-separated values as in RFC 4180, with fields quoted by `"` where they contain
+//!   a delimiter, a quote or a line break, and quotes within them doubled. Unquoted empty
+//!   fields are nulls, and quoted ones empty text.
+//!
+//! The delimiter and the text of nulls can be changed with the `delimiter` and `null` options,
+//! and `header true` skips the first line. Fields are read as values of the types of their
+//! columns; see [`value`].
+
+use crate::db::{DBType, DBValue};
+use std::fmt;
+
+/// A record of a file: the number of the line it starts on, from 1, and its fields, `None` for
+/// nulls.
+pub type Record = (usize, Vec<Option<String>>);
+
+/// Format of the file of a 'copy'-statement.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CopyFormat {
+    Text,
+    Csv,
+}
+
+impl fmt::Display for CopyFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Text => write!(f, "text"),
+            Self::Csv => write!(f, "csv"),
+        }
+    }
+}
+
+/// Options of a 'copy'-statement, with the defaults of its format for those not given.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CopyOptions {
+    pub format: CopyFormat,
+    /// Whether the first line names the columns, and is skipped.
+    pub header: bool,
+    pub delimiter: char,
+    /// The text of null fields; in CSV, only unquoted fields are null.
+    pub null: String,
+}
+
+impl CopyOptions {
+    /// The default options of the format: tabs and `\N` for text, and commas and empty fields
+    /// for CSV, without a header.
+    pub fn new(format: CopyFormat) -> Self {
+        let (delimiter, null) = match format {
+            CopyFormat::Text => ('\t', "\\N"),
+            CopyFormat::Csv => (',', ""),
+        };
+        Self {
+            format,
+            header: false,
+            delimiter,
+            null: String::from(null),
+        }
+    }
+}
+
+impl Default for CopyOptions {
+    fn default() -> Self {
+        Self::new(CopyFormat::Text)
+    }
+}
+
+/// The records of the file. Fails with the number of the line and a message for a quoted CSV
+/// field left open or a bad escape in text.
+pub fn records(text: &str, options: &CopyOptions) -> Result<Vec<Record>, (usize, String)> {
+    let mut records = match options.format {
+        CopyFormat::Text => text_records(text, options)?,
+        CopyFormat::Csv => csv_records(text, options)?,
+    };
+    if options.header && !records.is_empty() {
+        records.remove(0);
+    }
+    Ok(records)
+}
+
+fn text_records(text: &str, options: &CopyOptions) -> Result<Vec<Record>, (usize, String)> {
+    let mut records = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        // Postgres ends the data of a 'copy' from its input at a line of a period
+        if line == "\\." {
+            break;
+        }
+        let fields = line
+            .split(options.delimiter)
+            .map(|field| match field == options.null {
+                true => Ok(None),
+                false => unescape(field)
+                    .map(Some)
+                    .ok_or((i + 1, String::from("Invalid escape"))),
+            })
+            .collect::<Result<_, _>>()?;
+        records.push((i + 1, fields));
+    }
+    Ok(records)
+}
+
+/// The field with its backslash escapes replaced by the characters they stand for.
+fn unescape(field: &str) -> Option<String> {
+    let mut text = String::new();
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        text.push(match c {
+            '\\' => match chars.next()? {
+                't' => '\t',
+                'n' => '\n',
+                'r' => '\r',
+                c => c,
+            },
+            c => c,
+        });
+    }
+    Some(text)
+}
+
+fn csv_records(text: &str, options: &CopyOptions) -> Result<Vec<Record>, (usize, String)> {
+    let mut records = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    while let Some(&c) = chars.peek() {
+        // Blank lines hold no record
+        if c == '\n' || c == '\r' {
+            chars.next();
+            line += usize::from(c == '\n');
+            continue;
+        }
+        let start = line;
+        let mut fields = Vec::new();
+        loop {
+            let mut field = String::new();
+            let quoted = chars.peek() == Some(&'"');
+            if quoted {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        Some('"') if chars.peek() == Some(&'"') => {
+                            chars.next();
+                            field.push('"');
+                        }
+                        Some('"') => break,
+                        Some(c) => {
+                            line += usize::from(c == '\n');
+                            field.push(c);
+                        }
+                        None => return Err((start, String::from("Unterminated quoted field"))),
+                    }
+                }
+            }
+            while let Some(&c) = chars.peek() {
+                if c == options.delimiter || c == '\n' || c == '\r' {
+                    break;
+                }
+                field.push(c);
+                chars.next();
+            }
+            let null = !quoted && field == options.null;
+            fields.push(if null { None } else { Some(field) });
+            match chars.next() {
+                Some(c) if c == options.delimiter => continue,
+                // Lines may end in CR LF as well as in LF
+                Some('\r') if chars.peek() == Some(&'\n') => {
+                    chars.next();
+                }
+                _ => (),
+            }
+            line += 1;
+            break;
+        }
+        records.push((start, fields));
+    }
+    Ok(records)
+}
+
+/// The field as a value of the type of its column, or `None` if it doesn't read as one.
+/// Integers and reals are read as in SQL, booleans as `true`, `false`, `t`, `f`, `yes`, `no`,
+/// `on`, `off`, `1` or `0`, and other types as text is converted to them; see
+/// [`DBType::coerce`].
+pub fn value(db_type: DBType, field: &str) -> Option<DBValue> {
+    match db_type {
+        DBType::Text => Some(DBValue::Text(String::from(field))),
+        DBType::Integer => field.trim().parse().ok().map(DBValue::Integer),
+        DBType::Real => field.trim().parse().ok().map(DBValue::Real),
+        DBType::Boolean => match field.trim().to_ascii_lowercase().as_str() {
+            "true" | "t" | "yes" | "y" | "on" | "1" => Some(DBValue::Boolean(true)),
+            "false" | "f" | "no" | "n" | "off" | "0" => Some(DBValue::Boolean(false)),
+            _ => None,
+        },
+        db_type => db_type.coerce(&DBValue::Text(String::from(field.trim()))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(fields: &[Option<&str>]) -> Vec<Option<String>> {
+        fields.iter().map(|field| field.map(String::from)).collect()
+    }
+
+    #[test]
+    fn csv_and_text_records() {
+        let mut csv = CopyOptions::new(CopyFormat::Csv);
+        csv.header = true;
+        let text = "id,name\n1,\"a, \"\"b\"\"\"\r\n2,\n\n3,\"two\nlines\"\n4,\"\"";
+        let expected = vec![
+            (2, fields(&[Some("1"), Some("a, \"b\"")])),
+            (3, fields(&[Some("2"), None])),
+            (5, fields(&[Some("3"), Some("two\nlines")])),
+            (7, fields(&[Some("4"), Some("")])),
+        ];
+        assert_eq!(records(text, &csv), Ok(expected));
+        csv.header = false;
+        assert!(records("1,\"a\n", &csv).is_err());
+        csv.delimiter = ';';
+        csv.null = String::from("NA");
+        let expected = vec![(1, fields(&[Some("1,5"), None, Some("NA")]))];
+        assert_eq!(records("1,5;NA;\"NA\"", &csv), Ok(expected));
+        let text = "1\ta\\tb\\\\\t\\N\n2\t\t";
+        let expected = vec![
+            (1, fields(&[Some("1"), Some("a\tb\\"), None])),
+            (2, fields(&[Some("2"), Some(""), Some("")])),
+        ];
+        assert_eq!(records(text, &CopyOptions::default()), Ok(expected));
+        assert_eq!(value(DBType::Integer, " 42"), Some(DBValue::Integer(42)));
+        assert_eq!(value(DBType::Boolean, "T"), Some(DBValue::Boolean(true)));
+        assert_eq!(value(DBType::Integer, "4.2"), None);
+    }
+}
@@ -0,0 +1,508 @@
+//! The database as a library: a [`Database`] opens a database and runs SQL on it, with further
+//! connections to it for other threads, e.g.
+//!
+//! ```
+//! let mut db = juicydb::Database::open(":memory:")?;
+//! db.execute("create table users (id integer primary key, name text);")?;
+//! db.execute("insert into users values (1, 'ada');")?;
+//! let users = db.query("select name from users;")?;
+//! assert_eq!(users.rows.len(), 1);
+//! # Ok::<(), juicydb::Error>(())
+//! ```
+
+#[cfg(feature = "arrow")]
+use crate::arrow;
+use crate::connection::{Connection, Error, PreparedStatement};
+use crate::db::{DBValue, ResultSet};
+use crate::from_row::{FromRow, ResultRow};
+use crate::functions::Aggregate;
+use crate::hooks::RowChange;
+use crate::params::ToValue;
+use crate::progress::InterruptHandle;
+use crate::server;
+use crate::storage_manager::{Stats, StorageError, StorageManager, IN_MEMORY};
+use crate::virtual_table::VirtualTable;
+#[cfg(feature = "arrow")]
+use arrow_array::RecordBatch;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+use std::ops::ControlFlow;
+
+/// A database, run on through a connection of its own; see [`Connection`].
+pub struct Database {
+    connection: Connection,
+}
+
+impl Database {
+    /// A new database keeping its tables in memory.
+    pub fn new() -> Self {
+        Self::from(Connection::new())
+    }
+
+    /// A new database storing its tables in the given directory; see
+    /// [`StorageManager::with_location`].
+    pub fn with_location(location: &str) -> Self {
+        Self::from(Connection::with_location(location))
+    }
+
+    /// The database saved in the given file, or a new one saved there as it is changed if the
+    /// file doesn't exist yet; see [`StorageManager::open`]. For `:memory:`, a new database in
+    /// memory.
+    pub fn open(path: &str) -> Result<Self, Error> {
+        if path == IN_MEMORY {
+            return Ok(Self::new());
+        }
+        Connection::open(path).map(Self::from)
+    }
+
+    /// Opens the database in the given file like [`Database::open`], encrypted with a key
+    /// derived from the passphrase; see [`StorageManager::open_encrypted`].
+    pub fn open_encrypted(path: &str, passphrase: &str) -> Result<Self, Error> {
+        Connection::open_encrypted(path, passphrase).map(Self::from)
+    }
+
+    /// The database in the bytes of a file saved by [`Database::save`] or taken by
+    /// [`Database::to_bytes`], kept in memory; see [`StorageManager::from_bytes`].
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, Error> {
+        Connection::from_bytes(bytes).map(Self::from)
+    }
+
+    /// A new connection to the database, e.g. for another thread.
+    pub fn connect(&self) -> Connection {
+        self.connection.connect()
+    }
+
+    /// The connection the database runs statements through.
+    pub fn connection(&mut self) -> &mut Connection {
+        &mut self.connection
+    }
+
+    /// Executes a single statement, returning the rows of a query, or the rows some other
+    /// statements return, e.g. `vacuum`; see [`Connection::execute`].
+    pub fn execute(&mut self, sql: &str) -> Result<ResultSet, Error> {
+        self.connection.execute(sql)
+    }
+
+    /// Executes the statements of an SQL script, optionally in a transaction of their own; see
+    /// [`Connection::execute_batch`].
+    pub fn execute_batch(
+        &mut self,
+        sql: &str,
+        in_transaction: bool,
+    ) -> Result<Vec<ResultSet>, Error> {
+        self.connection.execute_batch(sql, in_transaction)
+    }
+
+    /// Runs a query, collecting all of its rows. Fails for statements other than queries.
+    pub fn query(&mut self, sql: &str) -> Result<ResultSet, Error> {
+        let rows = self.connection.query(sql)?;
+        let columns = rows.columns().to_vec();
+        Ok(ResultSet {
+            columns,
+            rows: rows.collect::<Result<_, StorageError>>()?,
+        })
+    }
+
+    /// Runs a query, collecting its rows into Arrow record batches of at most `batch_size` rows
+    /// each; see [`crate::arrow`].
+    #[cfg(feature = "arrow")]
+    pub fn query_arrow(&mut self, sql: &str, batch_size: usize) -> Result<Vec<RecordBatch>, Error> {
+        let rows = self.connection.query(sql)?;
+        let columns = rows.columns().to_vec();
+        Ok(arrow::record_batches(&columns, rows, batch_size)?)
+    }
+
+    /// Runs a query, passing its rows to `f` one at a time without collecting them, until `f`
+    /// breaks off; see [`Connection::query_each`].
+    pub fn query_each<B>(
+        &mut self,
+        sql: &str,
+        f: impl FnMut(&ResultRow<'_>) -> ControlFlow<B>,
+    ) -> Result<Option<B>, Error> {
+        self.connection.query_each(sql, f)
+    }
+
+    /// Runs a query, reading each of its rows as a `T`; see [`crate::from_row`].
+    pub fn query_as<T: FromRow>(&mut self, sql: &str) -> Result<Vec<T>, Error> {
+        self.connection.query_as(sql)
+    }
+
+    /// Parses a single statement once, for running it with [`Database::execute_prepared`] as
+    /// often as needed, each time with values bound to its `?` placeholders. The plan isn't
+    /// cached as those of [`Database::execute`] are, since it depends on the values bound: the
+    /// statement is planned anew on each run; see [`Connection::prepare`].
+    pub fn prepare(&self, sql: &str) -> Result<PreparedStatement, Error> {
+        self.connection.prepare(sql)
+    }
+
+    /// Executes a prepared statement with `params` bound to its placeholders, in order.
+    pub fn execute_prepared(
+        &mut self,
+        statement: &PreparedStatement,
+        params: &[DBValue],
+    ) -> Result<ResultSet, Error> {
+        self.connection.execute_prepared(statement, params)
+    }
+
+    /// Saves the database to the file it was opened from; see [`StorageManager::save`].
+    pub fn save(&mut self) -> Result<(), Error> {
+        self.connection.save()
+    }
+
+    /// Writes a copy of the database to the given file; see [`StorageManager::backup`].
+    pub fn backup(&mut self, path: &str) -> Result<(), Error> {
+        self.connection.backup(path)
+    }
+
+    /// The database as the bytes of a file, to be opened again with [`Database::from_bytes`],
+    /// e.g. by a web page keeping it in the storage of the browser.
+    pub fn to_bytes(&mut self) -> Result<Vec<u8>, Error> {
+        self.connection.to_bytes()
+    }
+
+    /// The statements recreating the given table or view, or all of them; see
+    /// [`StorageManager::schema`].
+    pub fn schema(&mut self, name: Option<&str>) -> Result<Vec<String>, Error> {
+        self.connection.schema(name)
+    }
+
+    /// The resources used by the database; see [`StorageManager::stats`].
+    pub fn stats(&self) -> Stats {
+        self.connection.stats()
+    }
+
+    /// The numbers of pages of the database read and written so far; see
+    /// [`StorageManager::page_counts`].
+    pub fn page_counts(&self) -> (u64, u64) {
+        self.connection.page_counts()
+    }
+
+    /// The number of rows fetched from the tables of the database so far; see
+    /// [`StorageManager::rows_scanned`].
+    pub fn rows_scanned(&self) -> u64 {
+        self.connection.rows_scanned()
+    }
+
+    /// Writes the rows of the table as JSON objects, one per line; see
+    /// [`StorageManager::export_json`].
+    pub fn export_json(&mut self, table: &str, out: impl Write) -> Result<usize, Error> {
+        self.connection.export_json(table, out)
+    }
+
+    /// Inserts the rows read as JSON objects, one per line, into the table, creating it if need
+    /// be; see [`StorageManager::import_json`].
+    pub fn import_json(&mut self, table: &str, input: impl Read) -> Result<usize, Error> {
+        self.connection.import_json(table, input)
+    }
+
+    /// Copies the tables of the SQLite database in the file along with their rows, returning
+    /// their names and numbers of rows; see [`crate::sqlite`].
+    #[cfg(feature = "sqlite")]
+    pub fn import_sqlite(&mut self, path: &str) -> Result<Vec<(String, usize)>, Error> {
+        self.connection.import_sqlite(path)
+    }
+
+    /// Serves clients connecting to the listener, each on a connection of its own, until the
+    /// listener fails; see [`crate::server`].
+    pub fn serve(&self, listener: TcpListener) -> io::Result<()> {
+        server::serve(&self.connection, listener)
+    }
+
+    /// Flushes the tables stored in files to the disk; see [`StorageManager::checkpoint`].
+    pub fn checkpoint(&mut self) -> Result<(), Error> {
+        self.connection.checkpoint()
+    }
+
+    /// Registers a function callable from expressions; see [`Connection::create_function`].
+    pub fn create_function<F, T>(&mut self, name: &str, arity: usize, f: F)
+    where
+        F: Fn(&[DBValue]) -> Result<T, String> + Send + Sync + 'static,
+        T: ToValue,
+    {
+        self.connection.create_function(name, arity, f);
+    }
+
+    /// Registers an aggregate function callable from expressions; see
+    /// [`Connection::create_aggregate`].
+    pub fn create_aggregate(&mut self, name: &str, arity: usize, aggregate: impl Aggregate) {
+        self.connection.create_aggregate(name, arity, aggregate);
+    }
+
+    /// Sets the hook called when changes are committed; see [`crate::hooks`].
+    pub fn set_commit_hook(&mut self, hook: impl FnMut() + Send + 'static) {
+        self.connection.set_commit_hook(hook);
+    }
+
+    /// Sets the hook called when a transaction is rolled back.
+    pub fn set_rollback_hook(&mut self, hook: impl FnMut() + Send + 'static) {
+        self.connection.set_rollback_hook(hook);
+    }
+
+    /// Sets the hook called for each row inserted, updated or deleted.
+    pub fn set_update_hook(&mut self, hook: impl FnMut(&RowChange<'_>) + Send + 'static) {
+        self.connection.set_update_hook(hook);
+    }
+
+    /// Removes the hooks.
+    pub fn clear_hooks(&mut self) {
+        self.connection.clear_hooks();
+    }
+
+    /// A handle interrupting the running statement from another thread; see
+    /// [`Connection::interrupt_handle`].
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.connection.interrupt_handle()
+    }
+
+    /// Sets the handler called every `steps` steps of a statement; see
+    /// [`Connection::set_progress_handler`].
+    pub fn set_progress_handler(
+        &mut self,
+        steps: u64,
+        handler: impl FnMut() -> ControlFlow<()> + Send + 'static,
+    ) {
+        self.connection.set_progress_handler(steps, handler);
+    }
+
+    pub fn clear_progress_handler(&mut self) {
+        self.connection.clear_progress_handler();
+    }
+
+    /// Registers a module making virtual tables; see [`Connection::create_module`].
+    pub fn create_module<F>(&mut self, name: &str, f: F)
+    where
+        F: Fn(&[DBValue]) -> Result<Box<dyn VirtualTable>, String> + Send + Sync + 'static,
+    {
+        self.connection.create_module(name, f);
+    }
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<Connection> for Database {
+    fn from(connection: Connection) -> Self {
+        Self { connection }
+    }
+}
+
+impl From<StorageManager> for Database {
+    fn from(storage: StorageManager) -> Self {
+        Self::from(Connection::from(storage))
+    }
+}
+
+impl fmt::Debug for Database {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.connection.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn execute_and_query() {
+        let mut db = Database::new();
+        assert!(db.execute("create table t (a integer);").is_ok());
+        assert!(db.execute("insert into t values (1);").is_ok());
+        let mut other = db.connect();
+        assert!(other.execute("insert into t values (2);").is_ok());
+        let rows = db.query("select a from t;").ok().map(|result| result.rows);
+        let int = DBValue::Integer;
+        assert_eq!(rows, Some(vec![vec![int(1)], vec![int(2)]]));
+        assert!(matches!(
+            db.query("insert into t values (3);"),
+            Err(Error::Storage(StorageError::SchemaMismatch))
+        ));
+        assert!(matches!(
+            db.execute("selec a from t;"),
+            Err(Error::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn query_each_row() {
+        let mut db = Database::new();
+        assert!(db.execute("create table t (a integer);").is_ok());
+        for a in 1..=5 {
+            let insert = format!("insert into t values ({});", a);
+            assert!(db.execute(&insert).is_ok());
+        }
+        let mut sum = 0;
+        let stopped = db.query_each("select a from t;", |row| match row.get::<i64>("a") {
+            Ok(a) if a > 3 => ControlFlow::Break(a),
+            Ok(a) => {
+                sum += a;
+                ControlFlow::Continue(())
+            }
+            Err(_) => ControlFlow::Break(-1),
+        });
+        assert_eq!(stopped.ok(), Some(Some(4)));
+        assert_eq!(sum, 6);
+        let all = db.query_each("select a from t;", |_| ControlFlow::<()>::Continue(()));
+        assert_eq!(all.ok(), Some(None));
+        // The locks of the query are released once it stops
+        assert!(db.execute("drop table t;").is_ok());
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn query_as_derived() {
+        #[derive(crate::FromRow, Debug, PartialEq)]
+        struct User {
+            id: i64,
+            #[allow(dead_code)]
+            pub(crate) name: Option<String>,
+        }
+        #[derive(crate::FromRow, Debug, PartialEq)]
+        struct Pair(i64, Option<String>);
+
+        let mut db = Database::new();
+        for sql in &[
+            "create table users (id integer, name text);",
+            "insert into users values (1, 'ada');",
+            "insert into users (id) values (2);",
+        ] {
+            assert!(db.execute(sql).is_ok());
+        }
+        let users = db.query_as::<User>("select id, name from users;").ok();
+        let ada = Some(String::from("ada"));
+        assert_eq!(
+            users,
+            Some(vec![
+                User {
+                    id: 1,
+                    name: ada.clone()
+                },
+                User { id: 2, name: None },
+            ])
+        );
+        let pairs = db.query_as::<Pair>("select id, name from users;").ok();
+        assert_eq!(pairs, Some(vec![Pair(1, ada), Pair(2, None)]));
+        assert!(matches!(
+            db.query_as::<User>("select id from users;"),
+            Err(Error::FromRow(_))
+        ));
+    }
+
+    #[test]
+    fn registered_functions() {
+        let mut db = Database::new();
+        db.create_function("reverse", 1, |args| match &args[0] {
+            DBValue::Text(text) => Ok(DBValue::Text(text.chars().rev().collect())),
+            DBValue::Null => Ok(DBValue::Null),
+            _ => Err(String::from("reverse takes text")),
+        });
+        for sql in &[
+            "create table t (a integer, b text);",
+            "insert into t values (1, 'abc');",
+            "insert into t values (2, 'xy');",
+        ] {
+            assert!(db.execute(sql).is_ok());
+        }
+        let rows = db
+            .query("select a, reverse(b) from t where reverse(b) = 'yx';")
+            .ok()
+            .map(|result| result.rows);
+        assert_eq!(
+            rows,
+            Some(vec![vec![
+                DBValue::Integer(2),
+                DBValue::Text(String::from("yx"))
+            ]])
+        );
+        assert!(matches!(
+            db.query("select reverse(a) from t;"),
+            Err(Error::Storage(StorageError::FunctionFailed(_, _)))
+        ));
+        assert!(matches!(
+            db.query("select reverse(a, b) from t;"),
+            Err(Error::Storage(StorageError::TypeError))
+        ));
+        assert!(matches!(
+            db.query("select rotate(b) from t;"),
+            Err(Error::Storage(StorageError::FunctionNotFound(_)))
+        ));
+    }
+
+    #[test]
+    fn registered_aggregates() {
+        /// Product of the integer values, skipping nulls.
+        struct Product;
+
+        impl Aggregate for Product {
+            type State = i64;
+
+            fn init(&self) -> i64 {
+                1
+            }
+
+            fn step(&self, product: &mut i64, args: &[DBValue]) -> Result<(), String> {
+                match args[0] {
+                    DBValue::Integer(i) => *product *= i,
+                    DBValue::Null => (),
+                    _ => return Err(String::from("product takes integers")),
+                }
+                Ok(())
+            }
+
+            fn finalize(&self, product: i64) -> Result<DBValue, String> {
+                Ok(DBValue::Integer(product))
+            }
+        }
+
+        let mut db = Database::new();
+        db.create_aggregate("product", 1, Product);
+        for sql in &[
+            "create table t (g text, v integer);",
+            "insert into t values ('a', 2);",
+            "insert into t values ('a', 3);",
+            "insert into t values ('b', 5);",
+            "insert into t values ('b', null);",
+        ] {
+            assert!(db.execute(sql).is_ok());
+        }
+        let rows = db
+            .query("select g, product(v) from t group by g order by g;")
+            .ok()
+            .map(|result| result.rows);
+        let row = |g: &str, v| vec![DBValue::Text(String::from(g)), DBValue::Integer(v)];
+        assert_eq!(rows, Some(vec![row("a", 6), row("b", 5)]));
+        let rows = db
+            .query("select product(v) from t where v > 10;")
+            .ok()
+            .map(|result| result.rows);
+        assert_eq!(rows, Some(vec![vec![DBValue::Integer(1)]]));
+        assert!(matches!(
+            db.query("select product(g) from t;"),
+            Err(Error::Storage(StorageError::FunctionFailed(_, _)))
+        ));
+        assert!(matches!(
+            db.query("select g from t where product(v) > 1;"),
+            Err(Error::Storage(StorageError::MisplacedAggregate(_)))
+        ));
+    }
+
+    #[test]
+    fn bytes_of_a_file() {
+        let mut db = Database::new();
+        let script = "create table t (a integer primary key, b text); \
+            insert into t values (1, 'one'); \
+            create view v as select b from t where a = 1;";
+        assert!(db.execute_batch(script, false).is_ok());
+        let bytes = db.to_bytes().unwrap();
+        let mut copy = Database::from_bytes(bytes).unwrap();
+        let rows = copy
+            .query("select b from v;")
+            .ok()
+            .map(|result| result.rows);
+        assert_eq!(rows, Some(vec![vec![DBValue::Text(String::from("one"))]]));
+        assert!(Database::from_bytes(vec![0; 16]).is_err());
+    }
+}
@@ -0,0 +1,287 @@
+//! Calendar arithmetic and ISO-8601 formats for the temporal types. Dates are stored as days
+//! since 1970-01-01 in the proleptic Gregorian calendar, times as microseconds since midnight and
+//! timestamps as microseconds since 1970-01-01 00:00:00, all without time zones. Supported years
+//! range from 0 to 9999.
+
+pub const MICROS_PER_SECOND: i64 = 1_000_000;
+pub const MICROS_PER_DAY: i64 = 24 * 60 * 60 * MICROS_PER_SECOND;
+
+/// Field of a date or time, as used by `date_add` and `extract`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Unit {
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+}
+
+impl Unit {
+    /// Parses a unit name, case-insensitively and in either singular or plural.
+    pub fn parse(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        match name.strip_suffix('s').unwrap_or(&name) {
+            "year" => Some(Self::Year),
+            "month" => Some(Self::Month),
+            "day" => Some(Self::Day),
+            "hour" => Some(Self::Hour),
+            "minute" => Some(Self::Minute),
+            "second" => Some(Self::Second),
+            _ => None,
+        }
+    }
+
+    /// Length of the unit in microseconds, for units of fixed length.
+    pub fn micros(self) -> Option<i64> {
+        match self {
+            Self::Year | Self::Month => None,
+            Self::Day => Some(MICROS_PER_DAY),
+            Self::Hour => Some(60 * 60 * MICROS_PER_SECOND),
+            Self::Minute => Some(60 * MICROS_PER_SECOND),
+            Self::Second => Some(MICROS_PER_SECOND),
+        }
+    }
+}
+
+/// Days since 1970-01-01 of the given date. The month and day are expected to be valid.
+pub fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Year, month and day of the date `days` since 1970-01-01.
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Whether the date is within the supported years.
+pub fn in_range(days: i64) -> bool {
+    (days_from_civil(0, 1, 1)..=days_from_civil(9999, 12, 31)).contains(&days)
+}
+
+/// Adds months to a date, clamping the day to the end of the resulting month, so that e.g.
+/// 2024-01-31 plus one month is 2024-02-29. Fails if the result is out of range.
+pub fn add_months(days: i64, months: i64) -> Option<i64> {
+    let (year, month, day) = civil_from_days(days);
+    let month = (year * 12 + month - 1).checked_add(months)?;
+    let (year, month) = (month.div_euclid(12), month.rem_euclid(12) + 1);
+    let days = days_from_civil(year, month, day.min(days_in_month(year, month)));
+    Some(days).filter(|days| in_range(*days))
+}
+
+/// Adds years, months or days to a date. Fails for the other units, or if the result is out of
+/// range.
+pub fn add_to_date(days: i64, amount: i64, unit: Unit) -> Option<i64> {
+    match unit {
+        Unit::Year => add_months(days, amount.checked_mul(12)?),
+        Unit::Month => add_months(days, amount),
+        Unit::Day => days.checked_add(amount).filter(|days| in_range(*days)),
+        _ => None,
+    }
+}
+
+/// Adds any unit to a timestamp, keeping the time of day when adding years or months. Fails if
+/// the result is out of range.
+pub fn add_to_timestamp(micros: i64, amount: i64, unit: Unit) -> Option<i64> {
+    let micros = match unit.micros() {
+        Some(length) => amount.checked_mul(length)?.checked_add(micros)?,
+        None => {
+            let (days, time) = split_timestamp(micros);
+            add_to_date(days, amount, unit)? * MICROS_PER_DAY + time
+        }
+    };
+    Some(micros).filter(|micros| in_range(split_timestamp(*micros).0))
+}
+
+/// Adds hours, minutes or seconds to a time, wrapping around at midnight. Fails for the other
+/// units.
+pub fn add_to_time(micros: i64, amount: i64, unit: Unit) -> Option<i64> {
+    match unit {
+        Unit::Hour | Unit::Minute | Unit::Second => {
+            let offset = i128::from(amount) * i128::from(unit.micros()?);
+            let offset = offset.rem_euclid(i128::from(MICROS_PER_DAY)) as i64;
+            Some((micros + offset) % MICROS_PER_DAY)
+        }
+        _ => None,
+    }
+}
+
+/// Splits a timestamp into days since 1970-01-01 and microseconds since midnight.
+pub fn split_timestamp(micros: i64) -> (i64, i64) {
+    (
+        micros.div_euclid(MICROS_PER_DAY),
+        micros.rem_euclid(MICROS_PER_DAY),
+    )
+}
+
+/// Parses exactly `n` ASCII digits from the start of the input.
+fn digits(input: &str, n: usize) -> Option<(i64, &str)> {
+    let head = input.get(..n)?;
+    if !head.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((head.parse().ok()?, &input[n..]))
+}
+
+fn parse_date_prefix(input: &str) -> Option<(i64, &str)> {
+    let (year, rest) = digits(input, 4)?;
+    let (month, rest) = digits(rest.strip_prefix('-')?, 2)?;
+    let (day, rest) = digits(rest.strip_prefix('-')?, 2)?;
+    if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return None;
+    }
+    Some((days_from_civil(year, month, day), rest))
+}
+
+/// Parses a date of the form `YYYY-MM-DD` into days since 1970-01-01.
+pub fn parse_date(input: &str) -> Option<i64> {
+    match parse_date_prefix(input)? {
+        (days, "") => Some(days),
+        _ => None,
+    }
+}
+
+/// Parses a time of the form `HH:MM`, `HH:MM:SS` or `HH:MM:SS.ffffff` with up to six fractional
+/// digits into microseconds since midnight.
+pub fn parse_time(input: &str) -> Option<i64> {
+    let (hour, rest) = digits(input, 2)?;
+    let (minute, rest) = digits(rest.strip_prefix(':')?, 2)?;
+    let (second, rest) = match rest.strip_prefix(':') {
+        Some(rest) => digits(rest, 2)?,
+        None => (0, rest),
+    };
+    let fraction = match rest.strip_prefix('.') {
+        Some(fraction) if (1..=6).contains(&fraction.len()) => {
+            digits(fraction, fraction.len())?.0 * 10_i64.pow(6 - fraction.len() as u32)
+        }
+        Some(_) => return None,
+        None if rest.is_empty() => 0,
+        None => return None,
+    };
+    if hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    Some(((hour * 60 + minute) * 60 + second) * MICROS_PER_SECOND + fraction)
+}
+
+/// Parses a timestamp of the form `YYYY-MM-DD HH:MM:SS`, with a 'T' allowed in place of the
+/// space as in ISO-8601, into microseconds since 1970-01-01 00:00:00. The time may be in any of
+/// the forms accepted by [`parse_time`] and may be omitted altogether for midnight.
+pub fn parse_timestamp(input: &str) -> Option<i64> {
+    let (days, rest) = parse_date_prefix(input)?;
+    let time = match rest.strip_prefix(|c| c == ' ' || c == 'T') {
+        Some(time) => parse_time(time)?,
+        None if rest.is_empty() => 0,
+        None => return None,
+    };
+    Some(days * MICROS_PER_DAY + time)
+}
+
+pub fn format_date(days: i64) -> String {
+    let (year, month, day) = civil_from_days(days);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Formats a time as `HH:MM:SS`, followed by the fraction of the second if there is one.
+pub fn format_time(micros: i64) -> String {
+    let seconds = micros / MICROS_PER_SECOND;
+    let mut time = format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    );
+    let fraction = micros % MICROS_PER_SECOND;
+    if fraction != 0 {
+        time.push_str(format!(".{:06}", fraction).trim_end_matches('0'));
+    }
+    time
+}
+
+pub fn format_timestamp(micros: i64) -> String {
+    let (days, time) = split_timestamp(micros);
+    format!("{} {}", format_date(days), format_time(time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn civil_conversions() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        for days in &[-719_528, -1, 0, 59, 11016, 19782, 2_932_896] {
+            let (year, month, day) = civil_from_days(*days);
+            assert_eq!(days_from_civil(year, month, day), *days);
+        }
+        let jan31 = days_from_civil(2024, 1, 31);
+        assert_eq!(add_months(jan31, 1), Some(days_from_civil(2024, 2, 29)));
+        assert_eq!(add_months(jan31, -13), Some(days_from_civil(2022, 12, 31)));
+        assert_eq!(add_months(jan31, 12 * 8000), None);
+        assert_eq!(add_to_date(jan31, 1, Unit::Hour), None);
+        let noon = 12 * 3600 * MICROS_PER_SECOND;
+        assert_eq!(
+            add_to_time(noon, -13, Unit::Hour),
+            Some(23 * 3600 * MICROS_PER_SECOND)
+        );
+        let timestamp = jan31 * MICROS_PER_DAY + noon;
+        assert_eq!(
+            add_to_timestamp(timestamp, 1, Unit::Year),
+            Some(days_from_civil(2025, 1, 31) * MICROS_PER_DAY + noon)
+        );
+        assert_eq!(add_to_timestamp(timestamp, i64::MAX, Unit::Second), None);
+    }
+
+    #[test]
+    fn parse_and_format() {
+        let days = parse_date("2024-02-29").unwrap();
+        assert_eq!(format_date(days), "2024-02-29");
+        assert_eq!(parse_date("2023-02-29"), None);
+        assert_eq!(parse_date("2024-2-1"), None);
+        let time = parse_time("23:05:09.25").unwrap();
+        assert_eq!(format_time(time), "23:05:09.25");
+        assert_eq!(parse_time("12:30"), parse_time("12:30:00"));
+        assert_eq!(parse_time("24:00"), None);
+        assert_eq!(parse_time("12:30:00.1234567"), None);
+        let timestamp = parse_timestamp("1969-12-31T23:59:59").unwrap();
+        assert_eq!(timestamp, -MICROS_PER_SECOND);
+        assert_eq!(format_timestamp(timestamp), "1969-12-31 23:59:59");
+        let midnight = days_from_civil(2024, 1, 1) * MICROS_PER_DAY;
+        assert_eq!(parse_timestamp("2024-01-01"), Some(midnight));
+        assert_eq!(parse_timestamp("2024-01-01 "), None);
+    }
+}
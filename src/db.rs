@@ -1,4 +1,23 @@
+use crate::btree::{BTree, DatabaseFile, Key, RowFile, SavedRows, SavedTree, MAX_KEY_SIZE};
+use crate::datetime;
+use crate::decimal::Decimal;
+use crate::executor::row_size;
+use crate::pager::{PageCounts, PageId, ReadMode};
+use crate::parser::Expression;
+use crate::record::{decode_integer_key, encode_key};
+use crate::uuid::Uuid;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{btree_map, BTreeMap, HashMap, HashSet};
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::iter::Peekable;
+use std::ops::Bound;
+use std::path::Path;
+use std::slice;
+use std::sync::atomic::{AtomicI64, Ordering as AtomicOrdering};
+use std::sync::Arc;
 
 /// Conceptually, a [`Database`] is a collection of [`Table`]s, a [`Table`] is a collection of
 /// [`Row`]s and a [`Row`] is a collection of supported values with some means of indexing the
@@ -25,107 +44,1519 @@ impl Database {
 #[derive(Debug)]
 pub struct Table {
     schema: Schema,
-    rows: Vec<Row>,
+    rows: RowStore,
+    indexes: HashMap<String, Index>,
+    primary_key: Option<usize>,
+    foreign_keys: Vec<ForeignKey>,
+    autoincrement: Option<(usize, i64)>,
+    statistics: Option<TableStatistics>,
+    /// The last rowid handed out to a row of a table without a primary key.
+    rowid: i64,
 }
 
+/// Where the rows of a [`Table`] are kept: in memory, in a b-tree file, or in a tree of the
+/// file of a database. Either way the rows are ordered by their keys, see [`Table::push`], and
+/// kept encoded by [`Schema::encode`].
 #[derive(Debug)]
+enum RowStore {
+    Memory(BTreeMap<Key, Row>),
+    File(Box<RowFile>),
+    /// Rows as last saved in the file of a database, read from their tree as needed, along with
+    /// the changes made to them since, by key: the new row, or `None` for a removed row.
+    Saved {
+        tree: Arc<SavedTree>,
+        changes: BTreeMap<Key, Option<Row>>,
+        len: usize,
+    },
+}
+
+/// A row of a [`RowStore`] along with its key, borrowed from rows kept in memory.
+type Entry<'a> = (Cow<'a, [u8]>, Cow<'a, Row>);
+
+/// The rows of a [`RowStore::Saved`] in key order: the rows read from the tree, merged with the
+/// changes made to them since.
+struct Merged<'a> {
+    changes: Peekable<btree_map::Range<'a, Key, Option<Row>>>,
+    saved: Peekable<SavedRows<'a>>,
+}
+
+impl<'a> Iterator for Merged<'a> {
+    type Item = io::Result<Entry<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(Err(_)) = self.saved.peek() {
+                return self.saved.next()?.err().map(Err);
+            }
+            let ordering = match (self.changes.peek(), self.saved.peek()) {
+                (Some((changed, _)), Some(Ok((saved, _)))) => {
+                    changed.as_slice().cmp(saved.as_slice())
+                }
+                (None, None) => return None,
+                (None, _) => Ordering::Greater,
+                (Some(_), _) => Ordering::Less,
+            };
+            if ordering != Ordering::Less {
+                let saved = self.saved.next()?;
+                if ordering == Ordering::Greater {
+                    return Some(saved.map(|(key, row)| (Cow::Owned(key), Cow::Owned(row))));
+                }
+            }
+            // The change comes first, or replaces the saved row with the same key
+            if let (key, Some(row)) = self.changes.next()? {
+                return Some(Ok((Cow::Borrowed(key.as_slice()), Cow::Borrowed(row))));
+            }
+        }
+    }
+}
+
+impl RowStore {
+    fn len(&self) -> usize {
+        match self {
+            RowStore::Memory(rows) => rows.len(),
+            RowStore::File(file) => file.len(),
+            RowStore::Saved { len, .. } => *len,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get(&self, key: &[u8]) -> io::Result<Option<Cow<'_, Row>>> {
+        match self {
+            RowStore::Memory(rows) => Ok(rows.get(key).map(Cow::Borrowed)),
+            RowStore::File(file) => Ok(file.get(key)?.map(Cow::Owned)),
+            RowStore::Saved { tree, changes, .. } => match changes.get(key) {
+                Some(change) => Ok(change.as_ref().map(Cow::Borrowed)),
+                None => Ok(tree.get(key)?.map(Cow::Owned)),
+            },
+        }
+    }
+
+    /// The rows along with their keys in key order.
+    fn iter(&self) -> Box<dyn Iterator<Item = io::Result<Entry<'_>>> + '_> {
+        self.range(&[])
+    }
+
+    /// The rows with keys from the given one on, along with their keys in key order.
+    fn range(&self, from: &[u8]) -> Box<dyn Iterator<Item = io::Result<Entry<'_>>> + '_> {
+        match self {
+            RowStore::Memory(rows) => Box::new(
+                rows.range::<[u8], _>((Bound::Included(from), Bound::Unbounded))
+                    .map(|(key, row)| Ok((Cow::Borrowed(key.as_slice()), Cow::Borrowed(row)))),
+            ),
+            RowStore::File(file) => Box::new(
+                file.range(from)
+                    .map(|row| row.map(|(key, row)| (Cow::Owned(key), Cow::Owned(row)))),
+            ),
+            RowStore::Saved { tree, changes, .. } => Box::new(Merged {
+                changes: changes
+                    .range::<[u8], _>((Bound::Included(from), Bound::Unbounded))
+                    .peekable(),
+                saved: tree.range(from).peekable(),
+            }),
+        }
+    }
+
+    /// Inserts the row under the key, replacing any row with the key. Keys of rows to be saved
+    /// in a tree are at most [`MAX_KEY_SIZE`] bytes, as in a file.
+    fn insert(&mut self, key: Key, row: Row) -> io::Result<()> {
+        match self {
+            RowStore::Memory(rows) => {
+                rows.insert(key, row);
+            }
+            RowStore::File(file) => {
+                file.insert(&key, &row)?;
+            }
+            RowStore::Saved { tree, changes, len } => {
+                if key.len() > MAX_KEY_SIZE {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "Key too large"));
+                }
+                let present = match changes.get(&key) {
+                    Some(change) => change.is_some(),
+                    None => tree.get(&key)?.is_some(),
+                };
+                if !present {
+                    *len += 1;
+                }
+                changes.insert(key, Some(row));
+            }
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &[u8]) -> io::Result<()> {
+        match self {
+            RowStore::Memory(rows) => {
+                rows.remove(key);
+            }
+            RowStore::File(file) => {
+                file.remove(key)?;
+            }
+            RowStore::Saved { tree, changes, len } => {
+                let present = match changes.get(key) {
+                    Some(change) => change.is_some(),
+                    None => tree.get(key)?.is_some(),
+                };
+                if present {
+                    *len -= 1;
+                }
+                changes.insert(key.to_vec(), None);
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces all rows at once. Rows in a file are loaded into its tree from scratch, which
+    /// packs the tree tighter than inserting them one by one. Rows saved in the file of a
+    /// database move to a new tree, loaded from scratch by the next save, and which copies of
+    /// the store don't share.
+    fn replace_all(&mut self, mut rows: Vec<(Key, Row)>) -> io::Result<()> {
+        match self {
+            RowStore::Memory(stored) => *stored = rows.into_iter().collect(),
+            RowStore::File(file) => {
+                rows.sort_by(|(k1, _), (k2, _)| k1.cmp(k2));
+                file.replace_all(rows)?;
+            }
+            RowStore::Saved { tree, .. } => {
+                let file = tree.file().clone();
+                let mut store = RowStore::saved(Arc::new(SavedTree::new(file)), 0);
+                for (key, row) in rows {
+                    store.insert(key, row)?;
+                }
+                *self = store;
+            }
+        }
+        Ok(())
+    }
+
+    /// The rows saved in the tree, without changes.
+    fn saved(tree: Arc<SavedTree>, len: usize) -> Self {
+        RowStore::Saved {
+            tree,
+            changes: BTreeMap::new(),
+            len,
+        }
+    }
+
+    fn checkpoint(&mut self) -> io::Result<()> {
+        match self {
+            RowStore::Memory(_) | RowStore::Saved { .. } => Ok(()),
+            RowStore::File(file) => file.checkpoint(),
+        }
+    }
+
+    fn vacuum(&mut self) -> io::Result<u64> {
+        match self {
+            RowStore::Memory(_) | RowStore::Saved { .. } => Ok(0),
+            RowStore::File(file) => file.vacuum(),
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            RowStore::Memory(rows) => Ok(RowStore::Memory(rows.clone())),
+            RowStore::File(file) => Ok(RowStore::File(Box::new(file.try_clone()?))),
+            RowStore::Saved { tree, changes, len } => Ok(RowStore::Saved {
+                tree: tree.clone(),
+                changes: changes.clone(),
+                len: *len,
+            }),
+        }
+    }
+
+    /// A new, empty store kept where this one is: in memory, in a new file beside its file, or
+    /// in a new tree of the file of its database.
+    fn create_beside(&self) -> io::Result<Self> {
+        match self {
+            RowStore::Memory(_) => Ok(RowStore::Memory(BTreeMap::new())),
+            RowStore::File(file) => {
+                Ok(RowStore::File(Box::new(file.create_beside(Schema::new())?)))
+            }
+            RowStore::Saved { tree, .. } => {
+                let file = tree.file().clone();
+                Ok(RowStore::saved(Arc::new(SavedTree::new(file)), 0))
+            }
+        }
+    }
+
+    /// Writes the rows into a tree of the given file of a database, returning the root page of
+    /// the tree. Rows saved in the file have the changes made since written into their tree,
+    /// which is created by its first save; other rows, or all rows if asked to copy them, are
+    /// loaded into a new tree. The trees created for saved rows are added to `created`, and
+    /// only take their root page once the whole save succeeded; see [`SavedTree::set_root`].
+    ///
+    /// Rows copied are read from where they are, which must not be the given file.
+    fn write(
+        &self,
+        file: &mut BTree,
+        copy: bool,
+        created: &mut Vec<(Arc<SavedTree>, PageId)>,
+    ) -> io::Result<PageId> {
+        let (tree, changes) = match self {
+            RowStore::Saved { tree, changes, .. } if !copy => (tree, changes),
+            _ => {
+                let rows = self
+                    .iter()
+                    .map(|entry| entry.map(|(key, row)| (key.into_owned(), row.into_owned())))
+                    .collect::<io::Result<Vec<_>>>()?;
+                let root = file.create_tree()?;
+                file.load_in(root, rows)?;
+                return Ok(root);
+            }
+        };
+        let root = match tree.root() {
+            Some(root) => root,
+            None => {
+                let root = file.create_tree()?;
+                let rows = changes
+                    .iter()
+                    .filter_map(|(key, row)| Some((key.clone(), row.clone()?)));
+                file.load_in(root, rows)?;
+                created.push((tree.clone(), root));
+                return Ok(root);
+            }
+        };
+        for (key, change) in changes {
+            match change {
+                Some(row) => file.insert_in(root, key, row)?,
+                None => file.delete_in(root, key)?,
+            };
+        }
+        Ok(root)
+    }
+
+    /// Forgets the changes made since the rows were saved, once written into their tree.
+    fn mark_saved(&mut self) {
+        if let RowStore::Saved { changes, .. } = self {
+            changes.clear();
+        }
+    }
+}
+
+/// Statistics of a [`Table`] as of its last 'analyze', used by the query planner to estimate the
+/// number of rows produced by each step of a query. They are not kept up to date as the table
+/// changes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TableStatistics {
+    pub rows: usize,
+    pub columns: Vec<ColumnStatistics>,
+}
+
+/// Statistics of a column: the number of distinct non-null values, under the collation of the
+/// column, the number of nulls and the least and greatest non-null values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnStatistics {
+    pub distinct: usize,
+    pub nulls: usize,
+    pub min: Option<DBValue>,
+    pub max: Option<DBValue>,
+}
+
+/// A column of a [`Table`] referencing a unique column in another (or the same) table. Every
+/// non-null value in the column must be present in the referenced column.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ForeignKey {
+    pub column: usize,
+    pub table: String,
+    pub references: usize,
+    pub on_delete: ReferentialAction,
+}
+
+/// What to do with the referencing rows when a referenced row is deleted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReferentialAction {
+    Restrict,
+    Cascade,
+    SetNull,
+}
+
+/// Secondary index over a single column of a [`Table`], mapping each value in the column to the
+/// keys of the rows containing it. Its entries are kept where the rows of the table are, in
+/// memory or in a b-tree file of their own, as empty rows keyed by the value followed by the key
+/// of the row; as [`encode_key`] encodes no value as a prefix of another, the entries of a value
+/// are those whose keys start with its encoding. A unique index additionally guarantees that no
+/// two rows share a value in the column. Values are keyed under the collation of the column, so
+/// e.g. in a `nocase` column 'a' and 'A' share an entry.
+#[derive(Debug)]
+pub struct Index {
+    column: usize,
+    unique: bool,
+    collation: Collation,
+    entries: RowStore,
+}
+
+impl Index {
+    fn new(column: usize, unique: bool, collation: Collation, entries: RowStore) -> Self {
+        Self {
+            column,
+            unique,
+            collation,
+            entries,
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        let entries = self.entries.try_clone()?;
+        Ok(Self::new(self.column, self.unique, self.collation, entries))
+    }
+
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    pub fn is_unique(&self) -> bool {
+        self.unique
+    }
+
+    /// Key of the value in the index; values with equal keys are equal under the collation.
+    pub fn key(&self, value: &DBValue) -> DBValue {
+        self.collation.key(value)
+    }
+
+    /// The start of the keys of the entries of the value.
+    fn prefix(&self, value: &DBValue) -> Key {
+        encode_key(slice::from_ref(&self.key(value)))
+    }
+
+    /// The keys of the rows with the value in the column, in key order.
+    pub fn get(&self, value: &DBValue) -> io::Result<Vec<Key>> {
+        let prefix = self.prefix(value);
+        let mut keys = Vec::new();
+        for entry in self.entries.range(&prefix) {
+            let (key, _) = entry?;
+            match key.strip_prefix(prefix.as_slice()) {
+                Some(key) => keys.push(key.to_vec()),
+                None => break,
+            }
+        }
+        Ok(keys)
+    }
+
+    /// The key of the entry of the row with the key.
+    fn entry(&self, row: &Row, key: &[u8]) -> Key {
+        [self.prefix(&row[self.column]), key.to_vec()].concat()
+    }
+
+    fn insert(&mut self, row: &Row, key: &[u8]) -> io::Result<()> {
+        self.entries.insert(self.entry(row, key), Vec::new())
+    }
+
+    fn remove(&mut self, row: &Row, key: &[u8]) -> io::Result<()> {
+        self.entries.remove(&self.entry(row, key))
+    }
+}
+
+/// Standalone generator of integer ids, shared by any number of tables. A value handed out by a
+/// sequence is never handed out again.
+#[derive(Debug)]
+pub struct Sequence {
+    next: AtomicI64,
+    increment: i64,
+}
+
+impl Sequence {
+    pub fn new(start: i64, increment: i64) -> Self {
+        Self {
+            next: AtomicI64::new(start),
+            increment,
+        }
+    }
+
+    pub fn next_value(&self) -> i64 {
+        self.next.fetch_add(self.increment, AtomicOrdering::SeqCst)
+    }
+
+    /// The value [`Sequence::next_value`] returns next, without consuming it.
+    pub fn peek_value(&self) -> i64 {
+        self.next.load(AtomicOrdering::SeqCst)
+    }
+
+    pub fn increment(&self) -> i64 {
+        self.increment
+    }
+}
+
+/// How text values are compared. Values of other types compare the same under any collation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Collation {
+    /// Compares text byte by byte.
+    Binary,
+    /// Compares text ignoring the case of ASCII letters.
+    NoCase,
+}
+
+impl Collation {
+    pub fn compare(self, t1: &str, t2: &str) -> Ordering {
+        match self {
+            Self::Binary => t1.cmp(t2),
+            Self::NoCase => t1
+                .bytes()
+                .map(|b| b.to_ascii_lowercase())
+                .cmp(t2.bytes().map(|b| b.to_ascii_lowercase())),
+        }
+    }
+
+    /// Total order of values with text compared under the collation, see [`DBValue`].
+    pub fn compare_values(self, v1: &DBValue, v2: &DBValue) -> Ordering {
+        match (v1, v2) {
+            (DBValue::Text(t1), DBValue::Text(t2)) => self.compare(t1, t2),
+            _ => v1.cmp(v2),
+        }
+    }
+
+    /// Canonical form of the value under the collation; two values are equal under the collation
+    /// exactly when their keys are equal.
+    pub fn key(self, value: &DBValue) -> DBValue {
+        match (self, value) {
+            (Self::NoCase, DBValue::Text(text)) => DBValue::Text(text.to_ascii_lowercase()),
+            _ => value.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Collation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Collation::Binary => write!(f, "binary"),
+            Collation::NoCase => write!(f, "nocase"),
+        }
+    }
+}
+
+/// Definition of a column of a [`Table`]: its name and type, along with the constraints that
+/// apply to the column alone. Constraints backed by an index, i.e. unique columns and the
+/// primary key, belong to the table instead.
+#[derive(Clone, Debug)]
+pub struct Column {
+    pub name: String,
+    pub db_type: DBType,
+    /// Collation of text values; binary unless declared otherwise.
+    pub collation: Collation,
+    /// Whether the column may contain null values.
+    pub nullable: bool,
+    /// Value of the column when it is omitted from an 'insert'-statement.
+    pub default: Option<Expression>,
+    /// For a text column restricted to a fixed set of values, the allowed values. Such columns
+    /// are stored as the position of the value in the set, see [`Table::row`].
+    pub allowed_values: Option<Vec<String>>,
+}
+
+impl Column {
+    pub fn new(name: String, db_type: DBType) -> Self {
+        Self {
+            name,
+            db_type,
+            collation: Collation::Binary,
+            nullable: true,
+            default: None,
+            allowed_values: None,
+        }
+    }
+}
+
+/// Columns of a [`Table`], looked up by position or by name.
+#[derive(Clone, Debug)]
 pub struct Schema {
-    schema: Vec<(String, DBType)>,
+    columns: Vec<Column>,
+    positions: HashMap<String, usize>,
+    /// Positions of the columns with allowed values.
+    enums: Vec<usize>,
 }
 
 impl Schema {
     pub fn new() -> Self {
-        Self { schema: Vec::new() }
+        Self::from(Vec::new())
     }
 
-    pub fn from(schema: Vec<(String, DBType)>) -> Self {
-        Self { schema }
+    /// Schema of columns without any constraints. If a name occurs more than once, looking the
+    /// name up gives the first such column.
+    pub fn from(columns: Vec<(String, DBType)>) -> Self {
+        let mut positions = HashMap::new();
+        for (i, (name, _)) in columns.iter().enumerate() {
+            positions.entry(name.clone()).or_insert(i);
+        }
+        let columns = columns
+            .into_iter()
+            .map(|(name, db_type)| Column::new(name, db_type))
+            .collect();
+        Self {
+            columns,
+            positions,
+            enums: Vec::new(),
+        }
     }
 
-    pub fn get_field_type(&self, id: &str) -> Option<DBType> {
-        for (field, db_type) in &self.schema {
-            if field == id {
-                return Some(*db_type);
-            }
+    pub fn columns(&self) -> impl Iterator<Item = &Column> {
+        self.columns.iter()
+    }
+
+    pub fn column(&self, index: usize) -> &Column {
+        &self.columns[index]
+    }
+
+    /// Schema of the given columns only, in the given order, describing rows with the other
+    /// columns pruned. The rows are taken to be decoded, see [`Table::row`].
+    pub fn project(&self, columns: &[usize]) -> Self {
+        let mut positions = HashMap::new();
+        for (i, &column) in columns.iter().enumerate() {
+            positions
+                .entry(self.columns[column].name.clone())
+                .or_insert(i);
         }
-        None
+        Self {
+            columns: columns.iter().map(|&c| self.columns[c].clone()).collect(),
+            positions,
+            enums: Vec::new(),
+        }
+    }
+
+    pub fn get_field_type(&self, id: &str) -> Option<DBType> {
+        self.get_column_index(id)
+            .map(|index| self.columns[index].db_type)
+    }
+
+    pub fn len(&self) -> usize {
+        self.columns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+
+    pub fn get_column_name(&self, index: usize) -> &str {
+        &self.columns[index].name
+    }
+
+    pub fn get_column_index(&self, id: &str) -> Option<usize> {
+        self.positions.get(id).copied()
+    }
+
+    /// Collation of the column at `index`; binary unless declared otherwise.
+    pub fn get_collation(&self, index: usize) -> Collation {
+        self.columns[index].collation
     }
 
     pub fn get_column_indices(&self, columns: Vec<String>) -> Option<Vec<usize>> {
-        let mut indices = Vec::new();
-        for col in columns {
-            let index = &self.schema.iter().position(|(f, _)| f == &col)?;
-            indices.push(*index);
+        columns
+            .iter()
+            .map(|column| self.get_column_index(column))
+            .collect()
+    }
+
+    /// Converts the values of a row to the types of their columns where possible, see
+    /// [`DBType::coerce`].
+    pub fn coerce(&self, row: &mut [DBValue]) {
+        for (column, value) in self.columns.iter().zip(row.iter_mut()) {
+            if let Some(coerced) = column.db_type.coerce(value) {
+                *value = coerced;
+            }
         }
-        Some(indices)
     }
 
-    pub fn type_check(&self, columns: Vec<DBType>) -> Option<()> {
-        if columns.len() != self.schema.len() {
+    /// Checks the types of a row against the schema. Null values, i.e. values without a type,
+    /// are accepted in any column.
+    pub fn type_check(&self, columns: Vec<Option<DBType>>) -> Option<()> {
+        if columns.len() != self.columns.len() {
             return None;
         }
 
-        for (t1, t2) in self.schema.iter().map(|(_, t)| t).zip(columns) {
-            if *t1 != t2 {
+        for (column, t2) in self.columns.iter().zip(columns) {
+            if t2.is_some_and(|t2| !column.db_type.accepts(t2)) {
                 return None;
             }
         }
         Some(())
     }
+
+    /// Replaces the values of enum columns by their positions among the allowed values.
+    fn encode(&self, mut row: Row) -> Row {
+        for &column in &self.enums {
+            let labels = self.columns[column]
+                .allowed_values
+                .as_deref()
+                .unwrap_or(&[]);
+            if let DBValue::Text(text) = &row[column] {
+                if let Some(code) = labels.iter().position(|label| label == text) {
+                    row[column] = DBValue::Integer(code as i64);
+                }
+            }
+        }
+        row
+    }
+
+    /// Inverse of [`Schema::encode`]. Rows of tables without enum columns are stored as is, so
+    /// borrowed rows stay borrowed rather than being copied.
+    fn decode<'a>(&self, row: Cow<'a, Row>) -> Cow<'a, Row> {
+        if self.enums.is_empty() {
+            return row;
+        }
+        let mut row = row.into_owned();
+        for &column in &self.enums {
+            let labels = self.columns[column]
+                .allowed_values
+                .as_deref()
+                .unwrap_or(&[]);
+            if let DBValue::Integer(code) = row[column] {
+                row[column] = DBValue::Text(labels[code as usize].clone());
+            }
+        }
+        Cow::Owned(row)
+    }
+}
+
+impl Default for Schema {
+    fn default() -> Self {
+        Self::new()
+    }
 }
+
 pub type Row = Vec<DBValue>;
 
+/// Rows resulting from a statement, along with the names and types of their columns. Statements
+/// other than queries result in no columns and no rows.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(from = "crate::serialize::RawResultSet")
+)]
+pub struct ResultSet {
+    pub columns: Vec<ResultColumn>,
+    pub rows: Vec<Row>,
+}
+
+/// Column of a [`ResultSet`]. The type is `None` if it isn't known before evaluating the query,
+/// e.g. for a null constant.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ResultColumn {
+    pub name: String,
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "type", with = "crate::serialize::db_type")
+    )]
+    pub db_type: Option<DBType>,
+}
+
 impl Table {
     pub fn new(schema: Schema) -> Self {
+        Self::with_rows(schema, RowStore::Memory(BTreeMap::new()))
+    }
+
+    /// A table holding the given rows in memory, without any constraints.
+    pub fn from_rows(schema: Schema, rows: Vec<Row>) -> Self {
+        let mut table = Self::new(schema);
+        let rows = rows.into_iter().map(|row| (table.new_key(&row), row));
+        table.rows = RowStore::Memory(rows.collect());
+        table
+    }
+
+    /// A table whose rows are stored in a b-tree in a new file in the given directory, rather
+    /// than in memory, counting the pages read and written in the given counts. The file is
+    /// removed along with the table.
+    pub fn in_file(
+        schema: Schema,
+        directory: &Path,
+        mode: ReadMode,
+        counts: Arc<PageCounts>,
+    ) -> io::Result<Self> {
+        let file = RowFile::create(directory, schema.clone(), mode, counts)?;
+        Ok(Self::with_rows(schema, RowStore::File(Box::new(file))))
+    }
+
+    /// A table whose rows are kept in the file of a database, in a tree of their own read as
+    /// needed, along with the changes made to them until the database is saved; see
+    /// [`SavedTree`].
+    pub fn in_database(schema: Schema, file: &DatabaseFile) -> Self {
+        let tree = Arc::new(SavedTree::new(file.clone()));
+        Self::with_rows(schema, RowStore::saved(tree, 0))
+    }
+
+    fn with_rows(schema: Schema, rows: RowStore) -> Self {
         Self {
             schema,
-            rows: Vec::new(),
+            rows,
+            indexes: HashMap::new(),
+            primary_key: None,
+            foreign_keys: Vec::new(),
+            autoincrement: None,
+            statistics: None,
+            rowid: 0,
         }
     }
 
+    /// A copy of the table, with the rows of a table stored in a file copied to a new file.
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            schema: self.schema.clone(),
+            rows: self.rows.try_clone()?,
+            indexes: self
+                .indexes
+                .iter()
+                .map(|(name, index)| Ok((name.clone(), index.try_clone()?)))
+                .collect::<io::Result<_>>()?,
+            primary_key: self.primary_key,
+            foreign_keys: self.foreign_keys.clone(),
+            autoincrement: self.autoincrement,
+            statistics: self.statistics.clone(),
+            rowid: self.rowid,
+        })
+    }
+
     pub fn schema(&self) -> &Schema {
         &self.schema
     }
 
-    pub fn rows(&self) -> &Vec<Row> {
-        &self.rows
+    /// Number of rows in the table. It is kept along with the rows, so `select count(*)` over a
+    /// whole table takes constant time, and transactions restore it with the rows.
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.len() == 0
+    }
+
+    /// The row with the given key, if any.
+    pub fn get(&self, key: &[u8]) -> io::Result<Option<Cow<'_, Row>>> {
+        Ok(self.rows.get(key)?.map(|row| self.schema.decode(row)))
+    }
+
+    /// The row with the given key, which is expected to be in the table.
+    pub fn row(&self, key: &[u8]) -> io::Result<Cow<'_, Row>> {
+        self.get(key)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Row not found"))
+    }
+
+    /// The rows in key order.
+    pub fn rows(&self) -> impl Iterator<Item = io::Result<Cow<'_, Row>>> {
+        self.entries().map(|entry| entry.map(|(_, row)| row))
+    }
+
+    /// The rows along with their keys, in key order.
+    pub fn entries(&self) -> impl Iterator<Item = io::Result<(Cow<'_, [u8]>, Cow<'_, Row>)>> {
+        self.rows
+            .iter()
+            .map(move |entry| entry.map(|(key, row)| (key, self.schema.decode(row))))
+    }
+
+    /// The keys of all rows, in key order.
+    pub fn keys(&self) -> io::Result<Vec<Key>> {
+        self.rows
+            .iter()
+            .map(|entry| entry.map(|(key, _)| key.into_owned()))
+            .collect()
+    }
+
+    /// The key of the row with the given value of the primary key. Rows are keyed by the value
+    /// of their primary key under the collation of its column, encoded by [`encode_key`], so
+    /// that the rows of a table with a primary key are clustered on it: they are kept in the
+    /// order of the key rather than in the order they were inserted in. Returns `None` for
+    /// tables without a primary key.
+    pub fn key_of(&self, value: &DBValue) -> Option<Key> {
+        let column = self.primary_key?;
+        let collation = self.schema.get_collation(column);
+        Some(encode_key(slice::from_ref(&collation.key(value))))
+    }
+
+    /// The rowid of the row with the key: the value of its primary key, or else the integer its
+    /// key encodes. Unlike the key, which holds the primary key as collated, this is the value
+    /// as stored.
+    pub fn rowid(&self, key: &[u8]) -> io::Result<DBValue> {
+        match self.primary_key {
+            Some(column) => Ok(self.row(key)?[column].clone()),
+            None => Ok(decode_integer_key(key).map_or(DBValue::Null, DBValue::Integer)),
+        }
+    }
+
+    /// The key of a new row: the key of its primary key, or else a new rowid. Rowids count up
+    /// from 1 and aren't handed out again, even once their rows are deleted, so that a row keeps
+    /// its key for as long as it is in the table.
+    fn new_key(&mut self, row: &Row) -> Key {
+        match self.primary_key {
+            Some(column) => self.key_of(&row[column]).unwrap_or_default(),
+            None => {
+                self.rowid += 1;
+                encode_key(&[DBValue::Integer(self.rowid)])
+            }
+        }
+    }
+
+    /// Approximate memory taken by the rows of a table kept in memory, in bytes, or `None` for a
+    /// table stored in a file.
+    pub fn memory(&self) -> Option<usize> {
+        match &self.rows {
+            RowStore::Memory(rows) => Some(
+                rows.iter()
+                    .map(|(key, row)| key.len() + row_size(row))
+                    .sum(),
+            ),
+            RowStore::File(_) | RowStore::Saved { .. } => None,
+        }
+    }
+
+    /// Flushes the rows and the indexes of a table stored in a file to the disk.
+    pub fn checkpoint(&mut self) -> io::Result<()> {
+        for index in self.indexes.values_mut() {
+            index.entries.checkpoint()?;
+        }
+        self.rows.checkpoint()
+    }
+
+    /// Rewrites the files of a table stored in a file compactly, those of its indexes included,
+    /// returning the number of bytes reclaimed.
+    pub fn vacuum(&mut self) -> io::Result<u64> {
+        let mut reclaimed = self.rows.vacuum()?;
+        for index in self.indexes.values_mut() {
+            reclaimed += index.entries.vacuum()?;
+        }
+        Ok(reclaimed)
+    }
+
+    /// Writes the rows and the entries of the indexes of the table into trees of the file of a
+    /// database: all of them if asked to copy them, or else only the changes made since the
+    /// last save of rows saved in the file. Returns the root pages of the rows and of the
+    /// entries of each index, by name.
+    pub fn write_trees(
+        &self,
+        file: &mut BTree,
+        copy: bool,
+        created: &mut Vec<(Arc<SavedTree>, PageId)>,
+    ) -> io::Result<(PageId, Vec<(String, PageId)>)> {
+        let root = self.rows.write(file, copy, created)?;
+        let mut indexes = Vec::new();
+        for (name, index) in &self.indexes {
+            indexes.push((name.clone(), index.entries.write(file, copy, created)?));
+        }
+        indexes.sort();
+        Ok((root, indexes))
+    }
+
+    /// Forgets the changes made to the rows and the indexes since they were saved in the file of
+    /// a database, once the save wrote them into their trees.
+    pub fn mark_saved(&mut self) {
+        self.rows.mark_saved();
+        for index in self.indexes.values_mut() {
+            index.entries.mark_saved();
+        }
+    }
+
+    /// Reads the rows from the tree of the file of a database with the given root page, and the
+    /// entries of each index from the tree with the root page given for its name, rather than
+    /// keeping them where they were. Every row has an entry in each index.
+    pub fn attach(
+        &mut self,
+        file: &DatabaseFile,
+        root: PageId,
+        len: usize,
+        indexes: &HashMap<String, PageId>,
+    ) {
+        let saved = |root| RowStore::saved(Arc::new(SavedTree::open(file.clone(), root)), len);
+        self.rows = saved(root);
+        for (name, index) in &mut self.indexes {
+            if let Some(root) = indexes.get(name) {
+                index.entries = saved(*root);
+            }
+        }
+    }
+
+    /// The last rowid handed out; see [`Table::rowid`].
+    pub fn last_rowid(&self) -> i64 {
+        self.rowid
+    }
+
+    /// Sets the last rowid handed out, e.g. when loading a table.
+    pub fn set_last_rowid(&mut self, rowid: i64) {
+        self.rowid = rowid;
+    }
+
+    pub fn indexes(&self) -> &HashMap<String, Index> {
+        &self.indexes
+    }
+
+    pub fn primary_key(&self) -> Option<usize> {
+        self.primary_key
+    }
+
+    pub fn foreign_keys(&self) -> &Vec<ForeignKey> {
+        &self.foreign_keys
+    }
+
+    pub fn add_foreign_key(&mut self, foreign_key: ForeignKey) {
+        self.foreign_keys.push(foreign_key);
+    }
+
+    pub fn add_not_null(&mut self, column: usize) {
+        self.schema.columns[column].nullable = false;
+    }
+
+    /// Sets the collation of a column, rekeying any indexes over the column, and the rows if
+    /// the column is the primary key.
+    pub fn set_collation(&mut self, column: usize, collation: Collation) -> io::Result<()> {
+        self.schema.columns[column].collation = collation;
+        match self.primary_key {
+            Some(key) if key == column => self.rekey(),
+            _ => self.rebuild_indexes(),
+        }
+    }
+
+    /// Returns the unique index over the given column, if any.
+    pub fn unique_index(&self, column: usize) -> Option<&Index> {
+        self.indexes
+            .values()
+            .find(|index| index.unique && index.column == column)
+    }
+
+    /// Makes the given column the primary key of the table. The rows of the table are clustered
+    /// on the primary key, i.e. keyed and so kept sorted by it, see [`Table::key_of`], instead of
+    /// being kept in insertion order. The uniqueness of the key is enforced by a unique index
+    /// with the given name.
+    pub fn create_primary_key(&mut self, name: String, column: usize) -> io::Result<Option<()>> {
+        if self.create_index(name, column, true)?.is_none() {
+            return Ok(None);
+        }
+        self.primary_key = Some(column);
+        self.rekey()?;
+        Ok(Some(()))
+    }
+
+    pub fn set_default(&mut self, column: usize, value: Expression) {
+        self.schema.columns[column].default = Some(value);
+    }
+
+    /// Restricts a text column to the given values, re-encoding the existing rows.
+    pub fn set_allowed_values(&mut self, column: usize, labels: Vec<String>) -> io::Result<()> {
+        let rows = self.owned_entries()?;
+        self.schema.columns[column].allowed_values = Some(labels);
+        if !self.schema.enums.contains(&column) {
+            self.schema.enums.push(column);
+        }
+        let schema = &self.schema;
+        let rows = rows.into_iter().map(|(key, row)| (key, schema.encode(row)));
+        self.rows.replace_all(rows.collect())
+    }
+
+    /// Copies of the rows along with their keys, decoded.
+    fn owned_entries(&self) -> io::Result<Vec<(Key, Row)>> {
+        self.entries()
+            .map(|entry| entry.map(|(key, row)| (key.into_owned(), row.into_owned())))
+            .collect()
+    }
+
+    pub fn statistics(&self) -> Option<&TableStatistics> {
+        self.statistics.as_ref()
+    }
+
+    /// Collects the statistics of the table, replacing any collected before.
+    pub fn analyze(&mut self) -> io::Result<()> {
+        let mut columns = Vec::new();
+        for (index, column) in self.schema.columns().enumerate() {
+            let collation = column.collation;
+            let mut distinct = HashSet::new();
+            let mut statistics = ColumnStatistics {
+                distinct: 0,
+                nulls: 0,
+                min: None,
+                max: None,
+            };
+            for row in self.rows() {
+                let row = row?;
+                let value = &row[index];
+                if let DBValue::Null = value {
+                    statistics.nulls += 1;
+                    continue;
+                }
+                distinct.insert(collation.key(value));
+                let beyond = |bound: &Option<DBValue>, ordering| {
+                    bound
+                        .as_ref()
+                        .is_none_or(|bound| collation.compare_values(value, bound) == ordering)
+                };
+                if beyond(&statistics.min, Ordering::Less) {
+                    statistics.min = Some(value.clone());
+                }
+                if beyond(&statistics.max, Ordering::Greater) {
+                    statistics.max = Some(value.clone());
+                }
+            }
+            statistics.distinct = distinct.len();
+            columns.push(statistics);
+        }
+        self.statistics = Some(TableStatistics {
+            rows: self.len(),
+            columns,
+        });
+        Ok(())
+    }
+
+    /// The autoincrementing column of the table, if any.
+    pub fn autoincrement(&self) -> Option<usize> {
+        self.autoincrement.map(|(column, _)| column)
+    }
+
+    pub fn set_autoincrement(&mut self, column: usize) {
+        self.autoincrement = Some((column, 0));
+    }
+
+    /// The next id of the autoincrementing column. Ids grow monotonically, so that the ids of
+    /// deleted rows are never handed out again.
+    pub fn next_id(&self) -> i64 {
+        self.autoincrement.map_or(1, |(_, high)| high + 1)
+    }
+
+    /// Sets the next id of the autoincrementing column, if any, e.g. when loading a table.
+    pub fn set_next_id(&mut self, id: i64) {
+        if let Some((_, high)) = &mut self.autoincrement {
+            *high = id - 1;
+        }
+    }
+
+    /// Inserts the row, bumping the high-water mark of the autoincrementing column if the row
+    /// has a greater id than handed out so far. Returns the key of the row.
+    pub fn push_and_bump(&mut self, row: Row) -> io::Result<Key> {
+        if let Some((column, high)) = &mut self.autoincrement {
+            if let DBValue::Integer(id) = row[*column] {
+                *high = id.max(*high);
+            }
+        }
+        self.push(row)
+    }
+
+    /// Adds the rows all at once, as a bulk load: rows of an empty table in a file are loaded
+    /// into its tree in one go, and the indexes are rebuilt once, rather than row by row as by
+    /// [`Table::push`]. Bumps the high-water mark of the autoincrementing column as
+    /// [`Table::push_and_bump`] does. Returns the keys of the rows.
+    pub fn load(&mut self, rows: Vec<Row>) -> io::Result<Vec<Key>> {
+        if let Some((column, high)) = &mut self.autoincrement {
+            for row in &rows {
+                if let DBValue::Integer(id) = row[*column] {
+                    *high = id.max(*high);
+                }
+            }
+        }
+        if !self.rows.is_empty() {
+            return rows.into_iter().map(|row| self.push(row)).collect();
+        }
+        let rows: Vec<_> = rows
+            .into_iter()
+            .map(|row| (self.new_key(&row), self.schema.encode(row)))
+            .collect();
+        let keys = rows.iter().map(|(key, _)| key.clone()).collect();
+        self.rows.replace_all(rows)?;
+        self.rebuild_indexes()?;
+        Ok(keys)
+    }
+
+    /// Inserts the row under a new key, see [`Table::key_of`], returning the key. A row with the
+    /// same primary key is replaced.
+    pub fn push(&mut self, row: Row) -> io::Result<Key> {
+        let key = self.new_key(&row);
+        if let Some(old) = self.rows.get(&key)? {
+            let old = self.schema.decode(old).into_owned();
+            for index in self.indexes.values_mut() {
+                index.remove(&old, &key)?;
+            }
+        }
+        for index in self.indexes.values_mut() {
+            index.insert(&row, &key)?;
+        }
+        self.rows.insert(key.clone(), self.schema.encode(row))?;
+        Ok(key)
+    }
+
+    /// Replaces the rows with the given keys, returning the new keys of the rows in the same
+    /// order. Rows whose primary key changes move to the key of their new primary key.
+    pub fn update(&mut self, rows: Vec<(Key, Row)>) -> io::Result<Vec<Key>> {
+        // All old rows go before any new row is written, so that rows can trade keys
+        let mut moved = Vec::with_capacity(rows.len());
+        for (key, row) in rows {
+            let old = self.row(&key)?.into_owned();
+            for index in self.indexes.values_mut() {
+                index.remove(&old, &key)?;
+            }
+            let new_key = match self.primary_key {
+                Some(column) => self.key_of(&row[column]).unwrap_or_default(),
+                None => key.clone(),
+            };
+            if new_key != key {
+                self.rows.remove(&key)?;
+            }
+            moved.push((new_key, row));
+        }
+        let mut keys = Vec::with_capacity(moved.len());
+        for (key, row) in moved {
+            for index in self.indexes.values_mut() {
+                index.insert(&row, &key)?;
+            }
+            self.rows.insert(key.clone(), self.schema.encode(row))?;
+            keys.push(key);
+        }
+        Ok(keys)
+    }
+
+    /// Removes the rows with the given keys. The other rows keep their keys.
+    pub fn remove(&mut self, keys: &[Key]) -> io::Result<()> {
+        for key in keys {
+            if let Some(old) = self.rows.get(key)? {
+                let old = self.schema.decode(old).into_owned();
+                for index in self.indexes.values_mut() {
+                    index.remove(&old, key)?;
+                }
+                self.rows.remove(key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Keys the rows anew, as the primary key or its collation changed, rebuilding the indexes.
+    fn rekey(&mut self) -> io::Result<()> {
+        let rows = self.owned_entries()?;
+        let mut rekeyed = Vec::with_capacity(rows.len());
+        for (key, row) in rows {
+            let key = match self.primary_key {
+                Some(column) => self.key_of(&row[column]).unwrap_or_default(),
+                None => key,
+            };
+            rekeyed.push((key, self.schema.encode(row)));
+        }
+        self.rows.replace_all(rekeyed)?;
+        self.rebuild_indexes()
     }
 
-    pub fn rows_mut(&mut self) -> &mut Vec<Row> {
-        &mut self.rows
+    fn rebuild_indexes(&mut self) -> io::Result<()> {
+        let indexes: Vec<_> = self
+            .indexes
+            .iter()
+            .map(|(name, index)| (name.clone(), index.column, index.unique))
+            .collect();
+        for (name, column, unique) in indexes {
+            if let Some(index) = self.build_index(column, unique)? {
+                self.indexes.insert(name, index);
+            }
+        }
+        Ok(())
     }
 
-    pub fn push(&mut self, row: Row) {
-        self.rows.push(row);
+    /// An index over the existing rows, loaded into its store at once, or `None` if the index
+    /// is unique but the column contains duplicate values.
+    fn build_index(&self, column: usize, unique: bool) -> io::Result<Option<Index>> {
+        let store = self.rows.create_beside()?;
+        let mut index = Index::new(column, unique, self.schema.get_collation(column), store);
+        let mut entries = Vec::with_capacity(self.rows.len());
+        let mut values = HashSet::new();
+        for entry in self.entries() {
+            let (key, row) = entry?;
+            let value = index.key(&row[column]);
+            if unique && value != DBValue::Null && !values.insert(value) {
+                return Ok(None);
+            }
+            entries.push((index.entry(&row, &key), Vec::new()));
+        }
+        index.entries.replace_all(entries)?;
+        Ok(Some(index))
+    }
+
+    /// Builds an index over the existing rows. Fails if the index is unique but the column
+    /// already contains duplicate values.
+    pub fn create_index(
+        &mut self,
+        name: String,
+        column: usize,
+        unique: bool,
+    ) -> io::Result<Option<()>> {
+        Ok(self.build_index(column, unique)?.map(|index| {
+            self.indexes.insert(name, index);
+        }))
+    }
+
+    /// Checks that writing the given rows keeps all unique indexes of the table unique. Each
+    /// change is a new row, along with the key of the row it replaces, if any.
+    pub fn check_unique(&self, changes: &[(Option<&[u8]>, &Row)]) -> io::Result<Option<()>> {
+        let replaced: HashSet<&[u8]> = changes.iter().filter_map(|(key, _)| *key).collect();
+        for index in self.indexes.values().filter(|index| index.unique) {
+            let mut values = HashSet::new();
+            for (_, row) in changes {
+                let value = index.key(&row[index.column]);
+                if value == DBValue::Null {
+                    continue;
+                }
+                let taken = index
+                    .get(&value)?
+                    .iter()
+                    .any(|key| !replaced.contains(&key[..]));
+                if taken || !values.insert(value) {
+                    return Ok(None);
+                }
+            }
+        }
+        Ok(Some(()))
     }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum DBType {
     Integer,
+    Real,
     Text,
+    Boolean,
+    Date,
+    Time,
+    Timestamp,
+    /// Exact number with the given precision, i.e. number of digits, and scale, i.e. number of
+    /// digits after the point.
+    Decimal(u8, u8),
+    Uuid,
+}
+
+impl DBType {
+    /// Converts a value into one of this type, when the conversion is lossless or otherwise
+    /// expected: integers into reals, ISO-8601 text into dates, times and timestamps, numbers and
+    /// text into decimals, rounded to the scale of the type, and text into UUIDs. Returns `None`
+    /// if the value needs no conversion or can't be converted.
+    pub fn coerce(self, value: &DBValue) -> Option<DBValue> {
+        match (self, value) {
+            (DBType::Real, DBValue::Integer(i)) => Some(DBValue::Real(*i as f64)),
+            (DBType::Date, DBValue::Text(t)) => datetime::parse_date(t).map(DBValue::Date),
+            (DBType::Time, DBValue::Text(t)) => datetime::parse_time(t).map(DBValue::Time),
+            (DBType::Timestamp, DBValue::Text(t)) => {
+                datetime::parse_timestamp(t).map(DBValue::Timestamp)
+            }
+            (DBType::Timestamp, DBValue::Date(days)) => {
+                Some(DBValue::Timestamp(days * datetime::MICROS_PER_DAY))
+            }
+            (DBType::Decimal(_, scale), value) => {
+                let decimal = match value {
+                    DBValue::Decimal(d) => *d,
+                    DBValue::Integer(i) => Decimal::from(*i),
+                    DBValue::Real(r) => Decimal::from_f64(*r)?,
+                    DBValue::Text(t) => Decimal::parse(t)?,
+                    _ => return None,
+                };
+                decimal.rescale(scale).map(DBValue::Decimal)
+            }
+            (DBType::Uuid, DBValue::Text(t)) => Uuid::parse(t).map(DBValue::Uuid),
+            _ => None,
+        }
+    }
+
+    /// Whether a column of this type accepts values of the given type. A decimal column accepts
+    /// decimals of its scale with at most its precision.
+    pub fn accepts(self, value_type: DBType) -> bool {
+        match (self, value_type) {
+            (DBType::Decimal(precision, scale), DBType::Decimal(digits, value_scale)) => {
+                scale == value_scale && digits <= precision
+            }
+            _ => self == value_type,
+        }
+    }
 }
 
 impl fmt::Display for DBType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             DBType::Integer => write!(f, "integer"),
+            DBType::Real => write!(f, "real"),
             DBType::Text => write!(f, "text"),
+            DBType::Boolean => write!(f, "boolean"),
+            DBType::Date => write!(f, "date"),
+            DBType::Time => write!(f, "time"),
+            DBType::Timestamp => write!(f, "timestamp"),
+            DBType::Decimal(precision, scale) => write!(f, "decimal({}, {})", precision, scale),
+            DBType::Uuid => write!(f, "uuid"),
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+/// Value of a field. For the representation of the temporal values, see [`datetime`].
+///
+/// Values are totally ordered, so that sorting, grouping and the keys of indexes all agree on
+/// which values are equal. Values of different kinds are ordered by kind: null, booleans,
+/// numbers, text, dates and timestamps, times and UUIDs. Numbers of any type compare by value,
+/// so that e.g. `1`, `1.0` and `decimal '1.00'` are equal, with all zeroes equal and all NaNs
+/// equal and greater than any other number. Dates compare as timestamps at midnight. Text is
+/// compared byte by byte; see [`Collation::compare_values`] for other collations.
+#[derive(Clone, Debug)]
 pub enum DBValue {
+    Null,
     Integer(i64),
+    Real(f64),
     Text(String),
+    Boolean(bool),
+    /// Days since 1970-01-01.
+    Date(i64),
+    /// Microseconds since midnight.
+    Time(i64),
+    /// Microseconds since 1970-01-01 00:00:00.
+    Timestamp(i64),
+    Decimal(Decimal),
+    Uuid(Uuid),
+}
+
+/// Kinds of values, in the order values of different kinds are sorted in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Kind {
+    Null,
+    Boolean,
+    Number,
+    Text,
+    Timestamp,
+    Time,
+    Uuid,
+}
+
+/// Compares reals, with NaNs equal to each other and greater than any other number.
+fn cmp_reals(r1: f64, r2: f64) -> Ordering {
+    r1.partial_cmp(&r2)
+        .unwrap_or_else(|| r1.is_nan().cmp(&r2.is_nan()))
+}
+
+impl DBValue {
+    fn kind(&self) -> Kind {
+        match self {
+            DBValue::Null => Kind::Null,
+            DBValue::Boolean(_) => Kind::Boolean,
+            DBValue::Integer(_) | DBValue::Real(_) | DBValue::Decimal(_) => Kind::Number,
+            DBValue::Text(_) => Kind::Text,
+            DBValue::Date(_) | DBValue::Timestamp(_) => Kind::Timestamp,
+            DBValue::Time(_) => Kind::Time,
+            DBValue::Uuid(_) => Kind::Uuid,
+        }
+    }
+
+    /// Whether the values are of the same kind, e.g. both numbers. Comparisons between values of
+    /// different kinds are type errors in SQL, even though the values are ordered.
+    pub fn is_comparable(&self, other: &Self) -> bool {
+        self.kind() == other.kind()
+    }
+
+    /// The value as a point on the real line, for numbers, dates and timestamps; the query
+    /// planner interpolates between such points to estimate how many values lie in a range.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self.kind() {
+            Kind::Number => Some(self.approximate()),
+            Kind::Timestamp => Some(self.micros() as f64),
+            _ => None,
+        }
+    }
+
+    /// Nearest real to a number, with all zeroes and NaNs normalized.
+    fn approximate(&self) -> f64 {
+        let real = match self {
+            DBValue::Integer(i) => *i as f64,
+            DBValue::Real(r) => *r,
+            DBValue::Decimal(d) => d.to_f64(),
+            _ => 0.0,
+        };
+        if real == 0.0 {
+            0.0
+        } else if real.is_nan() {
+            f64::NAN
+        } else {
+            real
+        }
+    }
+
+    /// Exact value of a number. Reals are taken to be their shortest decimal representation,
+    /// which only reals too large for a [`Decimal`] lack.
+    fn exact(&self) -> Option<Decimal> {
+        match self {
+            DBValue::Integer(i) => Some(Decimal::from(*i)),
+            DBValue::Real(r) => Decimal::from_f64(*r),
+            DBValue::Decimal(d) => Some(*d),
+            _ => None,
+        }
+    }
+
+    /// Compares numbers by their nearest reals, then by their exact values. As the nearest real
+    /// grows with the exact value, integers and decimals can be compared exactly right away.
+    fn cmp_numbers(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (DBValue::Integer(i1), DBValue::Integer(i2)) => i1.cmp(i2),
+            (DBValue::Real(r1), DBValue::Real(r2)) => cmp_reals(*r1, *r2),
+            (DBValue::Real(_), _) | (_, DBValue::Real(_)) => {
+                cmp_reals(self.approximate(), other.approximate())
+                    .then_with(|| self.exact().cmp(&other.exact()))
+            }
+            _ => self.exact().cmp(&other.exact()),
+        }
+    }
+
+    /// Microseconds since 1970-01-01 00:00:00 of a date or timestamp.
+    fn micros(&self) -> i64 {
+        match self {
+            DBValue::Date(days) => days.saturating_mul(datetime::MICROS_PER_DAY),
+            DBValue::Timestamp(micros) => *micros,
+            _ => 0,
+        }
+    }
+}
+
+impl Ord for DBValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.kind()
+            .cmp(&other.kind())
+            .then_with(|| match (self, other) {
+                (DBValue::Boolean(b1), DBValue::Boolean(b2)) => b1.cmp(b2),
+                (DBValue::Text(t1), DBValue::Text(t2)) => t1.cmp(t2),
+                (DBValue::Time(t1), DBValue::Time(t2)) => t1.cmp(t2),
+                (DBValue::Uuid(u1), DBValue::Uuid(u2)) => u1.cmp(u2),
+                _ => match self.kind() {
+                    Kind::Number => self.cmp_numbers(other),
+                    Kind::Timestamp => self.micros().cmp(&other.micros()),
+                    _ => Ordering::Equal,
+                },
+            })
+    }
+}
+
+impl PartialOrd for DBValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for DBValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for DBValue {}
+
+/// Hashes values consistently with their order: numbers by their nearest real, and dates and
+/// timestamps by their microseconds.
+impl Hash for DBValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.kind().hash(state);
+        match self {
+            DBValue::Null => (),
+            DBValue::Integer(_) | DBValue::Real(_) | DBValue::Decimal(_) => {
+                self.approximate().to_bits().hash(state)
+            }
+            DBValue::Text(t) => t.hash(state),
+            DBValue::Boolean(b) => b.hash(state),
+            DBValue::Date(_) | DBValue::Timestamp(_) => self.micros().hash(state),
+            DBValue::Time(t) => t.hash(state),
+            DBValue::Uuid(u) => u.hash(state),
+        }
+    }
 }
 
 impl DBValue {
-    pub fn val_to_type(&self) -> DBType {
+    pub fn val_to_type(&self) -> Option<DBType> {
         match &self {
-            DBValue::Integer(_) => DBType::Integer,
-            DBValue::Text(_) => DBType::Text,
+            DBValue::Null => None,
+            DBValue::Integer(_) => Some(DBType::Integer),
+            DBValue::Real(_) => Some(DBType::Real),
+            DBValue::Text(_) => Some(DBType::Text),
+            DBValue::Boolean(_) => Some(DBType::Boolean),
+            DBValue::Date(_) => Some(DBType::Date),
+            DBValue::Time(_) => Some(DBType::Time),
+            DBValue::Timestamp(_) => Some(DBType::Timestamp),
+            DBValue::Decimal(d) => Some(DBType::Decimal(d.precision(), d.scale())),
+            DBValue::Uuid(_) => Some(DBType::Uuid),
         }
     }
 }
@@ -133,8 +1564,183 @@ impl DBValue {
 impl fmt::Display for DBValue {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            DBValue::Null => write!(f, "NULL"),
             DBValue::Integer(i) => write!(f, "{}", i),
+            DBValue::Real(r) => write!(f, "{:?}", r),
             DBValue::Text(text) => write!(f, "{}", text),
+            DBValue::Boolean(b) => write!(f, "{}", b),
+            DBValue::Date(days) => write!(f, "{}", datetime::format_date(*days)),
+            DBValue::Time(micros) => write!(f, "{}", datetime::format_time(*micros)),
+            DBValue::Timestamp(micros) => write!(f, "{}", datetime::format_timestamp(*micros)),
+            DBValue::Decimal(d) => write!(f, "{}", d),
+            DBValue::Uuid(u) => write!(f, "{}", u),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::hash_map::DefaultHasher;
+
+    fn hash(value: &DBValue) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn schema_lookup() {
+        let mut table = Table::new(Schema::from(vec![
+            (String::from("id"), DBType::Integer),
+            (String::from("name"), DBType::Text),
+            (String::from("id"), DBType::Real),
+        ]));
+        table.add_not_null(0);
+        table.set_default(1, Expression::Value(DBValue::Text(String::from("?"))));
+        let schema = table.schema();
+        assert_eq!(schema.get_column_index("name"), Some(1));
+        assert_eq!(schema.get_field_type("id"), Some(DBType::Integer));
+        assert_eq!(schema.get_field_type("missing"), None);
+        let indices = schema.get_column_indices(vec![String::from("name"), String::from("id")]);
+        assert_eq!(indices, Some(vec![1, 0]));
+        let nullable: Vec<_> = schema.columns().map(|column| column.nullable).collect();
+        assert_eq!(nullable, vec![false, true, true]);
+        assert!(schema.column(1).default.is_some());
+        assert_eq!(schema.column(2).db_type, DBType::Real);
+    }
+
+    #[test]
+    fn keys_stay_with_rows() {
+        let schema = || Schema::from(vec![(String::from("id"), DBType::Integer)]);
+        let temporary = tempfile::tempdir().unwrap();
+        let directory = temporary.path();
+        let counts = Arc::new(PageCounts::default());
+        let in_file = Table::in_file(schema(), directory, ReadMode::Syscalls, counts).unwrap();
+        for mut table in [Table::new(schema()), in_file] {
+            let rows = (1..=3).map(|i| vec![DBValue::Integer(i)]).collect();
+            let keys = table.load(rows).unwrap();
+            table.remove(&keys[..1]).unwrap();
+            assert_eq!(table.row(&keys[2]).unwrap()[0], DBValue::Integer(3));
+            assert!(table.get(&keys[0]).unwrap().is_none());
+            // Rowids aren't handed out again
+            let key = table.push(vec![DBValue::Integer(4)]).unwrap();
+            assert!(!keys.contains(&key));
+
+            // Rows are clustered on the primary key, which they are keyed by from then on
+            table.create_primary_key(String::from("pk"), 0).unwrap();
+            table.push(vec![DBValue::Integer(0)]).unwrap();
+            let rows: Vec<_> = table.rows().map(|row| row.unwrap()[0].clone()).collect();
+            assert_eq!(
+                rows,
+                (0..=4)
+                    .filter(|&i| i != 1)
+                    .map(DBValue::Integer)
+                    .collect::<Vec<_>>()
+            );
+            let key = table.key_of(&DBValue::Integer(3)).unwrap();
+            let keys = table
+                .update(vec![(key, vec![DBValue::Integer(5)])])
+                .unwrap();
+            assert_eq!(Some(&keys[0]), table.key_of(&DBValue::Integer(5)).as_ref());
+            assert_eq!(table.len(), 4);
+        }
+    }
+
+    #[test]
+    fn index_lookups() {
+        let schema = || Schema::from(vec![(String::from("name"), DBType::Text)]);
+        let temporary = tempfile::tempdir().unwrap();
+        let directory = temporary.path();
+        let counts = Arc::new(PageCounts::default());
+        let in_file = Table::in_file(schema(), directory, ReadMode::Syscalls, counts).unwrap();
+        for mut table in [Table::new(schema()), in_file] {
+            table.set_collation(0, Collation::NoCase).unwrap();
+            let text = |text: &str| DBValue::Text(String::from(text));
+            let names = ["ab", "a", "A", "a\0", "b"];
+            let rows = names.iter().map(|name| vec![text(name)]).collect();
+            let keys = table.load(rows).unwrap();
+            table.push(vec![DBValue::Null]).unwrap();
+            table
+                .create_index(String::from("by_name"), 0, false)
+                .unwrap();
+            let index = &table.indexes()["by_name"];
+            // Only the entries of the value itself start with its encoding, not those of "ab"
+            assert_eq!(
+                index.get(&text("a")).unwrap(),
+                vec![keys[1].clone(), keys[2].clone()]
+            );
+            assert_eq!(index.get(&text("AB")).unwrap(), vec![keys[0].clone()]);
+            assert!(index.get(&text("c")).unwrap().is_empty());
+            let unique = table.create_index(String::from("unique"), 0, true).unwrap();
+            assert_eq!(unique, None);
+
+            table.remove(&keys[1..2]).unwrap();
+            let key = table.push(vec![text("c")]).unwrap();
+            let index = &table.indexes()["by_name"];
+            assert_eq!(index.get(&text("a")).unwrap(), vec![keys[2].clone()]);
+            assert_eq!(index.get(&text("C")).unwrap(), vec![key]);
+            let copy = table.try_clone().unwrap();
+            assert_eq!(
+                copy.indexes()["by_name"].get(&DBValue::Null).unwrap().len(),
+                1
+            );
+        }
+    }
+
+    #[test]
+    fn total_order() {
+        let decimal = |d: &str| DBValue::Decimal(Decimal::parse(d).unwrap());
+        let ascending = vec![
+            DBValue::Null,
+            DBValue::Boolean(false),
+            DBValue::Boolean(true),
+            DBValue::Real(f64::NEG_INFINITY),
+            DBValue::Integer(-1),
+            DBValue::Real(0.1),
+            decimal("0.1000000000000000001"),
+            DBValue::Integer(i64::MAX),
+            DBValue::Real(1e300),
+            DBValue::Real(f64::NAN),
+            DBValue::Text(String::from("A")),
+            DBValue::Text(String::from("a")),
+            DBValue::Date(0),
+            DBValue::Timestamp(1),
+            DBValue::Time(0),
+        ];
+        for (i, v1) in ascending.iter().enumerate() {
+            for (j, v2) in ascending.iter().enumerate() {
+                assert_eq!(v1.cmp(v2), i.cmp(&j), "{:?} {:?}", v1, v2);
+            }
+        }
+        let equal = [
+            vec![DBValue::Integer(1), DBValue::Real(1.0), decimal("1.00")],
+            vec![DBValue::Real(0.0), DBValue::Real(-0.0), decimal("-0.0")],
+            vec![DBValue::Real(0.1), decimal("0.1")],
+            vec![DBValue::Real(f64::NAN), DBValue::Real(-f64::NAN)],
+            vec![
+                DBValue::Date(1),
+                DBValue::Timestamp(datetime::MICROS_PER_DAY),
+            ],
+        ];
+        for values in &equal {
+            for (v1, v2) in values.iter().zip(&values[1..]) {
+                assert_eq!(v1, v2);
+                assert_eq!(hash(v1), hash(v2), "{:?} {:?}", v1, v2);
+            }
         }
+        assert!(!DBValue::Integer(1).is_comparable(&DBValue::Text(String::from("1"))));
+        let (upper, lower) = (
+            DBValue::Text(String::from("B")),
+            DBValue::Text(String::from("a")),
+        );
+        assert_eq!(
+            Collation::Binary.compare_values(&upper, &lower),
+            Ordering::Less
+        );
+        assert_eq!(
+            Collation::NoCase.compare_values(&upper, &lower),
+            Ordering::Greater
+        );
     }
 }
@@ -0,0 +1,275 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+/// Most significant digits a [`Decimal`] can hold.
+pub const MAX_PRECISION: u8 = 38;
+
+/// Extra digits kept after the point when dividing, beyond those of the operands.
+pub const DIVISION_SCALE: u8 = 6;
+
+/// Exact decimal number, `mantissa / 10^scale`. Arithmetic never rounds except to fit the scale
+/// of the result, and fails instead of overflowing. Numbers are equal when their values are,
+/// regardless of scale, so that e.g. 1.50 and 1.5 are the same key in an index; the scale only
+/// affects how a number is displayed.
+#[derive(Clone, Copy, Debug)]
+pub struct Decimal {
+    mantissa: i128,
+    scale: u8,
+}
+
+fn power_of_ten(exponent: u8) -> Option<i128> {
+    10_i128.checked_pow(u32::from(exponent))
+}
+
+/// Divides, rounding half away from zero.
+fn divide_rounded(dividend: i128, divisor: i128) -> Option<i128> {
+    let quotient = dividend.checked_div(divisor)?;
+    let remainder = dividend % divisor;
+    if remainder.unsigned_abs() >= divisor.unsigned_abs() - remainder.unsigned_abs() {
+        quotient.checked_add(dividend.signum() * divisor.signum())
+    } else {
+        Some(quotient)
+    }
+}
+
+impl Decimal {
+    pub fn new(mantissa: i128, scale: u8) -> Option<Self> {
+        if scale > MAX_PRECISION || mantissa.unsigned_abs() >= power_of_ten(MAX_PRECISION)? as u128
+        {
+            return None;
+        }
+        Some(Self { mantissa, scale })
+    }
+
+    pub fn mantissa(&self) -> i128 {
+        self.mantissa
+    }
+
+    pub fn scale(&self) -> u8 {
+        self.scale
+    }
+
+    /// Number of digits in the number, at least one and at least the scale.
+    pub fn precision(&self) -> u8 {
+        let digits = (1..=MAX_PRECISION)
+            .find(|&digits| {
+                power_of_ten(digits).is_some_and(|p| self.mantissa.unsigned_abs() < p as u128)
+            })
+            .unwrap_or(MAX_PRECISION);
+        digits.max(self.scale)
+    }
+
+    /// Parses a number of the form `-123.45`, with at most [`MAX_PRECISION`] digits.
+    pub fn parse(input: &str) -> Option<Self> {
+        let (negative, digits) = match input.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, input.strip_prefix('+').unwrap_or(input)),
+        };
+        let (whole, fraction) = match digits.find('.') {
+            Some(i) => (&digits[..i], &digits[i + 1..]),
+            None => (digits, ""),
+        };
+        if (whole.is_empty() && fraction.is_empty())
+            || !whole
+                .bytes()
+                .chain(fraction.bytes())
+                .all(|b| b.is_ascii_digit())
+            || whole.len() + fraction.len() > usize::from(MAX_PRECISION)
+        {
+            return None;
+        }
+        let mut mantissa: i128 = 0;
+        for b in whole.bytes().chain(fraction.bytes()) {
+            mantissa = mantissa * 10 + i128::from(b - b'0');
+        }
+        Self::new(
+            if negative { -mantissa } else { mantissa },
+            fraction.len() as u8,
+        )
+    }
+
+    /// Exact value of a real number, as given by its shortest representation, e.g. 19.99 rather
+    /// than 19.989999999999998436805981327779591083526611328125.
+    pub fn from_f64(real: f64) -> Option<Self> {
+        if !real.is_finite() {
+            return None;
+        }
+        Self::parse(&real.to_string())
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.to_string().parse().unwrap_or(f64::NAN)
+    }
+
+    /// The number with exactly `scale` digits after the point, rounding half away from zero.
+    pub fn rescale(self, scale: u8) -> Option<Self> {
+        let mantissa = match scale.cmp(&self.scale) {
+            Ordering::Equal => self.mantissa,
+            Ordering::Greater => self
+                .mantissa
+                .checked_mul(power_of_ten(scale - self.scale)?)?,
+            Ordering::Less => divide_rounded(self.mantissa, power_of_ten(self.scale - scale)?)?,
+        };
+        Self::new(mantissa, scale)
+    }
+
+    /// The mantissas of both numbers at their common scale.
+    fn aligned(self, other: Self) -> Option<(i128, i128, u8)> {
+        let scale = self.scale.max(other.scale);
+        Some((
+            self.rescale(scale)?.mantissa,
+            other.rescale(scale)?.mantissa,
+            scale,
+        ))
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        let (m1, m2, scale) = self.aligned(other)?;
+        Self::new(m1.checked_add(m2)?, scale)
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        let (m1, m2, scale) = self.aligned(other)?;
+        Self::new(m1.checked_sub(m2)?, scale)
+    }
+
+    pub fn checked_mul(self, other: Self) -> Option<Self> {
+        let mantissa = self.mantissa.checked_mul(other.mantissa)?;
+        let scale = self.scale + other.scale;
+        if scale > MAX_PRECISION {
+            let excess = power_of_ten(scale - MAX_PRECISION)?;
+            return Self::new(divide_rounded(mantissa, excess)?, MAX_PRECISION);
+        }
+        Self::new(mantissa, scale)
+    }
+
+    /// Divides, keeping [`DIVISION_SCALE`] more digits after the point than the operands have.
+    /// Fails on division by zero.
+    pub fn checked_div(self, other: Self) -> Option<Self> {
+        if other.mantissa == 0 {
+            return None;
+        }
+        let scale = (self.scale.max(other.scale) + DIVISION_SCALE).min(MAX_PRECISION);
+        // mantissa / 10^scale = (m1 / 10^s1) / (m2 / 10^s2)
+        let shift = power_of_ten(scale + other.scale - self.scale)?;
+        let mantissa = divide_rounded(self.mantissa.checked_mul(shift)?, other.mantissa)?;
+        Self::new(mantissa, scale)
+    }
+
+    /// Key that orders numbers by value: the integral part, and the fractional part scaled to
+    /// the maximum scale. Both parts carry the sign of the number.
+    fn key(&self) -> (i128, i128) {
+        let unit = power_of_ten(self.scale).unwrap_or(1);
+        let fraction =
+            (self.mantissa % unit) * power_of_ten(MAX_PRECISION - self.scale).unwrap_or(1);
+        (self.mantissa / unit, fraction)
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(integer: i64) -> Self {
+        Self {
+            mantissa: i128::from(integer),
+            scale: 0,
+        }
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Decimal {}
+
+impl Hash for Decimal {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = self.mantissa.unsigned_abs().to_string();
+        let scale = usize::from(self.scale);
+        let digits = format!("{:0>width$}", digits, width = scale + 1);
+        let (whole, fraction) = digits.split_at(digits.len() - scale);
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+        if fraction.is_empty() {
+            write!(f, "{}{}", sign, whole)
+        } else {
+            write!(f, "{}{}.{}", sign, whole, fraction)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decimal(input: &str) -> Decimal {
+        Decimal::parse(input).unwrap()
+    }
+
+    #[test]
+    fn parse_and_display() {
+        for input in &["0", "12.50", "-0.05", "100", "0.000"] {
+            assert_eq!(decimal(input).to_string(), *input);
+        }
+        assert_eq!(decimal(".5").to_string(), "0.5");
+        assert_eq!(decimal("+7.").to_string(), "7");
+        assert_eq!(Decimal::from_f64(19.99), Some(decimal("19.99")));
+        assert_eq!(decimal("-12.5").to_f64(), -12.5);
+        assert_eq!(decimal("123.45").precision(), 5);
+        assert_eq!(decimal("0.05").precision(), 2);
+        for invalid in &["", ".", "-", "1.2.3", "1e3", &"9".repeat(39)] {
+            assert!(Decimal::parse(invalid).is_none(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn exact_arithmetic() {
+        let sum = decimal("0.1").checked_add(decimal("0.2")).unwrap();
+        assert_eq!(sum.to_string(), "0.3");
+        let difference = decimal("10").checked_sub(decimal("0.01")).unwrap();
+        assert_eq!(difference.to_string(), "9.99");
+        let product = decimal("19.99").checked_mul(decimal("3")).unwrap();
+        assert_eq!(product.to_string(), "59.97");
+        let quotient = decimal("10.00").checked_div(decimal("3")).unwrap();
+        assert_eq!(quotient.to_string(), "3.33333333");
+        let quotient = decimal("-2").checked_div(decimal("3")).unwrap();
+        assert_eq!(quotient.to_string(), "-0.666667");
+        assert!(decimal("1").checked_div(decimal("0.00")).is_none());
+        let max = decimal(&"9".repeat(38));
+        assert!(max.checked_add(decimal("1")).is_none());
+        assert_eq!(decimal("2.345").rescale(2), Some(decimal("2.35")));
+        assert_eq!(decimal("-2.345").rescale(2), Some(decimal("-2.35")));
+        assert_eq!(
+            decimal("2.344").rescale(0).map(|d| d.to_string()),
+            Some(String::from("2"))
+        );
+    }
+
+    #[test]
+    fn compare_by_value() {
+        assert_eq!(decimal("1.50"), decimal("1.5"));
+        assert!(decimal("-1.5") < decimal("-1.2"));
+        assert!(decimal("-0.5") < decimal("0.2"));
+        assert!(decimal("2") > decimal("1.999"));
+        assert!(decimal(&"9".repeat(38)) > decimal("0.99"));
+    }
+}
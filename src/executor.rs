@@ -0,0 +1,994 @@
+//! Relational operators for executing queries, in the iterator ("volcano") model: every operator
+//! produces its rows one at a time on request, pulling rows from its inputs as needed. A query is
+//! executed by building a tree of operators and pulling rows from its root; see
+//! [`StorageManager::query_rows`](crate::storage_manager::StorageManager::query_rows).
+
+use crate::btree::Key;
+use crate::cipher::{Cipher, NONCE_SIZE, TAG_SIZE};
+use crate::db::*;
+use crate::parallel::map_chunks;
+use crate::parser::*;
+use crate::query_processor::*;
+use crate::record;
+use crate::storage_manager::StorageError;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::vec;
+
+/// Relational operator producing a stream of rows.
+pub trait Operator {
+    /// Produces the next row, or `None` once the operator is exhausted. An operator should not be
+    /// used any further after it has returned an error.
+    fn next(&mut self) -> Option<Result<Row, StorageError>>;
+}
+
+/// Drains an operator into a vector, failing at the first error.
+pub fn collect_rows(operator: &mut dyn Operator) -> Result<Vec<Row>, StorageError> {
+    let mut rows = Vec::new();
+    while let Some(row) = operator.next() {
+        rows.push(row?);
+    }
+    Ok(rows)
+}
+
+/// Produces the given rows.
+pub struct Values(vec::IntoIter<Row>);
+
+impl Values {
+    pub fn new(rows: Vec<Row>) -> Self {
+        Self(rows.into_iter())
+    }
+}
+
+impl Operator for Values {
+    fn next(&mut self) -> Option<Result<Row, StorageError>> {
+        self.0.next().map(Ok)
+    }
+}
+
+/// The given columns of the row with the key in the table, which must be distinct, counting the
+/// row in `scanned`, the rows fetched from the tables of the database for statistics. Values of
+/// rows decoded into a copy are moved out of it rather than cloned.
+fn fetch(
+    table: &Table,
+    key: &[u8],
+    columns: &[usize],
+    scanned: &AtomicU64,
+) -> Result<Row, StorageError> {
+    scanned.fetch_add(1, AtomicOrdering::Relaxed);
+    Ok(match table.row(key)? {
+        Cow::Borrowed(row) => columns.iter().map(|&column| row[column].clone()).collect(),
+        Cow::Owned(mut row) => columns
+            .iter()
+            .map(|&column| mem::replace(&mut row[column], DBValue::Null))
+            .collect(),
+    })
+}
+
+/// Produces the given columns of the rows of a table with the given keys, in the order of the
+/// keys. Leaving out the columns a query doesn't refer to keeps the rows passed through the
+/// rest of the pipeline narrow.
+pub struct SeqScan<'a> {
+    table: &'a Table,
+    keys: vec::IntoIter<Key>,
+    columns: Vec<usize>,
+    scanned: &'a AtomicU64,
+}
+
+impl<'a> SeqScan<'a> {
+    pub fn new(
+        table: &'a Table,
+        keys: Vec<Key>,
+        columns: Vec<usize>,
+        scanned: &'a AtomicU64,
+    ) -> Self {
+        Self {
+            table,
+            keys: keys.into_iter(),
+            columns,
+            scanned,
+        }
+    }
+}
+
+impl Operator for SeqScan<'_> {
+    fn next(&mut self) -> Option<Result<Row, StorageError>> {
+        let key = self.keys.next()?;
+        Some(fetch(self.table, &key, &self.columns, self.scanned))
+    }
+}
+
+/// Passes on the rows of its input for which the condition is true.
+pub struct Filter<'a> {
+    input: Box<dyn Operator + 'a>,
+    condition: Condition,
+    scope: Rc<Scope<'a>>,
+}
+
+impl<'a> Filter<'a> {
+    pub fn new(input: Box<dyn Operator + 'a>, condition: Condition, scope: Rc<Scope<'a>>) -> Self {
+        Self {
+            input,
+            condition,
+            scope,
+        }
+    }
+}
+
+impl Operator for Filter<'_> {
+    fn next(&mut self) -> Option<Result<Row, StorageError>> {
+        loop {
+            let row = match self.input.next()? {
+                Ok(row) => row,
+                Err(err) => return Some(Err(err)),
+            };
+            match eval_condition(&self.condition, &self.scope, &row) {
+                Ok(Some(true)) => return Some(Ok(row)),
+                Ok(_) => continue,
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+/// Rows of the left input of a join, each paired with candidate rows of the right input. Joins
+/// concatenate the pairs and keep those satisfying the join condition, if any.
+struct Matches<'a> {
+    left: Box<dyn Operator + 'a>,
+    current: Option<(Row, vec::IntoIter<Row>)>,
+    condition: Option<Condition>,
+    scope: Rc<Scope<'a>>,
+}
+
+impl<'a> Matches<'a> {
+    fn new(
+        left: Box<dyn Operator + 'a>,
+        condition: Option<Condition>,
+        scope: Rc<Scope<'a>>,
+    ) -> Self {
+        Self {
+            left,
+            current: None,
+            condition,
+            scope,
+        }
+    }
+
+    /// Produces the next joined row, taking the candidates for each left row from `candidates`.
+    fn next(
+        &mut self,
+        mut candidates: impl FnMut(&Row) -> Result<Vec<Row>, StorageError>,
+    ) -> Option<Result<Row, StorageError>> {
+        loop {
+            if let Some((left, rights)) = &mut self.current {
+                for right in rights {
+                    let mut row = left.clone();
+                    row.extend(right);
+                    let accept = match &self.condition {
+                        Some(condition) => eval_condition(condition, &self.scope, &row),
+                        None => Ok(Some(true)),
+                    };
+                    match accept {
+                        Ok(Some(true)) => return Some(Ok(row)),
+                        Ok(_) => continue,
+                        Err(err) => return Some(Err(err)),
+                    }
+                }
+            }
+            let left = match self.left.next()? {
+                Ok(row) => row,
+                Err(err) => return Some(Err(err)),
+            };
+            let rights = match candidates(&left) {
+                Ok(rights) => rights.into_iter(),
+                Err(err) => return Some(Err(err)),
+            };
+            self.current = Some((left, rights));
+        }
+    }
+}
+
+/// Inner join by nested loops: every row of the left input is concatenated with every row of the
+/// right input, keeping the combinations satisfying the condition. The right input is read once,
+/// on the first request, and kept in memory.
+pub struct NestedLoopJoin<'a> {
+    matches: Matches<'a>,
+    right: Option<Box<dyn Operator + 'a>>,
+    right_rows: Vec<Row>,
+}
+
+impl<'a> NestedLoopJoin<'a> {
+    pub fn new(
+        left: Box<dyn Operator + 'a>,
+        right: Box<dyn Operator + 'a>,
+        condition: Option<Condition>,
+        scope: Rc<Scope<'a>>,
+    ) -> Self {
+        Self {
+            matches: Matches::new(left, condition, scope),
+            right: Some(right),
+            right_rows: Vec::new(),
+        }
+    }
+}
+
+impl Operator for NestedLoopJoin<'_> {
+    fn next(&mut self) -> Option<Result<Row, StorageError>> {
+        if let Some(mut right) = self.right.take() {
+            match collect_rows(right.as_mut()) {
+                Ok(rows) => self.right_rows = rows,
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        let right_rows = &self.right_rows;
+        self.matches.next(|_| Ok(right_rows.clone()))
+    }
+}
+
+/// Inner join on the equality of a column of the left rows and a column of the right rows. The
+/// right input is read into a hash table on the first request, and each left row is joined with
+/// the right rows with an equal value, under the collation of the columns; nulls equal nothing.
+/// The rows are then checked against the condition like in a [`NestedLoopJoin`].
+pub struct HashJoin<'a> {
+    matches: Matches<'a>,
+    right: Option<Box<dyn Operator + 'a>>,
+    buckets: HashMap<DBValue, Vec<Row>>,
+    keys: (usize, usize),
+    collation: Collation,
+}
+
+impl<'a> HashJoin<'a> {
+    /// Joins on the equality of the column at `keys.0` in the left rows and the column at `keys.1`
+    /// in the right rows.
+    pub fn new(
+        left: Box<dyn Operator + 'a>,
+        right: Box<dyn Operator + 'a>,
+        keys: (usize, usize),
+        collation: Collation,
+        condition: Option<Condition>,
+        scope: Rc<Scope<'a>>,
+    ) -> Self {
+        Self {
+            matches: Matches::new(left, condition, scope),
+            right: Some(right),
+            buckets: HashMap::new(),
+            keys,
+            collation,
+        }
+    }
+}
+
+impl Operator for HashJoin<'_> {
+    fn next(&mut self) -> Option<Result<Row, StorageError>> {
+        if let Some(mut right) = self.right.take() {
+            let rows = match collect_rows(right.as_mut()) {
+                Ok(rows) => rows,
+                Err(err) => return Some(Err(err)),
+            };
+            for row in rows {
+                match row.get(self.keys.1) {
+                    None | Some(DBValue::Null) => continue,
+                    Some(value) => {
+                        let key = self.collation.key(value);
+                        self.buckets.entry(key).or_default().push(row);
+                    }
+                }
+            }
+        }
+        let (buckets, (left_key, _), collation) = (&self.buckets, self.keys, self.collation);
+        self.matches.next(|row| match row.get(left_key) {
+            None | Some(DBValue::Null) => Ok(Vec::new()),
+            Some(value) => Ok(buckets
+                .get(&collation.key(value))
+                .cloned()
+                .unwrap_or_default()),
+        })
+    }
+}
+
+/// Inner join looking up the value of a column of each left row in an index over the right
+/// table, and joining the given columns of the rows found. The rows are then checked against the
+/// condition like in a [`NestedLoopJoin`].
+pub struct IndexJoin<'a> {
+    matches: Matches<'a>,
+    table: &'a Table,
+    index: &'a Index,
+    left_key: usize,
+    columns: Vec<usize>,
+    scanned: &'a AtomicU64,
+}
+
+impl<'a> IndexJoin<'a> {
+    pub fn new(
+        left: Box<dyn Operator + 'a>,
+        (table, index): (&'a Table, &'a Index),
+        left_key: usize,
+        columns: Vec<usize>,
+        scanned: &'a AtomicU64,
+        condition: Option<Condition>,
+        scope: Rc<Scope<'a>>,
+    ) -> Self {
+        Self {
+            matches: Matches::new(left, condition, scope),
+            table,
+            index,
+            left_key,
+            columns,
+            scanned,
+        }
+    }
+}
+
+impl Operator for IndexJoin<'_> {
+    fn next(&mut self) -> Option<Result<Row, StorageError>> {
+        let (table, index, left_key) = (self.table, self.index, self.left_key);
+        let (columns, scanned) = (&self.columns, self.scanned);
+        self.matches.next(|row| match row.get(left_key) {
+            None | Some(DBValue::Null) => Ok(Vec::new()),
+            Some(value) => index
+                .get(value)?
+                .iter()
+                .map(|key| fetch(table, key, columns, scanned))
+                .collect(),
+        })
+    }
+}
+
+/// The rows of an input ordered by a key column, read in groups of rows with equal keys.
+struct Groups<'a> {
+    input: Box<dyn Operator + 'a>,
+    key: usize,
+    group: Option<(DBValue, Vec<Row>)>,
+    peeked: Option<Row>,
+    done: bool,
+}
+
+impl Groups<'_> {
+    /// The rows with the given key, skipping the groups with lesser keys. Keys must be looked up
+    /// in ascending order.
+    fn find(&mut self, key: &DBValue) -> Result<Vec<Row>, StorageError> {
+        loop {
+            match &self.group {
+                Some((group_key, rows)) if group_key == key => return Ok(rows.clone()),
+                Some((group_key, _)) if group_key > key => return Ok(Vec::new()),
+                _ if self.done => return Ok(Vec::new()),
+                _ => self.advance()?,
+            }
+        }
+    }
+
+    fn advance(&mut self) -> Result<(), StorageError> {
+        let first = match self.peeked.take().map(Ok).or_else(|| self.input.next()) {
+            Some(row) => row?,
+            None => {
+                self.group = None;
+                self.done = true;
+                return Ok(());
+            }
+        };
+        let key = first[self.key].clone();
+        let mut rows = vec![first];
+        while let Some(row) = self.input.next() {
+            let row = row?;
+            if row[self.key] != key {
+                self.peeked = Some(row);
+                break;
+            }
+            rows.push(row);
+        }
+        self.group = Some((key, rows));
+        Ok(())
+    }
+}
+
+/// Inner join of inputs both ordered by their join keys, by merging them: each left row is joined
+/// with the group of right rows with an equal key, which is found by reading on in the right
+/// input. Only one group of right rows is kept in memory at a time. Keys are compared in the
+/// order of [`DBValue`]s, so they must be ordered that way too; nulls equal nothing. The rows are
+/// then checked against the condition like in a [`NestedLoopJoin`].
+pub struct MergeJoin<'a> {
+    matches: Matches<'a>,
+    right: Groups<'a>,
+    left_key: usize,
+}
+
+impl<'a> MergeJoin<'a> {
+    /// Joins on the equality of the column at `keys.0` in the left rows and the column at `keys.1`
+    /// in the right rows.
+    pub fn new(
+        left: Box<dyn Operator + 'a>,
+        right: Box<dyn Operator + 'a>,
+        keys: (usize, usize),
+        condition: Option<Condition>,
+        scope: Rc<Scope<'a>>,
+    ) -> Self {
+        Self {
+            matches: Matches::new(left, condition, scope),
+            right: Groups {
+                input: right,
+                key: keys.1,
+                group: None,
+                peeked: None,
+                done: false,
+            },
+            left_key: keys.0,
+        }
+    }
+}
+
+impl Operator for MergeJoin<'_> {
+    fn next(&mut self) -> Option<Result<Row, StorageError>> {
+        let Self {
+            matches,
+            right,
+            left_key,
+        } = self;
+        matches.next(|row| match row.get(*left_key) {
+            None | Some(DBValue::Null) => Ok(Vec::new()),
+            Some(value) => right.find(value),
+        })
+    }
+}
+
+/// How [`Project`] produces a value of its output rows.
+enum Output {
+    /// Evaluates the expression against the input row.
+    Evaluate(Expression),
+    /// Copies the field at the position in the input row.
+    Copy(usize),
+    /// Moves the field at the position out of the input row, as no later output needs it.
+    Move(usize),
+}
+
+/// Evaluates a list of expressions against each row of its input. Fields selected as they are
+/// get moved into the output row on their last use rather than cloned.
+pub struct Project<'a> {
+    input: Box<dyn Operator + 'a>,
+    outputs: Vec<Output>,
+    scope: Rc<Scope<'a>>,
+}
+
+impl<'a> Project<'a> {
+    pub fn new(
+        input: Box<dyn Operator + 'a>,
+        expressions: Vec<Expression>,
+        scope: Rc<Scope<'a>>,
+    ) -> Self {
+        let mut outputs: Vec<Output> = Vec::new();
+        for expression in expressions.into_iter().rev() {
+            let field = match &expression {
+                Expression::Field(selector) => scope.resolve(selector).ok(),
+                _ => None,
+            };
+            outputs.push(match field {
+                Some(field) if outputs.iter().any(|output| output.moves(field)) => {
+                    Output::Copy(field)
+                }
+                Some(field) => Output::Move(field),
+                None => Output::Evaluate(expression),
+            });
+        }
+        outputs.reverse();
+        Self {
+            input,
+            outputs,
+            scope,
+        }
+    }
+
+    fn project(&self, mut row: Row) -> Result<Row, StorageError> {
+        // Evaluate the expressions first, while all of the fields are still in place
+        let mut output = Vec::with_capacity(self.outputs.len());
+        for projection in &self.outputs {
+            output.push(match projection {
+                Output::Evaluate(expression) => eval_expression(expression, &self.scope, &row)?,
+                _ => DBValue::Null,
+            });
+        }
+        for (value, projection) in output.iter_mut().zip(&self.outputs) {
+            let field = match projection {
+                Output::Copy(field) | Output::Move(field) => *field,
+                Output::Evaluate(_) => continue,
+            };
+            let field = row.get_mut(field).ok_or(StorageError::SchemaMismatch)?;
+            *value = match projection {
+                Output::Move(_) => mem::replace(field, DBValue::Null),
+                _ => field.clone(),
+            };
+        }
+        Ok(output)
+    }
+}
+
+impl Output {
+    fn moves(&self, field: usize) -> bool {
+        matches!(self, Output::Move(moved) if *moved == field)
+    }
+}
+
+impl Operator for Project<'_> {
+    fn next(&mut self) -> Option<Result<Row, StorageError>> {
+        let row = match self.input.next()? {
+            Ok(row) => row,
+            Err(err) => return Some(Err(err)),
+        };
+        Some(self.project(row))
+    }
+}
+
+/// Groups the rows of its input by the values of the `group_by` expressions and evaluates a list
+/// of expressions over each group, see [`group_rows`] and [`eval_grouped`]. The input is read
+/// completely on the first request. Grouping and evaluating the groups are split between
+/// `threads` threads.
+pub struct Aggregate<'a> {
+    input: Box<dyn Operator + 'a>,
+    group_by: Vec<Expression>,
+    expressions: Vec<Expression>,
+    scope: Rc<Scope<'a>>,
+    threads: usize,
+    output: Option<vec::IntoIter<Row>>,
+}
+
+impl<'a> Aggregate<'a> {
+    pub fn new(
+        input: Box<dyn Operator + 'a>,
+        group_by: Vec<Expression>,
+        expressions: Vec<Expression>,
+        scope: Rc<Scope<'a>>,
+        threads: usize,
+    ) -> Self {
+        Self {
+            input,
+            group_by,
+            expressions,
+            scope,
+            threads,
+            output: None,
+        }
+    }
+
+    fn aggregate(&mut self) -> Result<Vec<Row>, StorageError> {
+        let rows = collect_rows(self.input.as_mut())?;
+        let (scope, expressions): (&Scope, _) = (&self.scope, &self.expressions);
+        let groups = group_rows(&self.group_by, scope, rows.iter().collect(), self.threads)?;
+        map_chunks(&groups, self.threads, |groups| {
+            let mut output = Vec::new();
+            for group in groups {
+                let mut row = Vec::new();
+                for expression in expressions {
+                    row.push(eval_grouped(expression, scope, group)?);
+                }
+                output.push(row);
+            }
+            Ok(output)
+        })
+    }
+}
+
+impl Operator for Aggregate<'_> {
+    fn next(&mut self) -> Option<Result<Row, StorageError>> {
+        if self.output.is_none() {
+            match self.aggregate() {
+                Ok(rows) => self.output = Some(rows.into_iter()),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        self.output.as_mut()?.next().map(Ok)
+    }
+}
+
+/// Memory a [`Sort`] may use for rows by default, in bytes.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub const DEFAULT_SORT_MEMORY: usize = 64 * 1024 * 1024;
+
+/// Browsers have no files to spill sorted runs to, so sorts are kept in memory there.
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub const DEFAULT_SORT_MEMORY: usize = usize::MAX;
+
+/// Approximate memory taken by a row, in bytes.
+pub(crate) fn row_size(row: &[DBValue]) -> usize {
+    let text: usize = row
+        .iter()
+        .map(|value| match value {
+            DBValue::Text(text) => text.len(),
+            _ => 0,
+        })
+        .sum();
+    mem::size_of::<Row>() + mem::size_of_val(row) + text
+}
+
+/// Bytes of rows a spilled run writes at a time, encrypted as a block if the database is.
+const SPILL_BLOCK_SIZE: usize = 64 * 1024;
+
+/// Row with its sort keys.
+type Keyed = (Vec<DBValue>, Row);
+
+/// Sorted run of rows with their sort keys, spilled to a temporary file. The file has no name,
+/// so no one else can open it, and it is gone as it is closed. The rows are written in blocks,
+/// each its length, then its nonce and tag if encrypted, and its rows. Runs of encrypted
+/// databases are encrypted with a random key of their own, authenticating each block along with
+/// its position.
+struct Run {
+    file: BufReader<File>,
+    cipher: Option<Cipher>,
+    /// The rows of the block being read.
+    block: io::Cursor<Vec<u8>>,
+    /// Number of blocks read so far.
+    blocks: u64,
+}
+
+impl Run {
+    fn write(rows: Vec<Keyed>, encrypt: bool) -> io::Result<Self> {
+        let cipher = if encrypt {
+            Some(Cipher::random()?)
+        } else {
+            None
+        };
+        let mut writer = BufWriter::new(tempfile::tempfile()?);
+        let mut block = Vec::new();
+        let mut blocks = 0;
+        for (keys, row) in &rows {
+            record::write_row(&mut block, keys)?;
+            record::write_row(&mut block, row)?;
+            if block.len() >= SPILL_BLOCK_SIZE {
+                write_block(&mut writer, cipher.as_ref(), blocks, &mut block)?;
+                blocks += 1;
+            }
+        }
+        if !block.is_empty() {
+            write_block(&mut writer, cipher.as_ref(), blocks, &mut block)?;
+        }
+        let mut file = writer
+            .into_inner()
+            .map_err(io::IntoInnerError::into_error)?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(Self {
+            file: BufReader::new(file),
+            cipher,
+            block: io::Cursor::new(Vec::new()),
+            blocks: 0,
+        })
+    }
+
+    fn next(&mut self) -> io::Result<Option<Keyed>> {
+        let keys = loop {
+            match record::read_row(&mut self.block)? {
+                Some(keys) => break keys,
+                None => match self.read_block()? {
+                    Some(block) => self.block = io::Cursor::new(block),
+                    None => return Ok(None),
+                },
+            }
+        };
+        let row = record::read_row(&mut self.block)?;
+        let row = row.ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        Ok(Some((keys, row)))
+    }
+
+    /// The rows of the next block, decrypted, or `None` at the end of the file.
+    fn read_block(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut length = [0; 8];
+        match self.file.read_exact(&mut length) {
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            read => read?,
+        }
+        let mut nonce = [0; NONCE_SIZE];
+        let mut tag = [0; TAG_SIZE];
+        if self.cipher.is_some() {
+            self.file.read_exact(&mut nonce)?;
+            self.file.read_exact(&mut tag)?;
+        }
+        let mut block = vec![0; u64::from_le_bytes(length) as usize];
+        self.file.read_exact(&mut block)?;
+        if let Some(cipher) = &self.cipher {
+            if !cipher.decrypt(&mut block, &self.blocks.to_le_bytes(), &nonce, &tag) {
+                let message = "A spilled run of a sort was altered";
+                return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+            }
+        }
+        self.blocks += 1;
+        Ok(Some(block))
+    }
+}
+
+/// Writes the rows of a block of a run, encrypted if there is a cipher, and empties the block.
+fn write_block(
+    output: &mut impl Write,
+    cipher: Option<&Cipher>,
+    position: u64,
+    block: &mut Vec<u8>,
+) -> io::Result<()> {
+    output.write_all(&(block.len() as u64).to_le_bytes())?;
+    if let Some(cipher) = cipher {
+        let (nonce, tag) = cipher.encrypt(block, &position.to_le_bytes())?;
+        output.write_all(&nonce)?;
+        output.write_all(&tag)?;
+    }
+    output.write_all(block)?;
+    block.clear();
+    Ok(())
+}
+
+/// Output of a [`Sort`]: the sorted rows in memory, or the sorted runs spilled to disk, to be
+/// merged. Each run is paired with its least row not yet produced.
+enum Sorted {
+    Memory(vec::IntoIter<Keyed>),
+    Runs(Vec<(Option<Keyed>, Run)>),
+}
+
+/// Sorts the rows of its input, which end with the values of the `order_by` terms; these sort
+/// keys are removed from the rows passed on. The input is read completely on the first request.
+/// Rows exceeding the memory budget are sorted in runs, which are spilled to temporary files and
+/// merged as the output is produced.
+pub struct Sort<'a> {
+    input: Box<dyn Operator + 'a>,
+    order_by: Vec<(Expression, SortOrder)>,
+    order: KeyOrder,
+    scope: Rc<Scope<'a>>,
+    memory: usize,
+    encrypt: bool,
+    output: Option<Sorted>,
+}
+
+impl<'a> Sort<'a> {
+    /// Sorts the input, keeping at most about `memory` bytes of rows in memory. The runs spilled
+    /// to temporary files are encrypted if `encrypt` is set, as for encrypted databases.
+    pub fn new(
+        input: Box<dyn Operator + 'a>,
+        order_by: Vec<(Expression, SortOrder)>,
+        scope: Rc<Scope<'a>>,
+        memory: usize,
+        encrypt: bool,
+    ) -> Self {
+        Self {
+            input,
+            order: KeyOrder::new(&order_by, &scope),
+            order_by,
+            scope,
+            memory,
+            encrypt,
+            output: None,
+        }
+    }
+
+    fn sort(&mut self) -> Result<Sorted, StorageError> {
+        let mut rows = Vec::new();
+        let mut size = 0;
+        let mut runs = Vec::new();
+        while let Some(row) = self.input.next() {
+            let mut row = row?;
+            let columns = row
+                .len()
+                .checked_sub(self.order_by.len())
+                .ok_or(StorageError::SchemaMismatch)?;
+            size += row_size(&row);
+            let keys = row.split_off(columns);
+            rows.push((keys, row));
+            if size > self.memory {
+                sort_rows(&mut rows, &self.order_by, &self.scope);
+                runs.push(Run::write(mem::take(&mut rows), self.encrypt)?);
+                size = 0;
+            }
+        }
+        sort_rows(&mut rows, &self.order_by, &self.scope);
+        if runs.is_empty() {
+            return Ok(Sorted::Memory(rows.into_iter()));
+        }
+        if !rows.is_empty() {
+            runs.push(Run::write(rows, self.encrypt)?);
+        }
+        let mut heads = Vec::new();
+        for mut run in runs {
+            heads.push((run.next()?, run));
+        }
+        Ok(Sorted::Runs(heads))
+    }
+}
+
+impl Operator for Sort<'_> {
+    fn next(&mut self) -> Option<Result<Row, StorageError>> {
+        if self.output.is_none() {
+            match self.sort() {
+                Ok(sorted) => self.output = Some(sorted),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        let Self { output, order, .. } = self;
+        let runs = match output.as_mut()? {
+            Sorted::Memory(rows) => return rows.next().map(|(_, row)| Ok(row)),
+            Sorted::Runs(runs) => runs,
+        };
+        // Ties go to the earliest run, which holds the earliest rows of the input
+        let mut least: Option<usize> = None;
+        for (i, (head, _)) in runs.iter().enumerate() {
+            if let Some((keys, _)) = head {
+                let less = least.is_none_or(|least| match &runs[least].0 {
+                    Some((least_keys, _)) => order.compare(keys, least_keys).is_lt(),
+                    None => true,
+                });
+                if less {
+                    least = Some(i);
+                }
+            }
+        }
+        let (head, run) = &mut runs[least?];
+        let (_, row) = head.take()?;
+        match run.next() {
+            Ok(next) => *head = next,
+            Err(err) => return Some(Err(err.into())),
+        }
+        Some(Ok(row))
+    }
+}
+
+/// Skips the first `offset` rows of its input and passes on at most `count` of the rest.
+pub struct Limit<'a> {
+    input: Box<dyn Operator + 'a>,
+    offset: usize,
+    remaining: usize,
+}
+
+impl<'a> Limit<'a> {
+    pub fn new(input: Box<dyn Operator + 'a>, limit: crate::parser::Limit) -> Self {
+        Self {
+            input,
+            offset: limit.offset,
+            remaining: limit.count,
+        }
+    }
+}
+
+impl Operator for Limit<'_> {
+    fn next(&mut self) -> Option<Result<Row, StorageError>> {
+        while self.offset > 0 {
+            self.offset -= 1;
+            if let Err(err) = self.input.next()? {
+                return Some(Err(err));
+            }
+        }
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        self.input.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::Functions;
+    use crate::progress::Progress;
+    #[cfg(feature = "regexp")]
+    use std::sync::Mutex;
+
+    fn values(keys: &[Option<i64>]) -> Box<Values> {
+        let rows: Vec<_> = keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| {
+                let key = key.map_or(DBValue::Null, DBValue::Integer);
+                vec![key, DBValue::Integer(i as i64)]
+            })
+            .collect();
+        Box::new(Values::new(rows))
+    }
+
+    #[test]
+    fn merge_join() {
+        let sequences = HashMap::new();
+        let functions = Functions::new();
+        let progress = Progress::new();
+        let scope = Rc::new(Scope {
+            tables: Vec::new(),
+            sequences: &sequences,
+            functions: &functions,
+            progress: &progress,
+            #[cfg(feature = "regexp")]
+            regexes: Mutex::new(HashMap::new()),
+        });
+        let left = values(&[None, Some(1), Some(2), Some(2), Some(4)]);
+        let right = values(&[None, Some(2), Some(2), Some(3), Some(4), Some(5)]);
+        let mut join = MergeJoin::new(left, right, (0, 0), None, scope);
+        let pairs: Vec<_> = collect_rows(&mut join)
+            .ok()
+            .into_iter()
+            .flatten()
+            .map(|row| (row[1].clone(), row[3].clone()))
+            .collect();
+        let pair = |l, r| (DBValue::Integer(l), DBValue::Integer(r));
+        assert_eq!(
+            pairs,
+            vec![pair(2, 1), pair(2, 2), pair(3, 1), pair(3, 2), pair(4, 4)]
+        );
+        let mut limit = Limit::new(
+            values(&[Some(1); 5]),
+            crate::parser::Limit {
+                count: 2,
+                offset: 4,
+            },
+        );
+        assert_eq!(
+            collect_rows(&mut limit).map(|rows| rows.len()).ok(),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn project_moves_fields() {
+        let sequences = HashMap::new();
+        let functions = Functions::new();
+        let progress = Progress::new();
+        let schema = Schema::from(vec![
+            (String::from("a"), DBType::Integer),
+            (String::from("b"), DBType::Text),
+        ]);
+        let scope = Rc::new(Scope {
+            tables: vec![(Cow::Borrowed("t"), Cow::Owned(schema))],
+            sequences: &sequences,
+            functions: &functions,
+            progress: &progress,
+            #[cfg(feature = "regexp")]
+            regexes: Mutex::new(HashMap::new()),
+        });
+        let field = |name: &str| {
+            Expression::Field(Selector {
+                table: None,
+                field: String::from(name),
+            })
+        };
+        let plus_one = Expression::Arithmetic(
+            ArithmeticOperator::Add,
+            Box::new(field("a")),
+            Box::new(Expression::Value(DBValue::Integer(1))),
+        );
+        let text = DBValue::Text(String::from("x"));
+        let input = Values::new(vec![vec![DBValue::Integer(1), text.clone()]]);
+        let expressions = vec![field("a"), plus_one, field("b"), field("a")];
+        let mut project = Project::new(Box::new(input), expressions, scope);
+        assert_eq!(
+            collect_rows(&mut project).ok(),
+            Some(vec![vec![
+                DBValue::Integer(1),
+                DBValue::Integer(2),
+                text,
+                DBValue::Integer(1)
+            ]])
+        );
+    }
+
+    #[test]
+    fn spilled_runs() {
+        let keyed = |i: i64| {
+            let text = DBValue::Text(format!("secret number {}", i));
+            (vec![DBValue::Integer(i)], vec![text])
+        };
+        let rows: Vec<Keyed> = (0..5000).map(keyed).collect();
+        let encrypt = if cfg!(feature = "encryption") {
+            vec![false, true]
+        } else {
+            vec![false]
+        };
+        for encrypt in encrypt {
+            let mut run = Run::write(rows.clone(), encrypt).unwrap_or_else(|err| panic!("{}", err));
+            let mut bytes = Vec::new();
+            let file = run.file.get_mut();
+            assert!(file.read_to_end(&mut bytes).is_ok());
+            assert!(file.seek(SeekFrom::Start(0)).is_ok());
+            let secret = b"secret number";
+            let plain = bytes.windows(secret.len()).any(|window| window == secret);
+            assert_eq!(plain, !encrypt);
+            let mut read = Vec::new();
+            while let Some(row) = run.next().unwrap_or_else(|err| panic!("{}", err)) {
+                read.push(row);
+            }
+            assert!(run.blocks > 1);
+            assert_eq!(read, rows);
+        }
+    }
+}
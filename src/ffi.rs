@@ -0,0 +1,552 @@
+//! C interface to the database, under the 'ffi' feature, for embedding it in C or in other
+//! languages through their C bindings, e.g. Python's ctypes. The functions follow SQLite's: a
+//! database is opened into a handle, statements are prepared on it, have values bound to their
+//! placeholders and are stepped through their rows, whose columns are then read, e.g.
+//!
+//! ```c
+//! juicydb *db;
+//! juicydb_stmt *stmt;
+//! juicydb_open(":memory:", &db);
+//! juicydb_exec(db, "create table t (a integer); insert into t values (1);");
+//! juicydb_prepare(db, "select a from t where a = ?;", &stmt);
+//! juicydb_bind_int(stmt, 1, 1);
+//! while (juicydb_step(stmt) == JUICYDB_ROW) {
+//!     printf("%lld\n", juicydb_column_int(stmt, 0));
+//! }
+//! juicydb_finalize(stmt);
+//! juicydb_close(db);
+//! ```
+//!
+//! The declarations are in `include/juicydb.h`. Text passed in must be valid UTF-8 and
+//! nul-terminated. Text returned stays valid until the next call on the same handle.
+
+use crate::connection::{Error, PreparedStatement};
+use crate::database::Database;
+use crate::db::{DBValue, ResultSet, Row};
+use crate::storage_manager::Rows;
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_double, c_int};
+use std::ptr;
+
+pub const JUICYDB_OK: c_int = 0;
+/// The statement failed; see [`juicydb_errmsg`].
+pub const JUICYDB_ERROR: c_int = 1;
+/// The function was called with a null handle, text that isn't UTF-8, or a column or
+/// placeholder out of range.
+pub const JUICYDB_MISUSE: c_int = 2;
+/// [`juicydb_step`] produced a row.
+pub const JUICYDB_ROW: c_int = 100;
+/// [`juicydb_step`] finished the statement.
+pub const JUICYDB_DONE: c_int = 101;
+
+/// Types of values as seen through [`juicydb_column_type`]. Booleans are integers, and dates,
+/// times, timestamps, decimals and UUIDs are text, as the REPL shows them.
+pub const JUICYDB_NULL: c_int = 0;
+pub const JUICYDB_INTEGER: c_int = 1;
+pub const JUICYDB_REAL: c_int = 2;
+pub const JUICYDB_TEXT: c_int = 3;
+
+/// Handle of an open database, along with the message of its last error.
+pub struct JuicyDb {
+    database: Database,
+    error: Option<CString>,
+}
+
+/// Handle of a prepared statement, along with its bound values and, while it is being stepped
+/// through, its rows.
+pub struct JuicyStmt {
+    /// The database the statement was prepared on, which must outlive it.
+    db: *mut JuicyDb,
+    statement: PreparedStatement,
+    params: Vec<DBValue>,
+    rows: Option<Rows<'static>>,
+    columns: Vec<CString>,
+    row: Option<Row>,
+    /// Texts of the columns of the current row, made as they are asked for.
+    texts: Vec<Option<CString>>,
+}
+
+impl JuicyDb {
+    /// Keeps the message of the error for [`juicydb_errmsg`].
+    fn fail(&mut self, error: Error) -> c_int {
+        let message = error.to_string().replace('\0', " ");
+        self.error = CString::new(message).ok();
+        JUICYDB_ERROR
+    }
+}
+
+impl JuicyStmt {
+    fn value(&self, column: c_int) -> Option<&DBValue> {
+        self.row.as_ref()?.get(usize::try_from(column).ok()?)
+    }
+}
+
+/// The text, if the pointer isn't null and the text is UTF-8.
+///
+/// # Safety
+///
+/// `text` must be null or point to a nul-terminated string.
+unsafe fn text<'a>(text: *const c_char) -> Option<&'a str> {
+    if text.is_null() {
+        return None;
+    }
+    CStr::from_ptr(text).to_str().ok()
+}
+
+/// Opens the database in the file at `path`, or a new one in memory for `:memory:`, storing
+/// its handle in `db`; see [`Database::open`]. The handle is closed by [`juicydb_close`], even
+/// if opening fails, in which case it holds the error.
+///
+/// # Safety
+///
+/// `path` must be a nul-terminated string and `db` must point to memory for a handle.
+#[no_mangle]
+pub unsafe extern "C" fn juicydb_open(path: *const c_char, db: *mut *mut JuicyDb) -> c_int {
+    if db.is_null() {
+        return JUICYDB_MISUSE;
+    }
+    *db = ptr::null_mut();
+    let path = match text(path) {
+        Some(path) => path,
+        None => return JUICYDB_MISUSE,
+    };
+    let (database, error) = match Database::open(path) {
+        Ok(database) => (database, None),
+        Err(error) => (Database::new(), Some(error)),
+    };
+    let mut handle = Box::new(JuicyDb {
+        database,
+        error: None,
+    });
+    let status = match error {
+        Some(error) => handle.fail(error),
+        None => JUICYDB_OK,
+    };
+    *db = Box::into_raw(handle);
+    status
+}
+
+/// Closes the database, rolling back a transaction still in progress. The statements prepared
+/// on it must have been finalized.
+///
+/// # Safety
+///
+/// `db` must be null or a handle from [`juicydb_open`], not used again afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn juicydb_close(db: *mut JuicyDb) -> c_int {
+    if !db.is_null() {
+        drop(Box::from_raw(db));
+    }
+    JUICYDB_OK
+}
+
+/// Executes the statements of an SQL script in turn, until one fails; see
+/// [`Database::execute_batch`].
+///
+/// # Safety
+///
+/// `db` must be a handle from [`juicydb_open`] and `sql` a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn juicydb_exec(db: *mut JuicyDb, sql: *const c_char) -> c_int {
+    let db = match db.as_mut() {
+        Some(db) => db,
+        None => return JUICYDB_MISUSE,
+    };
+    let sql = match text(sql) {
+        Some(sql) => sql,
+        None => return JUICYDB_MISUSE,
+    };
+    match db.database.execute_batch(sql, false) {
+        Ok(_) => JUICYDB_OK,
+        Err(error) => db.fail(error),
+    }
+}
+
+/// The message of the last error on the database, or null if there was none.
+///
+/// # Safety
+///
+/// `db` must be a handle from [`juicydb_open`].
+#[no_mangle]
+pub unsafe extern "C" fn juicydb_errmsg(db: *const JuicyDb) -> *const c_char {
+    match db.as_ref().and_then(|db| db.error.as_ref()) {
+        Some(error) => error.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// Prepares a statement, storing its handle in `stmt`; see [`Database::prepare`].
+///
+/// # Safety
+///
+/// `db` must be a handle from [`juicydb_open`], `sql` a nul-terminated string and `stmt` must
+/// point to memory for a handle.
+#[no_mangle]
+pub unsafe extern "C" fn juicydb_prepare(
+    db: *mut JuicyDb,
+    sql: *const c_char,
+    stmt: *mut *mut JuicyStmt,
+) -> c_int {
+    if stmt.is_null() {
+        return JUICYDB_MISUSE;
+    }
+    *stmt = ptr::null_mut();
+    let handle = db;
+    let db = match db.as_mut() {
+        Some(db) => db,
+        None => return JUICYDB_MISUSE,
+    };
+    let sql = match text(sql) {
+        Some(sql) => sql,
+        None => return JUICYDB_MISUSE,
+    };
+    let statement = match db.database.prepare(sql) {
+        Ok(statement) => statement,
+        Err(error) => return db.fail(error),
+    };
+    *stmt = Box::into_raw(Box::new(JuicyStmt {
+        db: handle,
+        params: vec![DBValue::Null; statement.parameter_count()],
+        statement,
+        rows: None,
+        columns: Vec::new(),
+        row: None,
+        texts: Vec::new(),
+    }));
+    JUICYDB_OK
+}
+
+/// Binds a value to the placeholder at `index`, from 1.
+///
+/// # Safety
+///
+/// `stmt` must be a handle from [`juicydb_prepare`].
+unsafe fn bind(stmt: *mut JuicyStmt, index: c_int, value: DBValue) -> c_int {
+    let stmt = match stmt.as_mut() {
+        Some(stmt) => stmt,
+        None => return JUICYDB_MISUSE,
+    };
+    let param = usize::try_from(index)
+        .ok()
+        .and_then(|index| index.checked_sub(1))
+        .and_then(|index| stmt.params.get_mut(index));
+    match param {
+        Some(param) => {
+            *param = value;
+            JUICYDB_OK
+        }
+        None => JUICYDB_MISUSE,
+    }
+}
+
+/// Binds an integer to the placeholder at `index`, from 1. Values stay bound when the
+/// statement is reset.
+///
+/// # Safety
+///
+/// `stmt` must be a handle from [`juicydb_prepare`].
+#[no_mangle]
+pub unsafe extern "C" fn juicydb_bind_int(stmt: *mut JuicyStmt, index: c_int, value: i64) -> c_int {
+    bind(stmt, index, DBValue::Integer(value))
+}
+
+/// Binds a real to the placeholder at `index`, from 1.
+///
+/// # Safety
+///
+/// `stmt` must be a handle from [`juicydb_prepare`].
+#[no_mangle]
+pub unsafe extern "C" fn juicydb_bind_double(
+    stmt: *mut JuicyStmt,
+    index: c_int,
+    value: c_double,
+) -> c_int {
+    bind(stmt, index, DBValue::Real(value))
+}
+
+/// Binds text to the placeholder at `index`, from 1. The text is copied.
+///
+/// # Safety
+///
+/// `stmt` must be a handle from [`juicydb_prepare`] and `value` a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn juicydb_bind_text(
+    stmt: *mut JuicyStmt,
+    index: c_int,
+    value: *const c_char,
+) -> c_int {
+    match text(value) {
+        Some(value) => bind(stmt, index, DBValue::Text(String::from(value))),
+        None => JUICYDB_MISUSE,
+    }
+}
+
+/// Binds null to the placeholder at `index`, from 1.
+///
+/// # Safety
+///
+/// `stmt` must be a handle from [`juicydb_prepare`].
+#[no_mangle]
+pub unsafe extern "C" fn juicydb_bind_null(stmt: *mut JuicyStmt, index: c_int) -> c_int {
+    bind(stmt, index, DBValue::Null)
+}
+
+/// Steps through the statement: the first step executes it, and steps of a query then produce
+/// its rows one at a time. Returns [`JUICYDB_ROW`] for a row, [`JUICYDB_DONE`] once there are
+/// no more, or [`JUICYDB_ERROR`]. A finished statement is executed again by the next step.
+///
+/// # Safety
+///
+/// `stmt` must be a handle from [`juicydb_prepare`], whose database is still open.
+#[no_mangle]
+pub unsafe extern "C" fn juicydb_step(stmt: *mut JuicyStmt) -> c_int {
+    let stmt = match stmt.as_mut() {
+        Some(stmt) => stmt,
+        None => return JUICYDB_MISUSE,
+    };
+    let db = match stmt.db.as_mut() {
+        Some(db) => db,
+        None => return JUICYDB_MISUSE,
+    };
+    stmt.texts.clear();
+    if stmt.rows.is_none() {
+        let connection = db.database.connection();
+        if !stmt.statement.is_query() {
+            return match connection.execute_prepared(&stmt.statement, &stmt.params) {
+                Ok(_) => JUICYDB_DONE,
+                Err(error) => db.fail(error),
+            };
+        }
+        // The rows are collected, as a cursor would keep the database from other statements
+        // until the statement is reset
+        let rows = match connection.query_prepared(&stmt.statement, &stmt.params) {
+            Ok(cursor) => {
+                let columns = cursor.columns().to_vec();
+                cursor
+                    .collect::<Result<_, _>>()
+                    .map(|rows| Rows::from(ResultSet { columns, rows }))
+                    .map_err(Error::Storage)
+            }
+            Err(error) => Err(error),
+        };
+        let rows = match rows {
+            Ok(rows) => rows,
+            Err(error) => return db.fail(error),
+        };
+        stmt.columns = rows
+            .columns()
+            .iter()
+            .map(|column| CString::new(column.name.replace('\0', " ")).unwrap_or_default())
+            .collect();
+        stmt.rows = Some(rows);
+    }
+    match stmt.rows.as_mut().and_then(|rows| rows.next()) {
+        Some(Ok(row)) => {
+            stmt.texts = vec![None; row.len()];
+            stmt.row = Some(row);
+            JUICYDB_ROW
+        }
+        Some(Err(error)) => {
+            juicydb_reset(stmt);
+            db.fail(Error::Storage(error))
+        }
+        None => {
+            juicydb_reset(stmt);
+            JUICYDB_DONE
+        }
+    }
+}
+
+/// Resets the statement to be executed again by the next step, keeping its bound values.
+///
+/// # Safety
+///
+/// `stmt` must be a handle from [`juicydb_prepare`].
+#[no_mangle]
+pub unsafe extern "C" fn juicydb_reset(stmt: *mut JuicyStmt) -> c_int {
+    let stmt = match stmt.as_mut() {
+        Some(stmt) => stmt,
+        None => return JUICYDB_MISUSE,
+    };
+    stmt.rows = None;
+    stmt.row = None;
+    stmt.texts.clear();
+    JUICYDB_OK
+}
+
+/// Frees the statement.
+///
+/// # Safety
+///
+/// `stmt` must be null or a handle from [`juicydb_prepare`], not used again afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn juicydb_finalize(stmt: *mut JuicyStmt) -> c_int {
+    if !stmt.is_null() {
+        drop(Box::from_raw(stmt));
+    }
+    JUICYDB_OK
+}
+
+/// Number of columns of the rows of the query, once it has been stepped, or else zero.
+///
+/// # Safety
+///
+/// `stmt` must be a handle from [`juicydb_prepare`].
+#[no_mangle]
+pub unsafe extern "C" fn juicydb_column_count(stmt: *const JuicyStmt) -> c_int {
+    stmt.as_ref().map_or(0, |stmt| {
+        c_int::try_from(stmt.columns.len()).unwrap_or(c_int::MAX)
+    })
+}
+
+/// Name of the column at `column`, from 0, or null if out of range.
+///
+/// # Safety
+///
+/// `stmt` must be a handle from [`juicydb_prepare`].
+#[no_mangle]
+pub unsafe extern "C" fn juicydb_column_name(
+    stmt: *const JuicyStmt,
+    column: c_int,
+) -> *const c_char {
+    stmt.as_ref()
+        .and_then(|stmt| stmt.columns.get(usize::try_from(column).ok()?))
+        .map_or(ptr::null(), |name| name.as_ptr())
+}
+
+/// Type of the value of the current row at `column`, from 0, as one of [`JUICYDB_NULL`],
+/// [`JUICYDB_INTEGER`], [`JUICYDB_REAL`] and [`JUICYDB_TEXT`].
+///
+/// # Safety
+///
+/// `stmt` must be a handle from [`juicydb_prepare`].
+#[no_mangle]
+pub unsafe extern "C" fn juicydb_column_type(stmt: *const JuicyStmt, column: c_int) -> c_int {
+    match stmt.as_ref().and_then(|stmt| stmt.value(column)) {
+        None | Some(DBValue::Null) => JUICYDB_NULL,
+        Some(DBValue::Integer(_)) | Some(DBValue::Boolean(_)) => JUICYDB_INTEGER,
+        Some(DBValue::Real(_)) => JUICYDB_REAL,
+        Some(_) => JUICYDB_TEXT,
+    }
+}
+
+/// The value of the current row at `column`, from 0, as an integer: reals are truncated, and
+/// other values are zero.
+///
+/// # Safety
+///
+/// `stmt` must be a handle from [`juicydb_prepare`].
+#[no_mangle]
+pub unsafe extern "C" fn juicydb_column_int(stmt: *const JuicyStmt, column: c_int) -> i64 {
+    match stmt.as_ref().and_then(|stmt| stmt.value(column)) {
+        Some(DBValue::Integer(i)) => *i,
+        Some(DBValue::Boolean(b)) => i64::from(*b),
+        Some(DBValue::Real(r)) => *r as i64,
+        _ => 0,
+    }
+}
+
+/// The value of the current row at `column`, from 0, as a real; values other than numbers are
+/// zero.
+///
+/// # Safety
+///
+/// `stmt` must be a handle from [`juicydb_prepare`].
+#[no_mangle]
+pub unsafe extern "C" fn juicydb_column_double(stmt: *const JuicyStmt, column: c_int) -> c_double {
+    match stmt.as_ref().and_then(|stmt| stmt.value(column)) {
+        Some(DBValue::Integer(i)) => *i as c_double,
+        Some(DBValue::Boolean(b)) => c_double::from(u8::from(*b)),
+        Some(DBValue::Real(r)) => *r,
+        _ => 0.0,
+    }
+}
+
+/// The value of the current row at `column`, from 0, as text, or null for a null value. The
+/// text stays valid until the statement is stepped, reset or finalized.
+///
+/// # Safety
+///
+/// `stmt` must be a handle from [`juicydb_prepare`].
+#[no_mangle]
+pub unsafe extern "C" fn juicydb_column_text(stmt: *mut JuicyStmt, column: c_int) -> *const c_char {
+    let stmt = match stmt.as_mut() {
+        Some(stmt) => stmt,
+        None => return ptr::null(),
+    };
+    let text = match stmt.value(column) {
+        None | Some(DBValue::Null) => return ptr::null(),
+        Some(DBValue::Text(text)) => text.replace('\0', " "),
+        Some(value) => value.to_string(),
+    };
+    let text = stmt.texts[column as usize].get_or_insert_with(|| CString::new(text).unwrap());
+    text.as_ptr()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn c(text: &str) -> CString {
+        CString::new(text).unwrap()
+    }
+
+    #[test]
+    fn statements_through_handles() {
+        unsafe {
+            let mut db = ptr::null_mut();
+            assert_eq!(juicydb_open(c(":memory:").as_ptr(), &mut db), JUICYDB_OK);
+            let script = "create table t (a integer, b text); \
+                insert into t values (1, 'one'); insert into t values (2, null);";
+            assert_eq!(juicydb_exec(db, c(script).as_ptr()), JUICYDB_OK);
+            assert!(juicydb_errmsg(db).is_null());
+
+            let mut stmt = ptr::null_mut();
+            let select = c("select a, b from t where a >= ?;");
+            assert_eq!(juicydb_prepare(db, select.as_ptr(), &mut stmt), JUICYDB_OK);
+            assert_eq!(juicydb_bind_int(stmt, 2, 1), JUICYDB_MISUSE);
+            assert_eq!(juicydb_bind_int(stmt, 1, 1), JUICYDB_OK);
+            assert_eq!(juicydb_step(stmt), JUICYDB_ROW);
+            assert_eq!(juicydb_column_count(stmt), 2);
+            let name = CStr::from_ptr(juicydb_column_name(stmt, 1));
+            assert_eq!(name.to_str(), Ok("b"));
+            assert_eq!(juicydb_column_type(stmt, 0), JUICYDB_INTEGER);
+            assert_eq!(juicydb_column_int(stmt, 0), 1);
+            let text = CStr::from_ptr(juicydb_column_text(stmt, 1));
+            assert_eq!(text.to_str(), Ok("one"));
+            assert_eq!(juicydb_step(stmt), JUICYDB_ROW);
+            assert_eq!(juicydb_column_type(stmt, 1), JUICYDB_NULL);
+            assert!(juicydb_column_text(stmt, 1).is_null());
+            assert_eq!(juicydb_step(stmt), JUICYDB_DONE);
+
+            // Stepping again runs the query anew, with the values bound to it
+            assert_eq!(juicydb_bind_int(stmt, 1, 2), JUICYDB_OK);
+            assert_eq!(juicydb_step(stmt), JUICYDB_ROW);
+            assert_eq!(juicydb_column_double(stmt, 0), 2.0);
+            assert_eq!(juicydb_step(stmt), JUICYDB_DONE);
+            assert_eq!(juicydb_finalize(stmt), JUICYDB_OK);
+
+            let insert = c("insert into t values (?, ?);");
+            assert_eq!(juicydb_prepare(db, insert.as_ptr(), &mut stmt), JUICYDB_OK);
+            juicydb_bind_int(stmt, 1, 3);
+            juicydb_bind_text(stmt, 2, c("three").as_ptr());
+            assert_eq!(juicydb_step(stmt), JUICYDB_DONE);
+            juicydb_finalize(stmt);
+
+            let missing = c("select a from missing;");
+            assert_eq!(juicydb_prepare(db, missing.as_ptr(), &mut stmt), JUICYDB_OK);
+            assert_eq!(juicydb_step(stmt), JUICYDB_ERROR);
+            assert!(!juicydb_errmsg(db).is_null());
+            juicydb_finalize(stmt);
+
+            assert_eq!(
+                juicydb_exec(db, c("selec a from t;").as_ptr()),
+                JUICYDB_ERROR
+            );
+            let message = CStr::from_ptr(juicydb_errmsg(db)).to_str().unwrap();
+            assert!(message.starts_with("Statement 1 failed"));
+            assert_eq!(juicydb_close(db), JUICYDB_OK);
+        }
+    }
+}
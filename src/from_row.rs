@@ -0,0 +1,239 @@
+//! Mapping the rows of results into Rust values: [`FromValue`] converts a single value, e.g. to
+//! an `i64`, a `String`, or an `Option` of either for nullable columns, and [`FromRow`] a whole
+//! row, e.g. to a tuple by the positions of the columns, or to a struct by their names with
+//! `#[derive(FromRow)]` from the `juicydb-derive` crate, under the `derive` feature:
+//!
+//! ```
+//! # #[cfg(feature = "derive")]
+//! # {
+//! #[derive(juicydb::FromRow)]
+//! struct User {
+//!     id: i64,
+//!     name: Option<String>,
+//! }
+//!
+//! let mut db = juicydb::Database::new();
+//! db.execute("create table users (id integer, name text);")?;
+//! db.execute("insert into users values (1, 'ada');")?;
+//! let users: Vec<User> = db.query_as("select * from users;")?;
+//! assert_eq!(users[0].name.as_deref(), Some("ada"));
+//! # }
+//! # Ok::<(), juicydb::Error>(())
+//! ```
+
+use crate::db::{DBValue, ResultColumn};
+use crate::decimal::Decimal;
+use crate::uuid::Uuid;
+use std::any;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// A Rust type values of columns convert to.
+pub trait FromValue: Sized {
+    /// The value as `Self`, or `None` if it is of another type.
+    fn from_value(value: &DBValue) -> Option<Self>;
+}
+
+impl FromValue for DBValue {
+    fn from_value(value: &DBValue) -> Option<Self> {
+        Some(value.clone())
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &DBValue) -> Option<Self> {
+        match value {
+            DBValue::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for i32 {
+    fn from_value(value: &DBValue) -> Option<Self> {
+        i64::from_value(value).and_then(|i| i32::try_from(i).ok())
+    }
+}
+
+/// Integers convert to floats too, possibly losing precision.
+impl FromValue for f64 {
+    fn from_value(value: &DBValue) -> Option<Self> {
+        match value {
+            DBValue::Real(r) => Some(*r),
+            DBValue::Integer(i) => Some(*i as f64),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &DBValue) -> Option<Self> {
+        match value {
+            DBValue::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &DBValue) -> Option<Self> {
+        match value {
+            DBValue::Text(text) => Some(text.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for Decimal {
+    fn from_value(value: &DBValue) -> Option<Self> {
+        match value {
+            DBValue::Decimal(decimal) => Some(*decimal),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for Uuid {
+    fn from_value(value: &DBValue) -> Option<Self> {
+        match value {
+            DBValue::Uuid(uuid) => Some(*uuid),
+            _ => None,
+        }
+    }
+}
+
+/// Null converts to `None`, and any other value to `Some` of the value as `T`.
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &DBValue) -> Option<Self> {
+        match value {
+            DBValue::Null => Some(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+/// A row of a result along with the columns of the result, to get values by column name.
+pub struct ResultRow<'a> {
+    columns: &'a [ResultColumn],
+    values: &'a [DBValue],
+}
+
+impl<'a> ResultRow<'a> {
+    pub fn new(columns: &'a [ResultColumn], values: &'a [DBValue]) -> Self {
+        Self { columns, values }
+    }
+
+    /// The value of the first column of the name, as `T`.
+    pub fn get<T: FromValue>(&self, column: &str) -> Result<T, FromRowError> {
+        let index = self
+            .columns
+            .iter()
+            .position(|result_column| result_column.name == column)
+            .ok_or_else(|| FromRowError::MissingColumn(String::from(column)))?;
+        self.convert(index, column)
+    }
+
+    /// The value of the column at the index, as `T`.
+    pub fn get_index<T: FromValue>(&self, index: usize) -> Result<T, FromRowError> {
+        match self.columns.get(index) {
+            Some(column) => self.convert(index, &column.name),
+            None => Err(FromRowError::MissingColumn(index.to_string())),
+        }
+    }
+
+    fn convert<T: FromValue>(&self, index: usize, column: &str) -> Result<T, FromRowError> {
+        T::from_value(&self.values[index]).ok_or_else(|| FromRowError::InvalidType {
+            column: String::from(column),
+            expected: any::type_name::<T>(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+/// A Rust type rows of results convert to.
+pub trait FromRow: Sized {
+    fn from_row(row: &ResultRow<'_>) -> Result<Self, FromRowError>;
+}
+
+/// Tuples convert from rows by the positions of the columns.
+macro_rules! tuple_from_row {
+    ($($t:ident $i:tt),+) => {
+        impl<$($t: FromValue),+> FromRow for ($($t,)+) {
+            fn from_row(row: &ResultRow<'_>) -> Result<Self, FromRowError> {
+                Ok(($(row.get_index::<$t>($i)?,)+))
+            }
+        }
+    };
+}
+
+tuple_from_row!(A 0);
+tuple_from_row!(A 0, B 1);
+tuple_from_row!(A 0, B 1, C 2);
+tuple_from_row!(A 0, B 1, C 2, D 3);
+tuple_from_row!(A 0, B 1, C 2, D 3, E 4);
+tuple_from_row!(A 0, B 1, C 2, D 3, E 4, F 5);
+
+/// Error converting a row of a result into a Rust value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FromRowError {
+    /// No column of the name, or at the index.
+    MissingColumn(String),
+    /// The value of the column can't be converted into the type.
+    InvalidType {
+        column: String,
+        expected: &'static str,
+    },
+}
+
+impl fmt::Display for FromRowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingColumn(column) => write!(f, "No column '{}' in the result", column),
+            Self::InvalidType { column, expected } => {
+                write!(f, "Column '{}' can't be read as {}", column, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FromRowError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::DBType;
+
+    #[test]
+    fn rows_to_tuples() {
+        let columns: Vec<_> = ["id", "name"]
+            .iter()
+            .map(|name| ResultColumn {
+                name: String::from(*name),
+                db_type: Some(DBType::Integer),
+            })
+            .collect();
+        let values = vec![DBValue::Integer(1), DBValue::Null];
+        let row = ResultRow::new(&columns, &values);
+        let converted = <(i64, Option<String>)>::from_row(&row).ok();
+        assert_eq!(converted, Some((1, None)));
+        assert_eq!(row.get::<f64>("id").ok(), Some(1.0));
+        assert_eq!(
+            row.get::<String>("name"),
+            Err(FromRowError::InvalidType {
+                column: String::from("name"),
+                expected: any::type_name::<String>(),
+            })
+        );
+        assert!(matches!(
+            <(i64, Option<String>, bool)>::from_row(&row),
+            Err(FromRowError::MissingColumn(_))
+        ));
+    }
+}
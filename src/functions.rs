@@ -0,0 +1,259 @@
+//! Functions defined by the embedding application, callable from SQL expressions like the
+//! built-in ones: scalar functions from closures, e.g.
+//!
+//! ```
+//! let mut db = juicydb::Database::new();
+//! db.create_function("reverse", 1, |args| match &args[0] {
+//!     juicydb::DBValue::Text(text) => Ok(text.chars().rev().collect::<String>()),
+//!     _ => Err(String::from("reverse takes text")),
+//! });
+//! db.execute("create table words (word text);")?;
+//! db.execute("insert into words values ('abc');")?;
+//! let result = db.query("select reverse(word) from words;")?;
+//! assert_eq!(result.rows[0][0], juicydb::DBValue::Text(String::from("cba")));
+//! # Ok::<(), juicydb::Error>(())
+//! ```
+//!
+//! and aggregate functions from implementations of [`Aggregate`].
+
+use crate::db::DBValue;
+use crate::params::ToValue;
+use crate::storage_manager::StorageError;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+type ScalarBody = dyn Fn(&[DBValue]) -> Result<DBValue, String> + Send + Sync;
+
+/// A function of a fixed number of arguments, evaluated once per row.
+#[derive(Clone)]
+pub struct ScalarFunction {
+    arity: usize,
+    body: Arc<ScalarBody>,
+}
+
+impl ScalarFunction {
+    /// The body gets the values of the arguments and returns the value of the call, or an error
+    /// message failing the statement.
+    pub fn new<F, T>(arity: usize, body: F) -> Self
+    where
+        F: Fn(&[DBValue]) -> Result<T, String> + Send + Sync + 'static,
+        T: ToValue,
+    {
+        Self {
+            arity,
+            body: Arc::new(move |args| body(args).map(|value| value.to_value())),
+        }
+    }
+
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+}
+
+/// A function computed over the rows of a group, like `count`: a state is created for each
+/// group, updated with the values of the arguments for each row of the group, and turned into
+/// the value of the call once all rows are seen, e.g.
+///
+/// ```
+/// use juicydb::functions::Aggregate;
+/// use juicydb::DBValue;
+///
+/// /// The largest text value.
+/// struct Longest;
+///
+/// impl Aggregate for Longest {
+///     type State = Option<String>;
+///
+///     fn init(&self) -> Self::State {
+///         None
+///     }
+///
+///     fn step(&self, longest: &mut Self::State, args: &[DBValue]) -> Result<(), String> {
+///         if let DBValue::Text(text) = &args[0] {
+///             if longest.as_ref().map_or(true, |longest| text.len() > longest.len()) {
+///                 *longest = Some(text.clone());
+///             }
+///         }
+///         Ok(())
+///     }
+///
+///     fn finalize(&self, longest: Self::State) -> Result<DBValue, String> {
+///         Ok(longest.map_or(DBValue::Null, DBValue::Text))
+///     }
+/// }
+///
+/// let mut db = juicydb::Database::new();
+/// db.create_aggregate("longest", 1, Longest);
+/// db.execute("create table words (word text);")?;
+/// db.execute("insert into words values ('a');")?;
+/// db.execute("insert into words values ('abc');")?;
+/// let result = db.query("select longest(word) from words;")?;
+/// assert_eq!(result.rows[0][0], DBValue::Text(String::from("abc")));
+/// # Ok::<(), juicydb::Error>(())
+/// ```
+pub trait Aggregate: Send + Sync + 'static {
+    type State;
+
+    /// The state of an empty group.
+    fn init(&self) -> Self::State;
+
+    /// Adds a row of the group with the values of the arguments; an error message fails the
+    /// statement.
+    fn step(&self, state: &mut Self::State, args: &[DBValue]) -> Result<(), String>;
+
+    /// The value of the call for the group.
+    fn finalize(&self, state: Self::State) -> Result<DBValue, String>;
+}
+
+/// [`Aggregate`] with the state hidden, to register aggregates of different states together.
+trait Aggregator: Send + Sync {
+    fn aggregate(&self, rows: &[Vec<DBValue>]) -> Result<DBValue, String>;
+}
+
+impl<A: Aggregate> Aggregator for A {
+    fn aggregate(&self, rows: &[Vec<DBValue>]) -> Result<DBValue, String> {
+        let mut state = self.init();
+        for args in rows {
+            self.step(&mut state, args)?;
+        }
+        self.finalize(state)
+    }
+}
+
+/// An [`Aggregate`] of a fixed number of arguments.
+#[derive(Clone)]
+pub struct AggregateFunction {
+    arity: usize,
+    aggregate: Arc<dyn Aggregator>,
+}
+
+impl AggregateFunction {
+    pub fn new(arity: usize, aggregate: impl Aggregate) -> Self {
+        Self {
+            arity,
+            aggregate: Arc::new(aggregate),
+        }
+    }
+
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+}
+
+/// The functions registered with a database, by name. Names are case-insensitive, and the
+/// built-in functions take precedence over registered ones of the same name. A name is either
+/// of a scalar or of an aggregate function.
+#[derive(Clone, Default)]
+pub struct Functions {
+    scalar: HashMap<String, ScalarFunction>,
+    aggregate: HashMap<String, AggregateFunction>,
+}
+
+impl Functions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the function, replacing any registered before under the name.
+    pub fn insert_scalar(&mut self, name: &str, function: ScalarFunction) {
+        self.remove(name);
+        self.scalar.insert(name.to_ascii_lowercase(), function);
+    }
+
+    /// Registers the aggregate function, replacing any registered before under the name.
+    pub fn insert_aggregate(&mut self, name: &str, function: AggregateFunction) {
+        self.remove(name);
+        self.aggregate.insert(name.to_ascii_lowercase(), function);
+    }
+
+    /// Unregisters the function of the name, returning whether there was one.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        let scalar = self.scalar.remove(&name).is_some();
+        self.aggregate.remove(&name).is_some() || scalar
+    }
+
+    pub fn scalar(&self, name: &str) -> Option<&ScalarFunction> {
+        self.scalar.get(&name.to_ascii_lowercase())
+    }
+
+    pub fn is_aggregate(&self, name: &str) -> bool {
+        self.aggregate.contains_key(&name.to_ascii_lowercase())
+    }
+
+    /// Calls the scalar function of the name, failing if there is none or the number of
+    /// arguments doesn't match.
+    pub fn call(&self, name: &str, args: &[DBValue]) -> Result<DBValue, StorageError> {
+        let function = self
+            .scalar(name)
+            .ok_or_else(|| StorageError::FunctionNotFound(String::from(name)))?;
+        if args.len() != function.arity {
+            return Err(StorageError::TypeError);
+        }
+        (function.body)(args)
+            .map_err(|message| StorageError::FunctionFailed(String::from(name), message))
+    }
+
+    /// Computes the aggregate function of the name called with `arg_count` arguments over their
+    /// values for each row of a group, failing if there is none or the number of arguments
+    /// doesn't match.
+    pub fn call_aggregate(
+        &self,
+        name: &str,
+        arg_count: usize,
+        rows: &[Vec<DBValue>],
+    ) -> Result<DBValue, StorageError> {
+        let function = self
+            .aggregate
+            .get(&name.to_ascii_lowercase())
+            .ok_or_else(|| StorageError::FunctionNotFound(String::from(name)))?;
+        if arg_count != function.arity || rows.iter().any(|args| args.len() != arg_count) {
+            return Err(StorageError::TypeError);
+        }
+        function
+            .aggregate
+            .aggregate(rows)
+            .map_err(|message| StorageError::FunctionFailed(String::from(name), message))
+    }
+}
+
+impl fmt::Debug for Functions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names: Vec<_> = self.scalar.keys().chain(self.aggregate.keys()).collect();
+        names.sort();
+        f.debug_set().entries(names).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn call_registered() {
+        let mut functions = Functions::new();
+        functions.insert_scalar(
+            "Twice",
+            ScalarFunction::new(1, |args| match args {
+                [DBValue::Integer(i)] => Ok(i * 2),
+                _ => Err(String::from("not an integer")),
+            }),
+        );
+        let twice = functions.call("twice", &[DBValue::Integer(21)]).ok();
+        assert_eq!(twice, Some(DBValue::Integer(42)));
+        assert!(matches!(
+            functions.call("TWICE", &[DBValue::Null]),
+            Err(StorageError::FunctionFailed(_, message)) if message == "not an integer"
+        ));
+        assert!(matches!(
+            functions.call("twice", &[]),
+            Err(StorageError::TypeError)
+        ));
+        assert!(functions.remove("twice"));
+        assert!(matches!(
+            functions.call("twice", &[DBValue::Integer(1)]),
+            Err(StorageError::FunctionNotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,215 @@
+//! Descriptions of the meta commands of the REPL, shown by `.help`, and of the arguments of
+//! the binary, shown by `juicydb --help`.
+
+/// How the binary is used.
+pub const USAGE: &str = "\
+Usage: juicydb [OPTIONS] [DATABASE] [SQL]...
+
+Starts the REPL on the database in the file DATABASE, created if it doesn't exist, or on a
+temporary database. Given SQL, after the database if any, or commands on a pipe, runs them
+instead and exits, printing the results as CSV.
+
+Options:
+  --serve ADDRESS  Serve the database to clients connecting to the address
+  --no-color       Print without color
+  --help           Print this help
+";
+
+/// A meta command: its name, how it is used, a line summing it up, and the details shown by
+/// `.help` with its name.
+struct Help {
+    name: &'static str,
+    usage: &'static str,
+    summary: &'static str,
+    details: &'static str,
+}
+
+/// The meta commands, in the order they are listed.
+const COMMANDS: &[Help] = &[
+    Help {
+        name: "backup",
+        usage: ".backup FILE",
+        summary: "Write a copy of the database to a file",
+        details: "Writes a copy of the database as of the last commit to the file, which can \
+                  then be opened with .open. A copy of an encrypted database is encrypted with \
+                  the same passphrase.",
+    },
+    Help {
+        name: "bind",
+        usage: ".bind VALUE, ...",
+        summary: "Set the values of ? placeholders",
+        details: "Sets the values of the ? placeholders of the statements that follow, in \
+                  order, e.g. .bind 1, 'ada'. .bind alone clears them.",
+    },
+    Help {
+        name: "checkpoint",
+        usage: ".checkpoint",
+        summary: "Flush tables stored in files to the disk",
+        details: "Flushes the pages of the tables stored in files from the caches of the \
+                  operating system to the disk. Checkpoints are also taken automatically as \
+                  pages are written.",
+    },
+    Help {
+        name: "exit",
+        usage: ".exit",
+        summary: "Exit the REPL",
+        details: "Exits, saving the history of lines entered. Ctrl-D does the same.",
+    },
+    Help {
+        name: "export",
+        usage: ".export json TABLE [FILE]",
+        summary: "Write the rows of a table as JSON",
+        details: "Writes the rows of the table to the file, or else prints them, as JSON \
+                  objects keyed by column, one per line, e.g. .export json users users.json. \
+                  Values other than nulls, numbers and booleans are written as text.",
+    },
+    Help {
+        name: "headers",
+        usage: ".headers on|off",
+        summary: "Turn the names of columns in results on or off",
+        details: "Turns the header of column names of tables, CSV and TSV on or off, e.g. for \
+                  piping CSV into other tools. JSON and lines always name the column of each \
+                  value.",
+    },
+    Help {
+        name: "help",
+        usage: ".help [COMMAND]",
+        summary: "List the meta commands, or describe one",
+        details: "Lists the meta commands, or describes the given one, e.g. .help mode.",
+    },
+    Help {
+        name: "import",
+        usage: ".import --json FILE TABLE | --sqlite FILE",
+        summary: "Insert the rows of a JSON file or a SQLite database",
+        details: "Inserts the JSON objects of the file, one per line as written by .export \
+                  json, as rows into the table, creating it with columns typed by their values \
+                  if it doesn't exist. Text is converted to the types of the columns. If any \
+                  row fails, none are inserted. With --sqlite, copies the tables of the SQLite \
+                  database in the file, with their keys, indexes and rows, if juicydb was built \
+                  with the sqlite feature.",
+    },
+    Help {
+        name: "mode",
+        usage: ".mode table|csv|tsv|json|line",
+        summary: "Set how results are printed",
+        details: "Sets how results are printed: as a table under a header (table), as \
+                  comma-separated values quoted as in RFC 4180 (csv), as tab-separated values \
+                  (tsv), as a JSON array of an object per row (json), or as a value per line \
+                  after the name of its column (line).",
+    },
+    Help {
+        name: "nullvalue",
+        usage: ".nullvalue [TEXT]",
+        summary: "Set the text nulls are printed as",
+        details: "Sets the text nulls are printed as in all modes but JSON, e.g. .nullvalue \
+                  '\\N' to tell nulls from empty text in CSV. Without a text, nulls are NULL in \
+                  tables and lines and empty in CSV and TSV again.",
+    },
+    Help {
+        name: "open",
+        usage: ".open FILE [key PASSPHRASE]",
+        summary: "Open a database file",
+        details: "Opens the database in the file instead of the current one, creating the \
+                  file if it doesn't exist yet, e.g. .open my.db. With a passphrase, e.g. \
+                  .open my.db key 'secret', the file is encrypted with a key derived from it.",
+    },
+    Help {
+        name: "print",
+        usage: ".print",
+        summary: "Print the internal state of the database",
+        details: "Prints the tables, indexes and settings of the database as they are kept in \
+                  memory, for debugging juicydb itself.",
+    },
+    Help {
+        name: "read",
+        usage: ".read FILE",
+        summary: "Execute the SQL script in a file",
+        details: "Executes the statements of the SQL script in the file, printing their \
+                  results, e.g. .read schema.sql.",
+    },
+    Help {
+        name: "save",
+        usage: ".save",
+        summary: "Save the database to its file",
+        details: "Saves the database to the file it was opened from. Statements are saved as \
+                  they are committed unless pragma synchronous is off.",
+    },
+    Help {
+        name: "schema",
+        usage: ".schema [TABLE]",
+        summary: "Show the statements creating tables and views",
+        details: "Shows the statements creating the given table or view, or all of them, \
+                  rebuilt from the catalog. Each table is followed by its indexes.",
+    },
+    Help {
+        name: "stats",
+        usage: ".stats",
+        summary: "Show the resources used by the database",
+        details: "Shows the pages read and written, how many of the reads came from the page \
+                  cache, the rows scanned and the sizes of the database file and its journal, \
+                  followed by the rows and memory of each table.",
+    },
+    Help {
+        name: "timer",
+        usage: ".timer on|off",
+        summary: "Turn timing of statements on or off",
+        details: "Turns printing the time taken by each statement on or off, along with the \
+                  rows scanned and the pages read and written meanwhile.",
+    },
+    Help {
+        name: "width",
+        usage: ".width [WIDTH ...]",
+        summary: "Set the widths of the columns of tables",
+        details: "Pins the widths of the columns of tables, in order and in characters, e.g. \
+                  .width 10 0 20. Longer values are cut short, ending in …, or without it \
+                  in columns 3 characters wide or less, and 0 leaves a column as wide as its \
+                  widest value. Without widths, all columns fit their values again.",
+    },
+];
+
+/// The list of meta commands, or the description of the one named, or an error if there is no
+/// meta command of that name.
+pub fn help(name: Option<&str>) -> Result<String, String> {
+    match name {
+        None => {
+            let width = COMMANDS.iter().map(|help| help.usage.len()).max();
+            let width = width.unwrap_or(0);
+            let lines: Vec<_> = COMMANDS
+                .iter()
+                .map(|help| format!("{:width$}  {}\n", help.usage, help.summary))
+                .collect();
+            Ok(lines.concat())
+        }
+        Some(name) => COMMANDS
+            .iter()
+            .find(|help| help.name == name)
+            .map(|help| format!("{}\n{}\n", help.usage, help.details))
+            .ok_or_else(|| format!("No meta-command '{}', enter .help for a list", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use juicydb::parser::{ParseError, Parser};
+
+    #[test]
+    fn described_commands() {
+        for command in COMMANDS {
+            // Commands taking arguments fail without them, but are recognized
+            let parsed = Parser::new(&format!(".{}", command.name)).parse_command();
+            assert_ne!(
+                parsed,
+                Err(ParseError::UnrecognizedMetaCommand),
+                "{}",
+                command.name
+            );
+            let details = help(Some(command.name));
+            assert!(details.is_ok_and(|details| details.starts_with(command.usage)));
+        }
+        let list = help(None).unwrap_or_default();
+        assert_eq!(list.lines().count(), COMMANDS.len());
+        assert!(list.starts_with(".backup FILE    "));
+        assert!(help(Some("nope")).is_err());
+    }
+}
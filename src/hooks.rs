@@ -0,0 +1,132 @@
+//! Callbacks a connection registers to learn about the changes it makes, e.g. to invalidate
+//! caches or keep an audit log:
+//!
+//! ```
+//! use std::sync::{Arc, Mutex};
+//!
+//! let mut db = juicydb::Database::new();
+//! let log = Arc::new(Mutex::new(Vec::new()));
+//! let changes = Arc::clone(&log);
+//! db.set_update_hook(move |change| {
+//!     let entry = format!("{} {} #{}", change.operation, change.table, change.rowid);
+//!     changes.lock().unwrap().push(entry);
+//! });
+//! db.execute("create table t (a integer);")?;
+//! db.execute("insert into t values (1);")?;
+//! db.execute("delete from t where a = 1;")?;
+//! assert_eq!(*log.lock().unwrap(), vec!["insert t #1", "delete t #1"]);
+//! # Ok::<(), juicydb::Error>(())
+//! ```
+//!
+//! Hooks are called synchronously while the statement runs, holding the database, so they
+//! must not use the database themselves.
+
+use crate::db::DBValue;
+use std::fmt;
+
+/// The kind of change to a row.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operation {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Insert => write!(f, "insert"),
+            Self::Update => write!(f, "update"),
+            Self::Delete => write!(f, "delete"),
+        }
+    }
+}
+
+/// A change to a row of a table, including those cascading from foreign keys.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RowChange<'a> {
+    pub table: &'a str,
+    pub operation: Operation,
+    /// The value of the primary key of the row, after an update, or else its rowid: an integer
+    /// counting up from 1 as rows are inserted, and never handed out again within the table.
+    pub rowid: &'a DBValue,
+}
+
+type Hook = Box<dyn FnMut() + Send>;
+
+type UpdateHook = Box<dyn FnMut(&RowChange<'_>) + Send>;
+
+/// The hooks of a session; see [`crate::storage_manager::Session`].
+#[derive(Default)]
+pub struct Hooks {
+    commit: Option<Hook>,
+    rollback: Option<Hook>,
+    update: Option<UpdateHook>,
+}
+
+impl Hooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the hook called once changes are committed: when a transaction commits, or a
+    /// statement changing the database outside of a transaction succeeds.
+    pub fn set_commit(&mut self, hook: impl FnMut() + Send + 'static) {
+        self.commit = Some(Box::new(hook));
+    }
+
+    /// Sets the hook called when a transaction is rolled back.
+    pub fn set_rollback(&mut self, hook: impl FnMut() + Send + 'static) {
+        self.rollback = Some(Box::new(hook));
+    }
+
+    /// Sets the hook called for each row inserted, updated or deleted.
+    pub fn set_update(&mut self, hook: impl FnMut(&RowChange<'_>) + Send + 'static) {
+        self.update = Some(Box::new(hook));
+    }
+
+    /// Removes all hooks.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn committed(&mut self) {
+        if let Some(hook) = &mut self.commit {
+            hook();
+        }
+    }
+
+    pub fn rolled_back(&mut self) {
+        if let Some(hook) = &mut self.rollback {
+            hook();
+        }
+    }
+
+    /// Whether an update hook is set, and so rowids of changed rows are wanted.
+    pub fn has_update(&self) -> bool {
+        self.update.is_some()
+    }
+
+    /// Calls the update hook for each of the rows of the table with the given rowids.
+    pub fn rows_changed(&mut self, table: &str, operation: Operation, rowids: &[DBValue]) {
+        if let Some(hook) = &mut self.update {
+            for rowid in rowids {
+                hook(&RowChange {
+                    table,
+                    operation,
+                    rowid,
+                });
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("commit", &self.commit.is_some())
+            .field("rollback", &self.rollback.is_some())
+            .field("update", &self.update.is_some())
+            .finish()
+    }
+}
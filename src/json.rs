@@ -0,0 +1,218 @@
+//! Rows as JSON objects, one per line ("newline-delimited JSON"), for exporting tables and
+//! importing them back; see [`StorageManager::export_json`] and
+//! [`StorageManager::import_json`].
+//!
+//! Values map to JSON as closely as they can: null, integers, reals and booleans as themselves,
+//! and everything else as the text the REPL shows it as, e.g. `{"id":1,"at":"2024-01-02"}`.
+//! Reading an object back gives integers for numbers without a fraction or an exponent, reals
+//! for the other numbers, and text for strings, which inserting into a column coerces to the
+//! type of the column, turning e.g. text back into dates. Only objects of such plain values are
+//! read: arrays and objects as values are rejected.
+//!
+//! [`StorageManager::export_json`]: crate::storage_manager::StorageManager::export_json
+//! [`StorageManager::import_json`]: crate::storage_manager::StorageManager::import_json
+
+use crate::db::DBValue;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// The value as JSON. Reals that aren't finite have no JSON number, and are null.
+pub fn value(value: &DBValue) -> String {
+    match value {
+        DBValue::Null => String::from("null"),
+        DBValue::Real(r) if !r.is_finite() => String::from("null"),
+        DBValue::Integer(_) | DBValue::Real(_) | DBValue::Decimal(_) | DBValue::Boolean(_) => {
+            value.to_string()
+        }
+        value => string(&value.to_string()),
+    }
+}
+
+/// The text as a JSON string, quoted and escaped.
+pub fn string(text: &str) -> String {
+    let mut string = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => string.push_str("\\\""),
+            '\\' => string.push_str("\\\\"),
+            '\n' => string.push_str("\\n"),
+            '\r' => string.push_str("\\r"),
+            '\t' => string.push_str("\\t"),
+            c if c.is_control() => string.push_str(&format!("\\u{:04x}", c as u32)),
+            c => string.push(c),
+        }
+    }
+    string.push('"');
+    string
+}
+
+/// The row as a JSON object on one line, keyed by the names of the columns.
+pub fn object(columns: &[String], row: &[DBValue]) -> String {
+    let members: Vec<_> = columns
+        .iter()
+        .zip(row)
+        .map(|(column, value)| format!("{}:{}", string(column), self::value(value)))
+        .collect();
+    format!("{{{}}}", members.join(","))
+}
+
+/// The members of a JSON object of plain values, in order, or a message saying what is wrong
+/// with it.
+pub fn parse_object(text: &str) -> Result<Vec<(String, DBValue)>, String> {
+    let mut chars = text.trim().chars().peekable();
+    expect(&mut chars, '{')?;
+    let mut members = Vec::new();
+    skip_whitespace(&mut chars);
+    if chars.peek() == Some(&'}') {
+        chars.next();
+    } else {
+        loop {
+            skip_whitespace(&mut chars);
+            expect(&mut chars, '"')?;
+            let key = parse_string(&mut chars)?;
+            skip_whitespace(&mut chars);
+            expect(&mut chars, ':')?;
+            skip_whitespace(&mut chars);
+            members.push((key, parse_value(&mut chars)?));
+            skip_whitespace(&mut chars);
+            match chars.next() {
+                Some(',') => continue,
+                Some('}') => break,
+                _ => return Err(String::from("Expected ',' or '}'")),
+            }
+        }
+    }
+    match chars.next() {
+        None => Ok(members),
+        Some(_) => Err(String::from("Unexpected text after the object")),
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+        chars.next();
+    }
+}
+
+fn expect(chars: &mut Peekable<Chars>, expected: char) -> Result<(), String> {
+    match chars.next() {
+        Some(c) if c == expected => Ok(()),
+        _ => Err(format!("Expected '{}'", expected)),
+    }
+}
+
+fn parse_value(chars: &mut Peekable<Chars>) -> Result<DBValue, String> {
+    match chars.peek() {
+        Some('"') => {
+            chars.next();
+            parse_string(chars).map(DBValue::Text)
+        }
+        Some('-') | Some('0'..='9') => parse_number(chars),
+        Some('[') | Some('{') => Err(String::from("Arrays and objects can't be values")),
+        _ => {
+            let mut word = String::new();
+            while chars.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+                word.extend(chars.next());
+            }
+            match word.as_str() {
+                "null" => Ok(DBValue::Null),
+                "true" => Ok(DBValue::Boolean(true)),
+                "false" => Ok(DBValue::Boolean(false)),
+                _ => Err(String::from("Expected a value")),
+            }
+        }
+    }
+}
+
+/// Parses a number as an integer if it has neither a fraction nor an exponent and fits, or else
+/// as a real.
+fn parse_number(chars: &mut Peekable<Chars>) -> Result<DBValue, String> {
+    let mut number = String::new();
+    while chars
+        .peek()
+        .is_some_and(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+    {
+        number.extend(chars.next());
+    }
+    if let Ok(integer) = number.parse() {
+        return Ok(DBValue::Integer(integer));
+    }
+    match number.parse() {
+        Ok(real) => Ok(DBValue::Real(real)),
+        Err(_) => Err(format!("Invalid number '{}'", number)),
+    }
+}
+
+/// Parses the rest of a string after its opening quote.
+fn parse_string(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    let mut string = String::new();
+    loop {
+        match chars.next().ok_or("Unterminated string")? {
+            '"' => return Ok(string),
+            '\\' => match chars.next().ok_or("Unterminated string")? {
+                'n' => string.push('\n'),
+                'r' => string.push('\r'),
+                't' => string.push('\t'),
+                'b' => string.push('\u{8}'),
+                'f' => string.push('\u{c}'),
+                'u' => {
+                    let mut code = parse_hex(chars)?;
+                    // Characters outside the basic plane are escaped as a surrogate pair
+                    if (0xd800..0xdc00).contains(&code) {
+                        expect(chars, '\\')?;
+                        expect(chars, 'u')?;
+                        let low = parse_hex(chars)?;
+                        if !(0xdc00..0xe000).contains(&low) {
+                            return Err(String::from("Invalid escape"));
+                        }
+                        code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                    }
+                    string.push(char::from_u32(code).ok_or("Invalid escape")?);
+                }
+                c @ ('"' | '\\' | '/') => string.push(c),
+                _ => return Err(String::from("Invalid escape")),
+            },
+            c => string.push(c),
+        }
+    }
+}
+
+fn parse_hex(chars: &mut Peekable<Chars>) -> Result<u32, String> {
+    let digits: String = chars.take(4).collect();
+    match digits.len() {
+        4 => u32::from_str_radix(&digits, 16).map_err(|_| String::from("Invalid escape")),
+        _ => Err(String::from("Invalid escape")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn objects() {
+        let columns = vec![String::from("id"), String::from("name"), String::from("x")];
+        let row = vec![
+            DBValue::Integer(1),
+            DBValue::Text(String::from("a \"b\"\n")),
+            DBValue::Real(1.0),
+        ];
+        let text = object(&columns, &row);
+        assert_eq!(text, r#"{"id":1,"name":"a \"b\"\n","x":1.0}"#);
+        let members: Vec<_> = columns.into_iter().zip(row).collect();
+        assert_eq!(parse_object(&text), Ok(members));
+        let parsed = parse_object(r#" { "a" : null , "b": true, "c": -2.5e3, "d": "é😀" } "#);
+        let expected = vec![
+            (String::from("a"), DBValue::Null),
+            (String::from("b"), DBValue::Boolean(true)),
+            (String::from("c"), DBValue::Real(-2500.0)),
+            (String::from("d"), DBValue::Text(String::from("é😀"))),
+        ];
+        assert_eq!(parsed, Ok(expected));
+        assert_eq!(parse_object("{}"), Ok(vec![]));
+        assert!(parse_object(r#"{"a": [1]}"#).is_err());
+        assert!(parse_object(r#"{"a": 1"#).is_err());
+        assert!(parse_object(r#"{"a": 1} x"#).is_err());
+        assert!(parse_object(r#"{"a": "b}"#).is_err());
+    }
+}
@@ -0,0 +1,191 @@
+/// Byte range of a token in the input.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Kind of a lexed token. Keywords are lexed as words; whether a word is a keyword depends on
+/// where it appears in a statement.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenKind {
+    Word(String),
+    Integer(String),
+    Real(String),
+    Text(String),
+    UnterminatedText,
+    Symbol(&'static str),
+    Unknown(char),
+}
+
+impl TokenKind {
+    /// Whether the token is the given keyword (case-insensitive) or punctuation.
+    pub fn matches(&self, text: &str) -> bool {
+        match self {
+            Self::Word(word) => word.eq_ignore_ascii_case(text),
+            Self::Symbol(symbol) => *symbol == text,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+/// Punctuation recognized by the lexer; longer symbols come before their prefixes.
+const SYMBOLS: [&str; 17] = [
+    "<>", "!=", "<=", ">=", "(", ")", ",", ";", ".", "=", "<", ">", "-", "?", "+", "*", "/",
+];
+
+/// Splits the input into tokens, skipping whitespace along with any comments;
+/// `-- line comments` and `/* block comments */`. An unterminated block comment runs to the end
+/// of the input. Lexing never fails; unrecognized characters and unterminated text are kept as
+/// tokens and reported by the parser.
+pub fn tokenize(input: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    while let Some(c) = input[start..].chars().next() {
+        let rest = &input[start..];
+        let (kind, len) = if c.is_whitespace() {
+            start += c.len_utf8();
+            continue;
+        } else if rest.starts_with("--") {
+            start += rest.find('\n').unwrap_or(rest.len());
+            continue;
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            start += comment.find("*/").map_or(rest.len(), |i| i + 4);
+            continue;
+        } else if c.is_ascii_alphabetic() {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (TokenKind::Word(String::from(&rest[..len])), len)
+        } else if c.is_ascii_digit() || (c == '.' && starts_with_digit(&rest[1..])) {
+            lex_number(rest)
+        } else if c == '\'' {
+            match rest[1..].find('\'') {
+                Some(i) => (TokenKind::Text(String::from(&rest[1..i + 1])), i + 2),
+                None => (TokenKind::UnterminatedText, rest.len()),
+            }
+        } else if let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(*s)) {
+            (TokenKind::Symbol(symbol), symbol.len())
+        } else {
+            (TokenKind::Unknown(c), c.len_utf8())
+        };
+        tokens.push(Token {
+            kind,
+            span: Span {
+                start,
+                end: start + len,
+            },
+        });
+        start += len;
+    }
+    tokens
+}
+
+fn starts_with_digit(input: &str) -> bool {
+    input.chars().next().is_some_and(|c| c.is_ascii_digit())
+}
+
+fn digits(input: &str) -> usize {
+    input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len())
+}
+
+/// Lexes an integer, or a real number if it has a fractional part or an exponent, e.g. `1.5`,
+/// `.5` or `15e-1`.
+fn lex_number(input: &str) -> (TokenKind, usize) {
+    let mut len = digits(input);
+    let mut real = false;
+    if input[len..].starts_with('.') {
+        len += 1 + digits(&input[len + 1..]);
+        real = true;
+    }
+    if input[len..].starts_with(['e', 'E']) {
+        let sign = usize::from(input[len + 1..].starts_with(['+', '-']));
+        if starts_with_digit(&input[len + 1 + sign..]) {
+            len += 1 + sign + digits(&input[len + 1 + sign..]);
+            real = true;
+        }
+    }
+    let number = String::from(&input[..len]);
+    if real {
+        (TokenKind::Real(number), len)
+    } else {
+        (TokenKind::Integer(number), len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(input: &str) -> Vec<TokenKind> {
+        tokenize(input).into_iter().map(|t| t.kind).collect()
+    }
+
+    #[test]
+    fn tokenize_statement() {
+        let tokens = tokenize("select (a) from t where a <= -1;");
+        assert_eq!(tokens[0].span, Span { start: 0, end: 6 });
+        assert_eq!(tokens[8].span, Span { start: 26, end: 28 });
+        assert_eq!(
+            tokens.into_iter().map(|t| t.kind).collect::<Vec<_>>(),
+            vec![
+                TokenKind::Word(String::from("select")),
+                TokenKind::Symbol("("),
+                TokenKind::Word(String::from("a")),
+                TokenKind::Symbol(")"),
+                TokenKind::Word(String::from("from")),
+                TokenKind::Word(String::from("t")),
+                TokenKind::Word(String::from("where")),
+                TokenKind::Word(String::from("a")),
+                TokenKind::Symbol("<="),
+                TokenKind::Symbol("-"),
+                TokenKind::Integer(String::from("1")),
+                TokenKind::Symbol(";"),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_numbers() {
+        assert_eq!(
+            kinds("12 1.5 .5 2. 1e3 1.5E-2 3e x.y"),
+            vec![
+                TokenKind::Integer(String::from("12")),
+                TokenKind::Real(String::from("1.5")),
+                TokenKind::Real(String::from(".5")),
+                TokenKind::Real(String::from("2.")),
+                TokenKind::Real(String::from("1e3")),
+                TokenKind::Real(String::from("1.5E-2")),
+                TokenKind::Integer(String::from("3")),
+                TokenKind::Word(String::from("e")),
+                TokenKind::Word(String::from("x")),
+                TokenKind::Symbol("."),
+                TokenKind::Word(String::from("y")),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_words_and_text() {
+        assert_eq!(
+            kinds("selectx 'a b' 'open"),
+            vec![
+                TokenKind::Word(String::from("selectx")),
+                TokenKind::Text(String::from("a b")),
+                TokenKind::UnterminatedText,
+            ]
+        );
+        assert_eq!(
+            kinds("_ & -- comment"),
+            vec![TokenKind::Unknown('_'), TokenKind::Unknown('&')]
+        );
+    }
+}
@@ -1,5 +1,49 @@
-pub mod parser;
-pub mod db;
+// Lets the code generated by `#[derive(FromRow)]` refer to the crate by name within it too
+extern crate self as juicydb;
+
+#[cfg(feature = "arrow")]
+pub mod arrow;
 pub mod btree;
+pub mod cipher;
+pub mod connection;
+pub mod copy;
+pub mod database;
+pub mod datetime;
+pub mod db;
+pub mod decimal;
+pub mod executor;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod from_row;
+pub mod functions;
+pub mod hooks;
+pub mod json;
+pub mod lexer;
+pub mod lock_manager;
+pub mod optimizer;
+pub mod pager;
+pub mod parallel;
+pub mod params;
+pub mod parser;
+pub mod plan_cache;
+pub mod planner;
+pub mod progress;
 pub mod query_processor;
+pub mod record;
+#[cfg(feature = "serde")]
+pub mod serialize;
+pub mod server;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
 pub mod storage_manager;
+pub mod transaction;
+pub mod uuid;
+pub mod virtual_table;
+
+pub use connection::{Connection, Error};
+pub use database::Database;
+pub use db::{DBValue, ResultSet};
+pub use from_row::{FromRow, FromValue};
+#[cfg(feature = "derive")]
+pub use juicydb_derive::FromRow;
+pub use params::ToValue;
@@ -0,0 +1,335 @@
+//! Locks on the tables of a database and on the rows within them, held by transactions. Reads
+//! take shared locks and writes exclusive ones; a transaction keeps its locks until it commits
+//! or rolls back, so that no other transaction sees or overwrites its changes before then. A
+//! lock that conflicts with the lock of another transaction isn't granted, and the statement
+//! fails instead of waiting for it. Connections retry such statements for a while instead; see
+//! [`crate::storage_manager::Session::busy_timeout`].
+//!
+//! Rows are locked by ranges of their keys, encoded by [`crate::record::encode_key`], a single
+//! row being a range of one key. Locking rows first takes an intention lock on their table, which
+//! announces the row locks: intention locks of different transactions are compatible, so that
+//! transactions writing different rows of a table can proceed side by side, while they conflict
+//! with shared and exclusive locks on the whole table.
+
+use crate::btree::Key;
+use crate::storage_manager::StorageError;
+use crate::transaction::TransactionId;
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::sync::Mutex;
+
+/// How a table or rows are locked. Rows are only locked shared or exclusively; the intention
+/// modes are taken on the table of the rows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockMode {
+    /// Announces shared locks on rows of the table.
+    IntentionShared,
+    /// Announces exclusive locks on rows of the table.
+    IntentionExclusive,
+    Shared,
+    Exclusive,
+}
+
+impl LockMode {
+    fn compatible(self, other: LockMode) -> bool {
+        use LockMode::*;
+        match self {
+            IntentionShared => other != Exclusive,
+            IntentionExclusive => matches!(other, IntentionShared | IntentionExclusive),
+            Shared => matches!(other, IntentionShared | Shared),
+            Exclusive => false,
+        }
+    }
+
+    /// The weakest mode granting what both modes grant. Shared locks on a table along with
+    /// exclusive locks on some of its rows are taken as an exclusive lock on the table.
+    pub fn join(self, other: LockMode) -> LockMode {
+        use LockMode::*;
+        match (self.min(other), self.max(other)) {
+            (IntentionExclusive, Shared) => Exclusive,
+            (_, max) => max,
+        }
+    }
+
+    /// Whether locking a table in this mode covers locking any of its rows in the given mode.
+    fn covers_rows(self, mode: LockMode) -> bool {
+        self == LockMode::Exclusive || (self == LockMode::Shared && mode == LockMode::Shared)
+    }
+
+    fn intention(self) -> LockMode {
+        match self {
+            LockMode::Shared | LockMode::IntentionShared => LockMode::IntentionShared,
+            _ => LockMode::IntentionExclusive,
+        }
+    }
+}
+
+/// A lock on the rows of a table with keys in a range.
+#[derive(Debug)]
+struct RowLock {
+    keys: RangeInclusive<Key>,
+    owner: TransactionId,
+    mode: LockMode,
+}
+
+impl RowLock {
+    fn overlaps(&self, keys: &RangeInclusive<Key>) -> bool {
+        self.keys.start() <= keys.end() && keys.start() <= self.keys.end()
+    }
+}
+
+/// The locks on a table: the mode each transaction holds the table in, and the row locks.
+#[derive(Debug, Default)]
+struct TableLock {
+    holders: HashMap<TransactionId, LockMode>,
+    rows: Vec<RowLock>,
+}
+
+impl TableLock {
+    /// Whether the table can be locked in the mode by the transaction, i.e. no other
+    /// transaction holds a lock on the table it conflicts with.
+    fn grantable(&self, owner: TransactionId, mode: LockMode) -> bool {
+        self.holders
+            .iter()
+            .all(|(holder, held)| *holder == owner || mode.compatible(*held))
+    }
+
+    fn is_free(&self) -> bool {
+        self.holders.is_empty() && self.rows.is_empty()
+    }
+}
+
+/// Grants and releases the table and row locks of the transactions of a database. The locks are
+/// kept behind a mutex, so that transactions running on different threads can share the manager.
+#[derive(Debug, Default)]
+pub struct LockManager {
+    tables: Mutex<HashMap<String, TableLock>>,
+}
+
+impl LockManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Locks the table for the transaction, unless another transaction holds a conflicting
+    /// lock on it. Locks already held are kept, and strengthened if need be, e.g. a shared lock
+    /// to an exclusive one.
+    pub fn lock(
+        &self,
+        owner: TransactionId,
+        table: &str,
+        mode: LockMode,
+    ) -> Result<(), StorageError> {
+        let mut tables = self.tables.lock().unwrap();
+        let lock = tables.entry(String::from(table)).or_default();
+        Self::lock_table(lock, owner, table, mode)
+    }
+
+    fn lock_table(
+        lock: &mut TableLock,
+        owner: TransactionId,
+        table: &str,
+        mode: LockMode,
+    ) -> Result<(), StorageError> {
+        let held = lock.holders.get(&owner).copied();
+        let mode = held.map_or(mode, |held| held.join(mode));
+        if held == Some(mode) {
+            return Ok(());
+        }
+        // Row locks of other transactions come with their intention locks on the table
+        if !lock.grantable(owner, mode) {
+            return Err(StorageError::TableLocked(String::from(table)));
+        }
+        lock.holders.insert(owner, mode);
+        Ok(())
+    }
+
+    /// Locks each of the tables in turn; see [`LockManager::lock`]. The locks granted before a
+    /// conflict are kept, to be released along with the other locks of the transaction.
+    pub fn lock_all(
+        &self,
+        owner: TransactionId,
+        locks: &[(String, LockMode)],
+    ) -> Result<(), StorageError> {
+        for (table, mode) in locks {
+            self.lock(owner, table, *mode)?;
+        }
+        Ok(())
+    }
+
+    /// Locks the rows of the table with keys in the range, shared or exclusively, after taking
+    /// the matching intention lock on the table. Nothing more is locked if the lock the
+    /// transaction holds on the whole table already covers the rows.
+    pub fn lock_rows(
+        &self,
+        owner: TransactionId,
+        table: &str,
+        keys: RangeInclusive<Key>,
+        mode: LockMode,
+    ) -> Result<(), StorageError> {
+        let mut tables = self.tables.lock().unwrap();
+        let lock = tables.entry(String::from(table)).or_default();
+        if lock
+            .holders
+            .get(&owner)
+            .is_some_and(|held| held.covers_rows(mode))
+        {
+            return Ok(());
+        }
+        Self::lock_table(lock, owner, table, mode.intention())?;
+        let mut overlapping = lock.rows.iter().filter(|row| row.overlaps(&keys));
+        if overlapping
+            .clone()
+            .any(|row| row.owner != owner && !(mode == LockMode::Shared && row.mode == mode))
+        {
+            return Err(StorageError::RowLocked(String::from(table)));
+        }
+        if !overlapping.any(|row| row.owner == owner && row.mode >= mode && row.keys == keys) {
+            lock.rows.push(RowLock { keys, owner, mode });
+        }
+        Ok(())
+    }
+
+    /// The lock the transaction holds on the table, if any.
+    pub fn mode(&self, owner: TransactionId, table: &str) -> Option<LockMode> {
+        let tables = self.tables.lock().unwrap();
+        tables
+            .get(table)
+            .and_then(|lock| lock.holders.get(&owner).copied())
+    }
+
+    /// The key ranges of the rows of the table the transaction holds exclusive locks on.
+    pub fn locked_rows(&self, owner: TransactionId, table: &str) -> Vec<RangeInclusive<Key>> {
+        let tables = self.tables.lock().unwrap();
+        let rows = tables.get(table).into_iter().flat_map(|lock| &lock.rows);
+        rows.filter(|row| row.owner == owner && row.mode == LockMode::Exclusive)
+            .map(|row| row.keys.clone())
+            .collect()
+    }
+
+    /// Releases the shared locks of the transaction on tables and rows, keeping the locks of its
+    /// writes, as a statement of a transaction reading committed changes only ends.
+    pub fn release_shared(&self, owner: TransactionId) {
+        let mut tables = self.tables.lock().unwrap();
+        for lock in tables.values_mut() {
+            if let Some(LockMode::Shared | LockMode::IntentionShared) = lock.holders.get(&owner) {
+                lock.holders.remove(&owner);
+            }
+            lock.rows
+                .retain(|row| row.owner != owner || row.mode == LockMode::Exclusive);
+        }
+        tables.retain(|_, lock| !lock.is_free());
+    }
+
+    /// Releases all locks of the transaction, as it commits or rolls back.
+    pub fn release(&self, owner: TransactionId) {
+        let mut tables = self.tables.lock().unwrap();
+        for lock in tables.values_mut() {
+            lock.holders.remove(&owner);
+            lock.rows.retain(|row| row.owner != owner);
+        }
+        tables.retain(|_, lock| !lock.is_free());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_and_exclusive_locks() {
+        let locks = LockManager::new();
+        assert!(locks.lock(1, "t", LockMode::Shared).is_ok());
+        assert!(locks.lock(2, "t", LockMode::Shared).is_ok());
+        let upgrade = locks.lock(1, "t", LockMode::Exclusive);
+        assert!(matches!(upgrade, Err(StorageError::TableLocked(table)) if table == "t"));
+        assert!(locks.lock(2, "u", LockMode::Exclusive).is_ok());
+        assert!(locks.lock(1, "u", LockMode::Shared).is_err());
+        locks.release(2);
+        assert_eq!(locks.mode(2, "t"), None);
+        assert!(locks.lock(1, "t", LockMode::Exclusive).is_ok());
+        assert_eq!(locks.mode(1, "t"), Some(LockMode::Exclusive));
+        // Holding an exclusive lock covers reads as well
+        assert!(locks.lock(1, "t", LockMode::Shared).is_ok());
+        assert_eq!(locks.mode(1, "t"), Some(LockMode::Exclusive));
+        assert!(locks.lock(2, "t", LockMode::Shared).is_err());
+        let all = [
+            (String::from("u"), LockMode::Shared),
+            (String::from("t"), LockMode::Shared),
+        ];
+        assert!(locks.lock_all(2, &all).is_err());
+        assert_eq!(locks.mode(2, "u"), Some(LockMode::Shared));
+        locks.release(1);
+        assert!(locks.lock_all(2, &all).is_ok());
+        locks.release(2);
+        assert!(locks.tables.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn row_locks() {
+        let locks = LockManager::new();
+        let key = |n: u8| vec![n];
+        assert!(locks
+            .lock_rows(1, "t", key(1)..=key(1), LockMode::Exclusive)
+            .is_ok());
+        assert!(locks
+            .lock_rows(2, "t", key(2)..=key(4), LockMode::Exclusive)
+            .is_ok());
+        assert_eq!(locks.mode(2, "t"), Some(LockMode::IntentionExclusive));
+        let overlapping = locks.lock_rows(1, "t", key(4)..=key(4), LockMode::Shared);
+        assert!(matches!(overlapping, Err(StorageError::RowLocked(table)) if table == "t"));
+        assert!(locks
+            .lock_rows(1, "t", key(5)..=key(9), LockMode::Shared)
+            .is_ok());
+        assert!(locks
+            .lock_rows(2, "t", key(9)..=key(9), LockMode::Shared)
+            .is_ok());
+        // Locking the whole table conflicts with the row locks of other transactions
+        assert!(locks.lock(1, "t", LockMode::Shared).is_err());
+        assert_eq!(locks.locked_rows(2, "t"), vec![key(2)..=key(4)]);
+        locks.release(2);
+        assert!(locks.lock(1, "t", LockMode::Exclusive).is_ok());
+        // An exclusive lock on the table covers its rows
+        assert!(locks
+            .lock_rows(1, "t", key(0)..=key(0), LockMode::Exclusive)
+            .is_ok());
+        assert_eq!(locks.locked_rows(1, "t"), vec![key(1)..=key(1)]);
+        assert!(locks
+            .lock_rows(2, "t", key(7)..=key(7), LockMode::Shared)
+            .is_err());
+        locks.release(1);
+        assert!(locks.tables.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn isolation_anomalies() {
+        let key = |n: u8| vec![n];
+        let row = |n: u8| key(n)..=key(n);
+        for read_committed in &[true, false] {
+            // Reads end with their statement under read committed
+            let end_read = |locks: &LockManager, owner| {
+                if *read_committed {
+                    locks.release_shared(owner);
+                }
+            };
+            // Non-repeatable read: 2 changes a row between two reads of 1
+            let locks = LockManager::new();
+            assert!(locks.lock(1, "t", LockMode::Shared).is_ok());
+            end_read(&locks, 1);
+            let write = locks.lock_rows(2, "t", row(1), LockMode::Exclusive);
+            assert_eq!(write.is_ok(), *read_committed);
+            locks.release(2);
+            assert!(locks.lock(1, "t", LockMode::Shared).is_ok());
+
+            // Write skew: 1 and 2 both read the table, then each writes a different row
+            let locks = LockManager::new();
+            for owner in 1..=2 {
+                assert!(locks.lock(owner, "t", LockMode::Shared).is_ok());
+                end_read(&locks, owner);
+            }
+            let first = locks.lock_rows(1, "t", row(1), LockMode::Exclusive);
+            let second = locks.lock_rows(2, "t", row(2), LockMode::Exclusive);
+            assert_eq!(first.is_ok() && second.is_ok(), *read_committed);
+        }
+    }
+}
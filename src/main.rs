@@ -1,59 +1,465 @@
-use juicydb::db::*;
+mod help;
+mod output;
+
+use juicydb::connection::Cursor;
+use juicydb::db::ResultColumn;
 use juicydb::parser::*;
-use juicydb::storage_manager::*;
-use std::io::{self, Write};
+use juicydb::storage_manager::{Stats, IN_MEMORY};
+use juicydb::{DBValue, Database, ResultSet};
+use output::{caret, paint, Format, GREEN, RED};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Instant;
 
-fn main() {
-    println!("Welcome to juicydb");
+/// Name of the file in the home directory keeping the lines entered, across sessions.
+const HISTORY_FILE: &str = ".juicydb_history";
 
-    let mut storage = StorageManager::new();
+/// Prints the rows returned by a statement, if any: those of a query, or e.g. of 'vacuum'.
+fn print_result_set(result: &ResultSet, format: &Format) {
+    if result.columns.is_empty() {
+        return;
+    }
+    print!("{}", format.render(result));
+}
 
-    loop {
-        print!("> ");
-        io::stdout().flush().expect("Failed to flush prompt");
-
-        let mut input = String::new();
-        io::stdin()
-            .read_line(&mut input)
-            .expect("Failed to read line");
-
-        if input.is_empty() {
-            break;
-        }
-
-        let mut parser = Parser::new(&input);
-        let stmt = parser.parse_command();
-
-        match stmt {
-            Ok(stmt) => match stmt {
-                Command::Statement(stmt) => {
-                    let process = match stmt {
-                        Statement::CreateTable { table, columns } => {
-                            storage.create_table(table, Schema::from(columns))
-                        }
-                        Statement::InsertInto { table, values } => {
-                            storage.insert_into(table, values)
-                        }
-                        query => storage.query(query).and_then(|rows| {
-                            for row in rows {
-                                for col in row {
-                                    print!("{}, ", col);
-                                }
-                                println!();
-                            }
-                            Ok(())
-                        }),
-                    };
-                    if let Err(err) = process {
-                        println!("SQL error: {}", err);
+/// Prints the rows of a query as they are produced, but in table mode, which sizes the columns
+/// by all of the rows first.
+fn print_rows(rows: Cursor, format: &Format) -> Result<(), String> {
+    let columns = rows.columns().to_vec();
+    format.write_rows(&columns, rows, &mut io::stdout().lock())
+}
+
+/// Path of the history file, if there is a home directory to keep it in.
+fn history_path() -> Option<PathBuf> {
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?;
+    Some(PathBuf::from(home).join(HISTORY_FILE))
+}
+
+/// Opens the database in the file, encrypted with the key if given, creating the file if it
+/// doesn't exist yet, so that a mistyped path shows up as a new file right away.
+fn open(path: &str, key: Option<&str>) -> Result<Database, String> {
+    let exists = path == IN_MEMORY || Path::new(path).exists();
+    let mut db = match key {
+        Some(key) => Database::open_encrypted(path, key),
+        None => Database::open(path),
+    }
+    .map_err(|err| err.to_string())?;
+    if !exists {
+        db.save()
+            .map_err(|err| format!("Can't create '{}': {}", path, err))?;
+    }
+    Ok(db)
+}
+
+/// Measures a statement from its start: the wall-clock time, the rows scanned and the pages
+/// read and written.
+struct Timer {
+    start: Instant,
+    rows: u64,
+    pages: (u64, u64),
+}
+
+impl Timer {
+    fn start(db: &Database) -> Self {
+        Self {
+            start: Instant::now(),
+            rows: db.rows_scanned(),
+            pages: db.page_counts(),
+        }
+    }
+
+    /// The measures from the start until now, with the rows and pages counted for the database,
+    /// which a `.open` may have replaced meanwhile.
+    fn report(&self, db: &Database) -> String {
+        let elapsed = self.start.elapsed().as_secs_f64() * 1000.0;
+        let (read, written) = db.page_counts();
+        format!(
+            "Run time: {:.3} ms, rows scanned: {}, pages read: {}, written: {}",
+            elapsed,
+            db.rows_scanned().saturating_sub(self.rows),
+            read.saturating_sub(self.pages.0),
+            written.saturating_sub(self.pages.1)
+        )
+    }
+}
+
+/// Prints the resources used by the database, followed by the sizes of its tables as a result
+/// in the given format.
+fn print_stats(stats: &Stats, format: &Format) {
+    let mapped = match stats.pages_mapped {
+        0 => String::new(),
+        mapped => format!(" ({} from a memory mapping)", mapped),
+    };
+    println!("Pages read:    {}{}", stats.pages_read, mapped);
+    println!("Pages written: {}", stats.pages_written);
+    println!("Rows scanned:  {}", stats.rows_scanned);
+    if let (Some(file), Some(journal)) = (stats.file_size, stats.journal_size) {
+        println!("File size:     {} bytes, journal {} bytes", file, journal);
+    }
+    let column = |name: &str| ResultColumn {
+        name: String::from(name),
+        db_type: None,
+    };
+    let integer = |n: usize| DBValue::Integer(n as i64);
+    let tables = ResultSet {
+        columns: vec![column("table"), column("rows"), column("memory")],
+        rows: stats
+            .tables
+            .iter()
+            .map(|(name, rows, memory)| {
+                let memory = memory.map_or(DBValue::Null, integer);
+                vec![DBValue::Text(name.clone()), integer(*rows), memory]
+            })
+            .collect(),
+    };
+    print_result_set(&tables, format);
+}
+
+/// The database of a session and the settings made with meta commands, kept between commands.
+struct Session {
+    db: Database,
+    bindings: Vec<DBValue>,
+    format: Format,
+    /// Whether the time taken by each statement is printed after its results.
+    timer: bool,
+}
+
+impl Session {
+    fn new(db: Database) -> Self {
+        Self {
+            db,
+            bindings: Vec::new(),
+            format: Format::new(),
+            timer: false,
+        }
+    }
+
+    /// Runs the commands of complete input in turn, printing their results. Returns whether to
+    /// go on with the next input, or the error the first command that failed with.
+    fn run(&mut self, input: &str) -> Result<bool, String> {
+        let mut parser = Parser::new(input);
+        while !parser.at_end() {
+            let start = parser.offset();
+            let command = parser.parse_command().map_err(|err| {
+                format!("Parse error: {}\n{}", err, caret(input, parser.offset()))
+            })?;
+            if !self.run_command(&input[start..parser.offset()], command)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Runs a command given along with its input and prints its results. Returns whether to go
+    /// on with the next command, or the error the command failed with.
+    fn run_command(&mut self, input: &str, command: Command) -> Result<bool, String> {
+        let timed = matches!(
+            command,
+            Command::Statement(_) | Command::MetaCommand(MetaCommand::Read(_))
+        );
+        let timer = Timer::start(&self.db);
+        let Session {
+            db,
+            bindings,
+            format,
+            ..
+        } = self;
+        match command {
+            Command::Statement(stmt) => {
+                let prepared = stmt.parameter_count() > 0;
+                let connection = db.connection();
+                match stmt {
+                    Statement::Select { .. } => {
+                        let rows = if prepared {
+                            connection
+                                .prepare(input)
+                                .and_then(|stmt| connection.query_prepared(&stmt, bindings))
+                        } else {
+                            connection.query(input)
+                        };
+                        print_rows(rows.map_err(|err| err.to_string())?, format)?;
+                    }
+                    _ => {
+                        let result = if prepared {
+                            db.prepare(input)
+                                .and_then(|stmt| db.execute_prepared(&stmt, bindings))
+                        } else {
+                            db.execute(input)
+                        };
+                        print_result_set(&result.map_err(|err| err.to_string())?, format);
+                    }
+                }
+            }
+            Command::MetaCommand(cmd) => match cmd {
+                MetaCommand::Exit => return Ok(false),
+                MetaCommand::Print => println!("{:#?}", db),
+                MetaCommand::Bind(values) => *bindings = values,
+                MetaCommand::Checkpoint => db.checkpoint().map_err(|err| err.to_string())?,
+                MetaCommand::Open { path, key } => *db = open(&path, key.as_deref())?,
+                MetaCommand::Save => db.save().map_err(|err| err.to_string())?,
+                MetaCommand::Read(path) => {
+                    let script = fs::read_to_string(&path)
+                        .map_err(|err| format!("Can't read '{}': {}", path, err))?;
+                    let results = db
+                        .execute_batch(&script, false)
+                        .map_err(|err| err.to_string())?;
+                    results
+                        .iter()
+                        .for_each(|result| print_result_set(result, format));
+                }
+                MetaCommand::Mode(mode) => format.mode = mode,
+                MetaCommand::Headers(on) => format.headers = on,
+                MetaCommand::Timer(on) => self.timer = on,
+                MetaCommand::NullValue(text) => format.null = text,
+                MetaCommand::Width(widths) => format.widths = widths,
+                MetaCommand::Export { table, path } => {
+                    let exported = match &path {
+                        Some(path) => File::create(path)
+                            .map_err(|err| format!("Can't create '{}': {}", path, err))
+                            .and_then(|file| {
+                                let file = BufWriter::new(file);
+                                db.export_json(&table, file).map_err(|err| err.to_string())
+                            }),
+                        None => db
+                            .export_json(&table, io::stdout().lock())
+                            .map_err(|err| err.to_string()),
                     };
+                    exported?;
+                }
+                MetaCommand::Import { path, table } => {
+                    let file = File::open(&path)
+                        .map_err(|err| format!("Can't read '{}': {}", path, err))?;
+                    db.import_json(&table, BufReader::new(file))
+                        .map_err(|err| err.to_string())?;
+                }
+                #[cfg(feature = "sqlite")]
+                MetaCommand::ImportSqlite(path) => {
+                    for (table, rows) in db.import_sqlite(&path).map_err(|err| err.to_string())? {
+                        println!("Imported {} rows into {}", rows, table);
+                    }
                 }
-                Command::MetaCommand(cmd) => match cmd {
-                    MetaCommand::Exit => return,
-                    MetaCommand::Print => println!("{:#?}", storage),
-                },
+                #[cfg(not(feature = "sqlite"))]
+                MetaCommand::ImportSqlite(_) => {
+                    return Err(String::from("Importing SQLite needs the sqlite feature"));
+                }
+                MetaCommand::Stats => print_stats(&db.stats(), format),
+                MetaCommand::Help(name) => print!("{}", help::help(name.as_deref())?),
+                MetaCommand::Schema(name) => {
+                    let statements = db.schema(name.as_deref()).map_err(|err| err.to_string())?;
+                    statements
+                        .iter()
+                        .for_each(|statement| println!("{};", statement));
+                }
+                MetaCommand::Backup(path) => db.backup(&path).map_err(|err| err.to_string())?,
             },
-            Err(err) => println!("Parse error: {}", err),
+        }
+        if self.timer && timed {
+            println!("{}", timer.report(&self.db));
+        }
+        Ok(true)
+    }
+}
+
+/// Whether the input holds a passphrase, as in `.open my.db key 'secret'`, to keep it out of the
+/// history file. So does an `.open` that fails to parse, which may still hold one.
+fn holds_passphrase(input: &str) -> bool {
+    let mut parser = Parser::new(input);
+    while !parser.at_end() {
+        let start = parser.offset();
+        match parser.parse_command() {
+            Ok(Command::MetaCommand(MetaCommand::Open { key: Some(_), .. })) => return true,
+            Ok(_) => (),
+            Err(_) if input[start..].trim_start().starts_with(".open") => return true,
+            Err(_) => parser.skip_statement(),
+        }
+    }
+    false
+}
+
+/// Reads commands from the terminal with line editing and history, printing the errors of
+/// those that fail, until `.exit` or the end of input.
+fn repl(mut session: Session, path: Option<&str>) {
+    println!("Welcome to juicydb");
+    match path {
+        Some(path) => println!("Connected to {}", path),
+        None => println!("Connected to a temporary database; use .open to open a file"),
+    }
+
+    let mut editor = DefaultEditor::new().expect("Failed to set up line editing");
+    let history = history_path();
+    if let Some(history) = &history {
+        // There is no history yet on the first run
+        let _ = editor.load_history(history);
+    }
+
+    // Lines of the statement entered so far
+    let color = session.format.color;
+    let style = |style| if color { style } else { "" };
+    let prompts = (paint("> ", style(GREEN)), paint("...> ", style(GREEN)));
+    let mut input = String::new();
+    loop {
+        let prompt = if input.is_empty() {
+            &prompts.0
+        } else {
+            &prompts.1
+        };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                input.push_str(&line);
+                input.push('\n');
+            }
+            // Ctrl-C discards the statement being entered
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                continue;
+            }
+            // Ctrl-D exits
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                println!("Can't read line: {}", err);
+                break;
+            }
+        }
+
+        let parser = Parser::new(&input);
+        if parser.at_end() {
+            input.clear();
+            continue;
+        }
+        if !parser.is_complete() {
+            continue;
+        }
+        let input = std::mem::take(&mut input);
+        if !holds_passphrase(&input) {
+            let _ = editor.add_history_entry(input.trim_end());
+        }
+        match session.run(&input) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(err) => println!("{}", paint(&err, style(RED))),
+        }
+    }
+
+    if let Some(history) = &history {
+        if let Err(err) = editor.save_history(history) {
+            println!("Can't save history: {}", err);
+        }
+    }
+}
+
+/// Runs the commands of a script, without prompts, until `.exit`, the end of the script or the
+/// first command that fails. A last statement may leave out its semicolon.
+fn script(
+    session: &mut Session,
+    lines: impl Iterator<Item = io::Result<String>>,
+) -> Result<(), String> {
+    let mut input = String::new();
+    for line in lines {
+        let line = line.map_err(|err| format!("Can't read line: {}", err))?;
+        input.push_str(&line);
+        input.push('\n');
+        let parser = Parser::new(&input);
+        if parser.at_end() {
+            input.clear();
+            continue;
+        }
+        if parser.is_complete() && !session.run(&std::mem::take(&mut input))? {
+            return Ok(());
+        }
+    }
+    if !Parser::new(&input).at_end() {
+        input.push(';');
+        session.run(&input)?;
+    }
+    Ok(())
+}
+
+/// Whether the argument is SQL to run rather than the path of a database: statements ending in
+/// a semicolon, such as `select 1;`.
+fn is_sql(arg: &str) -> bool {
+    let parser = Parser::new(arg);
+    !parser.at_end() && !arg.trim_start().starts_with('.') && parser.is_complete()
+}
+
+/// The address to serve on and the path of the database given as arguments, leaving the SQL
+/// to run in `args`. Options are rejected unless known, rather than taken for a path or SQL;
+/// SQL may still start with a comment.
+fn arguments(args: &mut Vec<String>) -> Result<(Option<String>, Option<String>), String> {
+    let serve = match args.iter().position(|arg| arg == "--serve") {
+        Some(i) if i + 1 < args.len() => args.drain(i..i + 2).nth(1),
+        Some(_) => {
+            return Err(String::from(
+                "Expected an address to serve on after --serve",
+            ))
+        }
+        None => None,
+    };
+    let option = |arg: &&String| arg.starts_with('-') && !arg.contains(char::is_whitespace);
+    if let Some(option) = args.iter().find(option) {
+        return Err(format!("Unknown option '{}'; see juicydb --help", option));
+    }
+    let path = match args.first() {
+        Some(first) if !is_sql(first) => Some(args.remove(0)),
+        _ => None,
+    };
+    Ok((serve, path))
+}
+
+/// Starts the REPL on a terminal, on the database in the file given as the first argument, if
+/// any, or else on a temporary database. Given SQL as arguments, after the path of a database
+/// if any, e.g. `juicydb my.db "select id from t;"`, or given commands on a pipe, runs them
+/// instead and exits, printing CSV and exiting with 1 if a command fails.
+///
+/// Output to a terminal is in color, unless `--no-color` is given or `NO_COLOR` is set. Given
+/// `--serve ADDRESS`, e.g. `juicydb --serve 127.0.0.1:5433 my.db`, serves the database to clients
+/// connecting to the address instead; see [`juicydb::server`]. `--help` prints the usage.
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help") {
+        print!("{}", help::USAGE);
+        return;
+    }
+    let plain = args.iter().any(|arg| arg == "--no-color")
+        || env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    args.retain(|arg| arg != "--no-color");
+    let arguments = arguments(&mut args);
+    let path = arguments.as_ref().ok().and_then(|(_, path)| path.clone());
+    let db = arguments.and_then(|(serve, path)| {
+        let db = match path {
+            Some(path) => open(&path, None)?,
+            None => Database::with_location(&env::temp_dir().to_string_lossy()),
         };
+        Ok((db, serve))
+    });
+    let interactive = args.is_empty() && io::stdin().is_terminal();
+    let result = db.and_then(|(db, serve)| {
+        if let Some(address) = serve {
+            let listener = TcpListener::bind(&address)
+                .map_err(|err| format!("Can't listen on {}: {}", address, err))?;
+            println!("Listening on {}", address);
+            return db.serve(listener).map_err(|err| err.to_string());
+        }
+        let mut session = Session::new(db);
+        session.format.color = !plain && io::stdout().is_terminal();
+        if interactive {
+            repl(session, path.as_deref());
+            return Ok(());
+        }
+        session.format.mode = OutputMode::Csv;
+        match args.as_slice() {
+            [] => script(&mut session, io::stdin().lock().lines()),
+            sql => script(&mut session, sql.iter().map(|sql| Ok(sql.clone()))),
+        }
+    });
+    if let Err(err) = result {
+        let color = !plain && io::stderr().is_terminal();
+        eprintln!("{}", paint(&err, if color { RED } else { "" }));
+        process::exit(1);
     }
 }
@@ -0,0 +1,218 @@
+//! Rewrites of statements that make them cheaper to execute without changing their results.
+//!
+//! Constant subexpressions, i.e. those referring to no fields or placeholders and calling no
+//! functions with side effects or varying results, are evaluated once into values. Conditions are
+//! then simplified under three-valued logic: `x and true` becomes `x`, `x or true` becomes true,
+//! and so on. Expressions failing to evaluate, e.g. `1 / 0`, are left as they are, so that they
+//! fail only if the query actually evaluates them.
+
+use crate::db::*;
+use crate::parser::*;
+use crate::query_processor::*;
+use std::mem;
+
+/// Whether the function returns the same value whenever called with the same arguments.
+fn is_deterministic(name: &str) -> bool {
+    matches!(name, "date_add" | "extract")
+}
+
+/// Whether the expression evaluates to the same value wherever it is evaluated.
+fn is_constant(expression: &Expression) -> bool {
+    let mut constant = true;
+    expression.walk(&mut |expression| match expression {
+        Expression::Field(_) | Expression::Parameter(_) => constant = false,
+        Expression::Function(name, _) if !is_deterministic(name) => constant = false,
+        _ => (),
+    });
+    constant
+}
+
+/// Folds the constant subexpressions of an expression into values.
+pub fn fold_expression(expression: Expression, scope: &Scope) -> Expression {
+    match expression {
+        Expression::Value(_) => expression,
+        // An explicit collation applies to the comparison the expression is an operand of, so
+        // the expression must keep it
+        Expression::Collate(expression, collation) => {
+            Expression::Collate(Box::new(fold_expression(*expression, scope)), collation)
+        }
+        expression if is_constant(&expression) => match eval_expression(&expression, scope, &[]) {
+            Ok(value) => Expression::Value(value),
+            Err(_) => expression,
+        },
+        Expression::Function(name, args) => Expression::Function(
+            name,
+            args.into_iter()
+                .map(|arg| fold_expression(arg, scope))
+                .collect(),
+        ),
+        Expression::Arithmetic(operator, lhs, rhs) => Expression::Arithmetic(
+            operator,
+            Box::new(fold_expression(*lhs, scope)),
+            Box::new(fold_expression(*rhs, scope)),
+        ),
+        Expression::Condition(condition) => {
+            Expression::Condition(Box::new(simplify_condition(*condition, scope)))
+        }
+        expression => expression,
+    }
+}
+
+/// Condition that is always true, false or unknown.
+fn constant(value: Option<bool>) -> Condition {
+    Condition::Expression(Expression::Value(
+        value.map_or(DBValue::Null, DBValue::Boolean),
+    ))
+}
+
+/// The value of a condition that is a constant: `Some(Some(true))` for a condition that is always
+/// true, `Some(None)` for one that is always unknown, and `None` for one that depends on the row.
+pub fn constant_value(condition: &Condition) -> Option<Option<bool>> {
+    match condition {
+        Condition::Expression(Expression::Value(DBValue::Boolean(b))) => Some(Some(*b)),
+        Condition::Expression(Expression::Value(DBValue::Null)) => Some(None),
+        _ => None,
+    }
+}
+
+/// Folds the constant subexpressions of a condition and simplifies its connectives with constant
+/// operands.
+pub fn simplify_condition(condition: Condition, scope: &Scope) -> Condition {
+    match condition {
+        Condition::Literal(mut literal) => {
+            let (lhs, rhs) = literal.operands_mut();
+            for operand in [lhs, rhs] {
+                let expression = mem::replace(operand, Expression::Value(DBValue::Null));
+                *operand = fold_expression(expression, scope);
+            }
+            let operands = literal.operands();
+            let constant_operands =
+                matches!(operands, (Expression::Value(_), Expression::Value(_)));
+            let condition = Condition::Literal(literal);
+            if !constant_operands {
+                return condition;
+            }
+            match eval_condition(&condition, scope, &[]) {
+                Ok(value) => constant(value),
+                Err(_) => condition,
+            }
+        }
+        Condition::Expression(expression) => match fold_expression(expression, scope) {
+            Expression::Condition(condition) => *condition,
+            expression => Condition::Expression(expression),
+        },
+        Condition::Not(condition) => {
+            let condition = simplify_condition(*condition, scope);
+            match constant_value(&condition) {
+                Some(value) => constant(value.map(|b| !b)),
+                None => Condition::Not(Box::new(condition)),
+            }
+        }
+        Condition::And(c1, c2) => {
+            let (c1, c2) = (
+                simplify_condition(*c1, scope),
+                simplify_condition(*c2, scope),
+            );
+            match (constant_value(&c1), constant_value(&c2)) {
+                (Some(Some(false)), _) | (_, Some(Some(false))) => constant(Some(false)),
+                (Some(Some(true)), _) => c2,
+                (_, Some(Some(true))) => c1,
+                _ => Condition::And(Box::new(c1), Box::new(c2)),
+            }
+        }
+        Condition::Or(c1, c2) => {
+            let (c1, c2) = (
+                simplify_condition(*c1, scope),
+                simplify_condition(*c2, scope),
+            );
+            match (constant_value(&c1), constant_value(&c2)) {
+                (Some(Some(true)), _) | (_, Some(Some(true))) => constant(Some(true)),
+                (Some(Some(false)), _) => c2,
+                (_, Some(Some(false))) => c1,
+                _ => Condition::Or(Box::new(c1), Box::new(c2)),
+            }
+        }
+    }
+}
+
+/// Simplifies the condition of a 'where'-clause, dropping it if it is always true.
+pub fn simplify_where(condition: Option<Condition>, scope: &Scope) -> Option<Condition> {
+    let condition = simplify_condition(condition?, scope);
+    match constant_value(&condition) {
+        Some(Some(true)) => None,
+        _ => Some(condition),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::Functions;
+    use crate::progress::Progress;
+    use std::collections::HashMap;
+    #[cfg(feature = "regexp")]
+    use std::sync::Mutex;
+
+    fn parse_select(input: &str) -> (Vec<Expression>, Option<Condition>) {
+        match Parser::new(input).parse_command() {
+            Ok(Command::Statement(Statement::Select {
+                columns, condition, ..
+            })) => (columns, condition),
+            _ => panic!("Invalid test statement"),
+        }
+    }
+
+    #[test]
+    fn fold_constants() {
+        let sequences = HashMap::new();
+        let functions = Functions::new();
+        let progress = Progress::new();
+        let scope = Scope {
+            tables: Vec::new(),
+            sequences: &sequences,
+            functions: &functions,
+            progress: &progress,
+            #[cfg(feature = "regexp")]
+            regexes: Mutex::new(HashMap::new()),
+        };
+        let simplify = |input: &str| {
+            let (columns, condition) = parse_select(input);
+            let columns: Vec<_> = columns
+                .into_iter()
+                .map(|expression| fold_expression(expression, &scope))
+                .collect();
+            (columns, simplify_where(condition, &scope))
+        };
+        assert_eq!(
+            simplify("select 1 + 2 * 3, a * (2 - 1), nextval('s') from t;"),
+            parse_select("select 7, a * 1, nextval('s') from t;")
+        );
+        assert_eq!(
+            simplify("select a from t where a > 1 and 1 = 1 and not 2 < 1;"),
+            parse_select("select a from t where a > 1;")
+        );
+        assert_eq!(
+            simplify("select a from t where a > 1 or 2 > 1;"),
+            parse_select("select a from t;")
+        );
+        assert_eq!(
+            simplify("select a from t where a > 1 and (a < 0 or 1 > 2);"),
+            parse_select("select a from t where a > 1 and a < 0;")
+        );
+        assert_eq!(
+            simplify("select a from t where a = 1 and 1 = null;"),
+            parse_select("select a from t where a = 1 and null;")
+        );
+        for unchanged in &[
+            "select a from t where a = 1 / 0 or b = 'x' collate nocase;",
+            "select a from t where a = date_add(b, 1, 'day');",
+        ] {
+            assert_eq!(simplify(unchanged), parse_select(unchanged));
+        }
+        let (_, condition) = simplify("select a from t where 1 = 2 and a = 1;");
+        assert_eq!(
+            condition.as_ref().and_then(constant_value),
+            Some(Some(false))
+        );
+    }
+}
@@ -0,0 +1,501 @@
+//! Rendering of the results of statements in the REPL.
+
+use juicydb::db::{ResultColumn, Row};
+use juicydb::json;
+use juicydb::parser::OutputMode;
+use juicydb::{DBValue, ResultSet};
+use std::fmt;
+use std::io::Write;
+
+/// Widest a cell is shown, in characters; longer values are cut short, ending in `…`.
+const MAX_WIDTH: usize = 40;
+
+/// ANSI escape sequences styling text on a terminal.
+pub const BOLD: &str = "\x1b[1m";
+pub const DIM: &str = "\x1b[2m";
+pub const RED: &str = "\x1b[31m";
+pub const GREEN: &str = "\x1b[32m";
+const RESET: &str = "\x1b[0m";
+
+/// The text in the style, or as it is if the style is empty.
+pub fn paint(text: &str, style: &str) -> String {
+    match style {
+        "" => String::from(text),
+        style => format!("{}{}{}", style, text, RESET),
+    }
+}
+
+/// How results are printed, as set with meta commands.
+pub struct Format {
+    pub mode: OutputMode,
+    /// Whether tables, CSV and TSV start with the names of the columns. JSON and lines always
+    /// name the column of each value.
+    pub headers: bool,
+    /// Whether headers are bold and nulls dim in tables and lines, for a terminal. CSV, TSV and
+    /// JSON are left plain for other programs to read.
+    pub color: bool,
+    /// Text nulls are printed as, set with `.nullvalue`, in all modes but JSON. By default they
+    /// are `NULL` in tables and lines, and empty in CSV and TSV.
+    pub null: Option<String>,
+    /// Widths of the columns of tables, in order, set with `.width`. Columns without a width, or
+    /// with zero, are as wide as their widest cell up to [`MAX_WIDTH`].
+    pub widths: Vec<usize>,
+}
+
+impl Format {
+    pub fn new() -> Self {
+        Self {
+            mode: OutputMode::Table,
+            headers: true,
+            color: false,
+            null: None,
+            widths: Vec::new(),
+        }
+    }
+
+    /// The text of a value, with nulls as set or else as the given default.
+    fn text(&self, value: &DBValue, null: &str) -> String {
+        match value {
+            DBValue::Null => String::from(self.null.as_deref().unwrap_or(null)),
+            value => value.to_string(),
+        }
+    }
+
+    /// The style of a value in color: dim for nulls.
+    fn style(&self, value: &DBValue) -> &'static str {
+        match value {
+            DBValue::Null if self.color => DIM,
+            _ => "",
+        }
+    }
+
+    /// The rows of the result, rendered in the mode of the format.
+    pub fn render(&self, result: &ResultSet) -> String {
+        if self.mode == OutputMode::Table {
+            return table(result, self);
+        }
+        let mut output = self.head(&result.columns);
+        for (position, row) in result.rows.iter().enumerate() {
+            output.push_str(&self.row(&result.columns, position, row));
+        }
+        output.push_str(self.tail());
+        output
+    }
+
+    /// Writes the rows to `out` as they are produced, in the mode of the format: a row at a
+    /// time, but for tables, which need all of their rows to size their columns. Fails with the
+    /// first error of the rows, or of writing, leaving the rows before written.
+    pub fn write_rows<E: fmt::Display>(
+        &self,
+        columns: &[ResultColumn],
+        rows: impl Iterator<Item = Result<Row, E>>,
+        out: &mut impl Write,
+    ) -> Result<(), String> {
+        let failed = |err: &dyn fmt::Display| err.to_string();
+        if self.mode == OutputMode::Table {
+            let rows = rows.collect::<Result<_, _>>().map_err(|err| failed(&err))?;
+            let result = ResultSet {
+                columns: columns.to_vec(),
+                rows,
+            };
+            return write!(out, "{}", table(&result, self)).map_err(|err| failed(&err));
+        }
+        write!(out, "{}", self.head(columns)).map_err(|err| failed(&err))?;
+        for (position, row) in rows.enumerate() {
+            let row = row.map_err(|err| failed(&err))?;
+            let row = self.row(columns, position, &row);
+            write!(out, "{}", row).map_err(|err| failed(&err))?;
+        }
+        write!(out, "{}", self.tail()).map_err(|err| failed(&err))?;
+        out.flush().map_err(|err| failed(&err))
+    }
+
+    /// The text before the rows in modes other than tables: the names of the columns of CSV
+    /// and TSV, if asked for, or the start of the JSON array.
+    fn head(&self, columns: &[ResultColumn]) -> String {
+        match self.mode {
+            OutputMode::Csv if self.headers => names(columns, ',', csv_field),
+            OutputMode::Tsv if self.headers => names(columns, '\t', tsv_field),
+            OutputMode::Json => String::from("["),
+            _ => String::new(),
+        }
+    }
+
+    /// The text of the row at the position in modes other than tables.
+    fn row(&self, columns: &[ResultColumn], position: usize, row: &[DBValue]) -> String {
+        match self.mode {
+            OutputMode::Csv => separated(row, self, ',', csv_field),
+            OutputMode::Tsv => separated(row, self, '\t', tsv_field),
+            OutputMode::Json => {
+                let names: Vec<_> = columns.iter().map(|c| c.name.clone()).collect();
+                let separator = if position == 0 { "" } else { ",\n" };
+                format!("{}{}", separator, json::object(&names, row))
+            }
+            OutputMode::Line | OutputMode::Table => {
+                let separator = if position == 0 { "" } else { "\n" };
+                format!("{}{}", separator, lines(columns, row, self))
+            }
+        }
+    }
+
+    /// The text after the rows in modes other than tables: the end of the JSON array.
+    fn tail(&self) -> &'static str {
+        match self.mode {
+            OutputMode::Json => "]\n",
+            _ => "",
+        }
+    }
+}
+
+/// The result as a table of aligned columns under a header of the column names, if asked for,
+/// e.g.
+///
+/// ```text
+/// +----+------+
+/// | id | name |
+/// +----+------+
+/// |  1 | ada  |
+/// +----+------+
+/// ```
+///
+/// Numbers are aligned to the right and everything else to the left. In color, the header is
+/// bold and nulls are dim. Columns given a width by the format are exactly that wide.
+pub fn table(result: &ResultSet, format: &Format) -> String {
+    let header = format.headers;
+    let pinned = |column: usize| {
+        format
+            .widths
+            .get(column)
+            .copied()
+            .filter(|&width| width > 0)
+    };
+    let max = |column| pinned(column).unwrap_or(MAX_WIDTH);
+    let names: Vec<_> = result
+        .columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| cell(&column.name, max(i)))
+        .collect();
+    let rows: Vec<Vec<_>> = result
+        .rows
+        .iter()
+        .map(|row| {
+            row.iter()
+                .enumerate()
+                .map(|(i, value)| cell(&format.text(value, "NULL"), max(i)))
+                .collect()
+        })
+        .collect();
+    let mut widths: Vec<_> = match header {
+        true => names.iter().map(|name| width(name)).collect(),
+        false => vec![0; names.len()],
+    };
+    for row in &rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(self::width(value));
+        }
+    }
+    for (i, width) in widths.iter_mut().enumerate() {
+        *width = pinned(i).unwrap_or(*width);
+    }
+    let line = widths
+        .iter()
+        .map(|width| "-".repeat(width + 2))
+        .collect::<Vec<_>>()
+        .join("+");
+    let line = format!("+{}+\n", line);
+    if !header && rows.is_empty() {
+        return String::new();
+    }
+    let mut output = line.clone();
+    if header {
+        let names_left = vec![false; names.len()];
+        let bold = vec![if format.color { BOLD } else { "" }; names.len()];
+        output.push_str(&table_row(&names, &widths, &names_left, &bold));
+        output.push_str(&line);
+    }
+    for (cells, values) in rows.iter().zip(&result.rows) {
+        let numeric: Vec<_> = values.iter().map(is_number).collect();
+        let styles: Vec<_> = values.iter().map(|value| format.style(value)).collect();
+        output.push_str(&table_row(cells, &widths, &numeric, &styles));
+    }
+    if !rows.is_empty() {
+        output.push_str(&line);
+    }
+    output
+}
+
+/// A row of the table, with the cells padded to the widths of their columns, to the left if
+/// `right` says so, and painted in their styles.
+fn table_row(cells: &[String], widths: &[usize], right: &[bool], styles: &[&str]) -> String {
+    let cells: Vec<_> = cells
+        .iter()
+        .zip(widths)
+        .zip(right)
+        .zip(styles)
+        .map(|(((cell, width), right), style)| {
+            let padding = " ".repeat(width - self::width(cell));
+            let cell = paint(cell, style);
+            match right {
+                true => format!(" {}{} ", padding, cell),
+                false => format!(" {}{} ", cell, padding),
+            }
+        })
+        .collect();
+    format!("|{}|\n", cells.join("|"))
+}
+
+/// The names of the columns on a line, separated by `separator` and written by `field`.
+fn names(columns: &[ResultColumn], separator: char, field: fn(&str) -> String) -> String {
+    let names: Vec<_> = columns.iter().map(|column| field(&column.name)).collect();
+    format!("{}\n", names.join(&separator.to_string()))
+}
+
+/// The row as a line of CSV or TSV, with the fields separated by `separator` and written by
+/// `field`. Nulls are empty fields, unless the format sets a text for them.
+fn separated(
+    row: &[DBValue],
+    format: &Format,
+    separator: char,
+    field: fn(&str) -> String,
+) -> String {
+    let values: Vec<_> = row
+        .iter()
+        .map(|value| match (value, &format.null) {
+            (DBValue::Null, None) => String::new(),
+            (value, _) => field(&format.text(value, "")),
+        })
+        .collect();
+    format!("{}\n", values.join(&separator.to_string()))
+}
+
+/// A field of CSV, quoted if it holds a comma, a quote or a line break, with its quotes doubled.
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        String::from(text)
+    }
+}
+
+/// A field of TSV, with tabs, line breaks and backslashes escaped by backslashes.
+fn tsv_field(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// The row as a value per line, after the name of its column aligned to the right; rows are
+/// separated by empty lines. JSON is an array of an object per row, keyed by column name, with
+/// a row per line. In color, the names are bold and nulls are dim.
+fn lines(columns: &[ResultColumn], row: &[DBValue], format: &Format) -> String {
+    let width = columns
+        .iter()
+        .map(|column| self::width(&column.name))
+        .max()
+        .unwrap_or(0);
+    columns
+        .iter()
+        .zip(row)
+        .map(|(column, value)| {
+            let name = format!("{:>width$}", column.name);
+            let bold = if format.color { BOLD } else { "" };
+            let value = paint(&format.text(value, "NULL"), format.style(value));
+            format!("{} = {}\n", paint(&name, bold), value)
+        })
+        .collect()
+}
+
+/// The text of a cell, on one line and cut short to the given width, ending in `…`. Cells 3
+/// characters wide or less are cut without one, so that they keep some of their text.
+fn cell(text: &str, max: usize) -> String {
+    let text = text.replace('\n', " ");
+    if width(&text) <= max {
+        return text;
+    }
+    if max <= 3 {
+        return text.chars().take(max).collect();
+    }
+    let mut cut: String = text.chars().take(max - 1).collect();
+    cut.push('…');
+    cut
+}
+
+/// The line of the input holding the byte at the offset, e.g. where parsing failed, followed by
+/// a line with a caret under that byte.
+pub fn caret(input: &str, offset: usize) -> String {
+    let offset = offset.min(input.trim_end().len());
+    let start = input[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let end = input[offset..]
+        .find('\n')
+        .map_or(input.len(), |i| offset + i);
+    let column = width(&input[start..offset]);
+    format!("{}\n{}^", &input[start..end], " ".repeat(column))
+}
+
+fn width(text: &str) -> usize {
+    text.chars().count()
+}
+
+fn is_number(value: &DBValue) -> bool {
+    matches!(
+        value,
+        DBValue::Integer(_) | DBValue::Real(_) | DBValue::Decimal(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use juicydb::db::ResultColumn;
+
+    #[test]
+    fn aligned_table() {
+        let column = |name: &str| ResultColumn {
+            name: String::from(name),
+            db_type: None,
+        };
+        let long = "x".repeat(50);
+        let result = ResultSet {
+            columns: vec![column("id"), column("name")],
+            rows: vec![
+                vec![DBValue::Integer(1), DBValue::Text(String::from("ada"))],
+                vec![DBValue::Integer(100), DBValue::Text(long)],
+                vec![DBValue::Null, DBValue::Real(1.5)],
+            ],
+        };
+        let cut = format!("{}…", "x".repeat(MAX_WIDTH - 1));
+        let line = format!("+------+-{}-+\n", "-".repeat(MAX_WIDTH));
+        let expected = [
+            line.clone(),
+            format!("| id   | name{} |\n", " ".repeat(MAX_WIDTH - 4)),
+            line.clone(),
+            format!("|    1 | ada{} |\n", " ".repeat(MAX_WIDTH - 3)),
+            format!("|  100 | {} |\n", cut),
+            format!("| NULL | {}1.5 |\n", " ".repeat(MAX_WIDTH - 3)),
+            line,
+        ];
+        let mut format = Format::new();
+        assert_eq!(table(&result, &format), expected.concat());
+        let headless = [
+            &expected[0],
+            &expected[3],
+            &expected[4],
+            &expected[5],
+            &expected[6],
+        ];
+        let headless = headless.map(String::as_str).concat();
+        format.headers = false;
+        assert_eq!(table(&result, &format), headless);
+        format.color = true;
+        let colored = table(&result, &format);
+        assert_eq!(colored.replace(DIM, "").replace(RESET, ""), headless);
+        assert!(colored.contains(&format!("| {} |", paint("NULL", DIM))));
+    }
+
+    #[test]
+    fn carets() {
+        assert_eq!(caret("select x fro t;\n", 9), "select x fro t;\n         ^");
+        let input = "select x\nfrom t\nwhere;\n";
+        assert_eq!(caret(input, 21), "where;\n     ^");
+        assert_eq!(caret(input, input.len()), "where;\n      ^");
+    }
+
+    #[test]
+    fn other_modes() {
+        let column = |name: &str| ResultColumn {
+            name: String::from(name),
+            db_type: None,
+        };
+        let text = |text: &str| DBValue::Text(String::from(text));
+        let result = ResultSet {
+            columns: vec![column("id"), column("name")],
+            rows: vec![
+                vec![DBValue::Integer(1), text("a, \"b\"")],
+                vec![DBValue::Null, text("tab\there")],
+            ],
+        };
+        let mut format = Format::new();
+        format.mode = OutputMode::Csv;
+        let csv = "id,name\n1,\"a, \"\"b\"\"\"\n,tab\there\n";
+        assert_eq!(format.render(&result), csv);
+        format.mode = OutputMode::Tsv;
+        assert_eq!(
+            format.render(&result),
+            "id\tname\n1\ta, \"b\"\n\ttab\\there\n"
+        );
+        format.headers = false;
+        assert_eq!(format.render(&result), "1\ta, \"b\"\n\ttab\\there\n");
+        format.mode = OutputMode::Json;
+        let json =
+            "[{\"id\":1,\"name\":\"a, \\\"b\\\"\"},\n{\"id\":null,\"name\":\"tab\\there\"}]\n";
+        assert_eq!(format.render(&result), json);
+        format.mode = OutputMode::Line;
+        let lines = "  id = 1\nname = a, \"b\"\n\n  id = NULL\nname = tab\there\n";
+        assert_eq!(format.render(&result), lines);
+    }
+
+    #[test]
+    fn pinned_widths() {
+        let column = |name: &str| ResultColumn {
+            name: String::from(name),
+            db_type: None,
+        };
+        let text = |text: &str| DBValue::Text(String::from(text));
+        let result = ResultSet {
+            columns: vec![column("id"), column("description"), column("n")],
+            rows: vec![vec![
+                DBValue::Integer(1),
+                text("a long description"),
+                text("x"),
+            ]],
+        };
+        let mut format = Format::new();
+        format.widths = vec![4, 10];
+        let expected = "\
+            +------+------------+---+\n\
+            | id   | descripti… | n |\n\
+            +------+------------+---+\n\
+            |    1 | a long de… | x |\n\
+            +------+------------+---+\n";
+        assert_eq!(table(&result, &format), expected);
+        format.widths = vec![0, 2];
+        assert!(table(&result, &format).contains("|  1 | a  | x |"));
+    }
+
+    #[test]
+    fn narrow_cells() {
+        // Up to 3 characters wide, values are cut without an ellipsis, which would hide them
+        assert_eq!(cell("abcdef", 0), "");
+        assert_eq!(cell("abcdef", 1), "a");
+        assert_eq!(cell("abcdef", 3), "abc");
+        assert_eq!(cell("abcdef", 4), "abc…");
+        assert_eq!(cell("abcdef", 5), "abcd…");
+        assert_eq!(cell("abcdef", 6), "abcdef");
+        assert_eq!(cell("ab", 3), "ab");
+    }
+
+    #[test]
+    fn null_values() {
+        let column = |name: &str| ResultColumn {
+            name: String::from(name),
+            db_type: None,
+        };
+        let result = ResultSet {
+            columns: vec![column("a"), column("b")],
+            rows: vec![vec![DBValue::Null, DBValue::Text(String::new())]],
+        };
+        let mut format = Format::new();
+        format.mode = OutputMode::Csv;
+        assert_eq!(format.render(&result), "a,b\n,\n");
+        format.null = Some(String::from("\\N"));
+        assert_eq!(format.render(&result), "a,b\n\\N,\n");
+        format.null = Some(String::from("no, value"));
+        assert_eq!(format.render(&result), "a,b\n\"no, value\",\n");
+        format.mode = OutputMode::Table;
+        assert!(format.render(&result).contains("| no, value |   |"));
+        format.mode = OutputMode::Json;
+        assert_eq!(format.render(&result), "[{\"a\":null,\"b\":\"\"}]\n");
+    }
+}
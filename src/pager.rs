@@ -0,0 +1,888 @@
+//! Page-level access to the file of a b-tree. The file is a sequence of fixed-size pages, page
+//! `n` starting at byte `n * PAGE_SIZE`; the pager translates page ids into file offsets, so that
+//! the b-tree deals in pages only and the storage behind the pages can be changed on its own.
+//! The last 4 bytes of each page are a CRC-32 of the rest of the page, set as the page is
+//! written and verified as it is read, so that corrupt pages are caught instead of being read
+//! as garbage. With a [`Cipher`], every page but the first, which holds the header of the file,
+//! is encrypted; the nonce and the tag of the page take up the 40 bytes before the checksum,
+//! which are zero in unencrypted pages. Pages are read either with a system call per page or, on Unix, from a memory
+//! mapping of the file, which leaves caching the pages to the operating system.
+//!
+//! The pages are kept in a [`Storage`], which is a file, or a [`MemoryStorage`] where there is
+//! no file system to keep them in, as in browsers.
+//!
+//! Writes can be grouped with a rollback journal, a file next to the file of the pages which the
+//! original contents of each page are copied to before the page is first overwritten. Rolling
+//! back copies the pages back, and committing deletes the journal. A journal left behind by a
+//! crash is rolled back by [`Pager::recover`], so that the file holds either all of the writes
+//! or none of them.
+
+use crate::cipher::{Cipher, NONCE_SIZE, TAG_SIZE};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Size of a page in bytes.
+pub const PAGE_SIZE: usize = 4096;
+
+/// Number of bytes of a page available to its users, followed by the nonce and the tag of
+/// encrypted pages and the checksum of the page.
+pub const USABLE_SIZE: usize = CHECKSUM - NONCE_SIZE - TAG_SIZE;
+
+/// Offset of the checksum in a page.
+const CHECKSUM: usize = PAGE_SIZE - 4;
+
+/// Number of pages written after which a checkpoint is taken automatically.
+pub const CHECKPOINT_PAGES: usize = 1024;
+
+/// Appended to the path of a file for the path of its rollback journal.
+pub const JOURNAL_SUFFIX: &str = "-journal";
+
+/// Start of a rollback journal, followed by the number of pages in the file as the journal
+/// began and the saved pages, each as its page id followed by its bytes as they were in the file.
+const JOURNAL_MAGIC: &[u8; 8] = b"jdbjrnl\0";
+
+/// Numbers of pages read and written by the pagers sharing the counts, e.g. those of the files of
+/// a database, for tracing and statistics; see [`Pager::set_counts`].
+#[derive(Debug, Default)]
+pub struct PageCounts {
+    read: AtomicU64,
+    written: AtomicU64,
+    /// Number of the pages read which were copied from a memory mapping rather than read with
+    /// a system call; see [`ReadMode::Mmap`].
+    mapped: AtomicU64,
+}
+
+impl PageCounts {
+    /// The numbers of pages read and written so far.
+    pub fn get(&self) -> (u64, u64) {
+        (
+            self.read.load(Ordering::Relaxed),
+            self.written.load(Ordering::Relaxed),
+        )
+    }
+
+    /// The number of the pages read so far which were copied from a memory mapping.
+    pub fn mapped(&self) -> u64 {
+        self.mapped.load(Ordering::Relaxed)
+    }
+}
+
+pub type PageId = u32;
+pub type Page = [u8; PAGE_SIZE];
+
+/// The bytes behind the pages of a [`Pager`].
+pub trait Storage: Read + Write + Seek + Send + fmt::Debug {
+    /// Length of the storage in bytes.
+    fn size(&self) -> io::Result<u64>;
+
+    /// Truncates or extends the storage to the given length in bytes.
+    fn set_size(&mut self, size: u64) -> io::Result<()>;
+
+    /// Makes the bytes written so far durable.
+    fn sync_data(&mut self) -> io::Result<()>;
+
+    /// The file of the storage, if it is one, for mapping it into memory.
+    fn file(&self) -> Option<&File> {
+        None
+    }
+}
+
+impl Storage for File {
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn set_size(&mut self, size: u64) -> io::Result<()> {
+        self.set_len(size)
+    }
+
+    fn sync_data(&mut self) -> io::Result<()> {
+        File::sync_data(self)
+    }
+
+    fn file(&self) -> Option<&File> {
+        Some(self)
+    }
+}
+
+/// Storage in a buffer in memory. Clones share the buffer, each with a position of its own, so
+/// that the bytes written through a pager can be taken out with [`MemoryStorage::bytes`].
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStorage {
+    buffer: Arc<Mutex<Vec<u8>>>,
+    position: u64,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Storage holding the given bytes, e.g. of a file written by another pager.
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self {
+            buffer: Arc::new(Mutex::new(bytes)),
+            position: 0,
+        }
+    }
+
+    /// A copy of the bytes of the storage.
+    pub fn bytes(&self) -> Vec<u8> {
+        self.buffer.lock().unwrap().clone()
+    }
+}
+
+impl Read for MemoryStorage {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let buffer = self.buffer.lock().unwrap();
+        let start = (self.position as usize).min(buffer.len());
+        let read = buf.len().min(buffer.len() - start);
+        buf[..read].copy_from_slice(&buffer[start..(start + read)]);
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Write for MemoryStorage {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut buffer = self.buffer.lock().unwrap();
+        let start = self.position as usize;
+        let end = start + buf.len();
+        if buffer.len() < end {
+            buffer.resize(end, 0);
+        }
+        buffer[start..end].copy_from_slice(buf);
+        self.position = end as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for MemoryStorage {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let len = self.buffer.lock().unwrap().len() as i64;
+        let position = match position {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::End(offset) => len + offset,
+            SeekFrom::Current(offset) => self.position as i64 + offset,
+        };
+        if position < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Seek before the start of the storage",
+            ));
+        }
+        self.position = position as u64;
+        Ok(self.position)
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.buffer.lock().unwrap().len() as u64)
+    }
+
+    fn set_size(&mut self, size: u64) -> io::Result<()> {
+        self.buffer.lock().unwrap().resize(size as usize, 0);
+        Ok(())
+    }
+
+    fn sync_data(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// How a [`Pager`] reads pages.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReadMode {
+    /// A read from the file for each page.
+    Syscalls,
+    /// Copying the page from a read-only mapping of the file, for read-heavy workloads. Pages
+    /// written past the end of the mapping are read from the file until the file is mapped
+    /// again, which happens as the file doubles in size. Falls back to system calls on
+    /// platforms other than Unix.
+    Mmap,
+}
+
+/// Reads and writes the pages of a file, or of any other [`Storage`]. Pages are read through a shared reference, so that a
+/// b-tree can be read from several threads at once; the file is locked for each read. Pages are
+/// always written with system calls, which a shared mapping of the file reflects.
+#[derive(Debug)]
+pub struct Pager {
+    file: Mutex<Box<dyn Storage>>,
+    mode: ReadMode,
+    mapping: Option<Mapping>,
+    /// Number of pages in the file, counting the pages allocated but not written yet.
+    pages: PageId,
+    /// Number of pages written since the last checkpoint.
+    dirty: usize,
+    cipher: Option<Cipher>,
+    journal: Option<Journal>,
+    counts: Arc<PageCounts>,
+}
+
+/// A rollback journal in progress.
+#[derive(Debug)]
+struct Journal {
+    file: File,
+    path: PathBuf,
+    /// Number of pages in the file as the journal began; pages past it need not be saved.
+    pages: PageId,
+    saved: HashSet<PageId>,
+    /// Whether the journal and the file are synced to the disk, so that the writes survive a
+    /// crash of the operating system and not only of the process.
+    sync: bool,
+}
+
+impl Pager {
+    /// A pager over the file, which holds the pages written so far, reading pages with system
+    /// calls.
+    pub fn new(file: File) -> io::Result<Self> {
+        Self::with_mode(file, ReadMode::Syscalls)
+    }
+
+    pub fn with_mode(file: File, mode: ReadMode) -> io::Result<Self> {
+        Self::with_storage(Box::new(file), mode)
+    }
+
+    /// A pager over the storage, which holds the pages written so far. Storage other than a
+    /// file is read with system calls, whatever the mode.
+    pub fn with_storage(storage: Box<dyn Storage>, mode: ReadMode) -> io::Result<Self> {
+        let pages = (storage.size()? / PAGE_SIZE as u64) as PageId;
+        let mut pager = Self {
+            file: Mutex::new(storage),
+            mode,
+            mapping: None,
+            pages,
+            dirty: 0,
+            cipher: None,
+            journal: None,
+            counts: Arc::default(),
+        };
+        pager.remap()?;
+        Ok(pager)
+    }
+
+    pub fn mode(&self) -> ReadMode {
+        self.mode
+    }
+
+    /// Maps the whole file anew, if reading pages from a mapping.
+    fn remap(&mut self) -> io::Result<()> {
+        self.mapping = None;
+        if self.mode == ReadMode::Mmap {
+            if let Some(file) = self.file.get_mut().unwrap().file() {
+                self.mapping = Mapping::new(file)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Encrypts the pages written from now on, and decrypts the pages read, with the cipher.
+    pub fn set_cipher(&mut self, cipher: Option<Cipher>) {
+        self.cipher = cipher;
+    }
+
+    pub fn cipher(&self) -> Option<&Cipher> {
+        self.cipher.as_ref()
+    }
+
+    /// Counts the pages read and written from now on in the given counts, which other pagers
+    /// may share, rather than in counts of the pager's own.
+    pub fn set_counts(&mut self, counts: Arc<PageCounts>) {
+        self.counts = counts;
+    }
+
+    pub fn counts(&self) -> &Arc<PageCounts> {
+        &self.counts
+    }
+
+    pub fn page_count(&self) -> PageId {
+        self.pages
+    }
+
+    pub fn read_page(&self, page_id: PageId) -> io::Result<Page> {
+        self.counts.read.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "tracing")]
+        tracing::trace!(page_id, "read page");
+        let mut page = [0; PAGE_SIZE];
+        match self
+            .mapping
+            .as_ref()
+            .and_then(|mapping| mapping.page(page_id))
+        {
+            Some(mapped) => {
+                self.counts.mapped.fetch_add(1, Ordering::Relaxed);
+                page.copy_from_slice(mapped);
+            }
+            None => {
+                let mut file = self.file.lock().unwrap();
+                file.seek(SeekFrom::Start(offset(page_id)))?;
+                file.read_exact(&mut page)?;
+            }
+        }
+        if page[CHECKSUM..] != crc32(&page[..CHECKSUM]).to_le_bytes() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                CorruptPage(page_id),
+            ));
+        }
+        match &self.cipher {
+            Some(cipher) if page_id != 0 => {
+                let (bytes, rest) = page.split_at_mut(USABLE_SIZE);
+                let mut nonce = [0; NONCE_SIZE];
+                nonce.copy_from_slice(&rest[..NONCE_SIZE]);
+                let mut tag = [0; TAG_SIZE];
+                tag.copy_from_slice(&rest[NONCE_SIZE..(NONCE_SIZE + TAG_SIZE)]);
+                if !cipher.decrypt(bytes, &page_id.to_le_bytes(), &nonce, &tag) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        UndecryptablePage(page_id),
+                    ));
+                }
+            }
+            _ => (),
+        }
+        Ok(page)
+    }
+
+    /// Writes the page, replacing its last bytes with the nonce and the tag of the page if
+    /// encrypted, or zeros, and its checksum.
+    pub fn write_page(&mut self, page_id: PageId, page: &Page) -> io::Result<()> {
+        self.counts.written.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "tracing")]
+        tracing::trace!(page_id, "write page");
+        self.save_page(page_id)?;
+        let mut page = *page;
+        page[USABLE_SIZE..CHECKSUM].copy_from_slice(&[0; NONCE_SIZE + TAG_SIZE]);
+        match &self.cipher {
+            Some(cipher) if page_id != 0 => {
+                let (bytes, rest) = page.split_at_mut(USABLE_SIZE);
+                let (nonce, tag) = cipher.encrypt(bytes, &page_id.to_le_bytes())?;
+                rest[..NONCE_SIZE].copy_from_slice(&nonce);
+                rest[NONCE_SIZE..(NONCE_SIZE + TAG_SIZE)].copy_from_slice(&tag);
+            }
+            _ => (),
+        }
+        let checksum = crc32(&page[..CHECKSUM]);
+        page[CHECKSUM..].copy_from_slice(&checksum.to_le_bytes());
+        let file = self.file.get_mut().unwrap();
+        file.seek(SeekFrom::Start(offset(page_id)))?;
+        file.write_all(&page)?;
+        self.pages = self.pages.max(page_id + 1);
+        self.dirty += 1;
+        if self.dirty >= CHECKPOINT_PAGES {
+            self.checkpoint()?;
+        }
+        let mapped = self.mapping.as_ref().map_or(0, Mapping::len);
+        if self.mode == ReadMode::Mmap && offset(page_id) as usize >= 2 * mapped.max(PAGE_SIZE) {
+            self.remap()?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the pages written since the last checkpoint from the caches of the operating
+    /// system to the disk. Taken automatically every [`CHECKPOINT_PAGES`] pages, so that
+    /// unflushed writes don't pile up.
+    pub fn checkpoint(&mut self) -> io::Result<()> {
+        if self.dirty > 0 {
+            self.file.get_mut().unwrap().sync_data()?;
+            self.dirty = 0;
+        }
+        Ok(())
+    }
+
+    /// A new page at the end of the file. The page is only part of the file once written.
+    pub fn allocate_page(&mut self) -> PageId {
+        self.pages += 1;
+        self.pages - 1
+    }
+
+    /// Removes all pages from the given page on.
+    pub fn truncate(&mut self, pages: PageId) -> io::Result<()> {
+        let journaled = self.journal.as_ref().map_or(0, |journal| journal.pages);
+        for page_id in pages..journaled {
+            self.save_page(page_id)?;
+        }
+        // Pages removed from the file must not be read through the mapping
+        self.mapping = None;
+        self.file.get_mut().unwrap().set_size(offset(pages))?;
+        self.pages = pages;
+        self.remap()
+    }
+
+    /// Begins a rollback journal at the given path, usually the path of the file followed by
+    /// [`JOURNAL_SUFFIX`]. Until the journal is committed or rolled back, every page of the file
+    /// is copied to the journal before it is first overwritten or truncated. Without `sync`,
+    /// neither the journal nor the file is synced to the disk.
+    pub fn begin_journal(&mut self, path: PathBuf, sync: bool) -> io::Result<()> {
+        if self.journal.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "A journal is already in progress",
+            ));
+        }
+        let file = self.file.get_mut().unwrap();
+        let pages = (file.size()? / PAGE_SIZE as u64) as PageId;
+        let mut journal = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        journal.write_all(JOURNAL_MAGIC)?;
+        journal.write_all(&pages.to_le_bytes())?;
+        if sync {
+            journal.sync_data()?;
+        }
+        self.journal = Some(Journal {
+            file: journal,
+            path,
+            pages,
+            saved: HashSet::new(),
+            sync,
+        });
+        Ok(())
+    }
+
+    /// Keeps the writes since the journal began, syncing them to the disk before deleting the
+    /// journal if it was begun with `sync`.
+    pub fn commit_journal(&mut self) -> io::Result<()> {
+        if let Some(journal) = self.journal.take() {
+            if journal.sync {
+                self.file.get_mut().unwrap().sync_data()?;
+                self.dirty = 0;
+            }
+            fs::remove_file(&journal.path)?;
+        }
+        Ok(())
+    }
+
+    /// Undoes the writes since the journal began, copying the saved pages back into the file,
+    /// and deletes the journal.
+    pub fn rollback_journal(&mut self) -> io::Result<()> {
+        if let Some(mut journal) = self.journal.take() {
+            self.mapping = None;
+            let file = self.file.get_mut().unwrap();
+            self.pages = roll_back(file.as_mut(), &mut journal.file)?.unwrap_or(self.pages);
+            self.dirty = 0;
+            fs::remove_file(&journal.path)?;
+            self.remap()?;
+        }
+        Ok(())
+    }
+
+    /// Rolls back the journal at the given path if there is one, which a crash left behind in
+    /// the middle of a group of writes. Returns whether there was a journal.
+    pub fn recover(&mut self, journal: &Path) -> io::Result<bool> {
+        let mut journal_file = match File::open(journal) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err),
+        };
+        self.mapping = None;
+        let file = self.file.get_mut().unwrap();
+        self.pages = roll_back(file.as_mut(), &mut journal_file)?.unwrap_or(self.pages);
+        fs::remove_file(journal)?;
+        self.remap()?;
+        Ok(true)
+    }
+
+    /// Copies the page as it is in the file to the journal, if a journal is in progress and the
+    /// page hasn't been saved yet. A synced journal is synced before the page can be
+    /// overwritten.
+    fn save_page(&mut self, page_id: PageId) -> io::Result<()> {
+        let journal = match &mut self.journal {
+            Some(journal) if page_id < journal.pages && !journal.saved.contains(&page_id) => {
+                journal
+            }
+            _ => return Ok(()),
+        };
+        let mut page = [0; PAGE_SIZE];
+        let file = self.file.get_mut().unwrap();
+        file.seek(SeekFrom::Start(offset(page_id)))?;
+        file.read_exact(&mut page)?;
+        journal.file.seek(SeekFrom::End(0))?;
+        journal.file.write_all(&page_id.to_le_bytes())?;
+        journal.file.write_all(&page)?;
+        if journal.sync {
+            journal.file.sync_data()?;
+        }
+        journal.saved.insert(page_id);
+        Ok(())
+    }
+}
+
+/// Copies the pages saved in the journal back into the file and truncates the file to the pages
+/// it had as the journal began, syncing the file. Returns that number of pages, or `None` if the
+/// journal is incomplete, in which case nothing had been written under it yet. A page cut off at
+/// the end of the journal was never overwritten either, and is skipped.
+fn roll_back(file: &mut dyn Storage, journal: &mut File) -> io::Result<Option<PageId>> {
+    let mut bytes = Vec::new();
+    journal.seek(SeekFrom::Start(0))?;
+    journal.read_to_end(&mut bytes)?;
+    let start = JOURNAL_MAGIC.len() + 4;
+    if bytes.len() < start || &bytes[..JOURNAL_MAGIC.len()] != JOURNAL_MAGIC {
+        return Ok(None);
+    }
+    let mut pages = [0; 4];
+    pages.copy_from_slice(&bytes[JOURNAL_MAGIC.len()..start]);
+    let pages = PageId::from_le_bytes(pages);
+    for entry in bytes[start..].chunks_exact(4 + PAGE_SIZE) {
+        let mut page_id = [0; 4];
+        page_id.copy_from_slice(&entry[..4]);
+        file.seek(SeekFrom::Start(offset(PageId::from_le_bytes(page_id))))?;
+        file.write_all(&entry[4..])?;
+    }
+    file.set_size(offset(pages))?;
+    file.sync_data()?;
+    Ok(Some(pages))
+}
+
+/// The path of the rollback journal of the file at the given path.
+pub fn journal_path(path: &Path) -> PathBuf {
+    let mut journal = path.as_os_str().to_os_string();
+    journal.push(JOURNAL_SUFFIX);
+    PathBuf::from(journal)
+}
+
+fn offset(page_id: PageId) -> u64 {
+    page_id as u64 * PAGE_SIZE as u64
+}
+
+/// Read-only, shared memory mapping of a file, as long as the file was when mapped.
+#[derive(Debug)]
+struct Mapping {
+    #[cfg(unix)]
+    map: memmap2::Mmap,
+}
+
+impl Mapping {
+    /// Maps the file, unless it is empty.
+    #[cfg(unix)]
+    fn new(file: &File) -> io::Result<Option<Self>> {
+        if file.metadata()?.len() == 0 {
+            return Ok(None);
+        }
+        // SAFETY: the file is only written through the pager, with system calls, which the
+        // shared mapping reflects; the mapping never outlives the pager holding the file
+        let map = unsafe { memmap2::MmapOptions::new().map(file)? };
+        Ok(Some(Self { map }))
+    }
+
+    #[cfg(not(unix))]
+    fn new(_file: &File) -> io::Result<Option<Self>> {
+        Ok(None)
+    }
+
+    /// The mapped bytes of the page, if the page is within the mapping.
+    #[cfg(unix)]
+    fn page(&self, page_id: PageId) -> Option<&[u8]> {
+        let start = offset(page_id) as usize;
+        self.map.get(start..(start + PAGE_SIZE))
+    }
+
+    #[cfg(not(unix))]
+    fn page(&self, _page_id: PageId) -> Option<&[u8]> {
+        None
+    }
+
+    #[cfg(unix)]
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    #[cfg(not(unix))]
+    fn len(&self) -> usize {
+        0
+    }
+}
+
+/// Error of reading a page whose checksum doesn't match its contents, carried by an
+/// [`io::Error`] of kind [`io::ErrorKind::InvalidData`].
+#[derive(Debug, PartialEq)]
+pub struct CorruptPage(pub PageId);
+
+impl fmt::Display for CorruptPage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Page {} is corrupt", self.0)
+    }
+}
+
+impl Error for CorruptPage {}
+
+/// Error of reading an encrypted page that fails to authenticate, as the key is wrong or the
+/// page was altered, carried by an [`io::Error`] of kind [`io::ErrorKind::InvalidData`].
+#[derive(Debug, PartialEq)]
+pub struct UndecryptablePage(pub PageId);
+
+impl fmt::Display for UndecryptablePage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Page {} could not be decrypted: wrong key or altered page",
+            self.0
+        )
+    }
+}
+
+impl Error for UndecryptablePage {}
+
+/// Lookup tables of the CRC-32 of each byte followed by 0 to 7 zero bytes, for computing the
+/// checksum 8 bytes at a time.
+const CRC_TABLES: [[u32; 256]; 8] = crc_tables();
+
+const fn crc_tables() -> [[u32; 256]; 8] {
+    let mut tables = [[0; 256]; 8];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 0 {
+                crc >> 1
+            } else {
+                0xedb8_8320 ^ (crc >> 1)
+            };
+            bit += 1;
+        }
+        tables[0][i] = crc;
+        i += 1;
+    }
+    let mut table = 1;
+    while table < 8 {
+        let mut i = 0;
+        while i < 256 {
+            let previous = tables[table - 1][i];
+            tables[table][i] = (previous >> 8) ^ tables[0][(previous & 0xff) as usize];
+            i += 1;
+        }
+        table += 1;
+    }
+    tables
+}
+
+/// The CRC-32 (IEEE 802.3) of the bytes.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0;
+    let mut chunks = bytes.chunks_exact(8);
+    for chunk in &mut chunks {
+        let low = crc ^ u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        let t = &CRC_TABLES;
+        crc = t[7][(low & 0xff) as usize]
+            ^ t[6][((low >> 8) & 0xff) as usize]
+            ^ t[5][((low >> 16) & 0xff) as usize]
+            ^ t[4][(low >> 24) as usize]
+            ^ t[3][chunk[4] as usize]
+            ^ t[2][chunk[5] as usize]
+            ^ t[1][chunk[6] as usize]
+            ^ t[0][chunk[7] as usize];
+    }
+    for &byte in chunks.remainder() {
+        crc = CRC_TABLES[0][((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, OpenOptions};
+
+    #[test]
+    fn pages_of_a_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("pager");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        let mut pager = Pager::new(file).unwrap();
+        assert_eq!(pager.page_count(), 0);
+        let (first, second) = (pager.allocate_page(), pager.allocate_page());
+        assert_eq!((first, second), (0, 1));
+        pager.write_page(second, &[2; PAGE_SIZE]).unwrap();
+        pager.write_page(first, &[1; PAGE_SIZE]).unwrap();
+        assert_eq!(pager.dirty, 2);
+        pager.checkpoint().unwrap();
+        assert_eq!(pager.dirty, 0);
+        assert_eq!(
+            pager.read_page(second).unwrap()[..USABLE_SIZE],
+            [2; USABLE_SIZE]
+        );
+        assert!(pager.read_page(2).is_err());
+        assert_eq!(fs::metadata(&path).unwrap().len(), 2 * PAGE_SIZE as u64);
+
+        let mut pager = Pager::new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(pager.page_count(), 2);
+        assert_eq!(
+            pager.read_page(first).unwrap()[..USABLE_SIZE],
+            [1; USABLE_SIZE]
+        );
+        assert_eq!(pager.allocate_page(), 2);
+        drop(pager);
+    }
+
+    #[test]
+    fn counted_page_io() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("counts");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        let mut pager = Pager::new(file).unwrap();
+        let page = pager.allocate_page();
+        pager.write_page(page, &[1; PAGE_SIZE]).unwrap();
+        pager.read_page(page).unwrap();
+        pager.read_page(page).unwrap();
+        assert_eq!(pager.counts().get(), (2, 1));
+        let counts = Arc::new(PageCounts::default());
+        let mut other = Pager::new(File::open(&path).unwrap()).unwrap();
+        other.set_counts(counts.clone());
+        pager.set_counts(counts.clone());
+        pager.read_page(page).unwrap();
+        other.read_page(page).unwrap();
+        assert_eq!(counts.get(), (2, 0));
+        assert_eq!(counts.mapped(), 0);
+        drop(pager);
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("checksums");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        let mut pager = Pager::new(file).unwrap();
+        pager.write_page(0, &[7; PAGE_SIZE]).unwrap();
+        pager.write_page(1, &[7; PAGE_SIZE]).unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[PAGE_SIZE + 100] ^= 0x10;
+        fs::write(&path, &bytes).unwrap();
+        assert!(pager.read_page(0).is_ok());
+        let err = pager.read_page(1).unwrap_err();
+        let corrupt = err
+            .get_ref()
+            .and_then(|err| err.downcast_ref::<CorruptPage>());
+        assert_eq!(corrupt, Some(&CorruptPage(1)));
+        drop(pager);
+    }
+
+    #[test]
+    fn rollback_journal() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("journal");
+        let journal = journal_path(&path);
+        let open = || {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)
+                .unwrap();
+            Pager::new(file).unwrap()
+        };
+        let page = |byte: u8| [byte; PAGE_SIZE];
+        let first_bytes = |pager: &Pager| {
+            (0..pager.page_count())
+                .map(|page_id| pager.read_page(page_id).unwrap()[0])
+                .collect::<Vec<_>>()
+        };
+        let mut pager = open();
+        for page_id in 0..3 {
+            pager.write_page(page_id, &page(page_id as u8)).unwrap();
+        }
+        pager.begin_journal(journal.clone(), true).unwrap();
+        pager.write_page(1, &page(10)).unwrap();
+        pager.write_page(1, &page(11)).unwrap();
+        pager.write_page(4, &page(14)).unwrap();
+        pager.truncate(1).unwrap();
+        pager.write_page(2, &page(12)).unwrap();
+        pager.rollback_journal().unwrap();
+        assert_eq!(first_bytes(&pager), vec![0, 1, 2]);
+        assert!(!journal.exists());
+
+        pager.begin_journal(journal.clone(), true).unwrap();
+        pager.write_page(0, &page(20)).unwrap();
+        pager.commit_journal().unwrap();
+        assert!(!journal.exists());
+        assert_eq!(first_bytes(&pager), vec![20, 1, 2]);
+
+        // A journal left behind by a crash is rolled back as the file is opened again
+        pager.begin_journal(journal.clone(), true).unwrap();
+        pager.write_page(2, &page(32)).unwrap();
+        pager.write_page(3, &page(33)).unwrap();
+        drop(pager);
+        let mut pager = open();
+        assert_eq!(first_bytes(&pager), vec![20, 1, 32, 33]);
+        assert!(pager.recover(&journal).unwrap());
+        assert_eq!(first_bytes(&pager), vec![20, 1, 2]);
+        assert!(!pager.recover(&journal).unwrap());
+        drop(pager);
+    }
+
+    #[test]
+    fn memory_mapped_reads() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("mmap");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        let mut pager = Pager::with_mode(file, ReadMode::Mmap).unwrap();
+        let page = |byte: u8| [byte; PAGE_SIZE];
+        for page_id in 0..20 {
+            pager.write_page(page_id, &page(page_id as u8)).unwrap();
+        }
+        // Pages both inside and past the mapping, and pages overwritten after being mapped
+        pager.write_page(3, &page(42)).unwrap();
+        assert!(pager
+            .mapping
+            .as_ref()
+            .is_some_and(|mapping| mapping.len() < 20 * PAGE_SIZE));
+        for page_id in 0..20 {
+            let expected = if page_id == 3 { 42 } else { page_id as u8 };
+            let read = pager.read_page(page_id).unwrap();
+            assert_eq!(read[..USABLE_SIZE], page(expected)[..USABLE_SIZE]);
+        }
+        pager.truncate(2).unwrap();
+        assert!(pager.read_page(2).is_err());
+        assert_eq!(pager.read_page(1).unwrap()[0], 1);
+
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[PAGE_SIZE + 100] ^= 0x10;
+        fs::write(&path, &bytes).unwrap();
+        let err = pager.read_page(1).unwrap_err();
+        let corrupt = err
+            .get_ref()
+            .and_then(|err| err.downcast_ref::<CorruptPage>());
+        assert_eq!(corrupt, Some(&CorruptPage(1)));
+        drop(pager);
+    }
+}
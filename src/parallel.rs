@@ -0,0 +1,69 @@
+//! Partitioning of work across threads, for executing queries in parallel. The work is split into
+//! contiguous chunks, one per thread, and the results are concatenated in the order of the chunks,
+//! so a parallel run produces exactly the results of a sequential one.
+
+use crate::storage_manager::StorageError;
+use std::panic;
+use std::thread;
+
+/// Least number of items worth handing to a thread of its own.
+pub const MIN_CHUNK: usize = 256;
+
+/// Applies `f` to contiguous chunks of `items` on up to `threads` threads, concatenating the
+/// results in order. Fails with the error of the first failing chunk. With a single thread, or
+/// too few items to split, `f` is applied to all items on the calling thread, as it always is in
+/// browsers, which have no threads.
+pub fn map_chunks<T, R, F>(items: &[T], threads: usize, f: F) -> Result<Vec<R>, StorageError>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&[T]) -> Result<Vec<R>, StorageError> + Sync,
+{
+    let browser = cfg!(all(target_arch = "wasm32", target_os = "unknown"));
+    if threads <= 1 || browser || items.len() < 2 * MIN_CHUNK {
+        return f(items);
+    }
+    let size = items.len().div_ceil(threads).max(MIN_CHUNK);
+    let f = &f;
+    let results: Vec<_> = thread::scope(|scope| {
+        let workers: Vec<_> = items
+            .chunks(size)
+            .map(|chunk| scope.spawn(move || f(chunk)))
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|err| panic::resume_unwind(err))
+            })
+            .collect()
+    });
+    let mut output = Vec::with_capacity(items.len());
+    for result in results {
+        output.extend(result?);
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_keep_order() {
+        let items: Vec<usize> = (0..10 * MIN_CHUNK).collect();
+        let double = |chunk: &[usize]| Ok(chunk.iter().map(|i| 2 * i).collect());
+        let sequential = map_chunks(&items, 1, double).ok();
+        let parallel = map_chunks(&items, 4, double).ok();
+        assert_eq!(parallel, sequential);
+        let failing = map_chunks(&items, 4, |chunk: &[usize]| {
+            if chunk.contains(&(5 * MIN_CHUNK)) {
+                Err(StorageError::TypeError)
+            } else {
+                Ok(chunk.to_vec())
+            }
+        });
+        assert!(matches!(failing, Err(StorageError::TypeError)));
+    }
+}
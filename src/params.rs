@@ -0,0 +1,142 @@
+//! Binding Rust values to the `?` placeholders of prepared statements: [`ToValue`] converts a
+//! value into a [`DBValue`], and [`params!`](crate::params!) converts a list of them, e.g.
+//!
+//! ```
+//! let mut db = juicydb::Database::new();
+//! db.execute("create table users (id integer, name text, email text);")?;
+//! let insert = db.prepare("insert into users values (?, ?, ?);")?;
+//! db.execute_prepared(&insert, &juicydb::params![1, "ada", None])?;
+//! # Ok::<(), juicydb::Error>(())
+//! ```
+
+use crate::db::DBValue;
+use crate::decimal::Decimal;
+use crate::uuid::Uuid;
+
+/// A Rust type that converts into a value of a column; the reverse of
+/// [`crate::from_row::FromValue`].
+pub trait ToValue {
+    fn to_value(&self) -> DBValue;
+}
+
+impl ToValue for DBValue {
+    fn to_value(&self) -> DBValue {
+        self.clone()
+    }
+}
+
+impl ToValue for i64 {
+    fn to_value(&self) -> DBValue {
+        DBValue::Integer(*self)
+    }
+}
+
+impl ToValue for i32 {
+    fn to_value(&self) -> DBValue {
+        DBValue::Integer(i64::from(*self))
+    }
+}
+
+impl ToValue for u32 {
+    fn to_value(&self) -> DBValue {
+        DBValue::Integer(i64::from(*self))
+    }
+}
+
+impl ToValue for f64 {
+    fn to_value(&self) -> DBValue {
+        DBValue::Real(*self)
+    }
+}
+
+impl ToValue for bool {
+    fn to_value(&self) -> DBValue {
+        DBValue::Boolean(*self)
+    }
+}
+
+impl ToValue for str {
+    fn to_value(&self) -> DBValue {
+        DBValue::Text(String::from(self))
+    }
+}
+
+impl ToValue for String {
+    fn to_value(&self) -> DBValue {
+        DBValue::Text(self.clone())
+    }
+}
+
+impl ToValue for Decimal {
+    fn to_value(&self) -> DBValue {
+        DBValue::Decimal(*self)
+    }
+}
+
+impl ToValue for Uuid {
+    fn to_value(&self) -> DBValue {
+        DBValue::Uuid(*self)
+    }
+}
+
+/// `None` converts to null.
+impl<T: ToValue> ToValue for Option<T> {
+    fn to_value(&self) -> DBValue {
+        match self {
+            Some(value) => value.to_value(),
+            None => DBValue::Null,
+        }
+    }
+}
+
+impl<T: ToValue + ?Sized> ToValue for &T {
+    fn to_value(&self) -> DBValue {
+        (**self).to_value()
+    }
+}
+
+/// The values converted by [`ToValue`], as a `Vec<DBValue>` to bind to the placeholders of a
+/// prepared statement, in order. A bare `None` is null, needing no type.
+#[macro_export]
+macro_rules! params {
+    (@values [$($values:expr),*]) => {{
+        let params: ::std::vec::Vec<$crate::db::DBValue> = ::std::vec![$($values),*];
+        params
+    }};
+    (@values [$($values:expr),*] None $(, $($rest:tt)*)?) => {
+        $crate::params!(@values [$($values,)* $crate::db::DBValue::Null] $($($rest)*)?)
+    };
+    (@values [$($values:expr),*] $value:expr $(, $($rest:tt)*)?) => {
+        $crate::params!(
+            @values [$($values,)* $crate::params::ToValue::to_value(&$value)] $($($rest)*)?
+        )
+    };
+    ($($params:tt)*) => {
+        $crate::params!(@values [] $($params)*)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn params() {
+        let name = String::from("bar");
+        let missing: Option<i64> = None;
+        let params = crate::params![1, "foo", &name, None, Some(2.5), missing, true,];
+        assert_eq!(
+            params,
+            vec![
+                DBValue::Integer(1),
+                DBValue::Text(String::from("foo")),
+                DBValue::Text(name),
+                DBValue::Null,
+                DBValue::Real(2.5),
+                DBValue::Null,
+                DBValue::Boolean(true),
+            ]
+        );
+        assert!(crate::params![].is_empty());
+    }
+}
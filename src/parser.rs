@@ -1,55 +1,746 @@
+use crate::copy::{CopyFormat, CopyOptions};
+use crate::datetime;
 use crate::db::*;
-use std::convert::TryInto;
+use crate::decimal::{Decimal, MAX_PRECISION};
+use crate::lexer::{tokenize, Token, TokenKind};
+use crate::transaction::IsolationLevel;
+use crate::uuid::Uuid;
+use std::convert::TryFrom;
 use std::fmt;
 
 /// Datatype representing an SQL-statement.
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Statement {
     Select {
-        columns: Vec<Identifier>,
+        /// The selected columns, or none for `select *`, which selects all columns of the
+        /// queried tables.
+        columns: Vec<Expression>,
         table: Identifier,
+        joins: Vec<Join>,
         condition: Option<Condition>,
+        group_by: Vec<Expression>,
+        order_by: Vec<(Expression, SortOrder)>,
+        limit: Option<Limit>,
     },
     CreateTable {
         table: Identifier,
         columns: Vec<(Identifier, DBType)>,
+        constraints: Vec<Constraint>,
     },
     InsertInto {
         table: Identifier,
-        values: Vec<DBValue>,
+        columns: Option<Vec<Identifier>>,
+        values: Vec<Expression>,
+    },
+    CreateView {
+        view: Identifier,
+        query: Box<Statement>,
+    },
+    DropView {
+        view: Identifier,
+    },
+    DropTable {
+        table: Identifier,
+    },
+    CreateIndex {
+        index: Identifier,
+        table: Identifier,
+        column: Identifier,
+        unique: bool,
+    },
+    Update {
+        table: Identifier,
+        assignments: Vec<(Identifier, Expression)>,
+        condition: Option<Condition>,
+    },
+    DeleteFrom {
+        table: Identifier,
+        condition: Option<Condition>,
+    },
+    CreateSequence {
+        sequence: Identifier,
+        start: i64,
+        increment: i64,
+    },
+    /// Makes a table from a module registered by the application, e.g.
+    /// `create virtual table ten using numbers(10);`.
+    CreateVirtualTable {
+        table: Identifier,
+        module: Identifier,
+        args: Vec<Expression>,
+    },
+    DropSequence {
+        sequence: Identifier,
+    },
+    Begin,
+    Commit,
+    Rollback {
+        savepoint: Option<Identifier>,
+    },
+    Savepoint {
+        savepoint: Identifier,
+    },
+    Release {
+        savepoint: Identifier,
+    },
+    /// Sets the isolation level of the transactions begun from then on.
+    SetTransaction {
+        isolation: IsolationLevel,
+    },
+    /// Collects statistics for the query planner, of the given table or of all tables.
+    Analyze {
+        table: Option<Identifier>,
+    },
+    /// Rewrites the files of the database compactly.
+    Vacuum,
+    /// Reads or changes a setting of the database, e.g. `pragma synchronous = off;`.
+    Pragma {
+        name: Identifier,
+        value: Option<Identifier>,
+    },
+    /// Loads the records of a file into a table, matched to the given columns or to all of them
+    /// in order, e.g. `copy users (id, name) from 'users.csv' with (format csv, header true);`.
+    Copy {
+        table: Identifier,
+        columns: Option<Vec<Identifier>>,
+        path: String,
+        options: CopyOptions,
     },
 }
 
 type Identifier = String;
 
+type ColumnDefinitions = (Vec<(Identifier, DBType)>, Vec<Constraint>);
+
+/// Constraint on the columns of a table, declared alongside the column definitions in a
+/// 'create table'-statement.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Constraint {
+    Unique(Identifier),
+    PrimaryKey(Identifier),
+    NotNull(Identifier),
+    AutoIncrement(Identifier),
+    Default(Identifier, Expression),
+    Collate(Identifier, Collation),
+    /// Restricts a text column to the given values, as in `check in ('new', 'done')` or
+    /// `enum('new', 'done')`.
+    Enum(Identifier, Vec<String>),
+    ForeignKey {
+        column: Identifier,
+        table: Identifier,
+        references: Identifier,
+        on_delete: ReferentialAction,
+    },
+}
+
 /// Condition in a 'where'-clause of certain SQL-statements. Essentially an
 /// AST representing different kinds of logical formulas one can get combining field selectors
 /// (table.column) and (in)equalities.
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Condition {
     Literal(ConditionLiteral),
+    /// Boolean-valued expression, e.g. a boolean column.
+    Expression(Expression),
     Not(Box<Condition>),
     And(Box<Condition>, Box<Condition>),
     Or(Box<Condition>, Box<Condition>),
 }
 
-/// Field selector, e.g. table.column
-#[derive(Debug, PartialEq)]
+/// Field selector, e.g. table.column. The table may be omitted when it is clear from the
+/// statement.
+#[derive(Clone, Debug, PartialEq)]
 pub struct Selector {
-    table: Identifier,
-    field: Identifier,
+    pub table: Option<Identifier>,
+    pub field: Identifier,
+}
+
+/// Scalar expression; a field selector, a constant value, a function call, e.g.
+/// `nextval('seq')`, a `?` placeholder for a value bound with [`Statement::bind`], a condition or
+/// arithmetic over other expressions. Used as values in 'insert'- and 'update'-statements, as
+/// selected columns and as either side of a [`ConditionLiteral`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expression {
+    Field(Selector),
+    Value(DBValue),
+    Function(Identifier, Vec<Expression>),
+    /// Placeholder numbered by its position among the placeholders of the statement.
+    Parameter(usize),
+    /// Boolean-valued condition, e.g. `a > b` in `select (a > b) from t;`.
+    Condition(Box<Condition>),
+    Arithmetic(ArithmeticOperator, Box<Expression>, Box<Expression>),
+    /// Expression compared under the given collation, e.g. `name collate nocase`, overriding
+    /// the collation of any column.
+    Collate(Box<Expression>, Collation),
+}
+
+/// Table joined to the tables before it in a 'select'-statement, e.g. `join b on a.id = b.a_id`.
+/// Only rows satisfying the condition are joined, as in an SQL inner join.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Join {
+    pub table: Identifier,
+    pub condition: Condition,
+}
+
+/// A 'limit'-clause, e.g. `limit 10 offset 20`: the number of rows to return, after skipping
+/// `offset` rows.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limit {
+    pub count: usize,
+    pub offset: usize,
+}
+
+/// Direction of a term in an 'order by'-clause.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SortOrder {
+    Ascending,
+    Descending,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArithmeticOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
 }
 
 /// 'Literal' in a [`Condition`] AST. Essentially some form of (in)equality
-/// over a database field selector.
-#[derive(Debug, PartialEq)]
+/// over database field selectors and values.
+#[derive(Clone, Debug, PartialEq)]
 pub enum ConditionLiteral {
-    Eq(Selector, Selector),
-    Neq(Selector, Selector),
-    Lt(Selector, Selector),
-    Lte(Selector, Selector),
-    Gt(Selector, Selector),
-    Gte(Selector, Selector),
+    Eq(Expression, Expression),
+    Neq(Expression, Expression),
+    Lt(Expression, Expression),
+    Lte(Expression, Expression),
+    Gt(Expression, Expression),
+    Gte(Expression, Expression),
+    /// Text matching a regular expression, e.g. `name regexp '^ju.*db$'`.
+    Regexp(Expression, Expression),
+}
+
+impl Statement {
+    /// Number of `?` placeholders in the statement.
+    pub fn parameter_count(&self) -> usize {
+        let mut count = 0;
+        self.clone().visit_expressions(&mut |expression| {
+            if let Expression::Parameter(i) = expression {
+                count = count.max(*i + 1);
+            }
+        });
+        count
+    }
+
+    /// Substitutes the values for the `?` placeholders of the statement, in order. Exactly one
+    /// value must be given per placeholder.
+    pub fn bind(&self, values: &[DBValue]) -> Result<Statement, ParseError> {
+        let expected = self.parameter_count();
+        if values.len() != expected {
+            return Err(ParseError::ParameterCount {
+                expected,
+                found: values.len(),
+            });
+        }
+        let mut stmt = self.clone();
+        stmt.visit_expressions(&mut |expression| {
+            if let Expression::Parameter(i) = expression {
+                *expression = Expression::Value(values[*i].clone());
+            }
+        });
+        Ok(stmt)
+    }
+
+    fn visit_expressions(&mut self, f: &mut dyn FnMut(&mut Expression)) {
+        match self {
+            Self::Select {
+                columns,
+                joins,
+                condition,
+                group_by,
+                order_by,
+                ..
+            } => {
+                let order_by = order_by.iter_mut().map(|(expression, _)| expression);
+                for expression in columns.iter_mut().chain(group_by).chain(order_by) {
+                    expression.visit_mut(f);
+                }
+                let joins = joins.iter_mut().map(|join| &mut join.condition);
+                for condition in joins.chain(condition) {
+                    condition.visit_expressions(f);
+                }
+            }
+            Self::DeleteFrom {
+                condition: Some(condition),
+                ..
+            } => condition.visit_expressions(f),
+            Self::Update {
+                assignments,
+                condition,
+                ..
+            } => {
+                for (_, expression) in assignments {
+                    expression.visit_mut(f);
+                }
+                if let Some(condition) = condition {
+                    condition.visit_expressions(f);
+                }
+            }
+            Self::InsertInto { values, .. } => {
+                for expression in values {
+                    expression.visit_mut(f);
+                }
+            }
+            Self::CreateTable { constraints, .. } => {
+                for constraint in constraints {
+                    if let Constraint::Default(_, expression) = constraint {
+                        expression.visit_mut(f);
+                    }
+                }
+            }
+            Self::CreateView { query, .. } => query.visit_expressions(f),
+            _ => (),
+        }
+    }
+}
+
+impl Condition {
+    fn visit_expressions(&mut self, f: &mut dyn FnMut(&mut Expression)) {
+        match self {
+            Self::Literal(literal) => {
+                let (lhs, rhs) = literal.operands_mut();
+                lhs.visit_mut(f);
+                rhs.visit_mut(f);
+            }
+            Self::Expression(expression) => expression.visit_mut(f),
+            Self::Not(condition) => condition.visit_expressions(f),
+            Self::And(lhs, rhs) | Self::Or(lhs, rhs) => {
+                lhs.visit_expressions(f);
+                rhs.visit_expressions(f);
+            }
+        }
+    }
+
+    fn into_expression(self) -> Expression {
+        match self {
+            Self::Expression(expression) => expression,
+            condition => Expression::Condition(Box::new(condition)),
+        }
+    }
+
+    /// Applies `f` to every expression in the condition and their subexpressions, outermost
+    /// first.
+    pub fn walk<'a>(&'a self, f: &mut dyn FnMut(&'a Expression)) {
+        match self {
+            Self::Literal(literal) => {
+                let (lhs, rhs) = literal.operands();
+                lhs.walk(f);
+                rhs.walk(f);
+            }
+            Self::Expression(expression) => expression.walk(f),
+            Self::Not(condition) => condition.walk(f),
+            Self::And(lhs, rhs) | Self::Or(lhs, rhs) => {
+                lhs.walk(f);
+                rhs.walk(f);
+            }
+        }
+    }
+
+    /// Field selectors referenced anywhere in the condition.
+    pub fn selectors(&self) -> Vec<&Selector> {
+        let mut selectors = Vec::new();
+        self.walk(&mut |expression| {
+            if let Expression::Field(selector) = expression {
+                selectors.push(selector);
+            }
+        });
+        selectors
+    }
+}
+
+impl ConditionLiteral {
+    pub fn operands(&self) -> (&Expression, &Expression) {
+        match self {
+            Self::Eq(lhs, rhs)
+            | Self::Neq(lhs, rhs)
+            | Self::Lt(lhs, rhs)
+            | Self::Lte(lhs, rhs)
+            | Self::Gt(lhs, rhs)
+            | Self::Gte(lhs, rhs)
+            | Self::Regexp(lhs, rhs) => (lhs, rhs),
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> (&mut Expression, &mut Expression) {
+        match self {
+            Self::Eq(lhs, rhs)
+            | Self::Neq(lhs, rhs)
+            | Self::Lt(lhs, rhs)
+            | Self::Lte(lhs, rhs)
+            | Self::Gt(lhs, rhs)
+            | Self::Gte(lhs, rhs)
+            | Self::Regexp(lhs, rhs) => (lhs, rhs),
+        }
+    }
+}
+
+impl Expression {
+    /// Applies `f` to the expression and all of its subexpressions, innermost first.
+    pub fn visit_mut(&mut self, f: &mut dyn FnMut(&mut Expression)) {
+        match self {
+            Self::Function(_, args) => {
+                for arg in args {
+                    arg.visit_mut(f);
+                }
+            }
+            Self::Condition(condition) => condition.visit_expressions(f),
+            Self::Arithmetic(_, lhs, rhs) => {
+                lhs.visit_mut(f);
+                rhs.visit_mut(f);
+            }
+            Self::Collate(expression, _) => expression.visit_mut(f),
+            _ => (),
+        }
+        f(self);
+    }
+
+    /// Views the expression as a condition; a non-condition is expected to be boolean-valued.
+    fn into_condition(self) -> Condition {
+        match self {
+            Self::Condition(condition) => *condition,
+            expression => Condition::Expression(expression),
+        }
+    }
+
+    /// Applies `f` to the expression and all of its subexpressions, outermost first.
+    pub fn walk<'a>(&'a self, f: &mut dyn FnMut(&'a Expression)) {
+        f(self);
+        match self {
+            Self::Function(_, args) => {
+                for arg in args {
+                    arg.walk(f);
+                }
+            }
+            Self::Condition(condition) => condition.walk(f),
+            Self::Arithmetic(_, lhs, rhs) => {
+                lhs.walk(f);
+                rhs.walk(f);
+            }
+            Self::Collate(expression, _) => expression.walk(f),
+            Self::Field(_) | Self::Value(_) | Self::Parameter(_) => (),
+        }
+    }
+
+    /// Field selectors referenced anywhere in the expression.
+    pub fn selectors(&self) -> Vec<&Selector> {
+        let mut selectors = Vec::new();
+        self.walk(&mut |expression| {
+            if let Self::Field(selector) = expression {
+                selectors.push(selector);
+            }
+        });
+        selectors
+    }
+}
+
+/// Writes the items separated by commas.
+fn write_list<T: fmt::Display>(f: &mut fmt::Formatter, items: &[T]) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+/// Renders a statement as SQL text that parses back into the same statement, without the
+/// terminating semicolon. Text literals containing quotes can't be written in SQL, and are
+/// rendered as is.
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Select {
+                columns,
+                table,
+                joins,
+                condition,
+                group_by,
+                order_by,
+                limit,
+            } => {
+                // The columns are parenthesized, so that a parenthesized first column isn't
+                // taken for the start of the list
+                if columns.is_empty() {
+                    write!(f, "select * from {}", table)?;
+                } else {
+                    write!(f, "select (")?;
+                    write_list(f, columns)?;
+                    write!(f, ") from {}", table)?;
+                }
+                for join in joins {
+                    write!(f, " join {} on {}", join.table, join.condition)?;
+                }
+                if let Some(condition) = condition {
+                    write!(f, " where {}", condition)?;
+                }
+                if !group_by.is_empty() {
+                    write!(f, " group by ")?;
+                    write_list(f, group_by)?;
+                }
+                for (i, (expression, order)) in order_by.iter().enumerate() {
+                    let order = match order {
+                        SortOrder::Ascending => "asc",
+                        SortOrder::Descending => "desc",
+                    };
+                    let clause = if i == 0 { " order by" } else { "," };
+                    write!(f, "{} {} {}", clause, expression, order)?;
+                }
+                if let Some(limit) = limit {
+                    write!(f, " limit {} offset {}", limit.count, limit.offset)?;
+                }
+                Ok(())
+            }
+            Self::CreateTable {
+                table,
+                columns,
+                constraints,
+            } => {
+                write!(f, "create table {} (", table)?;
+                for (i, (column, db_type)) in columns.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{} {}", column, db_type)?;
+                    for constraint in constraints {
+                        constraint.fmt_for(column, f)?;
+                    }
+                }
+                write!(f, ")")
+            }
+            Self::InsertInto {
+                table,
+                columns,
+                values,
+            } => {
+                write!(f, "insert into {} ", table)?;
+                if let Some(columns) = columns {
+                    write!(f, "(")?;
+                    write_list(f, columns)?;
+                    write!(f, ") ")?;
+                }
+                write!(f, "values (")?;
+                write_list(f, values)?;
+                write!(f, ")")
+            }
+            Self::CreateView { view, query } => write!(f, "create view {} as {}", view, query),
+            Self::DropView { view } => write!(f, "drop view {}", view),
+            Self::DropTable { table } => write!(f, "drop table {}", table),
+            Self::CreateIndex {
+                index,
+                table,
+                column,
+                unique,
+            } => {
+                let unique = if *unique { "unique " } else { "" };
+                write!(
+                    f,
+                    "create {}index {} on {} ({})",
+                    unique, index, table, column
+                )
+            }
+            Self::Update {
+                table,
+                assignments,
+                condition,
+            } => {
+                write!(f, "update {} set ", table)?;
+                for (i, (column, value)) in assignments.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{} = {}", column, value)?;
+                }
+                if let Some(condition) = condition {
+                    write!(f, " where {}", condition)?;
+                }
+                Ok(())
+            }
+            Self::DeleteFrom { table, condition } => {
+                write!(f, "delete from {}", table)?;
+                if let Some(condition) = condition {
+                    write!(f, " where {}", condition)?;
+                }
+                Ok(())
+            }
+            Self::CreateSequence {
+                sequence,
+                start,
+                increment,
+            } => write!(
+                f,
+                "create sequence {} start with {} increment by {}",
+                sequence, start, increment
+            ),
+            Self::CreateVirtualTable {
+                table,
+                module,
+                args,
+            } => {
+                write!(f, "create virtual table {} using {}(", table, module)?;
+                write_list(f, args)?;
+                write!(f, ")")
+            }
+            Self::DropSequence { sequence } => write!(f, "drop sequence {}", sequence),
+            Self::Begin => write!(f, "begin"),
+            Self::Commit => write!(f, "commit"),
+            Self::Rollback { savepoint: None } => write!(f, "rollback"),
+            Self::Rollback {
+                savepoint: Some(savepoint),
+            } => write!(f, "rollback to savepoint {}", savepoint),
+            Self::Savepoint { savepoint } => write!(f, "savepoint {}", savepoint),
+            Self::Release { savepoint } => write!(f, "release savepoint {}", savepoint),
+            Self::SetTransaction { isolation } => {
+                write!(f, "set transaction isolation level {}", isolation)
+            }
+            Self::Analyze { table: None } => write!(f, "analyze"),
+            Self::Analyze { table: Some(table) } => write!(f, "analyze {}", table),
+            Self::Vacuum => write!(f, "vacuum"),
+            Self::Pragma { name, value: None } => write!(f, "pragma {}", name),
+            Self::Pragma {
+                name,
+                value: Some(value),
+            } => write!(f, "pragma {} = {}", name, value),
+            Self::Copy {
+                table,
+                columns,
+                path,
+                options,
+            } => {
+                write!(f, "copy {} ", table)?;
+                if let Some(columns) = columns {
+                    write!(f, "(")?;
+                    write_list(f, columns)?;
+                    write!(f, ") ")?;
+                }
+                let delimiter = match options.delimiter {
+                    '\t' => String::from("\\t"),
+                    delimiter => delimiter.to_string(),
+                };
+                write!(
+                    f,
+                    "from '{}' with (format {}, header {}, delimiter '{}', null '{}')",
+                    path, options.format, options.header, delimiter, options.null
+                )
+            }
+        }
+    }
+}
+
+impl Constraint {
+    /// Writes the constraint as a clause of a column definition, if it constrains the column.
+    fn fmt_for(&self, column: &str, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unique(c) if c == column => write!(f, " unique"),
+            Self::PrimaryKey(c) if c == column => write!(f, " primary key"),
+            Self::NotNull(c) if c == column => write!(f, " not null"),
+            Self::AutoIncrement(c) if c == column => write!(f, " autoincrement"),
+            Self::Default(c, value) if c == column => write!(f, " default {}", value),
+            Self::Collate(c, collation) if c == column => write!(f, " collate {}", collation),
+            Self::Enum(c, labels) if c == column => {
+                write!(f, " check in (")?;
+                for (i, label) in labels.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "'{}'", label)?;
+                }
+                write!(f, ")")
+            }
+            Self::ForeignKey {
+                column: c,
+                table,
+                references,
+                on_delete,
+            } if c == column => {
+                let on_delete = match on_delete {
+                    ReferentialAction::Restrict => "restrict",
+                    ReferentialAction::Cascade => "cascade",
+                    ReferentialAction::SetNull => "set null",
+                };
+                write!(
+                    f,
+                    " references {} ({}) on delete {}",
+                    table, references, on_delete
+                )
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Renders conditions fully parenthesized, so that they parse back regardless of precedence.
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Literal(literal) => {
+                let operator = match literal {
+                    ConditionLiteral::Eq(..) => "=",
+                    ConditionLiteral::Neq(..) => "<>",
+                    ConditionLiteral::Lt(..) => "<",
+                    ConditionLiteral::Lte(..) => "<=",
+                    ConditionLiteral::Gt(..) => ">",
+                    ConditionLiteral::Gte(..) => ">=",
+                    ConditionLiteral::Regexp(..) => "regexp",
+                };
+                let (lhs, rhs) = literal.operands();
+                write!(f, "({} {} {})", lhs, operator, rhs)
+            }
+            Self::Expression(expression) => write!(f, "{}", expression),
+            Self::Not(condition) => write!(f, "(not {})", condition),
+            Self::And(lhs, rhs) => write!(f, "({} and {})", lhs, rhs),
+            Self::Or(lhs, rhs) => write!(f, "({} or {})", lhs, rhs),
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Field(Selector {
+                table: Some(table),
+                field,
+            }) => write!(f, "{}.{}", table, field),
+            Self::Field(Selector { table: None, field }) => write!(f, "{}", field),
+            Self::Value(value) => match value {
+                DBValue::Null => write!(f, "null"),
+                DBValue::Text(text) => write!(f, "'{}'", text),
+                DBValue::Date(_) => write!(f, "date '{}'", value),
+                DBValue::Time(_) => write!(f, "time '{}'", value),
+                DBValue::Timestamp(_) => write!(f, "timestamp '{}'", value),
+                DBValue::Decimal(_) => write!(f, "decimal '{}'", value),
+                DBValue::Uuid(_) => write!(f, "uuid '{}'", value),
+                _ => write!(f, "{}", value),
+            },
+            Self::Function(name, args) => {
+                write!(f, "{}(", name)?;
+                write_list(f, args)?;
+                write!(f, ")")
+            }
+            Self::Parameter(_) => write!(f, "?"),
+            Self::Condition(condition) => write!(f, "{}", condition),
+            Self::Arithmetic(operator, lhs, rhs) => {
+                let operator = match operator {
+                    ArithmeticOperator::Add => "+",
+                    ArithmeticOperator::Subtract => "-",
+                    ArithmeticOperator::Multiply => "*",
+                    ArithmeticOperator::Divide => "/",
+                };
+                write!(f, "({} {} {})", lhs, operator, rhs)
+            }
+            Self::Collate(expression, collation) => {
+                write!(f, "{} collate {}", expression, collation)
+            }
+        }
+    }
 }
 
 /// Datatype for meta-commands accepted by the juicydb REPL.
@@ -57,18 +748,82 @@ pub enum ConditionLiteral {
 pub enum MetaCommand {
     Exit,
     Print,
+    /// Values for the `?` placeholders of subsequent statements.
+    Bind(Vec<DBValue>),
+    Checkpoint,
+    /// Opens the database in the given file, e.g. `.open foo.db`, encrypted with the passphrase
+    /// if given, e.g. `.open foo.db key 'secret'`.
+    Open {
+        path: String,
+        key: Option<String>,
+    },
+    Save,
+    /// Shows the resources used by the database, such as pages read and the sizes of tables.
+    Stats,
+    /// Writes a copy of the database to the given file, e.g. `.backup foo.bak`.
+    Backup(String),
+    /// Executes the SQL script in the given file, e.g. `.read schema.sql`.
+    Read(String),
+    /// Sets how results are printed, e.g. `.mode csv`.
+    Mode(OutputMode),
+    /// Turns the header of column names on or off, e.g. `.headers off`.
+    Headers(bool),
+    /// Turns printing the time taken by each statement on or off, e.g. `.timer on`.
+    Timer(bool),
+    /// Sets the text nulls are printed as, e.g. `.nullvalue '(null)'`, or restores the default
+    /// of each mode if none is given.
+    NullValue(Option<String>),
+    /// Writes the rows of a table as JSON objects, one per line, to the given file or else to the
+    /// output, e.g. `.export json users users.json`.
+    Export {
+        table: String,
+        path: Option<String>,
+    },
+    /// Inserts the rows of a file of JSON objects, one per line, into a table, e.g.
+    /// `.import --json users.json users`.
+    Import {
+        path: String,
+        table: String,
+    },
+    /// Copies the tables of a SQLite database file, e.g. `.import --sqlite app.sqlite`.
+    ImportSqlite(String),
+    /// Pins the widths of the columns of tables, in characters, e.g. `.width 10 0 20`, with 0
+    /// for a column sized to fit. Clears the widths if none are given.
+    Width(Vec<usize>),
+    /// Shows the statements creating the given table or view, or all of them, e.g. `.schema t`.
+    Schema(Option<String>),
+    /// Lists the meta commands, or describes the given one, e.g. `.help mode`.
+    Help(Option<String>),
+}
+
+/// How the REPL prints the rows of results.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputMode {
+    /// Aligned columns in a box, under a header.
+    Table,
+    Csv,
+    /// Values separated by tabs.
+    Tsv,
+    /// An array of an object per row, keyed by column name.
+    Json,
+    /// Each value on a line of its own, after the name of its column.
+    Line,
 }
 
 /// A user-provided command to the juicydb REPL. Either a [`MetaCommand`] or an SQL-[`Statement`]
 #[derive(Debug, PartialEq)]
+#[allow(clippy::large_enum_variant)]
 pub enum Command {
     MetaCommand(MetaCommand),
     Statement(Statement),
 }
 
-/// Parser wrapper for string data
-pub struct Parser<'a> {
-    input: &'a str,
+/// Parser over the tokens of a command.
+pub struct Parser {
+    input: String,
+    tokens: Vec<Token>,
+    position: usize,
+    parameters: usize,
 }
 
 #[derive(Debug, PartialEq)]
@@ -87,6 +842,35 @@ pub enum ParseError {
     RunawayText,
     MissingFrom,
     MissingType,
+    MissingAs,
+    InvalidViewQuery,
+    MissingOn,
+    MissingUsing,
+    MissingSet,
+    MissingEquals,
+    MissingKey,
+    UnrecognizedReferentialAction,
+    MissingNull,
+    ParameterCount {
+        expected: usize,
+        found: usize,
+    },
+    IntegerOutOfRange,
+    MissingBy,
+    UnrecognizedCollation,
+    InvalidDateTime,
+    InvalidPrecision,
+    MissingIn,
+    MissingFileName,
+    UnrecognizedMode,
+    MissingOnOrOff,
+    InvalidWidth,
+    InvalidExport,
+    InvalidImport,
+    InvalidCopy,
+    InvalidCopyOption,
+    /// Input left over after the statement, where a single statement was expected.
+    MultipleStatements,
 }
 
 impl ParseError {
@@ -109,395 +893,2217 @@ impl fmt::Display for ParseError {
             Self::MissingLParen => write!(f, "Missing left parenthesis from column list"),
             Self::MissingRParen => write!(f, "Missing right parenthesis from column list"),
             Self::MissingComma => write!(f, "Missing comma from column list"),
-            Self::UnrecognizedMetaCommand => write!(f, "Unrecognized meta-command"),
+            Self::UnrecognizedMetaCommand => {
+                write!(f, "Unrecognized meta-command, enter .help for a list")
+            }
             Self::UnrecognizedStatement => write!(f, "Unrecognized SQL statement"),
             Self::UnrecognizedType => write!(f, "Unrecognized data type"),
             Self::RunawayText => write!(f, "No closing delimiter for text"),
             Self::InvalidValue => write!(f, "Invalid value"),
             Self::MissingFrom => write!(f, "Missing 'from' clause in 'select'-statement"),
             Self::MissingType => write!(f, "Missing type in column list"),
+            Self::MissingAs => write!(f, "Missing 'as' in 'create view'-statement"),
+            Self::InvalidViewQuery => write!(f, "View must be defined by a 'select'-statement"),
+            Self::MissingOn => write!(f, "Missing 'on' in 'create index'-statement or join"),
+            Self::MissingUsing => {
+                write!(f, "Missing 'using' in 'create virtual table'-statement")
+            }
+            Self::MissingSet => write!(f, "Missing 'set' in 'update'-statement"),
+            Self::MissingEquals => write!(f, "Missing '=' in assignment"),
+            Self::MissingKey => write!(f, "Missing 'key' in primary key constraint"),
+            Self::MissingNull => write!(f, "Missing 'null' in not null constraint"),
+            Self::IntegerOutOfRange => write!(f, "Integer literal out of range"),
+            Self::MissingBy => write!(f, "Missing 'by' in 'group by'- or 'order by'-clause"),
+            Self::UnrecognizedCollation => write!(f, "Unrecognized collation"),
+            Self::InvalidPrecision => write!(f, "Invalid precision or scale for decimal type"),
+            Self::InvalidDateTime => write!(f, "Invalid date, time or timestamp literal"),
+            Self::MissingIn => write!(f, "Missing 'in' in check constraint"),
+            Self::MissingFileName => write!(f, "Missing file name"),
+            Self::MissingOnOrOff => write!(f, "Expected 'on' or 'off'"),
+            Self::InvalidWidth => write!(f, "Expected widths as whole numbers, e.g. .width 10 0"),
+            Self::InvalidExport => write!(f, "Expected .export json TABLE [FILE]"),
+            Self::InvalidImport => {
+                write!(
+                    f,
+                    "Expected .import --json FILE TABLE or .import --sqlite FILE"
+                )
+            }
+            Self::InvalidCopy => write!(f, "Expected copy TABLE [(COLUMN, ...)] from 'FILE'"),
+            Self::InvalidCopyOption => write!(
+                f,
+                "Expected copy options format csv|text, header, delimiter 'C' or null 'TEXT'"
+            ),
+            Self::UnrecognizedMode => {
+                write!(
+                    f,
+                    "Unrecognized mode, expected table, csv, tsv, json or line"
+                )
+            }
+            Self::ParameterCount { expected, found } => {
+                write!(f, "Expected {} bound parameters, found {}", expected, found)
+            }
+            Self::UnrecognizedReferentialAction => {
+                write!(f, "Unrecognized action in 'on delete'-clause")
+            }
+            Self::MultipleStatements => write!(
+                f,
+                "Input left over after the statement, use execute_batch for several statements"
+            ),
         }
     }
 }
 
-fn char_to_i64(input: char) -> i64 {
-    match input {
-        '0' => 0,
-        '1' => 1,
-        '2' => 2,
-        '3' => 3,
-        '4' => 4,
-        '5' => 5,
-        '6' => 6,
-        '7' => 7,
-        '8' => 8,
-        '9' => 9,
-        _ => panic!("Not a valid digit"),
+type ParseResult<T> = Result<T, ParseError>;
+
+/// Parses the digits of an integer literal, negated if `negative`. Fails if the literal doesn't
+/// fit in an `i64`.
+fn parse_integer(digits: &str, negative: bool) -> ParseResult<i64> {
+    let mut val: i64 = 0;
+    for c in digits.chars() {
+        let digit = i64::from(c.to_digit(10).ok_or(ParseError::InvalidValue)?);
+        val = val
+            .checked_mul(10)
+            .and_then(|val| {
+                if negative {
+                    val.checked_sub(digit)
+                } else {
+                    val.checked_add(digit)
+                }
+            })
+            .ok_or(ParseError::IntegerOutOfRange)?;
     }
+    Ok(val)
 }
 
-fn str_to_i64(input: &str) -> i64 {
-    let mut val = 0;
-    for (i, c) in input.chars().rev().enumerate() {
-        if c.is_ascii_digit() {
-            val += char_to_i64(c) * 10_i64.pow(i.try_into().unwrap());
-        } else if c == '-' && i == input.len() - 1 {
-            val = -val;
-        } else {
-            panic!("Not a valid integer");
+fn parse_real(input: &str) -> ParseResult<f64> {
+    input.parse().map_err(|_| ParseError::InvalidValue)
+}
+
+/// Binary operator in an expression, together with how to build the resulting AST node.
+#[derive(Clone, Copy)]
+enum BinaryOperator {
+    Connective(fn(Box<Condition>, Box<Condition>) -> Condition),
+    Comparison(fn(Expression, Expression) -> ConditionLiteral),
+    Arithmetic(ArithmeticOperator),
+}
+
+impl BinaryOperator {
+    fn apply(self, lhs: Expression, rhs: Expression) -> Expression {
+        match self {
+            Self::Connective(connective) => connective(
+                Box::new(lhs.into_condition()),
+                Box::new(rhs.into_condition()),
+            )
+            .into_expression(),
+            Self::Comparison(literal) => Condition::Literal(literal(lhs, rhs)).into_expression(),
+            Self::Arithmetic(operator) => {
+                Expression::Arithmetic(operator, Box::new(lhs), Box::new(rhs))
+            }
         }
     }
-    val
 }
 
-type ParseResult<T> = Result<T, ParseError>;
+/// Binary operators with their precedences; higher binds tighter. Longer symbols come before
+/// their prefixes.
+const BINARY_OPERATORS: [(&str, u8, BinaryOperator); 14] = [
+    ("or", 1, BinaryOperator::Connective(Condition::Or)),
+    ("and", 2, BinaryOperator::Connective(Condition::And)),
+    ("<=", 4, BinaryOperator::Comparison(ConditionLiteral::Lte)),
+    (">=", 4, BinaryOperator::Comparison(ConditionLiteral::Gte)),
+    ("<>", 4, BinaryOperator::Comparison(ConditionLiteral::Neq)),
+    ("!=", 4, BinaryOperator::Comparison(ConditionLiteral::Neq)),
+    ("<", 4, BinaryOperator::Comparison(ConditionLiteral::Lt)),
+    (">", 4, BinaryOperator::Comparison(ConditionLiteral::Gt)),
+    ("=", 4, BinaryOperator::Comparison(ConditionLiteral::Eq)),
+    (
+        "regexp",
+        4,
+        BinaryOperator::Comparison(ConditionLiteral::Regexp),
+    ),
+    ("+", 5, BinaryOperator::Arithmetic(ArithmeticOperator::Add)),
+    (
+        "-",
+        5,
+        BinaryOperator::Arithmetic(ArithmeticOperator::Subtract),
+    ),
+    (
+        "*",
+        6,
+        BinaryOperator::Arithmetic(ArithmeticOperator::Multiply),
+    ),
+    (
+        "/",
+        6,
+        BinaryOperator::Arithmetic(ArithmeticOperator::Divide),
+    ),
+];
 
-impl<'a> Parser<'a> {
-    pub fn new(input: &'a str) -> Self {
-        Self { input }
+/// Precedence of the prefix 'not'; binds looser than comparisons but tighter than 'and'.
+const NOT_PRECEDENCE: u8 = 3;
+
+impl Parser {
+    pub fn new(input: &str) -> Self {
+        Self {
+            input: String::from(input),
+            tokens: tokenize(input),
+            position: 0,
+            parameters: 0,
+        }
     }
 
-    fn skip_whitespace(&mut self) {
-        let count = self.input.chars().take_while(|c| c.is_whitespace()).count();
-        let (_, input) = self.input.split_at(count);
-        self.input = input;
+    /// Whether only whitespace and comments remain in the input.
+    pub fn at_end(&self) -> bool {
+        self.position >= self.tokens.len()
     }
 
-    fn lex_string(&mut self, string: &str) -> ParseResult<()> {
-        self.skip_whitespace();
-        if self.input.starts_with(string) {
-            let (_, input) = self.input.split_at(string.len());
-            self.input = input;
-            Ok(())
-        } else {
-            if self.input.is_empty() {
-                Err(ParseError::EndOfInput)
-            } else {
-                Err(ParseError::FailedToLex)
-            }
-        }
+    /// Byte offset of the next token in the input, or the length of the input at the end, e.g.
+    /// to slice out the text of the statement parsed next.
+    pub fn offset(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .map_or(self.input.len(), |token| token.span.start)
     }
 
-    fn lex_identifier(&mut self) -> ParseResult<Identifier> {
-        self.skip_whitespace();
-        let mut chars = self.input.chars();
-        if let Some(c) = chars.nth(0) {
-            if c.is_ascii_alphabetic() {
-                let count = 1 + chars
-                    .take_while(|&c| c.is_ascii_alphanumeric() || c == '_')
-                    .count();
-                let (parsed, input) = self.input.split_at(count);
-                self.input = input;
-                Ok(String::from(parsed))
-            } else {
-                Err(ParseError::InvalidIdentifier)
-            }
-        } else {
-            Err(ParseError::EndOfInput)
+    /// Whether the input is complete as a command: a meta command, which takes a single line, or
+    /// statements ending in a semicolon, not counting semicolons in text or comments. Lets the
+    /// REPL read the lines of a statement until it ends.
+    pub fn is_complete(&self) -> bool {
+        match self.tokens.first() {
+            Some(first) if first.kind.matches(".") => true,
+            _ => self.tokens.last().is_none_or(|last| last.kind.matches(";")),
         }
     }
 
-    fn parse_text(&mut self) -> ParseResult<String> {
-        let mut chars = self.input.chars();
-        if let Some(c) = chars.nth(0) {
-            if c == '\'' {
-                let count = 1 + chars.take_while(|&c| c != '\'').count();
-                if let Some('\'') = self.input.chars().nth(count) {
-                    let (parsed, input) = self.input.split_at(count + 1);
-                    self.input = input;
-                    Ok(String::from(&parsed[1..count]))
-                } else {
-                    Err(ParseError::RunawayText)
-                }
-            } else {
-                Err(ParseError::FailedToLex)
+    /// Skips the tokens up to and including the next semicolon, e.g. past a statement that
+    /// failed to parse.
+    pub fn skip_statement(&mut self) {
+        while !self.at_end() {
+            let semicolon = self.next_is(";");
+            self.position += 1;
+            if semicolon {
+                break;
             }
-        } else {
-            Err(ParseError::EndOfInput)
         }
     }
 
-    fn parse_positive_integer(&mut self) -> ParseResult<i64> {
-        let count = self
-            .input
-            .chars()
-            .take_while(|&c| c.is_ascii_digit())
-            .count();
-        if count > 0 {
-            let (parsed, input) = self.input.split_at(count);
-            self.input = input;
-            Ok(str_to_i64(parsed))
-        } else {
-            Err(ParseError::FailedToLex)
-        }
+    fn peek_nth(&self, n: usize) -> Option<&TokenKind> {
+        self.tokens.get(self.position + n).map(|token| &token.kind)
     }
 
-    fn parse_negative_integer(&mut self) -> ParseResult<i64> {
-        let mut chars = self.input.chars();
-        if let Some(c) = chars.nth(0) {
-            if c == '-' {
-                let count = chars.take_while(|&c| c.is_ascii_digit()).count();
-                if count > 0 {
-                    let (parsed, input) = self.input.split_at(count + 1);
-                    self.input = input;
-                    Ok(str_to_i64(parsed))
-                } else {
-                    Err(ParseError::FailedToLex)
-                }
-            } else {
-                Err(ParseError::FailedToLex)
+    fn next_is(&self, token: &str) -> bool {
+        self.peek_nth(0).is_some_and(|kind| kind.matches(token))
+    }
+
+    /// Consumes the next token if it is the given keyword or punctuation.
+    fn lex_token(&mut self, token: &str) -> ParseResult<()> {
+        match self.peek_nth(0) {
+            Some(kind) if kind.matches(token) => {
+                self.position += 1;
+                Ok(())
             }
-        } else {
-            Err(ParseError::EndOfInput)
+            Some(_) => Err(ParseError::FailedToLex),
+            None => Err(ParseError::EndOfInput),
         }
     }
 
-    fn parse_integer(&mut self) -> ParseResult<i64> {
-        self.parse_positive_integer()
-            .or_else(|_| self.parse_negative_integer())
+    fn lex_identifier(&mut self) -> ParseResult<Identifier> {
+        match self.peek_nth(0) {
+            Some(TokenKind::Word(word)) => {
+                let ident = word.clone();
+                self.position += 1;
+                Ok(ident)
+            }
+            Some(_) => Err(ParseError::InvalidIdentifier),
+            None => Err(ParseError::EndOfInput),
+        }
     }
 
     pub fn parse_command(&mut self) -> ParseResult<Command> {
         self.parse_meta_command()
-            .map(|cmd| Command::MetaCommand(cmd))
+            .map(Command::MetaCommand)
             .or_else(|e| {
                 e.ignore_fail()?;
-                self.parse_statement().map(|stmt| Command::Statement(stmt))
+                self.parse_statement().map(Command::Statement)
             })
     }
 
     fn parse_meta_command(&mut self) -> ParseResult<MetaCommand> {
-        self.lex_string(".")?;
-        self.lex_string("exit")
-            .map(|_| MetaCommand::Exit)
-            .or_else(|e| {
-                e.ignore_fail()?;
-                self.lex_string("print").map(|_| MetaCommand::Print)
-            })
-            .map_err(|_| ParseError::UnrecognizedMetaCommand)
+        self.lex_token(".")?;
+        if self.lex_token("exit").is_ok() {
+            Ok(MetaCommand::Exit)
+        } else if self.lex_token("print").is_ok() {
+            Ok(MetaCommand::Print)
+        } else if self.lex_token("bind").is_ok() {
+            self.parse_bindings().map(MetaCommand::Bind)
+        } else if self.lex_token("checkpoint").is_ok() {
+            Ok(MetaCommand::Checkpoint)
+        } else if self.lex_token("open").is_ok() {
+            self.parse_open()
+        } else if self.lex_token("save").is_ok() {
+            Ok(MetaCommand::Save)
+        } else if self.lex_token("stats").is_ok() {
+            Ok(MetaCommand::Stats)
+        } else if self.lex_token("backup").is_ok() {
+            self.parse_file_name().map(MetaCommand::Backup)
+        } else if self.lex_token("read").is_ok() {
+            self.parse_file_name().map(MetaCommand::Read)
+        } else if self.lex_token("mode").is_ok() {
+            self.parse_mode().map(MetaCommand::Mode)
+        } else if self.lex_token("headers").is_ok() {
+            self.parse_on_off().map(MetaCommand::Headers)
+        } else if self.lex_token("timer").is_ok() {
+            self.parse_on_off().map(MetaCommand::Timer)
+        } else if self.lex_token("nullvalue").is_ok() {
+            Ok(MetaCommand::NullValue(self.parse_rest()))
+        } else if self.lex_token("export").is_ok() {
+            self.parse_export()
+        } else if self.lex_token("import").is_ok() {
+            self.parse_import()
+        } else if self.lex_token("width").is_ok() {
+            self.parse_widths().map(MetaCommand::Width)
+        } else if self.lex_token("schema").is_ok() {
+            self.parse_schema()
+        } else if self.lex_token("help").is_ok() {
+            self.parse_help()
+        } else {
+            Err(ParseError::UnrecognizedMetaCommand)
+        }
     }
 
-    fn parse_semicolon(&mut self) -> ParseResult<()> {
-        self.lex_string(";")
-            .map(|_| ())
-            .map_err(|_| ParseError::MissingSemicolon)
+    fn parse_mode(&mut self) -> ParseResult<OutputMode> {
+        let modes = [
+            ("table", OutputMode::Table),
+            ("csv", OutputMode::Csv),
+            ("tsv", OutputMode::Tsv),
+            ("json", OutputMode::Json),
+            ("line", OutputMode::Line),
+        ];
+        let mode = modes
+            .iter()
+            .find(|(name, _)| self.lex_token(name).is_ok())
+            .map(|(_, mode)| *mode)
+            .ok_or(ParseError::UnrecognizedMode)?;
+        match self.at_end() {
+            true => Ok(mode),
+            false => Err(ParseError::UnrecognizedMode),
+        }
     }
 
-    fn parse_statement(&mut self) -> ParseResult<Statement> {
-        let stmt = self
-            .parse_select()
-            .or_else(|e| {
-                e.ignore_fail()?;
-                self.parse_create_table()
-            })
-            .or_else(|e| {
-                e.ignore_fail()?;
-                self.parse_insert_into()
-            })
-            .or_else(|e| {
-                e.ignore_fail()?;
-                Err(ParseError::UnrecognizedStatement)
-            })?;
-        self.parse_semicolon()?;
-        Ok(stmt)
+    /// Parses the optional name of a table or view after `.schema`.
+    fn parse_schema(&mut self) -> ParseResult<MetaCommand> {
+        if self.at_end() {
+            return Ok(MetaCommand::Schema(None));
+        }
+        let name = self.lex_identifier()?;
+        match self.at_end() {
+            true => Ok(MetaCommand::Schema(Some(name))),
+            false => Err(ParseError::InvalidIdentifier),
+        }
+    }
+
+    /// Parses the optional name of a meta command after `.help`, with or without its dot.
+    fn parse_help(&mut self) -> ParseResult<MetaCommand> {
+        let _ = self.lex_token(".");
+        if self.at_end() {
+            return Ok(MetaCommand::Help(None));
+        }
+        let name = self.lex_identifier()?;
+        match self.at_end() {
+            true => Ok(MetaCommand::Help(Some(name.to_lowercase()))),
+            false => Err(ParseError::InvalidIdentifier),
+        }
+    }
+
+    /// Parses the format, the table and the optional file after `.export`.
+    fn parse_export(&mut self) -> ParseResult<MetaCommand> {
+        self.lex_token("json")
+            .map_err(|_| ParseError::InvalidExport)?;
+        let table = self
+            .lex_identifier()
+            .map_err(|_| ParseError::InvalidExport)?;
+        let path = self.parse_rest();
+        Ok(MetaCommand::Export { table, path })
+    }
+
+    /// Parses `--json`, the file and the table, or `--sqlite` and the file, after `.import`.
+    /// They are read from the input as it is, as `--` would start a comment for the lexer.
+    fn parse_import(&mut self) -> ParseResult<MetaCommand> {
+        let start = self.tokens[self.position - 1].span.end;
+        self.position = self.tokens.len();
+        match self.input[start..].split_whitespace().collect::<Vec<_>>()[..] {
+            ["--json", path, table] => Ok(MetaCommand::Import {
+                path: String::from(path),
+                table: String::from(table),
+            }),
+            ["--sqlite", path] => Ok(MetaCommand::ImportSqlite(String::from(path))),
+            _ => Err(ParseError::InvalidImport),
+        }
+    }
+
+    /// Parses the widths of columns after `.width`, as whole numbers.
+    fn parse_widths(&mut self) -> ParseResult<Vec<usize>> {
+        let mut widths = Vec::new();
+        while let Some(token) = self.peek_nth(0) {
+            let width = match token {
+                TokenKind::Integer(digits) => digits.parse().ok(),
+                _ => None,
+            };
+            widths.push(width.ok_or(ParseError::InvalidWidth)?);
+            self.position += 1;
+        }
+        Ok(widths)
+    }
+
+    /// Parses `on` or `off` as the only argument of a meta command.
+    fn parse_on_off(&mut self) -> ParseResult<bool> {
+        let on = if self.lex_token("on").is_ok() {
+            true
+        } else if self.lex_token("off").is_ok() {
+            false
+        } else {
+            return Err(ParseError::MissingOnOrOff);
+        };
+        match self.at_end() {
+            true => Ok(on),
+            false => Err(ParseError::MissingOnOrOff),
+        }
+    }
+
+    /// Parses the file name of `.open`, optionally followed by `key` and a quoted passphrase.
+    fn parse_open(&mut self) -> ParseResult<MetaCommand> {
+        let count = self.tokens.len();
+        let mut key = None;
+        if count >= self.position + 3 && self.tokens[count - 2].kind.matches("key") {
+            if let TokenKind::Text(passphrase) = &self.tokens[count - 1].kind {
+                key = Some(passphrase.clone());
+                self.tokens.truncate(count - 2);
+            }
+        }
+        let path = self.parse_file_name()?;
+        Ok(MetaCommand::Open { path, key })
+    }
+
+    /// Parses the rest of the input as a file name, either quoted or as is, e.g. `foo.db`.
+    fn parse_file_name(&mut self) -> ParseResult<String> {
+        self.parse_rest().ok_or(ParseError::MissingFileName)
+    }
+
+    /// Parses the rest of the input as the argument of a meta command: the text of a single
+    /// quoted string, or else the input as it is, or `None` at the end.
+    fn parse_rest(&mut self) -> Option<String> {
+        let start = self.tokens.get(self.position)?;
+        let end = self.tokens[self.tokens.len() - 1].span.end;
+        let text = match &start.kind {
+            TokenKind::Text(text) if self.position + 1 == self.tokens.len() => text.clone(),
+            _ => String::from(&self.input[start.span.start..end]),
+        };
+        self.position = self.tokens.len();
+        Some(text)
+    }
+
+    fn parse_bindings(&mut self) -> ParseResult<Vec<DBValue>> {
+        let mut values = Vec::new();
+        while !self.at_end() {
+            if !values.is_empty() {
+                self.lex_token(",").map_err(|_| ParseError::MissingComma)?;
+            }
+            let value = self.lex_value().map_err(|e| {
+                if let ParseError::FailedToLex = e {
+                    ParseError::InvalidValue
+                } else {
+                    e
+                }
+            })?;
+            values.push(value);
+        }
+        Ok(values)
+    }
+
+    fn parse_semicolon(&mut self) -> ParseResult<()> {
+        self.lex_token(";")
+            .map(|_| ())
+            .map_err(|_| ParseError::MissingSemicolon)
+    }
+
+    fn parse_statement(&mut self) -> ParseResult<Statement> {
+        let stmt = self
+            .parse_select()
+            .or_else(|e| {
+                e.ignore_fail()?;
+                self.parse_create()
+            })
+            .or_else(|e| {
+                e.ignore_fail()?;
+                self.parse_insert_into()
+            })
+            .or_else(|e| {
+                e.ignore_fail()?;
+                self.parse_drop()
+            })
+            .or_else(|e| {
+                e.ignore_fail()?;
+                self.parse_update()
+            })
+            .or_else(|e| {
+                e.ignore_fail()?;
+                self.parse_delete_from()
+            })
+            .or_else(|e| {
+                e.ignore_fail()?;
+                self.parse_transaction_control()
+            })
+            .or_else(|e| {
+                e.ignore_fail()?;
+                self.parse_analyze()
+            })
+            .or_else(|e| {
+                e.ignore_fail()?;
+                self.parse_pragma()
+            })
+            .or_else(|e| {
+                e.ignore_fail()?;
+                self.parse_vacuum()
+            })
+            .or_else(|e| {
+                e.ignore_fail()?;
+                self.parse_copy()
+            })
+            .or_else(|e| {
+                e.ignore_fail()?;
+                Err(ParseError::UnrecognizedStatement)
+            })?;
+        self.parse_semicolon()?;
+        Ok(stmt)
     }
 
     fn parse_select(&mut self) -> ParseResult<Statement> {
-        self.lex_string("select")?;
-        let columns = self.parse_columns()?;
-        self.lex_string("from")
+        self.lex_token("select")?;
+        // The selected columns may be parenthesized, as in `select (a, b) from t;`, or all of
+        // them selected with `select *`
+        let columns = if self.lex_token("*").is_ok() {
+            Vec::new()
+        } else if self.lex_token("(").is_ok() {
+            let columns = self.parse_expression_list()?;
+            self.parse_right_paren()?;
+            columns
+        } else {
+            self.parse_expression_list()?
+        };
+        self.lex_token("from")
             .map_err(|_| ParseError::MissingFrom)?;
+        let table = self.parse_table_name()?;
+        let joins = self.parse_joins()?;
+        let condition = self.parse_where()?;
+        let group_by = if self.lex_token("group").is_ok() {
+            self.lex_token("by").map_err(|_| ParseError::MissingBy)?;
+            self.parse_expression_list()?
+        } else {
+            Vec::new()
+        };
+        let order_by = if self.lex_token("order").is_ok() {
+            self.lex_token("by").map_err(|_| ParseError::MissingBy)?;
+            self.parse_ordering_terms()?
+        } else {
+            Vec::new()
+        };
+        let limit = self.parse_limit()?;
+        Ok(Statement::Select {
+            columns,
+            table,
+            joins,
+            condition,
+            group_by,
+            order_by,
+            limit,
+        })
+    }
+
+    /// Parses any number of `join <table> on <condition>` clauses, optionally written as 'inner
+    /// join'.
+    fn parse_joins(&mut self) -> ParseResult<Vec<Join>> {
+        let mut joins = Vec::new();
+        loop {
+            let inner = self.lex_token("inner").is_ok();
+            if self.lex_token("join").is_err() {
+                if inner {
+                    return Err(ParseError::UnrecognizedStatement);
+                }
+                return Ok(joins);
+            }
+            let table = self.parse_table_name()?;
+            self.lex_token("on").map_err(|_| ParseError::MissingOn)?;
+            let condition = self.parse_condition()?;
+            joins.push(Join { table, condition });
+        }
+    }
+
+    /// Parses the name of a queried table, optionally qualified by its schema, as in
+    /// `information_schema.tables`.
+    fn parse_table_name(&mut self) -> ParseResult<Identifier> {
+        let name = self.lex_identifier()?;
+        if self.next_is(".") {
+            self.lex_token(".")?;
+            let table = self.lex_identifier()?;
+            return Ok(format!("{}.{}", name, table));
+        }
+        Ok(name)
+    }
+
+    /// Parses an optional 'limit'-clause with an optional offset.
+    fn parse_limit(&mut self) -> ParseResult<Option<Limit>> {
+        if self.lex_token("limit").is_err() {
+            return Ok(None);
+        }
+        let count = self.parse_count()?;
+        let offset = if self.lex_token("offset").is_ok() {
+            self.parse_count()?
+        } else {
+            0
+        };
+        Ok(Some(Limit { count, offset }))
+    }
+
+    /// Parses a non-negative integer literal.
+    fn parse_count(&mut self) -> ParseResult<usize> {
+        usize::try_from(self.parse_integer_value()?).map_err(|_| ParseError::InvalidValue)
+    }
+
+    /// Parses the terms of an 'order by'-clause, each optionally followed by 'asc' or 'desc'.
+    fn parse_ordering_terms(&mut self) -> ParseResult<Vec<(Expression, SortOrder)>> {
+        let mut terms = Vec::new();
+        loop {
+            let expression = self.parse_expression()?;
+            let order = if self.lex_token("desc").is_ok() {
+                SortOrder::Descending
+            } else {
+                self.lex_token("asc").ok();
+                SortOrder::Ascending
+            };
+            terms.push((expression, order));
+            if self.lex_token(",").is_err() {
+                return Ok(terms);
+            }
+        }
+    }
+
+    fn parse_expression_list(&mut self) -> ParseResult<Vec<Expression>> {
+        let mut columns = vec![self.parse_expression()?];
+        while self.lex_token(",").is_ok() {
+            columns.push(self.parse_expression()?);
+        }
+        Ok(columns)
+    }
+
+    fn parse_left_paren(&mut self) -> ParseResult<()> {
+        self.lex_token("(").map_err(|_| ParseError::MissingLParen)
+    }
+
+    fn parse_right_paren(&mut self) -> ParseResult<()> {
+        self.lex_token(")").map_err(|_| {
+            if self.lex_identifier().is_ok() {
+                ParseError::MissingComma
+            } else {
+                ParseError::MissingRParen
+            }
+        })
+    }
+
+    fn parse_columns(&mut self) -> ParseResult<Vec<Identifier>> {
+        self.parse_left_paren()?;
+        let ident = self.lex_identifier()?;
+        let mut columns = vec![ident];
+        while self.lex_token(",").is_ok() {
+            let ident = self.lex_identifier()?;
+            columns.push(ident);
+        }
+        self.parse_right_paren()?;
+        Ok(columns)
+    }
+
+    fn parse_column_definitions(&mut self) -> ParseResult<ColumnDefinitions> {
+        self.parse_left_paren()?;
+        let mut columns = Vec::new();
+        let mut constraints = Vec::new();
+        loop {
+            let ident = self.lex_identifier()?;
+            let db_type = if self.lex_token("enum").is_ok() {
+                let labels = self.parse_text_list()?;
+                constraints.push(Constraint::Enum(ident.clone(), labels));
+                DBType::Text
+            } else {
+                self.parse_db_type()?
+            };
+            self.parse_column_constraints(&ident, &mut constraints)?;
+            columns.push((ident, db_type));
+            if self.lex_token(",").is_err() {
+                break;
+            }
+        }
+        self.parse_right_paren()?;
+        Ok((columns, constraints))
+    }
+
+    fn parse_column_constraints(
+        &mut self,
+        column: &str,
+        constraints: &mut Vec<Constraint>,
+    ) -> ParseResult<()> {
+        loop {
+            if self.lex_token("unique").is_ok() {
+                constraints.push(Constraint::Unique(String::from(column)));
+            } else if self.lex_token("primary").is_ok() {
+                self.lex_token("key").map_err(|_| ParseError::MissingKey)?;
+                constraints.push(Constraint::PrimaryKey(String::from(column)));
+            } else if self.lex_token("default").is_ok() {
+                let value = self.parse_value_expression().map_err(|e| {
+                    if let ParseError::FailedToLex = e {
+                        ParseError::InvalidValue
+                    } else {
+                        e
+                    }
+                })?;
+                constraints.push(Constraint::Default(String::from(column), value));
+            } else if self.lex_token("collate").is_ok() {
+                let collation = self.parse_collation()?;
+                constraints.push(Constraint::Collate(String::from(column), collation));
+            } else if self.lex_token("check").is_ok() {
+                self.lex_token("in").map_err(|_| ParseError::MissingIn)?;
+                let labels = self.parse_text_list()?;
+                constraints.push(Constraint::Enum(String::from(column), labels));
+            } else if self.lex_token("autoincrement").is_ok() {
+                constraints.push(Constraint::AutoIncrement(String::from(column)));
+            } else if self.lex_token("not").is_ok() {
+                self.lex_token("null")
+                    .map_err(|_| ParseError::MissingNull)?;
+                constraints.push(Constraint::NotNull(String::from(column)));
+            } else if self.lex_token("references").is_ok() {
+                let table = self.lex_identifier()?;
+                self.parse_left_paren()?;
+                let references = self.lex_identifier()?;
+                self.parse_right_paren()?;
+                let on_delete = self.parse_on_delete()?;
+                constraints.push(Constraint::ForeignKey {
+                    column: String::from(column),
+                    table,
+                    references,
+                    on_delete,
+                });
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Parses a parenthesized, non-empty list of text literals, e.g. `('new', 'done')`.
+    fn parse_text_list(&mut self) -> ParseResult<Vec<String>> {
+        self.parse_left_paren()?;
+        let mut values = Vec::new();
+        loop {
+            match self.lex_value()? {
+                DBValue::Text(text) => values.push(text),
+                _ => return Err(ParseError::InvalidValue),
+            }
+            if self.lex_token(",").is_err() {
+                break;
+            }
+        }
+        self.parse_right_paren()?;
+        Ok(values)
+    }
+
+    fn parse_on_delete(&mut self) -> ParseResult<ReferentialAction> {
+        if self.lex_token("on").is_err() {
+            return Ok(ReferentialAction::Restrict);
+        }
+        self.lex_token("delete")
+            .map_err(|_| ParseError::UnrecognizedReferentialAction)?;
+        if self.lex_token("cascade").is_ok() {
+            Ok(ReferentialAction::Cascade)
+        } else if self.lex_token("set").is_ok() && self.lex_token("null").is_ok() {
+            Ok(ReferentialAction::SetNull)
+        } else if self.lex_token("restrict").is_ok()
+            || (self.lex_token("no").is_ok() && self.lex_token("action").is_ok())
+        {
+            Ok(ReferentialAction::Restrict)
+        } else {
+            Err(ParseError::UnrecognizedReferentialAction)
+        }
+    }
+
+    pub fn parse_db_type(&mut self) -> ParseResult<DBType> {
+        if self.lex_token("decimal").is_ok() || self.lex_token("numeric").is_ok() {
+            return self.parse_decimal_type();
+        }
+        self.lex_token("integer")
+            .map(|_| DBType::Integer)
+            .or_else(|_| self.lex_token("text").map(|_| DBType::Text))
+            .or_else(|_| self.lex_token("real").map(|_| DBType::Real))
+            .or_else(|_| self.lex_token("boolean").map(|_| DBType::Boolean))
+            .or_else(|_| self.lex_token("date").map(|_| DBType::Date))
+            .or_else(|_| self.lex_token("timestamp").map(|_| DBType::Timestamp))
+            .or_else(|_| self.lex_token("time").map(|_| DBType::Time))
+            .or_else(|_| self.lex_token("uuid").map(|_| DBType::Uuid))
+            .map_err(|e| {
+                if let ParseError::EndOfInput = e {
+                    ParseError::MissingType
+                } else {
+                    ParseError::UnrecognizedType
+                }
+            })
+    }
+
+    fn parse_collation(&mut self) -> ParseResult<Collation> {
+        match self.lex_identifier() {
+            Ok(name) if name.eq_ignore_ascii_case("binary") => Ok(Collation::Binary),
+            Ok(name) if name.eq_ignore_ascii_case("nocase") => Ok(Collation::NoCase),
+            Ok(_) | Err(ParseError::InvalidIdentifier) => Err(ParseError::UnrecognizedCollation),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Parses the optional precision and scale of a decimal type, e.g. `(10, 2)`. As in standard
+    /// SQL, the precision defaults to 10 and the scale to 0.
+    fn parse_decimal_type(&mut self) -> ParseResult<DBType> {
+        let (mut precision, mut scale) = (10, 0);
+        if self.lex_token("(").is_ok() {
+            precision = self.parse_integer_value()?;
+            if self.lex_token(",").is_ok() {
+                scale = self.parse_integer_value()?;
+            }
+            self.parse_right_paren()?;
+        }
+        if !(1..=i64::from(MAX_PRECISION)).contains(&precision) || !(0..=precision).contains(&scale)
+        {
+            return Err(ParseError::InvalidPrecision);
+        }
+        Ok(DBType::Decimal(precision as u8, scale as u8))
+    }
+
+    fn parse_create(&mut self) -> ParseResult<Statement> {
+        self.lex_token("create")?;
+        self.parse_create_table()
+            .or_else(|e| {
+                e.ignore_fail()?;
+                self.parse_create_view()
+            })
+            .or_else(|e| {
+                e.ignore_fail()?;
+                self.parse_create_index(false)
+            })
+            .or_else(|e| {
+                e.ignore_fail()?;
+                self.lex_token("unique")?;
+                self.parse_create_index(true)
+            })
+            .or_else(|e| {
+                e.ignore_fail()?;
+                self.parse_create_sequence()
+            })
+            .or_else(|e| {
+                e.ignore_fail()?;
+                self.parse_create_virtual_table()
+            })
+            .or_else(|e| {
+                e.ignore_fail()?;
+                Err(ParseError::UnrecognizedStatement)
+            })
+    }
+
+    fn parse_create_table(&mut self) -> ParseResult<Statement> {
+        self.lex_token("table")?;
+        let table = self.lex_identifier()?;
+        let (columns, constraints) = self.parse_column_definitions()?;
+        Ok(Statement::CreateTable {
+            table,
+            columns,
+            constraints,
+        })
+    }
+
+    fn parse_create_view(&mut self) -> ParseResult<Statement> {
+        self.lex_token("view")?;
+        let view = self.lex_identifier()?;
+        self.lex_token("as").map_err(|_| ParseError::MissingAs)?;
+        let query = self.parse_select().map_err(|e| {
+            if let ParseError::FailedToLex = e {
+                ParseError::InvalidViewQuery
+            } else {
+                e
+            }
+        })?;
+        Ok(Statement::CreateView {
+            view,
+            query: Box::new(query),
+        })
+    }
+
+    fn parse_create_index(&mut self, unique: bool) -> ParseResult<Statement> {
+        self.lex_token("index")?;
+        let index = self.lex_identifier()?;
+        self.lex_token("on").map_err(|_| ParseError::MissingOn)?;
+        let table = self.lex_identifier()?;
+        self.parse_left_paren()?;
+        let column = self.lex_identifier()?;
+        self.parse_right_paren()?;
+        Ok(Statement::CreateIndex {
+            index,
+            table,
+            column,
+            unique,
+        })
+    }
+
+    fn parse_create_sequence(&mut self) -> ParseResult<Statement> {
+        self.lex_token("sequence")?;
+        let sequence = self.lex_identifier()?;
+        let mut start = 1;
+        let mut increment = 1;
+        loop {
+            if self.lex_token("start").is_ok() {
+                self.lex_token("with").ok();
+                start = self.parse_integer_value()?;
+            } else if self.lex_token("increment").is_ok() {
+                self.lex_token("by").ok();
+                increment = self.parse_integer_value()?;
+            } else {
+                break;
+            }
+        }
+        Ok(Statement::CreateSequence {
+            sequence,
+            start,
+            increment,
+        })
+    }
+
+    fn parse_create_virtual_table(&mut self) -> ParseResult<Statement> {
+        self.lex_token("virtual")?;
+        self.lex_token("table")?;
+        let table = self.lex_identifier()?;
+        self.lex_token("using")
+            .map_err(|_| ParseError::MissingUsing)?;
+        let module = self.lex_identifier()?;
+        let mut args = Vec::new();
+        if self.lex_token("(").is_ok() && self.lex_token(")").is_err() {
+            args.push(self.parse_expression()?);
+            while self.lex_token(",").is_ok() {
+                args.push(self.parse_expression()?);
+            }
+            self.parse_right_paren()?;
+        }
+        Ok(Statement::CreateVirtualTable {
+            table,
+            module,
+            args,
+        })
+    }
+
+    fn parse_integer_value(&mut self) -> ParseResult<i64> {
+        match self.lex_value() {
+            Ok(DBValue::Integer(i)) => Ok(i),
+            Err(ParseError::FailedToLex) | Ok(_) => Err(ParseError::InvalidValue),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn parse_drop(&mut self) -> ParseResult<Statement> {
+        self.lex_token("drop")?;
+        self.parse_drop_table()
+            .or_else(|e| {
+                e.ignore_fail()?;
+                self.parse_drop_view()
+            })
+            .or_else(|e| {
+                e.ignore_fail()?;
+                self.parse_drop_sequence()
+            })
+            .or_else(|e| {
+                e.ignore_fail()?;
+                Err(ParseError::UnrecognizedStatement)
+            })
+    }
+
+    fn parse_drop_table(&mut self) -> ParseResult<Statement> {
+        self.lex_token("table")?;
+        let table = self.lex_identifier()?;
+        Ok(Statement::DropTable { table })
+    }
+
+    fn parse_drop_sequence(&mut self) -> ParseResult<Statement> {
+        self.lex_token("sequence")?;
+        let sequence = self.lex_identifier()?;
+        Ok(Statement::DropSequence { sequence })
+    }
+
+    fn parse_drop_view(&mut self) -> ParseResult<Statement> {
+        self.lex_token("view")?;
+        let view = self.lex_identifier()?;
+        Ok(Statement::DropView { view })
+    }
+
+    fn lex_value(&mut self) -> ParseResult<DBValue> {
+        let (value, len) = match (self.peek_nth(0), self.peek_nth(1)) {
+            (Some(TokenKind::Integer(digits)), _) => {
+                (DBValue::Integer(parse_integer(digits, false)?), 1)
+            }
+            (Some(TokenKind::Symbol("-")), Some(TokenKind::Integer(digits))) => {
+                (DBValue::Integer(parse_integer(digits, true)?), 2)
+            }
+            (Some(TokenKind::Real(real)), _) => (DBValue::Real(parse_real(real)?), 1),
+            (Some(TokenKind::Symbol("-")), Some(TokenKind::Real(real))) => {
+                (DBValue::Real(-parse_real(real)?), 2)
+            }
+            (Some(TokenKind::Text(text)), _) => (DBValue::Text(text.clone()), 1),
+            (Some(kind), Some(TokenKind::Text(text))) if kind.matches("decimal") => {
+                let decimal = Decimal::parse(text).ok_or(ParseError::InvalidValue)?;
+                (DBValue::Decimal(decimal), 2)
+            }
+            (Some(kind), Some(TokenKind::Text(text))) if kind.matches("date") => {
+                let days = datetime::parse_date(text).ok_or(ParseError::InvalidDateTime)?;
+                (DBValue::Date(days), 2)
+            }
+            (Some(kind), Some(TokenKind::Text(text))) if kind.matches("time") => {
+                let micros = datetime::parse_time(text).ok_or(ParseError::InvalidDateTime)?;
+                (DBValue::Time(micros), 2)
+            }
+            (Some(kind), Some(TokenKind::Text(text))) if kind.matches("timestamp") => {
+                let micros = datetime::parse_timestamp(text).ok_or(ParseError::InvalidDateTime)?;
+                (DBValue::Timestamp(micros), 2)
+            }
+            (Some(kind), Some(TokenKind::Text(text))) if kind.matches("uuid") => {
+                let uuid = Uuid::parse(text).ok_or(ParseError::InvalidValue)?;
+                (DBValue::Uuid(uuid), 2)
+            }
+            (Some(kind), _) if kind.matches("null") => (DBValue::Null, 1),
+            (Some(kind), _) if kind.matches("true") => (DBValue::Boolean(true), 1),
+            (Some(kind), _) if kind.matches("false") => (DBValue::Boolean(false), 1),
+            (Some(TokenKind::UnterminatedText), _) => return Err(ParseError::RunawayText),
+            (Some(_), _) => return Err(ParseError::FailedToLex),
+            (None, _) => return Err(ParseError::EndOfInput),
+        };
+        self.position += len;
+        Ok(value)
+    }
+
+    fn parse_values(&mut self) -> ParseResult<Vec<Expression>> {
+        self.parse_left_paren()?;
+        let value = self.parse_value_expression()?;
+        let mut columns = vec![value];
+        while self.lex_token(",").is_ok() {
+            let ident = self.parse_value_expression()?;
+            columns.push(ident);
+        }
+        self.parse_right_paren()?;
+        Ok(columns)
+    }
+
+    fn parse_insert_into(&mut self) -> ParseResult<Statement> {
+        self.lex_token("insert")?;
+        self.lex_token("into")?;
         let table = self.lex_identifier()?;
-        let condition = if let Ok(_) = self.lex_string("where") {
-            Some(self.parse_condition()?)
+        let columns = if self.next_is("(") {
+            Some(self.parse_columns()?)
         } else {
             None
         };
-        Ok(Statement::Select {
+        self.lex_token("values")?;
+        let values = self.parse_values().map_err(|e| {
+            if let ParseError::FailedToLex = e {
+                ParseError::InvalidValue
+            } else {
+                e
+            }
+        })?;
+        Ok(Statement::InsertInto {
+            table,
             columns,
-            table: String::from(table),
+            values,
+        })
+    }
+
+    fn parse_transaction_control(&mut self) -> ParseResult<Statement> {
+        if self.lex_token("begin").is_ok() {
+            self.lex_token("transaction").ok();
+            Ok(Statement::Begin)
+        } else if self.lex_token("commit").is_ok() {
+            Ok(Statement::Commit)
+        } else if self.lex_token("rollback").is_ok() {
+            let savepoint = if self.lex_token("to").is_ok() {
+                self.lex_token("savepoint").ok();
+                Some(self.lex_identifier()?)
+            } else {
+                None
+            };
+            Ok(Statement::Rollback { savepoint })
+        } else if self.lex_token("savepoint").is_ok() {
+            let savepoint = self.lex_identifier()?;
+            Ok(Statement::Savepoint { savepoint })
+        } else if self.lex_token("release").is_ok() {
+            self.lex_token("savepoint").ok();
+            let savepoint = self.lex_identifier()?;
+            Ok(Statement::Release { savepoint })
+        } else if self.lex_token("set").is_ok() {
+            self.lex_token("transaction")?;
+            self.lex_token("isolation")?;
+            self.lex_token("level")?;
+            let isolation = if self.lex_token("read").is_ok() {
+                self.lex_token("committed")?;
+                IsolationLevel::ReadCommitted
+            } else {
+                self.lex_token("serializable")?;
+                IsolationLevel::Serializable
+            };
+            Ok(Statement::SetTransaction { isolation })
+        } else {
+            Err(ParseError::FailedToLex)
+        }
+    }
+
+    fn parse_vacuum(&mut self) -> ParseResult<Statement> {
+        self.lex_token("vacuum")?;
+        Ok(Statement::Vacuum)
+    }
+
+    /// Parses a 'copy'-statement, with its options in parentheses as in Postgres, e.g.
+    /// `copy t from 't.csv' with (format csv, header true, delimiter ';', null 'NA')`. `with` is
+    /// optional, and so is `true` after `header`.
+    fn parse_copy(&mut self) -> ParseResult<Statement> {
+        self.lex_token("copy")?;
+        let table = self.lex_identifier()?;
+        let columns = if self.next_is("(") {
+            Some(self.parse_columns()?)
+        } else {
+            None
+        };
+        self.lex_token("from")
+            .map_err(|_| ParseError::InvalidCopy)?;
+        let path = match self.lex_value() {
+            Ok(DBValue::Text(path)) => path,
+            _ => return Err(ParseError::InvalidCopy),
+        };
+        let mut format = CopyFormat::Text;
+        let mut header = false;
+        let (mut delimiter, mut null) = (None, None);
+        let with = self.lex_token("with").is_ok();
+        if with || self.next_is("(") {
+            self.parse_left_paren()?;
+            loop {
+                let option = self.lex_identifier()?.to_ascii_lowercase();
+                match option.as_str() {
+                    "format" if self.lex_token("csv").is_ok() => format = CopyFormat::Csv,
+                    "format" if self.lex_token("text").is_ok() => format = CopyFormat::Text,
+                    "header" if self.next_is(",") || self.next_is(")") => header = true,
+                    "header" => match self.lex_value() {
+                        Ok(DBValue::Boolean(value)) => header = value,
+                        _ => return Err(ParseError::InvalidCopyOption),
+                    },
+                    "delimiter" => match self.lex_value() {
+                        Ok(DBValue::Text(text)) if text == "\\t" => delimiter = Some('\t'),
+                        Ok(DBValue::Text(text)) if text.chars().count() == 1 => {
+                            delimiter = text.chars().next()
+                        }
+                        _ => return Err(ParseError::InvalidCopyOption),
+                    },
+                    "null" => match self.lex_value() {
+                        Ok(DBValue::Text(text)) => null = Some(text),
+                        _ => return Err(ParseError::InvalidCopyOption),
+                    },
+                    _ => return Err(ParseError::InvalidCopyOption),
+                }
+                if self.lex_token(",").is_err() {
+                    break;
+                }
+            }
+            self.parse_right_paren()?;
+        }
+        let mut options = CopyOptions::new(format);
+        options.header = header;
+        options.delimiter = delimiter.unwrap_or(options.delimiter);
+        options.null = null.unwrap_or(options.null);
+        Ok(Statement::Copy {
+            table,
+            columns,
+            path,
+            options,
+        })
+    }
+
+    fn parse_analyze(&mut self) -> ParseResult<Statement> {
+        self.lex_token("analyze")?;
+        let table = self.lex_identifier().ok();
+        Ok(Statement::Analyze { table })
+    }
+
+    /// Parses a pragma, optionally setting it to a word or an integer, e.g. `synchronous = 0`.
+    fn parse_pragma(&mut self) -> ParseResult<Statement> {
+        self.lex_token("pragma")?;
+        let name = self.lex_identifier()?;
+        let value = if self.lex_token("=").is_ok() {
+            match self.peek_nth(0) {
+                Some(TokenKind::Integer(_)) => Some(self.parse_integer_value()?.to_string()),
+                _ => Some(self.lex_identifier()?),
+            }
+        } else {
+            None
+        };
+        Ok(Statement::Pragma { name, value })
+    }
+
+    fn parse_where(&mut self) -> ParseResult<Option<Condition>> {
+        if self.lex_token("where").is_ok() {
+            Ok(Some(self.parse_condition()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn parse_update(&mut self) -> ParseResult<Statement> {
+        self.lex_token("update")?;
+        let table = self.lex_identifier()?;
+        self.lex_token("set").map_err(|_| ParseError::MissingSet)?;
+        let mut assignments = vec![self.parse_assignment()?];
+        while self.lex_token(",").is_ok() {
+            assignments.push(self.parse_assignment()?);
+        }
+        let condition = self.parse_where()?;
+        Ok(Statement::Update {
+            table,
+            assignments,
             condition,
         })
     }
 
-    fn parse_left_paren(&mut self) -> ParseResult<()> {
-        self.lex_string("(").map_err(|_| ParseError::MissingLParen)
+    fn parse_assignment(&mut self) -> ParseResult<(Identifier, Expression)> {
+        let column = self.lex_identifier()?;
+        self.lex_token("=").map_err(|_| ParseError::MissingEquals)?;
+        let value = self.parse_expression()?;
+        Ok((column, value))
+    }
+
+    fn parse_delete_from(&mut self) -> ParseResult<Statement> {
+        self.lex_token("delete")?;
+        self.lex_token("from")
+            .map_err(|_| ParseError::MissingFrom)?;
+        let table = self.lex_identifier()?;
+        let condition = self.parse_where()?;
+        Ok(Statement::DeleteFrom { table, condition })
+    }
+
+    fn parse_condition(&mut self) -> ParseResult<Condition> {
+        self.parse_expression().map(Expression::into_condition)
+    }
+
+    /// Parses any expression, including arithmetic and conditions, e.g. `a + 1 > b and not c`.
+    fn parse_expression(&mut self) -> ParseResult<Expression> {
+        self.parse_expression_above(0)
+    }
+
+    /// Precedence climbing over the binary operators; parses an expression whose operators bind
+    /// at least as tightly as `min_precedence`. Operators are left-associative.
+    fn parse_expression_above(&mut self, min_precedence: u8) -> ParseResult<Expression> {
+        let mut expression = self.parse_operand()?;
+        while let Some(&(symbol, precedence, operator)) = BINARY_OPERATORS
+            .iter()
+            .find(|(symbol, precedence, _)| *precedence >= min_precedence && self.next_is(symbol))
+        {
+            self.lex_token(symbol)?;
+            let rhs = self.parse_expression_above(precedence + 1)?;
+            expression = operator.apply(expression, rhs);
+        }
+        Ok(expression)
+    }
+
+    /// Parses a negated or parenthesized expression, or a single value, function call or field.
+    /// A 'collate'-clause binds tighter than any operator, so `not a collate nocase` applies the
+    /// collation to `a`.
+    fn parse_operand(&mut self) -> ParseResult<Expression> {
+        if self.lex_token("not").is_ok() {
+            let operand = self.parse_expression_above(NOT_PRECEDENCE)?;
+            return Ok(Condition::Not(Box::new(operand.into_condition())).into_expression());
+        }
+        let mut operand = if self.lex_token("(").is_ok() {
+            let expression = self.parse_expression()?;
+            self.lex_token(")").map_err(|_| ParseError::MissingRParen)?;
+            expression
+        } else {
+            self.parse_value_expression()
+                .or_else(|e| {
+                    e.ignore_fail()?;
+                    self.parse_selector().map(Expression::Field)
+                })
+                .map_err(|e| {
+                    if let ParseError::FailedToLex = e {
+                        ParseError::InvalidValue
+                    } else {
+                        e
+                    }
+                })?
+        };
+        while self.lex_token("collate").is_ok() {
+            operand = Expression::Collate(Box::new(operand), self.parse_collation()?);
+        }
+        Ok(operand)
+    }
+
+    /// Parses an expression not referring to any fields, i.e. a value, a placeholder or a
+    /// function call.
+    fn parse_value_expression(&mut self) -> ParseResult<Expression> {
+        if self.lex_token("?").is_ok() {
+            self.parameters += 1;
+            return Ok(Expression::Parameter(self.parameters - 1));
+        }
+        self.lex_value().map(Expression::Value).or_else(|e| {
+            e.ignore_fail()?;
+            self.parse_function_call()
+        })
+    }
+
+    fn parse_function_call(&mut self) -> ParseResult<Expression> {
+        match (self.peek_nth(0), self.peek_nth(1)) {
+            (Some(TokenKind::Word(_)), Some(next)) if next.matches("(") => (),
+            _ => return Err(ParseError::FailedToLex),
+        }
+        let name = self.lex_identifier()?;
+        self.parse_left_paren()?;
+        let mut args = Vec::new();
+        // `count(*)` takes no arguments, like `count()`
+        if self.lex_token("*").is_ok() {
+            self.parse_right_paren()?;
+        } else if self.lex_token(")").is_err() {
+            args.push(self.parse_expression()?);
+            while self.lex_token(",").is_ok() {
+                args.push(self.parse_expression()?);
+            }
+            self.parse_right_paren()?;
+        }
+        Ok(Expression::Function(name, args))
+    }
+
+    fn parse_selector(&mut self) -> ParseResult<Selector> {
+        let ident = self.lex_identifier()?;
+        if self.lex_token(".").is_ok() {
+            let mut field = self.lex_identifier()?;
+            let mut table = ident;
+            // A field of a schema-qualified table, as in `information_schema.tables.table_name`
+            if self.lex_token(".").is_ok() {
+                table = format!("{}.{}", table, field);
+                field = self.lex_identifier()?;
+            }
+            Ok(Selector {
+                table: Some(table),
+                field,
+            })
+        } else {
+            Ok(Selector {
+                table: None,
+                field: ident,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &str) -> Expression {
+        Expression::Field(Selector {
+            table: None,
+            field: String::from(name),
+        })
+    }
+
+    #[test]
+    fn parse_select_with_single_column() {
+        let stmt = Parser::new("select (col) from tbl;").parse_command();
+        let select = Command::Statement(Statement::Select {
+            columns: vec![field("col")],
+            table: String::from("tbl"),
+            joins: Vec::new(),
+            condition: None,
+            group_by: Vec::new(),
+            order_by: Vec::new(),
+            limit: None,
+        });
+        assert_eq!(stmt, Ok(select));
+    }
+
+    #[test]
+    fn parse_select_with_multiple_columns() {
+        let stmt = Parser::new("select (col_1, col_2, col_3) from tbl;").parse_command();
+        let select = Command::Statement(Statement::Select {
+            columns: vec![field("col_1"), field("col_2"), field("col_3")],
+            table: String::from("tbl"),
+            joins: Vec::new(),
+            condition: None,
+            group_by: Vec::new(),
+            order_by: Vec::new(),
+            limit: None,
+        });
+        assert_eq!(stmt, Ok(select));
+    }
+
+    #[test]
+    fn parse_create_table_with_single_column() {
+        let stmt = Parser::new("create table tbl (col integer);").parse_command();
+        let create = Command::Statement(Statement::CreateTable {
+            table: String::from("tbl"),
+            columns: vec![(String::from("col"), DBType::Integer)],
+            constraints: vec![],
+        });
+        assert_eq!(stmt, Ok(create));
+    }
+
+    #[test]
+    fn parse_create_table_with_multiple_columns() {
+        let stmt = Parser::new("create table tbl (col_1 integer, col_2 text, col_3 text);")
+            .parse_command();
+        let create = Command::Statement(Statement::CreateTable {
+            table: String::from("tbl"),
+            columns: vec![
+                (String::from("col_1"), DBType::Integer),
+                (String::from("col_2"), DBType::Text),
+                (String::from("col_3"), DBType::Text),
+            ],
+            constraints: vec![],
+        });
+        assert_eq!(stmt, Ok(create));
+    }
+
+    #[test]
+    fn parse_copy() {
+        let sql = "copy t (a, b) from 'data/t.csv' with (format csv, header);";
+        let stmt = Parser::new(sql).parse_command();
+        let mut options = CopyOptions::new(CopyFormat::Csv);
+        options.header = true;
+        let copy = Statement::Copy {
+            table: String::from("t"),
+            columns: Some(vec![String::from("a"), String::from("b")]),
+            path: String::from("data/t.csv"),
+            options,
+        };
+        assert_eq!(stmt, Ok(Command::Statement(copy.clone())));
+        let shown = Parser::new(&format!("{};", copy)).parse_command();
+        assert_eq!(shown, Ok(Command::Statement(copy)));
+        let sql = "copy t from 't.tsv' (delimiter '|', null '', header false);";
+        let stmt = Parser::new(sql).parse_command();
+        let options = CopyOptions {
+            delimiter: '|',
+            null: String::new(),
+            ..CopyOptions::default()
+        };
+        let copy = Statement::Copy {
+            table: String::from("t"),
+            columns: None,
+            path: String::from("t.tsv"),
+            options,
+        };
+        assert_eq!(stmt, Ok(Command::Statement(copy)));
+        let tab = Parser::new("copy t from 't.tsv' (delimiter '\\t');").parse_command();
+        assert!(matches!(
+            tab,
+            Ok(Command::Statement(Statement::Copy { options, .. })) if options.delimiter == '\t'
+        ));
+        let missing = Parser::new("copy t 't.csv';").parse_command();
+        assert_eq!(missing, Err(ParseError::InvalidCopy));
+        let unknown = Parser::new("copy t from 't.csv' (format xml);").parse_command();
+        assert_eq!(unknown, Err(ParseError::InvalidCopyOption));
+    }
+
+    #[test]
+    fn parse_insert_into_with_single_column() {
+        let stmt = Parser::new("insert into tbl values (0);").parse_command();
+        let insert = Command::Statement(Statement::InsertInto {
+            table: String::from("tbl"),
+            columns: None,
+            values: vec![Expression::Value(DBValue::Integer(0))],
+        });
+        assert_eq!(stmt, Ok(insert));
+    }
+
+    #[test]
+    fn parse_insert_into_with_multiple_columns() {
+        let stmt = Parser::new("insert into tbl values (0, 'foo', 'bar');").parse_command();
+        let insert = Command::Statement(Statement::InsertInto {
+            table: String::from("tbl"),
+            columns: None,
+            values: vec![
+                Expression::Value(DBValue::Integer(0)),
+                Expression::Value(DBValue::Text(String::from("foo"))),
+                Expression::Value(DBValue::Text(String::from("bar"))),
+            ],
+        });
+        assert_eq!(stmt, Ok(insert));
+    }
+
+    #[test]
+    fn parse_insert_into_with_column_list() {
+        let stmt = Parser::new("insert into tbl (col_2, col_1) values ('foo', 0);").parse_command();
+        let insert = Command::Statement(Statement::InsertInto {
+            table: String::from("tbl"),
+            columns: Some(vec![String::from("col_2"), String::from("col_1")]),
+            values: vec![
+                Expression::Value(DBValue::Text(String::from("foo"))),
+                Expression::Value(DBValue::Integer(0)),
+            ],
+        });
+        assert_eq!(stmt, Ok(insert));
     }
 
-    fn parse_right_paren(&mut self) -> ParseResult<()> {
-        self.lex_string(")").map_err(|_| {
-            if let Ok(_) = self.lex_identifier() {
-                ParseError::MissingComma
-            } else {
-                ParseError::MissingRParen
-            }
-        })
+    #[test]
+    fn parse_null_value() {
+        let stmt = Parser::new("insert into tbl values (NULL, 0);").parse_command();
+        let insert = Command::Statement(Statement::InsertInto {
+            table: String::from("tbl"),
+            columns: None,
+            values: vec![
+                Expression::Value(DBValue::Null),
+                Expression::Value(DBValue::Integer(0)),
+            ],
+        });
+        assert_eq!(stmt, Ok(insert));
     }
 
-    fn parse_columns(&mut self) -> ParseResult<Vec<Identifier>> {
-        self.parse_left_paren()?;
-        let ident = self.lex_identifier()?;
-        let mut columns = vec![ident];
-        while let Ok(_) = self.lex_string(",") {
-            let ident = self.lex_identifier()?;
-            columns.push(ident);
-        }
-        self.parse_right_paren()?;
-        Ok(columns)
+    #[test]
+    fn parse_autoincrement() {
+        let stmt =
+            Parser::new("create table tbl (id integer primary key autoincrement);").parse_command();
+        let create = Command::Statement(Statement::CreateTable {
+            table: String::from("tbl"),
+            columns: vec![(String::from("id"), DBType::Integer)],
+            constraints: vec![
+                Constraint::PrimaryKey(String::from("id")),
+                Constraint::AutoIncrement(String::from("id")),
+            ],
+        });
+        assert_eq!(stmt, Ok(create));
     }
 
-    fn parse_column_pairs(&mut self) -> ParseResult<Vec<(Identifier, DBType)>> {
-        self.parse_left_paren()?;
-        let ident = self.lex_identifier()?;
-        let db_type = self.parse_db_type()?;
-        let mut columns = vec![(ident, db_type)];
-        while let Ok(_) = self.lex_string(",") {
-            let ident = self.lex_identifier()?;
-            let db_type = self.parse_db_type()?;
-            columns.push((ident, db_type));
-        }
-        self.parse_right_paren()?;
-        Ok(columns)
+    #[test]
+    fn parse_create_view() {
+        let stmt = Parser::new("create view v as select (col_1, col_2) from tbl;").parse_command();
+        let create = Command::Statement(Statement::CreateView {
+            view: String::from("v"),
+            query: Box::new(Statement::Select {
+                columns: vec![field("col_1"), field("col_2")],
+                table: String::from("tbl"),
+                joins: Vec::new(),
+                condition: None,
+                group_by: Vec::new(),
+                order_by: Vec::new(),
+                limit: None,
+            }),
+        });
+        assert_eq!(stmt, Ok(create));
     }
 
-    fn parse_db_type(&mut self) -> ParseResult<DBType> {
-        self.lex_string("integer")
-            .map(|_| DBType::Integer)
-            .or_else(|_| self.lex_string("text").map(|_| DBType::Text))
-            .map_err(|e| {
-                if let ParseError::EndOfInput = e {
-                    ParseError::MissingType
-                } else {
-                    ParseError::UnrecognizedType
-                }
-            })
+    #[test]
+    fn parse_drop_view_and_table() {
+        let view = Parser::new("drop view v;").parse_command();
+        let table = Parser::new("drop table tbl;").parse_command();
+        assert_eq!(
+            view,
+            Ok(Command::Statement(Statement::DropView {
+                view: String::from("v")
+            }))
+        );
+        assert_eq!(
+            table,
+            Ok(Command::Statement(Statement::DropTable {
+                table: String::from("tbl")
+            }))
+        );
     }
 
-    fn parse_create_table(&mut self) -> ParseResult<Statement> {
-        self.lex_string("create")?;
-        self.lex_string("table")?;
-        let table = self.lex_identifier()?;
-        let columns = self.parse_column_pairs()?;
-        Ok(Statement::CreateTable { table, columns })
+    #[test]
+    fn invalid_view_query_error() {
+        let missing_as = Parser::new("create view v select (col) from tbl;").parse_command();
+        let insert = Parser::new("create view v as insert into tbl values (0);").parse_command();
+        assert_eq!(missing_as, Err(ParseError::MissingAs));
+        assert_eq!(insert, Err(ParseError::InvalidViewQuery));
     }
 
-    fn lex_value(&mut self) -> ParseResult<DBValue> {
-        self.skip_whitespace();
-        self.parse_integer()
-            .map(|int| DBValue::Integer(int))
-            .or_else(|e| {
-                e.ignore_fail()?;
-                self.parse_text().map(|text| DBValue::Text(text))
+    #[test]
+    fn parse_select_with_condition() {
+        let stmt = Parser::new("select (col) from tbl where tbl.col = 1 and not col < 'foo';")
+            .parse_command();
+        let col = |table: Option<&str>| {
+            Expression::Field(Selector {
+                table: table.map(String::from),
+                field: String::from("col"),
             })
+        };
+        let select = Command::Statement(Statement::Select {
+            columns: vec![field("col")],
+            table: String::from("tbl"),
+            joins: Vec::new(),
+            condition: Some(Condition::And(
+                Box::new(Condition::Literal(ConditionLiteral::Eq(
+                    col(Some("tbl")),
+                    Expression::Value(DBValue::Integer(1)),
+                ))),
+                Box::new(Condition::Not(Box::new(Condition::Literal(
+                    ConditionLiteral::Lt(
+                        col(None),
+                        Expression::Value(DBValue::Text(String::from("foo"))),
+                    ),
+                )))),
+            )),
+            group_by: Vec::new(),
+            order_by: Vec::new(),
+            limit: None,
+        });
+        assert_eq!(stmt, Ok(select));
     }
 
-    fn parse_values(&mut self) -> ParseResult<Vec<DBValue>> {
-        self.parse_left_paren()?;
-        let value = self.lex_value()?;
-        let mut columns = vec![value];
-        while let Ok(_) = self.lex_string(",") {
-            let ident = self.lex_value()?;
-            columns.push(ident);
-        }
-        self.parse_right_paren()?;
-        Ok(columns)
+    #[test]
+    fn condition_precedence() {
+        let condition = |input: &str| match Parser::new(input).parse_command() {
+            Ok(Command::Statement(Statement::Select { condition, .. })) => condition.unwrap(),
+            other => panic!("{:?}", other),
+        };
+        let literal = |field: &str| {
+            Box::new(Condition::Literal(ConditionLiteral::Eq(
+                Expression::Field(Selector {
+                    table: None,
+                    field: String::from(field),
+                }),
+                Expression::Value(DBValue::Integer(1)),
+            )))
+        };
+        assert_eq!(
+            condition("select (a) from t where a = 1 or not b = 1 and c = 1;"),
+            Condition::Or(
+                literal("a"),
+                Box::new(Condition::And(
+                    Box::new(Condition::Not(literal("b"))),
+                    literal("c")
+                ))
+            )
+        );
+        assert_eq!(
+            condition("select (a) from t where a = 1 and b = 1 and c = 1;"),
+            Condition::And(
+                Box::new(Condition::And(literal("a"), literal("b"))),
+                literal("c")
+            )
+        );
+        assert_eq!(
+            condition("select (a) from t where not (a = 1 or b = 1) and c = 1;"),
+            Condition::And(
+                Box::new(Condition::Not(Box::new(Condition::Or(
+                    literal("a"),
+                    literal("b")
+                )))),
+                literal("c")
+            )
+        );
+        let unclosed = Parser::new("select (a) from t where (a = 1 or b = 1;").parse_command();
+        assert_eq!(unclosed, Err(ParseError::MissingRParen));
     }
 
-    fn parse_insert_into(&mut self) -> ParseResult<Statement> {
-        self.lex_string("insert")?;
-        self.lex_string("into")?;
-        let table = self.lex_identifier()?;
-        self.lex_string("values")?;
-        let values = self.parse_values().map_err(|e| {
-            if let ParseError::FailedToLex = e {
-                ParseError::InvalidValue
-            } else {
-                e
-            }
-        })?;
-        Ok(Statement::InsertInto { table, values })
+    #[test]
+    fn parse_create_index() {
+        let stmt = Parser::new("create index idx on tbl (col);").parse_command();
+        let create = Command::Statement(Statement::CreateIndex {
+            index: String::from("idx"),
+            table: String::from("tbl"),
+            column: String::from("col"),
+            unique: false,
+        });
+        assert_eq!(stmt, Ok(create));
     }
 
-    fn parse_condition(&mut self) -> ParseResult<Condition> {
-        todo!();
+    #[test]
+    fn parse_unique_constraints() {
+        let table =
+            Parser::new("create table tbl (col_1 integer unique, col_2 text);").parse_command();
+        let index = Parser::new("create unique index idx on tbl (col_2);").parse_command();
+        assert_eq!(
+            table,
+            Ok(Command::Statement(Statement::CreateTable {
+                table: String::from("tbl"),
+                columns: vec![
+                    (String::from("col_1"), DBType::Integer),
+                    (String::from("col_2"), DBType::Text),
+                ],
+                constraints: vec![Constraint::Unique(String::from("col_1"))],
+            }))
+        );
+        assert_eq!(
+            index,
+            Ok(Command::Statement(Statement::CreateIndex {
+                index: String::from("idx"),
+                table: String::from("tbl"),
+                column: String::from("col_2"),
+                unique: true,
+            }))
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn parse_update_and_delete() {
+        let update = Parser::new("update tbl set col_1 = 0, col_2 = 'foo';").parse_command();
+        let delete = Parser::new("delete from tbl where col_1 >= 0;").parse_command();
+        assert_eq!(
+            update,
+            Ok(Command::Statement(Statement::Update {
+                table: String::from("tbl"),
+                assignments: vec![
+                    (
+                        String::from("col_1"),
+                        Expression::Value(DBValue::Integer(0))
+                    ),
+                    (
+                        String::from("col_2"),
+                        Expression::Value(DBValue::Text(String::from("foo")))
+                    ),
+                ],
+                condition: None,
+            }))
+        );
+        assert_eq!(
+            delete,
+            Ok(Command::Statement(Statement::DeleteFrom {
+                table: String::from("tbl"),
+                condition: Some(Condition::Literal(ConditionLiteral::Gte(
+                    Expression::Field(Selector {
+                        table: None,
+                        field: String::from("col_1"),
+                    }),
+                    Expression::Value(DBValue::Integer(0)),
+                ))),
+            }))
+        );
+    }
 
     #[test]
-    fn parse_select_with_single_column() {
-        let stmt = Parser::new("select (col) from tbl;").parse_command();
+    fn parse_arithmetic() {
+        let stmt =
+            Parser::new("select (a + 2 * -1.5e1 - b / 2, 1 < a + 1) from tbl;").parse_command();
+        let arithmetic =
+            |operator, lhs, rhs| Expression::Arithmetic(operator, Box::new(lhs), Box::new(rhs));
         let select = Command::Statement(Statement::Select {
-            columns: vec![String::from("col")],
+            columns: vec![
+                arithmetic(
+                    ArithmeticOperator::Subtract,
+                    arithmetic(
+                        ArithmeticOperator::Add,
+                        field("a"),
+                        arithmetic(
+                            ArithmeticOperator::Multiply,
+                            Expression::Value(DBValue::Integer(2)),
+                            Expression::Value(DBValue::Real(-15.0)),
+                        ),
+                    ),
+                    arithmetic(
+                        ArithmeticOperator::Divide,
+                        field("b"),
+                        Expression::Value(DBValue::Integer(2)),
+                    ),
+                ),
+                Expression::Condition(Box::new(Condition::Literal(ConditionLiteral::Lt(
+                    Expression::Value(DBValue::Integer(1)),
+                    arithmetic(
+                        ArithmeticOperator::Add,
+                        field("a"),
+                        Expression::Value(DBValue::Integer(1)),
+                    ),
+                )))),
+            ],
             table: String::from("tbl"),
+            joins: Vec::new(),
             condition: None,
+            group_by: Vec::new(),
+            order_by: Vec::new(),
+            limit: None,
         });
         assert_eq!(stmt, Ok(select));
     }
 
     #[test]
-    fn parse_select_with_multiple_columns() {
-        let stmt = Parser::new("select (col_1, col_2, col_3) from tbl;").parse_command();
+    fn parse_boolean_expressions() {
+        let stmt = Parser::new("select a > b, not c from tbl where c;").parse_command();
+        let select = Command::Statement(Statement::Select {
+            columns: vec![
+                Expression::Condition(Box::new(Condition::Literal(ConditionLiteral::Gt(
+                    field("a"),
+                    field("b"),
+                )))),
+                Expression::Condition(Box::new(Condition::Not(Box::new(Condition::Expression(
+                    field("c"),
+                ))))),
+            ],
+            table: String::from("tbl"),
+            joins: Vec::new(),
+            condition: Some(Condition::Expression(field("c"))),
+            group_by: Vec::new(),
+            order_by: Vec::new(),
+            limit: None,
+        });
+        assert_eq!(stmt, Ok(select));
+        let stmt = Parser::new("create table tbl (flag boolean default true);").parse_command();
+        let create = Command::Statement(Statement::CreateTable {
+            table: String::from("tbl"),
+            columns: vec![(String::from("flag"), DBType::Boolean)],
+            constraints: vec![Constraint::Default(
+                String::from("flag"),
+                Expression::Value(DBValue::Boolean(true)),
+            )],
+        });
+        assert_eq!(stmt, Ok(create));
+    }
+
+    #[test]
+    fn parse_group_by() {
+        let stmt = Parser::new("select a, string_agg(b, ';') from tbl group by a;").parse_command();
         let select = Command::Statement(Statement::Select {
             columns: vec![
-                String::from("col_1"),
-                String::from("col_2"),
-                String::from("col_3"),
+                field("a"),
+                Expression::Function(
+                    String::from("string_agg"),
+                    vec![
+                        field("b"),
+                        Expression::Value(DBValue::Text(String::from(";"))),
+                    ],
+                ),
             ],
             table: String::from("tbl"),
+            joins: Vec::new(),
             condition: None,
+            group_by: vec![field("a")],
+            order_by: Vec::new(),
+            limit: None,
         });
         assert_eq!(stmt, Ok(select));
+        let stmt = Parser::new("select count(*) from tbl;").parse_command();
+        assert!(matches!(
+            stmt,
+            Ok(Command::Statement(Statement::Select { columns, .. }))
+                if columns == vec![Expression::Function(String::from("count"), Vec::new())]
+        ));
+        let missing = Parser::new("select a from tbl group a;").parse_command();
+        assert_eq!(missing, Err(ParseError::MissingBy));
     }
 
     #[test]
-    fn parse_create_table_with_single_column() {
-        let stmt = Parser::new("create table tbl (col integer);").parse_command();
+    fn parse_joins_and_limit() {
+        let stmt = Parser::new(
+            "select a.x, b.y from a join b on a.id = b.a_id inner join c on c.id = b.id limit 10 offset 5;",
+        )
+        .parse_command();
+        let selector = |table: &str, field: &str| {
+            Expression::Field(Selector {
+                table: Some(String::from(table)),
+                field: String::from(field),
+            })
+        };
+        let equals = |lhs, rhs| Condition::Literal(ConditionLiteral::Eq(lhs, rhs));
+        let select = Command::Statement(Statement::Select {
+            columns: vec![selector("a", "x"), selector("b", "y")],
+            table: String::from("a"),
+            joins: vec![
+                Join {
+                    table: String::from("b"),
+                    condition: equals(selector("a", "id"), selector("b", "a_id")),
+                },
+                Join {
+                    table: String::from("c"),
+                    condition: equals(selector("c", "id"), selector("b", "id")),
+                },
+            ],
+            condition: None,
+            group_by: Vec::new(),
+            order_by: Vec::new(),
+            limit: Some(Limit {
+                count: 10,
+                offset: 5,
+            }),
+        });
+        assert_eq!(stmt, Ok(select));
+        let missing = Parser::new("select x from a join b where x = 1;").parse_command();
+        assert_eq!(missing, Err(ParseError::MissingOn));
+        let negative = Parser::new("select x from a limit -1;").parse_command();
+        assert_eq!(negative, Err(ParseError::InvalidValue));
+        let stmt = Parser::new(
+            "select information_schema.columns.column_name from information_schema.columns;",
+        )
+        .parse_command();
+        assert!(matches!(
+            stmt,
+            Ok(Command::Statement(Statement::Select { columns, table, .. }))
+                if table == "information_schema.columns"
+                    && columns == vec![selector("information_schema.columns", "column_name")]
+        ));
+    }
+
+    #[test]
+    fn parse_collations_and_order_by() {
+        let stmt = Parser::new(
+            "select a from tbl where a = b collate nocase order by a desc, b collate binary asc, c;",
+        )
+        .parse_command();
+        let collate = |expression, collation| Expression::Collate(Box::new(expression), collation);
+        let select = Command::Statement(Statement::Select {
+            columns: vec![field("a")],
+            table: String::from("tbl"),
+            joins: Vec::new(),
+            condition: Some(Condition::Literal(ConditionLiteral::Eq(
+                field("a"),
+                collate(field("b"), Collation::NoCase),
+            ))),
+            group_by: Vec::new(),
+            order_by: vec![
+                (field("a"), SortOrder::Descending),
+                (collate(field("b"), Collation::Binary), SortOrder::Ascending),
+                (field("c"), SortOrder::Ascending),
+            ],
+            limit: None,
+        });
+        assert_eq!(stmt, Ok(select));
+        let stmt =
+            Parser::new("create table tbl (name text collate NOCASE unique);").parse_command();
         let create = Command::Statement(Statement::CreateTable {
             table: String::from("tbl"),
-            columns: vec![(String::from("col"), DBType::Integer)],
+            columns: vec![(String::from("name"), DBType::Text)],
+            constraints: vec![
+                Constraint::Collate(String::from("name"), Collation::NoCase),
+                Constraint::Unique(String::from("name")),
+            ],
         });
         assert_eq!(stmt, Ok(create));
+        let unknown = Parser::new("select a from tbl order by a collate french;").parse_command();
+        assert_eq!(unknown, Err(ParseError::UnrecognizedCollation));
+        let missing = Parser::new("select a from tbl order a;").parse_command();
+        assert_eq!(missing, Err(ParseError::MissingBy));
     }
 
     #[test]
-    fn parse_create_table_with_multiple_columns() {
-        let stmt = Parser::new("create table tbl (col_1 integer, col_2 text, col_3 text);")
+    fn parse_decimal_types() {
+        let stmt = Parser::new("create table tbl (a decimal, b numeric(12, 2), c decimal (5));")
             .parse_command();
         let create = Command::Statement(Statement::CreateTable {
             table: String::from("tbl"),
             columns: vec![
-                (String::from("col_1"), DBType::Integer),
-                (String::from("col_2"), DBType::Text),
-                (String::from("col_3"), DBType::Text),
+                (String::from("a"), DBType::Decimal(10, 0)),
+                (String::from("b"), DBType::Decimal(12, 2)),
+                (String::from("c"), DBType::Decimal(5, 0)),
             ],
+            constraints: Vec::new(),
         });
         assert_eq!(stmt, Ok(create));
+        for input in &[
+            "create table tbl (a decimal(0));",
+            "create table tbl (a decimal(39, 2));",
+            "create table tbl (a decimal(4, 5));",
+        ] {
+            let stmt = Parser::new(input).parse_command();
+            assert_eq!(stmt, Err(ParseError::InvalidPrecision), "{}", input);
+        }
+        let stmt = Parser::new("insert into tbl values (decimal '-1.50');").parse_command();
+        let insert = Command::Statement(Statement::InsertInto {
+            table: String::from("tbl"),
+            columns: None,
+            values: vec![Expression::Value(DBValue::Decimal(
+                Decimal::parse("-1.50").unwrap(),
+            ))],
+        });
+        assert_eq!(stmt, Ok(insert));
     }
 
     #[test]
-    fn parse_insert_into_with_single_column() {
-        let stmt = Parser::new("insert into tbl values (0);").parse_command();
-        let insert = Command::Statement(Statement::InsertInto {
+    fn parse_not_null() {
+        let stmt = Parser::new("create table tbl (col integer not null unique);").parse_command();
+        let create = Command::Statement(Statement::CreateTable {
             table: String::from("tbl"),
-            values: vec![DBValue::Integer(0)],
+            columns: vec![(String::from("col"), DBType::Integer)],
+            constraints: vec![
+                Constraint::NotNull(String::from("col")),
+                Constraint::Unique(String::from("col")),
+            ],
         });
-        assert_eq!(stmt, Ok(insert));
+        assert_eq!(stmt, Ok(create));
+        let missing = Parser::new("create table tbl (col integer not);").parse_command();
+        assert_eq!(missing, Err(ParseError::MissingNull));
     }
 
     #[test]
-    fn parse_insert_into_with_multiple_columns() {
-        let stmt = Parser::new("insert into tbl values (0, 'foo', 'bar');").parse_command();
+    fn parse_enum_constraints() {
+        let stmt = Parser::new(
+            "create table tbl (status text check in ('new', 'done') not null, size enum('s'));",
+        )
+        .parse_command();
+        let labels = |labels: &[&str]| labels.iter().map(|l| String::from(*l)).collect();
+        let create = Command::Statement(Statement::CreateTable {
+            table: String::from("tbl"),
+            columns: vec![
+                (String::from("status"), DBType::Text),
+                (String::from("size"), DBType::Text),
+            ],
+            constraints: vec![
+                Constraint::Enum(String::from("status"), labels(&["new", "done"])),
+                Constraint::NotNull(String::from("status")),
+                Constraint::Enum(String::from("size"), labels(&["s"])),
+            ],
+        });
+        assert_eq!(stmt, Ok(create));
+        let missing = Parser::new("create table tbl (a text check ('x'));").parse_command();
+        assert_eq!(missing, Err(ParseError::MissingIn));
+        let invalid = Parser::new("create table tbl (a enum('x', 1));").parse_command();
+        assert_eq!(invalid, Err(ParseError::InvalidValue));
+    }
+
+    #[test]
+    fn parse_foreign_keys() {
+        let stmt = Parser::new(
+            "create table tbl (a integer references t (id), b integer references t (id) on delete set null);",
+        )
+        .parse_command();
+        let foreign_key = |column: &str, on_delete| Constraint::ForeignKey {
+            column: String::from(column),
+            table: String::from("t"),
+            references: String::from("id"),
+            on_delete,
+        };
+        let create = Command::Statement(Statement::CreateTable {
+            table: String::from("tbl"),
+            columns: vec![
+                (String::from("a"), DBType::Integer),
+                (String::from("b"), DBType::Integer),
+            ],
+            constraints: vec![
+                foreign_key("a", ReferentialAction::Restrict),
+                foreign_key("b", ReferentialAction::SetNull),
+            ],
+        });
+        assert_eq!(stmt, Ok(create));
+        let invalid = Parser::new("create table tbl (a integer references t (id) on delete x);")
+            .parse_command();
+        assert_eq!(invalid, Err(ParseError::UnrecognizedReferentialAction));
+    }
+
+    #[test]
+    fn parse_create_virtual_table() {
+        let stmt = Parser::new("create virtual table ten using numbers(1, 'x');").parse_command();
+        let create = Statement::CreateVirtualTable {
+            table: String::from("ten"),
+            module: String::from("numbers"),
+            args: vec![
+                Expression::Value(DBValue::Integer(1)),
+                Expression::Value(DBValue::Text(String::from("x"))),
+            ],
+        };
+        assert_eq!(
+            create.to_string(),
+            "create virtual table ten using numbers(1, 'x')"
+        );
+        assert_eq!(stmt, Ok(Command::Statement(create)));
+        let bare = Parser::new("create virtual table files using files;").parse_command();
+        assert!(matches!(
+            bare,
+            Ok(Command::Statement(Statement::CreateVirtualTable { args, .. })) if args.is_empty()
+        ));
+        let missing = Parser::new("create virtual table ten numbers(10);").parse_command();
+        assert_eq!(missing, Err(ParseError::MissingUsing));
+    }
+
+    #[test]
+    fn parse_sequences() {
+        let create =
+            Parser::new("create sequence seq start with -1 increment by 2;").parse_command();
+        let insert = Parser::new("insert into tbl values (nextval('seq'));").parse_command();
+        let drop = Parser::new("drop sequence seq;").parse_command();
+        assert_eq!(
+            create,
+            Ok(Command::Statement(Statement::CreateSequence {
+                sequence: String::from("seq"),
+                start: -1,
+                increment: 2,
+            }))
+        );
+        assert_eq!(
+            insert,
+            Ok(Command::Statement(Statement::InsertInto {
+                table: String::from("tbl"),
+                columns: None,
+                values: vec![Expression::Function(
+                    String::from("nextval"),
+                    vec![Expression::Value(DBValue::Text(String::from("seq")))],
+                )],
+            }))
+        );
+        assert_eq!(
+            drop,
+            Ok(Command::Statement(Statement::DropSequence {
+                sequence: String::from("seq")
+            }))
+        );
+    }
+
+    #[test]
+    fn parse_transaction_control() {
+        let statements = vec![
+            ("begin;", Statement::Begin),
+            ("begin transaction;", Statement::Begin),
+            ("commit;", Statement::Commit),
+            ("rollback;", Statement::Rollback { savepoint: None }),
+            (
+                "rollback to savepoint s;",
+                Statement::Rollback {
+                    savepoint: Some(String::from("s")),
+                },
+            ),
+            (
+                "savepoint s;",
+                Statement::Savepoint {
+                    savepoint: String::from("s"),
+                },
+            ),
+            (
+                "release s;",
+                Statement::Release {
+                    savepoint: String::from("s"),
+                },
+            ),
+            (
+                "set transaction isolation level read committed;",
+                Statement::SetTransaction {
+                    isolation: IsolationLevel::ReadCommitted,
+                },
+            ),
+            (
+                "set transaction isolation level serializable;",
+                Statement::SetTransaction {
+                    isolation: IsolationLevel::Serializable,
+                },
+            ),
+            ("analyze;", Statement::Analyze { table: None }),
+            (
+                "analyze tbl;",
+                Statement::Analyze {
+                    table: Some(String::from("tbl")),
+                },
+            ),
+            ("vacuum;", Statement::Vacuum),
+            (
+                "pragma synchronous;",
+                Statement::Pragma {
+                    name: String::from("synchronous"),
+                    value: None,
+                },
+            ),
+            (
+                "pragma synchronous = 0;",
+                Statement::Pragma {
+                    name: String::from("synchronous"),
+                    value: Some(String::from("0")),
+                },
+            ),
+        ];
+        for (input, stmt) in statements {
+            assert_eq!(
+                Parser::new(input).parse_command(),
+                Ok(Command::Statement(stmt))
+            );
+        }
+    }
+
+    #[test]
+    fn skip_comments() {
+        let stmt =
+            Parser::new("select /* columns: */ (col) -- a comment\n from tbl /* unterminated ;")
+                .parse_command();
+        assert_eq!(stmt, Err(ParseError::MissingSemicolon));
+        let stmt =
+            Parser::new("-- select\nselect (col) from /* \n */ tbl; -- trailing").parse_command();
+        let select = Command::Statement(Statement::Select {
+            columns: vec![field("col")],
+            table: String::from("tbl"),
+            joins: Vec::new(),
+            condition: None,
+            group_by: Vec::new(),
+            order_by: Vec::new(),
+            limit: None,
+        });
+        assert_eq!(stmt, Ok(select));
+        let text = Parser::new("insert into tbl values ('-- /* */');").parse_command();
         let insert = Command::Statement(Statement::InsertInto {
             table: String::from("tbl"),
-            values: vec![DBValue::Integer(0),
-            DBValue::Text(String::from("foo")),
-            DBValue::Text(String::from("bar"))],
+            columns: None,
+            values: vec![Expression::Value(DBValue::Text(String::from("-- /* */")))],
         });
-        assert_eq!(stmt, Ok(insert));
+        assert_eq!(text, Ok(insert));
+        assert!(Parser::new(" -- comment only\n /* */ ").at_end());
+    }
+
+    #[test]
+    fn keyword_boundaries() {
+        let stmt = Parser::new("selectx (col) from tbl;").parse_command();
+        assert_eq!(stmt, Err(ParseError::UnrecognizedStatement));
+        let stmt = Parser::new("select (col) fromtbl;").parse_command();
+        assert_eq!(stmt, Err(ParseError::MissingFrom));
+        let stmt = Parser::new("SELECT (Col) From tbl;").parse_command();
+        let select = Command::Statement(Statement::Select {
+            columns: vec![field("Col")],
+            table: String::from("tbl"),
+            joins: Vec::new(),
+            condition: None,
+            group_by: Vec::new(),
+            order_by: Vec::new(),
+            limit: None,
+        });
+        assert_eq!(stmt, Ok(select));
+    }
+
+    #[test]
+    fn parse_and_bind_parameters() {
+        let stmt = match Parser::new("update tbl set a = ? where b = nextval(?);").parse_command() {
+            Ok(Command::Statement(stmt)) => stmt,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(stmt.parameter_count(), 2);
+        let bound = stmt.bind(&[DBValue::Integer(1), DBValue::Text(String::from("seq"))]);
+        let update = Statement::Update {
+            table: String::from("tbl"),
+            assignments: vec![(String::from("a"), Expression::Value(DBValue::Integer(1)))],
+            condition: Some(Condition::Literal(ConditionLiteral::Eq(
+                Expression::Field(Selector {
+                    table: None,
+                    field: String::from("b"),
+                }),
+                Expression::Function(
+                    String::from("nextval"),
+                    vec![Expression::Value(DBValue::Text(String::from("seq")))],
+                ),
+            ))),
+        };
+        assert_eq!(bound, Ok(update));
+        assert_eq!(
+            stmt.bind(&[DBValue::Integer(1)]),
+            Err(ParseError::ParameterCount {
+                expected: 2,
+                found: 1
+            })
+        );
+        let bind = Parser::new(".bind 1, 'foo', -2").parse_command();
+        let values = vec![
+            DBValue::Integer(1),
+            DBValue::Text(String::from("foo")),
+            DBValue::Integer(-2),
+        ];
+        assert_eq!(bind, Ok(Command::MetaCommand(MetaCommand::Bind(values))));
+        let bind = Parser::new(".bind 1 2").parse_command();
+        assert_eq!(bind, Err(ParseError::MissingComma));
+    }
+
+    #[test]
+    fn complete_commands() {
+        assert!(Parser::new(".open foo.db").is_complete());
+        assert!(Parser::new("select a\nfrom t; -- done").is_complete());
+        assert!(!Parser::new("select a\nfrom t").is_complete());
+        assert!(!Parser::new("select ';' from t").is_complete());
+        assert!(!Parser::new("insert into t values ('a;").is_complete());
+        assert!(!Parser::new("select a from t /* ; */").is_complete());
     }
 
     #[test]
@@ -507,6 +3113,115 @@ mod tests {
         assert_eq!(cmd, Ok(exit));
     }
 
+    #[test]
+    fn parse_meta_command_mode() {
+        let csv = Parser::new(".mode csv").parse_command();
+        assert_eq!(
+            csv,
+            Ok(Command::MetaCommand(MetaCommand::Mode(OutputMode::Csv)))
+        );
+        let json = Parser::new(".mode JSON").parse_command();
+        assert_eq!(
+            json,
+            Ok(Command::MetaCommand(MetaCommand::Mode(OutputMode::Json)))
+        );
+        let unknown = Parser::new(".mode html").parse_command();
+        assert_eq!(unknown, Err(ParseError::UnrecognizedMode));
+        let missing = Parser::new(".mode").parse_command();
+        assert_eq!(missing, Err(ParseError::UnrecognizedMode));
+    }
+
+    #[test]
+    fn parse_meta_command_headers() {
+        let off = Parser::new(".headers off").parse_command();
+        assert_eq!(off, Ok(Command::MetaCommand(MetaCommand::Headers(false))));
+        let on = Parser::new(".headers ON").parse_command();
+        assert_eq!(on, Ok(Command::MetaCommand(MetaCommand::Headers(true))));
+        let missing = Parser::new(".headers").parse_command();
+        assert_eq!(missing, Err(ParseError::MissingOnOrOff));
+        let extra = Parser::new(".headers on off").parse_command();
+        assert_eq!(extra, Err(ParseError::MissingOnOrOff));
+        let timer = Parser::new(".timer on").parse_command();
+        assert_eq!(timer, Ok(Command::MetaCommand(MetaCommand::Timer(true))));
+    }
+
+    #[test]
+    fn parse_meta_command_export_import() {
+        let export = Parser::new(".export json users users.json").parse_command();
+        let table = String::from("users");
+        let path = Some(String::from("users.json"));
+        let expected = MetaCommand::Export { table, path };
+        assert_eq!(export, Ok(Command::MetaCommand(expected)));
+        let output = Parser::new(".export json users").parse_command();
+        let table = String::from("users");
+        let expected = MetaCommand::Export { table, path: None };
+        assert_eq!(output, Ok(Command::MetaCommand(expected)));
+        let csv = Parser::new(".export csv users").parse_command();
+        assert_eq!(csv, Err(ParseError::InvalidExport));
+        let import = Parser::new(".import --json data/users.json users").parse_command();
+        let path = String::from("data/users.json");
+        let table = String::from("users");
+        let expected = MetaCommand::Import { path, table };
+        assert_eq!(import, Ok(Command::MetaCommand(expected)));
+        let sqlite = Parser::new(".import --sqlite app.sqlite").parse_command();
+        let expected = MetaCommand::ImportSqlite(String::from("app.sqlite"));
+        assert_eq!(sqlite, Ok(Command::MetaCommand(expected)));
+        let plain = Parser::new(".import users.json users").parse_command();
+        assert_eq!(plain, Err(ParseError::InvalidImport));
+    }
+
+    #[test]
+    fn parse_meta_command_width() {
+        let widths = Parser::new(".width 10 0 20").parse_command();
+        let expected = MetaCommand::Width(vec![10, 0, 20]);
+        assert_eq!(widths, Ok(Command::MetaCommand(expected)));
+        let reset = Parser::new(".width").parse_command();
+        assert_eq!(reset, Ok(Command::MetaCommand(MetaCommand::Width(vec![]))));
+        let negative = Parser::new(".width 10 -1").parse_command();
+        assert_eq!(negative, Err(ParseError::InvalidWidth));
+        let word = Parser::new(".width wide").parse_command();
+        assert_eq!(word, Err(ParseError::InvalidWidth));
+    }
+
+    #[test]
+    fn parse_meta_command_nullvalue() {
+        let null = |text: Option<&str>| {
+            let text = text.map(String::from);
+            Ok(Command::MetaCommand(MetaCommand::NullValue(text)))
+        };
+        let word = Parser::new(".nullvalue NULL").parse_command();
+        assert_eq!(word, null(Some("NULL")));
+        let quoted = Parser::new(".nullvalue '(no value)'").parse_command();
+        assert_eq!(quoted, null(Some("(no value)")));
+        let empty = Parser::new(".nullvalue ''").parse_command();
+        assert_eq!(empty, null(Some("")));
+        let reset = Parser::new(".nullvalue").parse_command();
+        assert_eq!(reset, null(None));
+    }
+
+    #[test]
+    fn parse_meta_command_help() {
+        let all = Parser::new(".help").parse_command();
+        assert_eq!(all, Ok(Command::MetaCommand(MetaCommand::Help(None))));
+        let name = Some(String::from("mode"));
+        for input in &[".help mode", ".help .MODE"] {
+            let one = Parser::new(input).parse_command();
+            assert_eq!(
+                one,
+                Ok(Command::MetaCommand(MetaCommand::Help(name.clone())))
+            );
+        }
+    }
+
+    #[test]
+    fn parse_meta_command_schema() {
+        let all = Parser::new(".schema").parse_command();
+        assert_eq!(all, Ok(Command::MetaCommand(MetaCommand::Schema(None))));
+        let table = Parser::new(".schema users").parse_command();
+        let name = Some(String::from("users"));
+        assert_eq!(table, Ok(Command::MetaCommand(MetaCommand::Schema(name))));
+    }
+
     #[test]
     fn parse_meta_command_print() {
         let cmd = Parser::new(".print").parse_command();
@@ -514,9 +3229,83 @@ mod tests {
         assert_eq!(cmd, Ok(print));
     }
 
+    #[test]
+    fn parse_meta_command_checkpoint() {
+        let cmd = Parser::new(".checkpoint").parse_command();
+        let checkpoint = Command::MetaCommand(MetaCommand::Checkpoint);
+        assert_eq!(cmd, Ok(checkpoint));
+    }
+
+    #[test]
+    fn parse_meta_command_open_and_save() {
+        let open = Parser::new(".open /tmp/foo.db").parse_command();
+        let quoted = Parser::new(".open 'my db.db'").parse_command();
+        let missing = Parser::new(".open").parse_command();
+        let save = Parser::new(".save").parse_command();
+        let path = String::from("/tmp/foo.db");
+        let key = None;
+        assert_eq!(
+            open,
+            Ok(Command::MetaCommand(MetaCommand::Open { path, key }))
+        );
+        let path = String::from("my db.db");
+        let key = None;
+        assert_eq!(
+            quoted,
+            Ok(Command::MetaCommand(MetaCommand::Open { path, key }))
+        );
+        let encrypted = Parser::new(".open my.db key 'top secret'").parse_command();
+        let path = String::from("my.db");
+        let key = Some(String::from("top secret"));
+        assert_eq!(
+            encrypted,
+            Ok(Command::MetaCommand(MetaCommand::Open { path, key }))
+        );
+        assert_eq!(missing, Err(ParseError::MissingFileName));
+        assert_eq!(save, Ok(Command::MetaCommand(MetaCommand::Save)));
+        let backup = Parser::new(".backup foo.bak").parse_command();
+        let path = String::from("foo.bak");
+        assert_eq!(backup, Ok(Command::MetaCommand(MetaCommand::Backup(path))));
+        let read = Parser::new(".read schema.sql").parse_command();
+        let path = String::from("schema.sql");
+        assert_eq!(read, Ok(Command::MetaCommand(MetaCommand::Read(path))));
+        let stats = Parser::new(".stats").parse_command();
+        assert_eq!(stats, Ok(Command::MetaCommand(MetaCommand::Stats)));
+    }
+
+    #[test]
+    fn statements_render_as_sql() {
+        let statements = [
+            "select (a, (b + 1) collate nocase, count()) from t join u on (t.id = u.id) \
+             where ((a > 1) and (not (b regexp '^x'))) group by a order by a desc, b asc \
+             limit 10 offset 5",
+            "create table t (id integer primary key autoincrement, \
+             name text not null default 'x' collate nocase, state text check in ('a', 'b'), \
+             parent integer references t (id) on delete set null, d decimal(10, 2))",
+            "create view v as select (a) from t where (a <> date '2020-01-02')",
+            "select * from t where (a = 1) limit 1 offset 0",
+            "create unique index i on t (a)",
+            "create sequence s start with 5 increment by -1",
+            "insert into t (a, b) values (-1, nextval('s'))",
+            "update t set a = (a * 2.5) where (b = null)",
+            "delete from t",
+            "rollback to savepoint p",
+        ];
+        for sql in statements.iter() {
+            let statement = Parser::new(&format!("{};", sql)).parse_command();
+            let statement = match statement {
+                Ok(Command::Statement(statement)) => statement,
+                other => panic!("{:?}", other),
+            };
+            let rendered = statement.to_string();
+            let reparsed = Parser::new(&format!("{};", rendered)).parse_command();
+            assert_eq!(reparsed, Ok(Command::Statement(statement)), "{}", rendered);
+        }
+    }
+
     #[test]
     fn invalid_identifier_error() {
-        let number = Parser::new("select (0) from tbl;").parse_command();
+        let number = Parser::new("select (col) from 0;").parse_command();
         let symbol = Parser::new("create table & (col integer);").parse_command();
         let underscore = Parser::new("insert into _ (0);").parse_command();
         assert_eq!(number, Err(ParseError::InvalidIdentifier));
@@ -536,6 +3325,38 @@ mod tests {
         assert_eq!(dash, Err(ParseError::InvalidValue));
     }
 
+    #[test]
+    fn integer_boundaries() {
+        let value = |input: &str| match Parser::new(&format!("insert into tbl values ({});", input))
+            .parse_command()
+        {
+            Ok(Command::Statement(Statement::InsertInto { mut values, .. })) => {
+                Ok(values.remove(0))
+            }
+            Ok(other) => panic!("{:?}", other),
+            Err(e) => Err(e),
+        };
+        let integer = |i| Ok(Expression::Value(DBValue::Integer(i)));
+        assert_eq!(value("9223372036854775807"), integer(i64::MAX));
+        assert_eq!(value("-9223372036854775808"), integer(i64::MIN));
+        assert_eq!(value("0"), integer(0));
+        assert_eq!(
+            value("9223372036854775808"),
+            Err(ParseError::IntegerOutOfRange)
+        );
+        assert_eq!(
+            value("-9223372036854775809"),
+            Err(ParseError::IntegerOutOfRange)
+        );
+        assert_eq!(
+            value("123456789012345678901234567890"),
+            Err(ParseError::IntegerOutOfRange)
+        );
+        let sequence =
+            Parser::new("create sequence s start with 99999999999999999999;").parse_command();
+        assert_eq!(sequence, Err(ParseError::IntegerOutOfRange));
+    }
+
     #[test]
     fn missing_semicolon_error() {
         let stmt_select = Parser::new("select (col) from tbl").parse_command();
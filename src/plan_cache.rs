@@ -0,0 +1,138 @@
+//! Cache of the plans of recently executed statements, keyed by their SQL tokens, so that running
+//! the same statement again skips parsing and planning. Entries are tagged with the version of
+//! the schema they were planned against, and are discarded once the schema changes. When the
+//! cache is full, the least recently used entry is evicted.
+
+use crate::lexer;
+use std::collections::HashMap;
+
+/// Number of plans cached by default.
+pub const DEFAULT_PLAN_CACHE_SIZE: usize = 64;
+
+#[derive(Debug)]
+struct Entry<T> {
+    schema_version: u64,
+    last_used: u64,
+    plan: T,
+}
+
+#[derive(Debug)]
+pub struct PlanCache<T> {
+    capacity: usize,
+    /// Counter ordering the uses of entries, the smallest being the least recent.
+    clock: u64,
+    entries: HashMap<String, Entry<T>>,
+}
+
+/// The key under which the plan of a statement is cached: the text of its tokens, as the parser
+/// sees them, separated by single spaces. Statements differing only in whitespace or comments
+/// share a plan, while text in quotes is kept as it is.
+pub fn normalize(sql: &str) -> String {
+    let tokens: Vec<_> = lexer::tokenize(sql)
+        .into_iter()
+        .map(|token| &sql[token.span.start..token.span.end])
+        .collect();
+    tokens.join(" ")
+}
+
+impl<T: Clone> PlanCache<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            clock: 0,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Sets the number of plans to keep, evicting the least recently used ones beyond it. A
+    /// capacity of zero disables the cache.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            self.evict();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The plan cached for the statement, unless it was planned against an older schema.
+    pub fn get(&mut self, sql: &str, schema_version: u64) -> Option<T> {
+        let key = normalize(sql);
+        match self.entries.get_mut(&key) {
+            Some(entry) if entry.schema_version == schema_version => {
+                self.clock += 1;
+                entry.last_used = self.clock;
+                Some(entry.plan.clone())
+            }
+            Some(_) => {
+                self.entries.remove(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&mut self, sql: &str, schema_version: u64, plan: T) {
+        if self.capacity == 0 {
+            return;
+        }
+        let key = normalize(sql);
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.capacity {
+            self.evict();
+        }
+        self.clock += 1;
+        let entry = Entry {
+            schema_version,
+            last_used: self.clock,
+            plan,
+        };
+        self.entries.insert(key, entry);
+    }
+
+    fn evict(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = oldest {
+            self.entries.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn least_recently_used_eviction() {
+        assert_eq!(
+            normalize("  select a,\n\tb  from t where c = 'x  y' ; "),
+            "select a , b from t where c = 'x  y' ;"
+        );
+        assert_eq!(
+            normalize("select a -- the first\nfrom /* all of */ t where c<>'--';"),
+            normalize("select a from t where c <> '--';")
+        );
+        assert_ne!(normalize("select 1.5;"), normalize("select 1 . 5;"));
+        let mut cache = PlanCache::new(2);
+        cache.insert("select 1;", 0, 1);
+        cache.insert("select 2;", 0, 2);
+        assert_eq!(cache.get("select  1;", 0), Some(1));
+        cache.insert("select 3;", 0, 3);
+        assert_eq!(cache.get("select 2;", 0), None);
+        assert_eq!(cache.get("select 1;", 0), Some(1));
+        assert_eq!(cache.get("select 3;", 1), None);
+        assert_eq!(cache.len(), 1);
+        cache.set_capacity(0);
+        cache.insert("select 1;", 1, 1);
+        assert!(cache.is_empty());
+    }
+}
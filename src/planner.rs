@@ -0,0 +1,490 @@
+//! Cost-based decisions of the query planner: the order in which to join tables, how to join
+//! each of them, and whether to look rows up in an index or scan the table. Decisions are based
+//! on estimated row counts, using the statistics collected by 'analyze' where available and
+//! fixed guesses otherwise. Costs are in units of rows read in a scan.
+
+use crate::db::*;
+use crate::parser::*;
+use crate::query_processor::resolve_selector;
+
+/// Cost of fetching a row through an index, relative to reading it in a scan.
+const INDEX_ROW_COST: f64 = 4.0;
+
+/// Cost of inserting a row into the hash table of a hash join, relative to reading it in a scan.
+const HASH_ROW_COST: f64 = 2.0;
+
+/// Fraction of rows assumed to satisfy an equality without statistics on its columns.
+const EQUALITY_SELECTIVITY: f64 = 0.1;
+
+/// Fraction of rows assumed to satisfy a comparison such as `<`, without statistics.
+const RANGE_SELECTIVITY: f64 = 1.0 / 3.0;
+
+/// Fraction of rows assumed to satisfy any other condition.
+const DEFAULT_SELECTIVITY: f64 = 0.5;
+
+/// A table of a query, by the name it is referred to in the query.
+pub type Source<'a> = (&'a str, &'a Table);
+
+/// A conjunct of the conditions of a query, with the tables, as positions in the sources of the
+/// query, that it refers to.
+pub type Conjunct<'a> = (&'a Condition, Vec<usize>);
+
+/// How to join a table to the rows of the tables before it. Columns of the tables joined before
+/// are given by the position of their table in the sources and their index in its schema.
+#[derive(Clone, Copy, Debug)]
+pub enum JoinMethod<'a> {
+    /// Compare every pair of rows.
+    NestedLoop,
+    /// Hash the rows of the table on its column `right`, and probe with the column `left` of the
+    /// rows joined so far.
+    Hash { left: (usize, usize), right: usize },
+    /// Merge the rows joined so far, ordered by their column `left`, with the rows of the table,
+    /// ordered by its column `right`.
+    Merge { left: (usize, usize), right: usize },
+    /// Look up the column `left` of the rows joined so far in the index of the table.
+    Index {
+        left: (usize, usize),
+        index: &'a Index,
+    },
+}
+
+/// Where the conditions of a query are checked in a pipeline joining its tables, as decided by
+/// [`plan_joins`].
+pub struct JoinPlan<'q> {
+    /// The order in which to join the tables, as positions in the sources.
+    pub order: Vec<usize>,
+    /// The conjuncts referring to a single table, per source, to check when reading the table.
+    pub filters: Vec<Vec<&'q Condition>>,
+    /// The conjuncts referring to several tables, per step of the order, to check when joining
+    /// the table of the step.
+    pub steps: Vec<Vec<&'q Condition>>,
+}
+
+/// Splits a condition into the conditions it is a conjunction of.
+pub fn conjuncts(condition: &Condition) -> Vec<&Condition> {
+    match condition {
+        Condition::And(c1, c2) => {
+            let mut found = conjuncts(c1);
+            found.extend(conjuncts(c2));
+            found
+        }
+        condition => vec![condition],
+    }
+}
+
+/// The conjunction of the conditions, if there are any.
+pub fn conjunction(conditions: &[&Condition]) -> Option<Condition> {
+    conditions
+        .iter()
+        .map(|condition| (*condition).clone())
+        .reduce(|c1, c2| Condition::And(Box::new(c1), Box::new(c2)))
+}
+
+/// Finds the column a selector refers to, as the position of its table in the sources and its
+/// index in the schema of the table.
+pub fn locate(selector: &Selector, sources: &[Source]) -> Option<(usize, usize)> {
+    sources.iter().enumerate().find_map(|(i, (name, table))| {
+        resolve_selector(selector, name, table.schema())
+            .ok()
+            .map(|column| (i, column))
+    })
+}
+
+/// The tables a condition refers to, as positions in the sources.
+pub fn tables_of(condition: &Condition, sources: &[Source]) -> Vec<usize> {
+    let mut tables: Vec<_> = condition
+        .selectors()
+        .into_iter()
+        .filter_map(|selector| locate(selector, sources))
+        .map(|(table, _)| table)
+        .collect();
+    tables.sort_unstable();
+    tables.dedup();
+    tables
+}
+
+/// Estimated number of rows in the table.
+pub fn table_rows(table: &Table) -> f64 {
+    table
+        .statistics()
+        .map_or(table.len(), |statistics| statistics.rows) as f64
+}
+
+fn column_statistics(table: &Table, column: usize) -> Option<&ColumnStatistics> {
+    table.statistics()?.columns.get(column)
+}
+
+/// Estimated fraction of the rows with the given value in the column: one over the number of
+/// distinct values.
+fn equality_selectivity(table: &Table, column: usize) -> f64 {
+    match column_statistics(table, column) {
+        Some(statistics) => 1.0 / statistics.distinct.max(1) as f64,
+        None => EQUALITY_SELECTIVITY,
+    }
+}
+
+/// Estimated fraction of the rows with a value in the column below the given one, assuming the
+/// values are spread evenly between the least and the greatest.
+fn below_selectivity(table: &Table, column: usize, value: &DBValue) -> f64 {
+    let bounds = column_statistics(table, column).and_then(|statistics| {
+        let min = statistics.min.as_ref()?.as_f64()?;
+        let max = statistics.max.as_ref()?.as_f64()?;
+        Some((min, max, value.as_f64()?))
+    });
+    match bounds {
+        Some((min, max, value)) if max > min => ((value - min) / (max - min)).clamp(0.0, 1.0),
+        Some((_, max, value)) => {
+            if value > max {
+                1.0
+            } else {
+                0.0
+            }
+        }
+        None => RANGE_SELECTIVITY,
+    }
+}
+
+/// Estimated fraction of the rows of the joined tables satisfying a conjunct.
+pub fn selectivity(condition: &Condition, sources: &[Source]) -> f64 {
+    let column = |expression: &Expression| match expression {
+        Expression::Field(selector) => locate(selector, sources),
+        _ => None,
+    };
+    let table = |table: usize| sources[table].1;
+    match condition {
+        Condition::Literal(ConditionLiteral::Eq(lhs, rhs)) => match (column(lhs), column(rhs)) {
+            (Some((t1, c1)), Some((t2, c2))) => {
+                equality_selectivity(table(t1), c1).min(equality_selectivity(table(t2), c2))
+            }
+            (Some((t, c)), None) | (None, Some((t, c))) => equality_selectivity(table(t), c),
+            (None, None) => EQUALITY_SELECTIVITY,
+        },
+        Condition::Literal(ConditionLiteral::Lt(lhs, rhs))
+        | Condition::Literal(ConditionLiteral::Lte(lhs, rhs)) => match (column(lhs), rhs) {
+            (Some((t, c)), Expression::Value(value)) => below_selectivity(table(t), c, value),
+            _ => RANGE_SELECTIVITY,
+        },
+        Condition::Literal(ConditionLiteral::Gt(lhs, rhs))
+        | Condition::Literal(ConditionLiteral::Gte(lhs, rhs)) => match (column(lhs), rhs) {
+            (Some((t, c)), Expression::Value(value)) => 1.0 - below_selectivity(table(t), c, value),
+            _ => RANGE_SELECTIVITY,
+        },
+        _ => DEFAULT_SELECTIVITY,
+    }
+}
+
+/// Whether to look up rows with a value in the column through an index, rather than scanning
+/// the whole table. Indexes are always used until the table has been analyzed.
+pub fn prefer_index(table: &Table, index: &Index) -> bool {
+    if index.is_unique() || table.statistics().is_none() {
+        return true;
+    }
+    let rows = table_rows(table);
+    rows * equality_selectivity(table, index.column()) * INDEX_ROW_COST < rows
+}
+
+/// Chooses the order in which to join the sources, greedily: starting with the table with the
+/// fewest estimated rows after its own conditions, then adding the table that keeps the
+/// estimated number of joined rows lowest. Ties are broken by the order in the query.
+pub fn join_order(sources: &[Source], conjuncts: &[Conjunct]) -> Vec<usize> {
+    let estimate = |joined: &[usize], next: usize| {
+        let mut rows: f64 = joined
+            .iter()
+            .chain(Some(&next))
+            .map(|&table| table_rows(sources[table].1))
+            .product();
+        for (condition, tables) in conjuncts {
+            if tables
+                .iter()
+                .all(|table| *table == next || joined.contains(table))
+            {
+                rows *= selectivity(condition, sources);
+            }
+        }
+        rows
+    };
+    let mut order: Vec<usize> = Vec::new();
+    while order.len() < sources.len() {
+        let next = (0..sources.len())
+            .filter(|table| !order.contains(table))
+            .map(|table| (table, estimate(&order, table)))
+            .fold(
+                None,
+                |best: Option<(usize, f64)>, (table, rows)| match best {
+                    Some((_, fewest)) if fewest <= rows => best,
+                    _ => Some((table, rows)),
+                },
+            );
+        match next {
+            Some((table, _)) => order.push(table),
+            None => break,
+        }
+    }
+    order
+}
+
+/// Decides where to check the conditions of a query over the sources, given as the conjuncts of
+/// the join conditions and the 'where'-clause; since all joins are inner joins, these are
+/// interchangeable. Conjuncts referring to a single table are pushed down to the reading of the
+/// table, where they may use an index and cut down the rows to join. The other conjuncts are
+/// checked as soon as all the tables they refer to have been joined.
+pub fn plan_joins<'q>(sources: &[Source], conditions: Vec<&'q Condition>) -> JoinPlan<'q> {
+    let conjuncts = conjuncts_of(sources, conditions);
+    let order = join_order(sources, &conjuncts);
+    place_conjuncts(sources, conjuncts, order)
+}
+
+/// Like [`plan_joins`], but joining the sources in the given order, as decided by an earlier
+/// plan of the same query.
+pub fn plan_joins_in_order<'q>(
+    sources: &[Source],
+    conditions: Vec<&'q Condition>,
+    order: Vec<usize>,
+) -> JoinPlan<'q> {
+    let conjuncts = conjuncts_of(sources, conditions);
+    place_conjuncts(sources, conjuncts, order)
+}
+
+fn conjuncts_of<'q>(sources: &[Source], conditions: Vec<&'q Condition>) -> Vec<Conjunct<'q>> {
+    conditions
+        .into_iter()
+        .flat_map(conjuncts)
+        .map(|condition| (condition, tables_of(condition, sources)))
+        .collect()
+}
+
+fn place_conjuncts<'q>(
+    sources: &[Source],
+    conjuncts: Vec<Conjunct<'q>>,
+    order: Vec<usize>,
+) -> JoinPlan<'q> {
+    let mut filters = vec![Vec::new(); sources.len()];
+    let mut steps = vec![Vec::new(); order.len()];
+    for (condition, tables) in conjuncts {
+        match tables[..] {
+            [] => filters[order[0]].push(condition),
+            [table] => filters[table].push(condition),
+            _ => {
+                let step = tables
+                    .iter()
+                    .filter_map(|table| order.iter().position(|t| t == table))
+                    .max()
+                    .unwrap_or(0);
+                steps[step].push(condition);
+            }
+        }
+    }
+    JoinPlan {
+        order,
+        filters,
+        steps,
+    }
+}
+
+/// The columns of each source that the query refers to through the selectors, in ascending
+/// order. Only these columns need to be read from the tables.
+pub fn referenced_columns<'s>(
+    selectors: impl Iterator<Item = &'s Selector>,
+    sources: &[Source],
+) -> Vec<Vec<usize>> {
+    let mut columns = vec![Vec::new(); sources.len()];
+    for (table, column) in selectors.filter_map(|selector| locate(selector, sources)) {
+        columns[table].push(column);
+    }
+    for columns in &mut columns {
+        columns.sort_unstable();
+        columns.dedup();
+    }
+    columns
+}
+
+/// The column a scan of the table produces rows in ascending order of, if any: the primary key,
+/// by which the rows are clustered. Keys are ordered as [`DBValue`]s, which agrees with equality
+/// in conditions only for columns with the binary collation and without an enum constraint.
+pub fn clustered_on(table: &Table) -> Option<usize> {
+    table.primary_key().filter(|&key| {
+        let column = table.schema().column(key);
+        column.collation == Collation::Binary && column.allowed_values.is_none()
+    })
+}
+
+/// Chooses how to join the table `next` to the rows of the `joined` tables, given the conjuncts
+/// to check when joining it. Hash, merge and index joins need an equality between columns of the
+/// same type and collation, one of `next` and one of a joined table. A merge join further needs
+/// the joined rows to be in order of their column, given in `ordered`, and the table to be
+/// clustered on its column.
+pub fn join_method<'a>(
+    sources: &[Source<'a>],
+    joined: &[usize],
+    next: usize,
+    conjuncts: &[&Condition],
+    ordered: &[(usize, usize)],
+) -> JoinMethod<'a> {
+    let left_rows: f64 = joined
+        .iter()
+        .map(|&table| table_rows(sources[table].1))
+        .product();
+    let table = sources[next].1;
+    let right_rows = table_rows(table);
+    let mut best = (JoinMethod::NestedLoop, left_rows * right_rows);
+    for condition in conjuncts {
+        let (lhs, rhs) = match condition {
+            Condition::Literal(ConditionLiteral::Eq(
+                Expression::Field(lhs),
+                Expression::Field(rhs),
+            )) => (lhs, rhs),
+            _ => continue,
+        };
+        let (left, right) = match (locate(lhs, sources), locate(rhs, sources)) {
+            (Some(left), Some(right)) if right.0 == next && joined.contains(&left.0) => {
+                (left, right)
+            }
+            (Some(right), Some(left)) if right.0 == next && joined.contains(&left.0) => {
+                (left, right)
+            }
+            _ => continue,
+        };
+        let left_column = sources[left.0].1.schema().column(left.1);
+        let right_column = table.schema().column(right.1);
+        if left_column.db_type != right_column.db_type
+            || left_column.collation != right_column.collation
+        {
+            continue;
+        }
+        let right = right.1;
+        let hash_cost = left_rows + right_rows * HASH_ROW_COST;
+        if hash_cost < best.1 {
+            best = (JoinMethod::Hash { left, right }, hash_cost);
+        }
+        let merge_cost = left_rows + right_rows;
+        if ordered.contains(&left) && clustered_on(table) == Some(right) && merge_cost < best.1 {
+            best = (JoinMethod::Merge { left, right }, merge_cost);
+        }
+        let index = table
+            .indexes()
+            .values()
+            .find(|index| index.column() == right);
+        if let Some(index) = index {
+            let matches = if index.is_unique() {
+                1.0
+            } else {
+                right_rows * equality_selectivity(table, right)
+            };
+            let index_cost = left_rows * (1.0 + matches * INDEX_ROW_COST);
+            if index_cost < best.1 {
+                best = (JoinMethod::Index { left, index }, index_cost);
+            }
+        }
+    }
+    best.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Table with an integer column `id` holding 0 to `rows` - 1 and a boolean column `flag`,
+    /// indexed on both.
+    fn table(rows: i64) -> Table {
+        let mut table = Table::new(Schema::from(vec![
+            (String::from("id"), DBType::Integer),
+            (String::from("flag"), DBType::Boolean),
+        ]));
+        for id in 0..rows {
+            table
+                .push(vec![DBValue::Integer(id), DBValue::Boolean(id % 2 == 0)])
+                .unwrap();
+        }
+        table.create_index(String::from("by_id"), 0, false).unwrap();
+        table
+            .create_index(String::from("by_flag"), 1, false)
+            .unwrap();
+        table
+    }
+
+    fn index<'a>(table: &'a Table, name: &str) -> &'a Index {
+        &table.indexes()[name]
+    }
+
+    #[test]
+    fn index_or_scan() {
+        let mut big = table(100);
+        assert!(prefer_index(&big, index(&big, "by_flag")));
+        big.analyze().unwrap();
+        assert!(!prefer_index(&big, index(&big, "by_flag")));
+        assert!(prefer_index(&big, index(&big, "by_id")));
+        let range = Condition::Literal(ConditionLiteral::Lt(
+            Expression::Field(Selector {
+                table: None,
+                field: String::from("id"),
+            }),
+            Expression::Value(DBValue::Integer(25)),
+        ));
+        let sources = [("big", &big)];
+        assert!((selectivity(&range, &sources) - 25.0 / 99.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn join_order_and_methods() {
+        let (mut big, mut small) = (table(100), table(3));
+        big.analyze().unwrap();
+        small.analyze().unwrap();
+        let sources = [("big", &big), ("small", &small)];
+        let field = |table: &str, field: &str| {
+            Expression::Field(Selector {
+                table: Some(String::from(table)),
+                field: String::from(field),
+            })
+        };
+        let on_id = Condition::Literal(ConditionLiteral::Eq(
+            field("big", "id"),
+            field("small", "id"),
+        ));
+        let conjuncts = [(&on_id, tables_of(&on_id, &sources))];
+        assert_eq!(join_order(&sources, &conjuncts), vec![1, 0]);
+        let method = join_method(&sources, &[1], 0, &[&on_id], &[]);
+        let expected_left = (1, 0);
+        assert!(matches!(
+            method,
+            JoinMethod::Index { left, index } if left == expected_left && index.column() == 0
+        ));
+        let on_flag = Condition::Literal(ConditionLiteral::Eq(
+            field("big", "flag"),
+            field("small", "flag"),
+        ));
+        let method = join_method(&sources, &[0], 1, &[&on_flag], &[]);
+        assert!(matches!(
+            method,
+            JoinMethod::Hash {
+                left: (0, 1),
+                right: 1
+            }
+        ));
+        let method = join_method(&sources, &[0], 1, &[], &[]);
+        assert!(matches!(method, JoinMethod::NestedLoop));
+    }
+
+    #[test]
+    fn pushdown_and_pruning() {
+        let (t1, t2) = (table(10), table(10));
+        let sources = [("t1", &t1), ("t2", &t2)];
+        let field = |table: &str, field: &str| {
+            Expression::Field(Selector {
+                table: Some(String::from(table)),
+                field: String::from(field),
+            })
+        };
+        let value = Expression::Value(DBValue::Boolean(true));
+        let on = Condition::Literal(ConditionLiteral::Eq(field("t1", "id"), field("t2", "id")));
+        let local = Condition::Literal(ConditionLiteral::Eq(field("t2", "flag"), value.clone()));
+        let constant = Condition::Literal(ConditionLiteral::Eq(value.clone(), value));
+        let condition = Condition::And(Box::new(local.clone()), Box::new(constant.clone()));
+        let plan = plan_joins(&sources, vec![&on, &condition]);
+        assert_eq!(plan.order, vec![1, 0]);
+        assert_eq!(plan.filters, vec![vec![], vec![&local, &constant]]);
+        assert_eq!(plan.steps, vec![vec![], vec![&on]]);
+        let selectors = on.selectors();
+        let columns = referenced_columns(selectors.into_iter(), &sources);
+        assert_eq!(columns, vec![vec![0], vec![0]]);
+    }
+}
@@ -0,0 +1,131 @@
+//! Keeping long statements in check: a statement counts the expressions it evaluates as steps,
+//! calling a progress handler every so many steps, and fails with
+//! [`StorageError::Interrupted`] once interrupted, either by the handler or from another thread
+//! through an [`InterruptHandle`], e.g.
+//!
+//! ```
+//! use juicydb::storage_manager::StorageError;
+//! use std::ops::ControlFlow;
+//!
+//! let mut db = juicydb::Database::new();
+//! db.execute("create table t (a integer);")?;
+//! db.execute("create table u (b integer);")?;
+//! for i in 0..100 {
+//!     let insert = format!("insert into t values ({0}); insert into u values ({0});", i);
+//!     db.execute_batch(&insert, false)?;
+//! }
+//! // Gives up after a thousand steps
+//! let mut calls = 0;
+//! db.set_progress_handler(100, move || {
+//!     calls += 1;
+//!     if calls < 10 {
+//!         ControlFlow::Continue(())
+//!     } else {
+//!         ControlFlow::Break(())
+//!     }
+//! });
+//! let join = db.query("select t.a from t join u on t.a < u.b;");
+//! assert!(matches!(
+//!     join,
+//!     Err(juicydb::Error::Storage(StorageError::Interrupted))
+//! ));
+//! # Ok::<(), juicydb::Error>(())
+//! ```
+
+use crate::storage_manager::StorageError;
+use std::fmt;
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+type Handler = Box<dyn FnMut() -> ControlFlow<()> + Send>;
+
+/// Interrupts the statements of a session from any thread; see [`Progress`].
+#[derive(Clone, Debug)]
+pub struct InterruptHandle {
+    interrupted: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    /// Makes the statement running in the session fail with [`StorageError::Interrupted`] at its
+    /// next step. Does nothing if no statement is running.
+    pub fn interrupt(&self) {
+        self.interrupted.store(true, Ordering::SeqCst);
+    }
+}
+
+/// The progress of the statement running in a session. Statements may evaluate expressions on
+/// several threads, which share the progress.
+#[derive(Default)]
+pub struct Progress {
+    interrupted: Arc<AtomicBool>,
+    steps: AtomicU64,
+    /// Called every so many steps, interrupting the statement if it breaks.
+    handler: Option<(u64, Mutex<Handler>)>,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        InterruptHandle {
+            interrupted: Arc::clone(&self.interrupted),
+        }
+    }
+
+    /// Sets the handler called every `steps` steps of a statement; zero removes it.
+    pub fn set_handler(
+        &mut self,
+        steps: u64,
+        handler: impl FnMut() -> ControlFlow<()> + Send + 'static,
+    ) {
+        self.handler = match steps {
+            0 => None,
+            steps => Some((steps, Mutex::new(Box::new(handler)))),
+        };
+    }
+
+    pub fn clear_handler(&mut self) {
+        self.handler = None;
+    }
+
+    /// Starts counting the steps of a new statement, forgetting any interrupt of the previous
+    /// one.
+    pub fn reset(&self) {
+        self.interrupted.store(false, Ordering::SeqCst);
+        self.steps.store(0, Ordering::Relaxed);
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted.load(Ordering::Relaxed)
+    }
+
+    /// Counts a step, failing if the statement is interrupted.
+    pub fn step(&self) -> Result<(), StorageError> {
+        if self.is_interrupted() {
+            return Err(StorageError::Interrupted);
+        }
+        if let Some((interval, handler)) = &self.handler {
+            let steps = self.steps.fetch_add(1, Ordering::Relaxed) + 1;
+            if steps.is_multiple_of(*interval) {
+                let mut handler = handler.lock().unwrap_or_else(|err| err.into_inner());
+                if handler().is_break() {
+                    self.interrupted.store(true, Ordering::SeqCst);
+                    return Err(StorageError::Interrupted);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Progress")
+            .field("interrupted", &self.is_interrupted())
+            .field("handler", &self.handler.as_ref().map(|(steps, _)| steps))
+            .finish()
+    }
+}
@@ -0,0 +1,752 @@
+use crate::btree::Key;
+use crate::datetime::{self, Unit, MICROS_PER_DAY, MICROS_PER_SECOND};
+use crate::db::*;
+use crate::decimal::{Decimal, DIVISION_SCALE, MAX_PRECISION};
+use crate::functions::Functions;
+use crate::parallel::map_chunks;
+use crate::parser::*;
+use crate::planner::prefer_index;
+use crate::progress::Progress;
+use crate::storage_manager::StorageError;
+use crate::uuid::Uuid;
+#[cfg(feature = "regexp")]
+use regex::Regex;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io;
+#[cfg(feature = "regexp")]
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Everything needed to evaluate expressions over the rows of a table, besides the rows
+/// themselves: the names and schemas of the tables for resolving field selectors, the sequences
+/// of the database for `nextval`, and the functions registered with it. A scope lives for the duration of a statement, so it
+/// also caches the compiled patterns of 'regexp'-operators. Scopes may be shared by the threads
+/// of a parallel query.
+///
+/// Rows of a scope with joined tables are the rows of the tables concatenated, in the order of
+/// the tables in the scope.
+pub struct Scope<'a> {
+    pub tables: Vec<(Cow<'a, str>, Cow<'a, Schema>)>,
+    pub sequences: &'a HashMap<String, Sequence>,
+    pub functions: &'a Functions,
+    /// Counts the expressions evaluated, to interrupt the statement; see [`crate::progress`].
+    pub progress: &'a Progress,
+    #[cfg(feature = "regexp")]
+    pub regexes: Mutex<HashMap<String, Arc<Regex>>>,
+}
+
+impl<'a> Scope<'a> {
+    /// Adds a joined table to the scope, its columns following those of the other tables.
+    pub fn join(&mut self, table: impl Into<Cow<'a, str>>, schema: Cow<'a, Schema>) {
+        self.tables.push((table.into(), schema));
+    }
+
+    /// Whether `name` is an aggregate function, built-in or registered.
+    pub fn is_aggregate(&self, name: &str) -> bool {
+        is_aggregate(name) || self.functions.is_aggregate(name)
+    }
+
+    /// Resolves a field selector into a position in the rows of the scope. An unqualified field
+    /// must be a column of exactly one of the tables.
+    pub fn resolve(&self, selector: &Selector) -> Result<usize, StorageError> {
+        self.locate(selector)
+            .map(|(offset, _, index)| offset + index)
+    }
+
+    /// Finds the column a selector refers to, as the offset of its table in the rows of the
+    /// scope, the schema of its table and its index in the schema.
+    fn locate(&self, selector: &Selector) -> Result<(usize, &Schema, usize), StorageError> {
+        let mut found = None;
+        let mut offset = 0;
+        for (table, schema) in &self.tables {
+            if let Ok(index) = resolve_selector(selector, table, schema) {
+                if found.is_some() {
+                    return Err(StorageError::AmbiguousColumn(selector.field.clone()));
+                }
+                found = Some((offset, &**schema, index));
+            }
+            offset += schema.len();
+        }
+        found.ok_or(StorageError::SchemaMismatch)
+    }
+}
+
+/// Evaluates a [`Condition`] against a row of the tables in scope. Field selectors are resolved
+/// using the schemas of the tables; selectors qualified with some other table name are rejected.
+///
+/// Conditions follow SQL three-valued logic: comparisons involving null are unknown (`None`), and
+/// unknown propagates through the connectives unless the result is determined by the other
+/// operand, e.g. `false and unknown` is false. A 'where'-clause accepts only rows for which the
+/// condition is true.
+pub fn eval_condition(
+    condition: &Condition,
+    scope: &Scope,
+    row: &[DBValue],
+) -> Result<Option<bool>, StorageError> {
+    match condition {
+        Condition::Literal(literal) => eval_literal(literal, scope, row),
+        Condition::Expression(expression) => match eval_expression(expression, scope, row)? {
+            DBValue::Boolean(b) => Ok(Some(b)),
+            DBValue::Null => Ok(None),
+            _ => Err(StorageError::TypeError),
+        },
+        Condition::Not(c) => Ok(eval_condition(c, scope, row)?.map(|b| !b)),
+        Condition::And(c1, c2) => match eval_condition(c1, scope, row)? {
+            Some(false) => Ok(Some(false)),
+            lhs => match eval_condition(c2, scope, row)? {
+                Some(false) => Ok(Some(false)),
+                rhs => Ok(lhs.and(rhs)),
+            },
+        },
+        Condition::Or(c1, c2) => match eval_condition(c1, scope, row)? {
+            Some(true) => Ok(Some(true)),
+            lhs => match eval_condition(c2, scope, row)? {
+                Some(true) => Ok(Some(true)),
+                rhs => Ok(lhs.and(rhs)),
+            },
+        },
+    }
+}
+
+fn eval_literal(
+    literal: &ConditionLiteral,
+    scope: &Scope,
+    row: &[DBValue],
+) -> Result<Option<bool>, StorageError> {
+    let (lhs, rhs, accept): (_, _, fn(Ordering) -> bool) = match literal {
+        ConditionLiteral::Regexp(text, pattern) => {
+            let text = eval_expression(text, scope, row)?;
+            let pattern = eval_expression(pattern, scope, row)?;
+            return eval_regexp(text, pattern, scope);
+        }
+        ConditionLiteral::Eq(lhs, rhs) => (lhs, rhs, |o| o == Ordering::Equal),
+        ConditionLiteral::Neq(lhs, rhs) => (lhs, rhs, |o| o != Ordering::Equal),
+        ConditionLiteral::Lt(lhs, rhs) => (lhs, rhs, |o| o == Ordering::Less),
+        ConditionLiteral::Lte(lhs, rhs) => (lhs, rhs, |o| o != Ordering::Greater),
+        ConditionLiteral::Gt(lhs, rhs) => (lhs, rhs, |o| o == Ordering::Greater),
+        ConditionLiteral::Gte(lhs, rhs) => (lhs, rhs, |o| o != Ordering::Less),
+    };
+    let collation = comparison_collation(lhs, rhs, scope);
+    let lhs = eval_expression(lhs, scope, row)?;
+    let rhs = eval_expression(rhs, scope, row)?;
+    if lhs == DBValue::Null || rhs == DBValue::Null {
+        return Ok(None);
+    }
+    Ok(Some(accept(compare(&lhs, &rhs, collation)?)))
+}
+
+/// Matches text against a regular expression, compiling each distinct pattern once per scope.
+#[cfg(feature = "regexp")]
+fn eval_regexp(
+    text: DBValue,
+    pattern: DBValue,
+    scope: &Scope,
+) -> Result<Option<bool>, StorageError> {
+    match (text, pattern) {
+        (DBValue::Null, _) | (_, DBValue::Null) => Ok(None),
+        (DBValue::Text(text), DBValue::Text(pattern)) => {
+            let mut regexes = scope.regexes.lock().unwrap_or_else(PoisonError::into_inner);
+            let regex = match regexes.get(&pattern) {
+                Some(regex) => Arc::clone(regex),
+                None => {
+                    let regex = Regex::new(&pattern)
+                        .map_err(|err| StorageError::InvalidPattern(err.to_string()))?;
+                    let regex = Arc::new(regex);
+                    regexes.insert(pattern, Arc::clone(&regex));
+                    regex
+                }
+            };
+            // Match without holding the lock, for scopes shared by threads
+            drop(regexes);
+            Ok(Some(regex.is_match(&text)))
+        }
+        _ => Err(StorageError::TypeError),
+    }
+}
+
+#[cfg(not(feature = "regexp"))]
+fn eval_regexp(_: DBValue, _: DBValue, _: &Scope) -> Result<Option<bool>, StorageError> {
+    Err(StorageError::FunctionNotFound(String::from("regexp")))
+}
+
+/// Collation of a comparison: an explicit 'collate' on either operand, the left one first, takes
+/// precedence over the collation of a column operand. Otherwise text is compared as binary.
+pub fn comparison_collation(lhs: &Expression, rhs: &Expression, scope: &Scope) -> Collation {
+    explicit_collation(lhs)
+        .or_else(|| explicit_collation(rhs))
+        .or_else(|| column_collation(lhs, scope))
+        .or_else(|| column_collation(rhs, scope))
+        .unwrap_or(Collation::Binary)
+}
+
+fn explicit_collation(expression: &Expression) -> Option<Collation> {
+    match expression {
+        Expression::Collate(_, collation) => Some(*collation),
+        _ => None,
+    }
+}
+
+/// Collation of the value of an expression when sorting or grouping by it.
+fn expression_collation(expression: &Expression, scope: &Scope) -> Collation {
+    explicit_collation(expression)
+        .or_else(|| column_collation(expression, scope))
+        .unwrap_or(Collation::Binary)
+}
+
+fn column_collation(expression: &Expression, scope: &Scope) -> Option<Collation> {
+    match expression {
+        Expression::Field(selector) => scope
+            .locate(selector)
+            .ok()
+            .map(|(_, schema, index)| schema.get_collation(index)),
+        _ => None,
+    }
+}
+
+/// Evaluates an [`Expression`] against a row of the table in scope. To evaluate expressions
+/// outside of any row, e.g. values in an 'insert'-statement, pass an empty row; any field
+/// selectors then fail to resolve.
+pub fn eval_expression(
+    expression: &Expression,
+    scope: &Scope,
+    row: &[DBValue],
+) -> Result<DBValue, StorageError> {
+    scope.progress.step()?;
+    match expression {
+        Expression::Value(value) => Ok(value.clone()),
+        Expression::Field(selector) => {
+            let index = scope.resolve(selector)?;
+            row.get(index).cloned().ok_or(StorageError::SchemaMismatch)
+        }
+        Expression::Function(name, args) => {
+            let mut values = Vec::new();
+            for arg in args {
+                values.push(eval_expression(arg, scope, row)?);
+            }
+            eval_function(name, values, scope)
+        }
+        Expression::Parameter(_) => Err(StorageError::UnboundParameter),
+        Expression::Condition(condition) => {
+            Ok(eval_condition(condition, scope, row)?.map_or(DBValue::Null, DBValue::Boolean))
+        }
+        Expression::Arithmetic(operator, lhs, rhs) => eval_arithmetic(
+            *operator,
+            eval_expression(lhs, scope, row)?,
+            eval_expression(rhs, scope, row)?,
+        ),
+        Expression::Collate(expression, _) => eval_expression(expression, scope, row),
+    }
+}
+
+/// Name of the result column of a selected expression: the name of a field or of a called
+/// function, or `?column?` for any other expression.
+pub fn column_name(expression: &Expression) -> String {
+    match expression {
+        Expression::Field(selector) => selector.field.clone(),
+        Expression::Function(name, _) => name.clone(),
+        Expression::Collate(expression, _) => column_name(expression),
+        _ => String::from("?column?"),
+    }
+}
+
+/// Type of the values of an expression, as far as it is known without evaluating it; `None` if
+/// it depends on the values, e.g. for null constants and placeholders.
+pub fn expression_type(expression: &Expression, scope: &Scope) -> Option<DBType> {
+    match expression {
+        Expression::Field(selector) => scope
+            .locate(selector)
+            .ok()
+            .map(|(_, schema, index)| schema.column(index).db_type),
+        Expression::Value(value) => value.val_to_type(),
+        Expression::Parameter(_) => None,
+        Expression::Condition(_) => Some(DBType::Boolean),
+        Expression::Arithmetic(operator, lhs, rhs) => arithmetic_type(
+            *operator,
+            expression_type(lhs, scope)?,
+            expression_type(rhs, scope)?,
+        ),
+        Expression::Collate(expression, _) => expression_type(expression, scope),
+        Expression::Function(name, args) => {
+            match (name.to_ascii_lowercase().as_str(), args.as_slice()) {
+                ("nextval", _) | ("extract", _) | ("count", _) => Some(DBType::Integer),
+                ("now", _) => Some(DBType::Timestamp),
+                ("uuid", _) => Some(DBType::Uuid),
+                ("group_concat", _) | ("string_agg", _) => Some(DBType::Text),
+                ("date_add", [value, _, unit]) => match expression_type(value, scope)? {
+                    DBType::Date => match unit {
+                        Expression::Value(unit) => {
+                            let unit = parse_unit(unit).ok()?;
+                            let whole_days =
+                                unit.micros().is_none_or(|length| length >= MICROS_PER_DAY);
+                            Some(if whole_days {
+                                DBType::Date
+                            } else {
+                                DBType::Timestamp
+                            })
+                        }
+                        _ => None,
+                    },
+                    db_type => Some(db_type),
+                },
+                _ => None,
+            }
+        }
+    }
+}
+
+/// Type of the result of an arithmetic operation on values of the given types, following
+/// [`eval_arithmetic`]. Decimal results may have any precision.
+fn arithmetic_type(operator: ArithmeticOperator, lhs: DBType, rhs: DBType) -> Option<DBType> {
+    let scale = |db_type| match db_type {
+        DBType::Integer => Some(0),
+        DBType::Decimal(_, scale) => Some(scale),
+        _ => None,
+    };
+    let is_number = |db_type| matches!(db_type, DBType::Real) || scale(db_type).is_some();
+    match (lhs, rhs) {
+        (DBType::Integer, DBType::Integer) => Some(DBType::Integer),
+        _ => match (scale(lhs), scale(rhs)) {
+            (Some(s1), Some(s2)) => {
+                let scale = match operator {
+                    ArithmeticOperator::Add | ArithmeticOperator::Subtract => s1.max(s2),
+                    ArithmeticOperator::Multiply => s1 + s2,
+                    ArithmeticOperator::Divide => s1.max(s2) + DIVISION_SCALE,
+                };
+                Some(DBType::Decimal(MAX_PRECISION, scale.min(MAX_PRECISION)))
+            }
+            _ if is_number(lhs) && is_number(rhs) => Some(DBType::Real),
+            _ => None,
+        },
+    }
+}
+
+/// Evaluates an arithmetic operation. Integer arithmetic is checked for overflow, and division of
+/// integers truncates. Decimal arithmetic is exact, and an integer operand is promoted to decimal
+/// if the other operand is decimal. Otherwise, the operands are promoted to real.
+fn eval_arithmetic(
+    operator: ArithmeticOperator,
+    lhs: DBValue,
+    rhs: DBValue,
+) -> Result<DBValue, StorageError> {
+    match (lhs, rhs) {
+        (DBValue::Null, _) | (_, DBValue::Null) => Ok(DBValue::Null),
+        (DBValue::Integer(i1), DBValue::Integer(i2)) => {
+            let result = match operator {
+                ArithmeticOperator::Add => i1.checked_add(i2),
+                ArithmeticOperator::Subtract => i1.checked_sub(i2),
+                ArithmeticOperator::Multiply => i1.checked_mul(i2),
+                ArithmeticOperator::Divide if i2 == 0 => return Err(StorageError::DivisionByZero),
+                ArithmeticOperator::Divide => i1.checked_div(i2),
+            };
+            result
+                .map(DBValue::Integer)
+                .ok_or(StorageError::IntegerOverflow)
+        }
+        (lhs, rhs) => {
+            if let (Some(d1), Some(d2)) = (as_decimal(&lhs), as_decimal(&rhs)) {
+                let result = match operator {
+                    ArithmeticOperator::Add => d1.checked_add(d2),
+                    ArithmeticOperator::Subtract => d1.checked_sub(d2),
+                    ArithmeticOperator::Multiply => d1.checked_mul(d2),
+                    ArithmeticOperator::Divide if d2 == Decimal::from(0) => {
+                        return Err(StorageError::DivisionByZero)
+                    }
+                    ArithmeticOperator::Divide => d1.checked_div(d2),
+                };
+                return result
+                    .map(DBValue::Decimal)
+                    .ok_or(StorageError::DecimalOverflow);
+            }
+            let (r1, r2) = (as_real(&lhs)?, as_real(&rhs)?);
+            Ok(DBValue::Real(match operator {
+                ArithmeticOperator::Add => r1 + r2,
+                ArithmeticOperator::Subtract => r1 - r2,
+                ArithmeticOperator::Multiply => r1 * r2,
+                ArithmeticOperator::Divide if r2 == 0.0 => {
+                    return Err(StorageError::DivisionByZero)
+                }
+                ArithmeticOperator::Divide => r1 / r2,
+            }))
+        }
+    }
+}
+
+fn as_real(value: &DBValue) -> Result<f64, StorageError> {
+    match value {
+        DBValue::Integer(i) => Ok(*i as f64),
+        DBValue::Real(r) => Ok(*r),
+        DBValue::Decimal(d) => Ok(d.to_f64()),
+        _ => Err(StorageError::TypeError),
+    }
+}
+
+fn as_decimal(value: &DBValue) -> Option<Decimal> {
+    match value {
+        DBValue::Integer(i) => Some(Decimal::from(*i)),
+        DBValue::Decimal(d) => Some(*d),
+        _ => None,
+    }
+}
+
+/// Whether the built-in `now` and `uuid` functions are there. Browsers have neither a clock nor
+/// randomness to call on, so there the functions are left to the application to register.
+const CLOCK_AND_RANDOMNESS: bool = !cfg!(all(target_arch = "wasm32", target_os = "unknown"));
+
+fn eval_function(name: &str, args: Vec<DBValue>, scope: &Scope) -> Result<DBValue, StorageError> {
+    if scope.is_aggregate(name) {
+        return Err(StorageError::MisplacedAggregate(String::from(name)));
+    }
+    match (name, args.as_slice()) {
+        ("nextval", [DBValue::Text(sequence)]) => scope
+            .sequences
+            .get(sequence)
+            .map(|sequence| DBValue::Integer(sequence.next_value()))
+            .ok_or(StorageError::SequenceNotFound),
+        ("nextval", _) => Err(StorageError::TypeError),
+        ("now", []) if CLOCK_AND_RANDOMNESS => {
+            let since_epoch = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            Ok(DBValue::Timestamp(since_epoch.as_micros() as i64))
+        }
+        ("date_add", [value, amount, unit]) => date_add(value, amount, unit),
+        ("extract", [unit, value]) => extract(unit, value),
+        ("uuid", []) if CLOCK_AND_RANDOMNESS => Ok(DBValue::Uuid(Uuid::new_v4())),
+        ("date_add", _) | ("extract", _) => Err(StorageError::TypeError),
+        ("now", _) | ("uuid", _) if CLOCK_AND_RANDOMNESS => Err(StorageError::TypeError),
+        _ => scope.functions.call(name, &args),
+    }
+}
+
+fn parse_unit(unit: &DBValue) -> Result<Unit, StorageError> {
+    match unit {
+        DBValue::Text(unit) => Unit::parse(unit).ok_or(StorageError::TypeError),
+        _ => Err(StorageError::TypeError),
+    }
+}
+
+/// `date_add(value, amount, unit)`, e.g. `date_add(d, 2, 'month')`. Adding less than a day to a
+/// date gives a timestamp, and adding to a time wraps around at midnight.
+fn date_add(value: &DBValue, amount: &DBValue, unit: &DBValue) -> Result<DBValue, StorageError> {
+    let amount = match (value, amount, unit) {
+        (DBValue::Null, _, _) | (_, DBValue::Null, _) | (_, _, DBValue::Null) => {
+            return Ok(DBValue::Null)
+        }
+        (_, DBValue::Integer(amount), _) => *amount,
+        _ => return Err(StorageError::TypeError),
+    };
+    let unit = parse_unit(unit)?;
+    let whole_days = unit.micros().is_none_or(|length| length >= MICROS_PER_DAY);
+    let result = match value {
+        DBValue::Date(days) if whole_days => {
+            datetime::add_to_date(*days, amount, unit).map(DBValue::Date)
+        }
+        DBValue::Date(days) => {
+            datetime::add_to_timestamp(days * MICROS_PER_DAY, amount, unit).map(DBValue::Timestamp)
+        }
+        DBValue::Timestamp(micros) => {
+            datetime::add_to_timestamp(*micros, amount, unit).map(DBValue::Timestamp)
+        }
+        DBValue::Time(micros) if !whole_days => {
+            datetime::add_to_time(*micros, amount, unit).map(DBValue::Time)
+        }
+        _ => return Err(StorageError::TypeError),
+    };
+    result.ok_or(StorageError::DateTimeOutOfRange)
+}
+
+/// `extract(unit, value)`, e.g. `extract('year', d)`; a field of a date, time or timestamp as an
+/// integer. The time fields of a date are zero.
+fn extract(unit: &DBValue, value: &DBValue) -> Result<DBValue, StorageError> {
+    let (days, time) = match (unit, value) {
+        (DBValue::Null, _) | (_, DBValue::Null) => return Ok(DBValue::Null),
+        (_, DBValue::Date(days)) => (Some(*days), 0),
+        (_, DBValue::Time(micros)) => (None, *micros),
+        (_, DBValue::Timestamp(micros)) => {
+            let (days, time) = datetime::split_timestamp(*micros);
+            (Some(days), time)
+        }
+        _ => return Err(StorageError::TypeError),
+    };
+    let seconds = time / MICROS_PER_SECOND;
+    let field = match (parse_unit(unit)?, days.map(datetime::civil_from_days)) {
+        (Unit::Year, Some((year, _, _))) => year,
+        (Unit::Month, Some((_, month, _))) => month,
+        (Unit::Day, Some((_, _, day))) => day,
+        (Unit::Year, None) | (Unit::Month, None) | (Unit::Day, None) => {
+            return Err(StorageError::TypeError)
+        }
+        (Unit::Hour, _) => seconds / 3600,
+        (Unit::Minute, _) => seconds / 60 % 60,
+        (Unit::Second, _) => seconds % 60,
+    };
+    Ok(DBValue::Integer(field))
+}
+
+/// Resolves a field selector into a column index of the table `table`.
+pub fn resolve_selector(
+    selector: &Selector,
+    table: &str,
+    schema: &Schema,
+) -> Result<usize, StorageError> {
+    if let Some(selector_table) = &selector.table {
+        if selector_table != table {
+            return Err(StorageError::SchemaMismatch);
+        }
+    }
+    schema
+        .get_column_index(&selector.field)
+        .ok_or(StorageError::SchemaMismatch)
+}
+
+/// Compares two values of the same kind, text under the given collation. Values of different
+/// kinds, e.g. text and numbers, can't be compared, except for UUIDs and text in UUID format.
+pub fn compare(
+    lhs: &DBValue,
+    rhs: &DBValue,
+    collation: Collation,
+) -> Result<Ordering, StorageError> {
+    match (lhs, rhs) {
+        (DBValue::Uuid(uuid), DBValue::Text(text)) => Uuid::parse(text)
+            .map(|other| uuid.cmp(&other))
+            .ok_or(StorageError::TypeError),
+        (DBValue::Text(_), DBValue::Uuid(_)) => compare(rhs, lhs, collation).map(Ordering::reverse),
+        _ if lhs.is_comparable(rhs) => Ok(collation.compare_values(lhs, rhs)),
+        _ => Err(StorageError::TypeError),
+    }
+}
+
+/// Looks for an equality between an indexed column and a constant value among the conjuncts of
+/// `condition`. If one is found, returns the keys of the rows that may satisfy the condition; all
+/// other rows are guaranteed not to.
+pub fn index_lookup(
+    condition: &Condition,
+    table: &str,
+    data: &Table,
+) -> Option<io::Result<Vec<Key>>> {
+    match condition {
+        Condition::Literal(ConditionLiteral::Eq(
+            Expression::Field(selector),
+            Expression::Value(value),
+        ))
+        | Condition::Literal(ConditionLiteral::Eq(
+            Expression::Value(value),
+            Expression::Field(selector),
+        )) => {
+            let column = resolve_selector(selector, table, data.schema()).ok()?;
+            let column_type = data.schema().column(column).db_type;
+            // Index keys are of the column type, so the value is converted to it when possible
+            let value = match value {
+                DBValue::Null => return Some(Ok(Vec::new())),
+                value => column_type.coerce(value).unwrap_or_else(|| value.clone()),
+            };
+            if !value.val_to_type().is_some_and(|t| column_type.accepts(t)) {
+                return None;
+            }
+            data.indexes()
+                .values()
+                .find(|index| index.column() == column)
+                .filter(|index| prefer_index(data, index))
+                .map(|index| index.get(&value))
+        }
+        Condition::And(c1, c2) => {
+            index_lookup(c1, table, data).or_else(|| index_lookup(c2, table, data))
+        }
+        _ => None,
+    }
+}
+
+/// Order of rows by their sort keys, i.e. the values of the `order_by` terms for each row. It
+/// follows the order of [`DBValue`]s, so that nulls come first in ascending order, with text
+/// compared under the collation of its term.
+pub struct KeyOrder {
+    terms: Vec<(SortOrder, Collation)>,
+}
+
+impl KeyOrder {
+    pub fn new(order_by: &[(Expression, SortOrder)], scope: &Scope) -> Self {
+        let terms = order_by
+            .iter()
+            .map(|(expression, order)| (*order, expression_collation(expression, scope)))
+            .collect();
+        Self { terms }
+    }
+
+    pub fn compare(&self, keys1: &[DBValue], keys2: &[DBValue]) -> Ordering {
+        for ((k1, k2), (order, collation)) in keys1.iter().zip(keys2).zip(&self.terms) {
+            let ordering = collation.compare_values(k1, k2);
+            let ordering = match order {
+                SortOrder::Ascending => ordering,
+                SortOrder::Descending => ordering.reverse(),
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+/// Sorts rows by their sort keys, see [`KeyOrder`]. The sort is stable.
+pub fn sort_rows(
+    rows: &mut [(Vec<DBValue>, Row)],
+    order_by: &[(Expression, SortOrder)],
+    scope: &Scope,
+) {
+    let order = KeyOrder::new(order_by, scope);
+    rows.sort_by(|(keys1, _), (keys2, _)| order.compare(keys1, keys2));
+}
+
+/// Whether `name` is a built-in aggregate function, computed over a group of rows rather than a
+/// single row; see [`Scope::is_aggregate`] for registered ones too.
+pub fn is_aggregate(name: &str) -> bool {
+    matches!(
+        name.to_ascii_lowercase().as_str(),
+        "count" | "group_concat" | "string_agg"
+    )
+}
+
+/// Whether the expression calls any aggregate functions.
+pub fn contains_aggregate(expression: &Expression, scope: &Scope) -> bool {
+    let mut found = false;
+    expression.walk(&mut |expression| {
+        if let Expression::Function(name, _) = expression {
+            found |= scope.is_aggregate(name);
+        }
+    });
+    found
+}
+
+/// Partitions rows into groups with equal values of the `group_by` expressions, in the order of
+/// their first rows. Text is grouped under the collation of its expression. Without any expressions, all rows form a single group, even if there are
+/// none.
+pub fn group_rows<'r>(
+    group_by: &[Expression],
+    scope: &Scope,
+    rows: Vec<&'r Row>,
+    threads: usize,
+) -> Result<Vec<Vec<&'r Row>>, StorageError> {
+    if group_by.is_empty() {
+        return Ok(vec![rows]);
+    }
+    let collations: Vec<_> = group_by
+        .iter()
+        .map(|expression| expression_collation(expression, scope))
+        .collect();
+    // The keys are evaluated in parallel, and the rows then grouped in order
+    let row_keys = map_chunks(&rows, threads, |rows| {
+        let mut keys = Vec::new();
+        for row in rows {
+            let mut key = Vec::new();
+            for (expression, collation) in group_by.iter().zip(&collations) {
+                key.push(collation.key(&eval_expression(expression, scope, row)?));
+            }
+            keys.push(key);
+        }
+        Ok(keys)
+    })?;
+    let mut groups: Vec<Vec<&Row>> = Vec::new();
+    let mut keys = HashMap::new();
+    for (row, key) in rows.into_iter().zip(row_keys) {
+        let group = *keys.entry(key).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[group].push(row);
+    }
+    Ok(groups)
+}
+
+/// Evaluates an expression over a group of rows. Aggregate functions are computed over the whole
+/// group, and everything else against the first row of the group; or as null if the group is
+/// empty.
+pub fn eval_grouped(
+    expression: &Expression,
+    scope: &Scope,
+    rows: &[&Row],
+) -> Result<DBValue, StorageError> {
+    let mut expression = expression.clone();
+    let mut error = None;
+    expression.visit_mut(&mut |expression| match expression {
+        Expression::Function(name, args) if scope.is_aggregate(name) => {
+            match eval_aggregate(name, args, scope, rows) {
+                Ok(value) => *expression = Expression::Value(value),
+                Err(err) => error = error.take().or(Some(err)),
+            }
+        }
+        Expression::Field(_) if rows.is_empty() => *expression = Expression::Value(DBValue::Null),
+        _ => (),
+    });
+    if let Some(err) = error {
+        return Err(err);
+    }
+    eval_expression(&expression, scope, rows.first().map_or(&[], |row| row))
+}
+
+fn eval_aggregate(
+    name: &str,
+    args: &[Expression],
+    scope: &Scope,
+    rows: &[&Row],
+) -> Result<DBValue, StorageError> {
+    match (name.to_ascii_lowercase().as_str(), args) {
+        ("count", []) => Ok(DBValue::Integer(rows.len() as i64)),
+        ("count", [value]) => {
+            let mut count = 0;
+            for row in rows {
+                if eval_expression(value, scope, row)? != DBValue::Null {
+                    count += 1;
+                }
+            }
+            Ok(DBValue::Integer(count))
+        }
+        ("group_concat", [value]) => group_concat(value, None, scope, rows),
+        ("group_concat", [value, separator]) | ("string_agg", [value, separator]) => {
+            group_concat(value, Some(separator), scope, rows)
+        }
+        _ if is_aggregate(name) => Err(StorageError::TypeError),
+        _ => {
+            let mut values = Vec::new();
+            for row in rows {
+                let mut row_values = Vec::new();
+                for arg in args {
+                    row_values.push(eval_expression(arg, scope, row)?);
+                }
+                values.push(row_values);
+            }
+            scope.functions.call_aggregate(name, args.len(), &values)
+        }
+    }
+}
+
+/// Concatenates the non-null values of the group as text, separated by `separator` or a comma.
+/// The result is null if there are no such values.
+fn group_concat(
+    value: &Expression,
+    separator: Option<&Expression>,
+    scope: &Scope,
+    rows: &[&Row],
+) -> Result<DBValue, StorageError> {
+    let mut result: Option<String> = None;
+    for row in rows {
+        let value = match eval_expression(value, scope, row)? {
+            DBValue::Null => continue,
+            value => value.to_string(),
+        };
+        match &mut result {
+            None => result = Some(value),
+            Some(result) => {
+                match separator.map(|separator| eval_expression(separator, scope, row)) {
+                    Some(Ok(DBValue::Text(separator))) => result.push_str(&separator),
+                    Some(Ok(_)) => return Err(StorageError::TypeError),
+                    Some(Err(err)) => return Err(err),
+                    None => result.push(','),
+                }
+                result.push_str(&value);
+            }
+        }
+    }
+    Ok(result.map_or(DBValue::Null, DBValue::Text))
+}
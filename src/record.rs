@@ -0,0 +1,391 @@
+//! Binary encoding of rows, used to spill rows to temporary files and to store rows in the data
+//! cells of b-tree leaf nodes. A row is its number of values followed by the values, each a tag
+//! byte and a fixed-size payload, except for text, which is its length in bytes followed by its
+//! UTF-8 bytes. Numbers are little-endian.
+//!
+//! Keys of b-trees are encoded differently, by [`encode_key`], such that comparing the bytes of
+//! two keys orders them as their values.
+
+use crate::datetime;
+use crate::db::{DBValue, Row};
+use crate::decimal::Decimal;
+use crate::uuid::Uuid;
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::iter;
+
+const NULL: u8 = 0;
+const INTEGER: u8 = 1;
+const REAL: u8 = 2;
+const TEXT: u8 = 3;
+const BOOLEAN: u8 = 4;
+const DATE: u8 = 5;
+const TIME: u8 = 6;
+const TIMESTAMP: u8 = 7;
+const DECIMAL: u8 = 8;
+const UUID: u8 = 9;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub fn write_row(output: &mut impl Write, row: &[DBValue]) -> io::Result<()> {
+    output.write_all(&(row.len() as u32).to_le_bytes())?;
+    for value in row {
+        write_value(output, value)?;
+    }
+    Ok(())
+}
+
+fn write_value(output: &mut impl Write, value: &DBValue) -> io::Result<()> {
+    match value {
+        DBValue::Null => output.write_all(&[NULL]),
+        DBValue::Integer(i) => {
+            output.write_all(&[INTEGER])?;
+            output.write_all(&i.to_le_bytes())
+        }
+        DBValue::Real(r) => {
+            output.write_all(&[REAL])?;
+            output.write_all(&r.to_bits().to_le_bytes())
+        }
+        DBValue::Text(text) => {
+            output.write_all(&[TEXT])?;
+            output.write_all(&(text.len() as u32).to_le_bytes())?;
+            output.write_all(text.as_bytes())
+        }
+        DBValue::Boolean(b) => output.write_all(&[BOOLEAN, u8::from(*b)]),
+        DBValue::Date(days) => {
+            output.write_all(&[DATE])?;
+            output.write_all(&days.to_le_bytes())
+        }
+        DBValue::Time(micros) => {
+            output.write_all(&[TIME])?;
+            output.write_all(&micros.to_le_bytes())
+        }
+        DBValue::Timestamp(micros) => {
+            output.write_all(&[TIMESTAMP])?;
+            output.write_all(&micros.to_le_bytes())
+        }
+        DBValue::Decimal(decimal) => {
+            output.write_all(&[DECIMAL])?;
+            output.write_all(&decimal.mantissa().to_le_bytes())?;
+            output.write_all(&[decimal.scale()])
+        }
+        DBValue::Uuid(uuid) => {
+            output.write_all(&[UUID])?;
+            output.write_all(uuid.as_bytes())
+        }
+    }
+}
+
+fn read_bytes<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Reads the next row, or `None` at the end of the input.
+pub fn read_row(input: &mut impl Read) -> io::Result<Option<Row>> {
+    let mut length = [0; 4];
+    match input.read(&mut length[..1])? {
+        0 => return Ok(None),
+        _ => input.read_exact(&mut length[1..])?,
+    }
+    let length = u32::from_le_bytes(length);
+    let mut row = Vec::new();
+    for _ in 0..length {
+        row.push(read_value(input)?);
+    }
+    Ok(Some(row))
+}
+
+fn read_value(input: &mut impl Read) -> io::Result<DBValue> {
+    let [tag] = read_bytes(input)?;
+    let value = match tag {
+        NULL => DBValue::Null,
+        INTEGER => DBValue::Integer(i64::from_le_bytes(read_bytes(input)?)),
+        REAL => DBValue::Real(f64::from_bits(u64::from_le_bytes(read_bytes(input)?))),
+        TEXT => {
+            let length = u32::from_le_bytes(read_bytes(input)?);
+            let mut bytes = vec![0; length as usize];
+            input.read_exact(&mut bytes)?;
+            let text = String::from_utf8(bytes).map_err(|_| invalid("Invalid text"))?;
+            DBValue::Text(text)
+        }
+        BOOLEAN => {
+            let [b] = read_bytes(input)?;
+            DBValue::Boolean(b != 0)
+        }
+        DATE => DBValue::Date(i64::from_le_bytes(read_bytes(input)?)),
+        TIME => DBValue::Time(i64::from_le_bytes(read_bytes(input)?)),
+        TIMESTAMP => DBValue::Timestamp(i64::from_le_bytes(read_bytes(input)?)),
+        DECIMAL => {
+            let mantissa = i128::from_le_bytes(read_bytes(input)?);
+            let [scale] = read_bytes(input)?;
+            let decimal =
+                Decimal::new(mantissa, scale).ok_or_else(|| invalid("Invalid decimal"))?;
+            DBValue::Decimal(decimal)
+        }
+        UUID => DBValue::Uuid(Uuid::from_bytes(read_bytes(input)?)),
+        _ => return Err(invalid("Invalid value tag")),
+    };
+    Ok(value)
+}
+
+/// Tags of the kinds of values in keys, in the order values of different kinds are sorted in.
+const KEY_NULL: u8 = 0;
+const KEY_BOOLEAN: u8 = 1;
+const KEY_NUMBER: u8 = 2;
+const KEY_TEXT: u8 = 3;
+const KEY_TIMESTAMP: u8 = 4;
+const KEY_TIME: u8 = 5;
+const KEY_UUID: u8 = 6;
+
+/// Signs of numbers in keys, following the number tag, in order.
+const NEGATIVE_INFINITY: u8 = 0;
+const NEGATIVE: u8 = 1;
+const ZERO: u8 = 2;
+const POSITIVE: u8 = 3;
+const INFINITY: u8 = 4;
+const NAN: u8 = 5;
+
+/// Encodes the values into a b-tree key whose bytes compare as the values do, value by value;
+/// composite keys and keys of secondary indexes, which are the indexed value followed by the
+/// key of the row, are the concatenation of their values. Each value starts with a tag of
+/// its kind. Integers, decimals and reals are compared by their decimal value, so a number
+/// is its sign followed by its decimal exponent, as a big-endian `u16` offset by 2^15, and its
+/// significant ASCII digits, ending in a zero byte; the bytes after the sign of negative numbers
+/// are complemented. Text is its UTF-8 bytes with zero bytes escaped as 0 255, ending in 0 0.
+/// Dates and timestamps are microseconds since the epoch and times microseconds since midnight,
+/// as big-endian integers with the sign bit flipped.
+pub fn encode_key(values: &[DBValue]) -> Vec<u8> {
+    let mut key = Vec::new();
+    for value in values {
+        encode_key_value(&mut key, value);
+    }
+    key
+}
+
+fn encode_key_value(key: &mut Vec<u8>, value: &DBValue) {
+    let ordered = |i: i64| (i as u64 ^ (1 << 63)).to_be_bytes();
+    match value {
+        DBValue::Null => key.push(KEY_NULL),
+        DBValue::Boolean(b) => key.extend_from_slice(&[KEY_BOOLEAN, u8::from(*b)]),
+        DBValue::Integer(i) => {
+            encode_number(key, *i < 0, &i.unsigned_abs().to_string(), 0);
+        }
+        DBValue::Decimal(d) => {
+            let digits = d.mantissa().unsigned_abs().to_string();
+            encode_number(key, d.mantissa() < 0, &digits, -i32::from(d.scale()));
+        }
+        DBValue::Real(r) if r.is_nan() => key.extend_from_slice(&[KEY_NUMBER, NAN]),
+        DBValue::Real(r) if r.is_infinite() => {
+            let sign = if *r < 0.0 {
+                NEGATIVE_INFINITY
+            } else {
+                INFINITY
+            };
+            key.extend_from_slice(&[KEY_NUMBER, sign]);
+        }
+        DBValue::Real(r) => {
+            // The shortest digits that read back as the real, e.g. 1.25e-3
+            let scientific = format!("{:e}", r.abs());
+            let (mantissa, exponent) = scientific.split_at(scientific.find('e').unwrap_or(0));
+            let digits = mantissa.replace('.', "");
+            let exponent = exponent[1..].parse::<i32>().unwrap_or(0) - (digits.len() as i32 - 1);
+            encode_number(key, *r < 0.0, &digits, exponent);
+        }
+        DBValue::Text(text) => {
+            key.push(KEY_TEXT);
+            for byte in text.bytes() {
+                key.push(byte);
+                if byte == 0 {
+                    key.push(255);
+                }
+            }
+            key.extend_from_slice(&[0, 0]);
+        }
+        DBValue::Date(_) | DBValue::Timestamp(_) => {
+            let micros = match value {
+                DBValue::Date(days) => days.saturating_mul(datetime::MICROS_PER_DAY),
+                DBValue::Timestamp(micros) => *micros,
+                _ => unreachable!(),
+            };
+            key.push(KEY_TIMESTAMP);
+            key.extend_from_slice(&ordered(micros));
+        }
+        DBValue::Time(micros) => {
+            key.push(KEY_TIME);
+            key.extend_from_slice(&ordered(*micros));
+        }
+        DBValue::Uuid(uuid) => {
+            key.push(KEY_UUID);
+            key.extend_from_slice(uuid.as_bytes());
+        }
+    }
+}
+
+/// Decodes a key [`encode_key`] encoded a single integer into, such as a rowid. Returns `None` for
+/// keys of other values, and for integers out of range.
+pub fn decode_integer_key(key: &[u8]) -> Option<i64> {
+    let (&sign, rest) = key.strip_prefix(&[KEY_NUMBER])?.split_first()?;
+    let negative = match sign {
+        ZERO if rest.is_empty() => return Some(0),
+        NEGATIVE => true,
+        POSITIVE => false,
+        _ => return None,
+    };
+    let rest: Vec<u8> = rest
+        .iter()
+        .map(|&byte| if negative { !byte } else { byte })
+        .collect();
+    let (exponent, digits) = (rest.get(..2)?, rest.get(2..)?.strip_suffix(&[0])?);
+    let exponent = i32::from(u16::from_be_bytes([exponent[0], exponent[1]])) - (1 << 15);
+    let zeros = usize::try_from(exponent).ok()?.checked_sub(digits.len())?;
+    let mut magnitude: i128 = 0;
+    for &digit in digits.iter().chain(iter::repeat_n(&b'0', zeros)) {
+        let digit = i128::from(digit.checked_sub(b'0').filter(|digit| *digit < 10)?);
+        magnitude = magnitude.checked_mul(10)?.checked_add(digit)?;
+    }
+    i64::try_from(if negative { -magnitude } else { magnitude }).ok()
+}
+
+/// Encodes the number `digits * 10^exponent`, given the ASCII digits of its magnitude.
+fn encode_number(key: &mut Vec<u8>, negative: bool, digits: &str, exponent: i32) {
+    key.push(KEY_NUMBER);
+    let significant = digits.trim_start_matches('0').trim_end_matches('0');
+    if significant.is_empty() {
+        key.push(ZERO);
+        return;
+    }
+    key.push(if negative { NEGATIVE } else { POSITIVE });
+    // As 0.d1d2... * 10^exponent, so that the exponent orders numbers of different magnitudes
+    let leading = digits.trim_start_matches('0').len() as i32;
+    let exponent = (exponent + leading + (1 << 15)) as u16;
+    let start = key.len();
+    key.extend_from_slice(&exponent.to_be_bytes());
+    key.extend_from_slice(significant.as_bytes());
+    key.push(0);
+    if negative {
+        for byte in &mut key[start..] {
+            *byte = !*byte;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::slice;
+
+    #[test]
+    fn round_trip() {
+        let rows = vec![
+            vec![
+                DBValue::Null,
+                DBValue::Integer(-42),
+                DBValue::Real(2.5),
+                DBValue::Text(String::from("héllo")),
+                DBValue::Boolean(true),
+            ],
+            Vec::new(),
+            vec![
+                DBValue::Date(19782),
+                DBValue::Time(1),
+                DBValue::Timestamp(-1),
+                DBValue::Decimal(Decimal::parse("-12.50").unwrap()),
+                DBValue::Uuid(Uuid::new_v4()),
+            ],
+        ];
+        let mut bytes = Vec::new();
+        for row in &rows {
+            write_row(&mut bytes, row).unwrap();
+        }
+        let mut input = &bytes[..];
+        let mut read = Vec::new();
+        while let Some(row) = read_row(&mut input).unwrap() {
+            read.push(row);
+        }
+        assert_eq!(read, rows);
+        assert!(read_row(&mut &[1, 0, 0, 0, 42][..]).is_err());
+    }
+
+    #[test]
+    fn keys_order_as_values() {
+        let decimal = |text| DBValue::Decimal(Decimal::parse(text).unwrap());
+        let text = |text: &str| DBValue::Text(String::from(text));
+        let values = vec![
+            DBValue::Null,
+            DBValue::Boolean(false),
+            DBValue::Boolean(true),
+            DBValue::Real(f64::NEG_INFINITY),
+            DBValue::Real(-1e300),
+            DBValue::Integer(i64::MIN),
+            decimal("-100.5"),
+            DBValue::Integer(-100),
+            DBValue::Real(-99.75),
+            DBValue::Integer(-2),
+            decimal("-0.0100"),
+            DBValue::Real(-0.0),
+            DBValue::Integer(0),
+            decimal("0.001"),
+            DBValue::Real(0.1),
+            decimal("0.10000000000000000001"),
+            DBValue::Integer(1),
+            DBValue::Real(1.5),
+            decimal("1.50"),
+            DBValue::Integer(10),
+            DBValue::Integer(1_000_000),
+            DBValue::Integer(i64::MAX),
+            DBValue::Real(1e300),
+            DBValue::Real(f64::INFINITY),
+            DBValue::Real(f64::NAN),
+            text(""),
+            text("a"),
+            text("a\0"),
+            text("a\0b"),
+            text("ab"),
+            text("é"),
+            DBValue::Timestamp(i64::MIN),
+            DBValue::Date(-1),
+            DBValue::Timestamp(-1),
+            DBValue::Date(0),
+            DBValue::Timestamp(1),
+            DBValue::Time(0),
+            DBValue::Time(1),
+            DBValue::Uuid(Uuid::from_bytes([0; 16])),
+            DBValue::Uuid(Uuid::from_bytes([1; 16])),
+        ];
+        for v1 in &values {
+            for v2 in &values {
+                let (k1, k2) = (
+                    encode_key(slice::from_ref(v1)),
+                    encode_key(slice::from_ref(v2)),
+                );
+                assert_eq!(k1.cmp(&k2), v1.cmp(v2), "{:?} and {:?}", v1, v2);
+            }
+        }
+
+        // Composite keys order by their first value, then their second one
+        let keys = [
+            vec![text("a"), DBValue::Integer(2)],
+            vec![text("a"), DBValue::Integer(10)],
+            vec![text("a\0"), DBValue::Integer(1)],
+            vec![text("ab"), DBValue::Null],
+        ];
+        for pair in keys.windows(2) {
+            assert!(encode_key(&pair[0]) < encode_key(&pair[1]));
+        }
+    }
+
+    #[test]
+    fn integer_keys_decode() {
+        for i in [0, 1, 9, 10, 120, -7, -1000, i64::MAX, i64::MIN] {
+            let key = encode_key(&[DBValue::Integer(i)]);
+            assert_eq!(decode_integer_key(&key), Some(i));
+        }
+        for value in [DBValue::Real(0.5), DBValue::Real(1e30), DBValue::Null] {
+            assert_eq!(decode_integer_key(&encode_key(&[value])), None);
+        }
+    }
+}
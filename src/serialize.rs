@@ -0,0 +1,167 @@
+//! Serde support, under the `serde` feature: values serialize as the closest plain value, i.e.
+//! null, integers, reals, booleans and text as themselves, and dates, times, timestamps,
+//! decimals and UUIDs as their text, as in the results of the REPL. Rows serialize as sequences
+//! of values, and result sets as their columns, with the names of their types, and rows, e.g. in
+//! JSON:
+//!
+//! ```json
+//! {"columns": [{"name": "id", "type": "integer"}], "rows": [[1], [2]]}
+//! ```
+//!
+//! Deserializing reads values back as the plain value, with text staying text, and coerces
+//! them to the types of the columns of a result set, turning e.g. text back into dates; see
+//! [`DBType::coerce`].
+
+use crate::db::{DBType, DBValue, ResultColumn, ResultSet, Row};
+use crate::parser::Parser;
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::ser::{Serialize, Serializer};
+use std::convert::TryFrom;
+use std::fmt;
+
+impl Serialize for DBValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            DBValue::Null => serializer.serialize_none(),
+            DBValue::Integer(i) => serializer.serialize_i64(*i),
+            DBValue::Real(r) => serializer.serialize_f64(*r),
+            DBValue::Text(text) => serializer.serialize_str(text),
+            DBValue::Boolean(b) => serializer.serialize_bool(*b),
+            value => serializer.collect_str(value),
+        }
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = DBValue;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "null, a boolean, a number or a string")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<DBValue, E> {
+        Ok(DBValue::Null)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<DBValue, E> {
+        Ok(DBValue::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<DBValue, D::Error> {
+        DBValue::deserialize(deserializer)
+    }
+
+    fn visit_bool<E: de::Error>(self, b: bool) -> Result<DBValue, E> {
+        Ok(DBValue::Boolean(b))
+    }
+
+    fn visit_i64<E: de::Error>(self, i: i64) -> Result<DBValue, E> {
+        Ok(DBValue::Integer(i))
+    }
+
+    /// Integers beyond the range of integers are read as reals.
+    fn visit_u64<E: de::Error>(self, u: u64) -> Result<DBValue, E> {
+        Ok(i64::try_from(u).map_or(DBValue::Real(u as f64), DBValue::Integer))
+    }
+
+    fn visit_f64<E: de::Error>(self, r: f64) -> Result<DBValue, E> {
+        Ok(DBValue::Real(r))
+    }
+
+    fn visit_str<E: de::Error>(self, text: &str) -> Result<DBValue, E> {
+        Ok(DBValue::Text(String::from(text)))
+    }
+
+    fn visit_string<E: de::Error>(self, text: String) -> Result<DBValue, E> {
+        Ok(DBValue::Text(text))
+    }
+}
+
+impl<'de> Deserialize<'de> for DBValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+/// A result set as read, before its values are coerced to the types of its columns.
+#[derive(serde::Deserialize)]
+pub(crate) struct RawResultSet {
+    columns: Vec<ResultColumn>,
+    rows: Vec<Row>,
+}
+
+impl From<RawResultSet> for ResultSet {
+    fn from(result: RawResultSet) -> Self {
+        let RawResultSet { columns, mut rows } = result;
+        for row in &mut rows {
+            for (value, column) in row.iter_mut().zip(&columns) {
+                let coerced = column.db_type.and_then(|db_type| db_type.coerce(value));
+                if let Some(coerced) = coerced {
+                    *value = coerced;
+                }
+            }
+        }
+        ResultSet { columns, rows }
+    }
+}
+
+/// The type of a column of a result set as its name in SQL, e.g. `decimal(10, 2)`, or null if
+/// unknown.
+pub(crate) mod db_type {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        db_type: &Option<DBType>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match db_type {
+            Some(db_type) => serializer.collect_str(db_type),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<DBType>, D::Error> {
+        let name = match Option::<String>::deserialize(deserializer)? {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        let mut parser = Parser::new(&name);
+        match parser.parse_db_type() {
+            Ok(db_type) if parser.at_end() => Ok(Some(db_type)),
+            _ => Err(de::Error::custom(format!("unknown type '{}'", name))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datetime;
+    use serde::de::value::{Error, UnitDeserializer};
+    use serde::de::IntoDeserializer;
+
+    #[test]
+    fn deserialize_values() {
+        let integer: Result<_, Error> = DBValue::deserialize(7i64.into_deserializer());
+        assert_eq!(integer.ok(), Some(DBValue::Integer(7)));
+        let null: Result<_, Error> = DBValue::deserialize(UnitDeserializer::new());
+        assert_eq!(null.ok(), Some(DBValue::Null));
+        let date = ResultColumn {
+            name: String::from("d"),
+            db_type: Some(DBType::Date),
+        };
+        let raw = RawResultSet {
+            columns: vec![date],
+            rows: vec![vec![DBValue::Text(String::from("2024-02-29"))]],
+        };
+        let days = datetime::parse_date("2024-02-29");
+        assert_eq!(
+            ResultSet::from(raw).rows,
+            vec![vec![days.map_or(DBValue::Null, DBValue::Date)]]
+        );
+    }
+}
@@ -0,0 +1,300 @@
+//! Serving a database over TCP, e.g. `juicydb --serve 127.0.0.1:5433 my.db`, to clients in other
+//! processes. Each client runs in a thread of its own on a connection of its own, so that it has
+//! its own session and transaction; see [`crate::connection`].
+//!
+//! Clients send SQL as lines of text, with statements ending in semicolons as in the REPL, and
+//! `.exit` to close the connection. Each statement is answered with a frame: a line of `ok` or
+//! `error` and the length of the payload in bytes, then the payload. The payload of `ok` is a
+//! JSON array of the names of the columns and one of the values of each row, a line each, or
+//! nothing for statements without results; that of `error` is the message on a line, e.g.
+//!
+//! ```text
+//! > select id, name from users;
+//! < ok 24
+//! < ["id","name"]
+//! < [1,"ada"]
+//! > selec;
+//! < error 40
+//! < Parse error: Unrecognized SQL statement
+//! ```
+//!
+//! Values are written as in [`crate::json`]. A client sending more than
+//! [`MAX_STATEMENT_SIZE`] bytes without ending the statement is answered with an `error` frame
+//! and disconnected, and so is a client connecting while [`MAX_CLIENTS`] are being served. The
+//! server doesn't authenticate clients, and statements such as 'copy' read files as the server,
+//! so it should only listen where trusted clients can reach it.
+
+use crate::connection::{Connection, Error};
+use crate::db::ResultSet;
+use crate::json;
+use crate::parser::{Command, MetaCommand, Parser};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// Number of bytes of input a statement may take, up to its semicolon.
+pub const MAX_STATEMENT_SIZE: usize = 1 << 20;
+
+/// Number of clients served at once.
+pub const MAX_CLIENTS: usize = 64;
+
+/// Accepts clients until the listener fails, serving each on a new connection to the database
+/// of `connection` in a thread of its own, up to [`MAX_CLIENTS`] at once. A client that fails
+/// to be accepted, or whose connection breaks, is dropped without affecting the others.
+pub fn serve(connection: &Connection, listener: TcpListener) -> io::Result<()> {
+    serve_clients(connection, listener, MAX_CLIENTS)
+}
+
+fn serve_clients(
+    connection: &Connection,
+    listener: TcpListener,
+    max_clients: usize,
+) -> io::Result<()> {
+    let clients = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            // E.g. the client gave up before being accepted
+            Err(_) => continue,
+        };
+        if clients.load(Ordering::SeqCst) >= max_clients {
+            let message = format!(
+                "Too many clients, at most {} are served at once\n",
+                max_clients
+            );
+            // The client is turned away either way
+            let _ = write_frame(&mut stream, "error", &message);
+            continue;
+        }
+        let client = Client::new(&clients);
+        let connection = connection.connect();
+        thread::spawn(move || {
+            let _client = client;
+            let input = BufReader::new(stream.try_clone()?);
+            session(connection, input, stream)
+        });
+    }
+    Ok(())
+}
+
+/// A client being served, counted among the clients until dropped.
+struct Client(Arc<AtomicUsize>);
+
+impl Client {
+    fn new(clients: &Arc<AtomicUsize>) -> Self {
+        clients.fetch_add(1, Ordering::SeqCst);
+        Self(clients.clone())
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Runs the statements read from `input` on the connection, writing a frame to `output` for
+/// each, until `.exit`, the end of input, or input that isn't UTF-8 or runs past
+/// [`MAX_STATEMENT_SIZE`] without ending a statement. A transaction left open is rolled back as
+/// the connection is dropped.
+pub fn session(
+    mut connection: Connection,
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> io::Result<()> {
+    let mut bytes = Vec::new();
+    loop {
+        // Reads at most one byte past the limit, however long the line
+        let start = bytes.len();
+        let limit = (MAX_STATEMENT_SIZE + 1 - start) as u64;
+        if (&mut input).take(limit).read_until(b'\n', &mut bytes)? == 0 {
+            return Ok(());
+        }
+        if bytes.len() > MAX_STATEMENT_SIZE {
+            let message = format!("Statement longer than {} bytes\n", MAX_STATEMENT_SIZE);
+            return write_frame(&mut output, "error", &message);
+        }
+        // Only the first line of a command, or one with a semicolon, can complete it, so other
+        // lines needn't parse the statement so far again
+        if start > 0 && !bytes[start..].contains(&b';') {
+            continue;
+        }
+        let text = match std::str::from_utf8(&bytes) {
+            Ok(text) => text,
+            Err(_) => return write_frame(&mut output, "error", "Input is not UTF-8\n"),
+        };
+        let parser = Parser::new(text);
+        if parser.at_end() {
+            bytes.clear();
+            continue;
+        }
+        if !parser.is_complete() {
+            continue;
+        }
+        let go_on = run(&mut connection, text, &mut output)?;
+        bytes.clear();
+        if !go_on {
+            return Ok(());
+        }
+    }
+}
+
+/// Runs the statements of the complete input in turn, writing a frame for each. Returns whether
+/// to go on with the session.
+fn run(connection: &mut Connection, text: &str, output: &mut impl Write) -> io::Result<bool> {
+    let mut parser = Parser::new(text);
+    while !parser.at_end() {
+        let start = parser.offset();
+        let parsed = parser.parse_command();
+        if parsed.is_err() {
+            parser.skip_statement();
+        }
+        let result = match parsed {
+            Ok(Command::Statement(_)) => connection.execute(&text[start..parser.offset()]),
+            Ok(Command::MetaCommand(MetaCommand::Exit)) => return Ok(false),
+            Ok(Command::MetaCommand(_)) => {
+                write_frame(output, "error", "Only .exit is available to clients\n")?;
+                continue;
+            }
+            Err(err) => Err(Error::from(err)),
+        };
+        match result {
+            Ok(result) => write_frame(output, "ok", &payload(&result))?,
+            Err(err) => write_frame(output, "error", &format!("{}\n", err))?,
+        }
+    }
+    Ok(true)
+}
+
+/// The columns and rows of the result as JSON arrays, a line each, or nothing if the result has
+/// no columns.
+fn payload(result: &ResultSet) -> String {
+    if result.columns.is_empty() {
+        return String::new();
+    }
+    let array = |values: Vec<String>| format!("[{}]\n", values.join(","));
+    let names = result
+        .columns
+        .iter()
+        .map(|column| json::string(&column.name));
+    let mut payload = array(names.collect());
+    for row in &result.rows {
+        payload.push_str(&array(row.iter().map(json::value).collect()));
+    }
+    payload
+}
+
+fn write_frame(output: &mut impl Write, kind: &str, payload: &str) -> io::Result<()> {
+    write!(output, "{} {}\n{}", kind, payload.len(), payload)?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpStream;
+
+    /// The frames of the output, as their kinds and payloads.
+    fn frames(mut output: impl BufRead) -> Vec<(String, String)> {
+        let mut frames = Vec::new();
+        let mut header = String::new();
+        while output.read_line(&mut header).unwrap_or(0) > 0 {
+            let (kind, length) = header.trim_end().split_once(' ').unwrap_or_default();
+            let mut payload = vec![0; length.parse().unwrap_or(0)];
+            assert!(output.read_exact(&mut payload).is_ok());
+            let payload = String::from_utf8(payload).unwrap_or_default();
+            frames.push((String::from(kind), payload));
+            header.clear();
+        }
+        frames
+    }
+
+    fn frame(kind: &str, payload: &str) -> (String, String) {
+        (String::from(kind), String::from(payload))
+    }
+
+    #[test]
+    fn session_frames() {
+        let input = "create table t (id integer, name text);\n\
+                     -- a comment\n\
+                     insert into t values (1, 'a \"b\"'); insert into t\n\
+                     values (2, null);\n\
+                     select id, name from t\n\
+                     order by id;\n\
+                     select nothing from t; selec;\n\
+                     .schema\n\
+                     .exit\n\
+                     select id from t;\n";
+        let mut output = Vec::new();
+        let session = session(Connection::new(), input.as_bytes(), &mut output);
+        assert!(session.is_ok());
+        assert_eq!(
+            frames(output.as_slice()),
+            vec![
+                frame("ok", ""),
+                frame("ok", ""),
+                frame("ok", ""),
+                frame("ok", "[\"id\",\"name\"]\n[1,\"a \\\"b\\\"\"]\n[2,null]\n"),
+                frame("error", "SQL error: Schema mismatch\n"),
+                frame("error", "Parse error: Unrecognized SQL statement\n"),
+                frame("error", "Only .exit is available to clients\n"),
+            ]
+        );
+    }
+
+    #[test]
+    fn long_statements() {
+        let mut input = String::from("select id from t where id in (1");
+        while input.len() <= MAX_STATEMENT_SIZE {
+            input.push_str(",\n1");
+        }
+        input.push_str(");\n");
+        let mut output = Vec::new();
+        let session = session(Connection::new(), input.as_bytes(), &mut output);
+        assert!(session.is_ok());
+        let message = format!("Statement longer than {} bytes\n", MAX_STATEMENT_SIZE);
+        assert_eq!(frames(output.as_slice()), vec![frame("error", &message)]);
+    }
+
+    #[test]
+    fn clients_are_limited() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap_or_else(|err| panic!("{}", err));
+        let address = listener
+            .local_addr()
+            .unwrap_or_else(|err| panic!("{}", err));
+        let connection = Connection::new();
+        thread::spawn(move || serve_clients(&connection, listener, 1));
+        let connect = || TcpStream::connect(address).unwrap_or_else(|err| panic!("{}", err));
+        let mut first = connect();
+        let second = connect();
+        let message = "Too many clients, at most 1 are served at once\n";
+        assert_eq!(
+            frames(BufReader::new(second)),
+            vec![frame("error", message)]
+        );
+        assert!(first.write_all(b".exit\n").is_ok());
+        assert_eq!(frames(BufReader::new(first)), vec![]);
+    }
+
+    #[test]
+    fn clients_share_the_database() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap_or_else(|err| panic!("{}", err));
+        let address = listener
+            .local_addr()
+            .unwrap_or_else(|err| panic!("{}", err));
+        let connection = Connection::new();
+        thread::spawn(move || serve(&connection, listener));
+        let request = |sql: &str| {
+            let mut stream = TcpStream::connect(address).unwrap_or_else(|err| panic!("{}", err));
+            assert!(stream.write_all(sql.as_bytes()).is_ok());
+            assert!(stream.write_all(b".exit\n").is_ok());
+            frames(BufReader::new(stream))
+        };
+        let created = request("create table t (id integer);\ninsert into t values (7);\n");
+        assert_eq!(created, vec![frame("ok", ""), frame("ok", "")]);
+        let selected = request("select id from t;\n");
+        assert_eq!(selected, vec![frame("ok", "[\"id\"]\n[7]\n")]);
+    }
+}
@@ -0,0 +1,338 @@
+//! Importing SQLite databases, under the `sqlite` feature: copies the tables of a SQLite file
+//! into a database along with their rows, for moving an existing database over to juicydb; see
+//! [`import`]. The file is read by SQLite itself, through rusqlite, which is built in.
+//!
+//! SQLite lets any column hold any value, so the type of a column is only a preference, which
+//! the rows have the last say on. A declared type juicydb knows, e.g. `decimal(10, 2)` or
+//! `date`, is kept as it is, and other declared types are mapped by the rules SQLite gives them
+//! their affinity by, e.g. `varchar(20)` to text and `bigint` to integer, with `bool` and
+//! `datetime` as booleans and timestamps. A type is only kept if all of the values of the
+//! column fit it without loss, e.g. text reading as dates into a date column and 0 and 1 into a
+//! boolean column. Otherwise, and for columns without a type, the column takes the type of its
+//! values: integer if all are integers, real if all are numbers and text otherwise. Blobs are
+//! imported as the hexadecimal text of their bytes.
+//!
+//! Primary keys and unique constraints of a single column, not null constraints and indexes of
+//! a single column are copied too. Foreign keys aren't, as SQLite doesn't enforce them unless
+//! asked to, so that the rows may well violate them, and neither are views and triggers, whose
+//! SQL is SQLite's. Names are made into identifiers juicydb can parse, e.g. `order items` into
+//! `order_items`.
+
+use crate::db::{DBType, DBValue, Row};
+use crate::decimal::Decimal;
+use crate::parser::{Constraint, Expression, Parser, Statement};
+use crate::storage_manager::{StorageError, StorageManager};
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, OpenFlags};
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(err: rusqlite::Error) -> Self {
+        Self::Sqlite(err.to_string())
+    }
+}
+
+/// Creates the tables of the SQLite database in the file, in order of creation, and inserts
+/// their rows, all or nothing, as [`StorageManager::atomically`] does. Fails if a table of the
+/// same name exists already. Returns the names of the tables created and their numbers of rows.
+pub fn import(
+    storage: &mut StorageManager,
+    path: &str,
+) -> Result<Vec<(String, usize)>, StorageError> {
+    let sqlite = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut statement = sqlite.prepare(
+        "select name from sqlite_master where type = 'table' and name not like 'sqlite_%' \
+         order by rowid",
+    )?;
+    let names = statement
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    storage.atomically(|storage| {
+        let mut tables = Vec::new();
+        for name in names {
+            let rows = import_table(&sqlite, storage, &name)?;
+            tables.push((identifier(&name), rows));
+        }
+        Ok(tables)
+    })
+}
+
+/// Creates the table and its indexes and inserts its rows, returning the number of rows.
+fn import_table(
+    sqlite: &Connection,
+    storage: &mut StorageManager,
+    name: &str,
+) -> Result<usize, StorageError> {
+    let table = identifier(name);
+    let mut statement = sqlite
+        .prepare("select name, type, \"notnull\", pk from pragma_table_info(?1) order by cid")?;
+    let declared = statement
+        .query_map([name], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get(2)?,
+                row.get(3)?,
+            ))
+        })?
+        .collect::<Result<Vec<(String, String, bool, i64)>, _>>()?;
+    let mut rows = read_rows(sqlite, name, declared.len())?;
+    let mut columns = Vec::new();
+    let mut constraints = Vec::new();
+    for (i, (column, declared_type, not_null, _)) in declared.iter().enumerate() {
+        let column = identifier(column);
+        let db_type = column_type(declared_type, rows.iter().map(|row| &row[i]));
+        for row in &mut rows {
+            row[i] = convert(db_type, &row[i]).unwrap_or(DBValue::Null);
+        }
+        if *not_null {
+            constraints.push(Constraint::NotNull(column.clone()));
+        }
+        columns.push((column, db_type));
+    }
+    // Keys of several columns are left out, as juicydb has none
+    let keys: Vec<_> = declared.iter().filter(|(.., pk)| *pk > 0).collect();
+    if let [(column, ..)] = keys[..] {
+        constraints.push(Constraint::PrimaryKey(identifier(column)));
+    }
+    let indexes = indexes(sqlite, name)?;
+    for (_, column, origin) in &indexes {
+        if origin == "u" {
+            constraints.push(Constraint::Unique(identifier(column)));
+        }
+    }
+    storage.execute(Statement::CreateTable {
+        table: table.clone(),
+        columns,
+        constraints,
+    })?;
+    for (index, column, origin) in indexes {
+        if origin == "c" {
+            storage.execute(Statement::CreateIndex {
+                index: identifier(&index),
+                table: table.clone(),
+                column: identifier(&column),
+                unique: false,
+            })?;
+        }
+    }
+    let count = rows.len();
+    for row in rows {
+        storage.execute(Statement::InsertInto {
+            table: table.clone(),
+            columns: None,
+            values: row.into_iter().map(Expression::Value).collect(),
+        })?;
+    }
+    Ok(count)
+}
+
+/// The rows of the table, with the values as SQLite stores them.
+fn read_rows(sqlite: &Connection, table: &str, columns: usize) -> Result<Vec<Row>, StorageError> {
+    let sql = format!("select * from \"{}\"", table.replace('"', "\"\""));
+    let mut statement = sqlite.prepare(&sql)?;
+    let mut rows = statement.query([])?;
+    let mut values = Vec::new();
+    while let Some(row) = rows.next()? {
+        let row = (0..columns)
+            .map(|i| row.get_ref(i).map(value))
+            .collect::<Result<_, _>>()?;
+        values.push(row);
+    }
+    Ok(values)
+}
+
+/// The indexes of a single column of the table, other than of its primary key: their names,
+/// their columns and whether they were made by `create index` (`c`) or a unique constraint
+/// (`u`). Unique indexes made by `create index` are left out too, as juicydb keeps them as
+/// constraints, and so are partial indexes and indexes of expressions.
+fn indexes(
+    sqlite: &Connection,
+    table: &str,
+) -> Result<Vec<(String, String, String)>, StorageError> {
+    let mut statement = sqlite.prepare(
+        "select l.name, min(i.name), l.origin from pragma_index_list(?1) as l, \
+         pragma_index_info(l.name) as i \
+         where l.origin in ('c', 'u') and not l.partial and (l.origin = 'u' or not l.\"unique\") \
+         group by l.name having count(*) = 1 and min(i.name) is not null order by l.name",
+    )?;
+    let indexes = statement
+        .query_map([table], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<Result<_, _>>()?;
+    Ok(indexes)
+}
+
+fn value(value: ValueRef) -> DBValue {
+    match value {
+        ValueRef::Null => DBValue::Null,
+        ValueRef::Integer(i) => DBValue::Integer(i),
+        ValueRef::Real(r) => DBValue::Real(r),
+        ValueRef::Text(text) => DBValue::Text(String::from_utf8_lossy(text).into_owned()),
+        ValueRef::Blob(bytes) => {
+            DBValue::Text(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+        }
+    }
+}
+
+/// The type of a column declared as the given type, holding the values.
+fn column_type<'a>(declared: &str, values: impl Iterator<Item = &'a DBValue> + Clone) -> DBType {
+    if let Some(db_type) = declared_type(declared) {
+        if values
+            .clone()
+            .all(|value| convert(db_type, value).is_some())
+        {
+            return db_type;
+        }
+    }
+    let mut db_type = None;
+    for value in values {
+        db_type = match (db_type, value) {
+            (db_type, DBValue::Null) => db_type,
+            (None | Some(DBType::Integer), DBValue::Integer(_)) => Some(DBType::Integer),
+            (None | Some(DBType::Integer) | Some(DBType::Real), DBValue::Real(_)) => {
+                Some(DBType::Real)
+            }
+            (Some(DBType::Real), DBValue::Integer(_)) => Some(DBType::Real),
+            _ => return DBType::Text,
+        };
+    }
+    db_type.unwrap_or(DBType::Text)
+}
+
+/// The type juicydb prefers for a column declared as the given type in SQLite, if any; see
+/// <https://www.sqlite.org/datatype3.html#determination_of_column_affinity>.
+fn declared_type(declared: &str) -> Option<DBType> {
+    let mut parser = Parser::new(declared);
+    match parser.parse_db_type() {
+        Ok(db_type) if parser.at_end() => return Some(db_type),
+        _ => (),
+    }
+    let declared = declared.to_ascii_uppercase();
+    let contains = |names: &[&str]| names.iter().any(|name| declared.contains(name));
+    if contains(&["INT"]) {
+        Some(DBType::Integer)
+    } else if contains(&["CHAR", "CLOB", "TEXT"]) {
+        Some(DBType::Text)
+    } else if contains(&["REAL", "FLOA", "DOUB"]) {
+        Some(DBType::Real)
+    } else if contains(&["BOOL"]) {
+        Some(DBType::Boolean)
+    } else if contains(&["DATETIME"]) {
+        Some(DBType::Timestamp)
+    } else {
+        None
+    }
+}
+
+/// The value as one of the type, if it fits without loss: numbers have to keep their value, and
+/// text has to read as a value of the type, exactly so for decimals.
+fn convert(db_type: DBType, value: &DBValue) -> Option<DBValue> {
+    let converted = match (db_type, value) {
+        (_, DBValue::Null) => return Some(DBValue::Null),
+        (DBType::Text, DBValue::Text(_)) => return Some(value.clone()),
+        (DBType::Text, value) => return Some(DBValue::Text(value.to_string())),
+        (DBType::Boolean, DBValue::Integer(i @ (0 | 1))) => return Some(DBValue::Boolean(*i == 1)),
+        (db_type, value) if value.val_to_type().is_some_and(|t| db_type.accepts(t)) => {
+            return Some(value.clone())
+        }
+        (db_type, value) => db_type.coerce(value)?,
+    };
+    let exact = match (value, &converted) {
+        (DBValue::Text(text), DBValue::Decimal(_)) => Decimal::parse(text).map(DBValue::Decimal),
+        (DBValue::Text(_), _) => Some(converted.clone()),
+        (value, _) => Some(value.clone()),
+    };
+    let fits = converted.val_to_type().is_some_and(|t| db_type.accepts(t));
+    (fits && exact.as_ref() == Some(&converted)).then_some(converted)
+}
+
+/// The name as an identifier of juicydb: letters, digits and underscores, starting with a
+/// letter.
+fn identifier(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    match name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        true => name,
+        false => format!("x{}", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    use crate::datetime;
+
+    #[test]
+    fn import_sqlite() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("import.sqlite");
+        let path = path.to_string_lossy().into_owned();
+        let sqlite = Connection::open(&path).unwrap_or_else(|err| panic!("{}", err));
+        let script = "
+            create table users (id integer primary key, name varchar(20) not null unique,
+                born date, active bool, score numeric, data blob, misc);
+            create index users_born on users (born);
+            create table \"order items\" (a, b, primary key (a, b));
+            insert into users values (1, 'ada', '1815-12-10', 1, 1.5, x'00ff', 'x');
+            insert into users values (2, 'bob', 'someday', 0, 2, null, 3);
+            insert into \"order items\" values (1, 2);
+        ";
+        sqlite
+            .execute_batch(script)
+            .unwrap_or_else(|err| panic!("{}", err));
+        drop(sqlite);
+        let mut db = Database::new();
+        let tables = db
+            .import_sqlite(&path)
+            .unwrap_or_else(|err| panic!("{}", err));
+        assert_eq!(
+            tables,
+            vec![(String::from("users"), 2), (String::from("order_items"), 1)]
+        );
+        let schema = db
+            .schema(Some("users"))
+            .unwrap_or_else(|err| panic!("{}", err));
+        assert_eq!(schema.len(), 2);
+        // Dates that don't read as dates keep the column text
+        for (column, db_type) in &[
+            ("id integer", "primary key"),
+            ("name text", "not null"),
+            ("born text", ""),
+            ("active boolean", ""),
+            ("score real", ""),
+            ("data text", ""),
+            ("misc text", ""),
+        ] {
+            assert!(schema[0].contains(column), "{} in {}", column, schema[0]);
+            assert!(schema[0].contains(db_type), "{} in {}", db_type, schema[0]);
+        }
+        assert!(schema[0].contains("unique"));
+        assert!(schema[1].starts_with("create index users_born "));
+        let rows = db.query("select active, data, misc from users where id = 1;");
+        let expected = vec![
+            DBValue::Boolean(true),
+            DBValue::Text(String::from("00ff")),
+            DBValue::Text(String::from("x")),
+        ];
+        assert_eq!(rows.ok().map(|result| result.rows), Some(vec![expected]));
+        // Importing again fails as a whole, as the tables exist
+        assert!(db.import_sqlite(&path).is_err());
+        let count = db.query("select id from users;");
+        assert_eq!(count.ok().map(|result| result.rows.len()), Some(2));
+        assert!(
+            convert(DBType::Date, &DBValue::Text(String::from("1815-12-10"))).is_some_and(|date| {
+                Some(date) == datetime::parse_date("1815-12-10").map(DBValue::Date)
+            })
+        );
+        assert_eq!(
+            convert(
+                DBType::Decimal(10, 2),
+                &DBValue::Text(String::from("1.234"))
+            ),
+            None
+        );
+        assert_eq!(identifier("2nd table"), "x2nd_table");
+    }
+}
@@ -1,11 +1,366 @@
+use crate::btree::{BTree, DatabaseFile, Key, SavedTree};
+use crate::cipher::Cipher;
+use crate::copy::{self, CopyOptions};
 use crate::db::*;
+use crate::executor::{
+    self, Aggregate, HashJoin, IndexJoin, MergeJoin, NestedLoopJoin, Operator, Project, SeqScan,
+    Sort, Values, DEFAULT_SORT_MEMORY,
+};
+use crate::from_row::ResultRow;
+use crate::functions::{AggregateFunction, Functions, ScalarFunction};
+use crate::hooks::{Hooks, Operation};
+use crate::json;
+use crate::lock_manager::{LockManager, LockMode};
+use crate::optimizer::*;
+use crate::pager::{
+    journal_path, CorruptPage, MemoryStorage, PageCounts, PageId, Pager, ReadMode,
+    UndecryptablePage,
+};
+use crate::parallel::map_chunks;
 use crate::parser::*;
-use std::collections::HashMap;
+use crate::plan_cache::{PlanCache, DEFAULT_PLAN_CACHE_SIZE};
+use crate::planner::*;
+use crate::progress::Progress;
+use crate::query_processor::*;
+use crate::record::encode_key;
+use crate::transaction::{IsolationLevel, TransactionId, TransactionManager};
+use crate::virtual_table::{Module, VirtualTables};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::mem;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::slice;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[derive(Debug)]
 pub struct StorageManager {
     tables: HashMap<String, Table>,
+    views: HashMap<String, Statement>,
+    /// Read-only tables describing the catalog, such as `information_schema.tables`, rebuilt
+    /// whenever a table or view is created or dropped.
+    information_schema: HashMap<String, Table>,
+    /// Incremented on every change to the schema, invalidating the plans cached before it.
+    schema_version: u64,
+    plans: Mutex<PlanCache<Plan>>,
+    sequences: HashMap<String, Sequence>,
+    functions: Functions,
+    virtual_tables: VirtualTables,
+    transactions: TransactionManager<Undo>,
+    locks: LockManager,
+    /// Transaction the running statement belongs to, which owns the row locks it takes.
+    owner: TransactionId,
+    /// The session statements run in; see [`StorageManager::in_session`].
+    session: Session,
+    sort_memory: usize,
+    parallelism: usize,
+    /// Directory holding the b-tree files of the tables, or `None` for `:memory:`.
+    directory: Option<PathBuf>,
+    read_mode: ReadMode,
+    /// Pages read and written by the pagers of the files of the database.
+    page_counts: Arc<PageCounts>,
+    /// Rows fetched from the tables of the database by scans and joins.
+    rows_scanned: AtomicU64,
+    /// File the database is saved to, if it was opened from one.
+    file: Option<PathBuf>,
+    /// The trees of the file the database was opened from, holding the rows of its tables as
+    /// they were last saved.
+    trees: Option<DatabaseFile>,
+    synchronous: Synchronous,
+    /// Cipher the file is encrypted with, if opened with a passphrase.
+    cipher: Option<Cipher>,
+    /// Whether each save of the file is also kept as an archive, set with `pragma archive`; see
+    /// [`StorageManager::restore_archive`].
+    archive: bool,
+    journal_mode: JournalMode,
+}
+
+/// When a database opened from a file is saved, set with `pragma synchronous`. Databases are
+/// durable by default: each statement is saved and synced to the disk as its changes are
+/// committed. A save writes only the pages the changes touch, so its cost follows the size of
+/// the changes rather than of the database. Bulk loads can relax this, and save once they are
+/// done.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Synchronous {
+    /// Only saves with [`StorageManager::save`].
+    Off,
+    /// Saves every committed statement without syncing the file or its journal, which survives
+    /// a crash of the process but not of the operating system.
+    Normal,
+    /// Saves and syncs every committed statement.
+    Full,
+}
+
+impl fmt::Display for Synchronous {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Off => write!(f, "off"),
+            Self::Normal => write!(f, "normal"),
+            Self::Full => write!(f, "full"),
+        }
+    }
+}
+
+/// How a database opened from a file is saved, set with `pragma journal_mode`. The file is
+/// written in place either way, writing only the pages the changes touch.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JournalMode {
+    /// Writes the pages without a journal, so a save cut off by a crash may leave the file
+    /// corrupt.
+    Off,
+    /// Copies the pages a save overwrites to a rollback journal first, which a failed save, or
+    /// the next opening of the file after a crash, copies back; see [`crate::pager`]. The
+    /// default.
+    Rollback,
+}
+
+/// Resources used by a database, as reported by [`StorageManager::stats`]. Pages are counted
+/// for the files of the database, and rows for its tables.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stats {
+    pub pages_read: u64,
+    /// Pages read by copying them from a memory mapping of their file rather than with a system
+    /// call; see [`ReadMode::Mmap`].
+    pub pages_mapped: u64,
+    pub pages_written: u64,
+    /// Rows fetched from tables by scans and joins.
+    pub rows_scanned: u64,
+    /// Size in bytes of the file the database was opened from, if any.
+    pub file_size: Option<u64>,
+    /// Size in bytes of the rollback journal of the file, which only exists while the file is
+    /// being saved, or after a crash. There is no write-ahead log.
+    pub journal_size: Option<u64>,
+    /// Name, number of rows and memory taken by the rows of each table, in order of name; see
+    /// [`Table::memory`].
+    pub tables: Vec<(String, usize, Option<usize>)>,
+}
+
+impl fmt::Display for JournalMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Off => write!(f, "off"),
+            Self::Rollback => write!(f, "rollback"),
+        }
+    }
+}
+
+/// Location keeping all tables in memory rather than in b-tree files.
+pub const IN_MEMORY: &str = ":memory:";
+
+/// Savepoint imports are run under within a transaction in progress; see
+/// [`StorageManager::atomically`].
+const IMPORT_SAVEPOINT: &str = "import";
+
+/// The columns of a table for the members of JSON objects, in order of first appearance, typed
+/// by their values other than null: integer if all are integers, real if all are numbers,
+/// boolean if all are booleans, and text otherwise, including for columns of nulls only.
+fn json_columns<'a>(
+    rows: impl Iterator<Item = &'a Vec<(String, DBValue)>>,
+) -> Vec<(String, DBType)> {
+    let mut columns: Vec<(String, Option<DBType>)> = Vec::new();
+    for (name, value) in rows.flatten() {
+        let i = match columns.iter().position(|(column, _)| column == name) {
+            Some(i) => i,
+            None => {
+                columns.push((name.clone(), None));
+                columns.len() - 1
+            }
+        };
+        let db_type = &mut columns[i].1;
+        *db_type = match (*db_type, value.val_to_type()) {
+            (db_type, None) => db_type,
+            (None, Some(new)) => Some(new),
+            (Some(DBType::Integer), Some(DBType::Real)) => Some(DBType::Real),
+            (Some(DBType::Real), Some(DBType::Integer)) => Some(DBType::Real),
+            (Some(old), Some(new)) if old == new => Some(old),
+            _ => Some(DBType::Text),
+        };
+    }
+    columns
+        .into_iter()
+        .map(|(name, db_type)| (name, db_type.unwrap_or(DBType::Text)))
+        .collect()
+}
+
+/// How long a session waits for the locks of other sessions by default; see
+/// [`Session::busy_timeout`].
+pub const DEFAULT_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Separates the name of a database file from the time of the save in the names of its
+/// archives; see [`StorageManager::restore_archive`].
+const ARCHIVE_SUFFIX: &str = "-archive-";
+
+/// The archives of the database file, with the times they were saved at.
+fn archives(path: &Path) -> io::Result<Vec<(i64, PathBuf)>> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let prefix = match path.file_name() {
+        Some(name) => format!("{}{}", name.to_string_lossy(), ARCHIVE_SUFFIX),
+        None => return Ok(Vec::new()),
+    };
+    let mut archives = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let saved = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(&prefix))
+            .and_then(|saved| saved.parse().ok());
+        if let Some(saved) = saved {
+            archives.push((saved, entry.path()));
+        }
+    }
+    Ok(archives)
+}
+
+/// Kinds of the records of the catalog of a database file; see
+/// [`StorageManager::write_catalog`].
+const TABLE: &str = "table";
+const INDEX: &str = "index";
+const VIEW: &str = "view";
+const SEQUENCE: &str = "sequence";
+
+/// Copy of the tables and views of the database.
+type Snapshot = (HashMap<String, Table>, HashMap<String, Statement>);
+
+/// What undoes the changes of a transaction from its beginning or from a savepoint on: the
+/// tables and views it changed since as they were before, and the rows it changed since in
+/// tables it only locked rows of, as they were before, by table and primary key. Sequences are
+/// not undone; like in most databases, they are unaffected by rollbacks.
+#[derive(Debug, Default)]
+struct Undo {
+    /// Tables and views by name, `None` if there was none by the name.
+    tables: HashMap<String, (Option<Table>, Option<Statement>)>,
+    /// Rows by primary key, `None` if there was no row with the key. Rows of tables in `tables`
+    /// aren't kept, as the whole table is put back anyway.
+    rows: HashMap<String, HashMap<DBValue, Option<Row>>>,
+}
+
+impl Undo {
+    /// Adds what undoes the later changes, so that the earliest version of each table and row
+    /// is put back.
+    fn merge(&mut self, later: Undo) {
+        for (name, rows) in later.rows {
+            if !self.tables.contains_key(&name) {
+                let saved = self.rows.entry(name).or_default();
+                for (key, row) in rows {
+                    saved.entry(key).or_insert(row);
+                }
+            }
+        }
+        for (name, saved) in later.tables {
+            self.tables.entry(name).or_insert(saved);
+        }
+    }
+
+    /// Puts back the tables, views and rows as they were. Tables go first, so that rows changed
+    /// before the table was kept are put back over it.
+    fn apply(
+        self,
+        tables: &mut HashMap<String, Table>,
+        views: &mut HashMap<String, Statement>,
+    ) -> io::Result<()> {
+        for (name, (table, view)) in self.tables {
+            match table {
+                Some(table) => tables.insert(name.clone(), table),
+                None => tables.remove(&name),
+            };
+            match view {
+                Some(view) => views.insert(name, view),
+                None => views.remove(&name),
+            };
+        }
+        for (name, rows) in self.rows {
+            if let Some(table) = tables.get_mut(&name) {
+                for (key, row) in rows {
+                    put_back_row(table, &key, row)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        let mut tables = HashMap::new();
+        for (name, (table, view)) in &self.tables {
+            let table = table.as_ref().map(Table::try_clone).transpose()?;
+            tables.insert(name.clone(), (table, view.clone()));
+        }
+        Ok(Self {
+            tables,
+            rows: self.rows.clone(),
+        })
+    }
+}
+
+/// What a session with a database keeps apart from other sessions: its transaction, its
+/// settings and its hooks. Sessions take turns running statements on the shared tables with
+/// [`StorageManager::in_session`].
+#[derive(Debug)]
+pub struct Session {
+    transaction: Option<TransactionId>,
+    /// Isolation level of the transactions begun from now on.
+    isolation: IsolationLevel,
+    busy_timeout: Duration,
+    hooks: Hooks,
+    progress: Progress,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self {
+            transaction: None,
+            isolation: IsolationLevel::Serializable,
+            busy_timeout: DEFAULT_BUSY_TIMEOUT,
+            hooks: Hooks::new(),
+            progress: Progress::new(),
+        }
+    }
+
+    /// The progress of the statement running in the session; see [`crate::progress`].
+    pub fn progress(&self) -> &Progress {
+        &self.progress
+    }
+
+    pub fn progress_mut(&mut self) -> &mut Progress {
+        &mut self.progress
+    }
+
+    /// The hooks called on the changes made in the session; see [`crate::hooks`].
+    pub fn hooks_mut(&mut self) -> &mut Hooks {
+        &mut self.hooks
+    }
+
+    /// The id of the transaction in progress, if any.
+    pub fn transaction(&self) -> Option<TransactionId> {
+        self.transaction
+    }
+
+    /// How long a statement of the session is retried while it conflicts with the locks of other
+    /// sessions, before failing with their error; set with `pragma busy_timeout = <ms>;`. Zero
+    /// fails such statements at once. Retrying is up to the owner of the session, e.g.
+    /// [`crate::connection::Connection`], which lets other sessions go on in between.
+    pub fn busy_timeout(&self) -> Duration {
+        self.busy_timeout
+    }
+
+    pub fn set_busy_timeout(&mut self, timeout: Duration) {
+        self.busy_timeout = timeout;
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 pub enum StorageError {
@@ -13,6 +368,159 @@ pub enum StorageError {
     SchemaMismatch,
     TypeError,
     TableNameAlreadyInUse,
+    ViewNotFound,
+    DependentViews,
+    IndexNameAlreadyInUse,
+    UniqueViolation,
+    MultiplePrimaryKeys,
+    ForeignKeyViolation,
+    ReferencedColumnNotUnique,
+    DependentForeignKeys,
+    NotNullViolation(String),
+    SequenceNotFound,
+    SequenceNameAlreadyInUse,
+    FunctionNotFound(String),
+    /// A registered function failed, with its name and message.
+    FunctionFailed(String, String),
+    ModuleNotFound(String),
+    /// The statement was interrupted; see [`crate::progress`].
+    Interrupted,
+    /// A virtual table failed to be made or scanned, with its name and message.
+    VirtualTableFailed(String, String),
+    TransactionAlreadyActive,
+    NoActiveTransaction,
+    SavepointNotFound,
+    UnboundParameter,
+    DivisionByZero,
+    IntegerOverflow,
+    MisplacedAggregate(String),
+    InvalidPattern(String),
+    DateTimeOutOfRange,
+    DecimalOverflow,
+    CheckViolation(String),
+    AmbiguousColumn(String),
+    Io(String),
+    CorruptPage(PageId),
+    UndecryptablePage(PageId),
+    NoDatabaseFile,
+    NoArchive,
+    UnknownPragma(String),
+    InvalidPragmaValue(String),
+    TableLocked(String),
+    RowLocked(String),
+    /// A line of JSON being imported couldn't be read, with its number from 1 and the message.
+    InvalidJson(usize, String),
+    /// A record of a file being loaded, by a 'copy'-statement or as JSON, couldn't be read or
+    /// inserted, with the number of its line from 1 and the message, which names the column at
+    /// fault if there is one.
+    InvalidData(usize, String),
+    /// A SQLite database being imported couldn't be read, with the message of SQLite.
+    Sqlite(String),
+}
+
+impl From<io::Error> for StorageError {
+    fn from(err: io::Error) -> Self {
+        let inner = err.get_ref();
+        if let Some(CorruptPage(page_id)) = inner.and_then(|err| err.downcast_ref()) {
+            return Self::CorruptPage(*page_id);
+        }
+        if let Some(UndecryptablePage(page_id)) = inner.and_then(|err| err.downcast_ref()) {
+            return Self::UndecryptablePage(*page_id);
+        }
+        Self::Io(err.to_string())
+    }
+}
+
+/// A 'select'-statement with its views inlined, i.e. a query over a base table and the base
+/// tables joined to it.
+#[derive(Clone, Debug)]
+struct InlinedQuery {
+    columns: Vec<Expression>,
+    table: String,
+    joins: Vec<Join>,
+    condition: Option<Condition>,
+    group_by: Vec<Expression>,
+    order_by: Vec<(Expression, SortOrder)>,
+    limit: Option<Limit>,
+}
+
+/// A query checked against the schema and simplified, along with the names and types of its
+/// result columns and the order in which to join its tables; see [`StorageManager::plan`].
+#[derive(Clone, Debug)]
+pub struct QueryPlan {
+    query: InlinedQuery,
+    columns: Vec<ResultColumn>,
+    order: Vec<usize>,
+}
+
+/// What is cached for a statement: the plan of a query, or the parsed form of any other
+/// statement.
+#[derive(Clone, Debug)]
+pub enum Plan {
+    Query(QueryPlan),
+    Statement(Statement),
+}
+
+/// Rows to delete, and columns to set to null in rows, per table, as a result of a deletion.
+type DeletePlan = (
+    HashMap<String, BTreeSet<Key>>,
+    HashMap<String, BTreeMap<Key, Vec<usize>>>,
+);
+
+impl InlinedQuery {
+    /// Field selectors referenced anywhere in the query.
+    fn selectors(&self) -> impl Iterator<Item = &Selector> {
+        let sort_keys = self.order_by.iter().map(|(expression, _)| expression);
+        self.columns
+            .iter()
+            .chain(&self.group_by)
+            .chain(sort_keys)
+            .flat_map(Expression::selectors)
+            .chain(
+                self.joins
+                    .iter()
+                    .map(|join| &join.condition)
+                    .chain(&self.condition)
+                    .flat_map(Condition::selectors),
+            )
+    }
+
+    /// Folds the constant subexpressions of the query and simplifies its conditions, see
+    /// [`optimizer`](crate::optimizer).
+    fn simplify(&mut self, scope: &Scope) {
+        let fold = |expression| fold_expression(expression, scope);
+        self.columns = mem::take(&mut self.columns).into_iter().map(fold).collect();
+        self.group_by = mem::take(&mut self.group_by)
+            .into_iter()
+            .map(fold)
+            .collect();
+        self.order_by = mem::take(&mut self.order_by)
+            .into_iter()
+            .map(|(expression, order)| (fold(expression), order))
+            .collect();
+        self.joins = mem::take(&mut self.joins)
+            .into_iter()
+            .map(|join| Join {
+                condition: simplify_condition(join.condition, scope),
+                ..join
+            })
+            .collect();
+        self.condition = simplify_where(self.condition.take(), scope);
+    }
+
+    /// Whether the query is `select count(*)` over a whole table, which the row count of the
+    /// table answers without scanning it.
+    fn counts_rows(&self) -> bool {
+        let count = matches!(
+            self.columns.as_slice(),
+            [Expression::Function(name, args)] if name.eq_ignore_ascii_case("count") && args.is_empty()
+        );
+        count
+            && self.joins.is_empty()
+            && self.condition.is_none()
+            && self.group_by.is_empty()
+            && self.order_by.is_empty()
+    }
 }
 
 impl fmt::Display for StorageError {
@@ -22,59 +530,4761 @@ impl fmt::Display for StorageError {
             Self::SchemaMismatch => write!(f, "Schema mismatch"),
             Self::TypeError => write!(f, "Type error"),
             Self::TableNameAlreadyInUse => write!(f, "Table name already in use"),
+            Self::ViewNotFound => write!(f, "View not found"),
+            Self::DependentViews => write!(f, "Other views depend on this table or view"),
+            Self::IndexNameAlreadyInUse => write!(f, "Index name already in use"),
+            Self::UniqueViolation => write!(f, "Duplicate value in unique column"),
+            Self::MultiplePrimaryKeys => write!(f, "Table can have only one primary key"),
+            Self::ForeignKeyViolation => write!(f, "Foreign key constraint violated"),
+            Self::ReferencedColumnNotUnique => {
+                write!(f, "Column referenced by a foreign key must be unique")
+            }
+            Self::DependentForeignKeys => {
+                write!(f, "Foreign keys in other tables reference this table")
+            }
+            Self::SequenceNotFound => write!(f, "Sequence not found"),
+            Self::SequenceNameAlreadyInUse => write!(f, "Sequence name already in use"),
+            Self::FunctionNotFound(name) => write!(f, "Function '{}' not found", name),
+            Self::FunctionFailed(name, message) => {
+                write!(f, "Function '{}' failed: {}", name, message)
+            }
+            Self::ModuleNotFound(name) => write!(f, "Module '{}' not found", name),
+            Self::Interrupted => write!(f, "Interrupted"),
+            Self::VirtualTableFailed(name, message) => {
+                write!(f, "Virtual table '{}' failed: {}", name, message)
+            }
+            Self::TransactionAlreadyActive => write!(f, "A transaction is already active"),
+            Self::NoActiveTransaction => write!(f, "No active transaction"),
+            Self::SavepointNotFound => write!(f, "Savepoint not found"),
+            Self::UnboundParameter => write!(f, "No value bound for parameter"),
+            Self::DivisionByZero => write!(f, "Division by zero"),
+            Self::IntegerOverflow => write!(f, "Integer overflow"),
+            Self::DecimalOverflow => write!(f, "Numeric value out of range"),
+            Self::DateTimeOutOfRange => write!(f, "Date or time out of range"),
+            Self::InvalidPattern(err) => write!(f, "Invalid regular expression: {}", err),
+            Self::MisplacedAggregate(name) => {
+                write!(f, "Aggregate function '{}' is not allowed here", name)
+            }
+            Self::CheckViolation(column) => {
+                write!(f, "Value not allowed in column '{}'", column)
+            }
+            Self::Io(err) => write!(f, "I/O error: {}", err),
+            Self::InvalidJson(line, message) => {
+                write!(f, "Invalid JSON on line {}: {}", line, message)
+            }
+            Self::InvalidData(line, message) => {
+                write!(f, "Invalid data on line {} of the file: {}", line, message)
+            }
+            Self::Sqlite(message) => write!(f, "SQLite error: {}", message),
+            Self::CorruptPage(page_id) => {
+                write!(
+                    f,
+                    "Database file is corrupt: checksum mismatch in page {}",
+                    page_id
+                )
+            }
+            Self::UndecryptablePage(page_id) => write!(
+                f,
+                "Database file could not be decrypted: wrong key or altered page {}",
+                page_id
+            ),
+            Self::NoDatabaseFile => write!(f, "No database file is open"),
+            Self::TableLocked(table) => {
+                write!(f, "Table '{}' is locked by another transaction", table)
+            }
+            Self::RowLocked(table) => {
+                write!(
+                    f,
+                    "Row of table '{}' is locked by another transaction",
+                    table
+                )
+            }
+            Self::NoArchive => write!(f, "No archive of the database file at or before the time"),
+            Self::UnknownPragma(name) => write!(f, "Unknown pragma '{}'", name),
+            Self::InvalidPragmaValue(value) => write!(f, "Invalid pragma value '{}'", value),
+            Self::AmbiguousColumn(column) => {
+                write!(f, "Column reference '{}' is ambiguous", column)
+            }
+            Self::NotNullViolation(column) => {
+                write!(
+                    f,
+                    "Null value in column '{}' violates not-null constraint",
+                    column
+                )
+            }
         }
     }
 }
 
+/// Orders the names, each given along with the names it depends on, such that every name comes
+/// after its dependencies among the names, and otherwise alphabetically.
+fn dependency_order<'a>(mut pending: Vec<(&'a String, Vec<&'a String>)>) -> Vec<&'a String> {
+    pending.sort();
+    let mut ordered = Vec::new();
+    while !pending.is_empty() {
+        let ready = pending
+            .iter()
+            .position(|(_, dependencies)| {
+                let is_pending = |dependency| pending.iter().any(|(name, _)| *name == dependency);
+                !dependencies
+                    .iter()
+                    .any(|dependency| is_pending(*dependency))
+            })
+            .unwrap_or(0);
+        ordered.push(pending.remove(ready).0);
+    }
+    ordered
+}
+
+/// Names of the columns of a view. Views may only select plain columns, which keep their names.
+fn view_columns(columns: &[Expression]) -> Result<Vec<&str>, StorageError> {
+    columns
+        .iter()
+        .map(|column| match column {
+            Expression::Field(selector) => Ok(selector.field.as_str()),
+            _ => Err(StorageError::SchemaMismatch),
+        })
+        .collect()
+}
+
+/// Error of a database file whose catalog can't be read.
+fn corrupt_file() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Corrupt database file")
+}
+
+/// Puts back the row of the table with the primary key as it was, removing the row with the
+/// key if there was none.
+fn put_back_row(table: &mut Table, key: &DBValue, row: Option<Row>) -> io::Result<()> {
+    let key = match table.key_of(key) {
+        Some(key) if table.get(&key)?.is_some() => Some(key),
+        _ => None,
+    };
+    match (key, row) {
+        (Some(key), Some(row)) => table.update(vec![(key, row)]).map(drop),
+        (Some(key), None) => table.remove(&[key]),
+        (None, Some(row)) => table.push(row).map(drop),
+        (None, None) => Ok(()),
+    }
+}
+
+/// Collects the rows of a query.
+/// A copy of the tables and views, with the rows of tables stored in files copied to new files.
+fn copy_snapshot(
+    tables: &HashMap<String, Table>,
+    views: &HashMap<String, Statement>,
+) -> Result<Snapshot, StorageError> {
+    let tables = tables
+        .iter()
+        .map(|(name, table)| Ok((name.clone(), table.try_clone()?)))
+        .collect::<io::Result<_>>()?;
+    Ok((tables, views.clone()))
+}
+
+fn result_set(rows: Rows<'_>) -> Result<ResultSet, StorageError> {
+    let columns = rows.columns().to_vec();
+    Ok(ResultSet {
+        columns,
+        rows: rows.collect::<Result<_, _>>()?,
+    })
+}
+
 impl StorageManager {
+    /// A database keeping its tables in memory; see [`StorageManager::with_location`].
     pub fn new() -> Self {
-        StorageManager {
+        let mut storage = StorageManager {
             tables: HashMap::new(),
+            views: HashMap::new(),
+            information_schema: HashMap::new(),
+            schema_version: 0,
+            plans: Mutex::new(PlanCache::new(DEFAULT_PLAN_CACHE_SIZE)),
+            sequences: HashMap::new(),
+            functions: Functions::new(),
+            virtual_tables: VirtualTables::new(),
+            transactions: TransactionManager::new(),
+            locks: LockManager::new(),
+            owner: 0,
+            session: Session::new(),
+            sort_memory: DEFAULT_SORT_MEMORY,
+            parallelism: 1,
+            directory: None,
+            read_mode: ReadMode::Syscalls,
+            page_counts: Arc::default(),
+            rows_scanned: AtomicU64::new(0),
+            file: None,
+            trees: None,
+            synchronous: Synchronous::Full,
+            cipher: None,
+            archive: false,
+            journal_mode: JournalMode::Rollback,
+        };
+        storage.refresh_information_schema();
+        storage
+    }
+
+    /// A database storing the rows of each table in a b-tree file of its own in the given
+    /// directory, which must exist, or keeping them in memory if the location is
+    /// [`IN_MEMORY`]. The files are removed as their tables are dropped.
+    pub fn with_location(location: &str) -> Self {
+        let mut storage = Self::new();
+        if location != IN_MEMORY {
+            storage.directory = Some(PathBuf::from(location));
         }
+        storage
     }
 
-    pub fn create_table(&mut self, name: String, schema: Schema) -> Result<(), StorageError> {
-        if self.tables.contains_key(&name) {
-            return Err(StorageError::TableNameAlreadyInUse);
+    /// Like [`StorageManager::with_location`], reading the pages of the b-tree files as given.
+    pub fn with_read_mode(location: &str, read_mode: ReadMode) -> Self {
+        let mut storage = Self::with_location(location);
+        storage.read_mode = read_mode;
+        storage
+    }
+
+    /// Flushes the tables stored in files to the disk. Checkpoints are also taken automatically
+    /// as pages are written; see [`crate::pager::CHECKPOINT_PAGES`].
+    pub fn checkpoint(&mut self) -> Result<(), StorageError> {
+        for table in self.tables.values_mut() {
+            table.checkpoint()?;
         }
-        self.tables.insert(name, Table::new(schema));
         Ok(())
     }
 
-    pub fn insert_into(&mut self, table: String, values: Vec<DBValue>) -> Result<(), StorageError> {
-        let table = self
+    /// Writes the rows of the table to `out` as JSON objects, one per line, keyed by the names of
+    /// the columns; see [`crate::json`]. Returns the number of rows written.
+    pub fn export_json(&self, table: &str, out: &mut impl Write) -> Result<usize, StorageError> {
+        let data = self.tables.get(table).ok_or(StorageError::TableNotFound)?;
+        let columns: Vec<_> = data.schema().columns().map(|c| c.name.clone()).collect();
+        let mut rows = 0;
+        for row in data.rows() {
+            writeln!(out, "{}", json::object(&columns, &row?))?;
+            rows += 1;
+        }
+        Ok(rows)
+    }
+
+    /// Inserts the rows read from `input` as JSON objects, one per line, into the table, setting
+    /// the columns named by the members of each object; see [`crate::json`]. A table that
+    /// doesn't exist yet is created with the members of the objects as its columns, typed by
+    /// their values; see [`json_columns`]. The rows are inserted all or none, in a transaction
+    /// of their own, or under a savepoint if a transaction is in progress. Returns the number
+    /// of rows inserted.
+    pub fn import_json(&mut self, table: &str, input: impl BufRead) -> Result<usize, StorageError> {
+        let mut rows = Vec::new();
+        for (i, line) in input.lines().enumerate() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                let members = json::parse_object(&line);
+                let members =
+                    members.map_err(|message| StorageError::InvalidJson(i + 1, message))?;
+                rows.push((i + 1, members));
+            }
+        }
+        if !self.tables.contains_key(table) {
+            self.execute(Statement::CreateTable {
+                table: String::from(table),
+                columns: json_columns(rows.iter().map(|(_, members)| members)),
+                constraints: Vec::new(),
+            })?;
+        }
+        let count = rows.len();
+        let invalid = |line, message| StorageError::InvalidData(line, message);
+        self.atomically(|storage| {
+            rows.into_iter().try_for_each(|(line, members)| {
+                let data = storage
+                    .tables
+                    .get(table)
+                    .ok_or(StorageError::TableNotFound)?;
+                let schema = data.schema();
+                for (name, value) in &members {
+                    let index = schema.get_column_index(name).ok_or_else(|| {
+                        invalid(line, format!("Table '{}' has no column '{}'", table, name))
+                    })?;
+                    let db_type = schema.column(index).db_type;
+                    let coerced = db_type.coerce(value).unwrap_or_else(|| value.clone());
+                    if coerced.val_to_type().is_some_and(|t| !db_type.accepts(t)) {
+                        let value = json::value(value);
+                        let message = format!("Invalid {} {} in column '{}'", db_type, value, name);
+                        return Err(invalid(line, message));
+                    }
+                }
+                let (columns, values) = members
+                    .into_iter()
+                    .map(|(column, value)| (column, Expression::Value(value)))
+                    .unzip();
+                let insert = Statement::InsertInto {
+                    table: String::from(table),
+                    columns: Some(columns),
+                    values,
+                };
+                storage
+                    .execute(insert)
+                    .map(|_| ())
+                    .map_err(|err| invalid(line, err.to_string()))
+            })
+        })?;
+        Ok(count)
+    }
+
+    /// Runs `f` all or nothing: in a transaction of its own, or under a savepoint if a
+    /// transaction is in progress, rolled back if `f` fails.
+    pub(crate) fn atomically<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, StorageError>,
+    ) -> Result<T, StorageError> {
+        let savepoint = self.session.transaction.is_some();
+        let name = || String::from(IMPORT_SAVEPOINT);
+        self.execute(match savepoint {
+            true => Statement::Savepoint { savepoint: name() },
+            false => Statement::Begin,
+        })?;
+        let result = f(self);
+        match (&result, savepoint) {
+            (Ok(_), true) => self.execute(Statement::Release { savepoint: name() })?,
+            (Ok(_), false) => self.execute(Statement::Commit)?,
+            (Err(_), true) => {
+                let savepoint = Some(name());
+                self.execute(Statement::Rollback { savepoint })?;
+                self.execute(Statement::Release { savepoint: name() })?
+            }
+            (Err(_), false) => self.execute(Statement::Rollback { savepoint: None })?,
+        };
+        result
+    }
+
+    /// Counts of the pages and rows read and written so far, the sizes of the file of the
+    /// database and of its journal, and the sizes of the tables.
+    pub fn stats(&self) -> Stats {
+        let (pages_read, pages_written) = self.page_counts.get();
+        let size = |path: &Path| fs::metadata(path).map_or(0, |metadata| metadata.len());
+        let mut tables: Vec<_> = self
             .tables
-            .get_mut(&table)
-            .ok_or(StorageError::TableNotFound)?;
-        let types = values.iter().map(|val| val.val_to_type()).collect();
-        table
-            .schema()
-            .type_check(types)
-            .ok_or(StorageError::TypeError)?;
-        table.push(values);
+            .iter()
+            .map(|(name, table)| (name.clone(), table.len(), table.memory()))
+            .collect();
+        tables.sort();
+        Stats {
+            pages_read,
+            pages_mapped: self.page_counts.mapped(),
+            pages_written,
+            rows_scanned: self.rows_scanned(),
+            file_size: self.file.as_deref().map(size),
+            journal_size: self.file.as_deref().map(|path| size(&journal_path(path))),
+            tables,
+        }
+    }
+
+    /// The numbers of pages read and written so far by the files of the database.
+    pub fn page_counts(&self) -> (u64, u64) {
+        self.page_counts.get()
+    }
+
+    /// The number of rows fetched from the tables of the database by scans and joins so far.
+    pub fn rows_scanned(&self) -> u64 {
+        self.rows_scanned.load(Ordering::Relaxed)
+    }
+
+    /// A database holding the tables, views and sequences saved in the given file by
+    /// [`StorageManager::save`], creating the file if it doesn't exist yet. Only the catalog of
+    /// the file is read: the tables read their rows from their trees in the file as needed, and
+    /// keep the changes made to them in memory until they are saved again as set with
+    /// `pragma synchronous`.
+    pub fn open(path: &str) -> Result<Self, StorageError> {
+        Self::open_with_passphrase(path, None)
+    }
+
+    /// Opens the database in the given file like [`StorageManager::open`], with the file
+    /// encrypted with a key derived from the passphrase; see [`crate::cipher`]. A new file is
+    /// created encrypted, and an existing one must have been encrypted with the same passphrase.
+    pub fn open_encrypted(path: &str, passphrase: &str) -> Result<Self, StorageError> {
+        Self::open_with_passphrase(path, Some(passphrase))
+    }
+
+    fn open_with_passphrase(path: &str, passphrase: Option<&str>) -> Result<Self, StorageError> {
+        let mut storage = Self::new();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let created = file.metadata()?.len() == 0;
+        let mut pager = Pager::new(file)?;
+        pager.recover(&journal_path(Path::new(path)))?;
+        pager.set_counts(storage.page_counts.clone());
+        let tree = if created {
+            pager.set_cipher(passphrase.map(Cipher::new).transpose()?);
+            let mut tree = BTree::create(pager, Schema::new())?;
+            tree.checkpoint()?;
+            tree
+        } else {
+            BTree::open_encrypted(pager, Schema::new(), passphrase)?
+        };
+        storage.cipher = tree.cipher().cloned();
+        storage.load_catalog(tree)?;
+        // Set only now, so that loading the catalog doesn't save it
+        storage.file = Some(PathBuf::from(path));
+        Ok(storage)
+    }
+
+    /// A database holding the tables, views and sequences in the bytes of an unencrypted file
+    /// saved by [`StorageManager::save`] or taken by [`StorageManager::to_bytes`]. The tables
+    /// read their rows from the bytes, and keep their changes in memory. Lets the database be
+    /// kept where there is no file system, e.g. by a web page in the storage of the browser.
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, StorageError> {
+        let mut storage = Self::new();
+        let memory = MemoryStorage::from_bytes(bytes);
+        let mut pager = Pager::with_storage(Box::new(memory), ReadMode::Syscalls)?;
+        pager.set_counts(storage.page_counts.clone());
+        storage.load_catalog(BTree::open(pager, Schema::new())?)?;
+        Ok(storage)
+    }
+
+    /// The bytes of a file holding the database as [`StorageManager::backup`] does, unencrypted.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, StorageError> {
+        let committed = self.committed()?;
+        let (tables, views) = match &committed {
+            Some((tables, views)) => (tables, views),
+            None => (&self.tables, &self.views),
+        };
+        let memory = MemoryStorage::new();
+        let mut pager = Pager::with_storage(Box::new(memory.clone()), ReadMode::Syscalls)?;
+        pager.set_counts(self.page_counts.clone());
+        let mut tree = BTree::create(pager, Schema::new())?;
+        self.write_catalog(&mut tree, tables, views, true, &mut Vec::new())?;
+        Ok(memory.bytes())
+    }
+
+    /// Saves the changes made to the database since it was last saved into the file it was
+    /// opened from, in place. The file holds a catalog of the tables, views and sequences in the
+    /// tree at page 1, and the rows of each table and the entries of each index in trees of
+    /// their own; see [`crate::btree`]. Only the pages of the trees the changes touch are
+    /// written, and the catalog if it changed. With a rollback journal, the default, a failed
+    /// save leaves the file as it was; see [`JournalMode`].
+    pub fn save(&mut self) -> Result<(), StorageError> {
+        if self.session.transaction.is_some() {
+            return Err(StorageError::TransactionAlreadyActive);
+        }
+        self.write_file(true)
+    }
+
+    /// Writes a copy of the database to the given file in the format of
+    /// [`StorageManager::save`], which can then be opened with [`StorageManager::open`], or
+    /// [`StorageManager::open_encrypted`] for a copy of an encrypted database. While
+    /// transactions are in progress, the copy holds the tables and views as of the last commit,
+    /// with the changes of the transactions undone. Sequences are copied as they are, as they
+    /// are not rolled back either.
+    pub fn backup(&self, path: &str) -> Result<(), StorageError> {
+        let committed = self.committed()?;
+        let (tables, views) = match &committed {
+            Some((tables, views)) => (tables, views),
+            None => (&self.tables, &self.views),
+        };
+        self.write_copy(Path::new(path), tables, views)?;
         Ok(())
     }
 
-    // TODO: Refactor into relational set operators and expect that as a parameter
-    // also note the schema/table interface
-    pub fn query(&self, query: Statement) -> Result<Vec<Row>, StorageError> {
-        if let Statement::Select { columns, table, .. } = query {
-            let table = self.tables.get(&table).ok_or(StorageError::TableNotFound)?;
-            let indices = table
-                .schema()
-                .get_column_indices(columns)
-                .ok_or(StorageError::SchemaMismatch)?;
-            let mut view = Vec::new();
-            for row in table.rows() {
-                let mut row_view = Vec::new();
-                for i in &indices {
-                    row_view.push(row[*i].clone());
+    /// Copies of the tables and views as of the last commit, if transactions are in progress,
+    /// with the changes of each transaction undone.
+    fn committed(&self) -> Result<Option<Snapshot>, StorageError> {
+        if self.transactions.active().next().is_none() {
+            return Ok(None);
+        }
+        let (mut tables, mut views) = copy_snapshot(&self.tables, &self.views)?;
+        for id in self.transactions.active() {
+            let undo: Vec<_> = self.transactions.undo(id).collect();
+            for undo in undo.into_iter().rev() {
+                undo.try_clone()?.apply(&mut tables, &mut views)?;
+            }
+        }
+        Ok(Some((tables, views)))
+    }
+
+    /// Names of the tables changed by transactions in progress.
+    fn changing_tables(&self) -> HashSet<&String> {
+        let mut names = HashSet::new();
+        for id in self.transactions.active() {
+            for undo in self.transactions.undo(id) {
+                names.extend(undo.tables.keys());
+                names.extend(undo.rows.keys());
+            }
+        }
+        names
+    }
+
+    /// Saves the database, syncing the file and its journal to the disk only if asked to.
+    /// Otherwise the operating system writes the pages back in its own time. Changes of
+    /// transactions in progress are left out, and kept in memory until saved along with their
+    /// commit.
+    fn write_file(&mut self, sync: bool) -> Result<(), StorageError> {
+        let (path, trees) = match (&self.file, &self.trees) {
+            (Some(path), Some(trees)) => (path.clone(), trees.clone()),
+            _ => return Err(StorageError::NoDatabaseFile),
+        };
+        let committed = self.committed()?;
+        let (tables, views) = match &committed {
+            Some((tables, views)) => (tables, views),
+            None => (&self.tables, &self.views),
+        };
+        let journaled = self.journal_mode == JournalMode::Rollback;
+        let mut created = Vec::new();
+        {
+            let mut tree = trees.write().unwrap();
+            if journaled {
+                tree.pager_mut().begin_journal(journal_path(&path), sync)?;
+            }
+            match self.write_catalog(&mut tree, tables, views, false, &mut created) {
+                Ok(()) if journaled => tree.pager_mut().commit_journal()?,
+                Ok(()) if sync => tree.checkpoint()?,
+                Ok(()) => (),
+                Err(err) => {
+                    if journaled {
+                        tree.pager_mut().rollback_journal()?;
+                        tree.reload()?;
+                    }
+                    return Err(err);
                 }
-                view.push(row_view);
             }
-            Ok(view)
-        } else {
-            Ok(Vec::new())
         }
+        for (tree, root) in created {
+            tree.set_root(root);
+        }
+        let changing: HashSet<_> = self.changing_tables().into_iter().cloned().collect();
+        drop(committed);
+        for (name, table) in &mut self.tables {
+            if !changing.contains(name) {
+                table.mark_saved();
+            }
+        }
+        if self.archive {
+            let since_epoch = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let mut archive = path.as_os_str().to_os_string();
+            archive.push(format!("{}{}", ARCHIVE_SUFFIX, since_epoch.as_micros()));
+            fs::copy(&path, &archive)?;
+        }
+        Ok(())
+    }
+
+    /// Restores the database file to the latest archive of it saved at or before the given time,
+    /// in microseconds since 1970-01-01 00:00:00, for point-in-time recovery. Archives are kept
+    /// with `pragma archive = on;`, which keeps a copy of the file as of each save, named after
+    /// the file and the time of the save, e.g. `foo.db-archive-1717243200000000`. As the file is
+    /// saved as each statement is committed, archives record the database after every commit.
+    /// The restored file is then opened like any other.
+    pub fn restore_archive(path: &str, until: i64) -> Result<(), StorageError> {
+        let path = Path::new(path);
+        let archive = archives(path)?
+            .into_iter()
+            .filter(|(saved, _)| *saved <= until)
+            .max_by_key(|(saved, _)| *saved)
+            .ok_or(StorageError::NoArchive)?;
+        let mut partial = path.as_os_str().to_os_string();
+        partial.push(".partial");
+        fs::copy(archive.1, &partial)?;
+        fs::rename(&partial, path)?;
+        Ok(())
+    }
+
+    /// Writes the tables, views and sequences to a new file, which then replaces the file at the
+    /// given path, so a failed copy leaves it as it was. Returns the tree of the new file.
+    fn write_copy(
+        &self,
+        path: &Path,
+        tables: &HashMap<String, Table>,
+        views: &HashMap<String, Statement>,
+    ) -> Result<BTree, StorageError> {
+        let mut partial = path.as_os_str().to_os_string();
+        partial.push(".partial");
+        let partial = PathBuf::from(partial);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&partial)?;
+        let mut pager = Pager::new(file)?;
+        pager.set_cipher(self.cipher.clone());
+        pager.set_counts(self.page_counts.clone());
+        let mut tree = BTree::create(pager, Schema::new())?;
+        self.write_catalog(&mut tree, tables, views, true, &mut Vec::new())?;
+        tree.checkpoint()?;
+        fs::rename(&partial, path)?;
+        Ok(tree)
+    }
+
+    /// Writes the tables, views and sequences into the file. The rows of each table and the
+    /// entries of each index go into trees of their own, written as [`Table::write_trees`]
+    /// describes. The tree at the root of the file holds the catalog: a record for each table,
+    /// with its 'create table'-statement, the next id of its autoincrementing column, the root
+    /// page and the number of its rows, its last rowid, and the name and root page of each of
+    /// its indexes, followed by a record for each remaining index, view and sequence, with the
+    /// statement creating it. Tables come after the tables they reference, and views after the
+    /// views they query. Only the records that changed are written.
+    fn write_catalog(
+        &self,
+        tree: &mut BTree,
+        tables: &HashMap<String, Table>,
+        views: &HashMap<String, Statement>,
+        copy: bool,
+        created: &mut Vec<(Arc<SavedTree>, PageId)>,
+    ) -> Result<(), StorageError> {
+        let mut records = Vec::new();
+        let text = |text: &str| DBValue::Text(String::from(text));
+        let mut indexes = Vec::new();
+        let order = tables.iter().map(|(name, table)| {
+            let parents = table.foreign_keys().iter().map(|fk| &fk.table);
+            (name, parents.filter(|parent| *parent != name).collect())
+        });
+        for name in dependency_order(order.collect()) {
+            let table = &tables[name];
+            let (definition, table_indexes) = Self::table_definition(tables, name, table);
+            indexes.extend(table_indexes);
+            let next_id = match table.autoincrement() {
+                Some(_) => DBValue::Integer(table.next_id()),
+                None => DBValue::Null,
+            };
+            let (root, index_roots) = table.write_trees(tree, copy, created)?;
+            let mut record = vec![
+                text(TABLE),
+                text(name),
+                text(&definition.to_string()),
+                next_id,
+                DBValue::Integer(i64::from(root)),
+                DBValue::Integer(table.len() as i64),
+                DBValue::Integer(table.last_rowid()),
+            ];
+            for (index, root) in index_roots {
+                record.extend(vec![text(&index), DBValue::Integer(i64::from(root))]);
+            }
+            records.push(record);
+        }
+        for (name, statement) in indexes {
+            records.push(vec![text(INDEX), text(&name), text(&statement)]);
+        }
+        let order = views.iter().map(|(name, query)| {
+            let sources = match query {
+                Statement::Select { table, joins, .. } => {
+                    let joined = joins.iter().map(|join| &join.table);
+                    std::iter::once(table).chain(joined).collect()
+                }
+                _ => Vec::new(),
+            };
+            (name, sources)
+        });
+        for name in dependency_order(order.collect()) {
+            let statement = Statement::CreateView {
+                view: name.clone(),
+                query: Box::new(views[name].clone()),
+            };
+            records.push(vec![text(VIEW), text(name), text(&statement.to_string())]);
+        }
+        let mut sequences: Vec<_> = self.sequences.iter().collect();
+        sequences.sort_by_key(|(name, _)| *name);
+        for (name, sequence) in sequences {
+            let statement = Statement::CreateSequence {
+                sequence: name.clone(),
+                start: sequence.peek_value(),
+                increment: sequence.increment(),
+            };
+            records.push(vec![
+                text(SEQUENCE),
+                text(name),
+                text(&statement.to_string()),
+            ]);
+        }
+        let saved = tree
+            .cursor()?
+            .map(|record| record.map(|(_, record)| record))
+            .collect::<io::Result<Vec<_>>>()?;
+        for (i, record) in records.iter().enumerate() {
+            if saved.get(i) != Some(record) {
+                tree.insert(&(i as u32).to_be_bytes(), record)?;
+            }
+        }
+        for i in records.len()..saved.len() {
+            tree.delete(&(i as u32).to_be_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// The statements recreating the table or view of the given name, or else all tables and
+    /// views, in order of name: each table as its 'create table'-statement followed by the
+    /// 'create index'-statements of its other indexes, then each view as its
+    /// 'create view'-statement.
+    pub fn schema(&self, name: Option<&str>) -> Result<Vec<String>, StorageError> {
+        let mut statements = Vec::new();
+        let mut tables: Vec<_> = self.tables.iter().collect();
+        tables.retain(|(table, _)| name.is_none_or(|name| *table == name));
+        tables.sort_by_key(|(name, _)| *name);
+        for (name, table) in tables {
+            let (definition, indexes) = Self::table_definition(&self.tables, name, table);
+            statements.push(definition.to_string());
+            statements.extend(indexes.into_iter().map(|(_, statement)| statement));
+        }
+        let mut views: Vec<_> = self.views.iter().collect();
+        views.retain(|(view, _)| name.is_none_or(|name| *view == name));
+        views.sort_by_key(|(name, _)| *name);
+        for (name, query) in views {
+            let statement = Statement::CreateView {
+                view: name.clone(),
+                query: Box::new(query.clone()),
+            };
+            statements.push(statement.to_string());
+        }
+        if name.is_some() && statements.is_empty() {
+            return Err(StorageError::TableNotFound);
+        }
+        Ok(statements)
+    }
+
+    /// The 'create table'-statement recreating the table along with its constraints, and the
+    /// 'create index'-statements of its other indexes, by name. Foreign keys reference the
+    /// given tables.
+    fn table_definition(
+        tables: &HashMap<String, Table>,
+        name: &str,
+        table: &Table,
+    ) -> (Statement, Vec<(String, String)>) {
+        let schema = table.schema();
+        let mut constraints = Vec::new();
+        for (i, column) in schema.columns().enumerate() {
+            let name = || column.name.clone();
+            if table.primary_key() == Some(i) {
+                constraints.push(Constraint::PrimaryKey(name()));
+            } else if !column.nullable {
+                constraints.push(Constraint::NotNull(name()));
+            }
+            if table.autoincrement() == Some(i) {
+                constraints.push(Constraint::AutoIncrement(name()));
+            }
+            if let Some(value) = &column.default {
+                constraints.push(Constraint::Default(name(), value.clone()));
+            }
+            if column.collation != Collation::Binary {
+                constraints.push(Constraint::Collate(name(), column.collation));
+            }
+            if let Some(labels) = &column.allowed_values {
+                constraints.push(Constraint::Enum(name(), labels.clone()));
+            }
+        }
+        for fk in table.foreign_keys() {
+            let parent = tables.get(&fk.table).unwrap_or(table);
+            constraints.push(Constraint::ForeignKey {
+                column: String::from(schema.get_column_name(fk.column)),
+                table: fk.table.clone(),
+                references: String::from(parent.schema().get_column_name(fk.references)),
+                on_delete: fk.on_delete,
+            });
+        }
+        let mut indexes = Vec::new();
+        let mut names: Vec<_> = table.indexes().keys().collect();
+        names.sort();
+        for index_name in names {
+            let index = &table.indexes()[index_name];
+            let column = schema.get_column_name(index.column());
+            if *index_name == format!("{}_pkey", name) && table.primary_key().is_some() {
+                continue;
+            }
+            if index.is_unique() && *index_name == format!("{}_{}_key", name, column) {
+                constraints.push(Constraint::Unique(String::from(column)));
+                continue;
+            }
+            let statement = Statement::CreateIndex {
+                index: index_name.clone(),
+                table: String::from(name),
+                column: String::from(column),
+                unique: index.is_unique(),
+            };
+            indexes.push((index_name.clone(), statement.to_string()));
+        }
+        let definition = Statement::CreateTable {
+            table: String::from(name),
+            columns: schema
+                .columns()
+                .map(|column| (column.name.clone(), column.db_type))
+                .collect(),
+            constraints,
+        };
+        (definition, indexes)
+    }
+
+    /// Creates the tables, indexes, views and sequences recorded in the catalog of the file,
+    /// with the tables reading their rows from their trees in the file; see
+    /// [`StorageManager::write_catalog`].
+    fn load_catalog(&mut self, tree: BTree) -> Result<(), StorageError> {
+        let records = tree
+            .cursor()?
+            .map(|record| record.map(|(_, record)| record))
+            .collect::<io::Result<Vec<_>>>()?;
+        let trees = Arc::new(RwLock::new(tree));
+        self.trees = Some(trees.clone());
+        for record in &records {
+            self.load_record(record)?;
+        }
+        // Only once the indexes exist, so that they aren't built over the rows
+        self.attach_tables(&trees, &records)
+    }
+
+    /// Adds a record of the catalog of a database file to the database, creating what it
+    /// records, empty.
+    fn load_record(&mut self, record: &[DBValue]) -> Result<(), StorageError> {
+        let (kind, name, sql) = match record {
+            [DBValue::Text(kind), DBValue::Text(name), DBValue::Text(sql), ..] => (kind, name, sql),
+            _ => return Err(corrupt_file().into()),
+        };
+        if ![TABLE, INDEX, VIEW, SEQUENCE].contains(&kind.as_str()) {
+            return Err(corrupt_file().into());
+        }
+        match Parser::new(&format!("{};", sql)).parse_command() {
+            Ok(Command::Statement(statement)) => self.execute(statement)?,
+            _ => return Err(corrupt_file().into()),
+        };
+        if let (Some(DBValue::Integer(id)), Some(table)) =
+            (record.get(3), self.tables.get_mut(name))
+        {
+            table.set_next_id(*id);
+        }
+        Ok(())
+    }
+
+    /// Makes the tables recorded in the catalog read their rows, and the entries of their
+    /// indexes, from the trees of the file the catalog gives.
+    fn attach_tables(&mut self, trees: &DatabaseFile, records: &[Row]) -> Result<(), StorageError> {
+        for record in records {
+            let name = match &record[..] {
+                [DBValue::Text(kind), DBValue::Text(name), ..] if kind == TABLE => name,
+                _ => continue,
+            };
+            let integer = |i: usize| match record.get(i) {
+                Some(DBValue::Integer(value)) => Ok(*value),
+                _ => Err(corrupt_file()),
+            };
+            let (root, len, rowid) = (integer(4)?, integer(5)?, integer(6)?);
+            let mut indexes = HashMap::new();
+            for index in record[7..].chunks(2) {
+                match index {
+                    [DBValue::Text(index), DBValue::Integer(root)] => {
+                        indexes.insert(index.clone(), *root as PageId)
+                    }
+                    _ => return Err(corrupt_file().into()),
+                };
+            }
+            let table = self
+                .tables
+                .get_mut(name)
+                .ok_or(StorageError::TableNotFound)?;
+            table.attach(trees, root as PageId, len as usize, &indexes);
+            table.set_last_rowid(rowid);
+        }
+        Ok(())
+    }
+
+    /// Sets the number of threads filtering the rows of tables and grouping rows for
+    /// aggregation. The work is split between the threads such that the results are the same as
+    /// with the default of a single thread.
+    pub fn set_parallelism(&mut self, threads: usize) {
+        self.parallelism = threads.max(1);
+    }
+
+    /// Sets the number of statements whose plans are cached; zero disables the cache.
+    pub fn set_plan_cache_size(&mut self, plans: usize) {
+        self.plans.get_mut().unwrap().set_capacity(plans);
+    }
+
+    /// Registers a function callable from expressions as `name(arg, ...)`, replacing any of the
+    /// name registered before; see [`crate::functions`].
+    pub fn create_function(&mut self, name: &str, function: ScalarFunction) {
+        self.functions.insert_scalar(name, function);
+    }
+
+    /// Registers an aggregate function callable from expressions as `name(arg, ...)`, replacing
+    /// any function of the name registered before; see [`crate::functions::Aggregate`].
+    pub fn create_aggregate(&mut self, name: &str, function: AggregateFunction) {
+        self.functions.insert_aggregate(name, function);
+    }
+
+    /// Registers a module making virtual tables, replacing any of the name registered before;
+    /// see [`crate::virtual_table`].
+    pub fn create_module(&mut self, name: &str, module: Arc<Module>) {
+        self.virtual_tables.insert_module(name, module);
+    }
+
+    /// Unregisters the function of the name, returning whether there was one.
+    pub fn remove_function(&mut self, name: &str) -> bool {
+        self.functions.remove(name)
+    }
+
+    /// The plan cached for the SQL text of a statement, if it is still valid; see
+    /// [`StorageManager::cache_plan`].
+    pub fn cached_plan(&self, sql: &str) -> Option<Plan> {
+        let version = self.schema_version;
+        self.plans.lock().unwrap().get(sql, version)
+    }
+
+    /// Caches the plan of a statement under its SQL text, until the schema changes. Plans keep
+    /// the order in which to join tables, which is not revised as rows are inserted or deleted,
+    /// but only once the tables are analyzed again.
+    pub fn cache_plan(&self, sql: &str, plan: Plan) {
+        let version = self.schema_version;
+        self.plans.lock().unwrap().insert(sql, version, plan);
+    }
+
+    /// Executes a statement from its plan.
+    pub fn execute_plan(&mut self, plan: Plan) -> Result<ResultSet, StorageError> {
+        match plan {
+            Plan::Query(plan) => {
+                self.scan_virtual_tables(&plan.query)?;
+                let locks = Self::query_locks(&plan);
+                self.with_locks(&locks, |storage| result_set(storage.run(plan)?))
+            }
+            Plan::Statement(statement) => self.execute(statement),
+        }
+    }
+
+    /// Runs a query, passing its rows to `f` one at a time as they are produced, until `f`
+    /// breaks off. The locks of the query are held meanwhile, so `f` should be quick about each
+    /// row, as others may be waiting for the locks.
+    pub fn query_each<B>(
+        &mut self,
+        plan: QueryPlan,
+        mut f: impl FnMut(&ResultRow<'_>) -> ControlFlow<B>,
+    ) -> Result<Option<B>, StorageError> {
+        self.scan_virtual_tables(&plan.query)?;
+        let locks = Self::query_locks(&plan);
+        self.with_locks(&locks, |storage| {
+            let rows = storage.run(plan)?;
+            let columns = rows.columns().to_vec();
+            for row in rows {
+                if let ControlFlow::Break(value) = f(&ResultRow::new(&columns, &row?)) {
+                    return Ok(Some(value));
+                }
+            }
+            Ok(None)
+        })
+    }
+
+    /// The shared locks on the tables a query reads.
+    fn query_locks(plan: &QueryPlan) -> Vec<(String, LockMode)> {
+        let query = &plan.query;
+        let tables =
+            std::iter::once(&query.table).chain(query.joins.iter().map(|join| &join.table));
+        tables
+            .map(|table| (table.clone(), LockMode::Shared))
+            .collect()
+    }
+
+    /// Runs a statement holding the given locks, under the transaction in progress, which keeps
+    /// the locks until it ends, or else as a transaction of its own, releasing them right after.
+    /// The transaction in progress keeps a copy of the tables and views the statement locks
+    /// exclusively, as they were before it first changes them.
+    fn with_locks<T>(
+        &mut self,
+        locks: &[(String, LockMode)],
+        run: impl FnOnce(&mut Self) -> Result<T, StorageError>,
+    ) -> Result<T, StorageError> {
+        let owner = self.lock_owner();
+        let result = self.take_locks(owner, locks).and_then(|_| run(self));
+        self.release_locks(owner);
+        result
+    }
+
+    /// Takes the locks of a query for a cursor to run it with [`StorageManager::run`], as
+    /// [`StorageManager::execute_plan`] does for the whole of the query. Returns the owner of
+    /// the locks, for [`StorageManager::end_query`] to release them once the cursor is done.
+    pub fn begin_query(&mut self, plan: &QueryPlan) -> Result<TransactionId, StorageError> {
+        self.scan_virtual_tables(&plan.query)?;
+        let owner = self.lock_owner();
+        if let Err(err) = self.take_locks(owner, &Self::query_locks(plan)) {
+            self.release_locks(owner);
+            return Err(err);
+        }
+        Ok(owner)
+    }
+
+    /// Releases the locks of a query begun with [`StorageManager::begin_query`], but those the
+    /// transaction in progress keeps.
+    pub fn end_query(&mut self, owner: TransactionId) {
+        self.release_locks(owner);
+    }
+
+    /// The owner of the locks of the next statement: the transaction in progress, or else a
+    /// transaction of its own.
+    fn lock_owner(&mut self) -> TransactionId {
+        let owner = match self.session.transaction {
+            Some(id) => id,
+            None => self.transactions.next_id(),
+        };
+        self.owner = owner;
+        self.session.progress.reset();
+        owner
+    }
+
+    fn take_locks(
+        &mut self,
+        owner: TransactionId,
+        locks: &[(String, LockMode)],
+    ) -> Result<(), StorageError> {
+        self.locks.lock_all(owner, locks)?;
+        match self.session.transaction {
+            Some(id) => self.keep_tables(id, locks),
+            None => Ok(()),
+        }
+    }
+
+    /// Releases the locks of a statement once it is done, but those the transaction in
+    /// progress keeps until it ends: all of them, or only the exclusive ones under
+    /// [`IsolationLevel::ReadCommitted`].
+    fn release_locks(&mut self, owner: TransactionId) {
+        if self.session.transaction != Some(owner) {
+            self.locks.release(owner);
+        } else if self.transactions.isolation(owner) == Some(IsolationLevel::ReadCommitted) {
+            self.locks.release_shared(owner);
+        }
+    }
+
+    /// Keeps a copy of the tables and views locked exclusively for the transaction to undo,
+    /// unless it has one from after its latest savepoint already.
+    fn keep_tables(
+        &mut self,
+        id: TransactionId,
+        locks: &[(String, LockMode)],
+    ) -> Result<(), StorageError> {
+        for (name, mode) in locks {
+            let kept = self
+                .transactions
+                .current(id)
+                .map(|undo| undo.tables.contains_key(name));
+            if *mode != LockMode::Exclusive || kept != Some(false) {
+                continue;
+            }
+            let table = self.tables.get(name).map(Table::try_clone).transpose()?;
+            let view = self.views.get(name).cloned();
+            if let Some(undo) = self.transactions.current(id) {
+                undo.tables.insert(name.clone(), (table, view));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the memory a sort may use for rows, in bytes, before spilling rows to disk.
+    pub fn set_sort_memory(&mut self, bytes: usize) {
+        self.sort_memory = bytes;
+    }
+
+    pub fn create_table(
+        &mut self,
+        name: String,
+        schema: Schema,
+        constraints: Vec<Constraint>,
+    ) -> Result<(), StorageError> {
+        if self.name_in_use(&name) {
+            return Err(StorageError::TableNameAlreadyInUse);
+        }
+        let mut table = match (&self.trees, &self.directory) {
+            (Some(trees), _) => Table::in_database(schema, trees),
+            (None, Some(directory)) => {
+                Table::in_file(schema, directory, self.read_mode, self.page_counts.clone())?
+            }
+            (None, None) => Table::new(schema),
+        };
+        let mut foreign_keys = Vec::new();
+        for constraint in constraints {
+            match constraint {
+                Constraint::Unique(column) => {
+                    let index = format!("{}_{}_key", name, column);
+                    if self.index_exists(&index) || table.indexes().contains_key(&index) {
+                        return Err(StorageError::IndexNameAlreadyInUse);
+                    }
+                    let column = table
+                        .schema()
+                        .get_column_index(&column)
+                        .ok_or(StorageError::SchemaMismatch)?;
+                    table.create_index(index, column, true)?;
+                }
+                Constraint::PrimaryKey(column) => {
+                    let index = format!("{}_pkey", name);
+                    if table.primary_key().is_some() {
+                        return Err(StorageError::MultiplePrimaryKeys);
+                    }
+                    if self.index_exists(&index) || table.indexes().contains_key(&index) {
+                        return Err(StorageError::IndexNameAlreadyInUse);
+                    }
+                    let column = table
+                        .schema()
+                        .get_column_index(&column)
+                        .ok_or(StorageError::SchemaMismatch)?;
+                    table.create_primary_key(index, column)?;
+                    table.add_not_null(column);
+                }
+                Constraint::AutoIncrement(column) => {
+                    if table.schema().get_field_type(&column) != Some(DBType::Integer) {
+                        return Err(StorageError::TypeError);
+                    }
+                    let column = table
+                        .schema()
+                        .get_column_index(&column)
+                        .ok_or(StorageError::SchemaMismatch)?;
+                    table.set_autoincrement(column);
+                }
+                Constraint::Default(column, value) => {
+                    let column = table
+                        .schema()
+                        .get_column_index(&column)
+                        .ok_or(StorageError::SchemaMismatch)?;
+                    table.set_default(column, value);
+                }
+                Constraint::Collate(column, collation) => {
+                    let column = table
+                        .schema()
+                        .get_column_index(&column)
+                        .ok_or(StorageError::SchemaMismatch)?;
+                    table.set_collation(column, collation)?;
+                }
+                Constraint::Enum(column, labels) => {
+                    if table.schema().get_field_type(&column) != Some(DBType::Text) {
+                        return Err(StorageError::TypeError);
+                    }
+                    let column = table
+                        .schema()
+                        .get_column_index(&column)
+                        .ok_or(StorageError::SchemaMismatch)?;
+                    table.set_allowed_values(column, labels)?;
+                }
+                Constraint::NotNull(column) => {
+                    let column = table
+                        .schema()
+                        .get_column_index(&column)
+                        .ok_or(StorageError::SchemaMismatch)?;
+                    table.add_not_null(column);
+                }
+                Constraint::ForeignKey {
+                    column,
+                    table: parent,
+                    references,
+                    on_delete,
+                } => foreign_keys.push((column, parent, references, on_delete)),
+            }
+        }
+        // Foreign keys are resolved last, as a table may reference its own primary key
+        for (column, parent, references, on_delete) in foreign_keys {
+            let parent_table = if parent == name {
+                &table
+            } else {
+                self.tables
+                    .get(&parent)
+                    .ok_or(StorageError::TableNotFound)?
+            };
+            let column_type = table
+                .schema()
+                .get_field_type(&column)
+                .ok_or(StorageError::SchemaMismatch)?;
+            if parent_table.schema().get_field_type(&references) != Some(column_type) {
+                return Err(StorageError::TypeError);
+            }
+            let references = parent_table
+                .schema()
+                .get_column_index(&references)
+                .ok_or(StorageError::SchemaMismatch)?;
+            if parent_table.unique_index(references).is_none() {
+                return Err(StorageError::ReferencedColumnNotUnique);
+            }
+            let column = table
+                .schema()
+                .get_column_index(&column)
+                .ok_or(StorageError::SchemaMismatch)?;
+            if on_delete == ReferentialAction::SetNull && table.primary_key() == Some(column) {
+                return Err(StorageError::SchemaMismatch);
+            }
+            table.add_foreign_key(ForeignKey {
+                column,
+                table: parent,
+                references,
+                on_delete,
+            });
+        }
+        self.tables.insert(name, table);
+        self.schema_changed();
+        Ok(())
+    }
+
+    pub fn drop_table(&mut self, name: String) -> Result<(), StorageError> {
+        if self.virtual_tables.remove(&name) {
+            self.schema_changed();
+            return Ok(());
+        }
+        if !self.tables.contains_key(&name) {
+            return Err(StorageError::TableNotFound);
+        }
+        if self.has_dependent_views(&name) {
+            return Err(StorageError::DependentViews);
+        }
+        if self.tables.iter().any(|(child, table)| {
+            child != &name && table.foreign_keys().iter().any(|fk| fk.table == name)
+        }) {
+            return Err(StorageError::DependentForeignKeys);
+        }
+        self.tables.remove(&name);
+        self.schema_changed();
+        Ok(())
+    }
+
+    /// Makes a virtual table from a module registered with [`StorageManager::create_module`],
+    /// passing it the values of the arguments.
+    pub fn create_virtual_table(
+        &mut self,
+        name: String,
+        module: String,
+        args: Vec<Expression>,
+    ) -> Result<(), StorageError> {
+        if self.name_in_use(&name) {
+            return Err(StorageError::TableNameAlreadyInUse);
+        }
+        let scope = self.empty_scope();
+        let args = args
+            .iter()
+            .map(|arg| eval_expression(arg, &scope, &[]))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.virtual_tables.create(name, &module, &args)?;
+        self.schema_changed();
+        Ok(())
+    }
+
+    /// Whether a table, view or virtual table is of the name.
+    fn name_in_use(&self, name: &str) -> bool {
+        self.tables.contains_key(name)
+            || self.views.contains_key(name)
+            || self.virtual_tables.contains(name)
+    }
+
+    /// Scans the rows of the virtual tables a query reads, for it to read them as of now. The
+    /// condition of a query of a single virtual table is passed on to it; see
+    /// [`crate::virtual_table::VirtualTable::scan`].
+    fn scan_virtual_tables(&mut self, query: &InlinedQuery) -> Result<(), StorageError> {
+        let tables =
+            std::iter::once(&query.table).chain(query.joins.iter().map(|join| &join.table));
+        for table in tables {
+            if self.virtual_tables.contains(table) {
+                let filter = match query.joins.is_empty() {
+                    true => query.condition.as_ref(),
+                    false => None,
+                };
+                self.virtual_tables.scan(table, filter)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Stores a view in the catalog. The defining query is validated against the current schema,
+    /// but otherwise kept as is; it gets inlined into every query referencing the view.
+    pub fn create_view(&mut self, name: String, query: Statement) -> Result<(), StorageError> {
+        if self.name_in_use(&name) {
+            return Err(StorageError::TableNameAlreadyInUse);
+        }
+        let mut query = query;
+        // The columns of `select *` are those of the table as the view is created
+        if let Statement::Select {
+            columns,
+            table,
+            joins,
+            ..
+        } = &mut query
+        {
+            if columns.is_empty() {
+                *columns = self.all_columns(table, joins)?;
+            }
+        }
+        let inlined = self.inline_views(query.clone())?;
+        let schema = self
+            .tables
+            .get(&inlined.table)
+            .ok_or(StorageError::TableNotFound)?
+            .schema();
+        view_columns(&inlined.columns)?;
+        if !inlined.group_by.is_empty()
+            || !inlined.order_by.is_empty()
+            || !inlined.joins.is_empty()
+            || inlined.limit.is_some()
+        {
+            return Err(StorageError::SchemaMismatch);
+        }
+        for selector in inlined.columns.iter().flat_map(Expression::selectors) {
+            resolve_selector(selector, &inlined.table, schema)?;
+        }
+        self.views.insert(name, query);
+        self.schema_changed();
+        Ok(())
+    }
+
+    pub fn drop_view(&mut self, name: String) -> Result<(), StorageError> {
+        if !self.views.contains_key(&name) {
+            return Err(StorageError::ViewNotFound);
+        }
+        if self.has_dependent_views(&name) {
+            return Err(StorageError::DependentViews);
+        }
+        self.views.remove(&name);
+        self.schema_changed();
+        Ok(())
+    }
+
+    /// Invalidates the cached plans and rebuilds the information schema after a change to the
+    /// schema.
+    fn schema_changed(&mut self) {
+        self.schema_version += 1;
+        self.refresh_information_schema();
+    }
+
+    /// Rebuilds `information_schema.tables` and `information_schema.columns`, which list the
+    /// tables and views of the database and their columns like in the SQL standard, for tools
+    /// that introspect schemas. The columns of a view take their types and constraints from the
+    /// columns of the underlying table.
+    fn refresh_information_schema(&mut self) {
+        let text = |text: &str| DBValue::Text(String::from(text));
+        let tables_schema = Schema::from(vec![
+            (String::from("table_schema"), DBType::Text),
+            (String::from("table_name"), DBType::Text),
+            (String::from("table_type"), DBType::Text),
+        ]);
+        let columns_schema = Schema::from(vec![
+            (String::from("table_schema"), DBType::Text),
+            (String::from("table_name"), DBType::Text),
+            (String::from("column_name"), DBType::Text),
+            (String::from("ordinal_position"), DBType::Integer),
+            (String::from("column_default"), DBType::Text),
+            (String::from("data_type"), DBType::Text),
+            (String::from("is_nullable"), DBType::Text),
+            (String::from("collation_name"), DBType::Text),
+        ]);
+        let (mut tables, mut columns) = (Vec::new(), Vec::new());
+        let mut entries: Vec<(&str, &str, Vec<&Column>)> = Vec::new();
+        for (name, table) in &self.tables {
+            entries.push((name, "BASE TABLE", table.schema().columns().collect()));
+        }
+        for (name, table) in self.virtual_tables.iter() {
+            entries.push((name, "VIRTUAL TABLE", table.schema().columns().collect()));
+        }
+        for (name, query) in &self.views {
+            let mut view_schema = Vec::new();
+            if let Ok(inlined) = self.inline_views(query.clone()) {
+                if let (Some(table), Ok(names)) = (
+                    self.tables.get(&inlined.table),
+                    view_columns(&inlined.columns),
+                ) {
+                    let schema = table.schema();
+                    view_schema = names
+                        .into_iter()
+                        .filter_map(|column| schema.get_column_index(column))
+                        .map(|column| schema.column(column))
+                        .collect();
+                }
+            }
+            entries.push((name, "VIEW", view_schema));
+        }
+        entries.sort_by_key(|(name, _, _)| *name);
+        for (name, table_type, schema) in entries {
+            tables.push(vec![text("main"), text(name), text(table_type)]);
+            for (position, column) in schema.into_iter().enumerate() {
+                columns.push(vec![
+                    text("main"),
+                    text(name),
+                    text(&column.name),
+                    DBValue::Integer(position as i64 + 1),
+                    // The text of the default expression, as the SQL standard has it
+                    column
+                        .default
+                        .as_ref()
+                        .map_or(DBValue::Null, |default| text(&default.to_string())),
+                    text(&column.db_type.to_string()),
+                    text(if column.nullable { "YES" } else { "NO" }),
+                    text(&column.collation.to_string()),
+                ]);
+            }
+        }
+        self.information_schema = HashMap::new();
+        self.information_schema.insert(
+            String::from("information_schema.tables"),
+            Table::from_rows(tables_schema, tables),
+        );
+        self.information_schema.insert(
+            String::from("information_schema.columns"),
+            Table::from_rows(columns_schema, columns),
+        );
+    }
+
+    /// Looks up a table to query, among the tables of the database, its virtual tables and those
+    /// of the information schema.
+    fn queried_table(&self, name: &str) -> Result<(&String, &Table), StorageError> {
+        self.tables
+            .get_key_value(name)
+            .or_else(|| self.virtual_tables.get(name))
+            .or_else(|| self.information_schema.get_key_value(name))
+            .ok_or(StorageError::TableNotFound)
+    }
+
+    fn has_dependent_views(&self, name: &str) -> bool {
+        self.views.values().any(|query| match query {
+            Statement::Select { table, .. } => table == name,
+            _ => false,
+        })
+    }
+
+    /// The columns `select *` selects from the table or view and the joined ones: all of their
+    /// columns in order, qualified by the names of their tables if any are joined.
+    fn all_columns(&self, table: &str, joins: &[Join]) -> Result<Vec<Expression>, StorageError> {
+        let mut columns = Vec::new();
+        let tables = std::iter::once(table).chain(joins.iter().map(|join| join.table.as_str()));
+        for name in tables {
+            let names: Vec<String> = match self.views.get(name) {
+                Some(view) => {
+                    let view = self.inline_views(view.clone())?;
+                    let names = view_columns(&view.columns)?;
+                    names.into_iter().map(String::from).collect()
+                }
+                None => {
+                    let (_, data) = self.queried_table(name)?;
+                    data.schema().columns().map(|c| c.name.clone()).collect()
+                }
+            };
+            columns.extend(names.into_iter().map(|field| {
+                Expression::Field(Selector {
+                    table: Some(String::from(name)).filter(|_| !joins.is_empty()),
+                    field,
+                })
+            }));
+        }
+        Ok(columns)
+    }
+
+    /// Rewrites a 'select'-statement over (possibly nested) views into an equivalent query over
+    /// the underlying table, with the columns of `select *` spelled out.
+    fn inline_views(&self, query: Statement) -> Result<InlinedQuery, StorageError> {
+        if let Statement::Select {
+            columns,
+            table,
+            joins,
+            condition,
+            group_by,
+            order_by,
+            limit,
+        } = query
+        {
+            let columns = match columns.is_empty() {
+                true => self.all_columns(&table, &joins)?,
+                false => columns,
+            };
+            let mut query = InlinedQuery {
+                columns,
+                table,
+                joins,
+                condition,
+                group_by,
+                order_by,
+                limit,
+            };
+            if let Some(view) = self.views.get(&query.table) {
+                let view = self.inline_views(view.clone())?;
+                let view_columns = view_columns(&view.columns)?;
+                if query
+                    .selectors()
+                    .any(|selector| !view_columns.contains(&selector.field.as_str()))
+                {
+                    return Err(StorageError::SchemaMismatch);
+                }
+                query.table = view.table;
+                query.condition = match (view.condition, query.condition) {
+                    (Some(c1), Some(c2)) => Some(Condition::And(Box::new(c1), Box::new(c2))),
+                    (c1, c2) => c1.or(c2),
+                };
+            }
+            Ok(query)
+        } else {
+            Err(StorageError::SchemaMismatch)
+        }
+    }
+
+    /// Inserts a row into the table. If a column list is given, the values are matched to the
+    /// listed columns and the omitted columns are set to null, or in the case of an
+    /// autoincrementing column, to the next id in the table.
+    pub fn insert_into(
+        &mut self,
+        table: String,
+        columns: Option<Vec<String>>,
+        values: Vec<Expression>,
+    ) -> Result<(), StorageError> {
+        let data = self.tables.get(&table).ok_or(StorageError::TableNotFound)?;
+        let scope = self.scope(&table, data);
+        let mut row = Vec::new();
+        for value in &values {
+            row.push(eval_expression(value, &scope, &[])?);
+        }
+        let mut values = match columns {
+            Some(columns) => {
+                if columns.len() != values.len() {
+                    return Err(StorageError::SchemaMismatch);
+                }
+                let indices = data
+                    .schema()
+                    .get_column_indices(columns)
+                    .ok_or(StorageError::SchemaMismatch)?;
+                let mut values = vec![None; data.schema().len()];
+                for (index, value) in indices.into_iter().zip(row) {
+                    values[index] = Some(value);
+                }
+                let mut row = Vec::new();
+                for (column, value) in data.schema().columns().zip(values) {
+                    row.push(match (value, &column.default) {
+                        (Some(value), _) => value,
+                        (None, Some(default)) => eval_expression(default, &scope, &[])?,
+                        (None, None) => DBValue::Null,
+                    });
+                }
+                row
+            }
+            None => row,
+        };
+        if let Some(column) = data.autoincrement() {
+            if let Some(DBValue::Null) = values.get(column) {
+                values[column] = DBValue::Integer(data.next_id());
+            }
+        }
+        data.schema().coerce(&mut values);
+        let types = values.iter().map(|val| val.val_to_type()).collect();
+        data.schema()
+            .type_check(types)
+            .ok_or(StorageError::TypeError)?;
+        Self::check_not_null(data, &values)?;
+        Self::check_enums(data, &values)?;
+        data.check_unique(&[(None, &values)])?
+            .ok_or(StorageError::UniqueViolation)?;
+        self.check_references(&table, data, &[&values])?;
+        let kept = self.lock_rows(&table, std::iter::once(&values))?;
+        self.keep_rows(&table, kept);
+        let data = self
+            .tables
+            .get_mut(&table)
+            .ok_or(StorageError::TableNotFound)?;
+        let key = data.push_and_bump(values)?;
+        let rowids = self.rowids(&table, &[key])?;
+        self.session
+            .hooks
+            .rows_changed(&table, Operation::Insert, &rowids);
+        Ok(())
+    }
+
+    /// Loads the records of the file into the table, as a 'copy'-statement does; see
+    /// [`crate::copy`]. The fields of each record are matched to the given columns, or to all
+    /// columns in order, with the other columns set as [`StorageManager::insert_into`] sets
+    /// omitted columns. All rows are checked as a row being inserted is, and then added to the
+    /// table at once; see [`Table::load`]. Returns the number of rows loaded as a single row.
+    fn copy_from(
+        &mut self,
+        table: String,
+        columns: Option<Vec<String>>,
+        path: String,
+        options: CopyOptions,
+    ) -> Result<ResultSet, StorageError> {
+        let text = fs::read_to_string(&path)?;
+        let invalid = |line, message| StorageError::InvalidData(line, message);
+        let records = copy::records(&text, &options).map_err(|(line, m)| invalid(line, m))?;
+        let data = self.tables.get(&table).ok_or(StorageError::TableNotFound)?;
+        let schema = data.schema();
+        let indices = match columns {
+            Some(columns) => schema
+                .get_column_indices(columns)
+                .ok_or(StorageError::SchemaMismatch)?,
+            None => (0..schema.len()).collect(),
+        };
+        let scope = self.scope(&table, data);
+        let mut next_id = data.next_id();
+        let mut rows = Vec::with_capacity(records.len());
+        for (line, fields) in records {
+            if fields.len() != indices.len() {
+                let message = format!("Expected {} fields, found {}", indices.len(), fields.len());
+                return Err(invalid(line, message));
+            }
+            let mut values = vec![None; schema.len()];
+            for (&index, field) in indices.iter().zip(fields) {
+                let column = schema.column(index);
+                values[index] = Some(match field {
+                    Some(field) => copy::value(column.db_type, &field).ok_or_else(|| {
+                        let (db_type, name) = (column.db_type, &column.name);
+                        let message =
+                            format!("Invalid {} '{}' in column '{}'", db_type, field, name);
+                        invalid(line, message)
+                    })?,
+                    None => DBValue::Null,
+                });
+            }
+            let mut row = Vec::new();
+            for (column, value) in schema.columns().zip(values) {
+                row.push(match (value, &column.default) {
+                    (Some(value), _) => value,
+                    (None, Some(default)) => eval_expression(default, &scope, &[])?,
+                    (None, None) => DBValue::Null,
+                });
+            }
+            if let Some(column) = data.autoincrement() {
+                match row[column] {
+                    DBValue::Null => {
+                        row[column] = DBValue::Integer(next_id);
+                        next_id += 1;
+                    }
+                    DBValue::Integer(id) => next_id = next_id.max(id + 1),
+                    _ => (),
+                }
+            }
+            schema.coerce(&mut row);
+            let types = row.iter().map(|val| val.val_to_type()).collect();
+            schema.type_check(types).ok_or(StorageError::TypeError)?;
+            Self::check_not_null(data, &row)?;
+            Self::check_enums(data, &row)?;
+            rows.push(row);
+        }
+        let changes: Vec<_> = rows.iter().map(|row| (None, row)).collect();
+        data.check_unique(&changes)?
+            .ok_or(StorageError::UniqueViolation)?;
+        self.check_references(&table, data, &rows.iter().collect::<Vec<_>>())?;
+        let kept = self.lock_rows(&table, &rows)?;
+        self.keep_rows(&table, kept);
+        let data = self
+            .tables
+            .get_mut(&table)
+            .ok_or(StorageError::TableNotFound)?;
+        let count = rows.len();
+        let keys = data.load(rows)?;
+        let rowids = self.rowids(&table, &keys)?;
+        self.session
+            .hooks
+            .rows_changed(&table, Operation::Insert, &rowids);
+        Ok(ResultSet {
+            columns: vec![ResultColumn {
+                name: String::from("rows"),
+                db_type: Some(DBType::Integer),
+            }],
+            rows: vec![vec![DBValue::Integer(count as i64)]],
+        })
+    }
+
+    /// Locks the rows of the table exclusively by their primary keys for the running statement.
+    /// Tables without a primary key are locked as a whole by the statement instead. Returns the
+    /// rows with the keys as they are now, for the transaction in progress to keep; see
+    /// [`StorageManager::keep_rows`].
+    fn lock_rows<'a>(
+        &self,
+        name: &str,
+        rows: impl IntoIterator<Item = &'a Row>,
+    ) -> Result<Vec<(DBValue, Option<Row>)>, StorageError> {
+        let data = self.tables.get(name).ok_or(StorageError::TableNotFound)?;
+        let column = match data.primary_key() {
+            Some(column) => column,
+            None => return Ok(Vec::new()),
+        };
+        // Rows of tables kept whole since the latest savepoint need not be kept
+        let undo = self
+            .session
+            .transaction
+            .and_then(|id| self.transactions.undo(id).last());
+        let keep = undo.filter(|undo| !undo.tables.contains_key(name));
+        let mut kept = Vec::new();
+        for row in rows {
+            let key = encode_key(slice::from_ref(&row[column]));
+            self.locks
+                .lock_rows(self.owner, name, key.clone()..=key, LockMode::Exclusive)?;
+            let value = &row[column];
+            let saved = keep.and_then(|undo| undo.rows.get(name));
+            if keep.is_none() || saved.is_some_and(|rows| rows.contains_key(value)) {
+                continue;
+            }
+            let old = match data.key_of(value) {
+                Some(key) => data.get(&key)?.map(Cow::into_owned),
+                None => None,
+            };
+            kept.push((value.clone(), old));
+        }
+        Ok(kept)
+    }
+
+    /// Keeps the rows of the table as they were before the transaction in progress changes them,
+    /// unless it has them from after its latest savepoint already.
+    fn keep_rows(&mut self, name: &str, rows: Vec<(DBValue, Option<Row>)>) {
+        let id = match self.session.transaction {
+            Some(id) => id,
+            None => return,
+        };
+        if let Some(undo) = self.transactions.current(id) {
+            let saved = undo.rows.entry(String::from(name)).or_default();
+            for (key, row) in rows {
+                saved.entry(key).or_insert(row);
+            }
+        }
+    }
+
+    fn check_not_null(table: &Table, row: &Row) -> Result<(), StorageError> {
+        for (column, value) in table.schema().columns().zip(row) {
+            if !column.nullable && *value == DBValue::Null {
+                return Err(StorageError::NotNullViolation(column.name.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the values of enum columns are among the allowed values.
+    fn check_enums(table: &Table, row: &Row) -> Result<(), StorageError> {
+        for (column, value) in table.schema().columns().zip(row) {
+            match (&column.allowed_values, value) {
+                (Some(labels), DBValue::Text(text)) if !labels.contains(text) => {
+                    return Err(StorageError::CheckViolation(column.name.clone()));
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the foreign keys of the given rows of the table `name` reference existing
+    /// rows.
+    fn check_references(
+        &self,
+        name: &str,
+        table: &Table,
+        rows: &[&Row],
+    ) -> Result<(), StorageError> {
+        for fk in table.foreign_keys() {
+            let parent = if fk.table == name {
+                table
+            } else {
+                self.tables
+                    .get(&fk.table)
+                    .ok_or(StorageError::TableNotFound)?
+            };
+            let index = parent
+                .unique_index(fk.references)
+                .ok_or(StorageError::ReferencedColumnNotUnique)?;
+            for row in rows {
+                let value = &row[fk.column];
+                let self_reference =
+                    fk.table == name && rows.iter().any(|row| &row[fk.references] == value);
+                if *value != DBValue::Null && index.get(value)?.is_empty() && !self_reference {
+                    return Err(StorageError::ForeignKeyViolation);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that updating the given rows of the table `name` doesn't change any values
+    /// referenced by foreign keys.
+    fn check_referenced(
+        &self,
+        name: &str,
+        table: &Table,
+        rows: &[(Key, Row)],
+    ) -> Result<(), StorageError> {
+        for child in self.tables.values() {
+            for fk in child.foreign_keys().iter().filter(|fk| fk.table == name) {
+                let mut changed = HashSet::new();
+                for (key, row) in rows {
+                    let old = table.row(key)?[fk.references].clone();
+                    if old != row[fk.references] && old != DBValue::Null {
+                        changed.insert(old);
+                    }
+                }
+                for row in child.rows() {
+                    if changed.contains(&row?[fk.column]) {
+                        return Err(StorageError::ForeignKeyViolation);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn update(
+        &mut self,
+        table: String,
+        assignments: Vec<(String, Expression)>,
+        condition: Option<Condition>,
+    ) -> Result<(), StorageError> {
+        let data = self.tables.get(&table).ok_or(StorageError::TableNotFound)?;
+        let mut columns = Vec::new();
+        for (column, value) in assignments {
+            let index = data
+                .schema()
+                .get_column_index(&column)
+                .ok_or(StorageError::SchemaMismatch)?;
+            columns.push((index, value));
+        }
+        let condition = simplify_where(condition, &self.empty_scope());
+        let scope = self.scope(&table, data);
+        let mut rows = Vec::new();
+        for key in self.matching_rows(&table, data, condition.as_ref())? {
+            let old_row = data.row(&key)?;
+            let mut row = old_row.to_vec();
+            for (index, value) in &columns {
+                row[*index] = eval_expression(value, &scope, &old_row)?;
+            }
+            data.schema().coerce(&mut row);
+            let types = row.iter().map(|val| val.val_to_type()).collect();
+            data.schema()
+                .type_check(types)
+                .ok_or(StorageError::TypeError)?;
+            rows.push((key, row));
+        }
+        for (_, row) in &rows {
+            Self::check_not_null(data, row)?;
+            Self::check_enums(data, row)?;
+        }
+        let changes: Vec<_> = rows
+            .iter()
+            .map(|(key, row)| (Some(key.as_slice()), row))
+            .collect();
+        data.check_unique(&changes)?
+            .ok_or(StorageError::UniqueViolation)?;
+        let new_rows: Vec<_> = rows.iter().map(|(_, row)| row).collect();
+        self.check_references(&table, data, &new_rows)?;
+        self.check_referenced(&table, data, &rows)?;
+        let mut kept = Vec::new();
+        for (key, row) in &rows {
+            kept.extend(self.lock_rows(&table, [&*data.row(key)?, row])?);
+        }
+        self.keep_rows(&table, kept);
+        let data = self
+            .tables
+            .get_mut(&table)
+            .ok_or(StorageError::TableNotFound)?;
+        let keys = data.update(rows)?;
+        let rowids = self.rowids(&table, &keys)?;
+        self.session
+            .hooks
+            .rows_changed(&table, Operation::Update, &rowids);
+        Ok(())
+    }
+
+    pub fn delete_from(
+        &mut self,
+        table: String,
+        condition: Option<Condition>,
+    ) -> Result<(), StorageError> {
+        let data = self.tables.get(&table).ok_or(StorageError::TableNotFound)?;
+        let condition = simplify_where(condition, &self.empty_scope());
+        let keys = self.matching_rows(&table, data, condition.as_ref())?;
+        let (deletes, set_nulls) = self.plan_delete(table, keys)?;
+        let mut updates = Vec::new();
+        for (name, nulls) in set_nulls {
+            let data = self.tables.get(&name).ok_or(StorageError::TableNotFound)?;
+            let mut rows = Vec::new();
+            for (key, columns) in nulls {
+                if deletes.get(&name).is_some_and(|d| d.contains(&key)) {
+                    continue;
+                }
+                let mut row = data.row(&key)?.into_owned();
+                for column in columns {
+                    row[column] = DBValue::Null;
+                }
+                Self::check_not_null(data, &row)?;
+                rows.push((key, row));
+            }
+            updates.push((name, rows));
+        }
+        let mut kept = Vec::new();
+        for (name, keys) in &deletes {
+            let data = self.tables.get(name).ok_or(StorageError::TableNotFound)?;
+            for key in keys {
+                let rows = self.lock_rows(name, std::iter::once(&*data.row(key)?))?;
+                kept.push((name.clone(), rows));
+            }
+        }
+        for (name, rows) in &updates {
+            let rows = self.lock_rows(name, rows.iter().map(|(_, row)| row))?;
+            kept.push((name.clone(), rows));
+        }
+        for (name, rows) in kept {
+            self.keep_rows(&name, rows);
+        }
+        for (name, rows) in updates {
+            let data = self
+                .tables
+                .get_mut(&name)
+                .ok_or(StorageError::TableNotFound)?;
+            let keys = data.update(rows)?;
+            let rowids = self.rowids(&name, &keys)?;
+            self.session
+                .hooks
+                .rows_changed(&name, Operation::Update, &rowids);
+        }
+        for (name, keys) in deletes {
+            let keys: Vec<_> = keys.into_iter().collect();
+            let rowids = self.rowids(&name, &keys)?;
+            let data = self
+                .tables
+                .get_mut(&name)
+                .ok_or(StorageError::TableNotFound)?;
+            data.remove(&keys)?;
+            self.session
+                .hooks
+                .rows_changed(&name, Operation::Delete, &rowids);
+        }
+        Ok(())
+    }
+
+    /// The rowids of the rows of the table with the given keys, for the update hook; see
+    /// [`Table::rowid`]. Empty without an update hook, which spares looking the rows up.
+    fn rowids(&self, name: &str, keys: &[Key]) -> Result<Vec<DBValue>, StorageError> {
+        if !self.session.hooks.has_update() {
+            return Ok(Vec::new());
+        }
+        let table = self.tables.get(name).ok_or(StorageError::TableNotFound)?;
+        Ok(keys
+            .iter()
+            .map(|key| table.rowid(key))
+            .collect::<io::Result<_>>()?)
+    }
+
+    /// Follows the foreign keys referencing the table to find out which rows (of which tables)
+    /// get deleted and which columns get set to null when deleting the given rows. Fails if a
+    /// restricting foreign key references any of the deleted rows.
+    fn plan_delete(&self, table: String, keys: Vec<Key>) -> Result<DeletePlan, StorageError> {
+        let mut deletes: HashMap<String, BTreeSet<Key>> = HashMap::new();
+        let mut set_nulls: HashMap<String, BTreeMap<Key, Vec<usize>>> = HashMap::new();
+        let mut restricted = Vec::new();
+        let mut worklist = vec![(table, keys)];
+        while let Some((name, keys)) = worklist.pop() {
+            let deleted = deletes.entry(name.clone()).or_default();
+            let keys: Vec<_> = keys
+                .into_iter()
+                .filter(|key| deleted.insert(key.clone()))
+                .collect();
+            let parent = self.tables.get(&name).ok_or(StorageError::TableNotFound)?;
+            for (child_name, child) in &self.tables {
+                for fk in child.foreign_keys().iter().filter(|fk| fk.table == name) {
+                    let mut values = HashSet::new();
+                    for key in &keys {
+                        let value = parent.row(key)?[fk.references].clone();
+                        if value != DBValue::Null {
+                            values.insert(value);
+                        }
+                    }
+                    let mut referencing = Vec::new();
+                    for entry in child.entries() {
+                        let (key, row) = entry?;
+                        if values.contains(&row[fk.column]) {
+                            referencing.push(key.into_owned());
+                        }
+                    }
+                    match fk.on_delete {
+                        ReferentialAction::Restrict => restricted.push((child_name, referencing)),
+                        ReferentialAction::Cascade => {
+                            worklist.push((child_name.clone(), referencing))
+                        }
+                        ReferentialAction::SetNull => {
+                            let nulls = set_nulls.entry(child_name.clone()).or_default();
+                            for key in referencing {
+                                nulls.entry(key).or_default().push(fk.column);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        // Rows referencing deleted rows are fine if they get deleted themselves
+        for (name, keys) in restricted {
+            let deleted = deletes.get(name);
+            if keys
+                .iter()
+                .any(|key| !deleted.is_some_and(|d| d.contains(key)))
+            {
+                return Err(StorageError::ForeignKeyViolation);
+            }
+        }
+        Ok((deletes, set_nulls))
+    }
+
+    pub fn create_index(
+        &mut self,
+        name: String,
+        table: String,
+        column: String,
+        unique: bool,
+    ) -> Result<(), StorageError> {
+        if self.index_exists(&name) {
+            return Err(StorageError::IndexNameAlreadyInUse);
+        }
+        let table = self
+            .tables
+            .get_mut(&table)
+            .ok_or(StorageError::TableNotFound)?;
+        let column = table
+            .schema()
+            .get_column_index(&column)
+            .ok_or(StorageError::SchemaMismatch)?;
+        table
+            .create_index(name, column, unique)?
+            .ok_or(StorageError::UniqueViolation)?;
+        self.schema_changed();
+        Ok(())
+    }
+
+    pub fn create_sequence(
+        &mut self,
+        name: String,
+        start: i64,
+        increment: i64,
+    ) -> Result<(), StorageError> {
+        if self.sequences.contains_key(&name) {
+            return Err(StorageError::SequenceNameAlreadyInUse);
+        }
+        self.sequences.insert(name, Sequence::new(start, increment));
+        Ok(())
+    }
+
+    pub fn drop_sequence(&mut self, name: String) -> Result<(), StorageError> {
+        self.sequences
+            .remove(&name)
+            .map(|_| ())
+            .ok_or(StorageError::SequenceNotFound)
+    }
+
+    /// Collects the statistics of the table, or of all tables, for the query planner.
+    pub fn analyze(&mut self, table: Option<String>) -> Result<(), StorageError> {
+        match table {
+            Some(name) => self
+                .tables
+                .get_mut(&name)
+                .ok_or(StorageError::TableNotFound)?
+                .analyze()?,
+            None => {
+                for table in self.tables.values_mut() {
+                    table.analyze()?;
+                }
+            }
+        }
+        // The statistics decide the order of joins in plans
+        self.schema_changed();
+        Ok(())
+    }
+
+    fn scope<'a>(&'a self, name: &'a str, table: &'a Table) -> Scope<'a> {
+        let mut scope = self.empty_scope();
+        scope.join(name, Cow::Borrowed(table.schema()));
+        scope
+    }
+
+    fn empty_scope(&self) -> Scope<'_> {
+        Scope {
+            tables: Vec::new(),
+            sequences: &self.sequences,
+            functions: &self.functions,
+            progress: &self.session.progress,
+            #[cfg(feature = "regexp")]
+            regexes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn index_exists(&self, name: &str) -> bool {
+        self.tables
+            .values()
+            .any(|table| table.indexes().contains_key(name))
+    }
+
+    /// Returns the keys of the rows satisfying the condition in key order. Uses an
+    /// index when the condition contains an equality on an indexed column. The candidate rows
+    /// are checked in parallel, see [`StorageManager::set_parallelism`].
+    fn matching_rows(
+        &self,
+        name: &str,
+        table: &Table,
+        condition: Option<&Condition>,
+    ) -> Result<Vec<Key>, StorageError> {
+        let condition = match condition {
+            Some(condition) => condition,
+            None => return Ok(table.keys()?),
+        };
+        match constant_value(condition) {
+            Some(Some(true)) => return Ok(table.keys()?),
+            Some(_) => return Ok(Vec::new()),
+            None => (),
+        }
+        let mut candidates = match index_lookup(condition, name, table) {
+            Some(keys) => keys?,
+            None => table.keys()?,
+        };
+        candidates.sort_unstable();
+        let scope = &self.scope(name, table);
+        map_chunks(&candidates, self.parallelism, |candidates| {
+            let mut keys = Vec::new();
+            for key in candidates {
+                if eval_condition(condition, scope, &table.row(key)?)? == Some(true) {
+                    keys.push(key.clone());
+                }
+            }
+            Ok(keys)
+        })
+    }
+
+    /// Executes any SQL-statement, returning the resulting rows in the case of a query and an
+    /// empty result set otherwise. Once the changes of a statement are committed, a database
+    /// opened from a file is saved as its synchronous setting requires; see [`Synchronous`].
+    pub fn execute(&mut self, stmt: Statement) -> Result<ResultSet, StorageError> {
+        let changes = !matches!(
+            stmt,
+            Statement::Select { .. }
+                | Statement::Begin
+                | Statement::Rollback { .. }
+                | Statement::Savepoint { .. }
+                | Statement::Release { .. }
+                | Statement::SetTransaction { .. }
+                | Statement::Analyze { .. }
+                | Statement::Pragma { .. }
+                | Statement::Vacuum
+        );
+        let autocommit =
+            changes && self.session.transaction.is_none() && !matches!(stmt, Statement::Commit);
+        if !self.virtual_tables.is_empty() && matches!(stmt, Statement::Select { .. }) {
+            self.scan_virtual_tables(&self.inline_views(stmt.clone())?)?;
+        }
+        let locks = self.statement_locks(&stmt);
+        let result = self.with_locks(&locks, |storage| storage.execute_statement(stmt))?;
+        if changes && self.session.transaction.is_none() && self.file.is_some() {
+            match self.synchronous {
+                Synchronous::Off => (),
+                Synchronous::Normal => self.write_file(false)?,
+                Synchronous::Full => self.write_file(true)?,
+            }
+        }
+        if autocommit {
+            self.session.hooks.committed();
+        }
+        Ok(result)
+    }
+
+    fn execute_statement(&mut self, stmt: Statement) -> Result<ResultSet, StorageError> {
+        match stmt {
+            Statement::CreateTable {
+                table,
+                columns,
+                constraints,
+            } => self.create_table(table, Schema::from(columns), constraints),
+            Statement::InsertInto {
+                table,
+                columns,
+                values,
+            } => self.insert_into(table, columns, values),
+            Statement::CreateView { view, query } => self.create_view(view, *query),
+            Statement::DropView { view } => self.drop_view(view),
+            Statement::DropTable { table } => self.drop_table(table),
+            Statement::CreateIndex {
+                index,
+                table,
+                column,
+                unique,
+            } => self.create_index(index, table, column, unique),
+            Statement::Update {
+                table,
+                assignments,
+                condition,
+            } => self.update(table, assignments, condition),
+            Statement::DeleteFrom { table, condition } => self.delete_from(table, condition),
+            Statement::CreateSequence {
+                sequence,
+                start,
+                increment,
+            } => self.create_sequence(sequence, start, increment),
+            Statement::DropSequence { sequence } => self.drop_sequence(sequence),
+            Statement::CreateVirtualTable {
+                table,
+                module,
+                args,
+            } => self.create_virtual_table(table, module, args),
+            Statement::Begin => self.begin().map(|_| ()),
+            Statement::Commit => self.commit(),
+            Statement::Rollback { savepoint: None } => self.rollback(),
+            Statement::Rollback {
+                savepoint: Some(savepoint),
+            } => self.rollback_to(savepoint),
+            Statement::Savepoint { savepoint } => self.savepoint(savepoint),
+            Statement::Release { savepoint } => self.release(savepoint),
+            Statement::SetTransaction { isolation } => self.set_isolation(isolation),
+            Statement::Analyze { table } => self.analyze(table),
+            Statement::Pragma { name, value } => return self.pragma(name, value),
+            Statement::Vacuum => return self.vacuum(),
+            Statement::Copy {
+                table,
+                columns,
+                path,
+                options,
+            } => return self.copy_from(table, columns, path, options),
+            query => return self.query(query),
+        }
+        .map(|_| ResultSet::default())
+    }
+
+    /// Rewrites the b-tree files of the tables stored in files, and the file the database was
+    /// opened from, dropping the pages no longer in use and filling the nodes of the trees
+    /// anew. Returns the number of bytes reclaimed as a single row. The file the database was
+    /// opened from is copied to a new file, which then replaces it, saving any changes not
+    /// saved yet along the way.
+    fn vacuum(&mut self) -> Result<ResultSet, StorageError> {
+        if self.transactions.active().next().is_some() {
+            return Err(StorageError::TransactionAlreadyActive);
+        }
+        let mut reclaimed = 0;
+        for table in self.tables.values_mut() {
+            reclaimed += table.vacuum()?;
+        }
+        if let Some(path) = self.file.clone() {
+            let size = || fs::metadata(&path).map_or(0, |metadata| metadata.len());
+            let before = size();
+            let tree = self.write_copy(&path, &self.tables, &self.views)?;
+            let records = tree
+                .cursor()?
+                .map(|record| record.map(|(_, record)| record))
+                .collect::<io::Result<Vec<_>>>()?;
+            let trees = Arc::new(RwLock::new(tree));
+            self.attach_tables(&trees, &records)?;
+            self.trees = Some(trees);
+            reclaimed += before.saturating_sub(size());
+        }
+        Ok(ResultSet {
+            columns: vec![ResultColumn {
+                name: String::from("reclaimed_bytes"),
+                db_type: Some(DBType::Integer),
+            }],
+            rows: vec![vec![DBValue::Integer(reclaimed as i64)]],
+        })
+    }
+
+    /// Reads or changes a setting: `synchronous`, `archive` or `journal_mode`. Reading a setting
+    /// returns it as a single row.
+    fn pragma(&mut self, name: String, value: Option<String>) -> Result<ResultSet, StorageError> {
+        let pragma = name.to_ascii_lowercase();
+        let current = match pragma.as_str() {
+            "synchronous" => self.synchronous.to_string(),
+            "archive" => String::from(if self.archive { "on" } else { "off" }),
+            "journal_mode" => self.journal_mode.to_string(),
+            "busy_timeout" => self.session.busy_timeout.as_millis().to_string(),
+            _ => return Err(StorageError::UnknownPragma(name)),
+        };
+        let value = match value {
+            Some(value) => value,
+            None => {
+                return Ok(ResultSet {
+                    columns: vec![ResultColumn {
+                        name,
+                        db_type: Some(DBType::Text),
+                    }],
+                    rows: vec![vec![DBValue::Text(current)]],
+                })
+            }
+        };
+        match (pragma.as_str(), value.to_ascii_lowercase().as_str()) {
+            ("synchronous", "off" | "0") => self.synchronous = Synchronous::Off,
+            ("synchronous", "normal" | "1") => self.synchronous = Synchronous::Normal,
+            ("synchronous", "full" | "2") => self.synchronous = Synchronous::Full,
+            ("archive", "off" | "0") => self.archive = false,
+            ("archive", "on" | "1") => self.archive = true,
+            ("journal_mode", "off") => self.journal_mode = JournalMode::Off,
+            ("journal_mode", "rollback") => self.journal_mode = JournalMode::Rollback,
+            ("busy_timeout", millis) => match millis.parse() {
+                Ok(millis) => self.session.busy_timeout = Duration::from_millis(millis),
+                Err(_) => return Err(StorageError::InvalidPragmaValue(value)),
+            },
+            _ => return Err(StorageError::InvalidPragmaValue(value)),
+        }
+        Ok(ResultSet::default())
+    }
+
+    /// Begins a transaction in the session, returning its id. Changes are grouped by the
+    /// transaction until it is committed, and undone at once if it is rolled back, whether to
+    /// tables in memory or in b-tree files.
+    pub fn begin(&mut self) -> Result<TransactionId, StorageError> {
+        if self.session.transaction.is_some() {
+            return Err(StorageError::TransactionAlreadyActive);
+        }
+        let id = self
+            .transactions
+            .begin(Undo::default(), self.session.isolation);
+        self.session.transaction = Some(id);
+        Ok(id)
+    }
+
+    /// Sets the isolation level of the transactions begun from now on in the session; see
+    /// [`IsolationLevel`]. Can't be changed in the middle of a transaction, whose locks were
+    /// taken under the old level.
+    pub fn set_isolation(&mut self, isolation: IsolationLevel) -> Result<(), StorageError> {
+        if self.session.transaction.is_some() {
+            return Err(StorageError::TransactionAlreadyActive);
+        }
+        self.session.isolation = isolation;
+        Ok(())
+    }
+
+    pub fn commit(&mut self) -> Result<(), StorageError> {
+        let id = self
+            .session
+            .transaction
+            .ok_or(StorageError::NoActiveTransaction)?;
+        self.transactions.commit(id)?;
+        self.session.transaction = None;
+        self.locks.release(id);
+        self.session.hooks.committed();
+        Ok(())
+    }
+
+    pub fn rollback(&mut self) -> Result<(), StorageError> {
+        let id = self
+            .session
+            .transaction
+            .ok_or(StorageError::NoActiveTransaction)?;
+        let undo = self.transactions.abort(id)?;
+        self.session.transaction = None;
+        let undone = self.undo(undo);
+        self.locks.release(id);
+        self.session.hooks.rolled_back();
+        undone
+    }
+
+    pub fn savepoint(&mut self, name: String) -> Result<(), StorageError> {
+        let id = self
+            .session
+            .transaction
+            .ok_or(StorageError::NoActiveTransaction)?;
+        self.transactions.savepoint(id, name, Undo::default())
+    }
+
+    /// Undoes the changes made after the savepoint. The savepoint itself stays in place, so that
+    /// the work can be retried and rolled back again.
+    pub fn rollback_to(&mut self, name: String) -> Result<(), StorageError> {
+        let id = self
+            .session
+            .transaction
+            .ok_or(StorageError::NoActiveTransaction)?;
+        let (kept, forgotten) = self.transactions.rollback_to(id, &name)?;
+        let mut undo = vec![mem::take(kept)];
+        undo.extend(forgotten);
+        self.undo(undo)
+    }
+
+    /// Forgets the savepoint and any savepoints after it, keeping the changes made since.
+    pub fn release(&mut self, name: String) -> Result<(), StorageError> {
+        let id = self
+            .session
+            .transaction
+            .ok_or(StorageError::NoActiveTransaction)?;
+        let released = self.transactions.release(id, &name)?;
+        if let Some(undo) = self.transactions.current(id) {
+            released.into_iter().for_each(|later| undo.merge(later));
+        }
+        Ok(())
+    }
+
+    /// Undoes the changes of a transaction, from the latest savepoint back to the earliest.
+    fn undo(&mut self, undo: Vec<Undo>) -> Result<(), StorageError> {
+        for undo in undo.into_iter().rev() {
+            undo.apply(&mut self.tables, &mut self.views)?;
+        }
+        self.schema_changed();
+        Ok(())
+    }
+
+    /// Runs `f` in the session: statements run by `f` belong to the transaction of the session
+    /// and follow its settings, while the tables are shared by all sessions. Sessions keep the
+    /// transactions of different users of the database apart; see [`crate::connection`].
+    pub fn in_session<T>(&mut self, session: &mut Session, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.swap_session(session);
+        let result = f(self);
+        self.swap_session(session);
+        result
+    }
+
+    /// Swaps the session in, or back out again, as [`StorageManager::in_session`] does around
+    /// its closure, for cursors running in the session after the call that creates them.
+    pub fn swap_session(&mut self, session: &mut Session) {
+        mem::swap(&mut self.session, session);
+    }
+
+    /// The table locks a statement takes; see [`crate::lock_manager`]. Reads take shared locks on
+    /// the tables they read, including the tables under views, and writes exclusive locks on the
+    /// table they write, or intention locks if the table has a primary key, locking the rows
+    /// they write by their keys instead. Inserts and updates also read the tables their foreign
+    /// keys reference, and updates and deletes write the tables referencing theirs, which
+    /// deletes may cascade to. Statements changing the schema lock the table or view they create
+    /// or drop.
+    pub fn statement_locks(&self, statement: &Statement) -> Vec<(String, LockMode)> {
+        let mut locks = BTreeMap::new();
+        let mut lock = |table: &str, mode: LockMode| {
+            let held = locks.entry(String::from(table)).or_insert(mode);
+            *held = held.join(mode);
+        };
+        match statement {
+            Statement::Select { .. } => self.read_locks(statement, &mut lock),
+            Statement::CreateView { view, query } => {
+                lock(view, LockMode::Exclusive);
+                self.read_locks(query, &mut lock);
+            }
+            Statement::InsertInto { table, .. }
+            | Statement::Copy { table, .. }
+            | Statement::Update { table, .. }
+            | Statement::DeleteFrom { table, .. } => {
+                let data = self.tables.get(table);
+                // Rows are locked by their primary keys as they are written
+                match data.and_then(Table::primary_key) {
+                    Some(_) => lock(table, LockMode::IntentionExclusive),
+                    None => lock(table, LockMode::Exclusive),
+                }
+                let writes_rows = !matches!(
+                    statement,
+                    Statement::InsertInto { .. } | Statement::Copy { .. }
+                );
+                if !matches!(statement, Statement::DeleteFrom { .. }) {
+                    for fk in data.into_iter().flat_map(|data| data.foreign_keys()) {
+                        lock(&fk.table, LockMode::Shared);
+                    }
+                }
+                for (name, child) in &self.tables {
+                    if writes_rows && child.foreign_keys().iter().any(|fk| &fk.table == table) {
+                        lock(name, LockMode::Exclusive);
+                    }
+                }
+            }
+            Statement::CreateTable {
+                table, constraints, ..
+            } => {
+                lock(table, LockMode::Exclusive);
+                for constraint in constraints {
+                    if let Constraint::ForeignKey { table, .. } = constraint {
+                        lock(table, LockMode::Shared);
+                    }
+                }
+            }
+            Statement::DropTable { table }
+            | Statement::CreateIndex { table, .. }
+            | Statement::CreateVirtualTable { table, .. } => lock(table, LockMode::Exclusive),
+            Statement::DropView { view } => lock(view, LockMode::Exclusive),
+            Statement::Analyze { table: Some(table) } => lock(table, LockMode::Shared),
+            Statement::Analyze { table: None } => self
+                .tables
+                .keys()
+                .for_each(|table| lock(table, LockMode::Shared)),
+            Statement::Vacuum => self
+                .tables
+                .keys()
+                .for_each(|table| lock(table, LockMode::Exclusive)),
+            _ => (),
+        }
+        locks.into_iter().collect()
+    }
+
+    /// Takes shared locks on the tables and views a query reads, and the tables under the views.
+    fn read_locks(&self, query: &Statement, lock: &mut impl FnMut(&str, LockMode)) {
+        if let Statement::Select { table, joins, .. } = query {
+            for table in std::iter::once(table).chain(joins.iter().map(|join| &join.table)) {
+                lock(table, LockMode::Shared);
+                if let Some(view) = self.views.get(table) {
+                    self.read_locks(view, lock);
+                }
+            }
+        }
+    }
+
+    /// The id of the transaction in progress in the session, if any.
+    pub fn transaction(&self) -> Option<TransactionId> {
+        self.session.transaction
+    }
+
+    /// Executes a query, collecting all of its rows; see [`StorageManager::query_rows`].
+    pub fn query(&self, query: Statement) -> Result<ResultSet, StorageError> {
+        if let Statement::Select { .. } = query {
+            result_set(self.query_rows(query)?)
+        } else {
+            Ok(ResultSet::default())
+        }
+    }
+
+    /// Executes a query, returning a cursor over its rows; see [`StorageManager::plan`] and
+    /// [`StorageManager::run`].
+    pub fn query_rows(&self, query: Statement) -> Result<Rows<'_>, StorageError> {
+        self.run(self.plan(query)?)
+    }
+
+    /// The tables a query reads, the queried table first, then the joined tables in the order
+    /// of the query. Names are borrowed from the catalog rather than the query, as the operators
+    /// reading the tables outlive it.
+    fn sources(&self, query: &InlinedQuery) -> Result<Vec<Source<'_>>, StorageError> {
+        let (name, table) = self.queried_table(&query.table)?;
+        let mut sources = vec![(name.as_str(), table)];
+        for join in &query.joins {
+            let (name, data) = self.queried_table(&join.table)?;
+            sources.push((name, data));
+        }
+        Ok(sources)
+    }
+
+    /// Plans a query: inlines its views, checks its columns against the schema, simplifies it
+    /// and decides the order in which to join its tables. The plan may be run any number of
+    /// times, until the schema changes.
+    pub fn plan(&self, query: Statement) -> Result<QueryPlan, StorageError> {
+        let mut query = self.inline_views(query)?;
+        let sources = self.sources(&query)?;
+        let (name, table) = sources[0];
+        let mut scope = self.scope(name, table);
+        for (name, data) in &sources[1..] {
+            scope.join(*name, Cow::Borrowed(data.schema()));
+        }
+        for selector in query.selectors() {
+            scope.resolve(selector)?;
+        }
+        let result_columns = query
+            .columns
+            .iter()
+            .map(|expression| ResultColumn {
+                name: column_name(expression),
+                db_type: expression_type(expression, &scope),
+            })
+            .collect();
+        query.simplify(&self.empty_scope());
+        let conditions = query.joins.iter().map(|join| &join.condition);
+        let order = plan_joins(&sources, conditions.chain(&query.condition).collect()).order;
+        Ok(QueryPlan {
+            query,
+            columns: result_columns,
+            order,
+        })
+    }
+
+    /// Runs a planned query by building a pipeline of relational operators: scans of the
+    /// tables, with the conditions on each table pushed down to its scan, the joins, then either
+    /// a projection or an aggregation, sorting and finally the limit. Scans read only the
+    /// columns the query refers to. The projection or aggregation evaluates the sort keys along
+    /// with the columns of the result, for the sort to strip off again.
+    ///
+    /// The rows are produced as the returned cursor is iterated, so only operators that need all
+    /// of their input, such as sorting, hold many rows at once.
+    pub fn run(&self, plan: QueryPlan) -> Result<Rows<'_>, StorageError> {
+        let QueryPlan {
+            query,
+            columns: result_columns,
+            order,
+        } = plan;
+        let sources = self.sources(&query)?;
+        if query.counts_rows() {
+            let count = DBValue::Integer(sources[0].1.len() as i64);
+            let mut pipeline: Box<dyn Operator> = Box::new(Values::new(vec![vec![count]]));
+            if let Some(limit) = query.limit {
+                pipeline = Box::new(executor::Limit::new(pipeline, limit));
+            }
+            return Ok(Rows {
+                columns: result_columns,
+                pipeline: Some(pipeline),
+            });
+        }
+        let columns = referenced_columns(query.selectors(), &sources);
+        let conditions = query.joins.iter().map(|join| &join.condition);
+        let conditions = conditions.chain(&query.condition).collect();
+        let plan = plan_joins_in_order(&sources, conditions, order);
+        let order = &plan.order;
+        let mut scope = self.empty_scope();
+        let mut offsets = vec![0; sources.len()];
+        let mut width = 0;
+        for &table in order {
+            let (name, data) = sources[table];
+            let schema = data.schema().project(&columns[table]);
+            offsets[table] = width;
+            width += schema.len();
+            scope.join(String::from(name), Cow::Owned(schema));
+        }
+        let position = |(table, column): (usize, usize)| {
+            let index = columns[table].binary_search(&column).ok();
+            index.map(|index| offsets[table] + index)
+        };
+        // Columns the rows joined so far are in ascending order of; joins keep the order of
+        // their left input
+        let first = order[0];
+        let mut ordered: Vec<_> = clustered_on(sources[first].1)
+            .map(|key| (first, key))
+            .into_iter()
+            .collect();
+        let mut methods = vec![JoinMethod::NestedLoop];
+        let mut step_conditions = vec![None];
+        for (i, &next) in order.iter().enumerate().skip(1) {
+            let steps = &plan.steps[i];
+            let method = join_method(&sources, &order[..i], next, steps, &ordered);
+            if let JoinMethod::Merge { right, .. } = method {
+                ordered.push((next, right));
+            }
+            let mut conjuncts = plan.steps[i].clone();
+            if let JoinMethod::Index { .. } = method {
+                // Rows looked up in an index bypass the scan with the pushed-down conditions
+                conjuncts.extend(&plan.filters[next]);
+            }
+            methods.push(method);
+            step_conditions.push(conjunction(&conjuncts));
+        }
+        let scope = Rc::new(scope);
+        let mut pipeline: Option<Box<dyn Operator>> = None;
+        for (i, &next) in order.iter().enumerate() {
+            let (name, data) = sources[next];
+            let scan = |filters: &[&Condition]| -> Result<Box<dyn Operator>, StorageError> {
+                let filter = conjunction(filters);
+                let keys = self.matching_rows(name, data, filter.as_ref())?;
+                let columns = columns[next].clone();
+                Ok(Box::new(SeqScan::new(
+                    data,
+                    keys,
+                    columns,
+                    &self.rows_scanned,
+                )))
+            };
+            let condition = step_conditions[i].take();
+            let scope = Rc::clone(&scope);
+            pipeline = Some(match (pipeline, methods[i]) {
+                (None, _) => scan(&plan.filters[next])?,
+                (Some(left), JoinMethod::Index { left: key, index }) => match position(key) {
+                    Some(key) => Box::new(IndexJoin::new(
+                        left,
+                        (data, index),
+                        key,
+                        columns[next].clone(),
+                        &self.rows_scanned,
+                        condition,
+                        scope,
+                    )),
+                    None => Box::new(NestedLoopJoin::new(left, scan(&[])?, condition, scope)),
+                },
+                (Some(left), JoinMethod::Hash { left: key, right })
+                | (Some(left), JoinMethod::Merge { left: key, right }) => {
+                    let right_scan = scan(&plan.filters[next])?;
+                    let keys = position(key).zip(columns[next].binary_search(&right).ok());
+                    match (keys, methods[i]) {
+                        (Some(keys), JoinMethod::Merge { .. }) => {
+                            Box::new(MergeJoin::new(left, right_scan, keys, condition, scope))
+                        }
+                        (Some(keys), _) => {
+                            let collation = data.schema().get_collation(right);
+                            Box::new(HashJoin::new(
+                                left, right_scan, keys, collation, condition, scope,
+                            ))
+                        }
+                        (None, _) => {
+                            Box::new(NestedLoopJoin::new(left, right_scan, condition, scope))
+                        }
+                    }
+                }
+                (Some(left), JoinMethod::NestedLoop) => {
+                    let right = scan(&plan.filters[next])?;
+                    Box::new(NestedLoopJoin::new(left, right, condition, scope))
+                }
+            });
+        }
+        let mut pipeline = pipeline.ok_or(StorageError::TableNotFound)?;
+        let sort_keys = query.order_by.iter().map(|(expression, _)| expression);
+        let outputs = query.columns.iter().chain(sort_keys).cloned().collect();
+        pipeline = if !query.group_by.is_empty()
+            || query
+                .columns
+                .iter()
+                .any(|column| contains_aggregate(column, &scope))
+        {
+            let group_by = query.group_by.clone();
+            let threads = self.parallelism;
+            Box::new(Aggregate::new(
+                pipeline,
+                group_by,
+                outputs,
+                Rc::clone(&scope),
+                threads,
+            ))
+        } else {
+            Box::new(Project::new(pipeline, outputs, Rc::clone(&scope)))
+        };
+        if !query.order_by.is_empty() {
+            let order_by = query.order_by.clone();
+            let encrypt = self.cipher.is_some();
+            pipeline = Box::new(Sort::new(
+                pipeline,
+                order_by,
+                scope,
+                self.sort_memory,
+                encrypt,
+            ));
+        }
+        if let Some(limit) = query.limit {
+            pipeline = Box::new(executor::Limit::new(pipeline, limit));
+        }
+        Ok(Rows {
+            columns: result_columns,
+            pipeline: Some(pipeline),
+        })
+    }
+}
+
+impl Default for StorageManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Cursor over the rows of a query, see [`StorageManager::query_rows`]. Iterating it pulls the
+/// rows from the operator pipeline one at a time; it ends after the first error.
+pub struct Rows<'a> {
+    columns: Vec<ResultColumn>,
+    pipeline: Option<Box<dyn Operator + 'a>>,
+}
+
+impl Rows<'_> {
+    /// Names and types of the columns of the rows.
+    pub fn columns(&self) -> &[ResultColumn] {
+        &self.columns
+    }
+}
+
+/// Cursor over rows already collected, e.g. by a query run under locks released since.
+impl From<ResultSet> for Rows<'_> {
+    fn from(result: ResultSet) -> Self {
+        Self {
+            columns: result.columns,
+            pipeline: Some(Box::new(Values::new(result.rows))),
+        }
+    }
+}
+
+impl Iterator for Rows<'_> {
+    type Item = Result<Row, StorageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let row = self.pipeline.as_mut()?.next();
+        if !matches!(row, Some(Ok(_))) {
+            self.pipeline = None;
+        }
+        row
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datetime;
+
+    fn execute(storage: &mut StorageManager, input: &str) -> Result<Vec<Row>, StorageError> {
+        match Parser::new(input).parse_command() {
+            Ok(Command::Statement(stmt)) => storage.execute(stmt).map(|result| result.rows),
+            _ => panic!("Invalid test statement"),
+        }
+    }
+
+    #[test]
+    fn query_view() {
+        let mut storage = StorageManager::new();
+        execute(&mut storage, "create table tbl (id integer, name text);").ok();
+        execute(&mut storage, "insert into tbl values (1, 'foo');").ok();
+        execute(&mut storage, "create view v as select (name, id) from tbl;").ok();
+        let rows = execute(&mut storage, "select (name) from v;").ok();
+        assert_eq!(rows, Some(vec![vec![DBValue::Text(String::from("foo"))]]));
+        assert!(execute(&mut storage, "select (missing) from v;").is_err());
+    }
+
+    #[test]
+    fn indexed_equality_lookup() {
+        let mut storage = StorageManager::new();
+        execute(&mut storage, "create table tbl (id integer, name text);").ok();
+        execute(&mut storage, "create index idx on tbl (name);").ok();
+        for input in &[
+            "insert into tbl values (1, 'foo');",
+            "insert into tbl values (2, 'bar');",
+            "insert into tbl values (3, 'foo');",
+            "update tbl set name = 'bar' where id = 1;",
+            "delete from tbl where id = 2;",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        let foo = execute(&mut storage, "select (id) from tbl where name = 'foo';").ok();
+        let bar = execute(&mut storage, "select (id) from tbl where 'bar' = name;").ok();
+        assert_eq!(foo, Some(vec![vec![DBValue::Integer(3)]]));
+        assert_eq!(bar, Some(vec![vec![DBValue::Integer(1)]]));
+        let duplicate = execute(&mut storage, "create index idx on tbl (id);");
+        assert!(matches!(
+            duplicate,
+            Err(StorageError::IndexNameAlreadyInUse)
+        ));
+    }
+
+    #[test]
+    fn bound_parameters() {
+        let mut storage = StorageManager::new();
+        execute(&mut storage, "create table tbl (id integer, name text);").ok();
+        let insert = match Parser::new("insert into tbl values (?, ?);").parse_command() {
+            Ok(Command::Statement(stmt)) => stmt,
+            _ => panic!("Invalid test statement"),
+        };
+        for (id, name) in [(1, "foo"), (2, "it's")] {
+            let values = [DBValue::Integer(id), DBValue::Text(String::from(name))];
+            assert!(storage.execute(insert.bind(&values).unwrap()).is_ok());
+        }
+        let rows = execute(&mut storage, "select (name) from tbl where id = 2;").ok();
+        assert_eq!(rows, Some(vec![vec![DBValue::Text(String::from("it's"))]]));
+        let unbound = storage.execute(insert);
+        assert!(matches!(unbound, Err(StorageError::UnboundParameter)));
+    }
+
+    #[test]
+    fn unique_violation() {
+        let mut storage = StorageManager::new();
+        execute(
+            &mut storage,
+            "create table tbl (id integer unique, name text);",
+        )
+        .ok();
+        execute(&mut storage, "insert into tbl values (1, 'foo');").ok();
+        execute(&mut storage, "insert into tbl values (2, 'foo');").ok();
+        let insert = execute(&mut storage, "insert into tbl values (1, 'bar');");
+        let update = execute(&mut storage, "update tbl set id = 3;");
+        let index = execute(&mut storage, "create unique index idx on tbl (name);");
+        assert!(matches!(insert, Err(StorageError::UniqueViolation)));
+        assert!(matches!(update, Err(StorageError::UniqueViolation)));
+        assert!(matches!(index, Err(StorageError::UniqueViolation)));
+        assert!(execute(&mut storage, "update tbl set id = 3 where id = 2;").is_ok());
+        assert!(execute(&mut storage, "update tbl set id = 1 where id = 1;").is_ok());
+    }
+
+    #[test]
+    fn rows_clustered_on_primary_key() {
+        let mut storage = StorageManager::new();
+        execute(
+            &mut storage,
+            "create table tbl (name text, id integer primary key);",
+        )
+        .ok();
+        execute(&mut storage, "insert into tbl values ('foo', 2);").ok();
+        execute(&mut storage, "insert into tbl values ('bar', 3);").ok();
+        execute(&mut storage, "insert into tbl values ('baz', 1);").ok();
+        let duplicate = execute(&mut storage, "insert into tbl values ('qux', 1);");
+        assert!(matches!(duplicate, Err(StorageError::UniqueViolation)));
+        execute(&mut storage, "update tbl set id = 0 where name = 'bar';").ok();
+        let rows = execute(&mut storage, "select (id) from tbl;").ok();
+        let ids = vec![0, 1, 2]
+            .into_iter()
+            .map(|i| vec![DBValue::Integer(i)])
+            .collect();
+        assert_eq!(rows, Some(ids));
+        let multiple = execute(
+            &mut storage,
+            "create table t (a integer primary key, b integer primary key);",
+        );
+        assert!(matches!(multiple, Err(StorageError::MultiplePrimaryKeys)));
+    }
+
+    #[test]
+    fn foreign_key_actions() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table parent (id integer primary key);",
+            "create table restrict (id integer references parent (id));",
+            "create table cascade (id integer references parent (id) on delete cascade);",
+            "create table set_null (id integer references parent (id) on delete set null);",
+            "insert into parent values (1);",
+            "insert into parent values (2);",
+            "insert into parent values (3);",
+            "insert into restrict values (1);",
+            "insert into cascade values (2);",
+            "insert into set_null values (3);",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        let missing = execute(&mut storage, "insert into cascade values (4);");
+        let restricted = execute(&mut storage, "delete from parent where id = 1;");
+        let updated = execute(&mut storage, "update parent set id = 4 where id = 1;");
+        assert!(matches!(missing, Err(StorageError::ForeignKeyViolation)));
+        assert!(matches!(restricted, Err(StorageError::ForeignKeyViolation)));
+        assert!(matches!(updated, Err(StorageError::ForeignKeyViolation)));
+        assert!(execute(&mut storage, "delete from parent where id > 1;").is_ok());
+        let cascade = execute(&mut storage, "select (id) from cascade;").ok();
+        let set_null = execute(&mut storage, "select (id) from set_null;").ok();
+        assert_eq!(cascade, Some(vec![]));
+        assert_eq!(set_null, Some(vec![vec![DBValue::Null]]));
+        let drop = execute(&mut storage, "drop table parent;");
+        assert!(matches!(drop, Err(StorageError::DependentForeignKeys)));
+    }
+
+    #[test]
+    fn not_null_violation() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table parent (id integer primary key);",
+            "create table child (id integer not null references parent (id) on delete set null);",
+            "insert into parent values (1);",
+            "insert into child values (1);",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        let delete = execute(&mut storage, "delete from parent;");
+        assert!(
+            matches!(delete, Err(StorageError::NotNullViolation(ref column)) if column == "id")
+        );
+        let rows = execute(&mut storage, "select (id) from child;").ok();
+        assert_eq!(rows, Some(vec![vec![DBValue::Integer(1)]]));
+    }
+
+    #[test]
+    fn three_valued_logic() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table tbl (id integer unique, name text not null);",
+            "insert into tbl values (1, 'foo');",
+            "insert into tbl values (null, 'bar');",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        let insert = execute(&mut storage, "insert into tbl values (2, null);");
+        assert!(matches!(insert, Err(StorageError::NotNullViolation(_))));
+        let select = |storage: &mut StorageManager, condition: &str| {
+            let query = format!("select (name) from tbl where {};", condition);
+            execute(storage, &query).ok().map(|rows| rows.len())
+        };
+        assert_eq!(select(&mut storage, "id = null"), Some(0));
+        assert_eq!(select(&mut storage, "not id = 1"), Some(0));
+        assert_eq!(select(&mut storage, "not (id = 1 and id = 2)"), Some(1));
+        assert_eq!(select(&mut storage, "id = 1 or name = 'bar'"), Some(2));
+        assert_eq!(
+            select(&mut storage, "not (id > 0 or name = 'baz')"),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn boolean_columns_and_expressions() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table tbl (a integer, b integer, flag boolean);",
+            "insert into tbl values (1, 2, true);",
+            "insert into tbl values (3, 2, false);",
+            "insert into tbl values (null, 2, null);",
+            "update tbl set flag = a > b where not flag;",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        let rows = execute(&mut storage, "select a, a < b, flag from tbl where flag;").ok();
+        let row = |a, lt| {
+            vec![
+                DBValue::Integer(a),
+                DBValue::Boolean(lt),
+                DBValue::Boolean(true),
+            ]
+        };
+        assert_eq!(rows, Some(vec![row(1, true), row(3, false)]));
+        let rows = execute(&mut storage, "select (a = 1 or b = 2) from tbl;").ok();
+        assert_eq!(rows, Some(vec![vec![DBValue::Boolean(true)]; 3]));
+        let insert = execute(&mut storage, "insert into tbl values (1, 2, 'yes');");
+        let condition = execute(&mut storage, "select (a) from tbl where a;");
+        assert!(matches!(insert, Err(StorageError::TypeError)));
+        assert!(matches!(condition, Err(StorageError::TypeError)));
+    }
+
+    #[test]
+    fn real_arithmetic() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table tbl (id integer, price real);",
+            "create unique index idx on tbl (price);",
+            "insert into tbl values (1, 2.5);",
+            "insert into tbl values (2, 4);",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        let rows = execute(&mut storage, "select id * price, id / 2, price from tbl;").ok();
+        assert_eq!(
+            rows,
+            Some(vec![
+                vec![DBValue::Real(2.5), DBValue::Integer(0), DBValue::Real(2.5)],
+                vec![DBValue::Real(8.0), DBValue::Integer(1), DBValue::Real(4.0)],
+            ])
+        );
+        let rows = execute(&mut storage, "select (id) from tbl where price = 4;").ok();
+        assert_eq!(rows, Some(vec![vec![DBValue::Integer(2)]]));
+        let rows = execute(&mut storage, "select (id) from tbl where price <= id * 2;").ok();
+        assert_eq!(rows, Some(vec![vec![DBValue::Integer(2)]]));
+        let division = execute(&mut storage, "select (price / 0) from tbl;");
+        let overflow = execute(&mut storage, "select (id * 9223372036854775807) from tbl;");
+        assert!(matches!(division, Err(StorageError::DivisionByZero)));
+        assert!(matches!(overflow, Err(StorageError::IntegerOverflow)));
+    }
+
+    #[test]
+    fn nocase_collation() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table tbl (name text collate nocase unique, code text);",
+            "insert into tbl values ('foo', 'b');",
+            "insert into tbl values ('Bar', 'B');",
+            "insert into tbl values ('baz', 'a');",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        let duplicate = execute(&mut storage, "insert into tbl values ('FOO', 'c');");
+        assert!(matches!(duplicate, Err(StorageError::UniqueViolation)));
+        let text = |t: &str| DBValue::Text(String::from(t));
+        let rows = execute(&mut storage, "select code from tbl where name = 'BAR';").ok();
+        assert_eq!(rows, Some(vec![vec![text("B")]]));
+        let rows = execute(&mut storage, "select name from tbl where code = 'b';").ok();
+        assert_eq!(rows, Some(vec![vec![text("foo")]]));
+        let rows = execute(
+            &mut storage,
+            "select name from tbl where code = 'b' collate nocase;",
+        )
+        .ok();
+        assert_eq!(rows, Some(vec![vec![text("foo")], vec![text("Bar")]]));
+        let rows = execute(&mut storage, "select name from tbl order by name;").ok();
+        assert_eq!(
+            rows,
+            Some(vec![
+                vec![text("Bar")],
+                vec![text("baz")],
+                vec![text("foo")]
+            ])
+        );
+        let rows = execute(
+            &mut storage,
+            "select name from tbl order by name collate binary desc;",
+        )
+        .ok();
+        assert_eq!(
+            rows,
+            Some(vec![
+                vec![text("foo")],
+                vec![text("baz")],
+                vec![text("Bar")]
+            ])
+        );
+        let rows = execute(
+            &mut storage,
+            "select name from tbl order by code collate nocase, name desc;",
+        )
+        .ok();
+        assert_eq!(
+            rows,
+            Some(vec![
+                vec![text("baz")],
+                vec![text("foo")],
+                vec![text("Bar")]
+            ])
+        );
+    }
+
+    #[cfg(feature = "regexp")]
+    #[test]
+    fn regexp_operator() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table tbl (name text);",
+            "insert into tbl values ('juicydb');",
+            "insert into tbl values ('juicy');",
+            "insert into tbl values (null);",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        let text = |t: &str| DBValue::Text(String::from(t));
+        let rows = execute(
+            &mut storage,
+            "select name from tbl where name regexp '^ju.*db$';",
+        )
+        .ok();
+        assert_eq!(rows, Some(vec![vec![text("juicydb")]]));
+        let rows = execute(
+            &mut storage,
+            "select name from tbl where not name regexp 'db' order by name;",
+        )
+        .ok();
+        assert_eq!(rows, Some(vec![vec![text("juicy")]]));
+        // Matches without backtracking, which would take exponential time here
+        let input = format!("insert into tbl values ('{}');", "a".repeat(64));
+        assert!(execute(&mut storage, &input).is_ok());
+        let rows = execute(
+            &mut storage,
+            "select name from tbl where name regexp '^(a*)*b$';",
+        );
+        assert_eq!(rows.ok(), Some(vec![]));
+        let invalid = execute(&mut storage, "select name from tbl where name regexp '(';");
+        assert!(matches!(invalid, Err(StorageError::InvalidPattern(_))));
+        let mistyped = execute(&mut storage, "select name from tbl where name regexp 1;");
+        assert!(matches!(mistyped, Err(StorageError::TypeError)));
+    }
+
+    #[test]
+    fn temporal_types() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table events (name text, day date, at timestamp, starts time);",
+            "insert into events values ('a', date '2024-01-31', '2024-01-31T08:00:00', '08:00');",
+            "insert into events values ('b', '2023-12-24', timestamp '2023-12-24 18:30', null);",
+            "insert into events values ('c', null, '2024-03-01', time '23:59:59.5');",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        let text = |t: &str| DBValue::Text(String::from(t));
+        let rows = execute(
+            &mut storage,
+            "select name from events where at >= date '2024-01-01' order by at desc;",
+        )
+        .ok();
+        assert_eq!(rows, Some(vec![vec![text("c")], vec![text("a")]]));
+        let rows = execute(
+            &mut storage,
+            "select date_add(day, 1, 'month'), date_add(starts, 2, 'hours'), \
+             extract('year', day), extract('second', starts) from events where name <> 'b';",
+        )
+        .ok()
+        .map(|rows| {
+            let display = |row: Vec<DBValue>| row.iter().map(DBValue::to_string).collect();
+            rows.into_iter().map(display).collect::<Vec<Vec<String>>>()
+        });
+        assert_eq!(
+            rows,
+            Some(vec![
+                vec!["2024-02-29", "10:00:00", "2024", "0"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect::<Vec<_>>(),
+                vec!["NULL", "01:59:59.5", "NULL", "59"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+            ])
+        );
+        let rows = execute(
+            &mut storage,
+            "select date_add(at, -1, 'day') from events where name = 'b';",
+        )
+        .ok();
+        let christmas_eve = datetime::parse_timestamp("2023-12-23 18:30:00").unwrap();
+        assert_eq!(rows, Some(vec![vec![DBValue::Timestamp(christmas_eve)]]));
+        let rows = execute(
+            &mut storage,
+            "select name from events where now() > at and starts > time '12:00';",
+        )
+        .ok();
+        assert_eq!(rows, Some(vec![vec![text("c")]]));
+        let invalid = execute(
+            &mut storage,
+            "insert into events values ('d', '2024-02-30', null, null);",
+        );
+        assert!(matches!(invalid, Err(StorageError::TypeError)));
+        let out_of_range = execute(
+            &mut storage,
+            "select date_add(day, 10000, 'years') from events;",
+        );
+        assert!(matches!(
+            out_of_range,
+            Err(StorageError::DateTimeOutOfRange)
+        ));
+        let mistyped = execute(
+            &mut storage,
+            "select date_add(starts, 1, 'day') from events;",
+        );
+        assert!(matches!(mistyped, Err(StorageError::TypeError)));
+        let literal =
+            Parser::new("select name from events where day = date '2024-13-01';").parse_command();
+        assert_eq!(literal, Err(ParseError::InvalidDateTime));
+    }
+
+    #[test]
+    fn enum_columns() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table tasks (id integer, status text check in ('new', 'done'), size enum('s', 'l'));",
+            "create index tasks_status on tasks (status);",
+            "insert into tasks values (1, 'new', 's');",
+            "insert into tasks values (2, 'done', null);",
+            "insert into tasks values (3, 'new', 'l');",
+            "update tasks set status = 'done' where id = 3;",
+        ] {
+            assert!(execute(&mut storage, input).is_ok(), "{}", input);
+        }
+        let text = |t: &str| DBValue::Text(String::from(t));
+        let rows = execute(
+            &mut storage,
+            "select id, size from tasks where status = 'done' order by id;",
+        )
+        .ok();
+        let expected = vec![
+            vec![DBValue::Integer(2), DBValue::Null],
+            vec![DBValue::Integer(3), text("l")],
+        ];
+        assert_eq!(rows, Some(expected));
+        let rows = execute(&mut storage, "select status from tasks where id = 1;").ok();
+        assert_eq!(rows, Some(vec![vec![text("new")]]));
+        let invalid = execute(&mut storage, "insert into tasks values (4, 'old', 's');");
+        assert!(matches!(invalid, Err(StorageError::CheckViolation(c)) if c == "status"));
+        let invalid = execute(&mut storage, "update tasks set size = 'm';");
+        assert!(matches!(invalid, Err(StorageError::CheckViolation(c)) if c == "size"));
+        let mismatch = execute(&mut storage, "create table t (a integer check in ('x'));");
+        assert!(matches!(mismatch, Err(StorageError::TypeError)));
+    }
+
+    #[test]
+    fn uuid_type() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table users (id uuid primary key default uuid(), name text);",
+            "insert into users values (uuid 'ffffffff-0000-4000-8000-000000000000', 'a');",
+            "insert into users values ('00000000-0000-4000-8000-00000000000A', 'b');",
+            "insert into users (name) values ('c');",
+            "insert into users (name) values ('d');",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        let text = |t: &str| DBValue::Text(String::from(t));
+        let rows = execute(
+            &mut storage,
+            "select name from users where id = '00000000-0000-4000-8000-00000000000a';",
+        )
+        .ok();
+        assert_eq!(rows, Some(vec![vec![text("b")]]));
+        let ids = match execute(&mut storage, "select id from users order by id;") {
+            Ok(rows) => rows.concat(),
+            Err(_) => panic!("Failed to select ids"),
+        };
+        assert_eq!(ids.len(), 4);
+        assert_eq!(ids.iter().collect::<HashSet<_>>().len(), 4);
+        assert!(ids.iter().all(|id| matches!(id, DBValue::Uuid(_))));
+        assert_eq!(
+            ids.first().map(DBValue::to_string).as_deref(),
+            Some("00000000-0000-4000-8000-00000000000a")
+        );
+        let invalid = execute(
+            &mut storage,
+            "insert into users values ('not a uuid', 'e');",
+        );
+        assert!(matches!(invalid, Err(StorageError::TypeError)));
+        let duplicate = execute(
+            &mut storage,
+            "insert into users values ('ffffffff000040008000000000000000', 'f');",
+        );
+        assert!(matches!(duplicate, Err(StorageError::UniqueViolation)));
+    }
+
+    #[test]
+    fn decimal_arithmetic() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table ledger (id integer, amount decimal(8, 2) unique);",
+            "insert into ledger values (1, 0.1);",
+            "insert into ledger values (2, '0.2');",
+            "insert into ledger values (3, 19.995);",
+            "insert into ledger values (4, decimal '-5');",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        let display = |rows: Result<Vec<Row>, StorageError>| {
+            rows.ok().map(|rows| {
+                rows.concat()
+                    .iter()
+                    .map(DBValue::to_string)
+                    .collect::<Vec<_>>()
+            })
+        };
+        let rows = execute(&mut storage, "select amount from ledger order by amount;");
+        let amounts = vec!["-5.00", "0.10", "0.20", "20.00"];
+        assert_eq!(
+            display(rows),
+            Some(amounts.into_iter().map(String::from).collect())
+        );
+        let rows = execute(
+            &mut storage,
+            "select id from ledger where amount = 20 or amount + decimal '0.1' = 0.3;",
+        );
+        let ids = vec!["2", "3"];
+        assert_eq!(
+            display(rows),
+            Some(ids.into_iter().map(String::from).collect())
+        );
+        let rows = execute(
+            &mut storage,
+            "select amount * 3, amount / 3, amount - 1.5 from ledger where id = 1;",
+        );
+        let results = vec!["0.30", "0.03333333", "-1.4"];
+        assert_eq!(
+            display(rows),
+            Some(results.into_iter().map(String::from).collect())
+        );
+        let duplicate = execute(&mut storage, "insert into ledger values (5, 0.100);");
+        assert!(matches!(duplicate, Err(StorageError::UniqueViolation)));
+        let too_large = execute(&mut storage, "insert into ledger values (6, 1000000);");
+        assert!(matches!(too_large, Err(StorageError::TypeError)));
+        let by_zero = execute(&mut storage, "select amount / decimal '0.00' from ledger;");
+        assert!(matches!(by_zero, Err(StorageError::DivisionByZero)));
+    }
+
+    #[test]
+    fn group_and_order_agree() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table tbl (name text collate nocase, amount real);",
+            "insert into tbl values ('foo', 1);",
+            "insert into tbl values ('FOO', 2.5);",
+            "insert into tbl values (null, 0);",
+            "insert into tbl values ('bar', null);",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        let text = |t: &str| DBValue::Text(String::from(t));
+        let rows = execute(
+            &mut storage,
+            "select name, group_concat(name) from tbl group by name order by name;",
+        )
+        .ok();
+        assert_eq!(
+            rows,
+            Some(vec![
+                vec![DBValue::Null, DBValue::Null],
+                vec![text("bar"), text("bar")],
+                vec![text("foo"), text("foo,FOO")],
+            ])
+        );
+        let rows = execute(
+            &mut storage,
+            "select amount / 2 from tbl order by amount desc;",
+        )
+        .ok();
+        let halves = vec![
+            vec![DBValue::Real(1.25)],
+            vec![DBValue::Real(0.5)],
+            vec![DBValue::Real(0.0)],
+            vec![DBValue::Null],
+        ];
+        assert_eq!(rows, Some(halves));
+        let rows = execute(&mut storage, "select name from tbl where amount = 1;").ok();
+        assert_eq!(rows, Some(vec![vec![text("foo")]]));
+    }
+
+    #[test]
+    fn group_concat() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table tbl (dept text, name text);",
+            "insert into tbl values ('a', 'foo');",
+            "insert into tbl values ('b', 'bar');",
+            "insert into tbl values ('a', null);",
+            "insert into tbl values ('a', 'baz');",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        let text = |t: &str| DBValue::Text(String::from(t));
+        let rows = execute(
+            &mut storage,
+            "select dept, group_concat(name), string_agg(name, '; ') from tbl group by dept;",
+        )
+        .ok();
+        assert_eq!(
+            rows,
+            Some(vec![
+                vec![text("a"), text("foo,baz"), text("foo; baz")],
+                vec![text("b"), text("bar"), text("bar")],
+            ])
+        );
+        let rows = execute(&mut storage, "select group_concat(dept, '') from tbl;").ok();
+        assert_eq!(rows, Some(vec![vec![text("abaa")]]));
+        let rows = execute(
+            &mut storage,
+            "select (group_concat(name)) from tbl where 1 = 0;",
+        )
+        .ok();
+        assert_eq!(rows, Some(vec![vec![DBValue::Null]]));
+        let misplaced = execute(
+            &mut storage,
+            "select (name) from tbl where group_concat(name);",
+        );
+        assert!(matches!(
+            misplaced,
+            Err(StorageError::MisplacedAggregate(_))
+        ));
+    }
+
+    #[test]
+    fn autoincrement_ids_are_not_reused() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table tbl (id integer primary key autoincrement, name text);",
+            "insert into tbl (name) values ('foo');",
+            "insert into tbl values (5, 'bar');",
+            "insert into tbl (name) values ('baz');",
+            "delete from tbl where id = 6;",
+            "insert into tbl (name) values ('qux');",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        let rows = execute(&mut storage, "select (id) from tbl;").ok();
+        let ids = vec![1, 5, 7]
+            .into_iter()
+            .map(|i| vec![DBValue::Integer(i)])
+            .collect();
+        assert_eq!(rows, Some(ids));
+    }
+
+    #[test]
+    fn sequence_shared_by_tables() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create sequence seq start with 10 increment by 5;",
+            "create table t1 (id integer default nextval('seq'), name text);",
+            "create table t2 (id integer);",
+            "insert into t1 (name) values ('foo');",
+            "insert into t2 values (nextval('seq'));",
+            "insert into t1 (name) values ('bar');",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        let t1 = execute(&mut storage, "select (id) from t1;").ok();
+        let t2 = execute(&mut storage, "select (id) from t2;").ok();
+        assert_eq!(
+            t1,
+            Some(vec![vec![DBValue::Integer(10)], vec![DBValue::Integer(20)]])
+        );
+        assert_eq!(t2, Some(vec![vec![DBValue::Integer(15)]]));
+        let missing = execute(&mut storage, "insert into t2 values (nextval('missing'));");
+        assert!(matches!(missing, Err(StorageError::SequenceNotFound)));
+    }
+
+    #[test]
+    fn rollback_rows_by_key() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table t (id integer primary key, v integer);",
+            "insert into t values (1, 1);",
+            "insert into t values (2, 2);",
+            "begin;",
+            "update t set v = 10 where id = 1;",
+            "savepoint s1;",
+            "delete from t where id = 2;",
+            "insert into t values (3, 3);",
+            "update t set id = 4 where id = 1;",
+            "release s1;",
+            "savepoint s2;",
+            "create table u (id integer);",
+            "insert into t values (5, 5);",
+            "rollback to s2;",
+        ] {
+            assert!(execute(&mut storage, input).is_ok(), "{}", input);
+        }
+        let int = DBValue::Integer;
+        let rows = execute(&mut storage, "select id, v from t;").ok();
+        assert_eq!(
+            rows,
+            Some(vec![vec![int(3), int(3)], vec![int(4), int(10)]])
+        );
+        let dropped = execute(&mut storage, "select id from u;");
+        assert!(matches!(dropped, Err(StorageError::TableNotFound)));
+        assert!(execute(&mut storage, "rollback;").is_ok());
+        let rows = execute(&mut storage, "select id, v from t;").ok();
+        assert_eq!(rows, Some(vec![vec![int(1), int(1)], vec![int(2), int(2)]]));
+    }
+
+    #[test]
+    fn rollback_to_savepoint() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table tbl (id integer);",
+            "begin;",
+            "insert into tbl values (1);",
+            "savepoint s1;",
+            "insert into tbl values (2);",
+            "savepoint s2;",
+            "insert into tbl values (3);",
+            "rollback to s1;",
+            "insert into tbl values (4);",
+            "rollback to savepoint s1;",
+            "insert into tbl values (5);",
+            "release s1;",
+            "commit;",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        let rows = execute(&mut storage, "select (id) from tbl;").ok();
+        assert_eq!(
+            rows,
+            Some(vec![vec![DBValue::Integer(1)], vec![DBValue::Integer(5)]])
+        );
+        let outside = execute(&mut storage, "savepoint s;");
+        assert!(matches!(outside, Err(StorageError::NoActiveTransaction)));
+        execute(&mut storage, "begin;").ok();
+        let released = execute(&mut storage, "rollback to s1;");
+        assert!(matches!(released, Err(StorageError::SavepointNotFound)));
+        execute(&mut storage, "insert into tbl values (6);").ok();
+        assert!(execute(&mut storage, "rollback;").is_ok());
+        let rows = execute(&mut storage, "select (id) from tbl;").ok();
+        assert_eq!(rows.map(|rows| rows.len()), Some(2));
+    }
+
+    #[test]
+    fn statement_locks() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table parent (id integer primary key);",
+            "create table child (id integer references parent (id) on delete cascade);",
+            "create table other (id integer);",
+            "create view v as select (id) from child;",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        let locks = |sql: &str| match Parser::new(sql).parse_command() {
+            Ok(Command::Statement(statement)) => storage.statement_locks(&statement),
+            _ => panic!("{}", sql),
+        };
+        let lock = |table: &str, mode| (String::from(table), mode);
+        use LockMode::*;
+        assert_eq!(
+            locks("select other.id from v join other on v.id = other.id;"),
+            vec![
+                lock("child", Shared),
+                lock("other", Shared),
+                lock("v", Shared)
+            ]
+        );
+        assert_eq!(
+            locks("insert into child values (1);"),
+            vec![lock("child", Exclusive), lock("parent", Shared)]
+        );
+        assert_eq!(
+            locks("delete from parent where id = 1;"),
+            vec![lock("child", Exclusive), lock("parent", IntentionExclusive)]
+        );
+        assert_eq!(locks("drop view v;"), vec![lock("v", Exclusive)]);
+        assert_eq!(locks("begin;"), vec![]);
+        // Rows of tables with a primary key are locked by their keys until the transaction ends
+        for input in &[
+            "insert into parent values (1);",
+            "insert into parent values (2);",
+            "begin;",
+            "update parent set id = 3 where id = 2;",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        let id = storage.transaction().unwrap();
+        let key = |n: i64| encode_key(&[DBValue::Integer(n)]);
+        assert_eq!(
+            storage.locks.locked_rows(id, "parent"),
+            vec![key(2)..=key(2), key(3)..=key(3)]
+        );
+        assert_eq!(storage.locks.mode(id, "parent"), Some(IntentionExclusive));
+        assert!(execute(&mut storage, "commit;").is_ok());
+        assert_eq!(storage.locks.mode(id, "parent"), None);
+    }
+
+    #[test]
+    fn isolation_levels() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table t (id integer primary key);",
+            "set transaction isolation level read committed;",
+            "begin;",
+            "select id from t;",
+            "insert into t values (1);",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        // Only the locks of writes outlive their statements
+        let id = storage.transaction().unwrap();
+        assert_eq!(
+            storage.locks.mode(id, "t"),
+            Some(LockMode::IntentionExclusive)
+        );
+        let changed = execute(
+            &mut storage,
+            "set transaction isolation level serializable;",
+        );
+        assert!(matches!(
+            changed,
+            Err(StorageError::TransactionAlreadyActive)
+        ));
+        for input in &[
+            "commit;",
+            "set transaction isolation level serializable;",
+            "begin;",
+            "select id from t;",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        let id = storage.transaction().unwrap();
+        assert_eq!(storage.locks.mode(id, "t"), Some(LockMode::Shared));
+        assert!(execute(&mut storage, "insert into t values (2);").is_ok());
+        assert_eq!(storage.locks.mode(id, "t"), Some(LockMode::Exclusive));
+    }
+
+    #[test]
+    fn drop_table_with_dependent_view() {
+        let mut storage = StorageManager::new();
+        execute(&mut storage, "create table tbl (id integer);").ok();
+        execute(&mut storage, "create view v as select (id) from tbl;").ok();
+        let drop_table = execute(&mut storage, "drop table tbl;");
+        assert!(matches!(drop_table, Err(StorageError::DependentViews)));
+        assert!(execute(&mut storage, "drop view v;").is_ok());
+        assert!(execute(&mut storage, "drop table tbl;").is_ok());
+    }
+
+    #[test]
+    fn select_star() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table author (id integer, name text);",
+            "create table book (title text, author_id integer);",
+            "insert into author values (1, 'Austen');",
+            "insert into book values ('Emma', 1);",
+            "create view authors as select * from author where id > 0;",
+        ] {
+            assert!(execute(&mut storage, input).is_ok(), "{}", input);
+        }
+        let text = |t: &str| DBValue::Text(String::from(t));
+        let columns =
+            |storage: &mut StorageManager, sql: &str| match Parser::new(sql).parse_command() {
+                Ok(Command::Statement(stmt)) => storage.execute(stmt).ok().map(|result| {
+                    let names = result.columns.into_iter().map(|column| column.name);
+                    names.collect::<Vec<_>>()
+                }),
+                _ => None,
+            };
+        let names = columns(&mut storage, "select * from authors;");
+        assert_eq!(names, Some(vec![String::from("id"), String::from("name")]));
+        let rows = execute(&mut storage, "select * from authors;");
+        assert_eq!(
+            rows.ok(),
+            Some(vec![vec![DBValue::Integer(1), text("Austen")]])
+        );
+        let rows = execute(
+            &mut storage,
+            "select * from book join author on author_id = author.id;",
+        );
+        assert_eq!(
+            rows.ok(),
+            Some(vec![vec![
+                text("Emma"),
+                DBValue::Integer(1),
+                DBValue::Integer(1),
+                text("Austen"),
+            ]])
+        );
+    }
+
+    #[test]
+    fn joins_and_limits() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table author (id integer, name text);",
+            "create table book (title text, author_id integer);",
+            "insert into author values (1, 'Austen');",
+            "insert into author values (2, 'Tolstoy');",
+            "insert into book values ('Emma', 1);",
+            "insert into book values ('War and Peace', 2);",
+            "insert into book values ('Persuasion', 1);",
+            "insert into book values ('Anonymous', null);",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        let text = |t: &str| DBValue::Text(String::from(t));
+        let rows = execute(
+            &mut storage,
+            "select title, name from book join author on author_id = author.id \
+             where name = 'Austen' order by title;",
+        )
+        .ok();
+        assert_eq!(
+            rows,
+            Some(vec![
+                vec![text("Emma"), text("Austen")],
+                vec![text("Persuasion"), text("Austen")],
+            ])
+        );
+        let rows = execute(
+            &mut storage,
+            "select name, group_concat(title) from author inner join book on id = author_id \
+             group by name order by name desc limit 1;",
+        )
+        .ok();
+        assert_eq!(
+            rows,
+            Some(vec![vec![text("Tolstoy"), text("War and Peace")]])
+        );
+        let rows = execute(
+            &mut storage,
+            "select title from book order by title limit 2 offset 1;",
+        )
+        .ok();
+        assert_eq!(
+            rows,
+            Some(vec![vec![text("Emma")], vec![text("Persuasion")]])
+        );
+        let rows = execute(&mut storage, "select title from book limit 0;").ok();
+        assert_eq!(rows, Some(Vec::new()));
+        let ambiguous = execute(
+            &mut storage,
+            "select id from author join author on name = 'Austen';",
+        );
+        assert!(matches!(ambiguous, Err(StorageError::AmbiguousColumn(c)) if c == "id"));
+        let missing = execute(&mut storage, "select title from book join nope on 1 = 1;");
+        assert!(matches!(missing, Err(StorageError::TableNotFound)));
+    }
+
+    #[test]
+    fn analyze_and_plan_joins() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table customer (id integer primary key, name text collate nocase);",
+            "create table purchase (customer integer, item text collate nocase);",
+            "create index by_item on purchase (item);",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        for id in 0..50 {
+            let input = format!("insert into customer values ({}, 'c{}');", id, id);
+            assert!(execute(&mut storage, &input).is_ok());
+            let input = format!("insert into purchase values ({}, 'item{}');", id, id % 5);
+            assert!(execute(&mut storage, &input).is_ok());
+        }
+        assert!(execute(&mut storage, "insert into purchase values (null, 'ITEM0');").is_ok());
+        assert!(execute(&mut storage, "analyze;").is_ok());
+        let statistics = storage.tables["purchase"].statistics().cloned();
+        let column = statistics.map(|statistics| statistics.columns[1].clone());
+        assert_eq!(
+            column,
+            Some(ColumnStatistics {
+                distinct: 5,
+                nulls: 0,
+                min: Some(DBValue::Text(String::from("item0"))),
+                max: Some(DBValue::Text(String::from("item4"))),
+            })
+        );
+        let text = |t: &str| DBValue::Text(String::from(t));
+        for input in &[
+            "select name from purchase join customer on id = customer where item = 'item3' \
+             order by name limit 2;",
+            "select name from customer join purchase on customer = id where item = 'ITEM3' \
+             order by name limit 2;",
+        ] {
+            let rows = execute(&mut storage, input).ok();
+            assert_eq!(rows, Some(vec![vec![text("c13")], vec![text("c18")]]));
+        }
+        let missing = execute(&mut storage, "analyze nope;");
+        assert!(matches!(missing, Err(StorageError::TableNotFound)));
+    }
+
+    #[test]
+    fn push_down_conditions() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table t1 (id integer, label text, note text);",
+            "create table t2 (id integer, t1_id integer, amount real);",
+            "insert into t1 values (1, 'one', 'unused');",
+            "insert into t1 values (2, 'two', 'unused');",
+            "insert into t2 values (1, 1, 1.5);",
+            "insert into t2 values (2, 1, 2.5);",
+            "insert into t2 values (3, 2, 3.5);",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        let rows = execute(
+            &mut storage,
+            "select label, t2.id from t1 join t2 on t1.id = t1_id \
+             where amount > 2 and label <> 'three' and 1 = 1 order by t2.id;",
+        )
+        .ok();
+        let text = |t: &str| DBValue::Text(String::from(t));
+        assert_eq!(
+            rows,
+            Some(vec![
+                vec![text("one"), DBValue::Integer(2)],
+                vec![text("two"), DBValue::Integer(3)],
+            ])
+        );
+        let rows = execute(
+            &mut storage,
+            "select label from t1 join t2 on t1.id = t1_id where 1 = 0;",
+        )
+        .ok();
+        assert_eq!(rows, Some(Vec::new()));
+    }
+
+    #[test]
+    fn merge_join_on_primary_keys() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table person (id integer primary key, name text);",
+            "create table account (person integer primary key, balance real);",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        for id in (0..20).rev() {
+            let input = format!("insert into person values ({}, 'p{}');", id, id);
+            assert!(execute(&mut storage, &input).is_ok());
+            if id % 3 == 0 {
+                let input = format!("insert into account values ({}, {});", id, id * 10);
+                assert!(execute(&mut storage, &input).is_ok());
+            }
+        }
+        let sources = [
+            ("person", &storage.tables["person"]),
+            ("account", &storage.tables["account"]),
+        ];
+        let on = match Parser::new("select 1 from t where person.id = account.person;")
+            .parse_command()
+        {
+            Ok(Command::Statement(Statement::Select {
+                condition: Some(condition),
+                ..
+            })) => condition,
+            _ => panic!("Invalid test statement"),
+        };
+        let method = join_method(&sources, &[0], 1, &[&on], &[(0, 0)]);
+        assert!(matches!(
+            method,
+            JoinMethod::Merge {
+                left: (0, 0),
+                right: 0
+            }
+        ));
+        let rows = execute(
+            &mut storage,
+            "select name, balance from person join account on id = person \
+             where balance > 100;",
+        )
+        .ok();
+        let row =
+            |name: &str, balance| vec![DBValue::Text(String::from(name)), DBValue::Real(balance)];
+        assert_eq!(
+            rows,
+            Some(vec![
+                row("p12", 120.0),
+                row("p15", 150.0),
+                row("p18", 180.0)
+            ])
+        );
+    }
+
+    #[test]
+    fn spill_sorts_to_disk() {
+        let mut storage = StorageManager::new();
+        assert!(execute(&mut storage, "create table t (a integer, b text);").is_ok());
+        for i in 0..50 {
+            let input = format!("insert into t values ({}, 'row {}');", (i * 7) % 10, i);
+            assert!(execute(&mut storage, &input).is_ok());
+        }
+        for input in &[
+            "select a, b from t order by a desc;",
+            "select b from t order by a, b desc;",
+            "select a, b from t order by a limit 5 offset 20;",
+        ] {
+            storage.set_sort_memory(DEFAULT_SORT_MEMORY);
+            let in_memory = execute(&mut storage, input).ok();
+            storage.set_sort_memory(1);
+            let spilled = execute(&mut storage, input).ok();
+            assert!(in_memory.is_some());
+            assert_eq!(spilled, in_memory);
+        }
+    }
+
+    #[test]
+    fn result_columns() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table t (id integer, price decimal(6, 2), day date, name text);",
+            "insert into t values (1, 2.5, '2024-02-29', 'foo');",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        let query = "select id, price * 2, price / id, id + 0.5, date_add(day, 1, 'hour'), \
+                     name collate nocase, id = 1, null from t;";
+        let result = match Parser::new(query).parse_command() {
+            Ok(Command::Statement(stmt)) => storage.execute(stmt).ok(),
+            _ => panic!("Invalid test statement"),
+        };
+        let columns = result.map(|result| result.columns);
+        let column = |name: &str, db_type| ResultColumn {
+            name: String::from(name),
+            db_type,
+        };
+        assert_eq!(
+            columns,
+            Some(vec![
+                column("id", Some(DBType::Integer)),
+                column("?column?", Some(DBType::Decimal(38, 2))),
+                column("?column?", Some(DBType::Decimal(38, 8))),
+                column("?column?", Some(DBType::Real)),
+                column("date_add", Some(DBType::Timestamp)),
+                column("name", Some(DBType::Text)),
+                column("?column?", Some(DBType::Boolean)),
+                column("?column?", None),
+            ])
+        );
+    }
+
+    #[test]
+    fn count_rows() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table t (a integer, b text);",
+            "insert into t values (1, 'x');",
+            "insert into t values (2, null);",
+            "insert into t values (2, 'y');",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        let count = |storage: &mut StorageManager, input| match execute(storage, input) {
+            Ok(rows) => rows,
+            Err(_) => panic!("Query failed"),
+        };
+        let int = DBValue::Integer;
+        assert_eq!(
+            count(&mut storage, "select count(*) from t;"),
+            vec![vec![int(3)]]
+        );
+        assert_eq!(
+            count(&mut storage, "select count(b) from t;"),
+            vec![vec![int(2)]]
+        );
+        assert_eq!(
+            count(&mut storage, "select count(*) from t where a = 2;"),
+            vec![vec![int(2)]]
+        );
+        assert_eq!(
+            count(
+                &mut storage,
+                "select a, count(b) from t group by a order by a;"
+            ),
+            vec![vec![int(1), int(1)], vec![int(2), int(1)]]
+        );
+        assert!(count(&mut storage, "select count(*) from t limit 1 offset 1;").is_empty());
+        for input in &["begin;", "delete from t where a = 2;"] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        assert_eq!(
+            count(&mut storage, "select count(*) from t;"),
+            vec![vec![int(1)]]
+        );
+        assert!(execute(&mut storage, "rollback;").is_ok());
+        assert_eq!(
+            count(&mut storage, "select count(*) from t;"),
+            vec![vec![int(3)]]
+        );
+        assert_eq!(storage.tables["t"].len(), 3);
+    }
+
+    #[test]
+    fn parallel_queries() {
+        let mut storage = StorageManager::new();
+        assert!(execute(&mut storage, "create table t (a integer, b text);").is_ok());
+        for i in 0..2000 {
+            let input = format!("insert into t values ({}, 'row {}');", i % 7, i);
+            assert!(execute(&mut storage, &input).is_ok());
+        }
+        for input in &[
+            "select a, b from t where a > 4 and b > 'row 15';",
+            "select a, count(*), group_concat(b) from t where b < 'row 3' group by a;",
+            "select count(b) from t where a = 3;",
+        ] {
+            storage.set_parallelism(1);
+            let sequential = execute(&mut storage, input).ok();
+            storage.set_parallelism(4);
+            let parallel = execute(&mut storage, input).ok();
+            assert!(sequential.as_ref().is_some_and(|rows| !rows.is_empty()));
+            assert_eq!(parallel, sequential);
+        }
+    }
+
+    #[test]
+    fn constant_conditions() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table t (a integer);",
+            "insert into t values (1);",
+            "insert into t values (2);",
+            "delete from t where 1 = 0;",
+            "update t set a = a + 2 * 5 where true or a = 1;",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        let int = DBValue::Integer;
+        let rows = execute(&mut storage, "select a from t where 1 = 1 order by a;").ok();
+        assert_eq!(rows, Some(vec![vec![int(11)], vec![int(12)]]));
+        let rows = execute(&mut storage, "select a from t where 1 = 2 and a / 0 = 1;").ok();
+        assert_eq!(rows, Some(Vec::new()));
+        let rows = execute(&mut storage, "select count(*) from t where 2 > 1;").ok();
+        assert_eq!(rows, Some(vec![vec![int(2)]]));
+    }
+
+    #[test]
+    fn information_schema() {
+        let mut storage = StorageManager::new();
+        for input in &[
+            "create table t (id integer primary key, name text collate nocase not null, d date);",
+            "create view v as select d, id from t;",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        let text = |text: &str| DBValue::Text(String::from(text));
+        let rows = execute(
+            &mut storage,
+            "select table_name, table_type from information_schema.tables;",
+        )
+        .ok();
+        let expected = vec![
+            vec![text("t"), text("BASE TABLE")],
+            vec![text("v"), text("VIEW")],
+        ];
+        assert_eq!(rows, Some(expected));
+        let rows = execute(
+            &mut storage,
+            "select column_name, ordinal_position, data_type, is_nullable, collation_name \
+             from information_schema.columns where table_name = 'v';",
+        )
+        .ok();
+        let expected = vec![
+            vec![
+                text("d"),
+                DBValue::Integer(1),
+                text("date"),
+                text("YES"),
+                text("binary"),
+            ],
+            vec![
+                text("id"),
+                DBValue::Integer(2),
+                text("integer"),
+                text("NO"),
+                text("binary"),
+            ],
+        ];
+        assert_eq!(rows, Some(expected));
+        let rows = execute(
+            &mut storage,
+            "select information_schema.columns.column_name from information_schema.columns \
+             join information_schema.tables on \
+             information_schema.tables.table_name = information_schema.columns.table_name \
+             where table_type = 'BASE TABLE' and is_nullable = 'NO' order by column_name;",
+        )
+        .ok();
+        assert_eq!(rows, Some(vec![vec![text("id")], vec![text("name")]]));
+        let create = "create table u (n integer default 7, m text default 'new', o text);";
+        assert!(execute(&mut storage, create).is_ok());
+        let rows = execute(
+            &mut storage,
+            "select column_default from information_schema.columns where table_name = 'u';",
+        )
+        .ok();
+        let expected = vec![vec![text("7")], vec![text("'new'")], vec![DBValue::Null]];
+        assert_eq!(rows, Some(expected));
+        assert!(execute(&mut storage, "drop table u;").is_ok());
+        assert!(execute(&mut storage, "drop view v;").is_ok());
+        let rows = execute(
+            &mut storage,
+            "select count(*) from information_schema.tables;",
+        )
+        .ok();
+        assert_eq!(rows, Some(vec![vec![DBValue::Integer(1)]]));
+    }
+
+    #[test]
+    fn tables_in_files() {
+        let temporary = tempfile::tempdir().unwrap();
+        let directory = temporary.path();
+        let mut files = StorageManager::with_location(&directory.to_string_lossy());
+        let mut memory = StorageManager::with_location(IN_MEMORY);
+        let mut statements = vec![
+            String::from("create table parent (id integer primary key, tag enum('a', 'b'));"),
+            String::from("create table child (id integer references parent (id) on delete cascade, note text);"),
+            String::from("create index child_id on child (id);"),
+        ];
+        // Keys out of order are inserted between the rows of the clustered table
+        for i in 0..120 {
+            statements.push(format!(
+                "insert into parent values ({}, 'a');",
+                (i * 7) % 120
+            ));
+        }
+        for i in 0..120 {
+            statements.push(format!(
+                "insert into child values ({}, '{}');",
+                i % 50,
+                "x".repeat(i)
+            ));
+        }
+        statements.extend(
+            [
+                "update parent set tag = 'b' where id < 100;",
+                "begin;",
+                "delete from parent where id >= 40;",
+                "rollback;",
+                "delete from parent where id > 100 or id < 20;",
+                "update parent set id = id + 1000 where id = 1;",
+            ]
+            .iter()
+            .map(|statement| String::from(*statement)),
+        );
+        for statement in &statements {
+            let in_files = execute(&mut files, statement).is_ok();
+            let in_memory = execute(&mut memory, statement).is_ok();
+            assert!(in_files && in_memory, "{}", statement);
+        }
+        // A file for each table, and one for each index: the primary key and `child_id`
+        let file_count = || std::fs::read_dir(directory).unwrap().count();
+        assert_eq!(file_count(), 4);
+        for query in &[
+            "select id, tag from parent;",
+            "select tag, count(*) from parent group by tag order by tag;",
+            "select child.id, note from child join parent on parent.id = child.id where child.id = 27;",
+            "select count(*) from child;",
+        ] {
+            let rows = execute(&mut files, query).ok();
+            assert!(rows.as_ref().is_some_and(|rows| !rows.is_empty()), "{}", query);
+            assert_eq!(rows, execute(&mut memory, query).ok(), "{}", query);
+        }
+        assert!(execute(&mut files, "drop table child;").is_ok());
+        assert_eq!(file_count(), 2);
+        drop(files);
+        assert_eq!(file_count(), 0);
+    }
+
+    #[test]
+    fn save_and_open() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("save.db");
+        let path = path.to_string_lossy();
+        let mut storage = StorageManager::open(&path).unwrap_or_else(|err| panic!("{}", err));
+        for statement in &[
+            "create table parent (id integer primary key autoincrement, name text not null collate nocase unique, state enum('new', 'done') default 'new');",
+            "create table child (id integer references parent (id) on delete cascade, amount decimal(6, 2), day date);",
+            "create index child_id on child (id);",
+            "create view names as select name from parent where state = 'new';",
+            "create view first_names as select name from names where name < 'c';",
+            "create sequence seq start with 10 increment by 5;",
+            "insert into parent (name) values ('alice');",
+            "insert into parent (name, state) values ('bob', 'done');",
+            "insert into parent (name) values ('carol');",
+            "insert into child values (1, decimal '1.50', date '2024-02-29');",
+            "insert into child values (2, nextval('seq'), null);",
+            "delete from parent where name = 'carol';",
+        ] {
+            assert!(execute(&mut storage, statement).is_ok(), "{}", statement);
+        }
+        assert!(storage.save().is_ok());
+        let mut opened = StorageManager::open(&path).unwrap_or_else(|err| panic!("{}", err));
+        for query in &[
+            "select id, name, state from parent;",
+            "select id, amount, day from child;",
+            "select name from first_names;",
+            "select nextval('seq') from parent;",
+        ] {
+            let rows = execute(&mut opened, query).ok();
+            assert!(
+                rows.as_ref().is_some_and(|rows| !rows.is_empty()),
+                "{}",
+                query
+            );
+            assert_eq!(rows, execute(&mut storage, query).ok(), "{}", query);
+        }
+        for statement in &[
+            "insert into parent (name) values ('Alice');",
+            "insert into parent (name, state) values ('dave', 'gone');",
+            "insert into child values (99, null, null);",
+            "drop table parent;",
+        ] {
+            assert!(execute(&mut opened, statement).is_err(), "{}", statement);
+        }
+        let rows = execute(&mut opened, "insert into parent (name) values ('dave');")
+            .and_then(|_| execute(&mut opened, "select id from parent where name = 'dave';"));
+        assert_eq!(rows.ok(), Some(vec![vec![DBValue::Integer(4)]]));
+        assert!(matches!(
+            StorageManager::new().save(),
+            Err(StorageError::NoDatabaseFile)
+        ));
+    }
+
+    #[test]
+    fn tables_read_from_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("lazy.db");
+        let path = path.to_string_lossy();
+        let mut storage = StorageManager::open(&path).unwrap_or_else(|err| panic!("{}", err));
+        for statement in &[
+            "pragma synchronous = off;",
+            "create table t (id integer primary key, note text);",
+            "create index t_note on t (note);",
+            "create table u (n integer);",
+            "insert into u values (1);",
+        ] {
+            assert!(execute(&mut storage, statement).is_ok(), "{}", statement);
+        }
+        for i in 0..1000 {
+            let insert = format!("insert into t values ({}, 'note {}');", i, i % 10);
+            assert!(execute(&mut storage, &insert).is_ok(), "{}", insert);
+        }
+        assert!(storage.save().is_ok());
+        let open = || StorageManager::open(&path).unwrap_or_else(|err| panic!("{}", err));
+        let count = |storage: &mut StorageManager, query| match execute(storage, query).ok() {
+            Some(rows) => rows[0][0].clone(),
+            None => panic!("{}", query),
+        };
+
+        // Opening only reads the header and the catalog, and counting reads no rows
+        let mut opened = open();
+        let (opening, _) = opened.page_counts();
+        assert!(opening <= 2, "{} pages read", opening);
+        let all = "select count(*) from t;";
+        assert_eq!(count(&mut opened, all), DBValue::Integer(1000));
+        assert_eq!(opened.page_counts().0, opening);
+        let rows = execute(&mut opened, "select note from t where id = 234;").ok();
+        assert_eq!(
+            rows,
+            Some(vec![vec![DBValue::Text(String::from("note 4"))]])
+        );
+        let pages = opened.page_counts().0 - opening;
+        assert!(pages < 10, "{} pages read", pages);
+
+        // A save leaves out the changes of a transaction in progress in another session
+        let mut session = Session::new();
+        for statement in &["begin;", "delete from t where id < 500;"] {
+            let result = opened.in_session(&mut session, |opened| execute(opened, statement));
+            assert!(result.is_ok(), "{}", statement);
+        }
+        assert!(execute(&mut opened, "insert into u values (2);").is_ok());
+        let mut reopened = open();
+        assert_eq!(count(&mut reopened, all), DBValue::Integer(1000));
+        assert_eq!(
+            count(&mut reopened, "select count(*) from u;"),
+            DBValue::Integer(2)
+        );
+        let commit = opened.in_session(&mut session, |opened| execute(opened, "commit;"));
+        assert!(commit.is_ok());
+        let noted = "select count(*) from t where note = 'note 3';";
+        for storage in &mut [open(), opened] {
+            assert_eq!(count(storage, all), DBValue::Integer(500));
+            assert_eq!(count(storage, noted), DBValue::Integer(50));
+            let rows = execute(storage, "select id from t limit 1;").ok();
+            assert_eq!(rows, Some(vec![vec![DBValue::Integer(500)]]));
+        }
+    }
+
+    #[test]
+    fn backups() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("backup.db");
+        let path = path.to_string_lossy();
+        let mut storage = StorageManager::new();
+        for statement in &[
+            "create table t (id integer primary key, name text);",
+            "insert into t values (1, 'a');",
+            "begin;",
+            "insert into t values (2, 'b');",
+            "create table u (id integer);",
+        ] {
+            assert!(execute(&mut storage, statement).is_ok(), "{}", statement);
+        }
+        // Only what was committed when the transaction began is copied
+        assert!(storage.backup(&path).is_ok());
+        let mut copy = StorageManager::open(&path).unwrap_or_else(|err| panic!("{}", err));
+        let rows = execute(&mut copy, "select id, name from t;").ok();
+        let committed = vec![vec![DBValue::Integer(1), DBValue::Text(String::from("a"))]];
+        assert_eq!(rows, Some(committed));
+        assert!(execute(&mut copy, "select id from u;").is_err());
+
+        assert!(execute(&mut storage, "commit;").is_ok());
+        assert!(storage.backup(&path).is_ok());
+        let mut copy = StorageManager::open(&path).unwrap_or_else(|err| panic!("{}", err));
+        let rows = execute(&mut copy, "select id from t;").ok();
+        assert_eq!(rows.map(|rows| rows.len()), Some(2));
+        assert!(execute(&mut copy, "select id from u;").is_ok());
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("crypt.db");
+        let path = path.to_string_lossy();
+        let mut storage = StorageManager::open_encrypted(&path, "hunter2")
+            .unwrap_or_else(|err| panic!("{}", err));
+        for statement in &[
+            "create table secrets (id integer primary key, secret text);",
+            "insert into secrets values (1, 'the eagle lands at midnight');",
+        ] {
+            assert!(execute(&mut storage, statement).is_ok(), "{}", statement);
+        }
+        let bytes = std::fs::read(&*path).unwrap();
+        let contains = |text: &[u8]| bytes.windows(text.len()).any(|window| window == text);
+        assert!(!contains(b"eagle") && !contains(b"secrets"));
+
+        let mut opened = StorageManager::open_encrypted(&path, "hunter2")
+            .unwrap_or_else(|err| panic!("{}", err));
+        let rows = execute(&mut opened, "select secret from secrets;").ok();
+        let secret = DBValue::Text(String::from("the eagle lands at midnight"));
+        assert_eq!(rows, Some(vec![vec![secret.clone()]]));
+        // Sorts spill runs encrypted with keys of their own
+        opened.set_sort_memory(1);
+        let sorted = execute(&mut opened, "select secret from secrets order by id desc;").ok();
+        assert_eq!(sorted, Some(vec![vec![secret]]));
+        assert!(matches!(
+            StorageManager::open_encrypted(&path, "hunter3"),
+            Err(StorageError::UndecryptablePage(_))
+        ));
+        let error = StorageManager::open(&path).err().map(|err| err.to_string());
+        assert_eq!(
+            error.as_deref(),
+            Some("I/O error: The database file is encrypted")
+        );
+    }
+
+    #[test]
+    fn synchronous_saves() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("sync.db");
+        let path = path.to_string_lossy();
+        let mut storage = StorageManager::open(&path).unwrap_or_else(|err| panic!("{}", err));
+        let count = || {
+            let mut opened = StorageManager::open(&path).unwrap_or_else(|err| panic!("{}", err));
+            execute(&mut opened, "select count(*) from t;")
+                .ok()
+                .map(|rows| rows[0][0].clone())
+        };
+        assert!(execute(&mut storage, "create table t (id integer);").is_ok());
+        assert!(execute(&mut storage, "insert into t values (1);").is_ok());
+        assert_eq!(count(), Some(DBValue::Integer(1)));
+        assert!(execute(&mut storage, "begin;").is_ok());
+        assert!(execute(&mut storage, "insert into t values (2);").is_ok());
+        assert_eq!(count(), Some(DBValue::Integer(1)));
+        assert!(execute(&mut storage, "commit;").is_ok());
+        assert_eq!(count(), Some(DBValue::Integer(2)));
+        for (setting, saved) in &[("off", 2), ("normal", 4), ("2", 5)] {
+            let pragma = format!("pragma synchronous = {};", setting);
+            assert!(execute(&mut storage, &pragma).is_ok());
+            assert!(execute(&mut storage, "insert into t values (3);").is_ok());
+            assert_eq!(count(), Some(DBValue::Integer(*saved)), "{}", setting);
+        }
+        // A commit writes the pages its changes touch, not the whole file
+        assert!(execute(&mut storage, "begin;").is_ok());
+        for i in 0..1000 {
+            let insert = format!("insert into t values ({});", i);
+            assert!(execute(&mut storage, &insert).is_ok(), "{}", insert);
+        }
+        assert!(execute(&mut storage, "commit;").is_ok());
+        let (_, written) = storage.page_counts();
+        assert!(execute(&mut storage, "insert into t values (1000);").is_ok());
+        let pages = storage.page_counts().1 - written;
+        assert!(pages < 5, "{} pages written", pages);
+        let setting = execute(&mut storage, "pragma synchronous;").ok();
+        let full = vec![vec![DBValue::Text(String::from("full"))]];
+        assert_eq!(setting, Some(full));
+        assert!(matches!(
+            execute(&mut storage, "pragma synchronous = always;"),
+            Err(StorageError::InvalidPragmaValue(_))
+        ));
+        assert!(matches!(
+            execute(&mut storage, "pragma page_size;"),
+            Err(StorageError::UnknownPragma(_))
+        ));
+    }
+
+    #[test]
+    fn rollback_journal_saves() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("journal.db");
+        let journal = journal_path(&path);
+        let path = path.to_string_lossy();
+        let mut storage = StorageManager::open(&path).unwrap_or_else(|err| panic!("{}", err));
+        let ids = || {
+            let mut opened = StorageManager::open(&path).unwrap_or_else(|err| panic!("{}", err));
+            execute(&mut opened, "select id from t;").ok()
+        };
+        assert!(execute(&mut storage, "pragma journal_mode = rollback;").is_ok());
+        let setting = execute(&mut storage, "pragma journal_mode;").ok();
+        assert_eq!(
+            setting,
+            Some(vec![vec![DBValue::Text(String::from("rollback"))]])
+        );
+        assert!(execute(&mut storage, "create table t (id integer);").is_ok());
+        assert!(execute(&mut storage, "insert into t values (1);").is_ok());
+        assert!(execute(&mut storage, "insert into t values (2);").is_ok());
+        assert!(!journal.exists());
+        let saved = Some(vec![vec![DBValue::Integer(1)], vec![DBValue::Integer(2)]]);
+        assert_eq!(ids(), saved);
+
+        // A save cut off by a crash, leaving its journal behind
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&*path)
+            .unwrap();
+        let mut pager = Pager::new(file).unwrap();
+        pager.begin_journal(journal.clone(), true).unwrap();
+        pager.truncate(0).unwrap();
+        pager.write_page(0, &[0; crate::pager::PAGE_SIZE]).unwrap();
+        drop(pager);
+        assert_eq!(ids(), saved);
+        assert!(!journal.exists());
+        assert!(matches!(
+            execute(&mut storage, "pragma journal_mode = wal;"),
+            Err(StorageError::InvalidPragmaValue(_))
+        ));
+    }
+
+    #[test]
+    fn archives_and_restores() {
+        let temporary = tempfile::tempdir().unwrap();
+        let directory = temporary.path();
+        let path = directory.join("pitr.db");
+        let path = path.to_string_lossy();
+        let now = || {
+            std::thread::sleep(std::time::Duration::from_millis(2));
+            let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+            since_epoch.as_micros() as i64
+        };
+        let start = now();
+        let mut storage = StorageManager::open(&path).unwrap_or_else(|err| panic!("{}", err));
+        assert!(execute(&mut storage, "pragma archive = on;").is_ok());
+        let mut times = Vec::new();
+        for statement in &[
+            "create table t (id integer);",
+            "insert into t values (1);",
+            "insert into t values (2);",
+            "update t set id = 10 where id = 1;",
+            "delete from t where id = 2;",
+        ] {
+            assert!(execute(&mut storage, statement).is_ok(), "{}", statement);
+            times.push(now());
+        }
+        drop(storage);
+        let ids = || {
+            let mut opened = StorageManager::open(&path).unwrap_or_else(|err| panic!("{}", err));
+            let rows = execute(&mut opened, "select id from t order by id;").ok();
+            rows.map(|rows| {
+                rows.into_iter()
+                    .map(|row| row[0].clone())
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(ids(), Some(vec![DBValue::Integer(10)]));
+        // Restoring undoes the changes made since, and restoring a later archive redoes them
+        assert!(StorageManager::restore_archive(&path, times[2]).is_ok());
+        assert_eq!(ids(), Some(vec![DBValue::Integer(1), DBValue::Integer(2)]));
+        assert!(StorageManager::restore_archive(&path, times[1]).is_ok());
+        assert_eq!(ids(), Some(vec![DBValue::Integer(1)]));
+        assert!(StorageManager::restore_archive(&path, times[3]).is_ok());
+        assert_eq!(ids(), Some(vec![DBValue::Integer(2), DBValue::Integer(10)]));
+        assert!(matches!(
+            StorageManager::restore_archive(&path, start),
+            Err(StorageError::NoArchive)
+        ));
+        assert_eq!(archives(Path::new(&*path)).map(|a| a.len()).ok(), Some(5));
+    }
+
+    #[test]
+    fn vacuum_reclaims_pages() {
+        let temporary = tempfile::tempdir().unwrap();
+        let directory = temporary.path();
+        let mut storage = StorageManager::with_location(&directory.to_string_lossy());
+        assert!(execute(&mut storage, "create table t (id integer, note text);").is_ok());
+        for i in 0..200 {
+            let insert = format!("insert into t values ({}, '{}');", i, "x".repeat(5000));
+            assert!(execute(&mut storage, &insert).is_ok());
+        }
+        assert!(execute(&mut storage, "delete from t where id >= 100;").is_ok());
+        // The overflow pages of the replaced notes are left in the file
+        assert!(execute(&mut storage, "update t set note = 'short';").is_ok());
+        let reclaimed = match execute(&mut storage, "vacuum;").ok().as_deref() {
+            Some([row]) => row[0].clone(),
+            _ => panic!("vacuum returns a single row"),
+        };
+        assert!(matches!(reclaimed, DBValue::Integer(bytes) if bytes > 100 * 4096));
+        let rows = execute(&mut storage, "select note, count(*) from t group by note;").ok();
+        let expected = vec![vec![
+            DBValue::Text(String::from("short")),
+            DBValue::Integer(100),
+        ]];
+        assert_eq!(rows, Some(expected));
+        assert!(execute(&mut storage, "begin;").is_ok());
+        assert!(matches!(
+            execute(&mut storage, "vacuum;"),
+            Err(StorageError::TransactionAlreadyActive)
+        ));
+        // Dropping the tables removes their files
+        drop(storage);
+        assert_eq!(std::fs::read_dir(directory).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn stats() {
+        let mut storage = StorageManager::new();
+        for statement in &[
+            "create table b (id integer);",
+            "create table a (name text);",
+            "insert into b values (1);",
+            "insert into b values (2);",
+        ] {
+            assert!(execute(&mut storage, statement).is_ok(), "{}", statement);
+        }
+        assert!(execute(&mut storage, "select id from b;").is_ok());
+        let stats = storage.stats();
+        assert_eq!(stats.rows_scanned, 2);
+        // Rows are counted for each database on its own
+        let mut other = StorageManager::new();
+        assert!(execute(&mut other, "create table c (id integer);").is_ok());
+        assert!(execute(&mut other, "insert into c values (1);").is_ok());
+        assert!(execute(&mut other, "select id from c;").is_ok());
+        assert_eq!((storage.rows_scanned(), other.rows_scanned()), (2, 1));
+        // Pages are only counted for the files of the database, and tables in memory have none
+        assert_eq!((stats.pages_read, stats.pages_written), (0, 0));
+        assert_eq!(stats.file_size, None);
+        let tables: Vec<_> = stats
+            .tables
+            .iter()
+            .map(|(name, rows, memory)| (name.as_str(), *rows, memory.is_some()))
+            .collect();
+        assert_eq!(tables, vec![("a", 0, true), ("b", 2, true)]);
+        assert!(stats.tables[1].2 > stats.tables[0].2);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let mut storage = StorageManager::new();
+        for statement in &[
+            "create table a (id integer primary key, name text, at date, x real);",
+            "insert into a values (1, 'ada \"l\"', '2024-01-02', 1.5);",
+            "insert into a (id) values (2);",
+        ] {
+            assert!(execute(&mut storage, statement).is_ok(), "{}", statement);
+        }
+        let mut out = Vec::new();
+        let exported = storage.export_json("a", &mut out);
+        assert_eq!(exported.ok(), Some(2));
+        let text = String::from_utf8(out).unwrap_or_default();
+        assert_eq!(
+            text,
+            concat!(
+                r#"{"id":1,"name":"ada \"l\"","at":"2024-01-02","x":1.5}"#,
+                "\n",
+                r#"{"id":2,"name":null,"at":null,"x":null}"#,
+                "\n",
+            )
+        );
+        // A copy of the table is created, with dates as text
+        let imported = storage.import_json("b", text.as_bytes());
+        assert_eq!(imported.ok(), Some(2));
+        let schema = storage.tables["b"].schema();
+        let columns: Vec<_> = schema.columns().map(|column| column.db_type).collect();
+        let types = vec![DBType::Integer, DBType::Text, DBType::Text, DBType::Real];
+        assert_eq!(columns, types);
+        // Into the original table, text is coerced back into dates
+        assert!(execute(&mut storage, "delete from a;").is_ok());
+        assert_eq!(storage.import_json("a", text.as_bytes()).ok(), Some(2));
+        let rows = execute(&mut storage, "select at from a where id = 1;");
+        let date = datetime::parse_date("2024-01-02").map(DBValue::Date);
+        assert_eq!(rows.ok().map(|rows| rows[0][0].clone()), date);
+        // A bad line or a failing row inserts nothing
+        let bad = format!("{}{{\"id\":3}}\n{{\"id\":\n", text);
+        match storage.import_json("a", bad.as_bytes()) {
+            Err(StorageError::InvalidJson(line, _)) => assert_eq!(line, 4),
+            _ => panic!("Expected invalid JSON"),
+        }
+        let duplicate = "{\"id\":3}\n{\"id\":1}\n";
+        match storage.import_json("a", duplicate.as_bytes()) {
+            Err(StorageError::InvalidData(line, _)) => assert_eq!(line, 2),
+            _ => panic!("Expected a unique violation on line 2"),
+        }
+        let mistyped = "{\"id\":3}\n\n{\"id\":4,\"at\":\"soon\"}\n";
+        match storage.import_json("a", mistyped.as_bytes()) {
+            Err(StorageError::InvalidData(line, message)) => {
+                assert_eq!(
+                    (line, message.as_str()),
+                    (3, "Invalid date \"soon\" in column 'at'")
+                )
+            }
+            _ => panic!("Expected an invalid date on line 3"),
+        }
+        assert_eq!(
+            execute(&mut storage, "select id from a;")
+                .map(|r| r.len())
+                .ok(),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn copy_from_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("copy.csv");
+        let csv = "id,name,score\n3,\"c, \"\"x\"\"\",1.5\n1,a,\n2,\"\",2\n";
+        fs::write(&path, csv).unwrap_or_else(|err| panic!("{}", err));
+        let mut storage = StorageManager::new();
+        for statement in &[
+            "create table t (id integer primary key, name text, score real, n integer default 7);",
+            "create unique index t_name on t (name);",
+        ] {
+            assert!(execute(&mut storage, statement).is_ok(), "{}", statement);
+        }
+        let copy = format!(
+            "copy t (id, name, score) from '{}' with (format csv, header true);",
+            path.display()
+        );
+        let rows = execute(&mut storage, &copy).unwrap_or_else(|err| panic!("{}", err));
+        assert_eq!(rows, vec![vec![DBValue::Integer(3)]]);
+        let rows = execute(&mut storage, "select id, name, score, n from t;");
+        let text = |text: &str| DBValue::Text(String::from(text));
+        let expected = vec![
+            vec![
+                DBValue::Integer(1),
+                text("a"),
+                DBValue::Null,
+                DBValue::Integer(7),
+            ],
+            vec![
+                DBValue::Integer(2),
+                text(""),
+                DBValue::Real(2.0),
+                DBValue::Integer(7),
+            ],
+            vec![
+                DBValue::Integer(3),
+                text("c, \"x\""),
+                DBValue::Real(1.5),
+                DBValue::Integer(7),
+            ],
+        ];
+        assert_eq!(rows.ok(), Some(expected));
+        let rows = execute(&mut storage, "select id from t where name = 'a';");
+        assert_eq!(rows.ok(), Some(vec![vec![DBValue::Integer(1)]]));
+        // A bad record or a duplicate key loads nothing
+        fs::write(&path, "4\tb\t\\N\t1\n5\tx\t0.5\n").unwrap_or_else(|err| panic!("{}", err));
+        let copy = format!("copy t from '{}';", path.display());
+        match execute(&mut storage, &copy) {
+            Err(StorageError::InvalidData(line, _)) => assert_eq!(line, 2),
+            _ => panic!("Expected invalid data"),
+        }
+        fs::write(&path, "4\tb\t\\N\t1\n1\tx\t0.5\t1\n").unwrap_or_else(|err| panic!("{}", err));
+        assert!(matches!(
+            execute(&mut storage, &copy),
+            Err(StorageError::UniqueViolation)
+        ));
+        fs::write(&path, "4\tb\t\\N\t1\n").unwrap_or_else(|err| panic!("{}", err));
+        assert!(execute(&mut storage, &copy).is_ok());
+        let rows = execute(&mut storage, "select id from t;");
+        assert_eq!(rows.map(|rows| rows.len()).ok(), Some(4));
+    }
+
+    #[test]
+    fn schema_statements() {
+        let mut storage = StorageManager::new();
+        for statement in &[
+            "create table users (id integer primary key, name text not null);",
+            "create index users_name on users (name);",
+            "create table a (x integer);",
+            "create view names as select name from users;",
+        ] {
+            assert!(execute(&mut storage, statement).is_ok(), "{}", statement);
+        }
+        let statements = storage.schema(None).unwrap_or_else(|err| panic!("{}", err));
+        assert_eq!(statements.len(), 4);
+        assert!(statements[0].starts_with("create table a "));
+        assert!(statements[1].starts_with("create table users "));
+        assert!(statements[2].starts_with("create index users_name "));
+        assert!(statements[3].starts_with("create view names "));
+        assert_eq!(storage.schema(Some("users")).map(|s| s.len()).ok(), Some(2));
+        assert_eq!(storage.schema(Some("names")).map(|s| s.len()).ok(), Some(1));
+        assert!(matches!(
+            storage.schema(Some("missing")),
+            Err(StorageError::TableNotFound)
+        ));
+    }
+
+    #[test]
+    fn virtual_tables() {
+        use crate::virtual_table::VirtualTable;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        /// Rows of a name and a number, counting the scans filtered by a condition.
+        struct Pairs {
+            rows: Vec<Row>,
+            filtered: Arc<AtomicUsize>,
+        }
+
+        impl VirtualTable for Pairs {
+            fn columns(&self) -> Vec<(String, DBType)> {
+                vec![
+                    (String::from("name"), DBType::Text),
+                    (String::from("n"), DBType::Real),
+                ]
+            }
+
+            fn scan(
+                &self,
+                filter: Option<&Condition>,
+            ) -> Box<dyn Iterator<Item = Result<Row, String>> + '_> {
+                if filter.is_some() {
+                    self.filtered.fetch_add(1, Ordering::SeqCst);
+                }
+                Box::new(self.rows.iter().cloned().map(Ok))
+            }
+        }
+
+        let mut storage = StorageManager::new();
+        let filtered = Arc::new(AtomicUsize::new(0));
+        let module_filtered = Arc::clone(&filtered);
+        storage.create_module(
+            "pairs",
+            Arc::new(move |args: &[DBValue]| {
+                let rows = args
+                    .iter()
+                    .enumerate()
+                    .map(|(i, arg)| vec![arg.clone(), DBValue::Integer(i as i64)])
+                    .collect();
+                Ok(Box::new(Pairs {
+                    rows,
+                    filtered: Arc::clone(&module_filtered),
+                }) as Box<dyn VirtualTable>)
+            }),
+        );
+        for input in &[
+            "create virtual table p using pairs('a', 'b', 'c');",
+            "create table t (name text, v integer);",
+            "insert into t values ('b', 20);",
+        ] {
+            assert!(execute(&mut storage, input).is_ok());
+        }
+        let text = |t: &str| DBValue::Text(String::from(t));
+        assert_eq!(
+            execute(&mut storage, "select name, n from p where n >= 1;").ok(),
+            Some(vec![
+                vec![text("b"), DBValue::Real(1.0)],
+                vec![text("c"), DBValue::Real(2.0)],
+            ])
+        );
+        assert_eq!(filtered.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            execute(&mut storage, "select t.v from p join t on p.name = t.name;").ok(),
+            Some(vec![vec![DBValue::Integer(20)]])
+        );
+        assert_eq!(filtered.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            execute(
+                &mut storage,
+                "select table_type from information_schema.tables where table_name = 'p';"
+            )
+            .ok(),
+            Some(vec![vec![text("VIRTUAL TABLE")]])
+        );
+        assert!(matches!(
+            execute(&mut storage, "insert into p values ('d', 3);"),
+            Err(StorageError::TableNotFound)
+        ));
+        assert!(matches!(
+            execute(&mut storage, "create table p (a integer);"),
+            Err(StorageError::TableNameAlreadyInUse)
+        ));
+        assert!(matches!(
+            execute(&mut storage, "create virtual table q using nothing;"),
+            Err(StorageError::ModuleNotFound(_))
+        ));
+        assert!(execute(&mut storage, "create virtual table q using pairs(1);").is_ok());
+        assert!(matches!(
+            execute(&mut storage, "select name from q;"),
+            Err(StorageError::VirtualTableFailed(_, _))
+        ));
+        assert!(execute(&mut storage, "drop table p;").is_ok());
+        assert!(matches!(
+            execute(&mut storage, "select name from p;"),
+            Err(StorageError::TableNotFound)
+        ));
     }
 }
@@ -0,0 +1,239 @@
+//! Transactions of a database. Changes are grouped into transactions by keeping what they
+//! undo: before a transaction first changes a table or a view, a copy of it is kept, which
+//! aborting the transaction puts back in place, undoing every change made since at once, whether
+//! to tables in memory or in b-tree files. Savepoints within a transaction keep copies of their
+//! own, of what changes after them. Several transactions can be active at once, kept apart by
+//! their locks; see [`crate::lock_manager`].
+
+use crate::storage_manager::StorageError;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Number identifying a transaction, increasing in the order transactions begin.
+pub type TransactionId = u64;
+
+/// How far transactions are kept apart, set with `set transaction isolation level`. Both
+/// levels hold the locks of writes until the transaction ends; they differ in how long reads
+/// hold their locks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IsolationLevel {
+    /// Reads release their locks as soon as their statement is done, so that they only see
+    /// committed changes, but reading the same rows twice may see another transaction's
+    /// changes in between, and transactions may each write based on what the other has
+    /// overwritten since (write skew).
+    ReadCommitted,
+    /// Reads keep their locks until the transaction ends, so that transactions behave as if they
+    /// ran one after the other.
+    Serializable,
+}
+
+impl fmt::Display for IsolationLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::ReadCommitted => write!(f, "read committed"),
+            Self::Serializable => write!(f, "serializable"),
+        }
+    }
+}
+
+/// An active transaction, with what to undo: one set of copies for the transaction itself, and
+/// one for each savepoint after it, by name.
+#[derive(Debug)]
+struct Transaction<S> {
+    isolation: IsolationLevel,
+    savepoints: Vec<(Option<String>, S)>,
+}
+
+/// Owns the transactions of a database, handing out transaction ids and keeping track of the
+/// active transactions. What to undo is of any kind `S`, e.g. copies of tables; the manager
+/// only decides what to undo as a transaction or a savepoint ends, and leaves undoing it to its
+/// owner.
+#[derive(Debug)]
+pub struct TransactionManager<S> {
+    next_id: TransactionId,
+    active: BTreeMap<TransactionId, Transaction<S>>,
+}
+
+impl<S> TransactionManager<S> {
+    pub fn new() -> Self {
+        Self {
+            next_id: 1,
+            active: BTreeMap::new(),
+        }
+    }
+
+    /// The ids of the active transactions, in the order they began.
+    pub fn active(&self) -> impl Iterator<Item = TransactionId> + '_ {
+        self.active.keys().copied()
+    }
+
+    pub fn is_active(&self, id: TransactionId) -> bool {
+        self.active.contains_key(&id)
+    }
+
+    pub fn isolation(&self, id: TransactionId) -> Option<IsolationLevel> {
+        self.active
+            .get(&id)
+            .map(|transaction| transaction.isolation)
+    }
+
+    /// A new transaction id, for a statement run on its own outside of a transaction.
+    pub fn next_id(&mut self) -> TransactionId {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    /// Begins a transaction with nothing to undo yet, returning its id.
+    pub fn begin(&mut self, undo: S, isolation: IsolationLevel) -> TransactionId {
+        let id = self.next_id();
+        let savepoints = vec![(None, undo)];
+        self.active.insert(
+            id,
+            Transaction {
+                isolation,
+                savepoints,
+            },
+        );
+        id
+    }
+
+    /// Ends the transaction, keeping its changes and dropping what would undo them.
+    pub fn commit(&mut self, id: TransactionId) -> Result<(), StorageError> {
+        self.active
+            .remove(&id)
+            .map(|_| ())
+            .ok_or(StorageError::NoActiveTransaction)
+    }
+
+    /// Ends the transaction, returning what undoes its changes, from the transaction itself and
+    /// from each savepoint in turn.
+    pub fn abort(&mut self, id: TransactionId) -> Result<Vec<S>, StorageError> {
+        let transaction = self
+            .active
+            .remove(&id)
+            .ok_or(StorageError::NoActiveTransaction)?;
+        Ok(transaction
+            .savepoints
+            .into_iter()
+            .map(|(_, undo)| undo)
+            .collect())
+    }
+
+    pub fn savepoint(
+        &mut self,
+        id: TransactionId,
+        name: String,
+        undo: S,
+    ) -> Result<(), StorageError> {
+        let transaction = self
+            .active
+            .get_mut(&id)
+            .ok_or(StorageError::NoActiveTransaction)?;
+        transaction.savepoints.push((Some(name), undo));
+        Ok(())
+    }
+
+    /// What undoes the changes of the transaction from the latest savepoint on, where further
+    /// changes are to be kept track of.
+    pub fn current(&mut self, id: TransactionId) -> Option<&mut S> {
+        let transaction = self.active.get_mut(&id)?;
+        transaction.savepoints.last_mut().map(|(_, undo)| undo)
+    }
+
+    /// What undoes the changes of the transaction, from the transaction itself and from each
+    /// savepoint in turn.
+    pub fn undo(&self, id: TransactionId) -> impl Iterator<Item = &S> {
+        let savepoints = self
+            .active
+            .get(&id)
+            .map(|transaction| &transaction.savepoints);
+        savepoints.into_iter().flatten().map(|(_, undo)| undo)
+    }
+
+    pub fn undo_mut(&mut self, id: TransactionId) -> impl Iterator<Item = &mut S> {
+        let savepoints = self
+            .active
+            .get_mut(&id)
+            .map(|transaction| &mut transaction.savepoints);
+        savepoints.into_iter().flatten().map(|(_, undo)| undo)
+    }
+
+    /// Forgets the savepoints after the savepoint, returning what undoes the changes since: that
+    /// of the savepoint itself, which stays in place, so that the work can be retried and
+    /// rolled back again, and that of the forgotten savepoints.
+    pub fn rollback_to(
+        &mut self,
+        id: TransactionId,
+        name: &str,
+    ) -> Result<(&mut S, Vec<S>), StorageError> {
+        let position = self.find_savepoint(id, name)?;
+        let savepoints = &mut self.transaction_mut(id).savepoints;
+        let forgotten = savepoints.split_off(position + 1);
+        let forgotten = forgotten.into_iter().map(|(_, undo)| undo).collect();
+        Ok((&mut savepoints[position].1, forgotten))
+    }
+
+    /// Forgets the savepoint and any savepoints after it, keeping the changes made since, and
+    /// returns what would have undone them, to be kept track of by the savepoint before.
+    pub fn release(&mut self, id: TransactionId, name: &str) -> Result<Vec<S>, StorageError> {
+        let position = self.find_savepoint(id, name)?;
+        let savepoints = &mut self.transaction_mut(id).savepoints;
+        let released = savepoints.split_off(position);
+        Ok(released.into_iter().map(|(_, undo)| undo).collect())
+    }
+
+    fn find_savepoint(&self, id: TransactionId, name: &str) -> Result<usize, StorageError> {
+        let transaction = self
+            .active
+            .get(&id)
+            .ok_or(StorageError::NoActiveTransaction)?;
+        transaction
+            .savepoints
+            .iter()
+            .rposition(|(savepoint, _)| savepoint.as_deref() == Some(name))
+            .ok_or(StorageError::SavepointNotFound)
+    }
+
+    fn transaction_mut(&mut self, id: TransactionId) -> &mut Transaction<S> {
+        self.active.get_mut(&id).expect("The transaction is active")
+    }
+}
+
+impl<S> Default for TransactionManager<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commits_and_aborts() {
+        let mut transactions = TransactionManager::new();
+        assert_eq!(transactions.begin("a", IsolationLevel::Serializable), 1);
+        assert_eq!(transactions.begin("b", IsolationLevel::ReadCommitted), 2);
+        assert_eq!(transactions.active().collect::<Vec<_>>(), vec![1, 2]);
+        assert!(transactions.commit(1).is_ok());
+        assert!(matches!(
+            transactions.abort(1),
+            Err(StorageError::NoActiveTransaction)
+        ));
+        assert!(transactions.savepoint(2, String::from("s1"), "c").is_ok());
+        assert!(transactions.savepoint(2, String::from("s2"), "d").is_ok());
+        let rolled_back = transactions.rollback_to(2, "s1");
+        let rolled_back = rolled_back.ok().map(|(kept, forgotten)| (*kept, forgotten));
+        assert_eq!(rolled_back, Some(("c", vec!["d"])));
+        let forgotten = transactions.release(2, "s2");
+        assert!(matches!(forgotten, Err(StorageError::SavepointNotFound)));
+        assert_eq!(transactions.release(2, "s1").ok(), Some(vec!["c"]));
+        assert_eq!(transactions.undo(2).collect::<Vec<_>>(), vec![&"b"]);
+        assert_eq!(
+            transactions.isolation(2),
+            Some(IsolationLevel::ReadCommitted)
+        );
+        assert_eq!(transactions.abort(2).ok(), Some(vec!["b"]));
+        assert_eq!(transactions.active().next(), None);
+    }
+}
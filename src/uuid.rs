@@ -0,0 +1,129 @@
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Universally unique identifier, kept as its 16 bytes in network byte order. These bytes are
+/// also how the value is stored on disk, rather than the 36 characters of its text form. UUIDs
+/// are ordered by their bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Uuid([u8; 16]);
+
+impl Uuid {
+    pub fn from_bytes(bytes: [u8; 16]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+
+    /// Generates a random (version 4) UUID. The random bits come from the operating system
+    /// under the 'encryption' feature, which brings in its random number generator, except on
+    /// wasm32. Otherwise,
+    /// or should that fail, they come from the randomly keyed hasher of the standard library, fed
+    /// with the time and a counter; good enough for unique keys, but not for anything that needs
+    /// to be unguessable.
+    pub fn new_v4() -> Self {
+        let mut bytes = random_bytes();
+        bytes[6] = bytes[6] & 0x0f | 0x40;
+        bytes[8] = bytes[8] & 0x3f | 0x80;
+        Self(bytes)
+    }
+
+    /// Parses the hyphenated form `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx`, or the 32 hex digits
+    /// without hyphens, in either case.
+    pub fn parse(input: &str) -> Option<Self> {
+        let digits: Vec<u8> = match input.len() {
+            36 => {
+                let hyphens = [8, 13, 18, 23];
+                if !hyphens.iter().all(|&i| input.as_bytes()[i] == b'-') {
+                    return None;
+                }
+                input
+                    .bytes()
+                    .enumerate()
+                    .filter(|(i, _)| !hyphens.contains(i))
+                    .map(|(_, b)| b)
+                    .collect()
+            }
+            32 => input.bytes().collect(),
+            _ => return None,
+        };
+        let mut bytes = [0; 16];
+        for (byte, pair) in bytes.iter_mut().zip(digits.chunks(2)) {
+            let pair = std::str::from_utf8(pair).ok()?;
+            if !pair.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            *byte = u8::from_str_radix(pair, 16).ok()?;
+        }
+        Some(Self(bytes))
+    }
+}
+
+fn random_bytes() -> [u8; 16] {
+    let mut bytes = [0; 16];
+    #[cfg(all(feature = "encryption", not(target_arch = "wasm32")))]
+    if getrandom::fill(&mut bytes).is_ok() {
+        return bytes;
+    }
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_nanos())
+        .unwrap_or_default();
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let state = RandomState::new();
+    for (i, half) in bytes.chunks_mut(8).enumerate() {
+        half.copy_from_slice(&state.hash_one((nanos, count, i)).to_be_bytes());
+    }
+    bytes
+}
+
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if [4, 6, 8, 10].contains(&i) {
+                write!(f, "-")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_display() {
+        let text = "123e4567-e89b-12d3-a456-426614174000";
+        let uuid = Uuid::parse(text).unwrap();
+        assert_eq!(uuid.to_string(), text);
+        assert_eq!(uuid.as_bytes()[..4], [0x12, 0x3e, 0x45, 0x67]);
+        assert_eq!(Uuid::parse("123E4567E89B12D3A456426614174000"), Some(uuid));
+        for invalid in &[
+            "",
+            "123e4567-e89b-12d3-a456-42661417400",
+            "123e4567e-89b-12d3-a456-426614174000",
+            "123e4567-e89b-12d3-a456-42661417400g",
+            "+23e4567e89b12d3a456426614174000",
+        ] {
+            assert_eq!(Uuid::parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn random_uuids() {
+        let (u1, u2) = (Uuid::new_v4(), Uuid::new_v4());
+        assert_ne!(u1, u2);
+        for uuid in &[u1, u2] {
+            assert_eq!(uuid.as_bytes()[6] >> 4, 4);
+            assert_eq!(uuid.as_bytes()[8] >> 6, 0b10);
+            assert_eq!(Uuid::parse(&uuid.to_string()), Some(*uuid));
+        }
+    }
+}
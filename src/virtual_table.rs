@@ -0,0 +1,164 @@
+//! Tables whose rows come from the embedding application rather than the database, e.g. an
+//! in-memory collection, a directory listing or a remote API. The application registers a
+//! module, which makes virtual tables from the arguments of `create virtual table <name> using
+//! <module>(<arg>, ...);`. Virtual tables are read-only and queried like any other table:
+//!
+//! ```
+//! use juicydb::db::{DBType, Row};
+//! use juicydb::parser::Condition;
+//! use juicydb::virtual_table::VirtualTable;
+//! use juicydb::DBValue;
+//!
+//! /// The integers from one up to a limit.
+//! struct Numbers(i64);
+//!
+//! impl VirtualTable for Numbers {
+//!     fn columns(&self) -> Vec<(String, DBType)> {
+//!         vec![(String::from("n"), DBType::Integer)]
+//!     }
+//!
+//!     fn scan(
+//!         &self,
+//!         _: Option<&Condition>,
+//!     ) -> Box<dyn Iterator<Item = Result<Row, String>> + '_> {
+//!         Box::new((1..=self.0).map(|n| Ok(vec![DBValue::Integer(n)])))
+//!     }
+//! }
+//!
+//! let mut db = juicydb::Database::new();
+//! db.create_module("numbers", |args| match args {
+//!     [DBValue::Integer(limit)] => Ok(Box::new(Numbers(*limit)) as Box<dyn VirtualTable>),
+//!     _ => Err(String::from("numbers takes the largest number")),
+//! });
+//! db.execute("create virtual table ten using numbers(10);")?;
+//! let result = db.query("select count(n) from ten where n > 3;")?;
+//! assert_eq!(result.rows[0][0], DBValue::Integer(7));
+//! # Ok::<(), juicydb::Error>(())
+//! ```
+//!
+//! The rows are scanned anew by every statement reading the table, before it runs, and kept as
+//! a snapshot for the statement. Virtual tables aren't saved with the database.
+
+use crate::db::{DBType, DBValue, Row, Schema, Table};
+use crate::parser::Condition;
+use crate::storage_manager::StorageError;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// A source of the rows of a virtual table.
+pub trait VirtualTable: Send + Sync {
+    /// The names and types of the columns of the table.
+    fn columns(&self) -> Vec<(String, DBType)>;
+
+    /// The rows of the table, with a value for each column. `filter` is the condition of a
+    /// query reading only this table, which the rows not satisfying it may be left out by, e.g.
+    /// to query a remote API more narrowly. Rows are checked against the condition anyway. An
+    /// error fails the statement.
+    fn scan(
+        &self,
+        filter: Option<&Condition>,
+    ) -> Box<dyn Iterator<Item = Result<Row, String>> + '_>;
+}
+
+/// Makes virtual tables from the arguments of `create virtual table`, or fails with a message.
+pub type Module = dyn Fn(&[DBValue]) -> Result<Box<dyn VirtualTable>, String> + Send + Sync;
+
+/// The modules registered with a database and the virtual tables made from them, along with the
+/// rows of each table as of its last scan.
+#[derive(Default)]
+pub struct VirtualTables {
+    modules: HashMap<String, Arc<Module>>,
+    tables: HashMap<String, Box<dyn VirtualTable>>,
+    snapshots: HashMap<String, Table>,
+}
+
+impl VirtualTables {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a module under a case-insensitive name, replacing any registered before.
+    /// Tables made from a replaced module are kept.
+    pub fn insert_module(&mut self, name: &str, module: Arc<Module>) {
+        self.modules.insert(name.to_ascii_lowercase(), module);
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.tables.contains_key(name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    /// Makes a virtual table from the module of the given name, with no rows until scanned.
+    pub fn create(
+        &mut self,
+        name: String,
+        module: &str,
+        args: &[DBValue],
+    ) -> Result<(), StorageError> {
+        let make = self
+            .modules
+            .get(&module.to_ascii_lowercase())
+            .ok_or_else(|| StorageError::ModuleNotFound(String::from(module)))?;
+        let table = make(args)
+            .map_err(|message| StorageError::VirtualTableFailed(name.clone(), message))?;
+        let schema = Schema::from(table.columns());
+        self.snapshots.insert(name.clone(), Table::new(schema));
+        self.tables.insert(name, table);
+        Ok(())
+    }
+
+    /// Drops the virtual table of the name, returning whether there was one.
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.snapshots.remove(name);
+        self.tables.remove(name).is_some()
+    }
+
+    /// The rows of the virtual table of the name as of its last scan.
+    pub fn get(&self, name: &str) -> Option<(&String, &Table)> {
+        self.snapshots.get_key_value(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Table)> {
+        self.snapshots.iter()
+    }
+
+    /// Scans the rows of the virtual table of the name into its snapshot, converting the values
+    /// to the types of the columns where needed; see [`VirtualTable::scan`].
+    pub fn scan(&mut self, name: &str, filter: Option<&Condition>) -> Result<(), StorageError> {
+        let (table, snapshot) = match (self.tables.get(name), self.snapshots.get_mut(name)) {
+            (Some(table), Some(snapshot)) => (table, snapshot),
+            _ => return Err(StorageError::TableNotFound),
+        };
+        let failed = |message| StorageError::VirtualTableFailed(String::from(name), message);
+        let schema = snapshot.schema().clone();
+        let mut rows = Vec::new();
+        for row in table.scan(filter) {
+            let mut row = row.map_err(failed)?;
+            schema.coerce(&mut row);
+            let types = row.iter().map(DBValue::val_to_type).collect();
+            schema
+                .type_check(types)
+                .ok_or_else(|| failed(String::from("Row doesn't match the columns")))?;
+            rows.push(row);
+        }
+        *snapshot = Table::from_rows(schema, rows);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for VirtualTables {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut modules: Vec<_> = self.modules.keys().collect();
+        modules.sort();
+        let mut tables: Vec<_> = self.tables.keys().collect();
+        tables.sort();
+        f.debug_struct("VirtualTables")
+            .field("modules", &modules)
+            .field("tables", &tables)
+            .finish()
+    }
+}